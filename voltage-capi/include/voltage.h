@@ -0,0 +1,119 @@
+/* Generated by cbindgen from voltage-capi; do not edit by hand. */
+
+#ifndef VOLTAGE_H
+#define VOLTAGE_H
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+/**
+ * Result of every fallible C API call.
+ */
+typedef enum VoltageStatus {
+  VOLTAGE_STATUS_OK = 0,
+  VOLTAGE_STATUS_NULL_POINTER,
+  VOLTAGE_STATUS_INVALID_UTF8,
+  VOLTAGE_STATUS_LEX_ERROR,
+  VOLTAGE_STATUS_PARSE_ERROR,
+  VOLTAGE_STATUS_COMPILE_ERROR,
+  VOLTAGE_STATUS_RUNTIME_ERROR,
+  VOLTAGE_STATUS_JSON_ERROR,
+  VOLTAGE_STATUS_PANIC,
+  VOLTAGE_STATUS_SCRIPT_PANIC,
+  VOLTAGE_STATUS_CONVERSION_ERROR,
+} VoltageStatus;
+
+/**
+ * An engine owned by C code. Create with `voltage_engine_new` and release
+ * with `voltage_engine_free`.
+ */
+typedef struct VoltageEngine VoltageEngine;
+
+/**
+ * Receives script output. `text` is NUL-terminated and `len` excludes the
+ * terminator; both are only valid for the duration of the call.
+ */
+typedef void (*VoltageOutputCallback)(const char *text, uintptr_t len, void *user_data);
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+/**
+ * Creates an engine, or returns null if that fails.
+ */
+struct VoltageEngine *voltage_engine_new(void);
+
+/**
+ * Releases an engine. Passing null does nothing.
+ *
+ * # Safety
+ *
+ * `engine` must be null or a pointer returned by `voltage_engine_new` that
+ * has not been freed yet.
+ */
+void voltage_engine_free(struct VoltageEngine *engine);
+
+/**
+ * Runs a script, keeping its functions and globals for later calls. On
+ * failure, the message is written to `err_buf` (truncated to `err_buf_len`
+ * bytes including the terminator) when it isn't null.
+ *
+ * # Safety
+ *
+ * `engine` must be a live engine, `source` a NUL-terminated string, and
+ * `err_buf` null or writable for `err_buf_len` bytes.
+ */
+enum VoltageStatus voltage_eval(struct VoltageEngine *engine,
+                                const char *source,
+                                char *err_buf,
+                                uintptr_t err_buf_len);
+
+/**
+ * Calls a script function. `args_json` is a JSON array of arguments, or null
+ * for none. On success `*out_result_json` receives the result as JSON text,
+ * which the caller releases with `voltage_string_free`.
+ *
+ * # Safety
+ *
+ * `engine` must be a live engine, `name` a NUL-terminated string,
+ * `args_json` null or a NUL-terminated string, `out_result_json` a valid
+ * pointer to write to, and `err_buf` null or writable for `err_buf_len` bytes.
+ */
+enum VoltageStatus voltage_call(struct VoltageEngine *engine,
+                                const char *name,
+                                const char *args_json,
+                                char **out_result_json,
+                                char *err_buf,
+                                uintptr_t err_buf_len);
+
+/**
+ * Releases a string returned by the API. Passing null does nothing.
+ *
+ * # Safety
+ *
+ * `text` must be null or a string returned through `voltage_call` that has
+ * not been freed yet.
+ */
+void voltage_string_free(char *text);
+
+/**
+ * Routes everything scripts print to `callback`, passing `user_data` back
+ * unchanged. A null callback restores printing to stdout.
+ *
+ * # Safety
+ *
+ * `engine` must be a live engine, and `callback` must be safe to call with
+ * `user_data` for as long as the engine runs scripts.
+ */
+enum VoltageStatus voltage_set_output_callback(struct VoltageEngine *engine,
+                                               VoltageOutputCallback callback,
+                                               void *user_data);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* VOLTAGE_H */
@@ -0,0 +1,361 @@
+//! C interface to the Voltage engine, built as a cdylib.
+//!
+//! Values cross the boundary as JSON text so C callers never depend on the
+//! layout of `RuntimeValue`. Every entry point checks its pointers, reports
+//! failures as a [`VoltageStatus`] plus a message written into a caller-owned
+//! buffer, and catches panics so none unwind into C. The header lives in
+//! `include/voltage.h` and is regenerated by the build script.
+
+use std::ffi::{c_char, c_void, CStr, CString};
+use std::io::{self, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use serde_json::Value;
+use voltage_vm::{Engine, RuntimeValue, VoltageError};
+
+/// Result of every fallible C API call.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoltageStatus {
+    Ok = 0,
+    NullPointer,
+    InvalidUtf8,
+    LexError,
+    ParseError,
+    CompileError,
+    RuntimeError,
+    JsonError,
+    Panic,
+    ScriptPanic,
+    ConversionError,
+}
+
+/// An engine owned by C code. Create with `voltage_engine_new` and release
+/// with `voltage_engine_free`.
+pub struct VoltageEngine {
+    engine: Engine,
+}
+
+/// Receives script output. `text` is NUL-terminated and `len` excludes the
+/// terminator; both are only valid for the duration of the call.
+pub type VoltageOutputCallback = Option<unsafe extern "C" fn(text: *const c_char, len: usize, user_data: *mut c_void)>;
+
+struct Failure {
+    status: VoltageStatus,
+    message: String,
+}
+
+impl Failure {
+    fn new(status: VoltageStatus, message: impl Into<String>) -> Self {
+        Self { status, message: message.into() }
+    }
+}
+
+impl From<VoltageError> for Failure {
+    fn from(error: VoltageError) -> Self {
+        let status = match error {
+            VoltageError::Lex(_) => VoltageStatus::LexError,
+            VoltageError::Parse(_) => VoltageStatus::ParseError,
+            VoltageError::Compile(_) => VoltageStatus::CompileError,
+            VoltageError::Runtime(_) => VoltageStatus::RuntimeError,
+            VoltageError::Panic(_) => VoltageStatus::ScriptPanic,
+            VoltageError::Conversion(_) => VoltageStatus::ConversionError,
+        };
+        Self::new(status, error.to_string())
+    }
+}
+
+/// Creates an engine, or returns null if that fails.
+#[no_mangle]
+pub extern "C" fn voltage_engine_new() -> *mut VoltageEngine {
+    panic::catch_unwind(|| Box::into_raw(Box::new(VoltageEngine { engine: Engine::new() })))
+        .unwrap_or(ptr::null_mut())
+}
+
+/// Releases an engine. Passing null does nothing.
+///
+/// # Safety
+///
+/// `engine` must be null or a pointer returned by `voltage_engine_new` that
+/// has not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn voltage_engine_free(engine: *mut VoltageEngine) {
+    if !engine.is_null() {
+        let _ = panic::catch_unwind(AssertUnwindSafe(|| drop(Box::from_raw(engine))));
+    }
+}
+
+/// Runs a script, keeping its functions and globals for later calls. On
+/// failure, the message is written to `err_buf` (truncated to `err_buf_len`
+/// bytes including the terminator) when it isn't null.
+///
+/// # Safety
+///
+/// `engine` must be a live engine, `source` a NUL-terminated string, and
+/// `err_buf` null or writable for `err_buf_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn voltage_eval(
+    engine: *mut VoltageEngine,
+    source: *const c_char,
+    err_buf: *mut c_char,
+    err_buf_len: usize,
+) -> VoltageStatus {
+    guard(err_buf, err_buf_len, || {
+        let engine = engine_mut(engine)?;
+        let source = read_str(source, "source")?;
+        engine.eval(source)?;
+        Ok(())
+    })
+}
+
+/// Calls a script function. `args_json` is a JSON array of arguments, or null
+/// for none. On success `*out_result_json` receives the result as JSON text,
+/// which the caller releases with `voltage_string_free`.
+///
+/// # Safety
+///
+/// `engine` must be a live engine, `name` a NUL-terminated string,
+/// `args_json` null or a NUL-terminated string, `out_result_json` a valid
+/// pointer to write to, and `err_buf` null or writable for `err_buf_len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn voltage_call(
+    engine: *mut VoltageEngine,
+    name: *const c_char,
+    args_json: *const c_char,
+    out_result_json: *mut *mut c_char,
+    err_buf: *mut c_char,
+    err_buf_len: usize,
+) -> VoltageStatus {
+    guard(err_buf, err_buf_len, || {
+        let engine = engine_mut(engine)?;
+        let name = read_str(name, "name")?;
+        if out_result_json.is_null() {
+            return Err(Failure::new(VoltageStatus::NullPointer, "out_result_json is null"));
+        }
+        *out_result_json = ptr::null_mut();
+
+        let args = if args_json.is_null() { Vec::new() } else { parse_args(read_str(args_json, "args_json")?)? };
+        let result = engine.call(name, &args)?;
+        let json = result.to_json().map_err(|e| Failure::new(VoltageStatus::JsonError, e))?;
+
+        // serde_json escapes control characters, so the text has no interior NUL
+        *out_result_json = CString::new(json.to_string()).unwrap().into_raw();
+        Ok(())
+    })
+}
+
+/// Releases a string returned by the API. Passing null does nothing.
+///
+/// # Safety
+///
+/// `text` must be null or a string returned through `voltage_call` that has
+/// not been freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn voltage_string_free(text: *mut c_char) {
+    if !text.is_null() {
+        drop(CString::from_raw(text));
+    }
+}
+
+/// Routes everything scripts print to `callback`, passing `user_data` back
+/// unchanged. A null callback restores printing to stdout.
+///
+/// # Safety
+///
+/// `engine` must be a live engine, and `callback` must be safe to call with
+/// `user_data` for as long as the engine runs scripts.
+#[no_mangle]
+pub unsafe extern "C" fn voltage_set_output_callback(
+    engine: *mut VoltageEngine,
+    callback: VoltageOutputCallback,
+    user_data: *mut c_void,
+) -> VoltageStatus {
+    guard(ptr::null_mut(), 0, || {
+        let engine = engine_mut(engine)?;
+        match callback {
+            Some(callback) => engine.set_output(CallbackWriter { callback, user_data }),
+            None => engine.set_output(io::stdout()),
+        }
+        Ok(())
+    })
+}
+
+struct CallbackWriter {
+    callback: unsafe extern "C" fn(*const c_char, usize, *mut c_void),
+    user_data: *mut c_void,
+}
+
+impl Write for CallbackWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let mut text = Vec::with_capacity(buf.len() + 1);
+        text.extend_from_slice(buf);
+        text.push(0);
+        unsafe { (self.callback)(text.as_ptr().cast(), buf.len(), self.user_data) };
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Runs an entry point body, turning errors and panics into a status code and
+// a message in `err_buf`.
+fn guard(err_buf: *mut c_char, err_buf_len: usize, body: impl FnOnce() -> Result<(), Failure>) -> VoltageStatus {
+    let failure = match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => return VoltageStatus::Ok,
+        Ok(Err(failure)) => failure,
+        Err(payload) => {
+            let message = payload
+                .downcast_ref::<String>()
+                .cloned()
+                .or_else(|| payload.downcast_ref::<&str>().map(|s| s.to_string()))
+                .unwrap_or_else(|| "unknown panic".to_string());
+            Failure::new(VoltageStatus::Panic, format!("internal error: {}", message))
+        }
+    };
+    unsafe { write_message(err_buf, err_buf_len, &failure.message) };
+    failure.status
+}
+
+unsafe fn engine_mut<'a>(engine: *mut VoltageEngine) -> Result<&'a mut Engine, Failure> {
+    engine
+        .as_mut()
+        .map(|engine| &mut engine.engine)
+        .ok_or_else(|| Failure::new(VoltageStatus::NullPointer, "engine is null"))
+}
+
+unsafe fn read_str<'a>(text: *const c_char, what: &str) -> Result<&'a str, Failure> {
+    if text.is_null() {
+        return Err(Failure::new(VoltageStatus::NullPointer, format!("{} is null", what)));
+    }
+    CStr::from_ptr(text)
+        .to_str()
+        .map_err(|e| Failure::new(VoltageStatus::InvalidUtf8, format!("{} is not valid UTF-8: {}", what, e)))
+}
+
+fn parse_args(json: &str) -> Result<Vec<RuntimeValue>, Failure> {
+    let json_error = |message: String| Failure::new(VoltageStatus::JsonError, message);
+    match serde_json::from_str(json).map_err(|e| json_error(format!("invalid arguments JSON: {}", e)))? {
+        Value::Array(args) => args.iter().map(|arg| RuntimeValue::from_json(arg).map_err(json_error)).collect(),
+        _ => Err(json_error("arguments must be a JSON array".to_string())),
+    }
+}
+
+// Copies as much of `message` as fits, always leaving a NUL-terminated string
+unsafe fn write_message(buf: *mut c_char, len: usize, message: &str) {
+    if buf.is_null() || len == 0 {
+        return;
+    }
+    let mut end = message.len().min(len - 1);
+    while !message.is_char_boundary(end) {
+        end -= 1;
+    }
+    ptr::copy_nonoverlapping(message.as_ptr().cast(), buf, end);
+    *buf.add(end) = 0;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn c(text: &str) -> CString {
+        CString::new(text).unwrap()
+    }
+
+    fn message(buf: &[c_char]) -> String {
+        unsafe { CStr::from_ptr(buf.as_ptr()) }.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn test_eval_and_call_exchange_json() {
+        unsafe {
+            let engine = voltage_engine_new();
+            let mut err = [0 as c_char; 128];
+            let source = c("fn pair(a, b) { return [a, b * 2]; }");
+            assert_eq!(voltage_eval(engine, source.as_ptr(), err.as_mut_ptr(), err.len()), VoltageStatus::Ok);
+
+            let mut result = ptr::null_mut();
+            let status = voltage_call(
+                engine,
+                c("pair").as_ptr(),
+                c(r#"["x", 21]"#).as_ptr(),
+                &mut result,
+                err.as_mut_ptr(),
+                err.len(),
+            );
+            assert_eq!(status, VoltageStatus::Ok);
+            assert_eq!(CStr::from_ptr(result).to_str(), Ok(r#"["x",42]"#));
+            voltage_string_free(result);
+            voltage_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_errors_map_to_status_and_message() {
+        unsafe {
+            let engine = voltage_engine_new();
+            let mut err = [0 as c_char; 128];
+            let status = voltage_eval(engine, c("1 / 0;").as_ptr(), err.as_mut_ptr(), err.len());
+            assert_eq!(status, VoltageStatus::RuntimeError);
+            assert!(message(&err).starts_with("Runtime error"));
+
+            let mut result = ptr::null_mut();
+            let status = voltage_call(engine, c("f").as_ptr(), c("{}").as_ptr(), &mut result, err.as_mut_ptr(), err.len());
+            assert_eq!(status, VoltageStatus::JsonError);
+            assert_eq!(message(&err), "arguments must be a JSON array");
+            assert!(result.is_null());
+            voltage_engine_free(engine);
+        }
+    }
+
+    #[test]
+    fn test_every_engine_error_has_its_own_status() {
+        let failure = Failure::from(VoltageError::Conversion("expected int, found string".to_string()));
+        assert_eq!(failure.status, VoltageStatus::ConversionError);
+        assert_eq!(failure.message, "Conversion error: expected int, found string");
+    }
+
+    #[test]
+    fn test_null_pointers_are_rejected() {
+        unsafe {
+            let mut err = [0 as c_char; 64];
+            let status = voltage_eval(ptr::null_mut(), c("1;").as_ptr(), err.as_mut_ptr(), err.len());
+            assert_eq!(status, VoltageStatus::NullPointer);
+            assert_eq!(message(&err), "engine is null");
+
+            let engine = voltage_engine_new();
+            assert_eq!(voltage_eval(engine, ptr::null(), ptr::null_mut(), 0), VoltageStatus::NullPointer);
+            let status = voltage_call(engine, c("f").as_ptr(), ptr::null(), ptr::null_mut(), ptr::null_mut(), 0);
+            assert_eq!(status, VoltageStatus::NullPointer);
+            voltage_engine_free(engine);
+            voltage_engine_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn test_messages_are_truncated_to_the_buffer() {
+        let mut err = [1 as c_char; 8];
+        unsafe { write_message(err.as_mut_ptr(), err.len(), "Parse error: é") };
+        assert_eq!(message(&err), "Parse e");
+    }
+
+    unsafe extern "C" fn collect(text: *const c_char, len: usize, user_data: *mut c_void) {
+        let output = &mut *(user_data as *mut Vec<u8>);
+        output.extend_from_slice(std::slice::from_raw_parts(text.cast(), len));
+    }
+
+    #[test]
+    fn test_output_callback_receives_prints() {
+        let mut output: Vec<u8> = Vec::new();
+        unsafe {
+            let engine = voltage_engine_new();
+            let user_data = &mut output as *mut Vec<u8> as *mut c_void;
+            assert_eq!(voltage_set_output_callback(engine, Some(collect), user_data), VoltageStatus::Ok);
+            let source = c("print(\"a\"); puts(1 + 1);");
+            assert_eq!(voltage_eval(engine, source.as_ptr(), ptr::null_mut(), 0), VoltageStatus::Ok);
+            voltage_engine_free(engine);
+        }
+        assert_eq!(output, b"a2\n");
+    }
+}
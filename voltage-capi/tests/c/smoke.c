@@ -0,0 +1,47 @@
+/* Exercises the C API end to end; run by tests/c_api.rs. */
+#include <stdio.h>
+#include <string.h>
+#include "voltage.h"
+
+static char output[256];
+
+static void collect(const char *text, uintptr_t len, void *user_data) {
+    (void)len;
+    (void)user_data;
+    strncat(output, text, sizeof(output) - strlen(output) - 1);
+}
+
+#define CHECK(cond)                                                          \
+    do {                                                                     \
+        if (!(cond)) {                                                       \
+            fprintf(stderr, "check failed at line %d: %s\n", __LINE__, #cond); \
+            return 1;                                                        \
+        }                                                                    \
+    } while (0)
+
+int main(void) {
+    char err[256] = {0};
+    char *result = NULL;
+
+    VoltageEngine *engine = voltage_engine_new();
+    CHECK(engine != NULL);
+    CHECK(voltage_set_output_callback(engine, collect, NULL) == VOLTAGE_STATUS_OK);
+
+    CHECK(voltage_eval(engine, "fn add(a, b) { puts(\"adding\"); return a + b; }", err, sizeof(err))
+          == VOLTAGE_STATUS_OK);
+    CHECK(voltage_call(engine, "add", "[40, 2]", &result, err, sizeof(err)) == VOLTAGE_STATUS_OK);
+    CHECK(strcmp(result, "42") == 0);
+    voltage_string_free(result);
+    CHECK(strcmp(output, "adding\n") == 0);
+
+    CHECK(voltage_eval(engine, "1 / 0;", err, sizeof(err)) == VOLTAGE_STATUS_RUNTIME_ERROR);
+    CHECK(strncmp(err, "Runtime error", 13) == 0);
+
+    CHECK(voltage_call(engine, "add", "[1]", &result, err, sizeof(err)) == VOLTAGE_STATUS_RUNTIME_ERROR);
+    CHECK(result == NULL);
+    CHECK(voltage_eval(NULL, "1;", err, sizeof(err)) == VOLTAGE_STATUS_NULL_POINTER);
+
+    voltage_engine_free(engine);
+    printf("ok\n");
+    return 0;
+}
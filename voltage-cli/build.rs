@@ -0,0 +1,6 @@
+// Tells `voltagec self info` the target it was built for, which is the one
+// the JIT generates code for.
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rustc-env=VOLTAGEC_TARGET={}", std::env::var("TARGET").unwrap());
+}
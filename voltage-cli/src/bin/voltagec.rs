@@ -1,88 +1,437 @@
-use clap::Parser as ClapParser;
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser as ClapParser, Subcommand};
 use voltage_core::*;
-use voltage_parser::{Parser, Lexer};
-use voltage_jit::JitCompiler;
-use voltage_vm::{VirtualMachine, BytecodeCompiler};
+use voltage_parser::{format, parse_with_diagnostics, Diagnostic, DEFAULT_DIAGNOSTIC_LIMIT};
+use voltage_jit::{JitCompiler, JitError, JitSupport};
+use voltage_vm::{
+    call_graph, disassemble, list_constants, unused_constants, BytecodeCompiler, CompileErrorKind, CompilerOptions, Engine, EnvAccess,
+    FsAccess, ModuleLoader, Program, VirtualMachine, VmConfig, VoltageError,
+};
 use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
 
-mod repl;
+use voltage_cli::cache::CompileCache;
+use voltage_cli::debugger;
+use voltage_cli::repl;
+use voltage_cli::report::{print_error, render_options, ColorChoice, MessageFormat, ReportOptions, Reporter};
+use voltage_cli::test_runner;
 
 #[derive(ClapParser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
     /// Input file to compile and run
     #[arg(value_name = "FILE")]
     input: Option<String>,
     
-    /// Run in REPL mode
+    /// Run in REPL mode, like `voltagec repl`. With --message-format=json
+    /// it's `voltagec repl --json`
     #[arg(long)]
     repl: bool,
+
+    /// Compile FILE to a .vbc bytecode file next to it instead of running it
+    #[arg(long, requires = "input")]
+    build: bool,
+
+    /// Let scripts read and write files, optionally only inside DIR
+    #[arg(long, value_name = "DIR", num_args = 0..=1, require_equals = true)]
+    allow_fs: Option<Option<PathBuf>>,
+
+    /// Keep scripts from reading or setting environment variables
+    #[arg(long)]
+    no_env: bool,
+
+    /// Also look for imported modules in DIR. Modules are looked for in
+    /// FILE's directory, then each --module-path in order, then the
+    /// directories in VOLTAGE_PATH
+    #[arg(long, value_name = "DIR", global = true)]
+    module_path: Vec<PathBuf>,
+
+    /// Reuse compiled scripts saved in DIR, or in the user cache directory
+    #[arg(long, value_name = "DIR", num_args = 0..=1, require_equals = true)]
+    cache_dir: Option<Option<PathBuf>>,
+
+    /// Compile the script even if --cache-dir is given
+    #[arg(long)]
+    no_cache: bool,
+
+    /// Keep functions that nothing calls instead of leaving them out
+    #[arg(long)]
+    keep_all: bool,
+
+    /// Load constant globals as constants and inline calls to small functions
+    #[arg(short = 'O', long, global = true)]
+    optimize: bool,
+
+    /// Treat warnings as errors, so a script with any doesn't run or build
+    #[arg(long, global = true)]
+    deny_warnings: bool,
+
+    /// Report the errors and warnings in FILE without running it, like
+    /// `voltagec check FILE`
+    #[arg(long, requires = "input")]
+    check: bool,
+
+    /// Show at most N errors and warnings, and count the rest
+    #[arg(long, value_name = "N", default_value_t = DEFAULT_DIAGNOSTIC_LIMIT, global = true)]
+    max_diagnostics: usize,
+
+    /// Print errors and warnings for people, or as one JSON object per line
+    /// on stdout for tools. In the REPL, each input gets one JSON line with
+    /// its result or its errors, and there's no prompt
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = MessageFormat::Human, global = true)]
+    message_format: MessageFormat,
+
+    /// Whether to color errors and warnings
+    #[arg(long, value_enum, value_name = "WHEN", default_value_t = ColorChoice::Auto, global = true)]
+    color: ColorChoice,
+
+    /// Run FILE on the bytecode VM, or compile it to machine code with the
+    /// JIT. The JIT lists what in FILE it can't compile yet, and runs nothing
+    /// if there's any
+    #[arg(long, value_enum, value_name = "BACKEND", default_value_t = Backend::Vm)]
+    backend: Backend,
+
+    /// Step through FILE with the debugger instead of running it
+    #[arg(long, requires = "input")]
+    debug: bool,
+
+    /// Print FILE's compiled bytecode, constant pool or call graph instead of
+    /// running it
+    #[arg(long, value_enum, value_name = "WHAT", requires = "input")]
+    emit: Option<Emit>,
+
+    /// Print how many lines of FILE ran once it finishes
+    #[arg(long)]
+    coverage: bool,
+
+    /// With --coverage, also write the report to PATH in lcov format
+    #[arg(long, value_name = "PATH", requires = "coverage")]
+    lcov: Option<PathBuf>,
+
+    /// Stop the script once N calls are in progress at once
+    #[arg(long, value_name = "N", value_parser = positive_usize())]
+    max_call_depth: Option<usize>,
+
+    /// Stop the script once its stack holds N values (VM backend only)
+    #[arg(long, value_name = "N", value_parser = positive_usize())]
+    max_stack: Option<usize>,
+
+    /// Stop the script after it runs N instructions, or on the JIT backend
+    /// after its loops go round N times
+    #[arg(long, value_name = "N", value_parser = clap::value_parser!(u64).range(1..))]
+    fuel: Option<u64>,
+
+    /// On a runtime error, also print the VM's stack, locals and last
+    /// instructions
+    #[arg(short, long)]
+    verbose: bool,
+}
+
+/// The `--emit` choices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Emit {
+    /// Every instruction, after the constant pool
+    Bytecode,
+    /// Only the constant pool
+    Constants,
+    /// Which functions call which, in Graphviz DOT format
+    Callgraph,
+}
+
+/// The `--backend` choices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Backend {
+    /// The bytecode VM, which runs the whole language
+    Vm,
+    /// Cranelift-compiled machine code, for functions on ints and bools
+    Jit,
+}
+
+/// Parses a count of at least 1, for the limits.
+fn positive_usize() -> clap::builder::RangedU64ValueParser<usize> {
+    clap::builder::RangedU64ValueParser::new().range(1..)
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Report the errors and warnings in FILE without running it
+    Check {
+        #[arg(value_name = "FILE")]
+        file: String,
+    },
+    /// Run every function in FILE whose name starts with `test_`
+    Test {
+        #[arg(value_name = "FILE")]
+        file: String,
+
+        /// Only run tests whose name contains SUBSTRING
+        #[arg(long, value_name = "SUBSTRING")]
+        filter: Option<String>,
+    },
+    /// Evaluate lines of input as they're typed
+    Repl {
+        /// Answer each input with one line of JSON instead of prompting,
+        /// for programs driving the REPL
+        #[arg(long)]
+        json: bool,
+    },
+    /// Rewrite FILE in the standard layout, keeping its comments
+    Fmt {
+        #[arg(value_name = "FILE")]
+        file: String,
+
+        /// Don't rewrite FILE; fail if it isn't formatted already
+        #[arg(long)]
+        check: bool,
+    },
+    /// Commands about voltagec itself
+    #[command(name = "self", subcommand)]
+    SelfCommand(SelfCommand),
+}
+
+#[derive(Subcommand)]
+enum SelfCommand {
+    /// Print a script that completes voltagec's commands and flags in SHELL
+    Completions {
+        #[arg(value_enum)]
+        shell: clap_complete::Shell,
+    },
+    /// Print the version and what this build supports, for bug reports
+    Info {
+        /// Also print the guarantees the language makes, like the order
+        /// expressions are evaluated in
+        #[arg(long)]
+        language: bool,
+    },
+}
+
+/// The file system access granted on the command line, if any.
+fn fs_access(cli: &Cli) -> Option<FsAccess> {
+    let access = match cli.allow_fs.as_ref()? {
+        Some(root) => FsAccess::within(root),
+        None => Ok(FsAccess::unrestricted()),
+    };
+    match access {
+        Ok(access) => Some(access),
+        Err(e) => {
+            eprintln!("{}", e);
+            process::exit(1);
+        }
+    }
+}
+
+/// The process environment, unless `--no-env` was given.
+fn env_access(cli: &Cli) -> Option<EnvAccess> {
+    (!cli.no_env).then(EnvAccess::process)
+}
+
+/// The modules `file` can import: the standard library, and module files in
+/// its directory, the `--module-path`s and `VOLTAGE_PATH`, searched in that
+/// order.
+fn modules(cli: &Cli, file: &str) -> ModuleLoader {
+    let mut modules = ModuleLoader::new();
+    let dir = Path::new(file).parent().filter(|dir| !dir.as_os_str().is_empty());
+    modules.set_entry_dir(dir.unwrap_or(Path::new(".")));
+    for dir in &cli.module_path {
+        modules.add_search_path(dir);
+    }
+    if let Some(voltage_path) = std::env::var_os("VOLTAGE_PATH") {
+        modules.set_voltage_path(&voltage_path);
+    }
+    modules
+}
+
+/// An engine to run `file` in, with the access, limits, coverage and module
+/// paths given on the command line.
+fn engine(cli: &Cli, file: &str) -> Engine {
+    let mut engine = Engine::with_options(vm_config(cli), compiler_options(cli));
+    engine.set_script_name(file);
+    *engine.modules_mut() = modules(cli, file);
+    if let Some(access) = fs_access(cli) {
+        engine.allow_fs(access);
+    }
+    if let Some(access) = env_access(cli) {
+        engine.allow_env(access);
+    }
+    engine
+}
+
+/// The VM's limits, and what it records, as given on the command line.
+fn vm_config(cli: &Cli) -> VmConfig {
+    let defaults = VmConfig::default();
+    VmConfig {
+        max_call_depth: cli.max_call_depth.unwrap_or(defaults.max_call_depth),
+        max_stack: cli.max_stack.unwrap_or(defaults.max_stack),
+        fuel: cli.fuel,
+        trace: cli.verbose,
+        coverage: cli.coverage,
+    }
+}
+
+/// How to compile scripts, as `-O` and `--deny-warnings` say.
+fn compiler_options(cli: &Cli) -> CompilerOptions {
+    CompilerOptions { optimize: cli.optimize, deny_warnings: cli.deny_warnings, ..CompilerOptions::default() }
+}
+
+/// Where to report coverage, if `--coverage` was given.
+struct CoverageReport {
+    lcov: Option<PathBuf>,
+}
+
+impl CoverageReport {
+    fn from_cli(cli: &Cli) -> Option<Self> {
+        cli.coverage.then(|| CoverageReport { lcov: cli.lcov.clone() })
+    }
+
+    /// Prints the summary for `file` and writes the lcov file, if any.
+    fn write(&self, engine: &Engine, file: &str) {
+        let Some(coverage) = engine.line_coverage() else {
+            return;
+        };
+        println!("{}", coverage.summary(file));
+        if let Some(path) = &self.lcov {
+            if let Err(e) = fs::write(path, coverage.to_lcov(file)) {
+                eprintln!("Could not write {}: {}", path.display(), e);
+            }
+        }
+    }
+}
+
+/// The compile cache to run scripts through, if one was asked for.
+fn compile_cache(cli: &Cli) -> Option<CompileCache> {
+    // The cache neither keeps every function for coverage nor makes
+    // warnings errors
+    if cli.no_cache || cli.coverage || cli.deny_warnings {
+        return None;
+    }
+    let cache = match cli.cache_dir.as_ref()? {
+        Some(dir) => Some(CompileCache::new(dir)),
+        None => CompileCache::default_dir().map(CompileCache::new),
+    };
+    cache.map(|cache| cache.keep_all(cli.keep_all))
+}
+
+/// How to print errors and warnings.
+fn report_options(cli: &Cli) -> ReportOptions {
+    ReportOptions { format: cli.message_format, max_diagnostics: cli.max_diagnostics, render: render_options(cli.color) }
+}
+
+/// Whether to compile functions nothing calls. Coverage keeps them so they
+/// show up as lines that never ran.
+fn keep_all(cli: &Cli) -> bool {
+    cli.keep_all || cli.coverage
 }
 
 fn main() {
     let cli = Cli::parse();
     
+    if let Some(Command::SelfCommand(command)) = &cli.command {
+        self_command(command);
+        return;
+    }
+    
+    if let Some(Command::Check { file }) = &cli.command {
+        let passed = check_voltage_file(file, compiler_options(&cli), &modules(&cli, file), report_options(&cli));
+        process::exit(if passed { 0 } else { 1 });
+    }
+    
+    if let Some(Command::Fmt { file, check }) = &cli.command {
+        let passed = format_voltage_file(file, *check, report_options(&cli));
+        process::exit(if passed { 0 } else { 1 });
+    }
+    
+    if let Some(Command::Test { file, filter }) = &cli.command {
+        let passed = test_voltage_file(
+            file,
+            filter.as_deref(),
+            engine(&cli, file),
+            CoverageReport::from_cli(&cli),
+        );
+        process::exit(if passed { 0 } else { 1 });
+    }
+    
+    if let Some(Command::Repl { json }) = &cli.command {
+        run_repl(&cli, *json || cli.message_format == MessageFormat::Json);
+        return;
+    }
+    
     if cli.repl {
-        // Run REPL mode
-        let mut repl_instance = repl::Repl::new();
-        repl_instance.run();
+        run_repl(&cli, cli.message_format == MessageFormat::Json);
         return;
     }
     
     match &cli.input {
-        Some(file) => {
-            if file.ends_with(".v") {
-                run_voltage_file(file);
-            } else {
-                println!("Compiling file: {}", file);
-                
-                // Read the source code from the file
-                let source = fs::read_to_string(file)
-                    .expect("Should have been able to read the file");
-                    
-                // Tokenize the source
-                let lexer = Lexer::new(source);
-                let tokens = lexer.tokenize().to_vec();
-                println!("Tokens: {:?}", tokens);
-                
-                // Parse the tokens into AST
-                let mut parser = Parser::new(tokens);
-                let ast = parser.parse();
-                println!("Parsed {} statements", ast.len());
-                
-                // Set up the JIT compiler
-                let mut jit = JitCompiler::new();
-                
-                // Declare built-in functions
-                if let Err(e) = jit.declare_builtins() {
-                    eprintln!("Error declaring built-ins: {}", e);
-                    return;
-                }
-                
-                // Compile each top-level function in the AST
-                for stmt in ast {
-                    match stmt {
-                        Statement::Function(func) => {
-                            println!("Compiling function: {}", func.name);
-                            if let Err(e) = jit.compile_function(&func) {
-                                eprintln!("Error compiling function '{}': {}", func.name, e);
-                            }
-                        }
-                        _ => {
-                            println!("Skipping non-function statement");
-                        }
-                    }
-                }
-                
-                println!("Compilation completed successfully!");
+        Some(file) if cli.check => {
+            let passed = check_voltage_file(file, compiler_options(&cli), &modules(&cli, file), report_options(&cli));
+            process::exit(if passed { 0 } else { 1 });
+        }
+        Some(file) if cli.build => {
+            build_voltage_file(file, cli.keep_all, compiler_options(&cli), &modules(&cli, file), report_options(&cli))
+        }
+        Some(file) if cli.debug => debug_voltage_file(
+            file,
+            vm_config(&cli),
+            compiler_options(&cli),
+            &modules(&cli, file),
+            fs_access(&cli),
+            env_access(&cli),
+        ),
+        Some(file) if cli.emit.is_some() => emit_voltage_file(
+            file,
+            cli.emit.unwrap(),
+            cli.keep_all,
+            compiler_options(&cli),
+            &modules(&cli, file),
+            report_options(&cli),
+        ),
+        Some(file) if cli.backend == Backend::Jit || file.ends_with(".vx") => {
+            if cli.max_stack.is_some() {
+                // Compiled code keeps its values in registers and on the native stack
+                Cli::command()
+                    .error(ErrorKind::ArgumentConflict, "--max-stack only applies to the VM backend")
+                    .exit();
             }
+            jit_voltage_file(file, vm_config(&cli), cli.keep_all, compiler_options(&cli), &modules(&cli, file), report_options(&cli))
         }
+        Some(file) => run_voltage_file(
+            file,
+            engine(&cli, file),
+            compile_cache(&cli),
+            keep_all(&cli),
+            compiler_options(&cli),
+            report_options(&cli),
+            CoverageReport::from_cli(&cli),
+        ),
         None => {
             println!("Voltage programming language");
             println!("Usage: voltage [OPTIONS] [FILE]");
             println!("  voltage file.v         Compile and run a .v file with Voltage Engine");
-            println!("  voltage file.vx        Compile with legacy JIT (for comparison)");
+            println!("  voltage file.vx        Compile and run a .vx file with the JIT, like --backend=jit");
             println!("  voltage --repl         Run in REPL mode");
+            println!("  voltage test file.v    Run the test_ functions in file.v");
+            println!("  voltage self completions SHELL  Print a completion script for SHELL");
+            println!("  voltage self info [--language]  Print the version and what this build supports");
+            println!("  voltage --build file.v Compile to file.vbc without running it");
+            println!("  voltage check file.v   Report errors and warnings in file.v without running it");
+            println!("  voltage check --message-format=json file.v  Report them as JSON lines on stdout");
+            println!("  voltage --color=auto|always|never file.v   Whether to color errors and warnings");
+            println!("  voltage --allow-fs[=DIR] file.v   Let the script use files (only inside DIR)");
+            println!("  voltage --no-env file.v           Keep the script out of environment variables");
+            println!("  voltage --cache-dir[=DIR] file.v  Reuse the compiled script from an earlier run");
+            println!("  voltage --keep-all file.v         Keep functions that nothing calls");
+            println!("  voltage -O file.v                 Optimize: propagate constants, inline small functions");
+            println!("  voltage --deny-warnings file.v    Treat warnings as errors");
+            println!("  voltage --backend=vm|jit file.v   Run on the bytecode VM, or compile to machine code");
+            println!("  voltage --debug file.v            Step through file.v (step, continue, break LINE, print)");
+            println!("  voltage --emit=bytecode|constants file.v  Print the compiled file.v instead of running it");
+            println!("  voltage --coverage [--lcov=PATH] file.v  Report which lines of file.v ran");
+            println!("  voltage --max-call-depth=N --max-stack=N --fuel=N file.v  Stop file.v past these limits");
+            println!("  voltage --verbose file.v          Show the VM's state on a runtime error");
             
             // Example of the syntax
             println!("\nExample syntax:");
@@ -96,51 +445,404 @@ fn main() {
     }
 }
 
-fn run_voltage_file(file: &str) {
-    println!("Running Voltage file: {}", file);
-    
-    // Read the source code from the file
+fn self_command(command: &SelfCommand) {
+    match command {
+        SelfCommand::Completions { shell } => {
+            clap_complete::generate(*shell, &mut Cli::command(), "voltagec", &mut io::stdout());
+        }
+        SelfCommand::Info { language } => {
+            // fs and env are always built in, and each script is given them or not
+            let mut capabilities = vec!["fs", "env"];
+            if voltage_vm::BuiltinRegistry::new().id("parse_json").is_some() {
+                capabilities.push("json");
+            }
+            println!("voltagec {}", env!("CARGO_PKG_VERSION"));
+            println!("bytecode format: {}", voltage_vm::BYTECODE_VERSION);
+            println!("capabilities: {}", capabilities.join(", "));
+            // The JIT generates code for the machine it runs on
+            println!("jit target: {}", env!("VOLTAGEC_TARGET"));
+            if *language {
+                println!();
+                print!("evaluation order:\n{}", voltage_vm::EVALUATION_ORDER);
+            }
+        }
+    }
+}
+
+/// Compiles a script as `options` say, leaving out the functions nothing
+/// calls unless `keep_all`, and prints a warning for each one left out.
+fn compile_script(
+    source: &str,
+    keep_all: bool,
+    options: CompilerOptions,
+    modules: &ModuleLoader,
+    report: &Reporter,
+) -> Result<Program, VoltageError> {
+    if keep_all {
+        return voltage_vm::compile_with_options(source, None, modules, options).map(|(program, _)| program);
+    }
+    let (program, warnings) = voltage_vm::compile_with_options(source, Some(&[]), modules, options)?;
+    report.diagnostics(&warnings);
+    Ok(program)
+}
+
+/// Prints every syntax error in `file`, or if there are none, the compile
+/// error or warnings, and returns whether there were no errors. Errors the
+/// parser ran into while recovering from an earlier one are left out.
+fn check_voltage_file(file: &str, compiler: CompilerOptions, modules: &ModuleLoader, options: ReportOptions) -> bool {
     let source = fs::read_to_string(file)
         .expect("Should have been able to read the file");
-        
-    // Tokenize the source
-    let lexer = Lexer::new(source);
-    let tokens = lexer.tokenize();
-    
-    // Parse the tokens into AST
-    let mut parser = Parser::new(tokens.to_vec());
-    let ast = parser.parse();
-    
-    // Find and execute the main function
-    for stmt in ast {
-        if let Statement::Function(func) = stmt {
-            if func.name == "main" {
-                // Compile to bytecode using the Voltage Engine
-                let mut compiler = BytecodeCompiler::new();
-                match compiler.compile_function(&func) {
-                    Ok((bytecode, constants)) => {
-                        println!("Successfully compiled to bytecode!");
-                        println!("Bytecode length: {}", bytecode.len());
-                        println!("Constants count: {}", constants.len());
-                        
-                        // Create and run the VM
-                        let mut vm = VirtualMachine::new();
-                        vm.load_bytecode(bytecode, constants);
-                        
-                        match vm.run() {
-                            Ok(result) => println!("Program completed with result: {:?}", result),
-                            Err(e) => eprintln!("Runtime error: {}", e),
-                        }
-                        return; // Successfully executed
-                    }
-                    Err(e) => {
-                        eprintln!("Compilation error: {}", e);
-                        return;
-                    }
-                }
+    let report = Reporter::new(options, file, &source);
+
+    let parsed = parse_with_diagnostics(&source);
+    let mut diagnostics = parsed.diagnostics;
+    if diagnostics.is_empty() {
+        // Compiled as running it would, keeping the warnings from before an error
+        let mut compiler = BytecodeCompiler::with_options(compiler);
+        compiler.use_modules(modules);
+        compiler.eliminate_dead_code(std::iter::empty::<&str>());
+        match compiler.compile_script(&parsed.statements) {
+            // The warnings it denies are among the warnings, as errors
+            Err(e) if e.kind == CompileErrorKind::DeniedWarning => {}
+            Err(e) => diagnostics.push(Diagnostic::from(e)),
+            Ok(_) => {}
+        }
+        diagnostics.extend_from_slice(compiler.warnings());
+    }
+    report.diagnostics(&diagnostics);
+    !diagnostics.iter().any(Diagnostic::is_error)
+}
+
+/// Reads and evaluates lines from stdin until it ends, answering each in JSON
+/// with `json` and otherwise interactively.
+fn run_repl(cli: &Cli, json: bool) {
+    let mut repl_instance = repl::Repl::new();
+    if let Some(access) = fs_access(cli) {
+        repl_instance.builtins_mut().enable_fs(access);
+    }
+    if let Some(access) = env_access(cli) {
+        repl_instance.builtins_mut().enable_env(access);
+    }
+    if json {
+        if let Err(e) = repl_instance.run_json(io::stdin().lock(), io::stdout().lock()) {
+            eprintln!("Error reading input: {}", e);
+            process::exit(1);
+        }
+    } else {
+        repl_instance.run();
+    }
+}
+
+/// Formats FILE in place, or with `check` only says whether it would change.
+/// Fails if FILE doesn't parse.
+fn format_voltage_file(file: &str, check: bool, options: ReportOptions) -> bool {
+    let source = fs::read_to_string(file)
+        .expect("Should have been able to read the file");
+    let formatted = match format::format_source(&source) {
+        Ok(formatted) => formatted,
+        Err(diagnostics) => {
+            Reporter::new(options, file, &source).diagnostics(&diagnostics);
+            return false;
+        }
+    };
+    if formatted == source {
+        return true;
+    }
+    if check {
+        eprintln!("{} isn't formatted", file);
+        return false;
+    }
+    if let Err(e) = fs::write(file, formatted) {
+        eprintln!("Could not write {}: {}", file, e);
+        return false;
+    }
+    true
+}
+
+/// Writes the compiled program next to the source, unless the bytecode already
+/// there is the same program.
+fn build_voltage_file(file: &str, keep_all: bool, compiler: CompilerOptions, modules: &ModuleLoader, options: ReportOptions) {
+    let source = fs::read_to_string(file)
+        .expect("Should have been able to read the file");
+    let report = Reporter::new(options, file, &source);
+
+    let program = match compile_script(&source, keep_all, compiler, modules, &report) {
+        Ok(program) => program,
+        Err(e) => {
+            report.error(&e);
+            process::exit(1);
+        }
+    };
+
+    let output = Path::new(file).with_extension("vbc");
+    let existing = fs::read(&output).ok().and_then(|bytes| Program::from_bytes(&bytes).ok());
+    if existing.is_some_and(|existing| existing.fingerprint() == program.fingerprint()) {
+        report.status(&format!("{} is up to date", output.display()));
+        return;
+    }
+
+    if let Err(e) = fs::write(&output, program.to_bytes()) {
+        eprintln!("Could not write {}: {}", output.display(), e);
+        process::exit(1);
+    }
+    report.status(&format!("Wrote {}", output.display()));
+}
+
+/// Prints what `--emit` asks for from the compiled `file`, then a warning for
+/// each constant nothing loads, which the compiler needn't have kept.
+fn emit_voltage_file(
+    file: &str,
+    emit: Emit,
+    keep_all: bool,
+    compiler: CompilerOptions,
+    modules: &ModuleLoader,
+    options: ReportOptions,
+) {
+    let source = fs::read_to_string(file)
+        .expect("Should have been able to read the file");
+    let report = Reporter::new(options, file, &source);
+
+    let program = match compile_script(&source, keep_all, compiler, modules, &report) {
+        Ok(program) => program,
+        Err(e) => {
+            report.error(&e);
+            process::exit(1);
+        }
+    };
+
+    match emit {
+        Emit::Bytecode => print!("{}", disassemble(&program, Some(&source))),
+        Emit::Constants => print!("{}", list_constants(&program.constants)),
+        Emit::Callgraph => print!("{}", call_graph(&program)),
+    }
+    let unused: Vec<Diagnostic> = unused_constants(&program.bytecode, &program.constants)
+        .into_iter()
+        .map(|index| Diagnostic::warning(format!("constant {} is never loaded", index), Span::default()))
+        .map(|warning| warning.with_code("unused_constant"))
+        .collect();
+    report.diagnostics(&unused);
+}
+
+/// Runs `file` under the debugger, taking commands from stdin. Every function
+/// is kept, so all of them can be stepped through.
+fn debug_voltage_file(
+    file: &str,
+    config: VmConfig,
+    options: CompilerOptions,
+    modules: &ModuleLoader,
+    fs_access: Option<FsAccess>,
+    env_access: Option<EnvAccess>,
+) {
+    let source = fs::read_to_string(file)
+        .expect("Should have been able to read the file");
+
+    let program = match voltage_vm::compile_with_options(&source, None, modules, options) {
+        Ok((program, _)) => program,
+        Err(e) => {
+            print_error(&e);
+            process::exit(1);
+        }
+    };
+
+    let mut vm = VirtualMachine::with_config(config);
+    if let Some(access) = fs_access {
+        vm.builtins_mut().enable_fs(access);
+    }
+    if let Some(access) = env_access {
+        vm.builtins_mut().enable_env(access);
+    }
+    if let Err(e) = debugger::debug(&mut vm, program, &mut io::stdin().lock(), &mut io::stdout()) {
+        eprintln!("{}", e);
+    }
+}
+
+/// Compiles `file` with the JIT and runs its `main`. The VM's compiler checks
+/// it first, so errors and warnings read as they would on the VM; then
+/// [`JitSupport`] lists each part of each function the JIT can't compile yet.
+/// `config`'s call depth and fuel limits apply, fuel counting loop
+/// iterations.
+fn jit_voltage_file(
+    file: &str,
+    config: VmConfig,
+    keep_all: bool,
+    compiler: CompilerOptions,
+    modules: &ModuleLoader,
+    options: ReportOptions,
+) {
+    let source = fs::read_to_string(file)
+        .expect("Should have been able to read the file");
+    let report = Reporter::new(options, file, &source);
+
+    if let Err(e) = compile_script(&source, keep_all, compiler, modules, &report) {
+        report.error(&e);
+        process::exit(1);
+    }
+    let program = parse_with_diagnostics(&source).statements;
+
+    let unsupported = JitSupport::check(&program);
+    if !unsupported.is_empty() {
+        report_jit_errors(&report, options, &format!("{} can't run on the JIT backend:", file), &unsupported);
+        process::exit(1);
+    }
+
+    report.status(&format!("Running Voltage file: {}", file));
+    let mut jit = JitCompiler::new();
+    jit.set_limits(config.max_call_depth, config.fuel);
+    if let Err(errors) = jit.compile_program(&program) {
+        report_jit_errors(&report, options, &format!("{} failed to compile on the JIT backend:", file), &errors);
+        process::exit(1);
+    }
+    if !jit.has_function("main") {
+        return;
+    }
+
+    let result = jit.run("main", &[]);
+    // What the script printed comes before the error, as on the VM
+    let _ = io::stdout().flush();
+    if let Err(message) = result {
+        match options.format {
+            MessageFormat::Human => eprintln!("Runtime error: {}", message),
+            MessageFormat::Json => {
+                report.diagnostics(&[Diagnostic::new(message, Span::default()).with_code("runtime_error")])
             }
         }
+        process::exit(1);
     }
-    
-    eprintln!("No main function found in {}", file);
-}
\ No newline at end of file
+}
+
+/// Prints `errors` from the JIT under `heading`, one per line, or as JSON
+/// diagnostics.
+fn report_jit_errors(report: &Reporter, options: ReportOptions, heading: &str, errors: &[JitError]) {
+    match options.format {
+        MessageFormat::Human => {
+            eprintln!("{}", heading);
+            for error in errors {
+                eprintln!("  {}", error);
+            }
+        }
+        MessageFormat::Json => {
+            let diagnostics: Vec<Diagnostic> = errors
+                .iter()
+                .map(|error| Diagnostic::new(format!("{}: {}", error.function, error.message), error.span).with_code("jit_unsupported"))
+                .collect();
+            report.diagnostics(&diagnostics);
+        }
+    }
+}
+
+/// Runs the tests in `file`, returning whether they all passed.
+fn test_voltage_file(
+    file: &str,
+    filter: Option<&str>,
+    mut engine: Engine,
+    coverage: Option<CoverageReport>,
+) -> bool {
+    let source = fs::read_to_string(file)
+        .expect("Should have been able to read the file");
+
+    let passed = match test_runner::run_tests(&mut engine, &source, filter, &mut io::stdout()) {
+        Ok(summary) => summary.success(),
+        Err(e) => {
+            print_error(&e);
+            false
+        }
+    };
+    if let Some(coverage) = coverage {
+        coverage.write(&engine, file);
+    }
+    passed
+}
+
+fn run_voltage_file(
+    file: &str,
+    mut engine: Engine,
+    cache: Option<CompileCache>,
+    keep_all: bool,
+    compiler: CompilerOptions,
+    options: ReportOptions,
+    coverage: Option<CoverageReport>,
+) {
+    let bytes = fs::read(file)
+        .expect("Should have been able to read the file");
+    // A `.vbc` file from --build runs as it was compiled
+    let compiled = Program::is_container(&bytes);
+    let source = match compiled {
+        true => String::new(),
+        false => String::from_utf8(bytes.clone()).unwrap_or_else(|_| {
+            eprintln!("Error: {} is neither UTF-8 source code nor a Voltage bytecode file", file);
+            process::exit(1);
+        }),
+    };
+    let report = Reporter::new(options, file, &source);
+    report.status(&format!("Running Voltage file: {}", file));
+
+    let result = match compiled {
+        true => run_bytecode(&mut engine, &bytes, &report),
+        false => run_script(&mut engine, &source, cache, keep_all, compiler, &report),
+    };
+    if let Err(e) = &result {
+        report.error(e);
+    }
+    // Coverage is reported even when the script fails, up to where it failed
+    if let Some(coverage) = coverage {
+        coverage.write(&engine, file);
+    }
+    // A panic exits like one in Rust; any other error, including hitting a
+    // limit, exits with 1
+    match result {
+        Err(VoltageError::Panic(_)) => process::exit(PANIC_EXIT_CODE),
+        Err(_) => process::exit(1),
+        Ok(()) => {}
+    }
+}
+
+/// The exit code when a script calls `panic`, as a Rust program's would be.
+const PANIC_EXIT_CODE: i32 = 101;
+
+fn run_script(
+    engine: &mut Engine,
+    source: &str,
+    cache: Option<CompileCache>,
+    keep_all: bool,
+    compiler: CompilerOptions,
+    report: &Reporter,
+) -> Result<(), VoltageError> {
+    // Loading runs the top-level statements, which is all a script without
+    // `main` has
+    let program = match &cache {
+        Some(cache) => cache.load_or_compile(source, engine.builtins(), engine.modules()).map(|(program, warnings)| {
+            report.diagnostics(&warnings);
+            // The cache keeps programs as compiled, so that -O makes no
+            // difference to it
+            match compiler.optimize {
+                true => voltage_vm::optimize(program),
+                false => program,
+            }
+        }),
+        None => compile_script(source, keep_all, compiler, engine.modules(), report),
+    };
+    program.and_then(|program| engine.load_program(program))?;
+    run_main(engine, report)
+}
+
+/// Loads the compiled program in `bytes` and runs it as [`run_script`] runs
+/// a script.
+fn run_bytecode(engine: &mut Engine, bytes: &[u8], report: &Reporter) -> Result<(), VoltageError> {
+    let program = Program::from_bytes(bytes).unwrap_or_else(|message| {
+        eprintln!("Error: {}", message);
+        process::exit(1);
+    });
+    engine.load_program(program)?;
+    run_main(engine, report)
+}
+
+// Calls the loaded program's `main`, if it has one
+fn run_main(engine: &mut Engine, report: &Reporter) -> Result<(), VoltageError> {
+    if engine.get_global("main").is_none() {
+        return Ok(());
+    }
+
+    let result = engine.call("main", &[])?;
+    report.status(&format!("Program completed with result: {:?}", result));
+    Ok(())
+}
@@ -0,0 +1,185 @@
+//! A directory of compiled scripts, so running an unchanged script again can
+//! skip lexing, parsing and compiling.
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use voltage_core::StatementKind;
+use voltage_parser::{parse_with_diagnostics, Diagnostic};
+use voltage_vm::{content_hash, validate, BuiltinRegistry, ModuleLoader, Program, VoltageError, BYTECODE_VERSION};
+
+/// Compiled programs saved as `.vbc` files, named by a hash of the source and
+/// the compiler version. An entry that can't be read back is recompiled and
+/// overwritten, so a damaged cache only costs time.
+#[derive(Debug, Clone)]
+pub struct CompileCache {
+    dir: PathBuf,
+    keep_all: bool,
+}
+
+impl CompileCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into(), keep_all: false }
+    }
+
+    /// Whether programs keep the functions that nothing calls, as with
+    /// `--keep-all`. Both kinds are cached separately.
+    pub fn keep_all(mut self, keep_all: bool) -> Self {
+        self.keep_all = keep_all;
+        self
+    }
+
+    /// The platform's per-user cache directory, with a `voltage` directory in
+    /// it: `$XDG_CACHE_HOME` or `~/.cache` on Unix, `%LOCALAPPDATA%` on Windows.
+    pub fn default_dir() -> Option<PathBuf> {
+        let base = if cfg!(windows) {
+            env::var_os("LOCALAPPDATA").map(PathBuf::from)
+        } else {
+            env::var_os("XDG_CACHE_HOME")
+                .map(PathBuf::from)
+                .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+        };
+        base.map(|base| base.join("voltage"))
+    }
+
+    /// Where the compiled form of `source` is kept.
+    pub fn entry_path(&self, source: &str) -> PathBuf {
+        let key = format!("{}\0{}\0{}\0{}", env!("CARGO_PKG_VERSION"), BYTECODE_VERSION, self.keep_all, source);
+        self.dir.join(format!("{:016x}.vbc", content_hash(key.as_bytes())))
+    }
+
+    /// The cached program for `source`, or a freshly compiled one that is
+    /// saved for next time, with the warnings from compiling it. Cached
+    /// programs keep their source map, and are checked against `builtins`
+    /// the same way the VM checks them before running. Scripts compiled with
+    /// warnings aren't saved, so the warnings are shown on every run; nor are
+    /// scripts importing module files from `modules`' search paths, since the
+    /// key doesn't cover the modules.
+    pub fn load_or_compile(
+        &self,
+        source: &str,
+        builtins: &BuiltinRegistry,
+        modules: &ModuleLoader,
+    ) -> Result<(Program, Vec<Diagnostic>), VoltageError> {
+        let exports: Option<&[&str]> = (!self.keep_all).then_some(&[]);
+        let compile = || {
+            let (program, warnings) = voltage_vm::compile_with_modules(source, exports, modules)?;
+            Ok(if self.keep_all { (program, Vec::new()) } else { (program, warnings) })
+        };
+        if imports_module_files(source, modules) {
+            return compile();
+        }
+
+        let path = self.entry_path(source);
+        let cached = fs::read(&path)
+            .ok()
+            .and_then(|bytes| Program::from_bytes(&bytes).ok())
+            .filter(|program| validate(&program.bytecode, &program.constants, &program.functions, builtins).is_ok());
+        if let Some(program) = cached {
+            return Ok((program, Vec::new()));
+        }
+
+        let (program, warnings) = compile()?;
+        if warnings.is_empty() {
+            // Failing to save only means compiling again next time
+            let _ = fs::create_dir_all(&self.dir).and_then(|_| fs::write(&path, program.to_bytes_with_source_map()));
+        }
+        Ok((program, warnings))
+    }
+}
+
+// Whether `source` imports a module that `modules` doesn't have, which
+// would be looked for in its search paths
+fn imports_module_files(source: &str, modules: &ModuleLoader) -> bool {
+    parse_with_diagnostics(source).statements.iter().any(|stmt| match &stmt.kind {
+        StatementKind::Import(path) | StatementKind::ImportAs(path, _) => modules.get(path).is_none(),
+        _ => false,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io::{self, Write};
+    use std::rc::Rc;
+    use voltage_vm::Engine;
+
+    const SOURCE: &str = "fn main() { let total = 2 * 21; puts(\"total is {}\", total); }";
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn cache(name: &str) -> CompileCache {
+        let dir = env::temp_dir().join(format!("voltage_cache_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        CompileCache::new(dir)
+    }
+
+    // Runs `main` from the cached or compiled program and returns its output
+    fn run(cache: &CompileCache) -> String {
+        let output = SharedBuffer::default();
+        let mut engine = Engine::new();
+        engine.set_output(output.clone());
+        let (program, _) = cache.load_or_compile(SOURCE, engine.builtins(), engine.modules()).unwrap();
+        engine.load_program(program).unwrap();
+        engine.call("main", &[]).unwrap();
+        let bytes = output.0.borrow().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_miss_compiles_and_populates() {
+        let cache = cache("miss");
+        assert!(!cache.entry_path(SOURCE).exists());
+        assert_eq!(run(&cache), "total is 42\n");
+        assert!(cache.entry_path(SOURCE).exists());
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn test_hit_uses_stored_program() {
+        let cache = cache("hit");
+        fs::create_dir_all(&cache.dir).unwrap();
+        let path = cache.entry_path(SOURCE);
+        fs::write(&path, voltage_vm::compile(SOURCE).unwrap().to_bytes()).unwrap();
+        assert_eq!(run(&cache), "total is 42\n");
+
+        // A different program stored under this source's key; only a hit can print it
+        let planted = voltage_vm::compile("fn main() { puts(\"from the cache\"); }").unwrap();
+        fs::write(&path, planted.to_bytes()).unwrap();
+        assert_eq!(run(&cache), "from the cache\n");
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn test_corrupted_entry_is_recompiled() {
+        let cache = cache("corrupt");
+        fs::create_dir_all(&cache.dir).unwrap();
+        let path = cache.entry_path(SOURCE);
+        fs::write(&path, b"VBC\0\x01\x00garbage").unwrap();
+
+        assert_eq!(run(&cache), "total is 42\n");
+        let repaired = Program::from_bytes(&fs::read(&path).unwrap()).unwrap();
+        assert_eq!(repaired.fingerprint(), voltage_vm::compile(SOURCE).unwrap().fingerprint());
+        fs::remove_dir_all(&cache.dir).unwrap();
+    }
+
+    #[test]
+    fn test_key_depends_on_source() {
+        let cache = CompileCache::new("cache");
+        assert_eq!(cache.entry_path(SOURCE), cache.entry_path(SOURCE));
+        assert_ne!(cache.entry_path(SOURCE), cache.entry_path("fn main() { }"));
+        assert_ne!(cache.entry_path(SOURCE), cache.clone().keep_all(true).entry_path(SOURCE));
+    }
+}
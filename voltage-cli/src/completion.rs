@@ -0,0 +1,147 @@
+use std::cell::RefCell;
+use std::collections::BTreeSet;
+use std::rc::Rc;
+use rustyline::completion::Completer;
+use rustyline::{Context, Helper, Highlighter, Hinter, Validator};
+use voltage_parser::KEYWORDS;
+use voltage_vm::BUILTINS;
+use crate::repl::SessionState;
+
+/// REPL meta-commands, typed after a leading `:`.
+pub const COMMANDS: &[&str] = &["history", "load", "locals", "reset"];
+
+/// Line editor helper that completes keywords, builtins, meta-commands and
+/// whatever the session has defined so far.
+#[derive(Helper, Highlighter, Hinter, Validator)]
+pub struct ReplHelper {
+    state: Rc<RefCell<SessionState>>,
+    // The functions in the session's builtin registry, besides the VM's own `BUILTINS`
+    builtins: Vec<String>,
+}
+
+impl ReplHelper {
+    pub fn new(state: Rc<RefCell<SessionState>>, builtins: Vec<String>) -> Self {
+        Self { state, builtins }
+    }
+
+    /// Returns where the word before `pos` starts and the sorted candidates
+    /// that complete it.
+    pub fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let before = &line[..pos];
+        if inside_string_literal(before) {
+            return (pos, Vec::new());
+        }
+
+        let start = before
+            .char_indices()
+            .rev()
+            .find(|(_, c)| !(c.is_alphanumeric() || *c == '_'))
+            .map_or(0, |(i, c)| i + c.len_utf8());
+        let word = &before[start..];
+
+        if before[..start].trim_start() == ":" {
+            let commands = COMMANDS
+                .iter()
+                .filter(|command| command.starts_with(word))
+                .map(|command| command.to_string())
+                .collect();
+            return (start, commands);
+        }
+
+        if word.is_empty() {
+            return (start, Vec::new());
+        }
+
+        let state = self.state.borrow();
+        let names: BTreeSet<&str> = KEYWORDS
+            .iter()
+            .chain(BUILTINS)
+            .copied()
+            .chain(self.builtins.iter().map(String::as_str))
+            .chain(state.functions.keys().map(String::as_str))
+            .chain(state.globals.iter().map(String::as_str))
+            .filter(|name| name.starts_with(word))
+            .collect();
+
+        (start, names.into_iter().map(str::to_string).collect())
+    }
+}
+
+impl Completer for ReplHelper {
+    type Candidate = String;
+
+    fn complete(&self, line: &str, pos: usize, _ctx: &Context<'_>) -> rustyline::Result<(usize, Vec<String>)> {
+        Ok(self.candidates(line, pos))
+    }
+}
+
+// An odd number of unescaped quotes means the cursor sits inside a string
+fn inside_string_literal(text: &str) -> bool {
+    let mut inside = false;
+    let mut escaped = false;
+    for c in text.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if inside => escaped = true,
+            '"' => inside = !inside,
+            _ => {}
+        }
+    }
+    inside
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::Repl;
+
+    fn complete(helper: &ReplHelper, line: &str) -> (usize, Vec<String>) {
+        helper.candidates(line, line.len())
+    }
+
+    #[test]
+    fn test_completes_keywords_and_builtins() {
+        let helper = Repl::new().completer();
+        assert_eq!(complete(&helper, "wh"), (0, vec!["while".to_string()]));
+        assert_eq!(complete(&helper, "let x = pu"), (8, vec!["pub".to_string(), "puts".to_string()]));
+        assert_eq!(complete(&helper, "el"), (0, vec!["elif".to_string(), "else".to_string()]));
+        // Builtins from the registry as well as the VM's own
+        assert_eq!(complete(&helper, "form"), (0, vec!["format".to_string()]));
+    }
+
+    #[test]
+    fn test_completes_session_definitions_as_they_are_made() {
+        let mut repl = Repl::new();
+        let helper = repl.completer();
+        assert_eq!(complete(&helper, "squ").1, Vec::<String>::new());
+
+        repl.process_input("fn square(n) { return n * n; }").unwrap();
+        repl.process_input("let squad = 4;").unwrap();
+        assert_eq!(complete(&helper, "squ"), (0, vec!["squad".to_string(), "square".to_string()]));
+        assert_eq!(complete(&helper, "square(squa"), (7, vec!["squad".to_string(), "square".to_string()]));
+    }
+
+    #[test]
+    fn test_completes_meta_commands_after_colon() {
+        let helper = Repl::new().completer();
+        assert_eq!(complete(&helper, ":loa"), (1, vec!["load".to_string()]));
+        assert_eq!(complete(&helper, ":re"), (1, vec!["reset".to_string()]));
+        assert_eq!(complete(&helper, ":"), (1, ["history", "load", "locals", "reset"].map(String::from).to_vec()));
+        // Commands are only offered at the start of the line
+        assert_eq!(complete(&helper, "x :hi").1, Vec::<String>::new());
+    }
+
+    #[test]
+    fn test_uses_cursor_position_not_line_end() {
+        let helper = Repl::new().completer();
+        assert_eq!(helper.candidates("whi + 1", 3), (0, vec!["while".to_string()]));
+    }
+
+    #[test]
+    fn test_no_completion_inside_string_literals() {
+        let helper = Repl::new().completer();
+        assert_eq!(complete(&helper, "puts(\"wh").1, Vec::<String>::new());
+        assert_eq!(complete(&helper, "puts(\"say \\\"wh").1, Vec::<String>::new());
+        assert_eq!(complete(&helper, "puts(\"done\", wh"), (13, vec!["while".to_string()]));
+    }
+}
@@ -0,0 +1,119 @@
+//! `voltagec --debug`: runs a script under a line-oriented debugger.
+
+use std::io::{self, BufRead, Write};
+use voltage_vm::{Program, RuntimeError, RuntimeValue, StepResult, VirtualMachine};
+
+/// Loads `program` into `vm` and runs it under the commands read from
+/// `input`, one per line:
+///
+/// - `step` (`s`) runs one instruction
+/// - `continue` (`c`) runs to the next breakpoint or the end
+/// - `break LINE` (`b LINE`) stops at the statements on LINE
+/// - `print` (`p`) shows the running function's variables and the stack
+/// - `quit` (`q`) stops debugging
+///
+/// The top-level code runs first, then `main` if the script defines one.
+/// Where the VM stopped and how the program ended are written to `out`.
+pub fn debug(vm: &mut VirtualMachine, program: Program, input: &mut dyn BufRead, out: &mut dyn Write) -> io::Result<()> {
+    if let Err(e) = vm.load_program(program) {
+        return writeln!(out, "Compile error: {}", e);
+    }
+    let mut in_main = false;
+    report_position(vm, out)?;
+
+    for line in input.lines() {
+        let line = line?;
+        let mut words = line.split_whitespace();
+        let continuing = match words.next() {
+            Some("step" | "s") => false,
+            Some("continue" | "c") => true,
+            Some("break" | "b") => {
+                match words.next().and_then(|word| word.parse().ok()) {
+                    Some(line) if vm.set_line_breakpoint(line).is_empty() => writeln!(out, "no code on line {}", line)?,
+                    Some(line) => writeln!(out, "breakpoint on line {}", line)?,
+                    None => writeln!(out, "usage: break LINE")?,
+                }
+                continue;
+            }
+            Some("print" | "p") => {
+                let locals: Vec<String> = vm.locals_snapshot().iter().map(|(name, value)| format!("{} = {}", name, value)).collect();
+                writeln!(out, "locals: [{}]", locals.join(", "))?;
+                writeln!(out, "stack: {}", list(&vm.stack_snapshot()))?;
+                continue;
+            }
+            Some("quit" | "q") => return Ok(()),
+            Some(other) => {
+                writeln!(out, "unknown command: {}", other)?;
+                continue;
+            }
+            None => continue,
+        };
+
+        let mut result = advance(vm, continuing);
+        // Once the top-level code is done, carry on into `main`
+        if let Ok(StepResult::Finished(_)) = result {
+            if !in_main && vm.get_global("main").is_some() {
+                in_main = true;
+                writeln!(out, "entering main")?;
+                result = vm.enter_function("main", &[]).and_then(|_| {
+                    if continuing {
+                        advance(vm, true)
+                    } else {
+                        Ok(StepResult::Running { ip: vm.ip() })
+                    }
+                });
+            }
+        }
+
+        match result {
+            Ok(StepResult::Running { .. }) => report_position(vm, out)?,
+            Ok(StepResult::Finished(value)) => return writeln!(out, "finished with {}", value),
+            Err(e) => {
+                writeln!(out, "Runtime error: {}", e)?;
+                return write!(out, "{}", e.backtrace());
+            }
+        }
+    }
+    Ok(())
+}
+
+fn advance(vm: &mut VirtualMachine, continuing: bool) -> Result<StepResult, RuntimeError> {
+    if continuing {
+        vm.continue_until_breakpoint()
+    } else {
+        vm.step()
+    }
+}
+
+fn report_position(vm: &VirtualMachine, out: &mut dyn Write) -> io::Result<()> {
+    match vm.source_map().span_for(vm.ip()) {
+        Some(span) => writeln!(out, "at line {} (instruction {})", span.line, vm.ip()),
+        None => writeln!(out, "at instruction {}", vm.ip()),
+    }
+}
+
+fn list(values: &[RuntimeValue]) -> String {
+    let values: Vec<String> = values.iter().map(ToString::to_string).collect();
+    format!("[{}]", values.join(", "))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_break_continue_and_print() {
+        let source = "fn main() {\n    let a = 20;\n    let b = a + 22;\n    return b;\n}";
+        let program = voltage_vm::compile(source).unwrap();
+        let mut input = "b 3\nc\np\nc\n".as_bytes();
+        let mut out = Vec::new();
+        debug(&mut VirtualMachine::new(), program, &mut input, &mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = out.lines().collect();
+        assert_eq!(lines[1], "breakpoint on line 3");
+        assert_eq!(lines[2], "entering main");
+        assert!(lines[3].starts_with("at line 3"), "{}", out);
+        assert_eq!(&lines[4..], ["locals: [a = 20]", "stack: [20]", "finished with 42"]);
+    }
+}
@@ -0,0 +1,6 @@
+pub mod cache;
+pub mod completion;
+pub mod debugger;
+pub mod repl;
+pub mod report;
+pub mod test_runner;
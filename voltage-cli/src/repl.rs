@@ -1,48 +1,143 @@
-use std::io::{self, Write};
-use voltage_parser::{Lexer, Parser};
-use voltage_jit::JitCompiler;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::time::Instant;
+use rustyline::error::ReadlineError;
+use rustyline::history::DefaultHistory;
+use rustyline::Editor;
+use serde::Serialize;
+use voltage_core::{Statement, StatementKind};
+use voltage_parser::{Diagnostic, Lexer, Parser};
+use voltage_vm::{BuiltinRegistry, BytecodeCompiler, OutputEvent, RuntimeValue, VirtualMachine, VoltageError};
+use crate::completion::ReplHelper;
+use crate::report::{error_diagnostics, JsonDiagnostic};
+
+/// Everything the session has defined so far. Shared with the completer so
+/// new definitions become completion candidates straight away.
+#[derive(Debug, Default)]
+pub struct SessionState {
+    // Function definitions; each input is compiled together with all of these
+    pub functions: BTreeMap<String, Statement>,
+    pub globals: BTreeSet<String>,
+}
 
 pub struct Repl {
-    jit: JitCompiler,
+    vm: VirtualMachine,
+    state: Rc<RefCell<SessionState>>,
+    // Names defined by each loaded file, so reloading a file can drop its old definitions
+    loaded_files: HashMap<PathBuf, Vec<String>>,
+    // The results of the session's expressions, which are `_1`, `_2`, ... in order
+    history: Vec<RuntimeValue>,
+    // What scripts have printed since the last input, when it's kept to
+    // report rather than written to stdout
+    printed: Option<Rc<RefCell<Vec<u8>>>>,
+}
+
+/// Why an input failed: the message the console shows, and the same error
+/// as diagnostics for `--message-format=json`.
+struct Failure {
+    message: String,
+    diagnostics: Vec<Diagnostic>,
+}
+
+impl From<Failure> for String {
+    fn from(failure: Failure) -> Self {
+        failure.message
+    }
+}
+
+/// What `--message-format=json` prints for each input, as one line.
+#[derive(Debug, Serialize)]
+#[serde(untagged)]
+enum Reply<'a> {
+    Success {
+        ok: bool,
+        /// The input's result, or a command's message; `null` for none.
+        value: Option<String>,
+        /// What `print` and `puts` wrote while it ran.
+        printed: String,
+        #[serde(rename = "type")]
+        type_name: &'static str,
+        duration_ms: u64,
+    },
+    Failure {
+        ok: bool,
+        diagnostics: Vec<JsonDiagnostic<'a>>,
+    },
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Repl {
     pub fn new() -> Self {
-        let mut jit = JitCompiler::new();
-        
-        // Declare built-in functions
-        if let Err(e) = jit.declare_builtins() {
-            eprintln!("Error declaring built-ins: {}", e);
+        Self {
+            vm: VirtualMachine::new(),
+            state: Rc::new(RefCell::new(SessionState::default())),
+            loaded_files: HashMap::new(),
+            history: Vec::new(),
+            printed: None,
         }
-        
-        Self { jit }
     }
-    
+
+    /// A completer that follows this session's definitions and offers the
+    /// builtins registered so far.
+    pub fn completer(&self) -> ReplHelper {
+        let builtins = self.vm.builtins().names().map(str::to_string).collect();
+        ReplHelper::new(Rc::clone(&self.state), builtins)
+    }
+
+    /// The builtins available to this session, e.g. to enable file access.
+    pub fn builtins_mut(&mut self) -> &mut BuiltinRegistry {
+        self.vm.builtins_mut()
+    }
+
     pub fn run(&mut self) {
+        let mut editor = match Editor::<ReplHelper, DefaultHistory>::new() {
+            Ok(editor) => editor,
+            Err(e) => {
+                eprintln!("Error starting line editor: {}", e);
+                return;
+            }
+        };
+        editor.set_helper(Some(self.completer()));
+
         println!("Welcome to the Voltage REPL!");
-        println!("Enter Voltage code (type 'exit' to quit)");
-        
+        println!("Enter Voltage code (type 'exit' to quit, ':load <file>' to load a file, ':locals' to list variables, ':history' to list results, ':reset' to start over)");
+
         loop {
-            print!("> ");
-            io::stdout().flush().unwrap(); // Make sure the prompt is displayed
-            
-            let mut input = String::new();
-            io::stdin().read_line(&mut input).expect("Failed to read line");
-            
-            let input = input.trim();
-            
+            let line = match editor.readline("> ") {
+                Ok(line) => line,
+                // Ctrl-C abandons the current line, Ctrl-D ends the session
+                Err(ReadlineError::Interrupted) => continue,
+                Err(ReadlineError::Eof) => break,
+                Err(e) => {
+                    eprintln!("Error reading input: {}", e);
+                    break;
+                }
+            };
+
+            let input = line.trim();
+            if !input.is_empty() {
+                let _ = editor.add_history_entry(input);
+            }
+
             if input == "exit" || input == "quit" {
                 println!("Goodbye!");
                 break;
             }
-            
+
             if input.is_empty() {
                 continue;
             }
-            
-            // For now, just echo back the input with basic processing
-            // In the future, we'd want to parse and execute the code
-            match self.process_input(input.to_string()) {
+
+            match self.process_input(input) {
                 Ok(result) => {
                     if !result.is_empty() {
                         println!("{}", result);
@@ -52,47 +147,418 @@ impl Repl {
             }
         }
     }
-    
-    fn process_input(&mut self, input: String) -> Result<String, String> {
-        // Check if this is a function definition or an expression
-        let source = if input.trim_end().ends_with(';') {
-            // If it ends with semicolon, it's an expression statement
-            format!("fn temp() {{ {} }}", input)
-        } else {
-            // For now, treat everything as needing to be in a function
-            format!("fn temp() {{ {}; }}", input)
+
+    /// Runs the session for another program rather than a person, reading
+    /// one input per line from `input` with no prompt. Each input gets one
+    /// line of JSON on `output`: its value, what it printed and how long it
+    /// took, or the diagnostics for why it failed. Printed text is kept for
+    /// the reply rather than mixed in with it.
+    pub fn run_json(&mut self, input: impl BufRead, mut output: impl Write) -> io::Result<()> {
+        let printed = Rc::new(RefCell::new(Vec::new()));
+        self.printed = Some(Rc::clone(&printed));
+        self.capture_output();
+
+        for line in input.lines() {
+            let line = line?;
+            let input = line.trim();
+            if input == "exit" || input == "quit" {
+                break;
+            }
+            if input.is_empty() {
+                continue;
+            }
+
+            let source = with_semicolon(input);
+            let start = Instant::now();
+            let result = match input.strip_prefix(':') {
+                Some(command) => self.process_command(command.trim()).map(RuntimeValue::String).map_err(|message| {
+                    let diagnostics = vec![Diagnostic::new(message.clone(), Default::default())];
+                    Failure { message, diagnostics }
+                }),
+                None => self.evaluate(input),
+            };
+            let duration_ms = start.elapsed().as_millis() as u64;
+            let printed = String::from_utf8_lossy(&std::mem::take(&mut *printed.borrow_mut())).into_owned();
+
+            let reply = match &result {
+                Ok(value) => Reply::Success {
+                    ok: true,
+                    value: (*value != RuntimeValue::Null).then(|| value.to_string()),
+                    printed,
+                    type_name: value.type_name(),
+                    duration_ms,
+                },
+                Err(failure) => Reply::Failure {
+                    ok: false,
+                    diagnostics: failure
+                        .diagnostics
+                        .iter()
+                        .map(|diagnostic| JsonDiagnostic::new("<repl>", diagnostic, &source))
+                        .collect(),
+                },
+            };
+            writeln!(output, "{}", serde_json::to_string(&reply).expect("replies serialize"))?;
+            output.flush()?;
+        }
+        Ok(())
+    }
+
+    pub(crate) fn process_input(&mut self, input: &str) -> Result<String, String> {
+        if let Some(command) = input.strip_prefix(':') {
+            return self.process_command(command.trim());
+        }
+        match self.evaluate(input)? {
+            RuntimeValue::Null => Ok(String::new()),
+            value => Ok(value.to_string()),
+        }
+    }
+
+    /// Runs a line of code and returns its result, which is kept for later
+    /// inputs if it has one.
+    fn evaluate(&mut self, input: &str) -> Result<RuntimeValue, Failure> {
+        let statements = parse_source(&with_semicolon(input), true)?;
+        let value = self.execute(statements)?;
+        if value != RuntimeValue::Null {
+            self.remember(value.clone());
+        }
+        Ok(value)
+    }
+
+    /// Sends what scripts print with `print` and `puts` to `printed`, to be
+    /// reported with each input's result. `dbg` still goes to stderr.
+    fn capture_output(&mut self) {
+        let Some(printed) = self.printed.clone() else {
+            return;
         };
-        
-        // Tokenize
-        let lexer = Lexer::new(source);
-        let tokens = lexer.tokenize().to_vec();
-        
-        // Parse
-        let mut parser = Parser::new(tokens);
-        let ast = parser.parse();
-        
-        // Find the function and compile it
-        for stmt in ast {
-            if let voltage_core::Statement::Function(func) = stmt {
-                if func.name == "temp" {
-                    // Try to compile the temporary function
-                    self.jit.compile_function(&func)?;
-                    return Ok("Compiled successfully".to_string());
+        self.vm.set_output_handler(move |event| {
+            // Writing to memory can't fail, and stderr failing isn't the script's fault
+            let _ = match event {
+                OutputEvent::Debug(_) => event.write_to(&mut io::stderr()),
+                _ => event.write_to(&mut *printed.borrow_mut()),
+            };
+        });
+    }
+
+    /// Keeps the result of an expression as `_`, and as `_N` for the Nth
+    /// result of the session, for later inputs to use.
+    fn remember(&mut self, value: RuntimeValue) {
+        self.history.push(value.clone());
+        self.vm.set_global(&format!("_{}", self.history.len()), value.clone());
+        self.vm.set_global("_", value);
+    }
+
+    fn process_command(&mut self, command: &str) -> Result<String, String> {
+        let (name, argument) = command.split_once(char::is_whitespace).unwrap_or((command, ""));
+        match name {
+            "load" if !argument.trim().is_empty() => self.load_file(Path::new(argument.trim())),
+            "load" => Err("Usage: :load <file>".to_string()),
+            "locals" => Ok(self.locals()),
+            "history" => Ok(self.history()),
+            "reset" => {
+                self.reset();
+                Ok("Session reset".to_string())
+            }
+            _ => Err(format!("Unknown command: :{}", name)),
+        }
+    }
+
+    /// The session's variables and their values, one per line.
+    fn locals(&self) -> String {
+        let state = self.state.borrow();
+        let bindings: Vec<String> = state
+            .globals
+            .iter()
+            .filter_map(|name| Some(format!("{} = {}", name, self.vm.get_global(name)?)))
+            .collect();
+        match bindings.is_empty() {
+            true => "No variables defined".to_string(),
+            false => bindings.join("\n"),
+        }
+    }
+
+    /// The session's results so far, numbered, one per line.
+    fn history(&self) -> String {
+        let results: Vec<String> =
+            self.history.iter().enumerate().map(|(i, value)| format!("_{} = {}", i + 1, value)).collect();
+        match results.is_empty() {
+            true => "No results yet".to_string(),
+            false => results.join("\n"),
+        }
+    }
+
+    /// Forgets everything the session has defined and starts over on a fresh
+    /// VM. The builtins, and any file access granted to them, carry over.
+    fn reset(&mut self) {
+        let builtins = self.vm.builtins().clone();
+        self.vm = VirtualMachine::new();
+        *self.vm.builtins_mut() = builtins;
+        self.capture_output();
+
+        // The completer shares the state, so clear it rather than replace it
+        let mut state = self.state.borrow_mut();
+        state.functions.clear();
+        state.globals.clear();
+        self.loaded_files.clear();
+        self.history.clear();
+    }
+
+    /// Parses a file and brings its functions and globals into the session.
+    /// Loading the same file again replaces whatever it defined last time.
+    fn load_file(&mut self, path: &Path) -> Result<String, String> {
+        let source = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        let statements = parse_source(&source, false)?;
+
+        let path = path.canonicalize().unwrap_or_else(|_| path.to_path_buf());
+        if let Some(names) = self.loaded_files.remove(&path) {
+            let mut state = self.state.borrow_mut();
+            for name in names {
+                state.functions.remove(&name);
+                state.globals.remove(&name);
+                self.vm.remove_global(&name);
+            }
+        }
+
+        let names: Vec<String> = statements
+            .iter()
+            .filter_map(|stmt| match &stmt.kind {
+                StatementKind::Function(func) => Some(func.name.clone()),
+                StatementKind::VariableDeclaration { name, .. } => Some(name.clone()),
+                _ => None,
+            })
+            .collect();
+
+        self.execute(statements)?;
+
+        let message = format!("Loaded {} definitions from {}", names.len(), path.display());
+        self.loaded_files.insert(path, names);
+        Ok(message)
+    }
+
+    /// Records any function definitions, then compiles the remaining statements
+    /// together with every known function and runs them on the session's VM.
+    fn execute(&mut self, statements: Vec<Statement>) -> Result<RuntimeValue, Failure> {
+        let mut functions = self.state.borrow().functions.clone();
+        let mut globals = Vec::new();
+        let mut top_level = Vec::new();
+        for stmt in statements {
+            match &stmt.kind {
+                StatementKind::Function(func) => {
+                    functions.insert(func.name.clone(), stmt);
+                }
+                kind => {
+                    if let StatementKind::VariableDeclaration { name, .. } = kind {
+                        globals.push(name.clone());
+                    }
+                    top_level.push(stmt);
                 }
             }
         }
-        
-        Ok("Processed".to_string())
+
+        let mut program: Vec<Statement> = functions.values().cloned().collect();
+        program.extend(top_level);
+
+        let mut compiler = BytecodeCompiler::new();
+        compiler.use_builtins(self.vm.builtins());
+        let program = compiler.compile_program(&program).map_err(|e| {
+            let diagnostic = Diagnostic::from(e);
+            Failure { message: format!("Compile error: {}", diagnostic), diagnostics: vec![diagnostic] }
+        })?;
+        self.vm.load_program(program).map_err(|e| Failure {
+            message: e.to_string(),
+            diagnostics: vec![Diagnostic::new(e.to_string(), Default::default())],
+        })?;
+
+        // Only keep new definitions once they compile
+        {
+            let mut state = self.state.borrow_mut();
+            state.functions = functions;
+            state.globals.extend(globals);
+        }
+
+        self.vm.run().map_err(|e| Failure {
+            message: e.to_string(),
+            diagnostics: error_diagnostics(&VoltageError::from(e), ""),
+        })
+    }
+}
+
+// Lets single expressions and statements omit the trailing semicolon
+fn with_semicolon(input: &str) -> String {
+    if input.ends_with(';') || input.ends_with('}') {
+        input.to_string()
+    } else {
+        format!("{};", input)
     }
 }
 
+// The statements of `source`; typed input can read the last result, `_`
+fn parse_source(source: &str, typed: bool) -> Result<Vec<Statement>, Failure> {
+    let lexer = Lexer::new(source);
+    if !lexer.errors().is_empty() {
+        let diagnostics: Vec<Diagnostic> = lexer.errors().iter().cloned().map(Diagnostic::from).collect();
+        let errors: Vec<String> = diagnostics.iter().map(|diagnostic| format!("Lex error: {}", diagnostic)).collect();
+        return Err(Failure { message: errors.join("\n"), diagnostics });
+    }
+    let parser = Parser::from_lexer(lexer).with_source(source);
+    let mut parser = if typed { parser.allow_underscore_reads() } else { parser };
+    parser.try_parse().map_err(|e| Failure {
+        message: format!("Parse error: {}", e),
+        diagnostics: error_diagnostics(&VoltageError::Parse(e), source),
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn fixture(name: &str) -> String {
+        format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), name)
+    }
+
     #[test]
     fn test_repl_creation() {
-        let repl = Repl::new();
-        assert!(true); // Basic test that REPL can be created
+        // Basic test that REPL can be created
+        let _repl = Repl::new();
+    }
+
+    #[test]
+    fn test_load_brings_definitions_into_session() {
+        let mut repl = Repl::new();
+        let message = repl.process_input(&format!(":load {}", fixture("square.v"))).unwrap();
+        assert!(message.starts_with("Loaded 2 definitions"));
+
+        assert_eq!(repl.process_input("square(9)").unwrap(), "81");
+        assert_eq!(repl.process_input("square(answer)").unwrap(), "1764");
+    }
+
+    #[test]
+    fn test_reload_replaces_definitions_from_file() {
+        let path = std::env::temp_dir().join(format!("voltage_repl_reload_{}.v", std::process::id()));
+        let mut repl = Repl::new();
+
+        fs::write(&path, "fn old_name() { return 1; }").unwrap();
+        repl.process_input(&format!(":load {}", path.display())).unwrap();
+        assert_eq!(repl.process_input("old_name()").unwrap(), "1");
+
+        fs::write(&path, "fn new_name() { return 2; }").unwrap();
+        repl.process_input(&format!(":load {}", path.display())).unwrap();
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(repl.process_input("new_name()").unwrap(), "2");
+        assert!(repl.process_input("old_name()").is_err());
+    }
+
+    #[test]
+    fn test_load_missing_file_is_an_error() {
+        let mut repl = Repl::new();
+        let error = repl.process_input(":load does/not/exist.v").unwrap_err();
+        assert!(error.starts_with("Could not read does/not/exist.v"));
+
+        // The session keeps working afterwards
+        assert_eq!(repl.process_input("1 + 2").unwrap(), "3");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_load_reports_parse_errors() {
+        let mut repl = Repl::new();
+        let error = repl.process_input(&format!(":load {}", fixture("broken.v"))).unwrap_err();
+        assert!(error.starts_with("Parse error"));
+    }
+
+    #[test]
+    fn test_defining_a_function_again_replaces_it() {
+        let mut repl = Repl::new();
+        repl.process_input("fn answer() { return 1; }").unwrap();
+        repl.process_input("fn answer() { return 42; }").unwrap();
+        assert_eq!(repl.process_input("answer()").unwrap(), "42");
+    }
+
+    #[test]
+    fn test_reset_forgets_definitions() {
+        let mut repl = Repl::new();
+        repl.process_input("fn answer() { return 42; }").unwrap();
+        repl.process_input("let x = 1;").unwrap();
+        assert_eq!(repl.process_input(":reset").unwrap(), "Session reset");
+
+        assert!(repl.state.borrow().functions.is_empty());
+        assert!(repl.process_input("answer()").is_err());
+        assert!(repl.process_input("x").is_err());
+        // The session keeps working afterwards
+        assert_eq!(repl.process_input("1 + 2").unwrap(), "3");
+    }
+
+    #[test]
+    fn test_locals_lists_the_session_variables() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.process_input(":locals").unwrap(), "No variables defined");
+        repl.process_input("fn answer() { return 42; }").unwrap();
+        repl.process_input("let x = answer();").unwrap();
+        repl.process_input("let name = \"volt\";").unwrap();
+        assert_eq!(repl.process_input(":locals").unwrap(), "name = volt\nx = 42");
+    }
+
+    #[test]
+    fn test_json_mode_replies_to_each_input_with_one_line() {
+        let input = "puts(\"hi\"); 1 + 2\nlet x = ;\n\n10 / 0\nexit\n1";
+        let mut output = Vec::new();
+        Repl::new().run_json(input.as_bytes(), &mut output).unwrap();
+
+        let replies: Vec<serde_json::Value> =
+            String::from_utf8(output).unwrap().lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        assert_eq!(replies.len(), 3);
+
+        assert_eq!(replies[0]["ok"], true);
+        assert_eq!(replies[0]["value"], "3");
+        assert_eq!(replies[0]["type"], "int");
+        assert_eq!(replies[0]["printed"], "hi\n");
+        assert!(replies[0]["duration_ms"].is_u64());
+
+        assert_eq!(replies[1]["ok"], false);
+        assert_eq!(replies[1]["diagnostics"][0]["code"], "syntax_error");
+        assert_eq!(replies[1]["diagnostics"][0]["file"], "<repl>");
+        assert_eq!(replies[1]["diagnostics"][0]["span"]["column"], 9);
+
+        assert_eq!(replies[2]["ok"], false);
+        assert_eq!(replies[2]["diagnostics"][0]["code"], "runtime_error");
+        assert!(replies[2]["diagnostics"][0]["message"].as_str().unwrap().contains("Division by zero"));
+    }
+
+    #[test]
+    fn test_results_are_kept_as_underscore_variables() {
+        let mut repl = Repl::new();
+        assert_eq!(repl.process_input(":history").unwrap(), "No results yet");
+        repl.process_input("1 + 2").unwrap();
+        repl.process_input("let x = 10;").unwrap();
+        repl.process_input("x * 2").unwrap();
+        repl.process_input("puts(\"no result\")").unwrap();
+        repl.process_input("\"volt\"").unwrap();
+        assert_eq!(repl.process_input("_2 + _1").unwrap(), "23");
+        assert_eq!(repl.process_input("_ - 3").unwrap(), "20");
+        assert_eq!(repl.process_input(":history").unwrap(), "_1 = 3\n_2 = 20\n_3 = volt\n_4 = 23\n_5 = 20");
+
+        // Files still can't read `_`
+        let path = std::env::temp_dir().join(format!("voltage_repl_underscore_{}.v", std::process::id()));
+        fs::write(&path, "let y = _;").unwrap();
+        assert!(repl.process_input(&format!(":load {}", path.display())).unwrap_err().contains("cannot read the value of `_`"));
+        fs::remove_file(&path).unwrap();
+
+        repl.process_input(":reset").unwrap();
+        assert_eq!(repl.process_input(":history").unwrap(), "No results yet");
+    }
+
+    #[test]
+    fn test_unknown_characters_are_errors() {
+        let mut repl = Repl::new();
+        let error = repl.process_input("let x = 1 @ 2;").unwrap_err();
+        assert_eq!(error, "Lex error: 1:11: Unexpected character \"@\"");
+    }
+
+    #[test]
+    fn test_compile_errors_are_reported_with_their_location() {
+        let mut repl = Repl::new();
+        repl.process_input("fn one(x) { return x; }").unwrap();
+        let error = repl.process_input("let a = 1;\none(1, 2);").unwrap_err();
+        assert_eq!(error, "Compile error: 2:1: Function one expects 1 arguments, got 2");
+    }
+}
@@ -0,0 +1,212 @@
+//! How `voltagec` prints errors and warnings: with the source they point at
+//! for people on stderr, or with `--message-format=json` as one JSON object
+//! per line on stdout, for editors and other tools.
+
+use std::io::IsTerminal;
+use serde::Serialize;
+use voltage_parser::{parse_with_diagnostics, summarize, Diagnostic, RenderOptions, DEFAULT_RENDER_WIDTH};
+use voltage_vm::VoltageError;
+
+/// The `--message-format` choices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MessageFormat {
+    /// Messages with the source they point at on stderr, at most
+    /// `--max-diagnostics`.
+    #[default]
+    Human,
+    /// Newline-delimited JSON on stdout, every diagnostic.
+    Json,
+}
+
+/// The `--color` choices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum ColorChoice {
+    /// Color when stderr is a terminal and `NO_COLOR` isn't set.
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+/// How to render diagnostics on stderr: in color if `color` says to, and
+/// wrapped to the terminal's width, or to [`DEFAULT_RENDER_WIDTH`] columns
+/// when stderr isn't a terminal.
+pub fn render_options(color: ColorChoice) -> RenderOptions {
+    let stderr = std::io::stderr();
+    let color = match color {
+        ColorChoice::Auto => stderr.is_terminal() && std::env::var_os("NO_COLOR").is_none(),
+        ColorChoice::Always => true,
+        ColorChoice::Never => false,
+    };
+    let width = terminal_size::terminal_size_of(&stderr).map_or(DEFAULT_RENDER_WIDTH, |(width, _)| width.0 as usize);
+    RenderOptions { color, width }
+}
+
+/// A diagnostic as `--message-format=json` prints it: the [`Diagnostic`]
+/// with the file it's in and where its span ends, which takes the source to
+/// work out.
+#[derive(Debug, Serialize)]
+pub(crate) struct JsonDiagnostic<'a> {
+    file: &'a str,
+    #[serde(flatten)]
+    diagnostic: &'a Diagnostic,
+    end_line: usize,
+    end_column: usize,
+}
+
+impl<'a> JsonDiagnostic<'a> {
+    pub(crate) fn new(file: &'a str, diagnostic: &'a Diagnostic, source: &str) -> Self {
+        let (end_line, end_column) = diagnostic.span.end_position(source);
+        Self { file, diagnostic, end_line, end_column }
+    }
+}
+
+/// How diagnostics are printed, as `--message-format`, `--max-diagnostics`
+/// and `--color` say.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReportOptions {
+    pub format: MessageFormat,
+    /// How many human-readable diagnostics to show before counting the rest.
+    pub max_diagnostics: usize,
+    pub render: RenderOptions,
+}
+
+/// Prints the diagnostics for one source file in the chosen format.
+pub struct Reporter<'a> {
+    options: ReportOptions,
+    file: &'a str,
+    source: &'a str,
+}
+
+impl<'a> Reporter<'a> {
+    pub fn new(options: ReportOptions, file: &'a str, source: &'a str) -> Self {
+        Self { options, file, source }
+    }
+
+    /// Prints the diagnostics worth showing, as [`summarize`] picks them.
+    /// JSON output isn't capped; tools decide for themselves what to show.
+    pub fn diagnostics(&self, diagnostics: &[Diagnostic]) {
+        match self.options.format {
+            MessageFormat::Human => {
+                let summary = summarize(diagnostics, self.options.max_diagnostics);
+                eprint!("{}", summary.render(self.file, self.source, self.options.render));
+            }
+            MessageFormat::Json => {
+                for diagnostic in summarize(diagnostics, usize::MAX).shown {
+                    println!("{}", self.to_json(&diagnostic));
+                }
+            }
+        }
+    }
+
+    /// Prints an error that stopped the file from compiling or running.
+    /// Errors in the source are shown with the source, as warnings are.
+    pub fn error(&self, error: &VoltageError) {
+        match (self.options.format, error) {
+            (MessageFormat::Human, VoltageError::Lex(_) | VoltageError::Parse(_) | VoltageError::Compile(_)) => {
+                self.diagnostics(&error_diagnostics(error, self.source))
+            }
+            (MessageFormat::Human, _) => print_error(error),
+            (MessageFormat::Json, _) => self.diagnostics(&error_diagnostics(error, self.source)),
+        }
+    }
+
+    /// Prints one of `voltagec`'s own progress messages, like which file is
+    /// running, to stdout. JSON output leaves them out, so that stdout has
+    /// nothing on it but diagnostics and what the script prints.
+    pub fn status(&self, message: &str) {
+        if self.options.format == MessageFormat::Human {
+            println!("{}", message);
+        }
+    }
+
+    fn to_json(&self, diagnostic: &Diagnostic) -> String {
+        let json = JsonDiagnostic::new(self.file, diagnostic, self.source);
+        serde_json::to_string(&json).expect("diagnostics serialize")
+    }
+}
+
+/// Prints an error to stderr for people, followed by the calls in progress
+/// if it happened at runtime.
+pub fn print_error(error: &VoltageError) {
+    match error {
+        VoltageError::Compile(e) => eprintln!("Compile error: {}", Diagnostic::from(e.clone())),
+        VoltageError::Runtime(e) | VoltageError::Panic(e) => {
+            eprintln!("{}", error);
+            eprint!("{}", e.backtrace());
+            if let Some(state) = &e.state {
+                eprint!("{}", state);
+            }
+        }
+        _ => eprintln!("{}", error),
+    }
+}
+
+/// `error` as diagnostics. The engine stops at the first syntax error, so
+/// those are found again in `source` along with any others. A runtime error
+/// points at the statement that failed, with a note for each call it was in
+/// and one with the VM's state if `--verbose` asked for it.
+pub fn error_diagnostics(error: &VoltageError, source: &str) -> Vec<Diagnostic> {
+    match error {
+        VoltageError::Lex(message) | VoltageError::Parse(message) => {
+            let diagnostics = parse_with_diagnostics(source).diagnostics;
+            match diagnostics.is_empty() {
+                true => vec![Diagnostic::new(message.clone(), Default::default()).with_code("syntax_error")],
+                false => diagnostics,
+            }
+        }
+        VoltageError::Compile(e) => vec![Diagnostic::from(e.clone())],
+        VoltageError::Conversion(message) => vec![Diagnostic::new(message.clone(), Default::default())],
+        VoltageError::Runtime(e) | VoltageError::Panic(e) => {
+            let code = if matches!(error, VoltageError::Panic(_)) { "panic" } else { "runtime_error" };
+            let span = e.location().unwrap_or_default();
+            let diagnostic = Diagnostic::new(e.message.clone(), span).with_code(code);
+            // Deep recursion is summed up as the backtrace sums it up
+            let trace = e.collapsed_trace();
+            let diagnostic = trace.iter().enumerate().fold(diagnostic, |diagnostic, (i, &(frame, more))| {
+                let span = frame.span.unwrap_or_default();
+                let diagnostic = match i {
+                    0 => diagnostic,
+                    _ => diagnostic.with_note(format!("called from {}", frame.function), span),
+                };
+                match more {
+                    0 => diagnostic,
+                    more => diagnostic.with_note(format!("... {} more calls to {}", more, frame.function), span),
+                }
+            });
+            vec![match &e.state {
+                Some(state) => diagnostic.with_note(state.to_string(), span),
+                None => diagnostic,
+            }]
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_json_lines_say_where_diagnostics_end() {
+        let source = "let a = 1;\nlet b = ;";
+        let options = ReportOptions { format: MessageFormat::Json, max_diagnostics: 20, render: RenderOptions::default() };
+        let reporter = Reporter::new(options, "broken.v", source);
+        let diagnostics = error_diagnostics(&voltage_vm::compile(source).unwrap_err(), source);
+        let json: serde_json::Value = serde_json::from_str(&reporter.to_json(&diagnostics[0])).unwrap();
+        assert_eq!(json["file"], "broken.v");
+        assert_eq!(json["code"], "syntax_error");
+        assert_eq!([&json["span"]["line"], &json["span"]["column"]], [2, 9]);
+        assert_eq!([&json["end_line"], &json["end_column"]], [2, 10]);
+    }
+
+    #[test]
+    fn test_runtime_errors_note_the_calls_they_were_in() {
+        let source = "fn invert(x) { return 1 / x; }\nlet y = invert(0);";
+        let error = voltage_vm::Engine::new().eval(source).unwrap_err();
+        let diagnostics = error_diagnostics(&error, source);
+        assert_eq!(diagnostics[0].code, Some("runtime_error"));
+        assert_eq!(diagnostics[0].span.line, 1);
+        assert_eq!(diagnostics[0].notes[0].message, "called from <top level>");
+        assert_eq!(diagnostics[0].notes[0].span.line, 2);
+    }
+}
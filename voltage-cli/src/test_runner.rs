@@ -0,0 +1,109 @@
+//! `voltagec test`: runs the test functions a script defines.
+
+use std::io::Write;
+use voltage_vm::{Engine, VoltageError};
+
+/// How many tests passed and failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct TestSummary {
+    pub passed: usize,
+    pub failed: usize,
+}
+
+impl TestSummary {
+    pub fn success(&self) -> bool {
+        self.failed == 0
+    }
+}
+
+/// Loads `source` into `engine`, then calls each top-level function whose
+/// name starts with `test_` (and contains `filter`, if given) in the order
+/// they are defined. Tests share the script's globals but each starts with an
+/// empty stack. A failed `assert()` or any other runtime error fails the test.
+///
+/// A line per test, with the message and location of each failure, and a
+/// summary line are written to `out`. An error is returned only when the
+/// script doesn't compile or its top-level code fails.
+pub fn run_tests(
+    engine: &mut Engine,
+    source: &str,
+    filter: Option<&str>,
+    out: &mut dyn Write,
+) -> Result<TestSummary, VoltageError> {
+    let (program, _) = voltage_vm::compile_with_modules(source, None, engine.modules())?;
+    let tests: Vec<String> = program
+        .functions
+        .iter()
+        .map(|function| function.name.clone())
+        .filter(|name| name.starts_with("test_"))
+        .filter(|name| filter.is_none_or(|filter| name.contains(filter)))
+        .collect();
+    engine.load_program(program)?;
+
+    let mut summary = TestSummary::default();
+    let _ = writeln!(out, "running {} tests", tests.len());
+    for name in &tests {
+        match engine.call(name, &[]) {
+            Ok(_) => {
+                summary.passed += 1;
+                let _ = writeln!(out, "test {} ... ok", name);
+            }
+            Err(e) => {
+                summary.failed += 1;
+                let _ = writeln!(out, "test {} ... FAILED", name);
+                match engine.last_span() {
+                    Some(span) => {
+                        let _ = writeln!(out, "    {} (line {}, column {})", e, span.line, span.column);
+                    }
+                    None => {
+                        let _ = writeln!(out, "    {}", e);
+                    }
+                }
+            }
+        }
+    }
+    let _ = writeln!(out, "{} passed, {} failed", summary.passed, summary.failed);
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "fn test_one() { assert(1 < 2); }\n\
+                          fn test_two() {\n    assert(2 < 1, \"backwards\");\n}\n\
+                          fn helper() { assert(false); }";
+
+    fn run(filter: Option<&str>) -> (TestSummary, String) {
+        let mut out = Vec::new();
+        let summary = run_tests(&mut Engine::new(), SOURCE, filter, &mut out).unwrap();
+        (summary, String::from_utf8(out).unwrap())
+    }
+
+    #[test]
+    fn test_reports_each_test_and_a_summary() {
+        let (summary, output) = run(None);
+        assert_eq!(summary, TestSummary { passed: 1, failed: 1 });
+        assert_eq!(
+            output,
+            "running 2 tests\n\
+             test test_one ... ok\n\
+             test test_two ... FAILED\n    \
+             Runtime error: Assertion failed: backwards (line 3, column 5)\n\
+             1 passed, 1 failed\n"
+        );
+    }
+
+    #[test]
+    fn test_filter_selects_tests_by_substring() {
+        let (summary, output) = run(Some("one"));
+        assert_eq!(summary, TestSummary { passed: 1, failed: 0 });
+        assert!(output.ends_with("1 passed, 0 failed\n"));
+    }
+
+    #[test]
+    fn test_compile_errors_are_not_test_failures() {
+        let result = run_tests(&mut Engine::new(), "fn test_x( {", None, &mut Vec::new());
+        assert!(matches!(result, Err(VoltageError::Parse(_))));
+    }
+}
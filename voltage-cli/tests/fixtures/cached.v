@@ -0,0 +1,12 @@
+fn check(state) {
+    let unused = 1;
+    if state > 2 {
+        panic("unreachable state: {}", state);
+    }
+    return state;
+}
+
+fn main() {
+    check(1);
+    check(3);
+}
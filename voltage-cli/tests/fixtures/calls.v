@@ -0,0 +1,16 @@
+fn area(w, h) {
+    return w * h;
+}
+
+fn square(n) {
+    return area(n, n);
+}
+
+fn squares(n) {
+    if n == 0 {
+        return [];
+    }
+    return squares(n - 1) + [square(n)];
+}
+
+puts(squares(3));
@@ -0,0 +1,3 @@
+let xs = [1, + @, 2 $ 3];
+let ys = (4 + ;
+puts(xs, ys);
@@ -0,0 +1,14 @@
+/* Prints the squares
+   of a few numbers */
+//with a helper
+fn square(n: int) -> int { return n*n; } // one line
+
+fn main() {
+    let limit = /* not inclusive */ 4;
+    for i in 0..limit {   //each one
+        puts(square(i));
+
+
+        // after a blank line
+    }
+}
@@ -0,0 +1,5 @@
+fn depth(n) {
+    return 1 + depth(n + 1);
+}
+
+depth(0);
@@ -0,0 +1,6 @@
+fn greet() { puts("hi"); }
+fn greet() { puts("hello"); }
+
+fn main() {
+    greet();
+}
@@ -0,0 +1,4 @@
+let ticks = 0;
+while true {
+    let ticks = ticks + 1;
+}
@@ -0,0 +1,7 @@
+fn add(a: int, b: int) -> int { return a + b; }
+fn main() {
+    let total = add(2, 3);
+    puts("total = {}", total);
+    print("big: ");
+    puts(total > 4);
+}
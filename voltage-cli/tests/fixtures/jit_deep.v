@@ -0,0 +1,7 @@
+fn depth(n) {
+    return 1 + depth(n + 1);
+}
+
+fn main() {
+    puts(depth(0));
+}
@@ -0,0 +1,10 @@
+fn spin() {
+    let spins: [int; 1] = [0];
+    loop {
+        spins[0] += 1;
+    }
+}
+
+fn main() {
+    spin();
+}
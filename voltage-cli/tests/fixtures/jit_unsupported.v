@@ -0,0 +1,10 @@
+fn main() {
+    let xs = [1, 2, 3];
+    for x in xs {
+        puts(half(x));
+    }
+}
+
+fn half(n) {
+    return n / 2.0;
+}
@@ -0,0 +1,5 @@
+fn main() {
+    puts("from main");
+}
+
+puts("from the top level");
@@ -0,0 +1,5 @@
+import greeting;
+import shapes;
+
+puts(greeting.hello("volt"));
+puts(shapes.source);
@@ -0,0 +1 @@
+pub let source = "VOLTAGE_PATH";
@@ -0,0 +1,3 @@
+pub fn hello(name) {
+    return format("hello {}", name);
+}
@@ -0,0 +1 @@
+pub let source = "module path";
@@ -0,0 +1,4 @@
+fn main() {
+    puts(7 % 2);
+    puts(1 % 0);
+}
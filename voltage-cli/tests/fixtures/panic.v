@@ -0,0 +1,9 @@
+fn check(state) {
+    if state > 2 {
+        panic("unreachable state: {}", state);
+    }
+    return state;
+}
+
+check(1);
+check(3);
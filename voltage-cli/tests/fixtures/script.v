@@ -0,0 +1,2 @@
+let x = 2;
+puts("{}", x * 21);
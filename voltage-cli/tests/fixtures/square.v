@@ -0,0 +1,5 @@
+let answer = 42;
+
+fn square(n) {
+    return n * n;
+}
@@ -0,0 +1,28 @@
+let limit = 10;
+
+fn double(n) {
+    return n * 2;
+}
+
+fn test_double() {
+    assert(double(4) == 8);
+}
+
+fn test_globals_are_shared() {
+    assert(limit == 10, "limit should be visible");
+}
+
+fn test_wrong_answer() {
+    let result = double(3);
+    assert(result == 7, "double(3) should be 7");
+}
+
+fn test_stack_starts_empty() {
+    let a = 1;
+    let b = 2;
+    assert(a + b == 3);
+}
+
+fn helper_is_not_a_test() {
+    assert(false);
+}
@@ -0,0 +1,6 @@
+fn helper() {
+    let unused = 1;
+    return 0;
+}
+puts(helper());
+puts(missing);
@@ -0,0 +1,589 @@
+use std::io::Write;
+use std::process::{Command, Output, Stdio};
+
+fn voltagec_run(fixture: &str) -> Output {
+    voltagec_run_with(&[], fixture)
+}
+
+fn voltagec_run_with(flags: &[&str], fixture: &str) -> Output {
+    let fixture = format!("{}/tests/fixtures/{}", env!("CARGO_MANIFEST_DIR"), fixture);
+    Command::new(env!("CARGO_BIN_EXE_voltagec"))
+        .args(flags)
+        .arg(fixture)
+        .output()
+        .unwrap()
+}
+
+// What was printed on stderr, with fixtures named as they are in the test
+fn rendered_diagnostics(output: &Output) -> String {
+    let stderr = String::from_utf8(output.stderr.clone()).unwrap();
+    stderr.replace(&format!("{}/tests/fixtures/", env!("CARGO_MANIFEST_DIR")), "")
+}
+
+const SQUARE_IS_NEVER_USED: &str = "\
+warning[unused_function]: function `square` is never used
+ --> square.v:3:1
+  |
+3 | fn square(n) {
+  | ^^^^^^^^^^^^^^
+...
+5 | }
+  | ^
+
+";
+
+#[test]
+fn test_script_without_main_runs_top_level_statements() {
+    let output = voltagec_run("script.v");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with("42\n"), "{}", stdout);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_main_and_top_level_statements_do_not_mix() {
+    let output = voltagec_run("mixed.v");
+    let stdout = String::from_utf8(output.stdout.clone()).unwrap();
+    assert!(!stdout.contains("from"), "{}", stdout);
+    assert_eq!(
+        rendered_diagnostics(&output),
+        "\
+error[mixed_main_and_statements]: cannot mix top-level statements with fn main
+ --> mixed.v:5:1
+  |
+5 | puts(\"from the top level\");
+  | ^^^^^^^^^^^^^^^^^^^^^^^^^^^
+
+"
+    );
+}
+
+#[test]
+fn test_compile_errors_show_the_source_when_running() {
+    let output = voltagec_run("duplicate.v");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        rendered_diagnostics(&output),
+        "\
+error[duplicate_definition]: duplicate definition of `greet`
+ --> duplicate.v:2:1
+  |
+2 | fn greet() { puts(\"hello\"); }
+  | ^^^^^^^^^^^^^^^^^^^^^^^^^^^^^
+  = note: 1:1: first defined here
+
+"
+    );
+    let output = voltagec_run_with(&["--color", "always"], "duplicate.v");
+    assert!(rendered_diagnostics(&output).starts_with("\x1b[1;31merror[duplicate_definition]\x1b[0m: "));
+}
+
+#[test]
+fn test_unused_functions_are_reported_unless_keep_all() {
+    let output = voltagec_run("square.v");
+    assert_eq!(rendered_diagnostics(&output), SQUARE_IS_NEVER_USED);
+
+    let output = voltagec_run_with(&["--keep-all"], "square.v");
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_coverage_counts_functions_that_never_ran() {
+    let lcov = std::env::temp_dir().join(format!("voltagec-coverage-{}.info", std::process::id()));
+    let output = voltagec_run_with(&["--coverage", &format!("--lcov={}", lcov.display())], "square.v");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with("square.v: 1/3 lines covered (33.3%)\n"), "{}", stdout);
+    // Coverage keeps `square`, so there's no warning about it
+    assert!(output.stderr.is_empty());
+
+    let report = std::fs::read_to_string(&lcov).unwrap();
+    std::fs::remove_file(&lcov).unwrap();
+    assert!(report.contains("DA:1,1\nDA:3,0\nDA:4,0\nLF:3\nLH:1\n"), "{}", report);
+}
+
+#[test]
+fn test_panic_exits_with_101_and_a_trace() {
+    let output = voltagec_run("panic.v");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(101));
+    assert_eq!(stderr, "Panic: unreachable state: 3\n  at check (line 3)\n  at <top level> (line 9)\n");
+}
+
+#[test]
+fn test_verbose_errors_dump_the_vm_state() {
+    let output = voltagec_run_with(&["--verbose"], "panic.v");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let state = stderr.strip_prefix("Panic: unreachable state: 3\n  at check (line 3)\n  at <top level> (line 9)\n").unwrap();
+    assert!(state.starts_with("state at instruction "), "{}", stderr);
+    assert!(state.contains(" in check:\n"), "{}", stderr);
+    assert!(state.contains("\n  locals:\n     0 state = int 3\n"), "{}", stderr);
+    assert!(state.contains("\n  last instructions run:\n"), "{}", stderr);
+}
+
+#[test]
+fn test_call_depth_limit_is_a_runtime_error() {
+    let output = voltagec_run_with(&["--max-call-depth=5"], "deep.v");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    // `depth` has no base case, which compiling it already warns about
+    let error = stderr.strip_prefix("warning[unconditional_recursion]: `depth` calls itself on every path").unwrap();
+    assert!(
+        error.contains("\nRuntime error: maximum recursion depth of 5 exceeded in function `depth`"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn test_running_out_of_fuel_stops_an_infinite_loop() {
+    let output = voltagec_run_with(&["--fuel", "1000"], "forever.v");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stderr.starts_with("Runtime error: out of fuel after running 1000 instructions\n"), "{}", stderr);
+}
+
+#[test]
+fn test_the_debugger_runs_with_the_same_limits() {
+    let fixture = format!("{}/tests/fixtures/forever.v", env!("CARGO_MANIFEST_DIR"));
+    let mut child = Command::new(env!("CARGO_BIN_EXE_voltagec"))
+        .args(["--debug", "--fuel", "1000", &fixture])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(b"continue\n").unwrap();
+    let stdout = String::from_utf8(child.wait_with_output().unwrap().stdout).unwrap();
+    assert!(stdout.contains("Runtime error: out of fuel after running 1000 instructions\n"), "{}", stdout);
+}
+
+#[test]
+fn test_limits_must_be_positive() {
+    let output = voltagec_run_with(&["--max-stack=0"], "script.v");
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8(output.stderr).unwrap().contains("--max-stack"));
+}
+
+#[test]
+fn test_check_leaves_out_errors_that_follow_from_others() {
+    // The characters that don't lex are inside the list skipped after its
+    // first bad item, so only the parse errors are shown
+    let output = voltagec_run_with(&["--check"], "cascade.v");
+    assert_eq!(output.status.code(), Some(1));
+    let first = "\
+error[syntax_error]: Expected expression, got Plus
+ --> cascade.v:1:14
+  |
+1 | let xs = [1, + @, 2 $ 3];
+  |              ^
+
+";
+    let second = "\
+error[syntax_error]: Expected expression, got Semi
+ --> cascade.v:2:15
+  |
+2 | let ys = (4 + ;
+  |               ^
+
+";
+    assert_eq!(rendered_diagnostics(&output), format!("{}{}", first, second));
+    assert!(output.stdout.is_empty());
+
+    let output = voltagec_run_with(&["--check", "--max-diagnostics", "1"], "cascade.v");
+    assert_eq!(rendered_diagnostics(&output), format!("{}... and 1 more error\n", first));
+}
+
+#[test]
+fn test_check_reports_warnings_without_failing() {
+    let output = voltagec_run_with(&["--check"], "square.v");
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(rendered_diagnostics(&output), SQUARE_IS_NEVER_USED);
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_denied_warnings_are_errors() {
+    let output = voltagec_run_with(&["--check", "--deny-warnings"], "square.v");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(rendered_diagnostics(&output), SQUARE_IS_NEVER_USED.replacen("warning", "error", 1));
+
+    let output = voltagec_run_with(&["--deny-warnings"], "square.v");
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(
+        rendered_diagnostics(&output),
+        SQUARE_IS_NEVER_USED
+            .replacen("warning[unused_function]", "error[denied_warning]", 1)
+            .replacen("never used", "never used (warnings are denied)", 1)
+    );
+}
+
+#[test]
+fn test_color_is_only_used_on_terminals_unless_asked_for() {
+    // Tests capture stderr, so it isn't a terminal
+    let output = voltagec_run_with(&["--check", "--color", "always"], "square.v");
+    assert!(rendered_diagnostics(&output).starts_with("\x1b[1;33mwarning[unused_function]\x1b[0m: "));
+    let output = voltagec_run_with(&["--check", "--color", "auto"], "square.v");
+    assert_eq!(rendered_diagnostics(&output), SQUARE_IS_NEVER_USED);
+}
+
+#[test]
+fn test_check_prints_json_lines_for_tools() {
+    let output = voltagec_run_with(&["check", "--message-format=json"], "warned.v");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stderr.is_empty());
+    // --check is the same command
+    assert_eq!(voltagec_run_with(&["--check", "--message-format=json"], "warned.v").stdout, output.stdout);
+
+    // Every line of stdout is a diagnostic, errors first
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let diagnostics: Vec<serde_json::Value> = stdout.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+    assert_eq!(diagnostics.len(), 2, "{}", stdout);
+
+    let [error, warning] = &diagnostics[..] else { unreachable!() };
+    assert!(error["file"].as_str().unwrap().ends_with("warned.v"));
+    assert_eq!([&error["severity"], &error["code"]], ["error", "undefined_variable"]);
+    assert_eq!(error["message"], "Undefined variable: missing");
+    assert_eq!([&error["span"]["line"], &error["span"]["column"], &error["end_line"], &error["end_column"]], [6, 1, 6, 15]);
+    assert_eq!(error["notes"], serde_json::json!([]));
+
+    assert_eq!([&warning["severity"], &warning["code"]], ["warning", "unused_variable"]);
+    assert_eq!([&warning["span"]["line"], &warning["span"]["column"], &warning["end_line"], &warning["end_column"]], [2, 5, 2, 20]);
+}
+
+#[test]
+fn test_json_errors_from_running_go_to_stdout() {
+    let output = voltagec_run_with(&["--message-format=json"], "panic.v");
+    assert_eq!(output.status.code(), Some(101));
+    assert!(output.stderr.is_empty());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let panic: serde_json::Value = serde_json::from_str(stdout.lines().last().unwrap()).unwrap();
+    assert_eq!([&panic["code"], &panic["message"]], ["panic", "unreachable state: 3"]);
+    assert_eq!(panic["notes"][0]["message"], "called from <top level>");
+}
+
+#[test]
+fn test_emit_constants_lists_the_pool_without_running() {
+    let output = voltagec_run_with(&["--emit=constants"], "script.v");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let expected = [
+        "     0  int       2",
+        "     1  string    \"{}\"",
+        "     2  int       21",
+        "     3  string    \"format\"",
+    ];
+    assert_eq!(stdout.lines().collect::<Vec<_>>(), expected);
+    assert!(output.stderr.is_empty());
+
+    let output = voltagec_run_with(&["--emit=bytecode"], "script.v");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("constants:\n     0  int       2\n"), "{}", stdout);
+    assert!(stdout.contains("code:\n     0  Jump(1)\n    ; 1 | let x = 2;\n"), "{}", stdout);
+}
+
+#[test]
+fn test_emit_callgraph_prints_the_calls_as_dot() {
+    let output = voltagec_run_with(&["--emit=callgraph"], "calls.v");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("digraph calls {\n"), "{}", stdout);
+    for edge in ["\"<top level>\" -> \"squares\";", "\"squares\" -> \"square\";", "\"squares\" -> \"squares\";", "\"square\" -> \"area\";"] {
+        assert!(stdout.contains(edge), "{}", stdout);
+    }
+    assert_eq!(stdout.matches(" -> ").count(), 4, "{}", stdout);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_optimizing_keeps_what_scripts_do() {
+    let runs: [(&[&str], &str); 8] = [
+        (&[], "script.v"),
+        (&[], "square.v"),
+        (&[], "panic.v"),
+        (&[], "mixed.v"),
+        (&[], "warned.v"),
+        (&[], "broken.v"),
+        (&[], "cascade.v"),
+        (&["--max-call-depth=5"], "deep.v"),
+    ];
+    for (flags, fixture) in runs {
+        let optimized = voltagec_run_with(&[flags, &["-O"]].concat(), fixture);
+        let output = voltagec_run_with(flags, fixture);
+        assert_eq!(optimized.status.code(), output.status.code(), "{}", fixture);
+        assert_eq!(optimized.stdout, output.stdout, "{}", fixture);
+        assert_eq!(optimized.stderr, output.stderr, "{}", fixture);
+    }
+}
+
+// Runs modules/app.v with `flags`, and `VOLTAGE_PATH` set to the fixture
+// directories in `voltage_path` or not set at all
+fn voltagec_run_app(flags: &[&str], voltage_path: &[&str]) -> Output {
+    let fixtures = format!("{}/tests/fixtures/modules", env!("CARGO_MANIFEST_DIR"));
+    let mut command = Command::new(env!("CARGO_BIN_EXE_voltagec"));
+    command.args(flags.iter().map(|flag| flag.replace("modules/", &format!("{}/", fixtures))));
+    command.env_remove("VOLTAGE_PATH");
+    if !voltage_path.is_empty() {
+        let dirs = voltage_path.iter().map(|dir| format!("{}/{}", fixtures, dir));
+        command.env("VOLTAGE_PATH", std::env::join_paths(dirs).unwrap());
+    }
+    command.arg(format!("{}/app.v", fixtures)).output().unwrap()
+}
+
+#[test]
+fn test_jit_backend_runs_functions_on_ints_and_bools() {
+    let output = voltagec_run_with(&["--backend=jit"], "jit.v");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(output.status.success());
+    assert!(stdout.ends_with("total = 5\nbig: true\n"), "{}", stdout);
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn test_jit_backend_lists_what_it_cannot_compile() {
+    let output = voltagec_run_with(&["--backend=jit"], "jit_unsupported.v");
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    assert_eq!(
+        rendered_diagnostics(&output),
+        "\
+jit_unsupported.v can't run on the JIT backend:
+  main: line 2: dynamic arrays are not yet supported by the JIT backend; use --backend=vm
+  main: line 3: for-loops are not yet supported by the JIT backend; use --backend=vm
+  half: line 9: floats are not yet supported by the JIT backend; use --backend=vm
+"
+    );
+}
+
+#[test]
+fn test_jit_backend_keeps_to_the_call_depth_and_fuel_limits() {
+    let output = voltagec_run_with(&["--backend=jit", "--max-call-depth=5"], "jit_deep.v");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(
+        stderr.ends_with(
+            "\nRuntime error: maximum recursion depth of 5 exceeded in function `depth` (most recent calls: depth <- depth <- depth)\n"
+        ),
+        "{}",
+        stderr
+    );
+
+    let output = voltagec_run_with(&["--backend=jit", "--fuel", "100"], "jit_forever.v");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(stderr, "Runtime error: out of fuel after running 100 loop iterations\n");
+
+    let output = voltagec_run_with(&["--backend=jit", "--max-stack=5"], "jit_forever.v");
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(stderr.starts_with("error: --max-stack only applies to the VM backend\n"), "{}", stderr);
+}
+
+#[test]
+fn test_integer_division_by_zero_fails_alike_on_both_backends() {
+    let cases = [("divide_by_zero.v", "3", "Runtime error: Division by zero"), ("modulo_by_zero.v", "1", "Runtime error: Modulo by zero")];
+    for (fixture, printed, error) in cases {
+        let vm = voltagec_run_with(&["--backend=vm"], fixture);
+        let jit = voltagec_run_with(&["--backend=jit"], fixture);
+        for output in [&vm, &jit] {
+            let stdout = String::from_utf8(output.stdout.clone()).unwrap();
+            assert_eq!(output.status.code(), Some(1), "{}", fixture);
+            assert!(stdout.ends_with(&format!("{}\n{}\n", fixture, printed)), "{}", stdout);
+            // The VM goes on to say where, which the JIT can't
+            assert_eq!(rendered_diagnostics(output).lines().next(), Some(error), "{}", fixture);
+        }
+    }
+}
+
+#[test]
+fn test_vx_files_run_on_the_jit_backend() {
+    let output = voltagec_run("squares.vx");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(1));
+    assert!(stdout.ends_with("squares.vx\n9\n"), "{}", stdout);
+    assert_eq!(stderr, "Runtime error: Index 4 is out of bounds for length 4\n");
+}
+
+#[test]
+fn test_modules_are_found_next_to_the_script_and_in_module_paths() {
+    let output = voltagec_run_app(&["--module-path", "modules/lib"], &[]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with("hello volt\nmodule path\n"), "{}", stdout);
+    assert!(output.stderr.is_empty(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = voltagec_run_app(&[], &["missing", "env"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.ends_with("hello volt\nVOLTAGE_PATH\n"), "{}", stdout);
+}
+
+#[test]
+fn test_module_paths_come_before_voltage_path() {
+    let output = voltagec_run_app(&["--module-path", "modules/missing", "--module-path=modules/lib"], &["env"]);
+    let stdout = String::from_utf8(output.stdout.clone()).unwrap();
+    assert!(stdout.ends_with("hello volt\nmodule path\n"), "{}", stdout);
+    // The message is wrapped as long as the paths were before they were shortened
+    let stderr = rendered_diagnostics(&output).split_whitespace().collect::<Vec<_>>().join(" ");
+    let warning = "warning[duplicate_module]: module `shapes` is also in modules/env/shapes.v; using modules/lib/shapes.v";
+    assert!(stderr.starts_with(warning), "{}", stderr);
+}
+
+#[test]
+fn test_missing_modules_list_the_directories_searched() {
+    let output = voltagec_run_app(&["--module-path", "modules/missing"], &["env/nothing"]);
+    assert_eq!(output.status.code(), Some(1));
+    let stderr = rendered_diagnostics(&output).split_whitespace().collect::<Vec<_>>().join(" ");
+    assert_eq!(
+        stderr,
+        "error[unknown_module]: unknown module `shapes`; the modules are greeting, std.io, std.math, std.string, \
+         and shapes.v is in none of modules, modules/missing, modules/env/nothing --> modules/app.v:2:1 | \
+         2 | import shapes; | ^^^^^^^^^^^^^^"
+    );
+}
+
+fn voltagec(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_voltagec")).args(args).output().unwrap()
+}
+
+#[test]
+fn test_self_completions_cover_flags_and_subcommands() {
+    let output = voltagec(&["self", "completions", "bash"]);
+    assert!(output.status.success());
+    let script = String::from_utf8(output.stdout).unwrap();
+    assert!(script.contains("--emit"), "{}", script);
+    assert!(script.contains("--max-call-depth"));
+    assert!(script.contains("--backend"));
+    assert!(script.contains("--check"));
+    assert!(script.contains("--keep-all"));
+    assert!(script.contains("--message-format"));
+    assert!(script.contains("completions"));
+    assert!(!voltagec(&["self", "completions", "tcsh"]).status.success());
+}
+
+#[test]
+fn test_self_info_names_the_version() {
+    let output = voltagec(&["self", "info"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let version = stdout.lines().find_map(|line| line.strip_prefix("voltagec ")).unwrap();
+    assert_eq!(version, env!("CARGO_PKG_VERSION"));
+    assert!(stdout.contains("\nbytecode format: "), "{}", stdout);
+    assert!(stdout.contains("\ncapabilities: fs, env, json\n"), "{}", stdout);
+    assert!(!stdout.contains("evaluation order"), "{}", stdout);
+
+    let output = voltagec(&["self", "info", "--language"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\nevaluation order:\nExpressions are evaluated left to right"), "{}", stdout);
+}
+
+#[test]
+fn test_built_bytecode_files_run_like_their_source() {
+    let dir = std::env::temp_dir().join(format!("voltagec-build-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("jit.v");
+    std::fs::copy(format!("{}/tests/fixtures/jit.v", env!("CARGO_MANIFEST_DIR")), &source).unwrap();
+
+    assert!(voltagec(&["--build", source.to_str().unwrap()]).status.success());
+    let output = voltagec(&[dir.join("jit.vbc").to_str().unwrap()]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(stdout.ends_with("jit.vbc\ntotal = 5\nbig: true\nProgram completed with result: Null\n"), "{}", stdout);
+}
+
+#[test]
+fn test_cached_runs_print_what_uncached_runs_do() {
+    let dir = std::env::temp_dir().join(format!("voltagec-cache-{}", std::process::id()));
+    let cache_dir = format!("--cache-dir={}", dir.display());
+    let uncached = voltagec_run("cached.v");
+    let first = voltagec_run_with(&[&cache_dir], "cached.v");
+    let second = voltagec_run_with(&[&cache_dir], "cached.v");
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let stderr = rendered_diagnostics(&uncached);
+    assert!(stderr.starts_with("warning[unused_variable]: "), "{}", stderr);
+    assert!(stderr.ends_with("Panic: unreachable state: 3\n  at check (line 4)\n  at main (line 11)\n"), "{}", stderr);
+    for cached in [first, second] {
+        assert_eq!(cached.status.code(), uncached.status.code());
+        assert_eq!(cached.stdout, uncached.stdout);
+        assert_eq!(rendered_diagnostics(&cached), stderr);
+    }
+
+    // Without warnings, the second run loads the program from the cache
+    let uncached = voltagec_run("panic.v");
+    let first = voltagec_run_with(&[&cache_dir], "panic.v");
+    let second = voltagec_run_with(&[&cache_dir], "panic.v");
+    assert!(std::fs::read_dir(&dir).unwrap().next().is_some());
+    std::fs::remove_dir_all(&dir).unwrap();
+    for cached in [first, second] {
+        assert_eq!(cached.status.code(), uncached.status.code());
+        assert_eq!(cached.stdout, uncached.stdout);
+        assert_eq!(cached.stderr, uncached.stderr);
+    }
+}
+
+#[test]
+fn test_fmt_rewrites_files_keeping_their_comments() {
+    let dir = std::env::temp_dir().join(format!("voltagec-fmt-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let source = dir.join("commented.v");
+    std::fs::copy(format!("{}/tests/fixtures/commented.v", env!("CARGO_MANIFEST_DIR")), &source).unwrap();
+    let file = source.to_str().unwrap();
+
+    let unformatted = voltagec(&["fmt", "--check", file]);
+    let formatted = voltagec(&["fmt", file]);
+    let contents = std::fs::read_to_string(&source).unwrap();
+    let formatted_again = voltagec(&["fmt", "--check", file]);
+    let run = voltagec(&[file]);
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(unformatted.status.code(), Some(1));
+    assert!(String::from_utf8(unformatted.stderr).unwrap().ends_with("commented.v isn't formatted\n"));
+    assert!(formatted.status.success(), "{}", String::from_utf8_lossy(&formatted.stderr));
+    assert!(formatted_again.status.success(), "{}", String::from_utf8_lossy(&formatted_again.stderr));
+    assert_eq!(contents, "\
+/* Prints the squares
+   of a few numbers */
+// with a helper
+fn square(n: int) -> int {
+    return n * n;
+} // one line
+
+fn main() {
+    let limit = /* not inclusive */ 4;
+    for i in 0..limit { // each one
+        puts(square(i));
+
+        // after a blank line
+    }
+}
+");
+    assert!(String::from_utf8(run.stdout).unwrap().ends_with("commented.v\n0\n1\n4\n9\nProgram completed with result: Null\n"));
+}
+
+#[test]
+fn test_repl_json_answers_each_input_with_a_line_of_json() {
+    let replies = |args: &[&str]| {
+        let mut child = Command::new(env!("CARGO_BIN_EXE_voltagec"))
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        child.stdin.take().unwrap().write_all(b"puts(1 + 2)\nlet x = ;\n1 / 0\n").unwrap();
+        let output = child.wait_with_output().unwrap();
+        assert!(output.status.success());
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        stdout
+            .lines()
+            .map(|line| {
+                let mut reply: serde_json::Value = serde_json::from_str(line).unwrap();
+                // The only part that changes from run to run
+                reply.as_object_mut().unwrap().remove("duration_ms");
+                reply
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let json = replies(&["repl", "--json"]);
+    assert_eq!(json.len(), 3);
+    assert_eq!(json[0]["printed"], "3\n");
+    assert_eq!(json[1]["ok"], false);
+    assert_eq!(json[2]["ok"], false);
+    assert_eq!(replies(&["--repl", "--message-format=json"]), json);
+}
@@ -0,0 +1,38 @@
+use std::process::{Command, Output};
+
+fn voltagec_test(extra: &[&str]) -> Output {
+    let fixture = format!("{}/tests/fixtures/tests.v", env!("CARGO_MANIFEST_DIR"));
+    Command::new(env!("CARGO_BIN_EXE_voltagec"))
+        .arg("test")
+        .arg(fixture)
+        .args(extra)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn test_failing_test_is_reported_with_nonzero_exit() {
+    let output = voltagec_test(&[]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(stdout.contains("test test_double ... ok\n"), "{}", stdout);
+    assert!(stdout.contains("test test_globals_are_shared ... ok\n"), "{}", stdout);
+    assert!(stdout.contains("test test_stack_starts_empty ... ok\n"), "{}", stdout);
+    assert!(stdout.contains(
+        "test test_wrong_answer ... FAILED\n    \
+         Runtime error: Assertion failed: double(3) should be 7 (line 17, column 5)\n"
+    ), "{}", stdout);
+    assert!(!stdout.contains("helper_is_not_a_test"));
+    assert!(stdout.ends_with("3 passed, 1 failed\n"), "{}", stdout);
+    assert_eq!(output.status.code(), Some(1));
+}
+
+#[test]
+fn test_filter_runs_a_subset() {
+    let output = voltagec_test(&["--filter", "double"]);
+    let stdout = String::from_utf8(output.stdout).unwrap();
+
+    assert!(stdout.starts_with("running 1 tests\n"), "{}", stdout);
+    assert!(stdout.ends_with("1 passed, 0 failed\n"), "{}", stdout);
+    assert_eq!(output.status.code(), Some(0));
+}
@@ -1,3 +1,5 @@
+use std::fmt;
+
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Type {
     Integer,
@@ -18,6 +20,37 @@ pub enum Type {
     Unknown,
 }
 
+/// Types are written the way source code spells them, e.g. `[int]`.
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Integer => write!(f, "int"),
+            Type::Float => write!(f, "float"),
+            Type::String => write!(f, "string"),
+            Type::Boolean => write!(f, "bool"),
+            Type::Void => write!(f, "void"),
+            Type::Reference(inner) => write!(f, "&{}", inner),
+            Type::MutableReference(inner) => write!(f, "&mut {}", inner),
+            Type::Array(element, len) => write!(f, "[{}; {}]", element, len),
+            Type::DynamicArray(element) => write!(f, "[{}]", element),
+            Type::Slice(element) => write!(f, "&[{}]", element),
+            Type::Pointer(inner) => write!(f, "*{}", inner),
+            Type::Function(params, ret) => {
+                write!(f, "fn(")?;
+                for (i, param) in params.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", param)?;
+                }
+                write!(f, ") -> {}", ret)
+            }
+            Type::Struct(name, _) | Type::Enum(name, _) | Type::Generic(name) => write!(f, "{}", name),
+            Type::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct TypedExpression {
     pub expression: Expression,
@@ -38,16 +71,46 @@ pub enum Expression {
         operator: BinaryOp,
         right: Box<Expression>,
     },
+    // `-operand`
+    Unary {
+        operator: UnaryOp,
+        operand: Box<Expression>,
+    },
+    // `&&` and `||`, which only evaluate `right` when `left` doesn't decide the result
+    Logical {
+        left: Box<Expression>,
+        operator: LogicalOp,
+        right: Box<Expression>,
+    },
+    // A call of a function by name, which may be a builtin
     Call {
         name: String,
         arguments: Vec<Expression>,
     },
+    // A call of whatever `callee` evaluates to, like `handlers[0](x)`
+    IndirectCall {
+        callee: Box<Expression>,
+        arguments: Vec<Expression>,
+    },
     FormatCall {
         name: String,
         format_string: String,
         arguments: Vec<Expression>,
     },
+    // `dbg(expression)`, with the source text of `expression` and the line
+    // it starts on, for the builtin to print along with its value
+    Debug {
+        expression: Box<Expression>,
+        source: String,
+        line: usize,
+    },
     ArrayLiteral(Vec<Expression>),
+    // `start..end`, or `start..=end` when `inclusive`
+    Range {
+        start: Box<Expression>,
+        end: Box<Expression>,
+        inclusive: bool,
+    },
     ArrayAccess {
         array: Box<Expression>,
         index: Box<Expression>,
@@ -57,9 +120,12 @@ pub enum Expression {
         index: Box<Expression>,
         value: Box<Expression>,
     },
-    StructDefinition {
-        name: String,
-        fields: Vec<(String, Type)>,
+    // `array[index] op= value`
+    ArrayCompoundAssignment {
+        array: Box<Expression>,
+        index: Box<Expression>,
+        operator: BinaryOp,
+        value: Box<Expression>,
     },
     StructInitialization {
         name: String,
@@ -83,6 +149,8 @@ pub enum Expression {
         expression: Box<Expression>,
         arms: Vec<(EnumPattern, Expression)>,
     },
+    // A `loop` as the value of a `let`: the value its `break` gives
+    Loop(Vec<Statement>),
 }
 
 #[derive(Debug, Clone)]
@@ -100,6 +168,8 @@ pub enum BinaryOp {
     Multiply,
     Divide,
     Modulo,
+    // `**`
+    Power,
     Equal,
     NotEqual,
     Less,
@@ -108,6 +178,38 @@ pub enum BinaryOp {
     GreaterEqual,
 }
 
+/// Operators are written the way source code spells them, e.g. `<=`.
+impl fmt::Display for BinaryOp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let symbol = match self {
+            BinaryOp::Add => "+",
+            BinaryOp::Subtract => "-",
+            BinaryOp::Multiply => "*",
+            BinaryOp::Divide => "/",
+            BinaryOp::Modulo => "%",
+            BinaryOp::Power => "**",
+            BinaryOp::Equal => "==",
+            BinaryOp::NotEqual => "!=",
+            BinaryOp::Less => "<",
+            BinaryOp::LessEqual => "<=",
+            BinaryOp::Greater => ">",
+            BinaryOp::GreaterEqual => ">=",
+        };
+        write!(f, "{}", symbol)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum UnaryOp {
+    Negate,
+}
+
+#[derive(Debug, Clone)]
+pub enum LogicalOp {
+    And,
+    Or,
+}
+
 #[derive(Debug, Clone)]
 pub struct TypedFunction {
     pub name: String,
@@ -128,13 +230,70 @@ pub enum TypedStatement {
     Function(TypedFunction),
 }
 
+/// A stretch of source text. Offsets are in bytes; lines and columns count
+/// from 1, so the default span (line 0) means "unknown location".
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// The span from the start of `self` to the end of `other`.
+    pub fn to(self, other: Span) -> Span {
+        Span { end: other.end, ..self }
+    }
+
+    pub fn is_known(&self) -> bool {
+        self.line > 0
+    }
+
+    /// The line and column just past the end of the span in `source`, the
+    /// text it was taken from. Like `column`, the column counts bytes. An
+    /// unknown span ends at line 0.
+    pub fn end_position(&self, source: &str) -> (usize, usize) {
+        if !self.is_known() {
+            return (0, 0);
+        }
+        let before = &source.as_bytes()[..self.end.min(source.len())];
+        let line_start = before.iter().rposition(|&b| b == b'\n').map_or(0, |newline| newline + 1);
+        let line = 1 + before.iter().filter(|&&b| b == b'\n').count();
+        (line, before.len() - line_start + 1)
+    }
+}
+
+/// A statement and where it appears in the source.
 #[derive(Debug, Clone)]
-pub enum Statement {
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, span: Span) -> Self {
+        Self { kind, span }
+    }
+}
+
+/// Statements built without source, e.g. by tests or the REPL.
+impl From<StatementKind> for Statement {
+    fn from(kind: StatementKind) -> Self {
+        Self::new(kind, Span::default())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum StatementKind {
     Expression(Expression),
     VariableDeclaration {
         name: String,
         value: Expression,
         explicit_type: Option<Type>,
+        // `pub let`, usable from outside its module
+        public: bool,
     },
     Block(Vec<Statement>),
     Function(Function),
@@ -153,11 +312,38 @@ pub enum Statement {
         iterable: Expression,
         body: Vec<Statement>,
     },
-    Break,
+    // `loop { ... }`, which only ends through `break` or `return`
+    Loop(Vec<Statement>),
+    // `break;`, or `break value;` in a `loop`
+    Break(Option<Expression>),
     Continue,
+    Return(Option<Expression>),
     UnsafeBlock(Vec<Statement>),
+    // `try { body } catch error_binding { handler }`; the handler runs with
+    // the message of a runtime error in the body
+    TryCatch {
+        body: Vec<Statement>,
+        error_binding: String,
+        // Where the name after `catch` is written
+        binding_span: Span,
+        handler: Vec<Statement>,
+    },
+    // `import std.math;`, whose members are then `math.sqrt` and so on
     Import(String),
+    // `import std.math as m;`
     ImportAs(String, String),
+    // `impl Point { fn dist(self) { ... } }`: functions called as methods of
+    // `type_name` values, each taking the value as its first parameter
+    Impl {
+        type_name: String,
+        methods: Vec<Function>,
+    },
+    // `struct Config { retries: int = 3, name: str }`: the fields that
+    // initializers of `name` give, some with defaults they may leave out
+    Struct {
+        name: String,
+        fields: Vec<StructField>,
+    },
 }
 
 #[derive(Debug, Clone)]
@@ -166,6 +352,17 @@ pub struct Function {
     pub parameters: Vec<(String, Type)>,
     pub return_type: Type,
     pub body: Vec<Statement>,
+    /// `pub fn`, callable from outside its module.
+    pub public: bool,
+}
+
+/// A field of a `struct` definition, and the value an initializer that
+/// leaves it out gives it, if any.
+#[derive(Debug, Clone)]
+pub struct StructField {
+    pub name: String,
+    pub field_type: Type,
+    pub default: Option<Expression>,
 }
 
 #[derive(Debug, Clone)]
@@ -1,245 +1,218 @@
+use std::collections::HashMap;
+use std::io::Write;
+
 use cranelift::prelude::*;
-use cranelift_module::{Linkage, Module};
+use cranelift_module::{FuncId, Linkage, Module};
 use cranelift_jit::{JITBuilder, JITModule};
-use voltage_core::{Expression, Statement, Function, Literal};
+use voltage_core::{Function, Span, Statement, StatementKind};
+
+mod runtime;
+mod support;
+mod translate;
 
+pub use support::{JitError, JitSupport};
+pub use runtime::DEFAULT_MAX_CALL_DEPTH;
+use runtime::Limits;
+use support::scalar_type;
+use translate::{FunctionInfo, FunctionTranslator, HostFunctions};
+
+/// Compiles Voltage functions to machine code with Cranelift, and runs them.
+/// Ints and bools are supported, along with the statements and calls that
+/// use them; [`JitSupport`] lists what isn't.
 pub struct JitCompiler {
     builder_context: FunctionBuilderContext,
     module: JITModule,
+    host: Option<HostFunctions>,
+    functions: HashMap<String, FunctionInfo>,
+    // The string literals compiled code prints
+    strings: Vec<Box<str>>,
+    output: Option<Box<dyn Write>>,
+    limits: Limits,
+}
+
+impl Default for JitCompiler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl JitCompiler {
     pub fn new() -> Self {
-        let builder = JITBuilder::new(cranelift_module::default_libcall_names()).expect("Failed to create JITBuilder");
+        let mut builder = JITBuilder::new(cranelift_module::default_libcall_names()).expect("Failed to create JITBuilder");
+        builder.symbols(runtime::symbols());
         let module = JITModule::new(builder);
-        
+
         Self {
             builder_context: FunctionBuilderContext::new(),
             module,
+            host: None,
+            functions: HashMap::new(),
+            strings: Vec::new(),
+            output: None,
+            limits: Limits { max_call_depth: DEFAULT_MAX_CALL_DEPTH, fuel: None },
         }
     }
-    
+
+    /// Sends what compiled code prints to `output` instead of stdout.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = Some(output);
+    }
+
+    /// Stops compiled code with a runtime error once more than
+    /// `max_call_depth` calls are in progress, or once loops have gone round
+    /// `fuel` times, if given.
+    pub fn set_limits(&mut self, max_call_depth: usize, fuel: Option<u64>) {
+        self.limits = Limits { max_call_depth, fuel };
+    }
+
+    /// Compiles one function. The functions it calls have to have been
+    /// compiled already.
     pub fn compile_function(&mut self, func: &Function) -> Result<(), String> {
-        // Create a signature for the function
-        let mut sig = self.module.make_signature();
-        
-        // For now, assuming all functions return i32
-        sig.returns.push(AbiParam::new(types::I32));
-        
-        // Create the function
-        let func_id = self.module
-            .declare_function(&func.name, Linkage::Export, &sig)
-            .map_err(|e| e.to_string())?;
-            
-        let mut ctx = self.module.make_context();
-        ctx.func.signature = sig;
-        
-        // Process the function body to extract information we need for compilation
-        let has_builtin_calls = self.function_has_builtin_calls(func);
-        
-        {
-            let mut builder = FunctionBuilder::new(&mut ctx.func, &mut self.builder_context);
-            
-            // Create the entry block
-            let block = builder.create_block();
-            builder.append_block_params_for_function_params(block);
-            builder.switch_to_block(block);
-            builder.seal_block(block);
-            
-            // If the function contains builtin calls, we'll need more complex logic
-            if has_builtin_calls {
-                // For now, just return 0
-                let return_val = builder.ins().iconst(types::I32, 0);
-                builder.ins().return_(&[return_val]);
-            } else {
-                // For functions without builtin calls, return 0
-                let return_val = builder.ins().iconst(types::I32, 0);
-                builder.ins().return_(&[return_val]);
+        self.compile_program(&[StatementKind::Function(func.clone()).into()]).map_err(|errors| {
+            errors.iter().map(ToString::to_string).collect::<Vec<_>>().join("\n")
+        })
+    }
+
+    /// Compiles every function in `program`, which may call each other and
+    /// those compiled before. Nothing is compiled if [`JitSupport`] finds
+    /// anything the JIT can't do; otherwise each function that fails to
+    /// compile is reported.
+    pub fn compile_program(&mut self, program: &[Statement]) -> Result<(), Vec<JitError>> {
+        let unsupported = JitSupport::check_with(program, self.functions.keys().map(String::as_str));
+        if !unsupported.is_empty() {
+            return Err(unsupported);
+        }
+        let module_error = |e: String| vec![JitError::new("<top level>", Span::default(), e)];
+        self.declare_builtins().map_err(module_error)?;
+
+        let functions: Vec<&Function> = program
+            .iter()
+            .filter_map(|stmt| match &stmt.kind {
+                StatementKind::Function(func) => Some(func),
+                _ => None,
+            })
+            .collect();
+        let returns = translate::return_types(&functions, &self.functions);
+        for func in &functions {
+            let params: Vec<_> = func
+                .parameters
+                .iter()
+                .map(|(_, declared)| scalar_type(declared).expect("JitSupport checked the parameters"))
+                .collect();
+            let sig = self.signature(params.len());
+            let id = self.module
+                .declare_function(&func.name, Linkage::Export, &sig)
+                .map_err(|e| module_error(e.to_string()))?;
+            self.functions.insert(func.name.clone(), FunctionInfo { id, params, returns: returns[&func.name] });
+        }
+
+        let errors: Vec<JitError> = functions.iter().filter_map(|func| self.define(func).err()).collect();
+        if !errors.is_empty() {
+            for func in &functions {
+                self.functions.remove(&func.name);
             }
+            return Err(errors);
         }
-        
-        // Compile the function
+        self.module.finalize_definitions().map_err(|e| module_error(e.to_string()))
+    }
+
+    fn define(&mut self, func: &Function) -> Result<(), JitError> {
+        let info = self.functions[&func.name].clone();
+        let host = self.host.expect("builtins are declared first");
+        let mut ctx = self.module.make_context();
+        ctx.func.signature = self.signature(info.params.len());
+
+        let builder = FunctionBuilder::new(&mut ctx.func, &mut self.builder_context);
+        let translator = FunctionTranslator::new(builder, &mut self.module, &self.functions, host, &mut self.strings, &func.name);
+        if let Err(e) = translator.translate(func) {
+            // The builder stopped part way, so its context isn't ready for the next function
+            self.builder_context = FunctionBuilderContext::new();
+            return Err(e);
+        }
+
         self.module
-            .define_function(func_id, &mut ctx)
-            .map_err(|e| e.to_string())?;
-            
-        let _ = self.module.finalize_definitions();
-        
-        Ok(())
+            .define_function(info.id, &mut ctx)
+            .map_err(|e| JitError::new(&func.name, Span::default(), e.to_string()))
+    }
+
+    fn signature(&self, params: usize) -> Signature {
+        let mut sig = self.module.make_signature();
+        sig.params.extend((0..params).map(|_| AbiParam::new(types::I64)));
+        sig.returns.push(AbiParam::new(types::I64));
+        sig
     }
-    
-    // Helper to check if a function contains built-in calls
-    fn function_has_builtin_calls(&self, func: &Function) -> bool {
-        for stmt in &func.body {
-            if self.statement_has_builtin_call(stmt) {
-                return true;
+
+    pub fn has_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    /// Calls the compiled function `name` with `args` (bools as 0 or 1) and
+    /// returns what it returns, or the runtime error it ran into. Functions
+    /// taking more than four arguments can only be called from other
+    /// compiled functions.
+    pub fn run(&mut self, name: &str, args: &[i64]) -> Result<i64, String> {
+        let info = self.functions.get(name).ok_or_else(|| format!("Unknown function: {}", name))?;
+        if args.len() != info.params.len() {
+            return Err(format!("Function {} expects {} arguments, got {}", name, info.params.len(), args.len()));
+        }
+        let code = self.module.get_finalized_function(info.id);
+        // Compiled code names each function by its id when it's called
+        let mut names = Vec::new();
+        for (name, info) in &self.functions {
+            let index = info.id.as_u32() as usize;
+            if names.len() <= index {
+                names.resize(index + 1, String::new());
             }
+            names[index] = name.clone();
         }
-        false
-    }
-    
-    fn statement_has_builtin_call(&self, stmt: &Statement) -> bool {
-        match stmt {
-            Statement::Expression(expr) => self.expression_has_builtin_call(expr),
-            Statement::Block(statements) => {
-                for stmt in statements {
-                    if self.statement_has_builtin_call(stmt) {
-                        return true;
-                    }
-                }
-                false
-            },
-            Statement::VariableDeclaration { value, .. } => self.expression_has_builtin_call(value),
-            Statement::Function(nested_func) => self.function_has_builtin_calls(nested_func),
-            Statement::If { condition, then_branch, elif_branches, else_branch } => {
-                // Check condition for builtin calls
-                if self.expression_has_builtin_call(condition) {
-                    return true;
-                }
-                // Check then branch
-                for stmt in then_branch {
-                    if self.statement_has_builtin_call(stmt) {
-                        return true;
-                    }
-                }
-                // Check elif branches
-                for (cond, body) in elif_branches {
-                    if self.expression_has_builtin_call(cond) {
-                        return true;
-                    }
-                    for stmt in body {
-                        if self.statement_has_builtin_call(stmt) {
-                            return true;
-                        }
-                    }
-                }
-                // Check else branch
-                if let Some(branches) = else_branch {
-                    for stmt in branches {
-                        if self.statement_has_builtin_call(stmt) {
-                            return true;
-                        }
-                    }
-                }
-                false
-            },
-            Statement::While { condition, body } => {
-                if self.expression_has_builtin_call(condition) {
-                    return true;
-                }
-                for stmt in body {
-                    if self.statement_has_builtin_call(stmt) {
-                        return true;
-                    }
-                }
-                false
-            },
-            Statement::For { iterable, body, .. } => {
-                if self.expression_has_builtin_call(iterable) {
-                    return true;
-                }
-                for stmt in body {
-                    if self.statement_has_builtin_call(stmt) {
-                        return true;
-                    }
-                }
-                false
-            },
-            Statement::Break | Statement::Continue => false,
-            Statement::UnsafeBlock(statements) => {
-                for stmt in statements {
-                    if self.statement_has_builtin_call(stmt) {
-                        return true;
-                    }
+
+        // Compiled functions take and return i64s in the platform's C calling convention
+        let (result, error) = runtime::with_runtime(&mut self.output, self.limits, names, || unsafe {
+            match *args {
+                [] => Ok(std::mem::transmute::<*const u8, extern "C" fn() -> i64>(code)()),
+                [a] => Ok(std::mem::transmute::<*const u8, extern "C" fn(i64) -> i64>(code)(a)),
+                [a, b] => Ok(std::mem::transmute::<*const u8, extern "C" fn(i64, i64) -> i64>(code)(a, b)),
+                [a, b, c] => Ok(std::mem::transmute::<*const u8, extern "C" fn(i64, i64, i64) -> i64>(code)(a, b, c)),
+                [a, b, c, d] => {
+                    Ok(std::mem::transmute::<*const u8, extern "C" fn(i64, i64, i64, i64) -> i64>(code)(a, b, c, d))
                 }
-                false
-            },
-            Statement::Import(_) | Statement::ImportAs(_, _) => {
-                // Import statements themselves don't have builtin calls,
-                // but the imported modules might use them
-                false
-            },
-        }
-    }
-    
-    fn expression_has_builtin_call(&self, expr: &Expression) -> bool {
-        match expr {
-            Expression::Call { name, .. } => name == "print" || name == "puts",
-            Expression::FormatCall { name, .. } => name == "print" || name == "puts", 
-            Expression::Binary { left, right, .. } => {
-                self.expression_has_builtin_call(left) || self.expression_has_builtin_call(right)
-            },
-            _ => false,
+                _ => Err(format!("{} takes too many arguments to be called from outside", name)),
+            }
+        });
+        match error {
+            Some(message) => Err(message),
+            None => result,
         }
     }
-    
-    // Method to declare external functions like print and puts
+
+    /// Declares the host functions compiled code calls to print, to report
+    /// runtime errors and to keep to its limits.
     pub fn declare_builtins(&mut self) -> Result<(), String> {
-        // Declare print function (for print without newline)
-        {
-            let mut sig = self.module.make_signature();
-            // For now, just take a pointer to string
-            sig.params.push(AbiParam::new(types::I64));
-            sig.returns.push(AbiParam::new(types::I32));
-            
-            self.module
-                .declare_function("print", Linkage::Import, &sig)
-                .map_err(|e| e.to_string())?;
+        if self.host.is_some() {
+            return Ok(());
         }
-        
-        // Declare puts function (for puts with newline)
-        {
+        let pointer_type = self.module.target_config().pointer_type();
+        let mut declare = |name: &str, params: &[Type], returns: &[Type]| -> Result<FuncId, String> {
             let mut sig = self.module.make_signature();
-            // For now, just take a pointer to string
-            sig.params.push(AbiParam::new(types::I64));
-            sig.returns.push(AbiParam::new(types::I32));
-            
+            sig.params.extend(params.iter().map(|&ty| AbiParam::new(ty)));
+            sig.returns.extend(returns.iter().map(|&ty| AbiParam::new(ty)));
             self.module
-                .declare_function("puts", Linkage::Import, &sig)
-                .map_err(|e| e.to_string())?;
-        }
-        
-        Ok(())
-    }
-    
-    // Enhanced compile_function to handle variable declarations
-    pub fn compile_function_advanced(&mut self, func: &Function) -> Result<(), String> {
-        // Create a signature for the function
-        let mut sig = self.module.make_signature();
-        
-        // For now, assuming all functions return i32
-        sig.returns.push(AbiParam::new(types::I32));
-        
-        // Create the function
-        let func_id = self.module
-            .declare_function(&func.name, Linkage::Export, &sig)
-            .map_err(|e| e.to_string())?;
-            
-        let mut ctx = self.module.make_context();
-        ctx.func.signature = sig;
-        
-        {
-            let mut builder = FunctionBuilder::new(&mut ctx.func, &mut self.builder_context);
-            
-            // Create the entry block
-            let block = builder.create_block();
-            builder.append_block_params_for_function_params(block);
-            builder.switch_to_block(block);
-            builder.seal_block(block);
-            
-            // Process the function body (we'll need to implement this properly)
-            // For now, just return 0
-            let return_val = builder.ins().iconst(types::I32, 0);
-            builder.ins().return_(&[return_val]);
-        }
-        
-        // Compile the function
-        self.module
-            .define_function(func_id, &mut ctx)
-            .map_err(|e| e.to_string())?;
-            
-        let _ = self.module.finalize_definitions();
-        
+                .declare_function(name, Linkage::Import, &sig)
+                .map_err(|e| e.to_string())
+        };
+
+        let host = HostFunctions {
+            trap: declare("voltage_jit_trap", &[types::I64; 3], &[])?,
+            failed: declare("voltage_jit_failed", &[], &[types::I64])?,
+            print_int: declare("voltage_jit_print_int", &[types::I64], &[])?,
+            print_bool: declare("voltage_jit_print_bool", &[types::I64], &[])?,
+            print_str: declare("voltage_jit_print_str", &[pointer_type, pointer_type], &[])?,
+            print_newline: declare("voltage_jit_print_newline", &[], &[])?,
+            enter: declare("voltage_jit_enter", &[types::I64], &[types::I64])?,
+            leave: declare("voltage_jit_leave", &[], &[])?,
+            tick: declare("voltage_jit_tick", &[], &[types::I64])?,
+        };
+        self.host = Some(host);
         Ok(())
     }
 }
@@ -247,17 +220,167 @@ impl JitCompiler {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+    use voltage_parser::{Lexer, Parser};
+
+    #[derive(Clone, Default)]
+    struct Captured(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Compiles a program and runs `main`, returning what it printed
+    fn run(source: &str) -> (String, Result<i64, String>) {
+        let program = Parser::from_lexer(Lexer::new(source)).parse();
+        let captured = Captured::default();
+        let mut compiler = JitCompiler::new();
+        compiler.set_output(Box::new(captured.clone()));
+        compiler.compile_program(&program).unwrap();
+        let result = compiler.run("main", &[]);
+        let output = String::from_utf8(captured.0.borrow().clone()).unwrap();
+        (output, result)
+    }
 
     #[test]
+    #[allow(clippy::assertions_on_constants)]
     fn test_jit_compiler_creation() {
         let _compiler = JitCompiler::new();
         // Basic test to ensure JIT compiler can be created
         assert!(true); // This will always pass, but ensures no panic on creation
     }
-    
+
     #[test]
     fn test_builtin_declaration() {
         let mut compiler = JitCompiler::new();
         assert!(compiler.declare_builtins().is_ok());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_functions_run_and_print() {
+        let source = r#"
+            fn fib(n: int) -> int {
+                if n < 2 { return n; }
+                return fib(n - 1) + fib(n - 2);
+            }
+            fn is_even(n) { return n % 2 == 0; }
+            fn main() {
+                let n = 10;
+                puts("fib({}) = {}", n, fib(n));
+                print("even: ");
+                puts(is_even(n) && n != 3);
+                loop {
+                    if fib(n) > 50 { break; }
+                    puts("unreachable");
+                }
+                return -fib(n);
+            }
+        "#;
+        assert_eq!(run(source), ("fib(10) = 55\neven: true\n".to_string(), Ok(-55)));
+    }
+
+    #[test]
+    fn test_runtime_errors_stop_every_caller() {
+        let source = r#"
+            fn divide(a, b) { return a / b; }
+            fn main() {
+                puts(divide(7, 2));
+                puts(divide(1, 0));
+                puts("not reached");
+            }
+        "#;
+        assert_eq!(run(source), ("3\n".to_string(), Err("Division by zero".to_string())));
+
+        let source = "fn main() { return -9223372036854775807 - 1 - 1; }";
+        assert_eq!(run(source).1, Err("Integer overflow: -9223372036854775808 - 1".to_string()));
+    }
+
+    #[test]
+    fn test_limits_stop_deep_recursion_and_endless_loops() {
+        let source = r#"
+            fn down(n) { return down(n + 1); }
+            fn spin() { loop { } }
+            fn count(n) {
+                let i: [int; 1] = [0];
+                while i[0] < n { i[0] += 1; }
+                return i[0];
+            }
+            fn main() { return down(0); }
+        "#;
+        let program = Parser::from_lexer(Lexer::new(source)).parse();
+        let mut compiler = JitCompiler::new();
+        compiler.set_limits(5, Some(100));
+        compiler.compile_program(&program).unwrap();
+        assert_eq!(
+            compiler.run("main", &[]),
+            Err("maximum recursion depth of 5 exceeded in function `down` (most recent calls: down <- down <- down)".to_string())
+        );
+        assert_eq!(compiler.run("spin", &[]), Err("out of fuel after running 100 loop iterations".to_string()));
+        // The while condition is checked 51 times, within the fuel each run is given
+        assert_eq!(compiler.run("count", &[50]), Ok(50));
+        assert_eq!(compiler.run("count", &[50]), Ok(50));
+    }
+
+    #[test]
+    fn test_fixed_arrays_live_on_the_stack() {
+        // Scalars can't be reassigned, so the counters are one-element arrays
+        let source = r#"
+            fn sum_of_squares(n) {
+                let squares: [int; 8] = [0, 0, 0, 0, 0, 0, 0, 0];
+                let i: [int; 1] = [0];
+                while i[0] < len(squares) {
+                    squares[i[0]] = i[0] * i[0];
+                    i[0] += 1;
+                }
+                let total: [int; 1] = [0];
+                i[0] = 0;
+                while i[0] < n {
+                    total[0] += squares[i[0]];
+                    i[0] += 1;
+                }
+                return total[0];
+            }
+            fn main() { return sum_of_squares(8); }
+        "#;
+        let program = Parser::from_lexer(Lexer::new(source)).parse();
+        let mut compiler = JitCompiler::new();
+        compiler.compile_program(&program).unwrap();
+        assert_eq!(compiler.run("main", &[]), Ok(140));
+        assert_eq!(compiler.run("sum_of_squares", &[9]), Err("Index 8 is out of bounds for length 8".to_string()));
+    }
+
+    #[test]
+    fn test_constant_indexes_are_checked_when_compiling() {
+        let source = "fn main() {\n    let xs: [int; 3] = [1, 2, 3];\n    return xs[3];\n}\n";
+        let program = Parser::from_lexer(Lexer::new(source)).parse();
+        let mut compiler = JitCompiler::new();
+        let errors: Vec<String> = compiler.compile_program(&program).unwrap_err().iter().map(ToString::to_string).collect();
+        assert_eq!(errors, ["main: line 3: Index 3 is out of bounds for length 3"]);
+        assert!(!compiler.has_function("main"));
+    }
+
+    #[test]
+    fn test_unsupported_constructs_are_listed_without_compiling() {
+        let source = "fn main() {\n    let xs = [1, 2];\n    for x in xs { puts(x); }\n}\nfn half(x) { return x / 2.0; }\n";
+        let program = Parser::from_lexer(Lexer::new(source)).parse();
+        let mut compiler = JitCompiler::new();
+        let errors: Vec<String> = compiler.compile_program(&program).unwrap_err().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            errors,
+            [
+                "main: line 2: dynamic arrays are not yet supported by the JIT backend; use --backend=vm",
+                "main: line 3: for-loops are not yet supported by the JIT backend; use --backend=vm",
+                "half: line 5: floats are not yet supported by the JIT backend; use --backend=vm",
+            ]
+        );
+        assert!(!compiler.has_function("main"));
+    }
+}
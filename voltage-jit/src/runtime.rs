@@ -0,0 +1,230 @@
+//! The host functions JIT-compiled code calls to print, to report runtime
+//! errors and to keep to the limits it runs under.
+//!
+//! Compiled code can't unwind, so a runtime error is recorded here and every
+//! function returns straight away; callers check [`voltage_jit_failed`]
+//! after each call and return too.
+
+use std::cell::{Cell, RefCell};
+use std::io::{self, Write};
+
+thread_local! {
+    static ERROR: RefCell<Option<String>> = const { RefCell::new(None) };
+    static OUTPUT: RefCell<Option<Box<dyn Write>>> = const { RefCell::new(None) };
+    // The functions in progress, by the index of their name in `NAMES`,
+    // innermost last
+    static CALLS: RefCell<Vec<i64>> = const { RefCell::new(Vec::new()) };
+    static NAMES: RefCell<Vec<String>> = const { RefCell::new(Vec::new()) };
+    static LIMITS: Cell<Limits> = const { Cell::new(Limits { max_call_depth: DEFAULT_MAX_CALL_DEPTH, fuel: None }) };
+    // The loop iterations left, while fuel is limited
+    static FUEL_LEFT: Cell<u64> = const { Cell::new(0) };
+}
+
+/// How many calls may be in progress at once unless
+/// [`crate::JitCompiler::set_limits`] says otherwise, as on the VM.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+
+// How many of the most recent calls a recursion error names, as on the VM
+const TRACE_FRAMES: usize = 3;
+
+/// What compiled code may do before it's stopped with a runtime error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Limits {
+    pub max_call_depth: usize,
+    /// How many loop iterations may run; `None` for no limit.
+    pub fuel: Option<u64>,
+}
+
+/// The runtime errors compiled code can raise. Each is passed to
+/// [`voltage_jit_trap`] as its index here, along with the operands, so the
+/// message reads as the VM's does.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Trap {
+    DivisionByZero,
+    ModuloByZero,
+    AddOverflow,
+    SubtractOverflow,
+    MultiplyOverflow,
+    DivideOverflow,
+    ModuloOverflow,
+    NegateOverflow,
+    IndexOutOfBounds,
+    CallDepth,
+    OutOfFuel,
+}
+
+const TRAPS: [Trap; 11] = [
+    Trap::DivisionByZero,
+    Trap::ModuloByZero,
+    Trap::AddOverflow,
+    Trap::SubtractOverflow,
+    Trap::MultiplyOverflow,
+    Trap::DivideOverflow,
+    Trap::ModuloOverflow,
+    Trap::NegateOverflow,
+    Trap::IndexOutOfBounds,
+    Trap::CallDepth,
+    Trap::OutOfFuel,
+];
+
+impl Trap {
+    pub(crate) fn code(self) -> i64 {
+        TRAPS.iter().position(|&trap| trap == self).expect("every trap is listed") as i64
+    }
+
+    fn message(self, a: i64, b: i64) -> String {
+        match self {
+            Trap::DivisionByZero => "Division by zero".to_string(),
+            Trap::ModuloByZero => "Modulo by zero".to_string(),
+            Trap::AddOverflow => format!("Integer overflow: {} + {}", a, b),
+            Trap::SubtractOverflow => format!("Integer overflow: {} - {}", a, b),
+            Trap::MultiplyOverflow => format!("Integer overflow: {} * {}", a, b),
+            Trap::DivideOverflow => format!("Integer overflow: {} / {}", a, b),
+            Trap::ModuloOverflow => format!("Integer overflow: {} % {}", a, b),
+            Trap::NegateOverflow => format!("Integer overflow: -({})", a),
+            Trap::IndexOutOfBounds => format!("Index {} is out of bounds for length {}", a, b),
+            Trap::CallDepth => {
+                // The callers of the call that went too deep
+                let recent = CALLS.with(|calls| {
+                    let calls = calls.borrow();
+                    let names: Vec<String> =
+                        calls.iter().rev().skip(1).take(TRACE_FRAMES).map(|&function| name(function)).collect();
+                    names.join(" <- ")
+                });
+                format!(
+                    "maximum recursion depth of {} exceeded in function `{}` (most recent calls: {})",
+                    b,
+                    name(a),
+                    recent
+                )
+            }
+            Trap::OutOfFuel => format!("out of fuel after running {} loop iterations", a),
+        }
+    }
+}
+
+fn name(function: i64) -> String {
+    NAMES.with(|names| names.borrow().get(function as usize).cloned().unwrap_or_default())
+}
+
+/// Records a runtime error, keeping the first if there's already one.
+pub(crate) extern "C" fn voltage_jit_trap(code: i64, a: i64, b: i64) {
+    let message = TRAPS[code as usize].message(a, b);
+    ERROR.with(|error| {
+        error.borrow_mut().get_or_insert(message);
+    });
+}
+
+/// Notes that `function` has been called, and returns 1 if that's more calls
+/// in progress than the limit allows, after recording the error. Every call
+/// is followed by [`voltage_jit_leave`], however the function returns.
+pub(crate) extern "C" fn voltage_jit_enter(function: i64) -> i64 {
+    let depth = CALLS.with(|calls| {
+        let mut calls = calls.borrow_mut();
+        calls.push(function);
+        calls.len()
+    });
+    let max = LIMITS.with(Cell::get).max_call_depth;
+    if depth <= max {
+        return 0;
+    }
+    voltage_jit_trap(Trap::CallDepth.code(), function, max as i64);
+    1
+}
+
+pub(crate) extern "C" fn voltage_jit_leave() {
+    CALLS.with(|calls| calls.borrow_mut().pop());
+}
+
+/// Uses up one loop iteration's fuel, and returns 1 if there was none left,
+/// after recording the error.
+pub(crate) extern "C" fn voltage_jit_tick() -> i64 {
+    let Some(given) = LIMITS.with(Cell::get).fuel else {
+        return 0;
+    };
+    match FUEL_LEFT.with(Cell::get) {
+        0 => {
+            voltage_jit_trap(Trap::OutOfFuel.code(), given as i64, 0);
+            1
+        }
+        left => {
+            FUEL_LEFT.with(|fuel| fuel.set(left - 1));
+            0
+        }
+    }
+}
+
+/// 1 if a runtime error has been recorded, else 0.
+pub(crate) extern "C" fn voltage_jit_failed() -> i64 {
+    ERROR.with(|error| error.borrow().is_some() as i64)
+}
+
+pub(crate) extern "C" fn voltage_jit_print_int(value: i64) {
+    write_output(&value.to_string());
+}
+
+pub(crate) extern "C" fn voltage_jit_print_bool(value: i64) {
+    write_output(if value != 0 { "true" } else { "false" });
+}
+
+/// Prints `len` bytes of UTF-8 at `ptr`, a string literal the compiler keeps.
+pub(crate) extern "C" fn voltage_jit_print_str(ptr: *const u8, len: usize) {
+    // The compiler only passes its own strings, which live as long as the code
+    let text = unsafe { std::str::from_utf8_unchecked(std::slice::from_raw_parts(ptr, len)) };
+    write_output(text);
+}
+
+pub(crate) extern "C" fn voltage_jit_print_newline() {
+    write_output("\n");
+}
+
+// Output errors are ignored, as a panic can't unwind out of compiled code
+fn write_output(text: &str) {
+    OUTPUT.with(|output| match output.borrow_mut().as_mut() {
+        Some(output) => {
+            let _ = output.write_all(text.as_bytes());
+        }
+        None => {
+            let _ = io::stdout().lock().write_all(text.as_bytes());
+        }
+    });
+}
+
+/// Runs `body` with printed text going to `output` (stdout if `None`), no
+/// runtime error recorded and `limits` to keep to, and returns what it
+/// returned along with the runtime error it recorded, if any. `names` are
+/// the functions' names, by the index compiled code passes for each.
+pub(crate) fn with_runtime<T>(
+    output: &mut Option<Box<dyn Write>>,
+    limits: Limits,
+    names: Vec<String>,
+    body: impl FnOnce() -> T,
+) -> (T, Option<String>) {
+    ERROR.with(|error| error.borrow_mut().take());
+    CALLS.with(|calls| calls.borrow_mut().clear());
+    NAMES.with(|slot| *slot.borrow_mut() = names);
+    LIMITS.with(|slot| slot.set(limits));
+    FUEL_LEFT.with(|fuel| fuel.set(limits.fuel.unwrap_or(0)));
+    OUTPUT.with(|slot| std::mem::swap(&mut *slot.borrow_mut(), output));
+    let result = body();
+    OUTPUT.with(|slot| std::mem::swap(&mut *slot.borrow_mut(), output));
+    if let Some(output) = output {
+        let _ = output.flush();
+    }
+    (result, ERROR.with(|error| error.borrow_mut().take()))
+}
+
+/// The host functions, by the names compiled code imports them as.
+pub(crate) fn symbols() -> [(&'static str, *const u8); 9] {
+    [
+        ("voltage_jit_trap", voltage_jit_trap as *const u8),
+        ("voltage_jit_failed", voltage_jit_failed as *const u8),
+        ("voltage_jit_enter", voltage_jit_enter as *const u8),
+        ("voltage_jit_leave", voltage_jit_leave as *const u8),
+        ("voltage_jit_tick", voltage_jit_tick as *const u8),
+        ("voltage_jit_print_int", voltage_jit_print_int as *const u8),
+        ("voltage_jit_print_bool", voltage_jit_print_bool as *const u8),
+        ("voltage_jit_print_str", voltage_jit_print_str as *const u8),
+        ("voltage_jit_print_newline", voltage_jit_print_newline as *const u8),
+    ]
+}
@@ -0,0 +1,288 @@
+//! The `JitSupport` pass: finds everything in a program the JIT backend
+//! can't compile yet, before any code is generated for it.
+
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use voltage_core::{BinaryOp, Expression, Function, Literal, Span, Statement, StatementKind, Type};
+
+/// Why the JIT backend can't compile part of a program, and where.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JitError {
+    /// The function the problem is in, or `<top level>`.
+    pub function: String,
+    pub span: Span,
+    pub message: String,
+}
+
+impl JitError {
+    pub fn new(function: &str, span: Span, message: impl Into<String>) -> Self {
+        Self { function: function.to_string(), span, message: message.into() }
+    }
+
+    /// `construct` (a plural, like "for-loops") isn't supported yet.
+    pub fn unsupported(function: &str, span: Span, construct: &str) -> Self {
+        Self::new(function, span, format!("{} are not yet supported by the JIT backend; use --backend=vm", construct))
+    }
+}
+
+/// `main: line 3: for-loops are not yet supported by the JIT backend; use --backend=vm`
+impl fmt::Display for JitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.span.is_known() {
+            write!(f, "{}: line {}: {}", self.function, self.span.line, self.message)
+        } else {
+            write!(f, "{}: {}", self.function, self.message)
+        }
+    }
+}
+
+/// Walks a whole program and lists each statement or expression the JIT
+/// backend can't compile, in source order.
+///
+/// ```
+/// use voltage_core::{Expression, Function, Literal, Statement, StatementKind, Type};
+/// use voltage_jit::JitSupport;
+///
+/// let body = vec![Statement::from(StatementKind::Expression(Expression::Literal(Literal::Float(1.5))))];
+/// let main = Function { name: "main".to_string(), parameters: vec![], return_type: Type::Void, body, public: false };
+/// let unsupported = JitSupport::check(&[StatementKind::Function(main).into()]);
+/// assert_eq!(unsupported[0].message, "floats are not yet supported by the JIT backend; use --backend=vm");
+/// ```
+pub struct JitSupport<'a> {
+    functions: HashSet<&'a str>,
+    // Whether each local in scope is a fixed-size array, innermost scope last
+    locals: Vec<HashMap<&'a str, bool>>,
+    function: &'a str,
+    span: Span,
+    unsupported: Vec<JitError>,
+}
+
+impl<'a> JitSupport<'a> {
+    pub fn check(program: &'a [Statement]) -> Vec<JitError> {
+        Self::check_with(program, std::iter::empty())
+    }
+
+    /// Like [`JitSupport::check`], for a program that may also call the
+    /// functions in `compiled`.
+    pub(crate) fn check_with(program: &'a [Statement], compiled: impl Iterator<Item = &'a str>) -> Vec<JitError> {
+        let functions = program
+            .iter()
+            .filter_map(|stmt| match &stmt.kind {
+                StatementKind::Function(func) => Some(func.name.as_str()),
+                _ => None,
+            })
+            .chain(compiled)
+            .collect();
+        let mut support = JitSupport {
+            functions,
+            locals: Vec::new(),
+            function: "<top level>",
+            span: Span::default(),
+            unsupported: Vec::new(),
+        };
+
+        for stmt in program {
+            match &stmt.kind {
+                StatementKind::Function(func) => support.function(func),
+                _ => {
+                    support.function = "<top level>";
+                    support.span = stmt.span;
+                    support.report("top-level statements");
+                }
+            }
+        }
+        support.unsupported
+    }
+
+    fn function(&mut self, func: &'a Function) {
+        self.function = &func.name;
+        self.span = Span::default();
+        for (name, param_type) in &func.parameters {
+            if scalar_type(param_type).is_none() {
+                self.report(&format!("{} parameters like `{}`", param_type, name));
+            }
+        }
+        if !matches!(func.return_type, Type::Void) && scalar_type(&func.return_type).is_none() {
+            self.report(&format!("functions returning {}", func.return_type));
+        }
+        self.locals = vec![func.parameters.iter().map(|(name, _)| (name.as_str(), false)).collect()];
+        self.statements(&func.body);
+    }
+
+    fn statements(&mut self, statements: &'a [Statement]) {
+        self.locals.push(HashMap::new());
+        for stmt in statements {
+            self.statement(stmt);
+        }
+        self.locals.pop();
+    }
+
+    fn statement(&mut self, stmt: &'a Statement) {
+        self.span = stmt.span;
+        match &stmt.kind {
+            StatementKind::Expression(expr) => self.expression(expr),
+            StatementKind::VariableDeclaration { name, value, explicit_type, .. } => {
+                let fixed_array = match explicit_type {
+                    Some(Type::Array(element, _)) if scalar_type(element).is_some() => true,
+                    Some(Type::DynamicArray(_)) => {
+                        self.report("dynamic arrays");
+                        false
+                    }
+                    Some(declared) if scalar_type(declared).is_none() => {
+                        self.report(&format!("{} variables", declared));
+                        false
+                    }
+                    _ => false,
+                };
+                match value {
+                    Expression::ArrayLiteral(elements) if fixed_array => {
+                        for element in elements {
+                            self.expression(element);
+                        }
+                    }
+                    _ => self.expression(value),
+                }
+                self.locals.last_mut().expect("a scope is open").insert(name.as_str(), fixed_array);
+            }
+            StatementKind::Block(statements) | StatementKind::UnsafeBlock(statements) | StatementKind::Loop(statements) => {
+                self.statements(statements)
+            }
+            StatementKind::If { condition, then_branch, elif_branches, else_branch } => {
+                self.expression(condition);
+                self.statements(then_branch);
+                for (condition, body) in elif_branches {
+                    self.span = stmt.span;
+                    self.expression(condition);
+                    self.statements(body);
+                }
+                if let Some(body) = else_branch {
+                    self.statements(body);
+                }
+            }
+            StatementKind::While { condition, body } => {
+                self.expression(condition);
+                self.statements(body);
+            }
+            StatementKind::Break(None) | StatementKind::Continue | StatementKind::Return(None) => {}
+            StatementKind::Return(Some(value)) => self.expression(value),
+            StatementKind::Break(Some(_)) => self.report("`break` with a value"),
+            StatementKind::For { .. } => self.report("for-loops"),
+            StatementKind::TryCatch { .. } => self.report("try/catch blocks"),
+            StatementKind::Function(_) => self.report("nested functions"),
+            StatementKind::Import(_) | StatementKind::ImportAs(_, _) => self.report("imports"),
+            StatementKind::Impl { .. } => self.report("methods"),
+            StatementKind::Struct { .. } => self.report("structs"),
+        }
+    }
+
+    fn expression(&mut self, expr: &'a Expression) {
+        match expr {
+            Expression::Literal(Literal::Integer(_) | Literal::Boolean(_)) => {}
+            Expression::Variable(name) if self.is_fixed_array(name) => {
+                self.report("fixed-size arrays outside indexing and `len` calls")
+            }
+            Expression::Variable(_) => {}
+            Expression::Literal(Literal::Float(_)) => self.report("floats"),
+            Expression::Literal(Literal::String(_)) => self.report("strings outside `print` and `puts` calls"),
+            Expression::Binary { operator: BinaryOp::Power, .. } => self.report("powers (`**`)"),
+            Expression::Binary { left, right, .. } | Expression::Logical { left, right, .. } => {
+                self.expression(left);
+                self.expression(right);
+            }
+            Expression::Unary { operand, .. } => self.expression(operand),
+            Expression::Call { name, arguments } if is_print(name) => match &arguments[..] {
+                [Expression::Literal(Literal::String(_))] => {}
+                [argument] => self.expression(argument),
+                _ => self.report(&format!("`{}` calls without exactly one argument", name)),
+            },
+            Expression::Call { name, arguments } if self.functions.contains(name.as_str()) => {
+                for argument in arguments {
+                    self.expression(argument);
+                }
+            }
+            Expression::Call { name, arguments } if name == "len" => match &arguments[..] {
+                [Expression::Variable(array)] if self.is_fixed_array(array) => {}
+                _ => self.report("calls to `len` on anything but fixed-size arrays"),
+            },
+            Expression::Call { name, .. } => self.report(&format!("calls to `{}`", name)),
+            Expression::FormatCall { name, arguments, .. } if is_print(name) => {
+                for argument in arguments {
+                    self.expression(argument);
+                }
+            }
+            Expression::FormatCall { name, .. } => self.report(&format!("calls to `{}`", name)),
+            Expression::Debug { .. } => self.report("calls to `dbg`"),
+            Expression::IndirectCall { .. } => self.report("calls through function values"),
+            Expression::ArrayAccess { array, index } => {
+                self.indexed(array);
+                self.expression(index);
+            }
+            Expression::ArrayAssignment { array, index, value } | Expression::ArrayCompoundAssignment { array, index, value, .. } => {
+                self.indexed(array);
+                self.expression(index);
+                self.expression(value);
+            }
+            Expression::ArrayLiteral(_) => self.report("dynamic arrays"),
+            Expression::Range { .. } => self.report("ranges"),
+            Expression::StructInitialization { .. }
+            | Expression::StructFieldAccess { .. }
+            | Expression::StructFieldAssignment { .. } => self.report("structs"),
+            Expression::EnumVariantCreation { .. } | Expression::EnumMatch { .. } => self.report("enums"),
+            Expression::Loop(_) => self.report("`loop` expressions"),
+            Expression::VariableDeclaration { .. } => self.report("declarations inside expressions"),
+        }
+    }
+
+    /// Only fixed-size arrays declared in the function can be indexed.
+    fn indexed(&mut self, array: &'a Expression) {
+        match array {
+            Expression::Variable(name) if self.is_fixed_array(name) => {}
+            _ => self.report("dynamic arrays"),
+        }
+    }
+
+    fn is_fixed_array(&self, name: &str) -> bool {
+        self.locals.iter().rev().find_map(|scope| scope.get(name)).copied().unwrap_or(false)
+    }
+
+    fn report(&mut self, construct: &str) {
+        let error = JitError::unsupported(self.function, self.span, construct);
+        // A statement only needs saying once per construct
+        if !self.unsupported.contains(&error) {
+            self.unsupported.push(error);
+        }
+    }
+}
+
+/// What the JIT backend keeps a value of type `declared` as, if it can.
+/// Parameters without an annotation are ints.
+pub(crate) fn scalar_type(declared: &Type) -> Option<ValueType> {
+    match declared {
+        Type::Integer | Type::Unknown => Some(ValueType::Int),
+        Type::Boolean => Some(ValueType::Bool),
+        _ => None,
+    }
+}
+
+pub(crate) fn is_print(name: &str) -> bool {
+    name == "print" || name == "puts"
+}
+
+/// The types of values in JIT-compiled code. Both are held as an `i64`;
+/// bools are 0 or 1. Void is what calls that return nothing give.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum ValueType {
+    Int,
+    Bool,
+    Void,
+}
+
+impl fmt::Display for ValueType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValueType::Int => write!(f, "int"),
+            ValueType::Bool => write!(f, "bool"),
+            ValueType::Void => write!(f, "nothing"),
+        }
+    }
+}
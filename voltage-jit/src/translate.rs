@@ -0,0 +1,824 @@
+//! Lowers a function's statements and expressions to Cranelift IR.
+
+use std::collections::HashMap;
+use std::iter;
+
+use cranelift::prelude::*;
+use cranelift_codegen::ir::{FuncRef, Inst, StackSlot};
+use cranelift_jit::JITModule;
+use cranelift_module::{FuncId, Module};
+use voltage_core::{BinaryOp, Expression, Function, Literal, LogicalOp, Span, Statement, StatementKind, Type, UnaryOp};
+
+use crate::runtime::Trap;
+use crate::support::{is_print, scalar_type, JitError, ValueType};
+
+/// A compiled function. Every parameter and the result are passed as an
+/// `i64`, whatever their type.
+#[derive(Debug, Clone)]
+pub(crate) struct FunctionInfo {
+    pub id: FuncId,
+    pub params: Vec<ValueType>,
+    pub returns: ValueType,
+}
+
+/// The host functions in [`crate::runtime`], as the module declares them.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct HostFunctions {
+    pub trap: FuncId,
+    pub failed: FuncId,
+    pub print_int: FuncId,
+    pub print_bool: FuncId,
+    pub print_str: FuncId,
+    pub print_newline: FuncId,
+    pub enter: FuncId,
+    pub leave: FuncId,
+    pub tick: FuncId,
+}
+
+/// A local in a function being compiled.
+#[derive(Debug, Clone, Copy)]
+enum Local {
+    Scalar(Variable, ValueType),
+    Array(FixedArray),
+}
+
+/// A fixed-size array, kept in a stack slot with each element as an `i64`.
+#[derive(Debug, Clone, Copy)]
+struct FixedArray {
+    slot: StackSlot,
+    element: ValueType,
+    len: usize,
+}
+
+pub(crate) struct FunctionTranslator<'a> {
+    builder: FunctionBuilder<'a>,
+    module: &'a mut JITModule,
+    functions: &'a HashMap<String, FunctionInfo>,
+    host: HostFunctions,
+    // String literals the code prints, which must live as long as it does
+    strings: &'a mut Vec<Box<str>>,
+    name: &'a str,
+    returns: ValueType,
+    span: Span,
+    scopes: Vec<HashMap<String, Local>>,
+    // The blocks `continue` and `break` jump to, innermost loop last
+    loops: Vec<(Block, Block)>,
+    variables: u32,
+    // Returns straight away once a runtime error has been recorded
+    bail: Option<Block>,
+    func_refs: HashMap<FuncId, FuncRef>,
+}
+
+impl<'a> FunctionTranslator<'a> {
+    pub fn new(
+        builder: FunctionBuilder<'a>,
+        module: &'a mut JITModule,
+        functions: &'a HashMap<String, FunctionInfo>,
+        host: HostFunctions,
+        strings: &'a mut Vec<Box<str>>,
+        name: &'a str,
+    ) -> Self {
+        let returns = functions[name].returns;
+        Self {
+            builder,
+            module,
+            functions,
+            host,
+            strings,
+            name,
+            returns,
+            span: Span::default(),
+            scopes: Vec::new(),
+            loops: Vec::new(),
+            variables: 0,
+            bail: None,
+            func_refs: HashMap::new(),
+        }
+    }
+
+    pub fn translate(mut self, func: &Function) -> Result<(), JitError> {
+        let entry = self.builder.create_block();
+        self.builder.append_block_params_for_function_params(entry);
+        self.builder.switch_to_block(entry);
+        let function = self.builder.ins().iconst(types::I64, i64::from(self.functions[self.name].id.as_u32()));
+        let call = self.call(self.host.enter, &[function]);
+        let too_deep = self.builder.inst_results(call)[0];
+        self.bail_if(too_deep);
+
+        let params = self.builder.block_params(entry).to_vec();
+        let param_types = self.functions[self.name].params.clone();
+        self.scopes.push(HashMap::new());
+        for (((name, _), value), value_type) in func.parameters.iter().zip(params).zip(param_types) {
+            let var = self.declare(name, value_type);
+            self.builder.def_var(var, value);
+        }
+        self.statements(&func.body)?;
+
+        // Falling off the end returns nothing
+        let zero = self.builder.ins().iconst(types::I64, 0);
+        self.return_(zero);
+        if let Some(bail) = self.bail {
+            self.builder.switch_to_block(bail);
+            let zero = self.builder.ins().iconst(types::I64, 0);
+            self.return_(zero);
+        }
+        self.builder.seal_all_blocks();
+        self.builder.finalize();
+        Ok(())
+    }
+
+    fn statements(&mut self, statements: &[Statement]) -> Result<(), JitError> {
+        self.scopes.push(HashMap::new());
+        let result = statements.iter().try_for_each(|stmt| self.statement(stmt));
+        self.scopes.pop();
+        result
+    }
+
+    fn statement(&mut self, stmt: &Statement) -> Result<(), JitError> {
+        self.span = stmt.span;
+        match &stmt.kind {
+            StatementKind::Expression(expr) => {
+                self.expression(expr)?;
+            }
+            StatementKind::VariableDeclaration { name, value, explicit_type: Some(declared @ Type::Array(element, len)), .. } => {
+                self.fixed_array(name, declared, element, *len, value)?;
+            }
+            StatementKind::VariableDeclaration { name, value, explicit_type, .. } => {
+                let (value, value_type) = self.value(value)?;
+                if let Some(declared) = explicit_type.as_ref().and_then(scalar_type) {
+                    if declared != value_type {
+                        return Err(self.error(format!("`{}` is declared {} but given {}", name, declared, value_type)));
+                    }
+                }
+                let var = self.declare(name, value_type);
+                self.builder.def_var(var, value);
+            }
+            StatementKind::Block(statements) | StatementKind::UnsafeBlock(statements) => self.statements(statements)?,
+            StatementKind::If { condition, then_branch, elif_branches, else_branch } => {
+                let merge = self.builder.create_block();
+                let branches = iter::once((condition, then_branch)).chain(elif_branches.iter().map(|(c, body)| (c, body)));
+                for (condition, body) in branches {
+                    self.span = stmt.span;
+                    let condition = self.condition(condition)?;
+                    let then_block = self.builder.create_block();
+                    let next = self.builder.create_block();
+                    self.builder.ins().brif(condition, then_block, &[], next, &[]);
+                    self.builder.switch_to_block(then_block);
+                    self.statements(body)?;
+                    self.builder.ins().jump(merge, &[]);
+                    self.builder.switch_to_block(next);
+                }
+                if let Some(body) = else_branch {
+                    self.statements(body)?;
+                }
+                self.builder.ins().jump(merge, &[]);
+                self.builder.switch_to_block(merge);
+            }
+            StatementKind::While { condition, body } => {
+                let header = self.builder.create_block();
+                let body_block = self.builder.create_block();
+                let exit = self.builder.create_block();
+                self.builder.ins().jump(header, &[]);
+                self.builder.switch_to_block(header);
+                self.tick();
+                let condition = self.condition(condition)?;
+                self.builder.ins().brif(condition, body_block, &[], exit, &[]);
+                self.builder.switch_to_block(body_block);
+                self.loop_body(header, exit, body)?;
+                self.builder.ins().jump(header, &[]);
+                self.builder.switch_to_block(exit);
+            }
+            StatementKind::Loop(body) => {
+                let body_block = self.builder.create_block();
+                let exit = self.builder.create_block();
+                self.builder.ins().jump(body_block, &[]);
+                self.builder.switch_to_block(body_block);
+                self.tick();
+                self.loop_body(body_block, exit, body)?;
+                self.builder.ins().jump(body_block, &[]);
+                self.builder.switch_to_block(exit);
+            }
+            StatementKind::Break(None) | StatementKind::Continue => {
+                let Some(&(next, exit)) = self.loops.last() else {
+                    return Err(self.error("`break` and `continue` must be inside a loop"));
+                };
+                let target = if matches!(stmt.kind, StatementKind::Continue) { next } else { exit };
+                self.builder.ins().jump(target, &[]);
+                self.unreachable();
+            }
+            StatementKind::Return(value) => {
+                let value = match value {
+                    Some(value) => {
+                        let (value, value_type) = self.value(value)?;
+                        if value_type != self.returns {
+                            return Err(self.error(format!("`{}` returns {} here but {} elsewhere", self.name, value_type, self.returns)));
+                        }
+                        value
+                    }
+                    None if self.returns == ValueType::Void => self.builder.ins().iconst(types::I64, 0),
+                    None => return Err(self.error(format!("`{}` returns nothing here but {} elsewhere", self.name, self.returns))),
+                };
+                self.return_(value);
+                self.unreachable();
+            }
+            _ => return Err(self.error("this statement can't be compiled by the JIT backend")),
+        }
+        Ok(())
+    }
+
+    fn loop_body(&mut self, next: Block, exit: Block, body: &[Statement]) -> Result<(), JitError> {
+        self.loops.push((next, exit));
+        let result = self.statements(body);
+        self.loops.pop();
+        result
+    }
+
+    /// Lowers `let name: [element; len] = [...]` to a stack slot, and stores
+    /// each element in it.
+    fn fixed_array(&mut self, name: &str, declared: &Type, element: &Type, len: usize, value: &Expression) -> Result<(), JitError> {
+        let Some(element) = scalar_type(element) else {
+            return Err(self.error(format!("{} variables can't be compiled by the JIT backend", declared)));
+        };
+        let Expression::ArrayLiteral(elements) = value else {
+            return Err(self.error(format!("`{}` has to be given an array literal", name)));
+        };
+        if elements.len() != len {
+            return Err(self.error(format!("`{}` is declared as {}, but its value has {} elements", name, declared, elements.len())));
+        }
+        let mut values = Vec::with_capacity(len);
+        for expr in elements {
+            let (value, value_type) = self.value(expr)?;
+            if value_type != element {
+                return Err(self.error(format!("`{}` is declared as {}, but is given {}", name, declared, value_type)));
+            }
+            values.push(value);
+        }
+
+        let Some(size) = len.checked_mul(8).and_then(|size| u32::try_from(size).ok()) else {
+            return Err(self.error(format!("`{}` is too big for the stack", name)));
+        };
+        let slot = self.builder.create_sized_stack_slot(StackSlotData::new(StackSlotKind::ExplicitSlot, size));
+        for (i, value) in values.into_iter().enumerate() {
+            self.builder.ins().stack_store(value, slot, (i * 8) as i32);
+        }
+        let array = FixedArray { slot, element, len };
+        self.scopes.last_mut().expect("a scope is open").insert(name.to_string(), Local::Array(array));
+        Ok(())
+    }
+
+    /// The fixed-size array `expr` names.
+    fn array(&self, expr: &Expression) -> Result<FixedArray, JitError> {
+        match expr {
+            Expression::Variable(name) => match self.lookup(name) {
+                Some(Local::Array(array)) => Ok(array),
+                Some(Local::Scalar(_, value_type)) => Err(self.error(format!("Cannot index a value of type {}", value_type))),
+                None => Err(self.error(format!("Undefined variable: {}", name))),
+            },
+            _ => Err(self.error("only fixed-size array variables can be indexed by the JIT backend")),
+        }
+    }
+
+    /// Lowers `index` into `array`. A constant index is checked against the
+    /// length here; any other is checked when `element_address` uses it.
+    fn index(&mut self, array: FixedArray, index: &Expression) -> Result<(Value, bool), JitError> {
+        if let Some(i) = constant_index(index) {
+            if !matches!(usize::try_from(i), Ok(i) if i < array.len) {
+                return Err(self.error(format!("Index {} is out of bounds for length {}", i, array.len)));
+            }
+            return Ok((self.builder.ins().iconst(types::I64, i), true));
+        }
+        match self.value(index)? {
+            (index, ValueType::Int) => Ok((index, false)),
+            (_, other) => Err(self.error(format!("Index must be an int, got {}", other))),
+        }
+    }
+
+    /// The address of element `index` of `array`, after a runtime bounds
+    /// check unless the index was checked already.
+    fn element_address(&mut self, array: FixedArray, (index, checked): (Value, bool)) -> Value {
+        if !checked {
+            // A negative index is a huge unsigned one, so one check covers both ends
+            let len = self.builder.ins().iconst(types::I64, array.len as i64);
+            let out_of_bounds = self.builder.ins().icmp(IntCC::UnsignedGreaterThanOrEqual, index, len);
+            self.trap_if(out_of_bounds, Trap::IndexOutOfBounds, index, len);
+        }
+        let pointer_type = self.module.target_config().pointer_type();
+        let base = self.builder.ins().stack_addr(pointer_type, array.slot, 0);
+        let offset = self.builder.ins().imul_imm(index, 8);
+        self.builder.ins().iadd(base, offset)
+    }
+
+    /// Lowers `expr`, which has to give a value.
+    fn value(&mut self, expr: &Expression) -> Result<(Value, ValueType), JitError> {
+        let (value, value_type) = self.expression(expr)?;
+        if value_type == ValueType::Void {
+            return Err(self.error("this gives nothing, where a value is needed"));
+        }
+        Ok((value, value_type))
+    }
+
+    fn condition(&mut self, expr: &Expression) -> Result<Value, JitError> {
+        match self.expression(expr)? {
+            (value, ValueType::Bool) => Ok(value),
+            (_, other) => Err(self.error(format!("expected bool in condition, found {}", other))),
+        }
+    }
+
+    fn expression(&mut self, expr: &Expression) -> Result<(Value, ValueType), JitError> {
+        match expr {
+            Expression::Literal(Literal::Integer(n)) => Ok((self.builder.ins().iconst(types::I64, *n), ValueType::Int)),
+            Expression::Literal(Literal::Boolean(b)) => {
+                Ok((self.builder.ins().iconst(types::I64, *b as i64), ValueType::Bool))
+            }
+            Expression::Variable(name) => match self.lookup(name) {
+                Some(Local::Scalar(var, value_type)) => Ok((self.builder.use_var(var), value_type)),
+                Some(Local::Array(_)) => {
+                    Err(self.error(format!("`{}` is a fixed-size array, which can only be indexed or passed to `len`", name)))
+                }
+                None => Err(self.error(format!("Undefined variable: {}", name))),
+            },
+            Expression::ArrayAccess { array, index } => {
+                let array = self.array(array)?;
+                let index = self.index(array, index)?;
+                let address = self.element_address(array, index);
+                Ok((self.builder.ins().load(types::I64, MemFlags::trusted(), address, 0), array.element))
+            }
+            // The index, then the value, are worked out before the bounds check, as on the VM
+            Expression::ArrayAssignment { array, index, value } => {
+                let array = self.array(array)?;
+                let index = self.index(array, index)?;
+                let (value, value_type) = self.value(value)?;
+                if value_type != array.element {
+                    return Err(self.error(format!("Cannot store {} in an array of {}", value_type, array.element)));
+                }
+                let address = self.element_address(array, index);
+                self.builder.ins().store(MemFlags::trusted(), value, address, 0);
+                Ok((self.builder.ins().iconst(types::I64, 0), ValueType::Void))
+            }
+            Expression::ArrayCompoundAssignment { array, index, operator, value } => {
+                let array = self.array(array)?;
+                let index = self.index(array, index)?;
+                let address = self.element_address(array, index);
+                let current = self.builder.ins().load(types::I64, MemFlags::trusted(), address, 0);
+                let (value, value_type) = self.value(value)?;
+                let (result, _) = self.binary(operator, current, array.element, value, value_type)?;
+                self.builder.ins().store(MemFlags::trusted(), result, address, 0);
+                Ok((self.builder.ins().iconst(types::I64, 0), ValueType::Void))
+            }
+            Expression::Binary { left, operator, right } => {
+                let (a, left_type) = self.value(left)?;
+                let (b, right_type) = self.value(right)?;
+                self.binary(operator, a, left_type, b, right_type)
+            }
+            Expression::Unary { operator: UnaryOp::Negate, operand } => match self.value(operand)? {
+                (value, ValueType::Int) => {
+                    let min = self.builder.ins().icmp_imm(IntCC::Equal, value, i64::MIN);
+                    self.trap_if(min, Trap::NegateOverflow, value, value);
+                    Ok((self.builder.ins().ineg(value), ValueType::Int))
+                }
+                (_, other) => Err(self.error(format!("Cannot negate a value of type {}", other))),
+            },
+            Expression::Logical { left, operator, right } => {
+                // `right` only runs when `left` doesn't decide the result
+                let left = self.condition(left)?;
+                let right_block = self.builder.create_block();
+                let merge = self.builder.create_block();
+                self.builder.append_block_param(merge, types::I64);
+                match operator {
+                    LogicalOp::And => {
+                        let decided = self.builder.ins().iconst(types::I64, 0);
+                        self.builder.ins().brif(left, right_block, &[], merge, &[decided]);
+                    }
+                    LogicalOp::Or => {
+                        let decided = self.builder.ins().iconst(types::I64, 1);
+                        self.builder.ins().brif(left, merge, &[decided], right_block, &[]);
+                    }
+                }
+                self.builder.switch_to_block(right_block);
+                let right = self.condition(right)?;
+                self.builder.ins().jump(merge, &[right]);
+                self.builder.switch_to_block(merge);
+                Ok((self.builder.block_params(merge)[0], ValueType::Bool))
+            }
+            Expression::Call { name, arguments } if is_print(name) => {
+                match &arguments[..] {
+                    [Expression::Literal(Literal::String(text))] => self.print_text(text),
+                    [argument] => {
+                        let (value, value_type) = self.expression(argument)?;
+                        self.print_value(value, value_type);
+                    }
+                    _ => return Err(self.error(format!("{} expects 1 argument", name))),
+                }
+                Ok(self.nothing(name))
+            }
+            Expression::FormatCall { name, format_string, arguments } if is_print(name) => {
+                let pieces = format_pieces(format_string).map_err(|message| self.error(message))?;
+                let placeholders = pieces.iter().filter(|piece| matches!(piece, Piece::Argument)).count();
+                if placeholders != arguments.len() {
+                    return Err(self.error(format!(
+                        "format string has {} placeholders but {} arguments were given",
+                        placeholders,
+                        arguments.len()
+                    )));
+                }
+                // Every argument is worked out before anything is printed
+                let mut values = Vec::with_capacity(arguments.len());
+                for argument in arguments {
+                    values.push(self.expression(argument)?);
+                }
+                let mut values = values.into_iter();
+                for piece in &pieces {
+                    match piece {
+                        Piece::Text(text) => self.print_text(text),
+                        Piece::Argument => {
+                            let (value, value_type) = values.next().expect("placeholders were counted");
+                            self.print_value(value, value_type);
+                        }
+                    }
+                }
+                Ok(self.nothing(name))
+            }
+            Expression::Call { name, arguments } if name == "len" && !self.functions.contains_key(name) => match &arguments[..] {
+                [argument] => {
+                    let array = self.array(argument)?;
+                    Ok((self.builder.ins().iconst(types::I64, array.len as i64), ValueType::Int))
+                }
+                _ => Err(self.error(format!("len expects 1 argument, got {}", arguments.len()))),
+            },
+            Expression::Call { name, arguments } => {
+                let Some(info) = self.functions.get(name) else {
+                    return Err(self.error(format!("Unknown function: {}", name)));
+                };
+                if arguments.len() != info.params.len() {
+                    return Err(self.error(format!(
+                        "Function {} expects {} arguments, got {}",
+                        name,
+                        info.params.len(),
+                        arguments.len()
+                    )));
+                }
+                let mut values = Vec::with_capacity(arguments.len());
+                for (i, (argument, &expected)) in arguments.iter().zip(&info.params).enumerate() {
+                    let (value, value_type) = self.value(argument)?;
+                    if value_type != expected {
+                        return Err(self.error(format!("{}: argument {} expected {}, found {}", name, i + 1, expected, value_type)));
+                    }
+                    values.push(value);
+                }
+                let call = self.call(info.id, &values);
+                let result = self.builder.inst_results(call)[0];
+                // The callee returned early if it ran into a runtime error
+                let failed = self.call(self.host.failed, &[]);
+                let failed = self.builder.inst_results(failed)[0];
+                self.bail_if(failed);
+                Ok((result, info.returns))
+            }
+            _ => Err(self.error("this expression can't be compiled by the JIT backend")),
+        }
+    }
+
+    fn binary(
+        &mut self,
+        operator: &BinaryOp,
+        a: Value,
+        left: ValueType,
+        b: Value,
+        right: ValueType,
+    ) -> Result<(Value, ValueType), JitError> {
+        let comparison = match operator {
+            BinaryOp::Equal => IntCC::Equal,
+            BinaryOp::NotEqual => IntCC::NotEqual,
+            BinaryOp::Less => IntCC::SignedLessThan,
+            BinaryOp::LessEqual => IntCC::SignedLessThanOrEqual,
+            BinaryOp::Greater => IntCC::SignedGreaterThan,
+            BinaryOp::GreaterEqual => IntCC::SignedGreaterThanOrEqual,
+            _ => {
+                if (left, right) != (ValueType::Int, ValueType::Int) {
+                    return Err(self.error(format!("Cannot apply `{}` to {} and {}", operator, left, right)));
+                }
+                return Ok((self.arithmetic(operator, a, b)?, ValueType::Int));
+            }
+        };
+        let equality = matches!(operator, BinaryOp::Equal | BinaryOp::NotEqual);
+        if left != right || (!equality && left != ValueType::Int) {
+            return Err(self.error(format!("Cannot compare {} and {} with `{}`", left, right, operator)));
+        }
+        let result = self.builder.ins().icmp(comparison, a, b);
+        Ok((self.builder.ins().uextend(types::I64, result), ValueType::Bool))
+    }
+
+    /// Int arithmetic, with the same runtime errors as the VM's.
+    fn arithmetic(&mut self, operator: &BinaryOp, a: Value, b: Value) -> Result<Value, JitError> {
+        let (result, overflowed, trap) = match operator {
+            BinaryOp::Add => {
+                let (result, overflowed) = self.builder.ins().sadd_overflow(a, b);
+                (result, overflowed, Trap::AddOverflow)
+            }
+            BinaryOp::Subtract => {
+                let (result, overflowed) = self.builder.ins().ssub_overflow(a, b);
+                (result, overflowed, Trap::SubtractOverflow)
+            }
+            BinaryOp::Multiply => {
+                let (result, overflowed) = self.builder.ins().smul_overflow(a, b);
+                (result, overflowed, Trap::MultiplyOverflow)
+            }
+            BinaryOp::Divide | BinaryOp::Modulo => {
+                let divide = matches!(operator, BinaryOp::Divide);
+                let zero = self.builder.ins().icmp_imm(IntCC::Equal, b, 0);
+                self.trap_if(zero, if divide { Trap::DivisionByZero } else { Trap::ModuloByZero }, a, b);
+                // i64::MIN / -1 doesn't fit, and i64::MIN % -1 fails along with it
+                let min = self.builder.ins().icmp_imm(IntCC::Equal, a, i64::MIN);
+                let minus_one = self.builder.ins().icmp_imm(IntCC::Equal, b, -1);
+                let overflowed = self.builder.ins().band(min, minus_one);
+                self.trap_if(overflowed, if divide { Trap::DivideOverflow } else { Trap::ModuloOverflow }, a, b);
+                return Ok(if divide { self.builder.ins().sdiv(a, b) } else { self.builder.ins().srem(a, b) });
+            }
+            _ => return Err(self.error(format!("`{}` can't be compiled by the JIT backend", operator))),
+        };
+        self.trap_if(overflowed, trap, a, b);
+        Ok(result)
+    }
+
+    /// Records `trap` and returns if `condition` is true.
+    fn trap_if(&mut self, condition: Value, trap: Trap, a: Value, b: Value) {
+        let fail = self.builder.create_block();
+        let ok = self.builder.create_block();
+        self.builder.set_cold_block(fail);
+        self.builder.ins().brif(condition, fail, &[], ok, &[]);
+        self.builder.switch_to_block(fail);
+        let code = self.builder.ins().iconst(types::I64, trap.code());
+        self.call(self.host.trap, &[code, a, b]);
+        let bail = self.bail();
+        self.builder.ins().jump(bail, &[]);
+        self.builder.switch_to_block(ok);
+    }
+
+    /// Returns if `condition` is true, a runtime error having been recorded.
+    fn bail_if(&mut self, condition: Value) {
+        let ok = self.builder.create_block();
+        let bail = self.bail();
+        self.builder.ins().brif(condition, bail, &[], ok, &[]);
+        self.builder.switch_to_block(ok);
+    }
+
+    /// Uses up a loop iteration's fuel, returning if there was none left.
+    fn tick(&mut self) {
+        let call = self.call(self.host.tick, &[]);
+        let out_of_fuel = self.builder.inst_results(call)[0];
+        self.bail_if(out_of_fuel);
+    }
+
+    /// Returns `value`, once the runtime knows this call is over.
+    fn return_(&mut self, value: Value) {
+        self.call(self.host.leave, &[]);
+        self.builder.ins().return_(&[value]);
+    }
+
+    fn print_value(&mut self, value: Value, value_type: ValueType) {
+        match value_type {
+            ValueType::Int => {
+                self.call(self.host.print_int, &[value]);
+            }
+            ValueType::Bool => {
+                self.call(self.host.print_bool, &[value]);
+            }
+            ValueType::Void => self.print_text("null"),
+        }
+    }
+
+    fn print_text(&mut self, text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        let text: Box<str> = text.into();
+        let pointer_type = self.module.target_config().pointer_type();
+        let ptr = self.builder.ins().iconst(pointer_type, text.as_ptr() as i64);
+        let len = self.builder.ins().iconst(pointer_type, text.len() as i64);
+        self.strings.push(text);
+        self.call(self.host.print_str, &[ptr, len]);
+    }
+
+    /// What a `print` or `puts` call gives, after the newline `puts` adds.
+    fn nothing(&mut self, name: &str) -> (Value, ValueType) {
+        if name == "puts" {
+            self.call(self.host.print_newline, &[]);
+        }
+        (self.builder.ins().iconst(types::I64, 0), ValueType::Void)
+    }
+
+    fn call(&mut self, id: FuncId, args: &[Value]) -> Inst {
+        let func_ref = match self.func_refs.get(&id) {
+            Some(&func_ref) => func_ref,
+            None => {
+                let func_ref = self.module.declare_func_in_func(id, self.builder.func);
+                self.func_refs.insert(id, func_ref);
+                func_ref
+            }
+        };
+        self.builder.ins().call(func_ref, args)
+    }
+
+    fn bail(&mut self) -> Block {
+        match self.bail {
+            Some(bail) => bail,
+            None => {
+                let bail = self.builder.create_block();
+                self.builder.set_cold_block(bail);
+                *self.bail.insert(bail)
+            }
+        }
+    }
+
+    /// Carries on in a block nothing jumps to, after a jump or return.
+    fn unreachable(&mut self) {
+        let block = self.builder.create_block();
+        self.builder.switch_to_block(block);
+    }
+
+    fn declare(&mut self, name: &str, value_type: ValueType) -> Variable {
+        let var = Variable::from_u32(self.variables);
+        self.variables += 1;
+        self.builder.declare_var(var, types::I64);
+        self.scopes.last_mut().expect("a scope is open").insert(name.to_string(), Local::Scalar(var, value_type));
+        var
+    }
+
+    fn lookup(&self, name: &str) -> Option<Local> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name).copied())
+    }
+
+    fn error(&self, message: impl Into<String>) -> JitError {
+        JitError::new(self.name, self.span, message)
+    }
+}
+
+/// The value of `expr` if it's an int literal, negated or not.
+fn constant_index(expr: &Expression) -> Option<i64> {
+    match expr {
+        Expression::Literal(Literal::Integer(n)) => Some(*n),
+        Expression::Unary { operator: UnaryOp::Negate, operand } => match operand.as_ref() {
+            Expression::Literal(Literal::Integer(n)) => n.checked_neg(),
+            _ => None,
+        },
+        _ => None,
+    }
+}
+
+/// A piece of a format string: text to print as it is, or a `{}`.
+enum Piece {
+    Text(String),
+    Argument,
+}
+
+/// Splits `template` as the VM's `format_values` reads it: `{{` and `}}` are
+/// literal braces and `{}` takes the next argument.
+fn format_pieces(template: &str) -> Result<Vec<Piece>, String> {
+    let mut pieces = Vec::new();
+    let mut text = String::new();
+    let mut chars = template.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                text.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                pieces.push(Piece::Text(std::mem::take(&mut text)));
+                pieces.push(Piece::Argument);
+            }
+            ('{', _) => return Err("unmatched `{` in format string; use `{{` for a literal brace".to_string()),
+            ('}', _) => return Err("unmatched `}` in format string; use `}}` for a literal brace".to_string()),
+            _ => text.push(c),
+        }
+    }
+    pieces.push(Piece::Text(text));
+    Ok(pieces)
+}
+
+/// The type each of `functions` returns: its annotation, or if it has none,
+/// the type of what its `return`s give. Functions whose returns only give
+/// what calls to other such functions give are worked out once those are,
+/// and any left, like one that only calls itself, return ints.
+pub(crate) fn return_types(functions: &[&Function], compiled: &HashMap<String, FunctionInfo>) -> HashMap<String, ValueType> {
+    let mut known: HashMap<String, ValueType> =
+        compiled.iter().map(|(name, info)| (name.clone(), info.returns)).collect();
+    let mut pending: Vec<&Function> = Vec::new();
+    for func in functions {
+        match scalar_type(&func.return_type) {
+            Some(declared) => {
+                known.insert(func.name.clone(), declared);
+            }
+            None => pending.push(func),
+        }
+    }
+
+    loop {
+        let before = pending.len();
+        pending.retain(|func| {
+            let mut scopes = vec![func
+                .parameters
+                .iter()
+                .map(|(name, declared)| (name.clone(), scalar_type(declared).unwrap_or(ValueType::Int)))
+                .collect()];
+            match returned_type(&func.body, &mut scopes, &known) {
+                Some(returns) => {
+                    known.insert(func.name.clone(), returns);
+                    false
+                }
+                None => true,
+            }
+        });
+        if pending.is_empty() || pending.len() == before {
+            break;
+        }
+    }
+    for func in pending {
+        known.insert(func.name.clone(), ValueType::Int);
+    }
+    known
+}
+
+/// The type the first `return` in `statements` whose type can be worked out
+/// gives, nothing if there are no `return`s with a value, or `None` if none
+/// of those can be worked out yet.
+fn returned_type(
+    statements: &[Statement],
+    scopes: &mut Vec<HashMap<String, ValueType>>,
+    known: &HashMap<String, ValueType>,
+) -> Option<ValueType> {
+    let mut unknown = false;
+    scopes.push(HashMap::new());
+    for stmt in statements {
+        let found = match &stmt.kind {
+            StatementKind::Return(Some(value)) => match expression_type(value, scopes, known) {
+                Some(value_type) => Some(value_type),
+                None => {
+                    unknown = true;
+                    None
+                }
+            },
+            // A fixed-size array is listed as its element type, which indexing it gives
+            StatementKind::VariableDeclaration { name, explicit_type: Some(Type::Array(element, _)), .. } => {
+                if let Some(element) = scalar_type(element) {
+                    scopes.last_mut().expect("a scope is open").insert(name.clone(), element);
+                }
+                None
+            }
+            StatementKind::VariableDeclaration { name, value, .. } => {
+                if let Some(value_type) = expression_type(value, scopes, known) {
+                    scopes.last_mut().expect("a scope is open").insert(name.clone(), value_type);
+                }
+                None
+            }
+            StatementKind::If { then_branch, elif_branches, else_branch, .. } => iter::once(then_branch)
+                .chain(elif_branches.iter().map(|(_, body)| body))
+                .chain(else_branch)
+                .map(|body| returned_type(body, scopes, known))
+                .find(|found| *found != Some(ValueType::Void))
+                .unwrap_or(Some(ValueType::Void)),
+            StatementKind::While { body, .. }
+            | StatementKind::Loop(body)
+            | StatementKind::Block(body)
+            | StatementKind::UnsafeBlock(body) => returned_type(body, scopes, known),
+            _ => Some(ValueType::Void),
+        };
+        match found {
+            Some(ValueType::Void) => {}
+            Some(value_type) => {
+                scopes.pop();
+                return Some(value_type);
+            }
+            None => unknown = true,
+        }
+    }
+    scopes.pop();
+    (!unknown).then_some(ValueType::Void)
+}
+
+fn expression_type(
+    expr: &Expression,
+    scopes: &[HashMap<String, ValueType>],
+    known: &HashMap<String, ValueType>,
+) -> Option<ValueType> {
+    match expr {
+        Expression::Literal(Literal::Integer(_)) | Expression::Unary { .. } => Some(ValueType::Int),
+        Expression::Literal(Literal::Boolean(_)) | Expression::Logical { .. } => Some(ValueType::Bool),
+        Expression::Variable(name) => scopes.iter().rev().find_map(|scope| scope.get(name).copied()),
+        Expression::Binary { operator, .. } => match operator {
+            BinaryOp::Equal
+            | BinaryOp::NotEqual
+            | BinaryOp::Less
+            | BinaryOp::LessEqual
+            | BinaryOp::Greater
+            | BinaryOp::GreaterEqual => Some(ValueType::Bool),
+            _ => Some(ValueType::Int),
+        },
+        Expression::ArrayAccess { array, .. } => expression_type(array, scopes, known),
+        Expression::ArrayAssignment { .. } | Expression::ArrayCompoundAssignment { .. } => Some(ValueType::Void),
+        Expression::Call { name, .. } | Expression::FormatCall { name, .. } if is_print(name) => Some(ValueType::Void),
+        Expression::Call { name, .. } if name == "len" && !known.contains_key(name) => Some(ValueType::Int),
+        Expression::Call { name, .. } => known.get(name).copied(),
+        _ => None,
+    }
+}
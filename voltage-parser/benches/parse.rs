@@ -0,0 +1,44 @@
+//! Lexing and parsing a large generated file, with the tokens copied into the
+//! parser as callers used to, and taken over with `Parser::from_lexer`.
+//!
+//! Run with `cargo bench -p voltage-parser`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use voltage_parser::{Lexer, Parser};
+
+const STATEMENTS: usize = 100_000;
+
+// A function every hundred statements, and lets, calls and loops between
+fn generated_source() -> String {
+    let mut source = String::new();
+    for i in 0..STATEMENTS {
+        let line = match i % 100 {
+            0 => format!("fn f{}(a, b) {{ return a * {} + b; }}\n", i, i),
+            n if n % 3 == 0 => format!("let x{} = f{}(x, [1, 2, {}]);\n", i, i / 100 * 100, i),
+            n if n % 3 == 1 => format!("while x < {} {{ let x = x + 1; }}\n", i),
+            _ => format!("puts(\"step {}: {{x}}\");\n", i),
+        };
+        source.push_str(&line);
+    }
+    source
+}
+
+fn parse(c: &mut Criterion) {
+    let source = generated_source();
+    let mut group = c.benchmark_group("parse 100k statements");
+    group.sample_size(10);
+    group.bench_function("copied tokens", |b| {
+        b.iter_batched(
+            || Lexer::new(&source),
+            |lexer| Parser::with_spans(lexer.tokenize().to_vec(), lexer.spans().to_vec()).parse(),
+            BatchSize::LargeInput,
+        )
+    });
+    group.bench_function("from_lexer", |b| {
+        b.iter_batched(|| Lexer::new(&source), |lexer| Parser::from_lexer(lexer).parse(), BatchSize::LargeInput)
+    });
+    group.finish();
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
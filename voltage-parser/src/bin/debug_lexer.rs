@@ -1,5 +1,5 @@
 fn main() {
-    let source = r#"fn main() { }"#.to_string();
+    let source = r#"fn main() { }"#;
     let lexer = voltage_parser::Lexer::new(source);
     let tokens = lexer.tokenize();
     
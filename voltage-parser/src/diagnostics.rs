@@ -0,0 +1,750 @@
+//! One-call parsing for editors: whatever structure the source has, plus
+//! everything that was wrong with it.
+
+use std::fmt;
+use voltage_core::{Span, Statement};
+use crate::lexer::{Lexer, Token};
+use crate::parser::Parser;
+
+/// How serious a [`Diagnostic`] is: errors keep a program from running,
+/// warnings don't.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize), serde(rename_all = "lowercase"))]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+        }
+    }
+}
+
+/// A problem found in the source, and where.
+///
+/// With the `serde` feature it serializes as an object with `severity`,
+/// `code`, `message`, `span` and `notes`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Diagnostic {
+    pub severity: Severity,
+    /// What kind of problem it is, like `syntax_error` or `unused_variable`,
+    /// for tools to tell them apart without matching on messages.
+    pub code: Option<&'static str>,
+    pub message: String,
+    pub span: Span,
+    /// Other places in the source that explain this one.
+    pub notes: Vec<Note>,
+    /// For a syntax error the parser recovered from, the source it skipped
+    /// to get back on track. Errors in there are likely to follow from this
+    /// one rather than be mistakes of their own.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    pub recovery: Option<Span>,
+}
+
+/// A place in the source that a [`Diagnostic`] refers to, like the earlier
+/// definition of a name defined twice.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Note {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    /// An error.
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Self { severity: Severity::Error, code: None, message: message.into(), span, notes: Vec::new(), recovery: None }
+    }
+
+    pub fn warning(message: impl Into<String>, span: Span) -> Self {
+        Self { severity: Severity::Warning, ..Self::new(message, span) }
+    }
+
+    pub fn with_code(self, code: &'static str) -> Self {
+        Self { code: Some(code), ..self }
+    }
+
+    pub fn with_note(mut self, message: impl Into<String>, span: Span) -> Self {
+        self.notes.push(Note { message: message.into(), span });
+        self
+    }
+
+    // Records that the parser skipped `skipped` to recover from this error
+    pub(crate) fn recovered(self, skipped: Span) -> Self {
+        let recovery = (skipped.is_known() && skipped.end > skipped.start).then_some(skipped);
+        Self { recovery, ..self }
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // Line 0 is an unknown location
+        if self.span.line == 0 {
+            return write!(f, "{}", self.message);
+        }
+        write!(f, "{}:{}: {}", self.span.line, self.span.column, self.message)
+    }
+}
+
+/// How many diagnostics [`summarize`] shows unless told otherwise.
+pub const DEFAULT_DIAGNOSTIC_LIMIT: usize = 20;
+
+/// The diagnostics worth showing out of everything found in a source file,
+/// from [`summarize`]. It displays as one `severity: line:column: message`
+/// line per diagnostic, then a `... and N more errors` line for the ones
+/// over the limit.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DiagnosticSummary {
+    /// Errors first, then warnings, each in the order they were found.
+    pub shown: Vec<Diagnostic>,
+    /// How many errors and warnings were over the limit.
+    pub omitted_errors: usize,
+    pub omitted_warnings: usize,
+}
+
+impl DiagnosticSummary {
+    /// The line saying how many diagnostics were over the limit, if any were.
+    pub fn omitted(&self) -> Option<String> {
+        let count = |n: usize, what: &str| format!("{} more {}{}", n, what, if n == 1 { "" } else { "s" });
+        let counts = match (self.omitted_errors, self.omitted_warnings) {
+            (0, 0) => return None,
+            (errors, 0) => count(errors, "error"),
+            (0, warnings) => count(warnings, "warning"),
+            (errors, warnings) => format!("{} and {}", count(errors, "error"), count(warnings, "warning")),
+        };
+        Some(format!("... and {}", counts))
+    }
+}
+
+impl fmt::Display for DiagnosticSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for diagnostic in &self.shown {
+            writeln!(f, "{}: {}", diagnostic.severity, diagnostic)?;
+        }
+        if let Some(omitted) = self.omitted() {
+            writeln!(f, "{}", omitted)?;
+        }
+        Ok(())
+    }
+}
+
+impl DiagnosticSummary {
+    /// Renders the diagnostics shown with [`Diagnostic::render`], a blank
+    /// line after each, then the line for the ones over the limit.
+    pub fn render(&self, file: &str, source: &str, options: RenderOptions) -> String {
+        let mut out = String::new();
+        for diagnostic in &self.shown {
+            out.push_str(&diagnostic.render(file, source, options));
+            out.push('\n');
+        }
+        if let Some(omitted) = self.omitted() {
+            out.push_str(&omitted);
+            out.push('\n');
+        }
+        out
+    }
+}
+
+/// How wide [`Diagnostic::render`] wraps messages when the terminal's width
+/// isn't known.
+pub const DEFAULT_RENDER_WIDTH: usize = 100;
+
+/// How many columns a tab in the source takes up in a rendered snippet.
+const TAB_WIDTH: usize = 4;
+
+// ANSI escapes for the colored parts of a rendered diagnostic
+const RESET: &str = "\x1b[0m";
+const GUTTER: &str = "\x1b[1;34m";
+
+/// How [`Diagnostic::render`] lays diagnostics out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RenderOptions {
+    /// Color the severity, code, underline and gutter with ANSI escapes.
+    pub color: bool,
+    /// The columns to wrap messages to. Source lines are never wrapped, so
+    /// that the underline stays under them.
+    pub width: usize,
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        Self { color: false, width: DEFAULT_RENDER_WIDTH }
+    }
+}
+
+impl RenderOptions {
+    fn paint(&self, color: &str, text: &str) -> String {
+        match self.color && !text.is_empty() {
+            true => format!("{}{}{}", color, text, RESET),
+            false => text.to_string(),
+        }
+    }
+}
+
+impl Severity {
+    // What its label and underline are colored with
+    fn color(self) -> &'static str {
+        match self {
+            Severity::Error => "\x1b[1;31m",
+            Severity::Warning => "\x1b[1;33m",
+        }
+    }
+}
+
+/// One source line of a rendered snippet, with the bytes of it to underline.
+struct Underline<'s> {
+    line: usize,
+    text: &'s str,
+    from: usize,
+    to: usize,
+}
+
+impl Diagnostic {
+    /// Renders the diagnostic for people, with the source it points at and
+    /// the span underlined:
+    ///
+    /// ```text
+    /// error[syntax_error]: Expected expression, got Semi
+    ///  --> broken.v:2:9
+    ///   |
+    /// 2 | let b = ;
+    ///   |         ^
+    /// ```
+    ///
+    /// `file` is only used to say where the source came from. A span over
+    /// several lines shows its first and last lines, with `...` for the ones
+    /// in between.
+    pub fn render(&self, file: &str, source: &str, options: RenderOptions) -> String {
+        let label = match self.code {
+            Some(code) => format!("{}[{}]", self.severity, code),
+            None => self.severity.to_string(),
+        };
+        let mut out = options.paint(self.severity.color(), &label);
+        out.push_str(": ");
+        out.push_str(&wrapped(&self.message, label.len() + 2, options.width));
+        out.push('\n');
+        if !self.span.is_known() {
+            for note in &self.notes {
+                out.push_str(&render_note(note, "", options));
+            }
+            return out;
+        }
+
+        let underlines = self.underlines(source);
+        let last_line = underlines.last().map_or(self.span.line, |underline| underline.line);
+        let pad = " ".repeat(last_line.to_string().len());
+        let gutter = |text: &str| options.paint(GUTTER, &format!("{}{}", pad, text));
+        out.push_str(&format!("{}{}:{}:{}\n", gutter("--> "), file, self.span.line, self.span.column));
+        if !underlines.is_empty() {
+            out.push_str(&format!("{}\n", gutter(" |")));
+        }
+        let mut previous = None;
+        for underline in &underlines {
+            if previous.is_some_and(|line| underline.line > line + 1) {
+                out.push_str(&format!("{}\n", options.paint(GUTTER, "...")));
+            }
+            previous = Some(underline.line);
+            let number = format!("{:>width$} |", underline.line, width = pad.len());
+            let text = expand_tabs(underline.text);
+            out.push_str(format!("{} {}", options.paint(GUTTER, &number), text).trim_end());
+            out.push('\n');
+            let from = display_column(underline.text, underline.from);
+            let to = display_column(underline.text, underline.to).max(from + 1);
+            let carets = options.paint(self.severity.color(), &"^".repeat(to - from));
+            out.push_str(&format!("{} {}{}\n", gutter(" |"), " ".repeat(from), carets));
+        }
+        for note in &self.notes {
+            out.push_str(&render_note(note, &pad, options));
+        }
+        out
+    }
+
+    // The lines of `source` the span is on: just its first and last, with
+    // byte offsets into each of what to underline
+    fn underlines<'s>(&self, source: &'s str) -> Vec<Underline<'s>> {
+        let lines: Vec<&str> = source.lines().collect();
+        let Some(&first) = lines.get(self.span.line - 1) else {
+            return Vec::new();
+        };
+        let from = (self.span.column - 1).min(first.len());
+        let (mut end_line, mut end_column) = self.span.end_position(source);
+        // A span that takes in a newline ends on the line before it
+        if end_line > self.span.line && end_column == 1 {
+            end_line -= 1;
+            end_column = lines.get(end_line - 1).map_or(1, |line| line.len() + 1);
+        }
+        match lines.get(end_line.wrapping_sub(1)) {
+            Some(&last) if end_line > self.span.line => {
+                let indent = last.len() - last.trim_start().len();
+                vec![
+                    Underline { line: self.span.line, text: first, from, to: first.len() },
+                    Underline { line: end_line, text: last, from: indent, to: (end_column - 1).min(last.len()) },
+                ]
+            }
+            // Spans that end before they start, or past the end of the
+            // source, are underlined where they start
+            _ => {
+                let to = match end_line == self.span.line {
+                    true => (end_column - 1).min(first.len()),
+                    false => from,
+                };
+                vec![Underline { line: self.span.line, text: first, from, to }]
+            }
+        }
+    }
+}
+
+// A note as an `= note:` line under the snippet
+fn render_note(note: &Note, pad: &str, options: RenderOptions) -> String {
+    let message = match note.span.is_known() {
+        true => format!("{}:{}: {}", note.span.line, note.span.column, note.message),
+        false => note.message.clone(),
+    };
+    let prefix = format!("{} = note: ", pad);
+    format!("{}{}\n", prefix, wrapped(&message, prefix.len(), options.width))
+}
+
+// `text` wrapped at spaces to fit in `width` columns after `indent`, with
+// the lines after the first indented to line up under it. A word too long
+// for a line gets one to itself.
+fn wrapped(text: &str, indent: usize, width: usize) -> String {
+    let room = width.saturating_sub(indent);
+    if text.chars().count() <= room {
+        return text.to_string();
+    }
+    let mut lines: Vec<String> = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        if !line.is_empty() && line.chars().count() + 1 + word.chars().count() > room {
+            lines.push(std::mem::take(&mut line));
+        }
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(word);
+    }
+    lines.push(line);
+    lines.join(&format!("\n{}", " ".repeat(indent)))
+}
+
+// The column `byte` in `line` starts at on screen, with tabs going to the
+// next tab stop. Bytes past the end of the line are one column each.
+fn display_column(line: &str, byte: usize) -> usize {
+    let mut column = 0;
+    for (at, c) in line.char_indices() {
+        if at >= byte {
+            return column;
+        }
+        column += if c == '\t' { TAB_WIDTH - column % TAB_WIDTH } else { 1 };
+    }
+    column + byte.saturating_sub(line.len())
+}
+
+// `line` with its tabs turned into spaces, out to the columns
+// `display_column` puts them at
+fn expand_tabs(line: &str) -> String {
+    let mut expanded = String::with_capacity(line.len());
+    let mut column = 0;
+    for c in line.chars() {
+        if c == '\t' {
+            let spaces = TAB_WIDTH - column % TAB_WIDTH;
+            expanded.push_str(&" ".repeat(spaces));
+            column += spaces;
+        } else {
+            expanded.push(c);
+            column += 1;
+        }
+    }
+    expanded
+}
+
+/// Picks the diagnostics to show out of `diagnostics`, so that one mistake
+/// doesn't bury itself under the errors it causes:
+///
+/// - Exact repeats of a diagnostic are dropped.
+/// - So are errors inside the source the parser skipped to recover from
+///   another error, which are most likely follow-on errors.
+/// - Errors come before warnings.
+/// - At most `limit` are shown; the rest are only counted.
+pub fn summarize(diagnostics: &[Diagnostic], limit: usize) -> DiagnosticSummary {
+    let follows_another = |diagnostic: &Diagnostic| {
+        diagnostic.is_error()
+            && diagnostics.iter().any(|other| {
+                other != diagnostic
+                    && other.is_error()
+                    && other.recovery.is_some_and(|skipped| {
+                        skipped.start <= diagnostic.span.start && diagnostic.span.end <= skipped.end
+                    })
+            })
+    };
+    let mut kept: Vec<&Diagnostic> = Vec::new();
+    for diagnostic in diagnostics {
+        let repeated = kept.iter().any(|earlier| {
+            (&earlier.message, earlier.span, earlier.severity) == (&diagnostic.message, diagnostic.span, diagnostic.severity)
+        });
+        if !repeated && !follows_another(diagnostic) {
+            kept.push(diagnostic);
+        }
+    }
+    // A stable sort keeps the order within each severity
+    kept.sort_by_key(|diagnostic| !diagnostic.is_error());
+
+    let omitted = kept.split_off(kept.len().min(limit));
+    let omitted_errors = omitted.iter().filter(|diagnostic| diagnostic.is_error()).count();
+    DiagnosticSummary {
+        shown: kept.into_iter().cloned().collect(),
+        omitted_errors,
+        omitted_warnings: omitted.len() - omitted_errors,
+    }
+}
+
+/// A token and where it appears in the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpannedToken {
+    pub token: Token,
+    pub span: Span,
+}
+
+/// Everything [`parse_with_diagnostics`] could make of a source file.
+#[derive(Debug, Clone)]
+pub struct ParseResult {
+    /// The top-level statements that parsed cleanly, in source order.
+    pub statements: Vec<Statement>,
+    /// Lexing and syntax errors, in the order they were found.
+    pub diagnostics: Vec<Diagnostic>,
+    /// Every valid token, for syntax highlighting.
+    pub tokens: Vec<SpannedToken>,
+}
+
+impl ParseResult {
+    pub fn has_errors(&self) -> bool {
+        !self.diagnostics.is_empty()
+    }
+}
+
+/// Lexes and parses `source` without stopping at the first error. Characters
+/// that aren't part of any token are skipped, and a top-level statement with
+/// a syntax error is left out while the ones around it are still returned.
+///
+/// ```
+/// let result = voltage_parser::parse_with_diagnostics("let x = ;\nlet y = 2;");
+/// assert_eq!(result.statements.len(), 1);
+/// assert_eq!(result.diagnostics[0].span.line, 1);
+/// ```
+pub fn parse_with_diagnostics(source: &str) -> ParseResult {
+    let lexer = Lexer::new(source);
+    let mut diagnostics: Vec<Diagnostic> = lexer.errors().iter().cloned().map(Diagnostic::from).collect();
+    let tokens = lexer
+        .tokenize()
+        .iter()
+        .zip(lexer.spans())
+        .map(|(token, span)| SpannedToken { token: token.clone(), span: *span })
+        .collect();
+
+    // Where a piece of source didn't lex, the parser finds a token missing
+    // right after it, or at the end of the file after the last token; that's
+    // the same mistake again
+    let lex_errors: Vec<Span> = lexer.errors().iter().map(|error| error.span).collect();
+    let last_token_end = lexer.spans().last().map_or(0, |span| span.end);
+    let follows_lex_error = |diagnostic: &Diagnostic| {
+        let span = diagnostic.span;
+        lex_errors.iter().any(|error| {
+            let blank = |range: std::ops::Range<usize>| source.get(range).is_some_and(|text| text.trim().is_empty());
+            (span.start < error.end && error.start < span.end)
+                || blank(error.end..span.start)
+                || (span.end == last_token_end && error.start >= last_token_end && blank(error.end..source.len()))
+        })
+    };
+
+    let (statements, parse_diagnostics) = Parser::from_lexer(lexer).with_source(source).parse_recovering();
+    // Lex errors in interpolations already say what they are
+    diagnostics.extend(parse_diagnostics.into_iter().filter(|diagnostic| !follows_lex_error(diagnostic)).map(|diagnostic| {
+        match diagnostic.code {
+            Some(_) => diagnostic,
+            None => diagnostic.with_code("syntax_error"),
+        }
+    }));
+
+    ParseResult { statements, diagnostics, tokens }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use voltage_core::StatementKind;
+
+    fn function_names(result: &ParseResult) -> Vec<&str> {
+        result
+            .statements
+            .iter()
+            .filter_map(|stmt| match &stmt.kind {
+                StatementKind::Function(func) => Some(func.name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_clean_source_has_no_diagnostics() {
+        let result = parse_with_diagnostics("fn main() {\n    puts(1);\n}");
+        assert!(!result.has_errors());
+        assert_eq!(function_names(&result), vec!["main"]);
+        assert_eq!(result.tokens[0], SpannedToken { token: Token::Fn, span: Span { start: 0, end: 2, line: 1, column: 1 } });
+    }
+
+    #[test]
+    fn test_error_in_middle_function_keeps_the_others() {
+        let source = "fn first() {\n    return 1;\n}\n\
+                      fn second() {\n    let x = ;\n}\n\
+                      fn third() {\n    return 3;\n}";
+        let result = parse_with_diagnostics(source);
+
+        assert_eq!(function_names(&result), vec!["first", "third"]);
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].span.line, 5);
+        // The tokens of the broken function are still there for highlighting
+        assert!(result.tokens.iter().any(|t| t.token == Token::Identifier("second".to_string())));
+    }
+
+    #[test]
+    fn test_unclosed_function_at_end_of_input() {
+        let result = parse_with_diagnostics("let a = 1;\nfn open() {\n    puts(a);");
+        assert_eq!(result.statements.len(), 1);
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!(result.diagnostics[0].span.line, 3);
+    }
+
+    #[test]
+    fn test_bad_list_item_is_reported_once() {
+        let source = "fn main() {\n    puts(1, let, 3);\n    let x = [1 2];\n    return x;\n}";
+        let result = parse_with_diagnostics(source);
+        // Both lists are skipped to their end, and the rest of `main` parses
+        assert_eq!(function_names(&result), vec!["main"]);
+        let messages: Vec<String> = result.diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(messages, vec!["2:13: Expected expression, got Let", "3:16: Expected `,` or `]`, got Number(2)"]);
+    }
+
+    #[test]
+    fn test_errors_leave_the_parser_as_it_was_for_the_next_statement() {
+        // A struct literal after an error in a loop header, and `pub` after
+        // one inside a block
+        let source = "while ready + { }\nlet p = Point { x: 1 };\nloop { let = 1; }\npub fn after() { }";
+        let result = parse_with_diagnostics(source);
+        let lines: Vec<usize> = result.diagnostics.iter().map(|diagnostic| diagnostic.span.line).collect();
+        assert_eq!(lines, vec![1, 3]);
+        assert_eq!(result.statements.len(), 2);
+        assert_eq!(function_names(&result), vec!["after"]);
+    }
+
+    #[test]
+    fn test_chained_comparison_spans_the_whole_chain() {
+        let result = parse_with_diagnostics("let x = 5;\nif 0 < x <= 10 {\n    puts(x);\n}");
+        assert_eq!(result.statements.len(), 2);
+        assert_eq!(result.diagnostics.len(), 1);
+        let diagnostic = &result.diagnostics[0];
+        assert_eq!(diagnostic.to_string(), "2:4: comparison operators cannot be chained; use `0 < x && x <= 10`");
+        assert_eq!((diagnostic.span.start, diagnostic.span.end), (14, 25));
+    }
+
+    #[test]
+    fn test_lexing_errors_and_stray_braces_are_reported() {
+        let result = parse_with_diagnostics("let a = 1;\n}\nlet b = #2;");
+        assert_eq!(result.statements.len(), 2);
+        let messages: Vec<String> = result.diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(messages, vec!["3:9: Unexpected character \"#\"", "2:1: Unexpected '}'"]);
+    }
+
+    #[test]
+    fn test_diagnostics_say_what_kind_they_are() {
+        let result = parse_with_diagnostics("let a = #1;\nlet b = ;");
+        let codes: Vec<Option<&str>> = result.diagnostics.iter().map(|diagnostic| diagnostic.code).collect();
+        assert_eq!(codes, vec![Some("unexpected_character"), Some("syntax_error")]);
+        assert_eq!(result.diagnostics[1].span.end_position("let a = #1;\nlet b = ;"), (2, 10));
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_diagnostics_serialize_for_tools() {
+        let diagnostic = Diagnostic::new("Expected expression, got Semi", at(2, 8, 9))
+            .with_code("syntax_error")
+            .with_note("the statement starts here", at(2, 0, 3))
+            .recovered(at(2, 8, 9));
+        let json = serde_json::to_value(&diagnostic).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "severity": "error",
+                "code": "syntax_error",
+                "message": "Expected expression, got Semi",
+                "span": { "start": 8, "end": 9, "line": 2, "column": 9 },
+                "notes": [{ "message": "the statement starts here", "span": { "start": 0, "end": 3, "line": 2, "column": 1 } }],
+            })
+        );
+    }
+
+    fn at(line: usize, start: usize, end: usize) -> Span {
+        Span { start, end, line, column: start + 1 }
+    }
+
+    #[test]
+    fn test_summary_drops_repeats_and_puts_errors_first() {
+        let diagnostics = vec![
+            Diagnostic::warning("unused variable `a`", at(1, 4, 5)),
+            Diagnostic::new("Expected expression, got Semi", at(2, 8, 9)),
+            Diagnostic::new("Expected expression, got Semi", at(2, 8, 9)),
+            Diagnostic::warning("unused variable `a`", at(1, 4, 5)),
+            // The same message somewhere else isn't a repeat
+            Diagnostic::new("Expected expression, got Semi", at(3, 8, 9)),
+        ];
+        let summary = summarize(&diagnostics, DEFAULT_DIAGNOSTIC_LIMIT);
+        assert_eq!(
+            summary.to_string(),
+            "error: 2:9: Expected expression, got Semi\n\
+             error: 3:9: Expected expression, got Semi\n\
+             warning: 1:5: unused variable `a`\n"
+        );
+        assert_eq!(summary.omitted(), None);
+    }
+
+    #[test]
+    fn test_summary_is_capped() {
+        let errors = (1..=25).map(|line| Diagnostic::new("Unexpected '}'", at(line, 0, 1)));
+        let warnings = (1..=3).map(|line| Diagnostic::warning("unused variable `a`", at(line, 4, 5)));
+        let diagnostics: Vec<Diagnostic> = warnings.chain(errors).collect();
+
+        let summary = summarize(&diagnostics, DEFAULT_DIAGNOSTIC_LIMIT);
+        assert_eq!(summary.shown.len(), 20);
+        assert!(summary.shown.iter().all(Diagnostic::is_error));
+        assert_eq!(summary.omitted().as_deref(), Some("... and 5 more errors and 3 more warnings"));
+        assert!(summary.to_string().ends_with("error: 20:1: Unexpected '}'\n... and 5 more errors and 3 more warnings\n"));
+
+        assert_eq!(summarize(&diagnostics[2..5], 1).omitted().as_deref(), Some("... and 1 more error and 1 more warning"));
+        assert_eq!(summarize(&diagnostics[..3], 1).omitted().as_deref(), Some("... and 2 more warnings"));
+    }
+
+    #[test]
+    fn test_errors_in_skipped_source_are_left_out() {
+        // Text pasted into a list: the list is skipped from the first bad
+        // item, and the characters that don't lex are in what's skipped
+        let source = "let xs = [1, + @, 2 $ 3];\nlet ys = (4 + ;\nputs(xs, ys);";
+        let result = parse_with_diagnostics(source);
+        let messages: Vec<String> = result.diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            messages,
+            vec![
+                "1:16: Unexpected character \"@\"",
+                "1:21: Unexpected character \"$\"",
+                "1:14: Expected expression, got Plus",
+                "2:15: Expected expression, got Semi",
+            ]
+        );
+        let summary = summarize(&result.diagnostics, DEFAULT_DIAGNOSTIC_LIMIT);
+        assert_eq!(
+            summary.to_string(),
+            "error: 1:14: Expected expression, got Plus\nerror: 2:15: Expected expression, got Semi\n"
+        );
+    }
+
+    #[test]
+    fn test_a_character_that_does_not_lex_is_one_error() {
+        for source in ["let x = @;\nputs(x);", "let x = 1 @ 2;", "puts(#\n  1);", "let x = @  \n"] {
+            let result = parse_with_diagnostics(source);
+            let codes: Vec<_> = result.diagnostics.iter().map(|diagnostic| diagnostic.code).collect();
+            assert_eq!(codes, vec![Some("unexpected_character")], "{:?}", source);
+        }
+        // A parse error further along is still its own
+        let result = parse_with_diagnostics("let x = @;\nlet y = ;");
+        let messages: Vec<String> = result.diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(messages, vec!["1:9: Unexpected character \"@\"", "2:9: Expected expression, got Semi"]);
+    }
+
+    #[test]
+    fn test_errors_inside_interpolations_point_into_the_string() {
+        let result = parse_with_diagnostics("let a = 1;\nputs(\"sum: ${a + * 2}\");");
+        assert_eq!(result.statements.len(), 2);
+        let messages: Vec<String> = result.diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(messages, vec!["2:18: Expected expression, got Star"]);
+        assert_eq!(result.diagnostics[0].span.start, 28);
+
+        // Further down a string that spans lines, and from the lexer
+        let result = parse_with_diagnostics("let s = \"first\n  ${a @}\";");
+        let messages: Vec<String> = result.diagnostics.iter().map(ToString::to_string).collect();
+        assert_eq!(messages, vec!["2:7: Unexpected character \"@\""]);
+    }
+
+    #[test]
+    fn test_render_underlines_the_span() {
+        let source = "let a = 1;\nlet b = ;";
+        let diagnostic = &parse_with_diagnostics(source).diagnostics[0];
+        assert_eq!(
+            diagnostic.render("broken.v", source, RenderOptions::default()),
+            "error[syntax_error]: Expected expression, got Semi\n \
+             --> broken.v:2:9\n  \
+             |\n\
+             2 | let b = ;\n  \
+             |         ^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_spans_over_several_lines() {
+        let source = "fn main() {\n    let total = add(1,\n        2,\n        3);\n}";
+        let start = source.find("add").unwrap();
+        let end = source.find(");").unwrap() + 1;
+        let span = Span { start, end, line: 2, column: 17 };
+        let diagnostic = Diagnostic::new("`add` is not defined", span).with_code("undefined_name");
+        assert_eq!(
+            diagnostic.render("calls.v", source, RenderOptions::default()),
+            "error[undefined_name]: `add` is not defined\n \
+             --> calls.v:2:17\n  \
+             |\n\
+             2 |     let total = add(1,\n  \
+             |                 ^^^^^^\n\
+             ...\n\
+             4 |         3);\n  \
+             |         ^^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_expands_tabs_under_the_underline() {
+        let source = "fn main() {\n\tlet x = ;\n}";
+        let diagnostic = &parse_with_diagnostics(source).diagnostics[0];
+        assert_eq!(
+            diagnostic.render("tabs.v", source, RenderOptions::default()),
+            "error[syntax_error]: Expected expression, got Semi\n \
+             --> tabs.v:2:10\n  \
+             |\n\
+             2 |     let x = ;\n  \
+             |             ^\n"
+        );
+    }
+
+    #[test]
+    fn test_render_wraps_messages_and_colors_labels() {
+        let source = "let a = 1;";
+        let diagnostic = Diagnostic::warning("unused variable `a`, which nothing reads", at(1, 4, 5))
+            .with_code("unused_variable")
+            .with_note("declared here", at(1, 4, 5));
+        let rendered = diagnostic.render("unused.v", source, RenderOptions { color: false, width: 40 });
+        let message = "warning[unused_variable]: unused\n\
+        \x20                         variable `a`,\n\
+        \x20                         which nothing\n\
+        \x20                         reads\n";
+        assert!(rendered.starts_with(message), "{}", rendered);
+        assert!(rendered.ends_with("  |     ^\n  = note: 1:5: declared here\n"), "{}", rendered);
+
+        let colored = diagnostic.render("unused.v", source, RenderOptions { color: true, width: 40 });
+        assert!(colored.starts_with("\x1b[1;33mwarning[unused_variable]\x1b[0m: "));
+        assert!(colored.contains("\x1b[1;33m^\x1b[0m"));
+    }
+}
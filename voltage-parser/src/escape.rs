@@ -0,0 +1,173 @@
+//! Backslash escapes in string literals: `\n`, `\t`, `\r`, `\0`, `\\`, `\"`,
+//! `\$` and `\u{...}`, which names a Unicode scalar value in one to six hex
+//! digits. [`unescape`] reads them and [`escape`] writes them, so a string
+//! shown as a literal reads back as the same string.
+
+use std::fmt;
+use std::ops::Range;
+
+/// Why an escape in a string literal is invalid.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscapeErrorKind {
+    /// A backslash before a character that has no escape, like `\q`, or at
+    /// the very end.
+    Unknown,
+    /// A `\u` not followed by `{`, one to six hex digits and `}`.
+    MalformedUnicode,
+    /// A `\u{...}` naming a UTF-16 surrogate, `D800` to `DFFF`, which
+    /// isn't a character.
+    Surrogate,
+    /// A `\u{...}` past `10FFFF`, the last Unicode code point.
+    OutOfRange,
+}
+
+/// An invalid escape and where it is in the text given to [`unescape`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EscapeError {
+    pub kind: EscapeErrorKind,
+    /// The bytes of the escape, from its backslash.
+    pub range: Range<usize>,
+    /// The escape as written.
+    pub escape: String,
+}
+
+impl fmt::Display for EscapeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            EscapeErrorKind::Unknown => write!(f, "unknown escape `{}`", self.escape),
+            EscapeErrorKind::MalformedUnicode => {
+                write!(f, "invalid escape `{}`: write it as `\\u{{...}}` with one to six hex digits", self.escape)
+            }
+            EscapeErrorKind::Surrogate => write!(f, "invalid escape `{}`: surrogates aren't characters", self.escape),
+            EscapeErrorKind::OutOfRange => {
+                write!(f, "invalid escape `{}`: the last Unicode code point is `\\u{{10FFFF}}`", self.escape)
+            }
+        }
+    }
+}
+
+impl std::error::Error for EscapeError {}
+
+/// `text`, the contents of a string literal, with its escapes replaced by
+/// the characters they stand for. Fails at the first invalid escape.
+///
+/// ```
+/// use voltage_parser::escape::unescape;
+///
+/// assert_eq!(unescape(r"tab\there \u{1F600}"), Ok("tab\there 😀".to_string()));
+/// assert_eq!(unescape(r"x\u{D800}").unwrap_err().range, 1..9);
+/// ```
+pub fn unescape(text: &str) -> Result<String, EscapeError> {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.char_indices().peekable();
+    while let Some((start, c)) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        let error = |kind, end: usize| EscapeError { kind, range: start..end, escape: text[start..end].to_string() };
+        let Some((at, escaped)) = chars.next() else {
+            return Err(error(EscapeErrorKind::Unknown, text.len()));
+        };
+        let end = at + escaped.len_utf8();
+        match escaped {
+            'n' => out.push('\n'),
+            't' => out.push('\t'),
+            'r' => out.push('\r'),
+            '0' => out.push('\0'),
+            '\\' | '"' | '$' => out.push(escaped),
+            'u' => {
+                let braced = text[end..].strip_prefix('{').and_then(|inner| Some(&inner[..inner.find('}')?]));
+                let Some(digits) = braced else {
+                    return Err(error(EscapeErrorKind::MalformedUnicode, end));
+                };
+                let end = end + digits.len() + 2;
+                if !(1..=6).contains(&digits.len()) || !digits.bytes().all(|b| b.is_ascii_hexdigit()) {
+                    return Err(error(EscapeErrorKind::MalformedUnicode, end));
+                }
+                let value = u32::from_str_radix(digits, 16).expect("one to six hex digits");
+                match char::from_u32(value) {
+                    Some(c) => out.push(c),
+                    None if (0xD800..=0xDFFF).contains(&value) => return Err(error(EscapeErrorKind::Surrogate, end)),
+                    None => return Err(error(EscapeErrorKind::OutOfRange, end)),
+                }
+                while chars.peek().is_some_and(|&(i, _)| i < end) {
+                    chars.next();
+                }
+            }
+            _ => return Err(error(EscapeErrorKind::Unknown, end)),
+        }
+    }
+    Ok(out)
+}
+
+/// `text` written as the contents of a string literal that [`unescape`]
+/// reads back as `text`. Printable characters are written as they are, and
+/// others as escapes: `\n`, `\t`, `\r` and `\0` where they have one and
+/// `\u{...}` for the other control characters and whitespace besides the
+/// space. Quotes, backslashes and the `$` of a `${` are escaped.
+///
+/// ```
+/// use voltage_parser::escape::escape;
+///
+/// assert_eq!(escape("say \"hi\"\n😀\u{7}"), r#"say \"hi\"\n😀\u{7}"#);
+/// assert_eq!(escape("${x} costs $5"), r"\${x} costs $5");
+/// ```
+pub fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            '\\' | '"' => {
+                out.push('\\');
+                out.push(c);
+            }
+            '$' if chars.peek() == Some(&'{') => out.push_str("\\$"),
+            c if c.is_control() || (c.is_whitespace() && c != ' ') => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+/// `text` as a string literal: [`escape`]d, in double quotes.
+pub fn quoted(text: &str) -> String {
+    format!("\"{}\"", escape(text))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unicode_escapes_are_checked() {
+        assert_eq!(unescape(r"\u{41}\u{00e9}\u{10FFFF}"), Ok("Aé\u{10FFFF}".to_string()));
+        let kind = |text| unescape(text).unwrap_err().kind;
+        assert_eq!(kind(r"\u{DFFF}"), EscapeErrorKind::Surrogate);
+        assert_eq!(kind(r"\u{110000}"), EscapeErrorKind::OutOfRange);
+        assert_eq!(kind(r"\u{}"), EscapeErrorKind::MalformedUnicode);
+        assert_eq!(kind(r"\u{1234567}"), EscapeErrorKind::MalformedUnicode);
+        assert_eq!(kind(r"\u{+41}"), EscapeErrorKind::MalformedUnicode);
+        assert_eq!(kind(r"\u41"), EscapeErrorKind::MalformedUnicode);
+        assert_eq!(kind(r"\q"), EscapeErrorKind::Unknown);
+        assert_eq!(kind("\\"), EscapeErrorKind::Unknown);
+
+        let error = unescape(r"ab \u{zz} cd").unwrap_err();
+        assert_eq!((error.range.clone(), error.escape.as_str()), (3..9, r"\u{zz}"));
+        assert_eq!(error.to_string(), r"invalid escape `\u{zz}`: write it as `\u{...}` with one to six hex digits");
+    }
+
+    #[test]
+    fn test_escaped_text_reads_back_the_same() {
+        for text in ["plain", "😀 and é", "a\tb\r\n\0c", r#"quote " and \ slash"#, "${x} and $y", "bell\u{7}\u{2028}"] {
+            assert_eq!(unescape(&escape(text)).as_deref(), Ok(text), "{:?}", text);
+        }
+        // An escaped printable character comes back written as itself
+        assert_eq!(escape(&unescape(r"\u{1F600}\u{e9}").unwrap()), "😀é");
+        assert_eq!(escape(&unescape(r"\u{7}").unwrap()), r"\u{7}");
+    }
+}
@@ -0,0 +1,472 @@
+//! The source formatter behind `voltagec fmt`. It works on the tokens of a
+//! file, comments included, rather than its syntax tree, so it only ever
+//! changes the whitespace between tokens: comments stay where they were
+//! written, either on a line of their own above what follows them or
+//! trailing what they were on the same line as.
+//!
+//! Statements go one to a line, indented four spaces a block, with at most
+//! one blank line kept wherever the source had any. Struct definitions put a
+//! field on each line; struct initializers stay on one.
+
+use crate::diagnostics::{parse_with_diagnostics, Diagnostic};
+use crate::lexer::{Lexer, Token};
+use voltage_core::Span;
+
+const INDENT: &str = "    ";
+
+/// `source` formatted, or the errors that keep it from being formatted,
+/// since code that doesn't parse can't be laid out by its structure.
+///
+/// ```
+/// let formatted = voltage_parser::format::format_source("fn main(){let x=-1;//x\nputs(x) ;}");
+/// assert_eq!(formatted.unwrap(), "fn main() {\n    let x = -1; // x\n    puts(x);\n}\n");
+/// ```
+pub fn format_source(source: &str) -> Result<String, Vec<Diagnostic>> {
+    let parsed = parse_with_diagnostics(source);
+    if parsed.diagnostics.iter().any(Diagnostic::is_error) {
+        return Err(parsed.diagnostics);
+    }
+    let (tokens, spans) = Lexer::with_comments(source).into_tokens();
+    Ok(Printer::new(source, &tokens, &spans).print())
+}
+
+/// How the contents of a pair of braces are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    /// Statements, one to a line.
+    Block,
+    /// Comma-separated items, one to a line: the fields of a struct
+    /// definition, or anything with a comment between its items.
+    Fields,
+    /// On the same line as the braces, like a struct initializer.
+    Inline,
+}
+
+/// What the tokens being printed are inside of.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Frame {
+    Braces(Layout),
+    Parens,
+}
+
+/// The layout of each pair of braces in `tokens`, by the index of its `{`.
+/// Braces holding a statement are a block; anything else is inline unless
+/// it's a struct definition or holds a comment.
+fn layouts(tokens: &[Token]) -> Vec<Option<Layout>> {
+    struct Open {
+        at: usize,
+        braces: bool,
+        statements: bool,
+        comments: bool,
+        empty: bool,
+    }
+
+    let mut layouts = vec![None; tokens.len()];
+    let mut open: Vec<Open> = Vec::new();
+    for (i, token) in tokens.iter().enumerate() {
+        let closes = matches!(token, Token::RightBrace | Token::RightParen | Token::RightBracket);
+        if let Some(inner) = open.last_mut().filter(|_| !closes) {
+            inner.empty = false;
+            if inner.braces {
+                inner.statements |= starts_statement(token) || *token == Token::Semi;
+                inner.comments |= token.is_comment();
+            }
+        }
+        match token {
+            Token::LeftBrace | Token::LeftParen | Token::LeftBracket => {
+                open.push(Open { at: i, braces: *token == Token::LeftBrace, statements: false, comments: false, empty: true });
+            }
+            Token::RightBrace | Token::RightParen | Token::RightBracket => {
+                let Some(group) = open.pop() else { continue };
+                if !group.braces {
+                    continue;
+                }
+                let struct_definition = matches!(previous_before(tokens, group.at), [Some(Token::Struct), Some(Token::Identifier(_))]);
+                let layout = if group.statements {
+                    Layout::Block
+                } else if group.comments || (struct_definition && !group.empty) {
+                    Layout::Fields
+                } else {
+                    Layout::Inline
+                };
+                layouts[group.at] = Some(layout);
+                // A block inside braces makes them a block too
+                if let Some(outer) = open.last_mut().filter(|outer| outer.braces) {
+                    outer.statements |= layout == Layout::Block;
+                }
+            }
+            _ => {}
+        }
+    }
+    layouts
+}
+
+// The two tokens before the one at `at`, leaving out comments
+fn previous_before(tokens: &[Token], at: usize) -> [Option<&Token>; 2] {
+    let mut before = tokens[..at].iter().rev().filter(|token| !token.is_comment());
+    let last = before.next();
+    [before.next(), last]
+}
+
+// Whether `token` can only be the first token of a statement
+fn starts_statement(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Let
+            | Token::If
+            | Token::While
+            | Token::For
+            | Token::Loop
+            | Token::Return
+            | Token::Break
+            | Token::Continue
+            | Token::Fn
+            | Token::Try
+            | Token::Import
+            | Token::Struct
+            | Token::Impl
+            | Token::Pub
+            | Token::Unsafe
+    )
+}
+
+// Whether `token` can come right after the `}` of an expression or an `if`,
+// carrying on the same statement
+fn continues(token: &Token) -> bool {
+    !matches!(
+        token,
+        Token::Identifier(_)
+            | Token::Number(_)
+            | Token::Float(_)
+            | Token::String(_)
+            | Token::True
+            | Token::False
+            | Token::LeftParen
+            | Token::LeftBracket
+            | Token::LeftBrace
+            | Token::Minus
+            | Token::Reserved(_)
+    )
+}
+
+// Whether `token` can end an operand, which makes a `-` after it a
+// subtraction rather than a negation
+fn ends_operand(token: &Token) -> bool {
+    matches!(
+        token,
+        Token::Identifier(_)
+            | Token::Number(_)
+            | Token::Float(_)
+            | Token::String(_)
+            | Token::True
+            | Token::False
+            | Token::RightParen
+            | Token::RightBracket
+            | Token::RightBrace
+    )
+}
+
+// Whether there's a space between `previous` and `next` on the same line.
+// `negation` is whether `previous` is a `-` negating what follows it.
+fn spaced(previous: &Token, next: &Token, negation: bool) -> bool {
+    use Token::*;
+    match (previous, next) {
+        (LeftBrace, RightBrace) => false,
+        (_, Comma | Semi | RightParen | RightBracket | Dot | Colon | DoubleColon | DotDot | DotDotEquals) => false,
+        (LeftParen | LeftBracket | Dot | DoubleColon | DotDot | DotDotEquals, _) => false,
+        (Minus, _) if negation => false,
+        // A call or an index, rather than a parenthesized expression or an
+        // array after an operator
+        (Identifier(_) | RightParen | RightBracket | String(_), LeftParen | LeftBracket) => false,
+        _ => true,
+    }
+}
+
+// A line comment as it's printed: with a space after the slashes unless it
+// has one already, or starts like a `///` or `//!`
+fn line_comment(text: &str) -> String {
+    let text = text.trim_end();
+    match text.chars().next() {
+        None | Some(' ' | '\t' | '/' | '!') => format!("//{}", text),
+        Some(_) => format!("// {}", text),
+    }
+}
+
+struct Printer<'a> {
+    source: &'a str,
+    tokens: &'a [Token],
+    spans: &'a [Span],
+    layouts: Vec<Option<Layout>>,
+    out: String,
+    frames: Vec<Frame>,
+    // Whether the next token goes on a new line
+    newline: bool,
+    // The last token printed, comments included, and the last that wasn't
+    // a comment
+    last: Option<usize>,
+    last_code: Option<usize>,
+    // Whether the last token that wasn't a comment was a negating `-`
+    negation: bool,
+}
+
+impl<'a> Printer<'a> {
+    fn new(source: &'a str, tokens: &'a [Token], spans: &'a [Span]) -> Self {
+        Printer {
+            source,
+            tokens,
+            spans,
+            layouts: layouts(tokens),
+            out: String::new(),
+            frames: Vec::new(),
+            newline: false,
+            last: None,
+            last_code: None,
+            negation: false,
+        }
+    }
+
+    fn print(mut self) -> String {
+        let tokens = self.tokens;
+        for (i, token) in tokens.iter().enumerate() {
+            match token {
+                Token::LineComment(text) => {
+                    let comment = line_comment(text);
+                    self.comment(i, &comment);
+                    self.newline = true;
+                }
+                Token::BlockComment(_) => {
+                    let comment = self.text(i).to_string();
+                    self.comment(i, &comment);
+                    // One that ends its line keeps ending it
+                    self.newline |= self.tokens.get(i + 1).is_some_and(|_| self.lines_between(i, i + 1) > 0);
+                }
+                _ => self.code(i),
+            }
+        }
+        if !self.out.is_empty() {
+            self.out.push('\n');
+        }
+        self.out
+    }
+
+    fn code(&mut self, i: usize) {
+        let token = &self.tokens[i];
+        match token {
+            Token::LeftParen | Token::LeftBracket => {
+                self.write(i);
+                self.frames.push(Frame::Parens);
+            }
+            Token::RightParen | Token::RightBracket => {
+                self.frames.pop();
+                self.write(i);
+            }
+            Token::LeftBrace => {
+                let layout = self.layouts[i].unwrap_or(Layout::Inline);
+                self.write(i);
+                self.frames.push(Frame::Braces(layout));
+                self.newline = layout != Layout::Inline;
+            }
+            Token::RightBrace => {
+                if let Some(Frame::Braces(layout)) = self.frames.pop() {
+                    self.newline |= layout != Layout::Inline;
+                }
+                self.write(i);
+                // Whatever follows on the same line continues what the
+                // braces were part of, like an `else` or the `;` of a `let`
+                if self.in_statements() {
+                    self.newline = self.next_code(i).is_some_and(|next| starts_statement(next) || !continues(next));
+                }
+            }
+            Token::Semi => {
+                self.write(i);
+                self.newline = self.in_statements();
+            }
+            Token::Comma => {
+                self.write(i);
+                self.newline = self.frames.last() == Some(&Frame::Braces(Layout::Fields));
+            }
+            _ => self.write(i),
+        }
+        self.negation = *token == Token::Minus && !self.last_code.is_some_and(|last| ends_operand(&self.tokens[last]));
+        self.last_code = Some(i);
+    }
+
+    // A comment, trailing the token before it if it was on the same line
+    fn comment(&mut self, i: usize, text: &str) {
+        let trailing = self.last.is_some_and(|last| self.lines_between(last, i) == 0);
+        if trailing {
+            let tight = self.last_code == self.last
+                && self.last_code.is_some_and(|last| matches!(self.tokens[last], Token::LeftParen | Token::LeftBracket));
+            if !tight {
+                self.out.push(' ');
+            }
+        } else {
+            self.newline = self.last.is_some();
+            self.start_line(i);
+        }
+        self.out.push_str(text);
+        self.last = Some(i);
+    }
+
+    // Prints the token at `i`, on a new line or after the token before it
+    fn write(&mut self, i: usize) {
+        if self.newline {
+            self.start_line(i);
+        } else if let Some(last) = self.last {
+            let negation = self.negation && self.last_code == Some(last);
+            if spaced(&self.tokens[last], &self.tokens[i], negation) {
+                self.out.push(' ');
+            }
+        }
+        self.out.push_str(self.text(i));
+        self.last = Some(i);
+    }
+
+    // Ends the line before the token at `i`, if the line has to end, with a
+    // blank line if there was one in the source
+    fn start_line(&mut self, i: usize) {
+        if !std::mem::take(&mut self.newline) {
+            return;
+        }
+        let after_open = self.last.is_some_and(|last| self.tokens[last] == Token::LeftBrace);
+        let blank = self.last.is_some_and(|last| self.lines_between(last, i) > 1);
+        self.out.push('\n');
+        if blank && !after_open && self.tokens[i] != Token::RightBrace {
+            self.out.push('\n');
+        }
+        let indent = self.frames.iter().filter(|frame| matches!(frame, Frame::Braces(Layout::Block | Layout::Fields))).count();
+        // A line broken inside brackets or an initializer is a continuation
+        let continuation = matches!(self.frames.last(), Some(Frame::Parens | Frame::Braces(Layout::Inline)));
+        self.out.push_str(&INDENT.repeat(indent + continuation as usize));
+    }
+
+    // Whether the tokens being printed are statements, in a block or at the
+    // top level of the file
+    fn in_statements(&self) -> bool {
+        matches!(self.frames.last(), None | Some(Frame::Braces(Layout::Block)))
+    }
+
+    fn next_code(&self, i: usize) -> Option<&Token> {
+        self.tokens[i + 1..].iter().find(|token| !token.is_comment())
+    }
+
+    fn text(&self, i: usize) -> &'a str {
+        let span = self.spans[i];
+        &self.source[span.start..span.end]
+    }
+
+    // How many line breaks the source has between the tokens at `from` and `to`
+    fn lines_between(&self, from: usize, to: usize) -> usize {
+        self.source[self.spans[from].end..self.spans[to].start].matches('\n').count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The tokens of `source`, comments included, without their spans or
+    // the spacing of line comments
+    fn tokens(source: &str) -> Vec<Token> {
+        let tokens = Lexer::with_comments(source).into_tokens().0;
+        tokens
+            .into_iter()
+            .map(|token| match token {
+                Token::LineComment(text) => Token::LineComment(text.trim().to_string()),
+                token => token,
+            })
+            .collect()
+    }
+
+    fn format(source: &str) -> String {
+        let formatted = format_source(source).unwrap();
+        assert_eq!(tokens(&formatted), tokens(source), "formatting changed more than whitespace:\n{}", formatted);
+        assert_eq!(format_source(&formatted).unwrap(), formatted, "formatting again changed:\n{}", formatted);
+        formatted
+    }
+
+    #[test]
+    fn test_statements_are_laid_out_by_block() {
+        let source = "struct Point{x:int,y:int=0}\nimpl Point{fn norm(self)->int{return self.x*self.x+self.y*self.y;}}\n\n\n\
+            fn main(){let p=Point{x:3,y:-4};if p.norm()>10{puts(p.x);}elif -p.y<0{puts(-(p.y));}else{puts([1,2][0]);}\
+            try{for i in 0..=2{puts(i);}}catch e{puts(e);}}";
+        let expected = "\
+struct Point {
+    x: int,
+    y: int = 0
+}
+impl Point {
+    fn norm(self) -> int {
+        return self.x * self.x + self.y * self.y;
+    }
+}
+
+fn main() {
+    let p = Point { x: 3, y: -4 };
+    if p.norm() > 10 {
+        puts(p.x);
+    } elif -p.y < 0 {
+        puts(-(p.y));
+    } else {
+        puts([1, 2][0]);
+    }
+    try {
+        for i in 0..=2 {
+            puts(i);
+        }
+    } catch e {
+        puts(e);
+    }
+}
+";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn test_comments_are_kept_where_they_were() {
+        let source = "/* leading\n   block */\n//line before\nfn main() { // after the brace\n\
+            let x = /* interior */ 1;   //trailing\n\n\n    // before the loop\nlet y = loop { break x; }; /* after */\n\
+            puts(x,\n// between arguments\ny);\n//   at the end\n}\n// after everything";
+        let expected = "\
+/* leading
+   block */
+// line before
+fn main() { // after the brace
+    let x = /* interior */ 1; // trailing
+
+    // before the loop
+    let y = loop {
+        break x;
+    }; /* after */
+    puts(x,
+        // between arguments
+        y);
+    //   at the end
+}
+// after everything
+";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn test_comments_in_initializers_put_a_field_on_each_line() {
+        let source = "let p = Point { x: 1, // across\n y: 2 };\nstruct Empty {}\nfn f() { // nothing yet\n}";
+        let expected = "\
+let p = Point {
+    x: 1, // across
+    y: 2
+};
+struct Empty {}
+fn f() { // nothing yet
+}
+";
+        assert_eq!(format(source), expected);
+    }
+
+    #[test]
+    fn test_code_that_does_not_parse_is_not_formatted() {
+        let errors = format_source("fn main() { let x = ; }").unwrap_err();
+        assert!(errors.iter().any(Diagnostic::is_error));
+        assert_eq!(format_source("").unwrap(), "");
+    }
+}
@@ -1,7 +1,7 @@
 #[cfg(test)]
 mod integration_tests {
     use crate::{Lexer, Parser};
-    use voltage_core::Statement;
+    use voltage_core::{Expression, Span, StatementKind};
 
     #[test]
     fn test_complete_program_parsing() {
@@ -14,7 +14,7 @@ mod integration_tests {
         }
         "#.to_string();
         
-        let lexer = Lexer::new(source);
+        let lexer = Lexer::new(&source);
         let tokens = lexer.tokenize();
         
         // Should have tokens
@@ -27,8 +27,8 @@ mod integration_tests {
         assert!(!ast.is_empty());
         
         // The first statement should be a function
-        match &ast[0] {
-            Statement::Function(func) => {
+        match &ast[0].kind {
+            StatementKind::Function(func) => {
                 assert_eq!(func.name, "main");
                 // Function should have body statements
                 assert!(!func.body.is_empty());
@@ -40,7 +40,7 @@ mod integration_tests {
     #[test]
     fn test_variable_declaration_parsing() {
         let source = r#"let x = 123;"#.to_string();
-        let lexer = Lexer::new(source);
+        let lexer = Lexer::new(&source);
         let tokens = lexer.tokenize().to_vec();
         let mut parser = Parser::new(tokens);
         let ast = parser.parse();
@@ -52,7 +52,7 @@ mod integration_tests {
     #[test]
     fn test_function_call_parsing() {
         let source = r#"puts("test");"#.to_string();
-        let lexer = Lexer::new(source);
+        let lexer = Lexer::new(&source);
         let tokens = lexer.tokenize().to_vec();
         let mut parser = Parser::new(tokens);
         let ast = parser.parse();
@@ -64,7 +64,7 @@ mod integration_tests {
     #[test]
     fn test_format_call_parsing() {
         let source = r#"puts("value is {}", x);"#.to_string();
-        let lexer = Lexer::new(source);
+        let lexer = Lexer::new(&source);
         let tokens = lexer.tokenize().to_vec();
         let mut parser = Parser::new(tokens);
         let ast = parser.parse();
@@ -72,4 +72,69 @@ mod integration_tests {
         // Should have exactly one statement
         assert_eq!(ast.len(), 1);
     }
-}
+
+    #[test]
+    fn test_format_builtin_captures_format_string() {
+        let source = r#"let s = format("{{}} costs {}", price);"#;
+        let ast = Parser::from_lexer(Lexer::new(source)).parse();
+
+        match &ast[0].kind {
+            StatementKind::VariableDeclaration { value: Expression::FormatCall { name, format_string, arguments }, .. } => {
+                assert_eq!(name, "format");
+                assert_eq!(format_string, "{{}} costs {}");
+                assert_eq!(arguments.len(), 1);
+            }
+            other => panic!("expected a format call, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_statements_carry_spans() {
+        let source = "fn main() {\n    let x = 1;\n    puts(x);\n}";
+        let lexer = Lexer::new(source);
+        let ast = Parser::from_lexer(lexer).parse();
+
+        assert_eq!(ast[0].span, Span { start: 0, end: 41, line: 1, column: 1 });
+        match &ast[0].kind {
+            StatementKind::Function(func) => {
+                assert_eq!(func.body[0].span, Span { start: 16, end: 26, line: 2, column: 5 });
+                assert_eq!(func.body[1].span, Span { start: 31, end: 39, line: 3, column: 5 });
+            }
+            _ => panic!("Expected a function statement"),
+        }
+    }
+
+    #[test]
+    fn test_truncated_programs_are_errors_not_crashes() {
+        let source = "fn add(a: int, b: int) -> int {\n    return a + b;\n}\n\
+                      let xs: [int] = [1, 2, 3,];\n\
+                      let p = Point { x: 1, y: 2 };\n\
+                      for x in xs {\n    if x > 1 { puts(\"{}\", x); } elif x == 1 { } else { }\n}\n\
+                      while false && p.x < 3 { break; }\n\
+                      let s = Shape::Circle(1.5);\n\
+                      puts(add(xs[0], xs[1..2][0]));\n";
+        assert!(!crate::parse_with_diagnostics(source).has_errors());
+
+        for end in 0..source.len() {
+            let result = crate::parse_with_diagnostics(&source[..end]);
+            for diagnostic in &result.diagnostics {
+                // Anything the parser didn't raise itself is a crash
+                assert!(
+                    !diagnostic.message.contains("out of bounds") && !diagnostic.message.contains("unwrap"),
+                    "{:?} crashed the parser: {}",
+                    &source[..end],
+                    diagnostic.message
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_end_of_file_errors_point_at_the_last_token() {
+        let result = crate::parse_with_diagnostics("fn main() {\n    let x:");
+        assert_eq!(result.diagnostics.len(), 1);
+        let diagnostic = &result.diagnostics[0];
+        assert!(diagnostic.message.contains("unexpected end of file, expected"), "{}", diagnostic.message);
+        assert_eq!((diagnostic.span.line, diagnostic.span.column), (2, 10));
+    }
+}
\ No newline at end of file
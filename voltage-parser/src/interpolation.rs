@@ -0,0 +1,137 @@
+//! `${...}` interpolation in string literals. `"x = ${x + 1}"` parses as
+//! `format("x = {}", x + 1)`, so it goes through the same format engine as
+//! `format` itself and works anywhere an expression does. `\${` is a literal
+//! `${`, like any other [escape](crate::escape).
+
+use voltage_core::Span;
+
+/// A piece of the contents of a string literal.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum StringPart {
+    /// Text as written, escapes and all, and the byte offset in the
+    /// contents where it starts.
+    Text { text: String, offset: usize },
+    /// The source between `${` and `}`, and the byte offset in the contents
+    /// where it starts.
+    Expression { source: String, offset: usize },
+}
+
+// Where the string literal whose contents start `text` ends: the index of its
+// closing quote. Quotes inside an interpolation belong to string literals of
+// the embedded expression.
+pub(crate) fn string_end(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'\\' => i += 2,
+            b'"' => return Some(i),
+            b'$' if bytes.get(i + 1) == Some(&b'{') => i += 2 + interpolation_end(&text[i + 2..])? + 1,
+            _ => i += 1,
+        }
+    }
+    None
+}
+
+// Where the interpolation whose source starts `text` ends: the index of the
+// `}` that closes it
+fn interpolation_end(text: &str) -> Option<usize> {
+    let bytes = text.as_bytes();
+    let mut depth = 0usize;
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'"' => i += 1 + string_end(&text[i + 1..])?,
+            b'{' => depth += 1,
+            b'}' if depth == 0 => return Some(i),
+            b'}' => depth -= 1,
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Splits the contents of a string literal into text and the interpolations
+/// in it. An unterminated `${` is kept as text.
+pub(crate) fn split(contents: &str) -> Vec<StringPart> {
+    let bytes = contents.as_bytes();
+    let mut parts = Vec::new();
+    let mut text_start = 0;
+    let mut i = 0;
+    let text = |start: usize, end: usize| StringPart::Text { text: contents[start..end].to_string(), offset: start };
+    while i < bytes.len() {
+        match bytes[i] {
+            // Escaped, like the `$` of `\${`
+            b'\\' => i += 2,
+            b'$' if bytes.get(i + 1) == Some(&b'{') => {
+                let start = i + 2;
+                let Some(end) = interpolation_end(&contents[start..]) else {
+                    break;
+                };
+                if i > text_start {
+                    parts.push(text(text_start, i));
+                }
+                parts.push(StringPart::Expression { source: contents[start..start + end].to_string(), offset: start });
+                i = start + end + 1;
+                text_start = i;
+            }
+            _ => i += 1,
+        }
+    }
+    if contents.len() > text_start {
+        parts.push(text(text_start, contents.len()));
+    }
+    parts
+}
+
+/// Where `span`, from lexing the interpolation at `offset` in `contents` on
+/// its own, is in the source of the whole string `literal`.
+pub(crate) fn locate(span: Span, literal: Span, contents: &str, offset: usize) -> Span {
+    // Without a location for the literal there is none for what's in it
+    if literal.line == 0 {
+        return Span::default();
+    }
+    // The contents start after the opening quote
+    let before = &contents[..offset];
+    let start = literal.start + 1 + offset;
+    let (line, column) = match before.rfind('\n') {
+        Some(newline) => (literal.line + before.matches('\n').count(), offset - newline),
+        None => (literal.line, literal.column + 1 + offset),
+    };
+    Span {
+        start: start + span.start,
+        end: start + span.end,
+        line: line + span.line - 1,
+        column: if span.line == 1 { column + span.column - 1 } else { span.column },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_alternates_text_and_expressions() {
+        let text = |text: &str, offset| StringPart::Text { text: text.to_string(), offset };
+        assert_eq!(
+            split("a ${x + 1} b ${f(\"}\")}"),
+            [
+                text("a ", 0),
+                StringPart::Expression { source: "x + 1".to_string(), offset: 4 },
+                text(" b ", 10),
+                StringPart::Expression { source: "f(\"}\")".to_string(), offset: 15 },
+            ]
+        );
+        assert_eq!(split(r"cost: \${price}"), [text(r"cost: \${price}", 0)]);
+        assert_eq!(split("${x"), [text("${x", 0)]);
+    }
+
+    #[test]
+    fn test_string_end_skips_nested_literals() {
+        assert_eq!(string_end(r#"plain" rest"#), Some(5));
+        assert_eq!(string_end(r#"a ${g("b ${"c"}")} d" rest"#), Some(20));
+        assert_eq!(string_end(r#"escaped \" quote" rest"#), Some(16));
+        assert_eq!(string_end("${\"unterminated}"), None);
+    }
+}
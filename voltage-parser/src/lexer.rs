@@ -1,6 +1,12 @@
+use std::fmt;
+use std::ops::Range;
 use logos::Logos;
+use voltage_core::Span;
+use crate::diagnostics::Diagnostic;
+use crate::interpolation;
 
 #[derive(Logos, Clone, Debug, PartialEq)]
+#[logos(error = LexErrorKind)]
 pub enum Token {
     #[token("fn")]
     Fn,
@@ -23,6 +29,9 @@ pub enum Token {
     #[token("while")]
     While,
     
+    #[token("loop")]
+    Loop,
+    
     #[token("break")]
     Break,
     
@@ -35,12 +44,45 @@ pub enum Token {
     #[token("unsafe")]
     Unsafe,
     
+    #[token("try")]
+    Try,
+    
+    #[token("catch")]
+    Catch,
+    
     #[token("import")]
     Import,
     
     #[token("as")]
     As,
     
+    #[token("impl")]
+    Impl,
+    
+    #[token("struct")]
+    Struct,
+    
+    #[token("pub")]
+    Pub,
+    
+    #[token("return")]
+    Return,
+    
+    #[token("true")]
+    True,
+    
+    #[token("false")]
+    False,
+    
+    /// A word in [`RESERVED`], kept for a feature the language doesn't have
+    /// yet so that programs naming things with it don't break when it does.
+    #[token("match", |lex| lex.slice().to_string())]
+    #[token("enum", |lex| lex.slice().to_string())]
+    #[token("const", |lex| lex.slice().to_string())]
+    #[token("mut", |lex| lex.slice().to_string())]
+    #[token("null", |lex| lex.slice().to_string())]
+    Reserved(String),
+    
     #[token("=")]
     Equals,
     
@@ -95,12 +137,36 @@ pub enum Token {
     #[token("*")]
     Star,
     
+    #[token("**")]
+    StarStar,
+    
     #[token("/")]
     Slash,
     
     #[token("%")]
     Percent,
     
+    #[token("&&")]
+    And,
+    
+    #[token("||")]
+    Or,
+    
+    #[token("+=")]
+    PlusEquals,
+    
+    #[token("-=")]
+    MinusEquals,
+    
+    #[token("*=")]
+    StarEquals,
+    
+    #[token("/=")]
+    SlashEquals,
+    
+    #[token("%=")]
+    PercentEquals,
+    
     #[token("[")]
     LeftBracket,
     
@@ -110,51 +176,291 @@ pub enum Token {
     #[token(".")]
     Dot,
     
+    #[token("..")]
+    DotDot,
+    
+    #[token("..=")]
+    DotDotEquals,
+    
     #[token("::")]
     DoubleColon,
     
     #[regex(r"[a-zA-Z_][a-zA-Z0-9_]*", |lex| lex.slice().to_string())]
     Identifier(String),
     
-    #[regex(r"[0-9]+", |lex| lex.slice().parse().unwrap_or(0))]
+    #[regex(r"[0-9]+", |lex| integer(lex.slice(), 10))]
+    #[regex(r"0x[0-9a-fA-F]+", |lex| integer(&lex.slice()[2..], 16))]
+    #[regex(r"0b[01]+", |lex| integer(&lex.slice()[2..], 2))]
     Number(i64),
     
-    #[regex(r#""([^"\\]|\\.)*""#, |lex| lex.slice().to_string())]
+    #[regex(r"[0-9]+\.[0-9]+", |lex| lex.slice().parse().unwrap_or(0.0))]
+    Float(f64),
+    
+    #[token("\"", string_literal)]
     String(String),
     
+    /// A `// ...` comment, with the text after the slashes. Only
+    /// [`Lexer::with_comments`] keeps comments.
+    #[regex(r"//[^\n]*", |lex| lex.slice()[2..].to_string())]
+    LineComment(String),
+    
+    /// A `/* ... */` comment, with the text between the markers.
+    #[token("/*", block_comment)]
+    BlockComment(String),
+    
     #[regex(r"[ \t\n\f]+", logos::skip)]
     Whitespace,
 }
 
+// The value of an integer literal's digits, for every base literals are
+// written in
+fn integer(digits: &str, radix: u32) -> Result<i64, LexErrorKind> {
+    i64::from_str_radix(digits, radix).map_err(|_| LexErrorKind::IntegerOutOfRange)
+}
+
+// Whether an integer literal is 2^63, the magnitude of i64::MIN
+fn is_min_magnitude(literal: &str) -> bool {
+    let (digits, radix) = match literal.get(..2) {
+        Some("0x") => (&literal[2..], 16),
+        Some("0b") => (&literal[2..], 2),
+        _ => (literal, 10),
+    };
+    u64::from_str_radix(digits, radix) == Ok(i64::MIN.unsigned_abs())
+}
+
+// The contents of the string literal whose opening quote was just lexed,
+// without the quotes. Scanned by hand rather than matched with a regex, since
+// a `${...}` interpolation can hold string literals of its own.
+fn string_literal(lex: &mut logos::Lexer<Token>) -> Result<String, LexErrorKind> {
+    let end = interpolation::string_end(lex.remainder()).ok_or(LexErrorKind::UnexpectedCharacter)?;
+    let contents = lex.remainder()[..end].to_string();
+    lex.bump(end + 1);
+    Ok(contents)
+}
+
+// The text of the block comment whose `/*` was just lexed. Block comments
+// don't nest.
+fn block_comment(lex: &mut logos::Lexer<Token>) -> Result<String, LexErrorKind> {
+    let end = lex.remainder().find("*/").ok_or(LexErrorKind::UnterminatedComment)?;
+    let text = lex.remainder()[..end].to_string();
+    lex.bump(end + 2);
+    Ok(text)
+}
+
+/// Reserved words of the language, including the boolean literals.
+pub const KEYWORDS: &[&str] = &[
+    "fn", "let", "if", "else", "elif", "for", "while", "loop", "break", "continue",
+    "in", "unsafe", "try", "catch", "import", "as", "impl", "struct", "pub", "return", "true", "false",
+];
+
+/// Words that mean nothing yet but can't be names either, lexed as
+/// [`Token::Reserved`].
+pub const RESERVED: &[&str] = &["match", "enum", "const", "mut", "null"];
+
+/// Why a piece of source couldn't be tokenized.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LexErrorKind {
+    /// It isn't part of any token, like `@` or a lone `!`.
+    #[default]
+    UnexpectedCharacter,
+    /// An integer literal too large for an `i64`.
+    IntegerOutOfRange,
+    /// A `/*` without a `*/` after it.
+    UnterminatedComment,
+}
+
+/// A piece of source that isn't a valid token.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+    /// The source text that couldn't be tokenized.
+    pub snippet: String,
+}
+
+impl fmt::Display for LexError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.kind {
+            LexErrorKind::UnexpectedCharacter => write!(f, "Unexpected character {:?}", self.snippet),
+            LexErrorKind::IntegerOutOfRange => write!(f, "integer literal out of range for i64: {}", self.snippet),
+            LexErrorKind::UnterminatedComment => write!(f, "unterminated block comment"),
+        }
+    }
+}
+
+impl LexErrorKind {
+    /// The [`Diagnostic::code`] for errors of this kind.
+    pub fn code(self) -> &'static str {
+        match self {
+            LexErrorKind::UnexpectedCharacter => "unexpected_character",
+            LexErrorKind::IntegerOutOfRange => "integer_out_of_range",
+            LexErrorKind::UnterminatedComment => "unterminated_comment",
+        }
+    }
+}
+
+impl From<LexError> for Diagnostic {
+    fn from(error: LexError) -> Self {
+        Diagnostic::new(error.to_string(), error.span).with_code(error.kind.code())
+    }
+}
+
+/// The tokens of a piece of source. The source is only borrowed while it's
+/// lexed, not kept: what's kept of it is where its lines start, for
+/// [`Lexer::line_col`].
 #[derive(Debug)]
 pub struct Lexer {
-    source: String,
     tokens: Vec<Token>,
+    // Where each token came from, parallel to `tokens`
+    spans: Vec<Span>,
+    errors: Vec<LexError>,
+    lines: LineIndex,
 }
 
 impl Lexer {
-    pub fn new(source: String) -> Self {
-        let mut lexer = Token::lexer(&source);
+    /// Tokenizes `source`. Pieces that aren't valid tokens are left out of
+    /// the tokens and reported by [`Lexer::errors`], and so are comments.
+    pub fn new(source: &str) -> Self {
+        let Self { tokens, spans, errors, lines } = Self::with_comments(source);
+        let (tokens, spans) = tokens.into_iter().zip(spans).filter(|(token, _)| !token.is_comment()).unzip();
+        Self { tokens, spans, errors, lines }
+    }
+
+    /// Like `new`, but keeps comments as [`Token::LineComment`] and
+    /// [`Token::BlockComment`] tokens, for tools that rewrite source and
+    /// mustn't lose them. The parser doesn't expect comments.
+    pub fn with_comments(source: &str) -> Self {
+        let lines = LineIndex::new(source);
+        let mut lexer = Token::lexer(source);
         let mut tokens = Vec::new();
+        let mut spans = Vec::new();
+        let mut errors = Vec::new();
         
         while let Some(token_result) = lexer.next() {
+            let span = lines.span(lexer.span());
             match token_result {
-                Ok(token) => tokens.push(token),
-                Err(_) => {
-                    // Log the error for debugging but continue processing
-                    eprintln!("Lexer error: Could not tokenize a portion of the source");
-                    // Skip the problematic part and continue
-                    continue;
+                Ok(token) => {
+                    tokens.push(token);
+                    spans.push(span);
+                }
+                // The digits of `-9223372036854775808` don't fit an i64, but
+                // negated they're i64::MIN. After a `-` they're kept as
+                // i64::MIN for the parser to fold the sign into.
+                Err(LexErrorKind::IntegerOutOfRange)
+                    if tokens.iter().rev().find(|token| !token.is_comment()) == Some(&Token::Minus)
+                        && is_min_magnitude(lexer.slice()) =>
+                {
+                    tokens.push(Token::Number(i64::MIN));
+                    spans.push(span);
                 }
+                Err(kind) => errors.push(LexError { kind, span, snippet: lexer.slice().to_string() }),
             }
         }
         
-        Self { source, tokens }
+        Self { tokens, spans, errors, lines }
+    }
+    
+    /// Like `new`, but fails on the first piece of source that isn't a valid
+    /// token.
+    pub fn try_new(source: &str) -> Result<Self, String> {
+        let lexer = Self::new(source);
+        match lexer.errors.first() {
+            Some(error) => Err(format!("{} at line {}, column {}", error, error.span.line, error.span.column)),
+            None => Ok(lexer),
+        }
+    }
+
+    /// The pieces of source that weren't valid tokens, in order.
+    pub fn errors(&self) -> &[LexError] {
+        &self.errors
     }
     
     pub fn tokenize(&self) -> &[Token] {
         &self.tokens
     }
+
+    /// The source location of each token returned by `tokenize`.
+    pub fn spans(&self) -> &[Span] {
+        &self.spans
+    }
+
+    /// The tokens and their spans, given up rather than copied, as
+    /// [`Parser::from_lexer`](crate::Parser::from_lexer) takes them.
+    pub fn into_tokens(self) -> (Vec<Token>, Vec<Span>) {
+        (self.tokens, self.spans)
+    }
+
+    /// The line and column of the byte at `offset` in the source, both from
+    /// 1, as spans give them. Like a span's column, the column counts bytes,
+    /// and a `\r` before a `\n` ends the line it's on.
+    pub fn line_col(&self, offset: usize) -> (u32, u32) {
+        let (line, column) = self.lines.position(offset);
+        (line as u32, column as u32)
+    }
+}
+
+impl Token {
+    /// The word this token is written as, if it's one of [`KEYWORDS`] or
+    /// [`RESERVED`] and so can't be used as a name.
+    pub fn keyword(&self) -> Option<&str> {
+        let keyword = match self {
+            Token::Fn => "fn",
+            Token::Let => "let",
+            Token::If => "if",
+            Token::Else => "else",
+            Token::Elif => "elif",
+            Token::For => "for",
+            Token::While => "while",
+            Token::Loop => "loop",
+            Token::Break => "break",
+            Token::Continue => "continue",
+            Token::In => "in",
+            Token::Unsafe => "unsafe",
+            Token::Try => "try",
+            Token::Catch => "catch",
+            Token::Import => "import",
+            Token::As => "as",
+            Token::Impl => "impl",
+            Token::Struct => "struct",
+            Token::Pub => "pub",
+            Token::Return => "return",
+            Token::True => "true",
+            Token::False => "false",
+            Token::Reserved(word) => word,
+            _ => return None,
+        };
+        Some(keyword)
+    }
+
+    /// Whether this is a line or block comment.
+    pub fn is_comment(&self) -> bool {
+        matches!(self, Token::LineComment(_) | Token::BlockComment(_))
+    }
+}
+
+// Turns byte offsets into line and column numbers
+#[derive(Debug)]
+struct LineIndex {
+    line_starts: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(source: &str) -> Self {
+        let line_starts = std::iter::once(0)
+            .chain(source.match_indices('\n').map(|(i, _)| i + 1))
+            .collect();
+        Self { line_starts }
+    }
+
+    fn position(&self, offset: usize) -> (usize, usize) {
+        let line = self.line_starts.partition_point(|&start| start <= offset);
+        (line, offset - self.line_starts[line - 1] + 1)
+    }
+
+    fn span(&self, range: Range<usize>) -> Span {
+        let (line, column) = self.position(range.start);
+        Span { start: range.start, end: range.end, line, column }
+    }
 }
 
 #[cfg(test)]
@@ -163,7 +469,7 @@ mod tests {
 
     #[test]
     fn test_basic_tokenization() {
-        let source = r#"fn main() { let x = 1; }"#.to_string();
+        let source = r#"fn main() { let x = 1; }"#;
         let lexer = Lexer::new(source);
         let tokens = lexer.tokenize();
         
@@ -179,4 +485,130 @@ mod tests {
         assert_eq!(tokens[9], Token::Semi);
         assert_eq!(tokens[10], Token::RightBrace);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_boolean_keywords() {
+        let lexer = Lexer::new("true false truely false_");
+        assert_eq!(
+            lexer.tokenize(),
+            [
+                Token::True,
+                Token::False,
+                Token::Identifier("truely".to_string()),
+                Token::Identifier("false_".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_string_tokens_exclude_quotes() {
+        let lexer = Lexer::new(r#"puts("hi there")"#);
+        assert_eq!(lexer.tokenize()[2], Token::String("hi there".to_string()));
+    }
+
+    #[test]
+    fn test_try_new_reports_invalid_characters() {
+        assert!(Lexer::try_new("let x = 1;").is_ok());
+        let error = Lexer::try_new("let x = 1;\nlet y = #;").unwrap_err();
+        assert_eq!(error, "Unexpected character \"#\" at line 2, column 9");
+    }
+
+    #[test]
+    fn test_unknown_characters_are_collected() {
+        let lexer = Lexer::new("let x = 1 @ 2;");
+        assert_eq!(
+            lexer.errors(),
+            [LexError {
+                kind: LexErrorKind::UnexpectedCharacter,
+                span: Span { start: 10, end: 11, line: 1, column: 11 },
+                snippet: "@".to_string(),
+            }]
+        );
+        assert_eq!(lexer.errors()[0].to_string(), "Unexpected character \"@\"");
+        // The tokens around it are still there
+        assert_eq!(lexer.tokenize().len(), 6);
+        assert!(Lexer::new("let x = 1;").errors().is_empty());
+    }
+
+    #[test]
+    fn test_integer_literals_in_every_base() {
+        let lexer = Lexer::new("9223372036854775807 0x1F 0b101");
+        assert_eq!(lexer.tokenize(), [Token::Number(i64::MAX), Token::Number(31), Token::Number(5)]);
+        assert!(lexer.errors().is_empty());
+    }
+
+    #[test]
+    fn test_integer_literals_out_of_range_are_errors() {
+        let lexer = Lexer::new("let a = 9223372036854775808;\nlet b = 0xFFFFFFFFFFFFFFFFFF;");
+        let errors: Vec<String> = lexer.errors().iter().map(ToString::to_string).collect();
+        assert_eq!(
+            errors,
+            [
+                "integer literal out of range for i64: 9223372036854775808",
+                "integer literal out of range for i64: 0xFFFFFFFFFFFFFFFFFF",
+            ]
+        );
+        assert_eq!(lexer.errors()[1].kind, LexErrorKind::IntegerOutOfRange);
+        assert_eq!((lexer.errors()[1].span.line, lexer.errors()[1].span.column), (2, 9));
+    }
+
+    #[test]
+    fn test_the_magnitude_of_i64_min_lexes_after_a_minus() {
+        let lexer = Lexer::new("-9223372036854775808 - 0x8000000000000000");
+        assert_eq!(lexer.tokenize()[1], Token::Number(i64::MIN));
+        assert_eq!(lexer.tokenize()[3], Token::Number(i64::MIN));
+        let lexer = Lexer::new("-9223372036854775809;");
+        assert_eq!(lexer.errors()[0].kind, LexErrorKind::IntegerOutOfRange);
+    }
+
+    #[test]
+    fn test_tokens_carry_spans() {
+        let lexer = Lexer::new("let x = 1;\n  puts(x);");
+        assert_eq!(lexer.spans().len(), lexer.tokenize().len());
+        assert_eq!(lexer.spans()[0], Span { start: 0, end: 3, line: 1, column: 1 });
+        // `puts` on the second line, after two spaces of indentation
+        assert_eq!(lexer.spans()[5], Span { start: 13, end: 17, line: 2, column: 3 });
+    }
+
+    #[test]
+    fn test_line_col_with_crlf_line_endings() {
+        let source = "let x = 1;\r\n\r\n  puts(x);\r\n";
+        let lexer = Lexer::new(source);
+        assert_eq!(lexer.line_col(0), (1, 1));
+        // The `\r` is the last byte of its line
+        assert_eq!(lexer.line_col(10), (1, 11));
+        assert_eq!(lexer.line_col(12), (2, 1));
+        let puts = source.find("puts").unwrap();
+        assert_eq!(lexer.line_col(puts), (3, 3));
+        assert_eq!((lexer.spans()[5].line, lexer.spans()[5].column), (3, 3));
+    }
+
+    #[test]
+    fn test_comments_are_skipped_unless_kept() {
+        let source = "let x = 1; // one\n/* a\n   block */ puts(x);";
+        let lexer = Lexer::new(source);
+        assert_eq!(lexer.tokenize().len(), 10);
+        assert_eq!(lexer.spans().len(), 10);
+        assert_eq!(lexer.spans()[5].line, 3);
+
+        let lexer = Lexer::with_comments(source);
+        assert_eq!(lexer.tokenize()[5], Token::LineComment(" one".to_string()));
+        assert_eq!(lexer.tokenize()[6], Token::BlockComment(" a\n   block ".to_string()));
+        assert_eq!(lexer.spans()[6].line, 2);
+        // Division is still division
+        assert_eq!(Lexer::new("a / b").tokenize()[1], Token::Slash);
+
+        let lexer = Lexer::new("let x = 1; /* never closed");
+        assert_eq!(lexer.errors()[0].kind, LexErrorKind::UnterminatedComment);
+    }
+
+    #[test]
+    fn test_keywords_lex_as_themselves() {
+        for word in KEYWORDS.iter().chain(RESERVED) {
+            let lexer = Lexer::new(word);
+            assert_eq!(lexer.tokenize().len(), 1, "{}", word);
+            assert_eq!(lexer.tokenize()[0].keyword(), Some(*word));
+        }
+        assert_eq!(Lexer::new("matches").tokenize()[0], Token::Identifier("matches".to_string()));
+    }
+}
@@ -1,9 +1,20 @@
 pub mod lexer;
-pub use lexer::{Lexer, Token};
+pub use lexer::{LexError, LexErrorKind, Lexer, Token, KEYWORDS, RESERVED};
 
 pub mod parser;
 pub use parser::Parser;
 
+pub mod diagnostics;
+
+pub mod format;
+mod interpolation;
+pub mod escape;
+pub use diagnostics::{
+    parse_with_diagnostics, summarize, Diagnostic, DiagnosticSummary, Note, ParseResult, RenderOptions, Severity,
+    SpannedToken, DEFAULT_DIAGNOSTIC_LIMIT, DEFAULT_RENDER_WIDTH,
+};
+
+#[allow(clippy::module_inception)]
 mod integration_tests;
 
 pub use voltage_core::*;
\ No newline at end of file
@@ -1,151 +1,329 @@
-use crate::lexer::{Token, Lexer};
-use voltage_core::{Expression, Literal, BinaryOp, Statement, Function};
+use std::rc::Rc;
+use crate::diagnostics::Diagnostic;
+use crate::escape;
+use crate::interpolation::{self, StringPart};
+use crate::lexer::{LexError, Lexer, Token};
+use voltage_core::{Expression, Literal, BinaryOp, LogicalOp, UnaryOp, Statement, StatementKind, Function, Span, StructField};
 
 pub struct Parser {
     tokens: Vec<Token>,
+    // Source locations of `tokens`; empty when parsing tokens without source
+    spans: Vec<Span>,
+    // The text `spans` point into, for `dbg` to quote; `None` without source
+    source: Option<Rc<str>>,
     current: usize,
+    // Errors in lists that were recovered from by skipping to the end of
+    // the list, so parsing could carry on
+    errors: Vec<Diagnostic>,
+    // Set while parsing the condition of an `if` or `while` or what a `for`
+    // loops over, where `name {` starts the body rather than a struct
+    no_struct_literal: bool,
+    // How many blocks the parser is in, where `pub` can't go
+    depth: usize,
+    // Whether `_` can be read as a variable, as the REPL's last result
+    underscore_reads: bool,
 }
 
 impl Parser {
-    pub fn new(tokens: Vec<Token>) -> Self {
-        Parser { tokens, current: 0 }
+    pub fn new(tokens: impl IntoIterator<Item = Token>) -> Self {
+        Self::with_spans(tokens, Vec::new())
+    }
+
+    /// A parser that records where each statement came from, using the spans
+    /// from [`Lexer::spans`](crate::Lexer::spans).
+    pub fn with_spans(tokens: impl IntoIterator<Item = Token>, spans: impl IntoIterator<Item = Span>) -> Self {
+        let (tokens, spans) = (tokens.into_iter().collect(), spans.into_iter().collect());
+        Parser { tokens, spans, source: None, current: 0, errors: Vec::new(), no_struct_literal: false, depth: 0, underscore_reads: false }
+    }
+
+    /// A parser for the tokens `lexer` found, with their spans. It takes them
+    /// over rather than copying them, so a big file's tokens are only held
+    /// once.
+    pub fn from_lexer(lexer: Lexer) -> Self {
+        let (tokens, spans) = lexer.into_tokens();
+        Self::with_spans(tokens, spans)
+    }
+
+    /// Keeps `source`, which the spans point into, so `dbg(x)` can print the
+    /// text of `x` as written rather than as the parser would write it.
+    pub fn with_source(mut self, source: &str) -> Self {
+        self.source = Some(Rc::from(source));
+        self
+    }
+
+    /// Lets `_` be read like any other variable, for the REPL, which keeps
+    /// its last result there. Elsewhere `_` only discards values.
+    pub fn allow_underscore_reads(mut self) -> Self {
+        self.underscore_reads = true;
+        self
     }
     
+    /// Parses the whole program, panicking with the first syntax error. Use
+    /// [`try_parse`](Self::try_parse) to get it back as an error instead.
     pub fn parse(&mut self) -> Vec<Statement> {
+        self.try_parse().unwrap_or_else(|error| panic!("{}", error))
+    }
+    
+    /// Parses like `parse`, but returns the first syntax error instead of
+    /// panicking with it.
+    pub fn try_parse(&mut self) -> Result<Vec<Statement>, String> {
         let mut statements = Vec::new();
         
         while !self.is_at_end() {
-            if let Some(stmt) = self.declaration() {
-                statements.push(stmt);
+            match self.declaration()? {
+                Some(stmt) => statements.push(stmt),
+                // Only a stray '}' stops a declaration without an error
+                None => return Err("Unexpected '}'".to_string()),
+            }
+            if let Some(error) = self.errors.first() {
+                return Err(error.message.clone());
             }
         }
         
-        statements
+        Ok(statements)
+    }
+
+    /// Parses every top-level statement it can. A statement with a syntax
+    /// error is reported and skipped, and parsing resumes after it.
+    pub(crate) fn parse_recovering(&mut self) -> (Vec<Statement>, Vec<Diagnostic>) {
+        let mut statements = Vec::new();
+        let mut diagnostics = Vec::new();
+        
+        while !self.is_at_end() {
+            let start = self.current;
+            let result = self.declaration();
+            diagnostics.append(&mut self.errors);
+            match result {
+                Ok(Some(stmt)) => statements.push(stmt),
+                // Only a stray '}' stops a declaration without an error
+                Ok(None) => {
+                    diagnostics.push(Diagnostic::new("Unexpected '}'", self.error_span()));
+                    self.current = start + 1;
+                }
+                Err(message) => {
+                    let error = Diagnostic::new(message, self.error_span());
+                    let at = self.current;
+                    self.synchronize(start);
+                    diagnostics.push(error.recovered(self.span_of(at, self.current)));
+                }
+            }
+        }
+        
+        (statements, diagnostics)
+    }
+
+    // Where the parser gave up: the token it was looking at, or the last
+    // token when it ran out of input
+    fn error_span(&self) -> Span {
+        let index = self.current.min(self.tokens.len().saturating_sub(1));
+        self.spans.get(index).copied().unwrap_or_default()
+    }
+
+    // Skips the statement starting at token `start`: up to its closing ';' or
+    // '}', or up to the next function, whichever comes first
+    fn synchronize(&mut self, start: usize) {
+        self.current = start;
+        let mut depth = 0usize;
+        while !self.is_at_end() {
+            if self.current > start && depth == 0 && self.check(&Token::Fn) {
+                return;
+            }
+            let token = self.peek().cloned();
+            self.current += 1;
+            match token {
+                Some(Token::LeftBrace) => depth += 1,
+                Some(Token::RightBrace) => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        return;
+                    }
+                }
+                Some(Token::Semi) if depth == 0 => return,
+                _ => {}
+            }
+        }
     }
     
-    fn declaration(&mut self) -> Option<Statement> {
+    // The next declaration, or `None` at the end of a block or the file
+    fn declaration(&mut self) -> Result<Option<Statement>, String> {
+        let start = self.current;
+        let Some(kind) = self.declaration_kind()? else {
+            return Ok(None);
+        };
+        Ok(Some(Statement::new(kind, self.span_of(start, self.current))))
+    }
+
+    // The span from token `first` up to (not including) token `end`
+    fn span_of(&self, first: usize, end: usize) -> Span {
+        match (self.spans.get(first), end.checked_sub(1).and_then(|last| self.spans.get(last))) {
+            (Some(first), Some(last)) => first.to(*last),
+            _ => Span::default(),
+        }
+    }
+
+    fn declaration_kind(&mut self) -> Result<Option<StatementKind>, String> {
         if self.is_at_end() || self.check(&Token::RightBrace) {
-            return None;
+            return Ok(None);
         }
         
         if self.match_token(&Token::Fn) {
-            return Some(self.function_declaration());
+            return Ok(Some(StatementKind::Function(self.function_declaration()?)));
+        }
+        
+        if self.match_token(&Token::Pub) {
+            return Ok(Some(self.public_declaration()?));
+        }
+        
+        if self.match_token(&Token::Impl) {
+            return Ok(Some(self.impl_block()?));
+        }
+        
+        if self.match_token(&Token::Struct) {
+            return Ok(Some(self.struct_definition()?));
         }
         
         if self.match_token(&Token::Let) {
-            return Some(self.var_declaration());
+            return Ok(Some(self.var_declaration()?));
         }
         
         // Check if it's the end of the block before attempting to parse a statement
         if self.is_at_end() || self.check(&Token::RightBrace) {
-            return None;
+            return Ok(None);
         }
         
         self.statement()
     }
     
-    fn statement(&mut self) -> Option<Statement> {
+    fn statement(&mut self) -> Result<Option<StatementKind>, String> {
         if self.check(&Token::RightBrace) || self.is_at_end() {
-            return None;
+            return Ok(None);
         }
         
         if self.match_token(&Token::If) {
-            return Some(self.if_statement());
+            return Ok(Some(self.if_statement()?));
         }
         
         if self.match_token(&Token::While) {
-            return Some(self.while_statement());
+            return Ok(Some(self.while_statement()?));
         }
         
         if self.match_token(&Token::For) {
-            return Some(self.for_statement());
+            return Ok(Some(self.for_statement()?));
+        }
+        
+        if self.match_token(&Token::Loop) {
+            return Ok(Some(StatementKind::Loop(self.loop_body()?)));
         }
         
         if self.match_token(&Token::Break) {
-            self.consume(&Token::Semi).expect("Expected ';'");
-            return Some(Statement::Break);
+            let value = if self.check(&Token::Semi) {
+                None
+            } else {
+                Some(self.expression()?)
+            };
+            self.expect(&Token::Semi, "Expected ';' after break")?;
+            return Ok(Some(StatementKind::Break(value)));
         }
         
         if self.match_token(&Token::Continue) {
-            self.consume(&Token::Semi).expect("Expected ';'");
-            return Some(Statement::Continue);
+            self.expect(&Token::Semi, "Expected ';'")?;
+            return Ok(Some(StatementKind::Continue));
+        }
+        
+        if self.match_token(&Token::Return) {
+            let value = if self.check(&Token::Semi) {
+                None
+            } else {
+                Some(self.expression()?)
+            };
+            self.expect(&Token::Semi, "Expected ';' after return value")?;
+            return Ok(Some(StatementKind::Return(value)));
         }
         
         if self.match_token(&Token::Unsafe) {
-            self.consume(&Token::LeftBrace).expect("Expected '{' after unsafe block");
-            let body = self.parse_block_contents();
-            return Some(Statement::UnsafeBlock(body));
+            self.expect(&Token::LeftBrace, "Expected '{' after unsafe block")?;
+            let body = self.parse_block_contents()?;
+            return Ok(Some(StatementKind::UnsafeBlock(body)));
+        }
+        
+        if self.match_token(&Token::Try) {
+            return Ok(Some(self.try_statement()?));
         }
         
         if self.match_token(&Token::Import) {
-            let module_name = self.consume_identifier().expect("Expected module name after import");
+            // A module path is names separated by dots, like `std.math`
+            let mut module_name = self.consume_identifier("Expected module name after import")?;
+            while self.match_token(&Token::Dot) {
+                let name = self.consume_identifier("Expected module name after '.'")?;
+                module_name = format!("{}.{}", module_name, name);
+            }
             
             // Check if there's an 'as' alias
-            if self.match_token(&Token::As) {
-                let alias = self.consume_identifier().expect("Expected alias name after 'as'");
-                return Some(Statement::ImportAs(module_name, alias));
+            let import = if self.match_token(&Token::As) {
+                let alias = self.consume_identifier("Expected alias name after 'as'")?;
+                StatementKind::ImportAs(module_name, alias)
             } else {
-                return Some(Statement::Import(module_name));
-            }
+                StatementKind::Import(module_name)
+            };
+            self.expect(&Token::Semi, "Expected ';' after import")?;
+            return Ok(Some(import));
         }
         
         // Also add a call to handle the in token in for loops if not already handled
         // We already handle 'in' in the for_statement method
         
         if self.match_token(&Token::LeftBrace) {
-            return Some(self.block().unwrap());
+            return Ok(Some(self.block()?));
         }
 
         // Check again after handling block
         if self.check(&Token::RightBrace) || self.is_at_end() {
-            return None;
+            return Ok(None);
         }
 
         // Parse expression statement
-        let expr = self.expression();
-        self.consume(&Token::Semi).expect("Expected ';'");
-        Some(Statement::Expression(expr))
+        let expr = self.expression()?;
+        self.expect(&Token::Semi, "Expected ';'")?;
+        Ok(Some(StatementKind::Expression(expr)))
     }
     
-    fn function_declaration(&mut self) -> Statement {
-        let name = self.consume_identifier().expect("Expected function name");
+    fn function_declaration(&mut self) -> Result<Function, String> {
+        let name = self.consume_identifier("Expected function name")?;
         
-        self.consume(&Token::LeftParen).expect("Expected '(' after function name");
+        self.expect(&Token::LeftParen, "Expected '(' after function name")?;
         
-        let mut parameters = Vec::new();  // This should be a Vec<(String, Type)> to match Function definition
-        if !self.check(&Token::RightParen) {
-            loop {
-                let param_name = self.consume_identifier().expect("Expected parameter name");
-                
-                // Check if there's a type annotation for this parameter
-                let param_type = if self.check(&Token::Colon) {
-                    self.consume(&Token::Colon).expect("Expected ':' for parameter type");
-                    if let Ok(parsed_type) = self.parse_type() {
-                        parsed_type
-                    } else {
-                        voltage_core::Type::Unknown  // Default to Unknown if parsing fails
-                    }
+        let parameters = self.comma_separated(&Token::RightParen, |parser| {
+            let start = parser.current;
+            let param_name = parser.consume_identifier("Expected parameter name")?;
+            
+            // Check if there's a type annotation for this parameter
+            let param_type = if parser.check(&Token::Colon) {
+                parser.expect(&Token::Colon, "Expected ':' for parameter type")?;
+                if let Ok(parsed_type) = parser.parse_type() {
+                    parsed_type
                 } else {
-                    voltage_core::Type::Unknown  // Will be inferred
-                };
-                
-                parameters.push((param_name, param_type));
-                
-                if !self.match_token(&Token::Comma) {
-                    break;
-                }
-                
-                if self.check(&Token::RightParen) {
-                    break;
+                    voltage_core::Type::Unknown  // Default to Unknown if parsing fails
                 }
+            } else {
+                voltage_core::Type::Unknown  // Will be inferred
+            };
+            
+            Ok(((param_name, param_type), start))
+        })?;
+        // Any number of parameters can be `_`, as none of them can be read
+        let mut seen: Vec<(&String, usize)> = Vec::new();
+        for ((name, _), start) in &parameters {
+            if let Some(&(_, first)) = seen.iter().find(|(seen, _)| *seen == name && name != "_") {
+                let error = Diagnostic::new(format!("duplicate parameter `{}`", name), self.span_of(*start, start + 1));
+                self.errors.push(error.with_note("first declared here", self.span_of(first, first + 1)));
             }
+            seen.push((name, *start));
         }
-        
-        self.consume(&Token::RightParen).expect("Expected ')'");
+        let parameters = parameters.into_iter().map(|(parameter, _)| parameter).collect();
         
         // Check if there's a return type annotation using '->'
         let return_type = if self.check(&Token::Arrow) {
-            self.consume(&Token::Arrow).expect("Expected '->' for return type");
+            self.expect(&Token::Arrow, "Expected '->' for return type")?;
             if let Ok(parsed_type) = self.parse_type() {
                 parsed_type
             } else {
@@ -156,53 +334,113 @@ impl Parser {
         };
         
         // At this point, the next token should be the opening brace of the function body
-        self.consume(&Token::LeftBrace).expect("Expected '{' for function body");
+        self.expect(&Token::LeftBrace, "Expected '{' for function body")?;
         
-        let body = self.parse_block_contents();
+        let body = self.parse_block_contents()?;
         
-        Statement::Function(Function {
+        Ok(Function {
             name,
             parameters,
             return_type,
             body,
+            public: false,
         })
     }
     
-    fn var_declaration(&mut self) -> Statement {
-        let name = self.consume_identifier().expect("Expected variable name");
+    // `pub fn` or `pub let`, which only go at the top level of a file
+    fn public_declaration(&mut self) -> Result<StatementKind, String> {
+        if self.depth > 0 {
+            return Err("`pub` only goes on top-level functions and variables".to_string());
+        }
+        if self.match_token(&Token::Fn) {
+            return Ok(StatementKind::Function(Function { public: true, ..self.function_declaration()? }));
+        }
+        self.expect(&Token::Let, "Expected 'fn' or 'let' after 'pub'")?;
+        match self.var_declaration()? {
+            StatementKind::VariableDeclaration { name, value, explicit_type, .. } => {
+                Ok(StatementKind::VariableDeclaration { name, value, explicit_type, public: true })
+            }
+            _ => unreachable!("var_declaration only parses variable declarations"),
+        }
+    }
+    
+    // `impl Name { fn ... }`, which holds nothing but functions
+    fn impl_block(&mut self) -> Result<StatementKind, String> {
+        let type_name = self.consume_identifier("Expected a type name after impl")?;
+        self.expect(&Token::LeftBrace, "Expected '{' after the type name of impl")?;
+        let mut methods = Vec::new();
+        while !self.match_token(&Token::RightBrace) {
+            self.expect(&Token::Fn, "Expected 'fn' in impl block")?;
+            methods.push(self.function_declaration()?);
+        }
+        Ok(StatementKind::Impl { type_name, methods })
+    }
+    
+    // `struct Name { field: type, field: type = default }`
+    fn struct_definition(&mut self) -> Result<StatementKind, String> {
+        let name = self.consume_identifier("Expected a struct name after struct")?;
+        self.expect(&Token::LeftBrace, "Expected '{' after the struct name")?;
+        let fields = self.comma_separated(&Token::RightBrace, |parser| {
+            let name = parser.consume_identifier("Expected field name in struct definition")?;
+            parser.expect(&Token::Colon, "Expected ':' after the field name")?;
+            let field_type = parser.parse_type().map_err(|e| format!("Invalid field type: {}", e))?;
+            let default = match parser.match_token(&Token::Equals) {
+                true => Some(parser.expression()?),
+                false => None,
+            };
+            Ok(StructField { name, field_type, default })
+        })?;
+        Ok(StatementKind::Struct { name, fields })
+    }
+    
+    fn var_declaration(&mut self) -> Result<StatementKind, String> {
+        let name = self.consume_identifier("Expected variable name")?;
         
         // Check if there's a type annotation
         let explicit_type = if self.check(&Token::Colon) {
-            self.consume(&Token::Colon).expect("Expected ':' after variable name");
-            if let Ok(parsed_type) = self.parse_type() {
-                Some(parsed_type)
-            } else {
-                // If parsing the type failed, try to continue by skipping ahead
-                // For now, we'll just return None but in a real implementation we'd handle this better
-                None
-            }
+            self.expect(&Token::Colon, "Expected ':' after variable name")?;
+            let annotation = self.parse_type();
+            Some(annotation.map_err(|e| format!("Invalid type annotation: {}", e))?)
         } else {
             None
         };
         
-        self.consume(&Token::Equals).expect("Expected '=' after variable name");
+        self.expect(&Token::Equals, "Expected '=' after variable name")?;
         
-        let value = self.expression();
+        // A `loop` only gives a value as the whole value of a `let`
+        let value = if self.match_token(&Token::Loop) {
+            Expression::Loop(self.loop_body()?)
+        } else {
+            self.expression()?
+        };
         
-        self.consume(&Token::Semi).expect("Expected ';' after variable declaration");
+        self.expect(&Token::Semi, "Expected ';' after variable declaration")?;
         
-        Statement::VariableDeclaration {
+        Ok(StatementKind::VariableDeclaration {
             name,
             value,
             explicit_type,
-        }
+            public: false,
+        })
     }
     
     fn parse_type(&mut self) -> Result<voltage_core::Type, String> {
         // For now, we'll support basic types
         // Later we might support function types, generics, etc.
-        match &self.tokens[self.current] {
-            Token::Identifier(type_name) => {
+        match self.peek() {
+            Some(Token::LeftBracket) => {
+                self.current += 1;
+                let element = self.parse_type()?;
+                if self.match_token(&Token::Semi) {
+                    let size = self.array_size()?;
+                    self.consume(&Token::RightBracket)?;
+                    return Ok(voltage_core::Type::Array(Box::new(element), size));
+                }
+                self.consume(&Token::RightBracket)?;
+                Ok(voltage_core::Type::DynamicArray(Box::new(element)))
+            },
+            Some(Token::Identifier(type_name)) => {
+                let type_name = type_name.clone();
                 self.current += 1; // consume the identifier
                 
                 match type_name.as_str() {
@@ -214,17 +452,44 @@ impl Parser {
                     _ => Err(format!("Unknown type: {}", type_name)),
                 }
             },
-            _ => Err("Expected type identifier".to_string()),
+            Some(_) => Err("Expected type identifier".to_string()),
+            None => Err(end_of_file("type")),
         }
     }
+
+    // The N of `[T; N]`, which has to be spelled as a non-negative integer
+    // literal; sizes can't be computed
+    fn array_size(&mut self) -> Result<usize, String> {
+        let size = match self.peek() {
+            Some(Token::Number(n)) => usize::try_from(*n).map_err(|_| format!("array size can't be negative: {}", n)),
+            Some(Token::Minus) => match self.peek_ahead(1) {
+                Some(Token::Number(n)) => Err(format!("array size can't be negative: -{}", n)),
+                _ => Err("array size must be an integer literal, got `-`".to_string()),
+            },
+            Some(Token::Identifier(name)) => Err(format!("array size must be an integer literal, got `{}`", name)),
+            Some(other) => Err(format!("array size must be an integer literal, got {:?}", other)),
+            None => Err(end_of_file("array size")),
+        }?;
+        self.current += 1;
+        Ok(size)
+    }
     
 
     
-    fn parse_block_contents(&mut self) -> Vec<Statement> {
+    // The statements of a block up to and including its '}'. The depth is
+    // back to what it was afterwards even if there's an error, so recovering
+    // from it can carry on at the top level.
+    fn parse_block_contents(&mut self) -> Result<Vec<Statement>, String> {
+        self.depth += 1;
+        let statements = self.block_statements();
+        self.depth -= 1;
+        statements
+    }
+    
+    fn block_statements(&mut self) -> Result<Vec<Statement>, String> {
         let mut statements = Vec::new();
-        
         while !self.check(&Token::RightBrace) && !self.is_at_end() {
-            if let Some(stmt) = self.declaration() {
+            if let Some(stmt) = self.declaration()? {
                 statements.push(stmt);
             } else {
                 // No statement could be parsed, likely reached end of block
@@ -232,387 +497,534 @@ impl Parser {
             }
         }
         
-        self.consume(&Token::RightBrace).expect("Expected '}'");
-        
-        statements
+        self.expect(&Token::RightBrace, "Expected '}'")?;
+        Ok(statements)
     }
     
     // Keep the block function for inline blocks like { stmt; }
-    fn block(&mut self) -> Option<Statement> {
+    fn block(&mut self) -> Result<StatementKind, String> {
         // This function is meant to handle inline blocks that start with '{'
         // First consume the left brace
-        self.consume(&Token::LeftBrace).unwrap(); // This is called from statement which already matched LeftBrace
+        self.consume(&Token::LeftBrace)?; // This is called from statement which already matched LeftBrace
         
-        let statements = self.parse_block_contents();
-        Some(Statement::Block(statements))
+        let statements = self.parse_block_contents()?;
+        Ok(StatementKind::Block(statements))
     }
     
-    fn expression(&mut self) -> Expression {
+    fn expression(&mut self) -> Result<Expression, String> {
         self.assignment()
     }
     
-    fn assignment(&mut self) -> Expression {
-        let expr = self.equality();
+    fn assignment(&mut self) -> Result<Expression, String> {
+        let expr = self.binary(0)?;
         
         if self.match_token(&Token::Equals) {
-            // For now, we'll just return the expression as-is since we don't handle assignments yet
-            // This is just a placeholder to avoid compilation errors
-        }
-        
-        expr
-    }
-    
-    fn equality(&mut self) -> Expression {
-        let mut expr = self.comparison();
-        
-        while self.match_token(&Token::Equal) || 
-              self.match_token(&Token::NotEqual) {
-            let operator = match self.previous_token() {
-                Token::Equal => BinaryOp::Equal,
-                Token::NotEqual => BinaryOp::NotEqual,
-                _ => panic!("Unexpected operator"),
-            };
-            
-            let right = self.comparison();
-            expr = Expression::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
+            let value = Box::new(self.assignment()?);
+            return match expr {
+                Expression::ArrayAccess { array, index } => Ok(Expression::ArrayAssignment { array, index, value }),
+                Expression::StructFieldAccess { object, field } => {
+                    Ok(Expression::StructFieldAssignment { object, field, value })
+                }
+                _ => Err("Invalid assignment target".to_string()),
             };
         }
         
-        expr
-    }
-    
-    fn comparison(&mut self) -> Expression {
-        let mut expr = self.term();
-        
-        while self.match_token(&Token::Less) || 
-              self.match_token(&Token::LessEqual) || 
-              self.match_token(&Token::Greater) || 
-              self.match_token(&Token::GreaterEqual) {
+        if self.match_token(&Token::PlusEquals) ||
+           self.match_token(&Token::MinusEquals) ||
+           self.match_token(&Token::StarEquals) ||
+           self.match_token(&Token::SlashEquals) ||
+           self.match_token(&Token::PercentEquals) {
             let operator = match self.previous_token() {
-                Token::Less => BinaryOp::Less,
-                Token::LessEqual => BinaryOp::LessEqual,
-                Token::Greater => BinaryOp::Greater,
-                Token::GreaterEqual => BinaryOp::GreaterEqual,
+                Token::PlusEquals => BinaryOp::Add,
+                Token::MinusEquals => BinaryOp::Subtract,
+                Token::StarEquals => BinaryOp::Multiply,
+                Token::SlashEquals => BinaryOp::Divide,
+                Token::PercentEquals => BinaryOp::Modulo,
                 _ => panic!("Unexpected operator"),
             };
             
-            let right = self.term();
-            expr = Expression::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
+            let value = Box::new(self.assignment()?);
+            return match expr {
+                Expression::ArrayAccess { array, index } => {
+                    Ok(Expression::ArrayCompoundAssignment { array, index, operator, value })
+                }
+                _ => Err("Invalid assignment target".to_string()),
             };
         }
         
-        expr
+        Ok(expr)
     }
     
-    fn term(&mut self) -> Expression {
-        let mut expr = self.factor();
-        
-        while self.match_token(&Token::Plus) || 
-              self.match_token(&Token::Minus) {
-            let operator = match self.previous_token() {
-                Token::Plus => BinaryOp::Add,
-                Token::Minus => BinaryOp::Subtract,
-                _ => panic!("Unexpected operator"),
-            };
-            
-            let right = self.factor();
-            expr = Expression::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
+    // Parses operators binding at least as tightly as `min_precedence`, by
+    // precedence climbing over `infix_operator`
+    fn binary(&mut self, min_precedence: u8) -> Result<Expression, String> {
+        let start = self.current;
+        let mut expr = self.unary()?;
+        // The precedence of the last non-associative operator applied, which
+        // can't take another operator of the same precedence
+        let mut non_associative = None;
+        
+        while let Some((precedence, associativity, operator)) = self.peek().and_then(infix_operator) {
+            if precedence < min_precedence || non_associative == Some(precedence) {
+                break;
+            }
+            self.current += 1;
+            // A right-associative operator takes another of its own kind as its right operand
+            let right = match associativity {
+                Associativity::Right => self.binary(precedence)?,
+                Associativity::Left | Associativity::None => self.binary(precedence + 1)?,
             };
+            let comparison = matches!(operator, Infix::Binary(_));
+            expr = operator.apply(expr, right);
+            if let Associativity::None = associativity {
+                if comparison && self.peek().and_then(infix_operator).is_some_and(|(next, ..)| next == precedence) {
+                    expr = self.chained_comparison(start, expr, precedence)?;
+                }
+                non_associative = Some(precedence);
+            }
         }
         
-        expr
+        Ok(expr)
     }
     
-    fn factor(&mut self) -> Expression {
-        let mut expr = self.unary();
-        
-        while self.match_token(&Token::Star) || 
-              self.match_token(&Token::Slash) ||
-              self.match_token(&Token::Percent) {
-            let operator = match self.previous_token() {
-                Token::Star => BinaryOp::Multiply,
-                Token::Slash => BinaryOp::Divide,
-                Token::Percent => BinaryOp::Modulo,
-                _ => panic!("Unexpected operator"),
-            };
-            
-            let right = self.unary();
-            expr = Expression::Binary {
-                left: Box::new(expr),
-                operator,
-                right: Box::new(right),
-            };
+    // Records an error for `a < b < c`, which would compare the bool `a < b`
+    // with `c`, and parses the rest of the chain to carry on after it
+    fn chained_comparison(&mut self, start: usize, first: Expression, precedence: u8) -> Result<Expression, String> {
+        let Expression::Binary { left, operator, right } = first else {
+            return Ok(first);
+        };
+        let mut operands = vec![*left, *right];
+        let mut operators = vec![operator];
+        while let Some((_, _, Infix::Binary(operator))) =
+            self.peek().and_then(infix_operator).filter(|(next, ..)| *next == precedence)
+        {
+            self.current += 1;
+            operators.push(operator);
+            operands.push(self.binary(precedence + 1)?);
         }
         
-        expr
+        let comparisons: Option<Vec<String>> = operators
+            .iter()
+            .zip(operands.windows(2))
+            .map(|(operator, pair)| Some(format!("{} {} {}", source_text(&pair[0])?, operator, source_text(&pair[1])?)))
+            .collect();
+        let suggestion = comparisons.map_or_else(|| "a < b && b < c".to_string(), |comparisons| comparisons.join(" && "));
+        self.errors.push(Diagnostic::new(
+            format!("comparison operators cannot be chained; use `{}`", suggestion),
+            self.span_of(start, self.current),
+        ));
+        
+        let mut operands = operands.into_iter();
+        let first = operands.next().expect("a chain has operands");
+        Ok(operators.into_iter().zip(operands).fold(first, |left, (operator, right)| Expression::Binary {
+            left: Box::new(left),
+            operator,
+            right: Box::new(right),
+        }))
     }
     
-    fn unary(&mut self) -> Expression {
+    fn unary(&mut self) -> Result<Expression, String> {
+        if self.match_token(&Token::Minus) {
+            // The lexer only gives i64::MIN for `-9223372036854775808`, whose
+            // digits alone don't fit an i64, so the sign is folded into the
+            // literal unless something binding tighter than `-` follows
+            let postfix = matches!(
+                self.peek_ahead(1),
+                Some(Token::StarStar | Token::LeftParen | Token::LeftBracket | Token::Dot)
+            );
+            if self.peek() == Some(&Token::Number(i64::MIN)) && !postfix {
+                self.current += 1;
+                return Ok(Expression::Literal(Literal::Integer(i64::MIN)));
+            }
+            // Binds looser than `**`, so `-2 ** 2` is `-(2 ** 2)`
+            let operand = self.binary(NEGATION_PRECEDENCE)?;
+            return Ok(Expression::Unary {
+                operator: UnaryOp::Negate,
+                operand: Box::new(operand),
+            });
+        }
         self.call()
     }
     
-    fn call(&mut self) -> Expression {
-        let mut expr = self.primary();
+    fn call(&mut self) -> Result<Expression, String> {
+        let mut expr = self.primary()?;
         
         loop {
             if self.match_token(&Token::LeftParen) {
-                expr = self.finish_call(expr);
+                expr = self.finish_call(expr)?;
             } else if self.match_token(&Token::LeftBracket) {
                 // Handle array access: array[index]
-                let index = self.expression();
-                self.consume(&Token::RightBracket).expect("Expected ']'");
+                let index = self.with_struct_literals(true, Self::expression)?;
+                self.expect(&Token::RightBracket, "Expected ']'")?;
                 expr = Expression::ArrayAccess {
                     array: Box::new(expr),
                     index: Box::new(index),
                 };
             } else if self.match_token(&Token::Dot) {
                 // Handle field access: obj.field or method calls
-                if let Token::Identifier(field_name) = self.tokens[self.current].clone() {
+                if let Some(Token::Identifier(field_name)) = self.peek().cloned() {
                     self.current += 1;
                     
-                    // Check if this is a method call (with parentheses)
-                    if self.match_token(&Token::LeftParen) {
-                        // This would be a method call - for now just handle as field access
-                        // In a future implementation, this could be extended to method calls
-                    }
-                    
+                    // `math.sqrt(2.0)` calls whatever `math.sqrt` is, as the
+                    // next time round the loop parses
                     expr = Expression::StructFieldAccess {
                         object: Box::new(expr),
                         field: field_name,
                     };
+                } else if self.is_at_end() {
+                    return Err(end_of_file("field name after '.'"));
                 } else {
-                    panic!("Expected field name after '.'");
+                    return Err("Expected field name after '.'".to_string());
                 }
             } else {
                 break;
             }
         }
         
-        expr
+        Ok(expr)
     }
     
-    fn finish_call(&mut self, callee: Expression) -> Expression {
+    // A string literal with `${...}` in it, as a call to `format`. Errors in
+    // the embedded expressions are recorded with where they are inside the
+    // literal at `span`.
+    fn interpolated_string(&mut self, contents: &str, span: Span) -> Expression {
+        let parts = interpolation::split(contents);
+        // Only escaped `${`s
+        if let [StringPart::Text { text, offset }] = parts.as_slice() {
+            return Expression::Literal(Literal::String(self.unescape(text, *offset, contents, span)));
+        }
+
+        let mut format_string = String::new();
         let mut arguments = Vec::new();
-        
-        if !self.check(&Token::RightParen) {
-            loop {
-                arguments.push(self.expression());
-                
-                if !self.match_token(&Token::Comma) {
-                    break;
+        for part in parts {
+            match part {
+                StringPart::Text { text, offset } => {
+                    let text = self.unescape(&text, offset, contents, span);
+                    format_string.push_str(&text.replace('{', "{{").replace('}', "}}"));
+                }
+                StringPart::Expression { source, offset } => {
+                    format_string.push_str("{}");
+                    let locate = |inner| interpolation::locate(inner, span, contents, offset);
+                    arguments.push(self.interpolation(&source, locate));
                 }
             }
         }
+        Expression::FormatCall { name: "format".to_string(), format_string, arguments }
+    }
+
+    // The `text` at `offset` in the contents of the string literal at `span`
+    // with its escapes read. An invalid escape is an error at the escape,
+    // and the text is kept as written.
+    fn unescape(&mut self, text: &str, offset: usize, contents: &str, span: Span) -> String {
+        escape::unescape(text).unwrap_or_else(|error| {
+            let escape = Span { start: 0, end: error.range.len(), line: 1, column: 1 };
+            let at = interpolation::locate(escape, span, contents, offset + error.range.start);
+            self.errors.push(Diagnostic::new(error.to_string(), at).with_code("invalid_escape"));
+            text.to_string()
+        })
+    }
+
+    // Parses the source of one interpolation, which must be a single
+    // expression
+    fn interpolation(&mut self, source: &str, locate: impl Fn(Span) -> Span) -> Expression {
+        let lexer = Lexer::new(source);
+        for error in lexer.errors() {
+            self.errors.push(Diagnostic::from(LexError { span: locate(error.span), ..error.clone() }));
+        }
+        let (tokens, spans) = lexer.into_tokens();
+        let spans = match self.spans.is_empty() {
+            true => Vec::new(),
+            false => spans.into_iter().map(&locate).collect(),
+        };
+        let mut parser = Parser::with_spans(tokens, spans);
+        // The spans are located in the enclosing source, so its text goes too
+        parser.source = self.source.clone();
+
+        let result = parser.with_struct_literals(true, Self::expression).and_then(|expr| match parser.peek() {
+            Some(token) => Err(format!("Expected '}}' to end the interpolation, got {:?}", token)),
+            None => Ok(expr),
+        });
+        self.errors.append(&mut parser.errors);
+        match result {
+            Ok(expr) => expr,
+            Err(message) => {
+                // An empty interpolation has no token to point at
+                let span = match parser.tokens.is_empty() {
+                    true => locate(Span { line: 1, column: 1, ..Span::default() }),
+                    false => parser.error_span(),
+                };
+                self.errors.push(Diagnostic::new(message, span));
+                // Stands in for it so the rest of the literal still parses
+                Expression::Literal(Literal::String(String::new()))
+            }
+        }
+    }
+
+    fn finish_call(&mut self, callee: Expression) -> Result<Expression, String> {
+        let first = self.current;
+        let mut arguments = self.comma_separated(&Token::RightParen, Self::expression)?;
         
-        self.consume(&Token::RightParen).expect("Expected ')'");
-        
+        // Plain names are called by name, so builtins can be resolved
         if let Expression::Variable(name) = callee {
-            // Check if this is a format string call (print/puts with {} formatting)
-            if (name == "puts" || name == "print") && !arguments.is_empty() {
+            if name == "dbg" && arguments.len() == 1 {
+                let expression = arguments.remove(0);
+                // Up to but not including the closing bracket
+                let span = self.span_of(first, self.current - 1);
+                let source = match self.source.as_deref().and_then(|source| source.get(span.start..span.end)) {
+                    Some(text) if !self.spans.is_empty() => text.to_string(),
+                    _ => source_text(&expression).unwrap_or_default(),
+                };
+                return Ok(Expression::Debug { expression: Box::new(expression), source, line: span.line });
+            }
+
+            // Check if this is a format string call: format(...) always, and
+            // print/puts/panic when they have placeholders or extra arguments
+            if matches!(name.as_str(), "puts" | "print" | "panic" | "format") && !arguments.is_empty() {
                 // For format string calls like puts("the value of x is {}", x)
                 // We need to check if the first argument contains {}
                 if let Expression::Literal(Literal::String(ref format_str)) = &arguments[0] {
-                    if format_str.contains("{}") {
-                        return Expression::FormatCall {
+                    if name == "format" || format_str.contains("{}") || arguments.len() > 1 {
+                        return Ok(Expression::FormatCall {
                             name,
                             format_string: format_str.clone(),
                             arguments: arguments[1..].to_vec(),
-                        };
+                        });
                     }
                 }
             }
             
-            Expression::Call {
+            Ok(Expression::Call {
                 name,
                 arguments,
-            }
+            })
         } else {
-            panic!("Only variable names can be called (got {:?})", callee);
+            Ok(Expression::IndirectCall {
+                callee: Box::new(callee),
+                arguments,
+            })
         }
     }
     
-    fn primary(&mut self) -> Expression {
-        if self.current >= self.tokens.len() {
-            panic!("Unexpected end of input");
-        }
+    fn primary(&mut self) -> Result<Expression, String> {
+        let Some(token) = self.peek().cloned() else {
+            return Err(end_of_file("expression"));
+        };
 
         // Handle array literals: [expr, expr, ...]
         if self.match_token(&Token::LeftBracket) {
-            let mut elements = Vec::new();
-            
-            if !self.check(&Token::RightBracket) {
-                loop {
-                    elements.push(self.expression());
-                    
-                    if !self.match_token(&Token::Comma) {
-                        break;
-                    }
-                    
-                    if self.check(&Token::RightBracket) {
-                        break;
-                    }
-                }
-            }
-            
-            self.consume(&Token::RightBracket).expect("Expected ']'");
-            return Expression::ArrayLiteral(elements);
+            let elements = self.comma_separated(&Token::RightBracket, Self::expression)?;
+            return Ok(Expression::ArrayLiteral(elements));
         }
         
         // Handle grouped expressions: (expr)
         if self.match_token(&Token::LeftParen) {
-            let expr = self.expression();
-            self.consume(&Token::RightParen).expect("Expected ')'");
-            return expr;
+            let expr = self.with_struct_literals(true, Self::expression)?;
+            self.expect(&Token::RightParen, "Expected ')'")?;
+            return Ok(expr);
         }
         
         // Handle literals and identifiers
-        if let Token::Number(n) = &self.tokens[self.current] {
+        if let Token::Number(n) = token {
+            if n == i64::MIN {
+                return Err("integer literal out of range for i64: 9223372036854775808 only fits negated".to_string());
+            }
             self.current += 1;
-            return Expression::Literal(Literal::Integer(*n));
+            return Ok(Expression::Literal(Literal::Integer(n)));
         }
         
-        if let Token::String(s) = &self.tokens[self.current] {
+        if let Token::Float(x) = token {
             self.current += 1;
-            return Expression::Literal(Literal::String(s.clone()));
+            return Ok(Expression::Literal(Literal::Float(x)));
+        }
+        
+        if let Token::String(s) = token {
+            let span = self.error_span();
+            self.current += 1;
+            if s.contains("${") {
+                return Ok(self.interpolated_string(&s, span));
+            }
+            return Ok(Expression::Literal(Literal::String(self.unescape(&s, 0, &s, span))));
         }
         
         // Handle boolean literals
-        if let Token::Identifier(name) = &self.tokens[self.current] {
-            if name == "true" {
-                self.current += 1;
-                return Expression::Literal(Literal::Boolean(true));
-            } else if name == "false" {
-                self.current += 1;
-                return Expression::Literal(Literal::Boolean(false));
-            } else {
-                // Regular identifier - check for special syntax before consuming it
-                let identifier_name = name.clone();
-                
-                // Check if this is a struct initialization: Name { field: value }
-                // We look ahead to see if next token is LeftBrace
-                if self.current + 1 < self.tokens.len() && self.tokens[self.current + 1] == Token::LeftBrace {
-                    self.current += 1;  // Consume the identifier
-                    return self.struct_initialization(identifier_name);
-                }
-                
-                // Check if this is an enum variant: EnumName::Variant(...) or EnumName::Variant
-                // We look ahead to see if next token is DoubleColon
-                if self.current + 1 < self.tokens.len() && self.tokens[self.current + 1] == Token::DoubleColon {
-                    self.current += 1;  // Consume the identifier
-                    return self.enum_variant_creation(identifier_name);
-                }
-                
-                // Regular variable usage
-                self.current += 1;
-                return Expression::Variable(identifier_name);
+        if self.match_token(&Token::True) {
+            return Ok(Expression::Literal(Literal::Boolean(true)));
+        }
+        if self.match_token(&Token::False) {
+            return Ok(Expression::Literal(Literal::Boolean(false)));
+        }
+
+        if let Token::Identifier(identifier_name) = token {
+            // Regular identifier - check for special syntax before consuming it
+            
+            // Check if this is a struct initialization: Name { field: value }
+            // We look ahead to see if next token is LeftBrace
+            if !self.no_struct_literal && self.peek_ahead(1) == Some(&Token::LeftBrace) {
+                self.current += 1;  // Consume the identifier
+                return self.struct_initialization(identifier_name);
             }
+            
+            // Check if this is an enum variant: EnumName::Variant(...) or EnumName::Variant
+            // We look ahead to see if next token is DoubleColon
+            if self.peek_ahead(1) == Some(&Token::DoubleColon) {
+                self.current += 1;  // Consume the identifier
+                return self.enum_variant_creation(identifier_name);
+            }
+            
+            // Regular variable usage. `_` only ever discards a value.
+            if identifier_name == "_" && !self.underscore_reads {
+                self.errors.push(Diagnostic::new("cannot read the value of `_`", self.error_span()));
+            }
+            self.current += 1;
+            return Ok(Expression::Variable(identifier_name));
+        }
+        
+        if let Token::Reserved(_) = token {
+            return Err(keyword_as_name(&token));
         }
         
         // If we reach here, we didn't match any known expression form
-        panic!("Expected expression, got {:?}", self.tokens[self.current])
+        Err(format!("Expected expression, got {:?}", token))
     }
     
-    fn struct_initialization(&mut self, struct_name: String) -> Expression {
+    fn struct_initialization(&mut self, struct_name: String) -> Result<Expression, String> {
         // Expect opening brace
-        self.consume(&Token::LeftBrace).expect("Expected '{' for struct initialization");
-        
-        let mut fields = Vec::new();
-        
-        if !self.check(&Token::RightBrace) {
-            loop {
-                let field_name = self.consume_identifier().expect("Expected field name in struct initialization");
-                self.consume(&Token::Colon).expect("Expected ':' in struct initialization");
-                let field_value = self.expression();
-                
-                fields.push((field_name, field_value));
-                
-                if !self.match_token(&Token::Comma) {
-                    break;
-                }
-                
-                if self.check(&Token::RightBrace) {
-                    break;
-                }
-            }
-        }
+        self.expect(&Token::LeftBrace, "Expected '{' for struct initialization")?;
         
-        self.consume(&Token::RightBrace).expect("Expected '}' after struct initialization");
+        let fields = self.comma_separated(&Token::RightBrace, |parser| {
+            let field_name = parser.consume_identifier("Expected field name in struct initialization")?;
+            parser.expect(&Token::Colon, "Expected ':' in struct initialization")?;
+            Ok((field_name, parser.expression()?))
+        })?;
         
-        Expression::StructInitialization {
+        Ok(Expression::StructInitialization {
             name: struct_name,
             fields,
-        }
+        })
     }
     
-    fn enum_variant_creation(&mut self, enum_name: String) -> Expression {
+    fn enum_variant_creation(&mut self, enum_name: String) -> Result<Expression, String> {
         // Parse EnumName::Variant format
-        self.consume(&Token::DoubleColon).expect("Expected '::' for enum variant");
-        let variant_name = self.consume_identifier().expect("Expected variant name");
+        self.expect(&Token::DoubleColon, "Expected '::' for enum variant")?;
+        let variant_name = self.consume_identifier("Expected variant name")?;
         
         // If followed by parentheses, it has values; otherwise it's a unit variant
         let values = if self.match_token(&Token::LeftParen) {
-            let mut args = Vec::new();
-            
-            if !self.check(&Token::RightParen) {
-                loop {
-                    args.push(self.expression());
-                    
-                    if !self.match_token(&Token::Comma) {
-                        break;
-                    }
-                    
-                    if self.check(&Token::RightParen) {
-                        break;
-                    }
-                }
-            }
-            
-            self.consume(&Token::RightParen).expect("Expected ')'");
-            args
+            self.comma_separated(&Token::RightParen, Self::expression)?
         } else {
             Vec::new()  // Unit variant with no values
         };
         
-        Expression::EnumVariantCreation {
+        Ok(Expression::EnumVariantCreation {
             enum_name,
             variant_name,
             values,
-        }
+        })
     }
     
+    // Parses `item`s separated by commas, with an optional trailing comma, up
+    // to and including `close`. When an item fails to parse or a comma is
+    // missing, the error is recorded and the rest of the list skipped, so
+    // one mistake in a list is reported once.
+    fn comma_separated<T>(
+        &mut self,
+        close: &Token,
+        mut item: impl FnMut(&mut Self) -> Result<T, String>,
+    ) -> Result<Vec<T>, String> {
+        let mut items = Vec::new();
+        // The error that ended the list early, and the token it was found at
+        let mut error = None;
+        // Inside brackets a struct initializer can't be mistaken for a block
+        let outer = std::mem::replace(&mut self.no_struct_literal, false);
+        while !self.check(close) {
+            match item(self) {
+                Ok(value) => items.push(value),
+                Err(message) => {
+                    error = Some((Diagnostic::new(message, self.error_span()), self.current));
+                    break;
+                }
+            }
+            if self.match_token(&Token::Comma) {
+                continue;
+            }
+            if !self.check(close) {
+                let expected = format!("`,` or `{}`", delimiter(close));
+                let message = match self.peek() {
+                    Some(token) => format!("Expected {}, got {:?}", expected, token),
+                    None => end_of_file(&expected),
+                };
+                error = Some((Diagnostic::new(message, self.error_span()), self.current));
+                break;
+            }
+        }
+
+        self.no_struct_literal = outer;
+
+        if let Some((error, at)) = error {
+            self.skip_to(close);
+            // Without the end of the list to pick up from, the error is the
+            // statement's to recover from
+            if !self.check(close) {
+                return Err(error.message);
+            }
+            self.errors.push(error.recovered(self.span_of(at, self.current)));
+        }
+        self.consume(close)?;
+        Ok(items)
+    }
+
+    // Moves up to `close`, skipping anything nested in brackets on the way.
+    // Stops early at the end of the statement, or at a closing bracket that
+    // belongs to something outside the list.
+    fn skip_to(&mut self, close: &Token) {
+        let mut depth = 0usize;
+        while let Some(token) = self.peek() {
+            if depth == 0 && (token == close || *token == Token::Semi) {
+                return;
+            }
+            match token {
+                Token::LeftParen | Token::LeftBracket | Token::LeftBrace => depth += 1,
+                Token::RightParen | Token::RightBracket | Token::RightBrace => {
+                    if depth == 0 {
+                        return;
+                    }
+                    depth -= 1;
+                }
+                _ => {}
+            }
+            self.current += 1;
+        }
+    }
+
     fn consume(&mut self, token: &Token) -> Result<(), String> {
         if self.check(token) {
             self.current += 1;
             Ok(())
         } else {
-            let current_token_str = if self.is_at_end() {
-                "EOF".to_string()
-            } else {
-                format!("{:?}", self.current_token())
-            };
-            Err(format!("Expected {:?}, got {}", token, current_token_str))
+            match self.peek() {
+                Some(found) => Err(format!("Expected {:?}, got {:?}", token, found)),
+                None => Err(end_of_file(&format!("{:?}", token))),
+            }
         }
     }
     
-    fn consume_identifier(&mut self) -> Result<String, String> {
-        if self.is_at_end() {
-            return Err("Expected identifier, got EOF".to_string());
-        }
-        
-        if let Token::Identifier(name) = &self.tokens[self.current] {
-            self.current += 1;
-            Ok(name.clone())
-        } else {
-            Err(format!("Expected identifier, got {:?}", self.current_token()))
+    // Like `consume`, with `message` saying what the token was for
+    fn expect(&mut self, token: &Token, message: &str) -> Result<(), String> {
+        self.consume(token).map_err(|found| expected(message, &found))
+    }
+    
+    // A keyword where a name should be is reported as that, whatever kind of
+    // name the caller expected; anything else as `message`
+    fn consume_identifier(&mut self, message: &str) -> Result<String, String> {
+        match self.peek() {
+            Some(Token::Identifier(name)) => {
+                let name = name.clone();
+                self.current += 1;
+                Ok(name)
+            }
+            Some(token) if token.keyword().is_some() => Err(keyword_as_name(token)),
+            Some(other) => Err(expected(message, &format!("Expected identifier, got {:?}", other))),
+            None => Err(expected(message, &end_of_file("identifier"))),
         }
     }
     
@@ -626,20 +1038,7 @@ impl Parser {
     }
     
     fn check(&self, token: &Token) -> bool {
-        if self.is_at_end() {
-            false
-        } else {
-            matches!(&self.tokens[self.current], t if std::mem::discriminant(t) == std::mem::discriminant(token))
-        }
-    }
-    
-    fn advance_if_identifier(&mut self) -> Option<String> {
-        if let Token::Identifier(name) = &self.tokens[self.current] {
-            self.current += 1;
-            Some(name.clone())
-        } else {
-            None
-        }
+        self.peek().is_some_and(|t| std::mem::discriminant(t) == std::mem::discriminant(token))
     }
     
     fn previous_token(&self) -> &Token {
@@ -649,89 +1048,721 @@ impl Parser {
         &self.tokens[self.current - 1]
     }
     
-    fn current_token(&self) -> &Token {
-        &self.tokens[self.current]
+    // The token the parser is looking at, or `None` at the end of the file
+    fn peek(&self) -> Option<&Token> {
+        self.peek_ahead(0)
+    }
+
+    // The token `n` past the one the parser is looking at
+    fn peek_ahead(&self, n: usize) -> Option<&Token> {
+        self.tokens.get(self.current + n)
     }
     
     fn is_at_end(&self) -> bool {
         self.current >= self.tokens.len()
     }
     
-    fn if_statement(&mut self) -> Statement {
+    // An expression followed by a block, like an `if` condition. A struct
+    // initializer can only appear in it inside brackets, so that `if ready {`
+    // starts the block.
+    fn header_expression(&mut self) -> Result<Expression, String> {
+        self.with_struct_literals(false, Self::expression)
+    }
+
+    fn with_struct_literals<T>(&mut self, allowed: bool, parse: impl FnOnce(&mut Self) -> T) -> T {
+        let outer = std::mem::replace(&mut self.no_struct_literal, !allowed);
+        let result = parse(self);
+        self.no_struct_literal = outer;
+        result
+    }
+
+    fn if_statement(&mut self) -> Result<StatementKind, String> {
         // Parse the condition
-        let condition = self.expression();
+        let condition = self.header_expression()?;
         
         // Expect the opening brace for the then branch
-        self.consume(&Token::LeftBrace).expect("Expected '{' after if condition");
-        let then_branch = self.parse_block_contents();
+        self.expect(&Token::LeftBrace, "Expected '{' after if condition")?;
+        let then_branch = self.parse_block_contents()?;
         
         // Check for elif branches
         let mut elif_branches = Vec::new();
         while self.match_token(&Token::Elif) {
-            let elif_condition = self.expression();
-            self.consume(&Token::LeftBrace).expect("Expected '{' after elif condition");
-            let elif_body = self.parse_block_contents();
+            let elif_condition = self.header_expression()?;
+            self.expect(&Token::LeftBrace, "Expected '{' after elif condition")?;
+            let elif_body = self.parse_block_contents()?;
             elif_branches.push((elif_condition, elif_body));
         }
         
         // Check for else branch
         let else_branch = if self.match_token(&Token::Else) {
-            self.consume(&Token::LeftBrace).expect("Expected '{' after else");
-            Some(self.parse_block_contents())
+            self.expect(&Token::LeftBrace, "Expected '{' after else")?;
+            Some(self.parse_block_contents()?)
         } else {
             None
         };
         
-        Statement::If {
+        Ok(StatementKind::If {
             condition,
             then_branch,
             elif_branches,
             else_branch,
-        }
+        })
     }
     
-    fn while_statement(&mut self) -> Statement {
-        let condition = self.expression();
-        self.consume(&Token::LeftBrace).expect("Expected '{' after while condition");
-        let body = self.parse_block_contents();
+    fn while_statement(&mut self) -> Result<StatementKind, String> {
+        let condition = self.header_expression()?;
+        self.expect(&Token::LeftBrace, "Expected '{' after while condition")?;
+        let body = self.parse_block_contents()?;
         
-        Statement::While {
+        Ok(StatementKind::While {
             condition,
             body,
-        }
+        })
     }
     
-    fn for_statement(&mut self) -> Statement {
-        let variable = self.consume_identifier().expect("Expected variable name in for loop");
+    fn loop_body(&mut self) -> Result<Vec<Statement>, String> {
+        self.expect(&Token::LeftBrace, "Expected '{' after loop")?;
+        self.parse_block_contents()
+    }
+    
+    // `try { ... } catch err { ... }`
+    fn try_statement(&mut self) -> Result<StatementKind, String> {
+        self.expect(&Token::LeftBrace, "Expected '{' after try")?;
+        let body = self.parse_block_contents()?;
+        self.expect(&Token::Catch, "Expected 'catch' after try block")?;
+        let error_binding = self.consume_identifier("Expected a name for the error after catch")?;
+        let binding_span = self.spans[self.current - 1];
+        self.expect(&Token::LeftBrace, "Expected '{' after catch")?;
+        let handler = self.parse_block_contents()?;
+        
+        Ok(StatementKind::TryCatch {
+            body,
+            error_binding,
+            binding_span,
+            handler,
+        })
+    }
+    
+    fn for_statement(&mut self) -> Result<StatementKind, String> {
+        let variable = self.consume_identifier("Expected variable name in for loop")?;
         
         // Expect 'in' token
-        self.consume(&Token::In).expect("Expected 'in' in for loop");
+        self.expect(&Token::In, "Expected 'in' in for loop")?;
         
-        let iterable = self.expression();
-        self.consume(&Token::LeftBrace).expect("Expected '{' after for loop");
-        let body = self.parse_block_contents();
+        let iterable = self.header_expression()?;
+        self.expect(&Token::LeftBrace, "Expected '{' after for loop")?;
+        let body = self.parse_block_contents()?;
         
-        Statement::For {
+        Ok(StatementKind::For {
             variable,
             iterable,
             body,
+        })
+    }
+}
+
+// The error for running out of tokens while looking for `expected`
+fn keyword_as_name(token: &Token) -> String {
+    let keyword = token.keyword().expect("only keywords are reported as keywords");
+    format!("`{}` is a reserved keyword and cannot be used as an identifier", keyword)
+}
+
+fn end_of_file(expected: &str) -> String {
+    format!("unexpected end of file, expected {}", expected)
+}
+
+// How a closing bracket is written, for error messages
+fn delimiter(token: &Token) -> &'static str {
+    match token {
+        Token::RightParen => ")",
+        Token::RightBracket => "]",
+        Token::RightBrace => "}",
+        _ => "end of list",
+    }
+}
+
+// `message`, saying what the parser was looking for, with what it found
+fn expected(message: &str, found: &str) -> String {
+    format!("{}: {:?}", message, found)
+}
+
+// How the operands of an infix operator group: `a - b - c` is `(a - b) - c`,
+// `a ** b ** c` is `a ** (b ** c)`, and `a..b..c` and `a < b < c` are errors
+#[derive(Clone, Copy)]
+enum Associativity {
+    Left,
+    Right,
+    None,
+}
+
+// What an infix operator builds from its operands
+enum Infix {
+    Binary(BinaryOp),
+    Logical(LogicalOp),
+    Range { inclusive: bool },
+}
+
+impl Infix {
+    fn apply(self, left: Expression, right: Expression) -> Expression {
+        let (left, right) = (Box::new(left), Box::new(right));
+        match self {
+            Infix::Binary(operator) => Expression::Binary { left, operator, right },
+            Infix::Logical(operator) => Expression::Logical { left, operator, right },
+            Infix::Range { inclusive } => Expression::Range { start: left, end: right, inclusive },
         }
     }
 }
 
+// How `expr` could be written, for suggesting rewrites in error messages;
+// `None` for expressions too involved to be worth spelling out
+fn source_text(expr: &Expression) -> Option<String> {
+    Some(match expr {
+        Expression::Literal(Literal::Integer(n)) => n.to_string(),
+        Expression::Literal(Literal::Float(f)) => format!("{:?}", f),
+        Expression::Literal(Literal::String(s)) => format!("{:?}", s),
+        Expression::Literal(Literal::Boolean(b)) => b.to_string(),
+        Expression::Variable(name) => name.clone(),
+        Expression::Binary { left, operator, right } => {
+            // Brackets keep nested operations grouped whatever their precedence
+            let operand = |expr: &Expression| match expr {
+                Expression::Binary { .. } => source_text(expr).map(|text| format!("({})", text)),
+                _ => source_text(expr),
+            };
+            format!("{} {} {}", operand(left)?, operator, operand(right)?)
+        }
+        Expression::Unary { operand, .. } => format!("-{}", source_text(operand)?),
+        Expression::Call { name, arguments } => {
+            let arguments: Option<Vec<String>> = arguments.iter().map(source_text).collect();
+            format!("{}({})", name, arguments?.join(", "))
+        }
+        Expression::ArrayAccess { array, index } => format!("{}[{}]", source_text(array)?, source_text(index)?),
+        _ => return None,
+    })
+}
+
+// Unary minus binds tighter than `*` but looser than `**`
+const NEGATION_PRECEDENCE: u8 = 8;
+
+// The precedence (higher binds tighter) and associativity of every infix
+// operator, loosest first
+fn infix_operator(token: &Token) -> Option<(u8, Associativity, Infix)> {
+    Some(match token {
+        Token::DotDot => (1, Associativity::None, Infix::Range { inclusive: false }),
+        Token::DotDotEquals => (1, Associativity::None, Infix::Range { inclusive: true }),
+        Token::Or => (2, Associativity::Left, Infix::Logical(LogicalOp::Or)),
+        Token::And => (3, Associativity::Left, Infix::Logical(LogicalOp::And)),
+        Token::Equal => (4, Associativity::None, Infix::Binary(BinaryOp::Equal)),
+        Token::NotEqual => (4, Associativity::None, Infix::Binary(BinaryOp::NotEqual)),
+        Token::Less => (5, Associativity::None, Infix::Binary(BinaryOp::Less)),
+        Token::LessEqual => (5, Associativity::None, Infix::Binary(BinaryOp::LessEqual)),
+        Token::Greater => (5, Associativity::None, Infix::Binary(BinaryOp::Greater)),
+        Token::GreaterEqual => (5, Associativity::None, Infix::Binary(BinaryOp::GreaterEqual)),
+        Token::Plus => (6, Associativity::Left, Infix::Binary(BinaryOp::Add)),
+        Token::Minus => (6, Associativity::Left, Infix::Binary(BinaryOp::Subtract)),
+        Token::Star => (7, Associativity::Left, Infix::Binary(BinaryOp::Multiply)),
+        Token::Slash => (7, Associativity::Left, Infix::Binary(BinaryOp::Divide)),
+        Token::Percent => (7, Associativity::Left, Infix::Binary(BinaryOp::Modulo)),
+        Token::StarStar => (9, Associativity::Right, Infix::Binary(BinaryOp::Power)),
+        _ => return None,
+    })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::lexer::Lexer;
 
     #[test]
     fn test_parse_simple_function() {
-        let source = r#"fn main() { }"#.to_string();
+        let source = r#"fn main() { }"#;
         let lexer = Lexer::new(source);
-        let tokens = lexer.tokenize().to_vec();
-        let mut parser = Parser::new(tokens);
+        let mut parser = Parser::from_lexer(lexer);
         let ast = parser.parse();
         
         assert!(!ast.is_empty());
         // Additional assertions can be added here
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_parse_index_assignment() {
+        let ast = Parser::from_lexer(Lexer::new("a[0] = b[1];")).parse();
+        match &ast[0].kind {
+            StatementKind::Expression(Expression::ArrayAssignment { value, .. }) => {
+                assert!(matches!(value.as_ref(), Expression::ArrayAccess { .. }));
+            }
+            other => panic!("Expected an index assignment, got {:?}", other),
+        }
+
+        let mut parser = Parser::from_lexer(Lexer::new("a = 1;"));
+        assert_eq!(parser.try_parse().unwrap_err(), "Invalid assignment target");
+    }
+
+    #[test]
+    fn test_parse_ranges() {
+        let ast = Parser::from_lexer(Lexer::new("for i in 0..n + 1 { } let r = 1..=3;")).parse();
+        match &ast[0].kind {
+            StatementKind::For { iterable: Expression::Range { end, inclusive: false, .. }, .. } => {
+                assert!(matches!(end.as_ref(), Expression::Binary { operator: BinaryOp::Add, .. }));
+            }
+            other => panic!("Expected a loop over a range, got {:?}", other),
+        }
+        assert!(matches!(
+            &ast[1].kind,
+            StatementKind::VariableDeclaration { value: Expression::Range { inclusive: true, .. }, .. }
+        ));
+
+        // A slice is an index that is a range
+        let ast = Parser::from_lexer(Lexer::new("xs[1..3];")).parse();
+        match &ast[0].kind {
+            StatementKind::Expression(Expression::ArrayAccess { index, .. }) => {
+                assert!(matches!(index.as_ref(), Expression::Range { .. }));
+            }
+            other => panic!("Expected a slice, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_calls_of_expressions() {
+        let ast = Parser::from_lexer(Lexer::new("make()(1); handlers[0](); f(2);")).parse();
+        match &ast[0].kind {
+            StatementKind::Expression(Expression::IndirectCall { callee, arguments }) => {
+                assert!(matches!(callee.as_ref(), Expression::Call { name, .. } if name == "make"));
+                assert_eq!(arguments.len(), 1);
+            }
+            other => panic!("Expected a call of a call, got {:?}", other),
+        }
+        match &ast[1].kind {
+            StatementKind::Expression(Expression::IndirectCall { callee, .. }) => {
+                assert!(matches!(callee.as_ref(), Expression::ArrayAccess { .. }));
+            }
+            other => panic!("Expected a call of an element, got {:?}", other),
+        }
+        // Names are still called by name
+        assert!(matches!(&ast[2].kind, StatementKind::Expression(Expression::Call { .. })));
+    }
+
+    #[test]
+    fn test_parse_boolean_literals() {
+        let ast = Parser::from_lexer(Lexer::new("let done = true && false;")).parse();
+        match &ast[0].kind {
+            StatementKind::VariableDeclaration { value: Expression::Logical { left, .. }, .. } => {
+                assert!(matches!(left.as_ref(), Expression::Literal(Literal::Boolean(true))));
+            }
+            other => panic!("Expected a logical expression, got {:?}", other),
+        }
+
+        for source in ["let true = 1;", "fn false() { }", "fn f(true) { }"] {
+            let error = Parser::from_lexer(Lexer::new(source)).try_parse().unwrap_err();
+            assert!(error.ends_with("is a reserved keyword and cannot be used as an identifier"), "{}: {}", source, error);
+        }
+    }
+
+    #[test]
+    fn test_lists_allow_a_trailing_comma() {
+        let source = "fn pair(a, b,) { return [a, b,]; } \
+                      pair(1, 2,); \
+                      let p = Point { x: 1, y: 2, }; \
+                      let s = Shape::Circle(1,);";
+        let ast = Parser::from_lexer(Lexer::new(source)).try_parse().unwrap();
+        match &ast[0].kind {
+            StatementKind::Function(func) => {
+                assert_eq!(func.parameters.len(), 2);
+                assert!(matches!(&func.body[0].kind, StatementKind::Return(Some(Expression::ArrayLiteral(elements))) if elements.len() == 2));
+            }
+            other => panic!("Expected a function, got {:?}", other),
+        }
+        assert!(matches!(&ast[1].kind, StatementKind::Expression(Expression::Call { arguments, .. }) if arguments.len() == 2));
+        assert!(matches!(
+            &ast[2].kind,
+            StatementKind::VariableDeclaration { value: Expression::StructInitialization { fields, .. }, .. } if fields.len() == 2
+        ));
+        assert!(matches!(
+            &ast[3].kind,
+            StatementKind::VariableDeclaration { value: Expression::EnumVariantCreation { values, .. }, .. } if values.len() == 1
+        ));
+    }
+
+    #[test]
+    fn test_missing_comma_names_what_was_found() {
+        let mut parser = Parser::from_lexer(Lexer::new("f(1 2);"));
+        assert_eq!(parser.try_parse().unwrap_err(), "Expected `,` or `)`, got Number(2)");
+        let mut parser = Parser::from_lexer(Lexer::new("let a = [1, 2 3];"));
+        assert_eq!(parser.try_parse().unwrap_err(), "Expected `,` or `]`, got Number(3)");
+    }
+
+    // The expression statement `source` as nested prefix operations
+    fn grouping(source: &str) -> String {
+        fn render(expr: &Expression) -> String {
+            match expr {
+                Expression::Literal(Literal::Integer(n)) => n.to_string(),
+                Expression::Literal(Literal::Boolean(b)) => b.to_string(),
+                Expression::Variable(name) => name.clone(),
+                Expression::Binary { left, operator, right } => format!("({:?} {} {})", operator, render(left), render(right)),
+                Expression::Logical { left, operator, right } => format!("({:?} {} {})", operator, render(left), render(right)),
+                Expression::Unary { operator, operand } => format!("({:?} {})", operator, render(operand)),
+                Expression::Range { start, end, inclusive } => {
+                    format!("({} {} {})", if *inclusive { "RangeInclusive" } else { "Range" }, render(start), render(end))
+                }
+                other => panic!("No rendering for {:?}", other),
+            }
+        }
+        match &Parser::from_lexer(Lexer::new(source)).parse()[0].kind {
+            StatementKind::Expression(expr) => render(expr),
+            other => panic!("Expected an expression, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        assert_eq!(grouping("1 + 2 * 3 - 4;"), "(Subtract (Add 1 (Multiply 2 3)) 4)");
+        assert_eq!(grouping("a - b - c;"), "(Subtract (Subtract a b) c)");
+        assert_eq!(grouping("a / b % c * d;"), "(Multiply (Modulo (Divide a b) c) d)");
+        assert_eq!(
+            grouping("a || b && c == d < e + f * g;"),
+            "(Or a (And b (Equal c (Less d (Add e (Multiply f g))))))"
+        );
+        assert_eq!(grouping("a <= b != c >= d;"), "(NotEqual (LessEqual a b) (GreaterEqual c d))");
+        assert_eq!(grouping("a && b || c && d;"), "(Or (And a b) (And c d))");
+        assert_eq!(grouping("0..n + 1;"), "(Range 0 (Add n 1))");
+        assert_eq!(grouping("a || b..=c && d;"), "(RangeInclusive (Or a b) (And c d))");
+        assert_eq!(grouping("(1 + 2) * 3;"), "(Multiply (Add 1 2) 3)");
+    }
+
+    #[test]
+    fn test_chained_comparisons_are_errors() {
+        let error = |source: &str| Parser::from_lexer(Lexer::new(source)).try_parse().unwrap_err();
+        assert_eq!(
+            error("if 0 < x < 10 { }"),
+            "comparison operators cannot be chained; use `0 < x && x < 10`"
+        );
+        assert_eq!(
+            error("a <= len(b) + 1 <= c[0] < 5;"),
+            "comparison operators cannot be chained; use `a <= len(b) + 1 && len(b) + 1 <= c[0] && c[0] < 5`"
+        );
+        assert_eq!(error("a == b != c;"), "comparison operators cannot be chained; use `a == b && b != c`");
+        assert_eq!(error("a < [1] < c;"), "comparison operators cannot be chained; use `a < b && b < c`");
+
+        // Brackets, or comparisons at different levels, are fine
+        assert_eq!(grouping("(a < b) == flag;"), "(Equal (Less a b) flag)");
+        assert_eq!(grouping("(0 < x) < 10;"), "(Less (Less 0 x) 10)");
+        assert_eq!(grouping("a < b == c > d;"), "(Equal (Less a b) (Greater c d))");
+    }
+
+    #[test]
+    fn test_underscore_only_discards() {
+        let parse = |source: &str| Parser::from_lexer(Lexer::new(source)).try_parse();
+        assert_eq!(parse("let y = _ + 1;").unwrap_err(), "cannot read the value of `_`");
+        assert_eq!(parse("fn f(_) { return _; }").unwrap_err(), "cannot read the value of `_`");
+        assert_eq!(parse("fn f(a, b, a) { }").unwrap_err(), "duplicate parameter `a`");
+
+        // `_` can be bound any number of times
+        let program = parse("fn f(_, x, _) { let _ = x; }").unwrap();
+        let StatementKind::Function(func) = &program[0].kind else {
+            panic!("expected a function, got {:?}", program[0].kind);
+        };
+        assert_eq!(func.parameters.len(), 3);
+        assert!(parse("for _ in 0..3 { }").is_ok());
+
+        let reads = Parser::from_lexer(Lexer::new("_ + 1;")).allow_underscore_reads().try_parse();
+        assert!(reads.is_ok());
+    }
+
+    #[test]
+    fn test_duplicate_parameters_point_at_both() {
+        let result = crate::parse_with_diagnostics("fn f(x, y,
+     x) { }");
+        let error = &result.diagnostics[0];
+        assert_eq!(error.message, "duplicate parameter `x`");
+        assert_eq!((error.span.line, error.span.column), (2, 6));
+        assert_eq!(error.notes[0].message, "first declared here");
+        assert_eq!((error.notes[0].span.line, error.notes[0].span.column), (1, 6));
+    }
+
+    #[test]
+    fn test_exponents_and_negation() {
+        // `**` groups to the right and binds tighter than everything else
+        assert_eq!(grouping("2 ** 3 ** 2;"), "(Power 2 (Power 3 2))");
+        assert_eq!(grouping("a * b ** c;"), "(Multiply a (Power b c))");
+        // Negation binds looser than `**`, as in mathematics: `-2 ** 2` is `-(2 ** 2)`
+        assert_eq!(grouping("-2 ** 2;"), "(Negate (Power 2 2))");
+        assert_eq!(grouping("2 ** -1;"), "(Power 2 (Negate 1))");
+        assert_eq!(grouping("-a * b;"), "(Multiply (Negate a) b)");
+        assert_eq!(grouping("a - -b;"), "(Subtract a (Negate b))");
+
+        assert!(Parser::from_lexer(Lexer::new("0..1..2;")).try_parse().is_err());
+    }
+
+    #[test]
+    fn test_negated_i64_min_is_one_literal() {
+        assert_eq!(grouping("-9223372036854775808;"), "-9223372036854775808");
+        assert_eq!(grouping("- 9223372036854775808;"), "-9223372036854775808");
+        assert_eq!(grouping("-(-x);"), "(Negate (Negate x))");
+        for source in ["a - 9223372036854775808;", "-9223372036854775808 ** 2;"] {
+            let error = Parser::from_lexer(Lexer::new(source)).try_parse().unwrap_err();
+            assert!(error.to_string().contains("only fits negated"), "{}", source);
+        }
+    }
+
+    #[test]
+    fn test_parse_imports() {
+        let parse = |source: &str| Parser::from_lexer(Lexer::new(source)).try_parse();
+        let program = parse("import std.math; import std.string as s; math.sqrt(2.0);").unwrap();
+        assert!(matches!(&program[0].kind, StatementKind::Import(path) if path == "std.math"));
+        assert!(matches!(&program[1].kind, StatementKind::ImportAs(path, alias) if path == "std.string" && alias == "s"));
+        let StatementKind::Expression(Expression::IndirectCall { callee, arguments }) = &program[2].kind else {
+            panic!("expected a call, got {:?}", program[2].kind);
+        };
+        assert!(matches!(callee.as_ref(), Expression::StructFieldAccess { field, .. } if field == "sqrt"));
+        assert_eq!(arguments.len(), 1);
+
+        assert!(parse("import std.math").is_err());
+    }
+
+    #[test]
+    fn test_parse_pub_declarations() {
+        let parse = |source: &str| Parser::from_lexer(Lexer::new(source)).try_parse();
+        let program = parse("pub fn area(w, h) { return w * h; } fn helper() { } pub let unit = 1; let x = 2;").unwrap();
+        let public: Vec<bool> = program
+            .iter()
+            .map(|stmt| match &stmt.kind {
+                StatementKind::Function(func) => func.public,
+                StatementKind::VariableDeclaration { public, .. } => *public,
+                other => panic!("expected a declaration, got {:?}", other),
+            })
+            .collect();
+        assert_eq!(public, [true, false, true, false]);
+
+        assert_eq!(parse("fn f() { pub let x = 1; }").unwrap_err(), "`pub` only goes on top-level functions and variables");
+        assert!(parse("pub struct Point { x: int }").unwrap_err().starts_with("Expected 'fn' or 'let' after 'pub'"));
+    }
+
+    #[test]
+    fn test_parse_impl_blocks() {
+        let parse = |source: &str| Parser::from_lexer(Lexer::new(source)).try_parse();
+        let program = parse("impl Point { fn norm(self) { return self.x; } fn scale(self, k) { return self; } }").unwrap();
+        let StatementKind::Impl { type_name, methods } = &program[0].kind else {
+            panic!("expected an impl block, got {:?}", program[0].kind);
+        };
+        assert_eq!(type_name, "Point");
+        let names: Vec<(&str, usize)> = methods.iter().map(|m| (m.name.as_str(), m.parameters.len())).collect();
+        assert_eq!(names, [("norm", 1), ("scale", 2)]);
+        assert!(matches!(&parse("impl Empty {}").unwrap()[0].kind, StatementKind::Impl { methods, .. } if methods.is_empty()));
+
+        // Only functions go in an impl block
+        assert!(parse("impl Point { let x = 1; }").is_err());
+        assert!(parse("impl Point { fn norm(self) { return 0; }").is_err());
+    }
+
+    #[test]
+    fn test_parse_struct_definitions() {
+        let parse = |source: &str| Parser::from_lexer(Lexer::new(source)).try_parse();
+        let program = parse("struct Config { retries: int = 3, name: str }").unwrap();
+        let StatementKind::Struct { name, fields } = &program[0].kind else {
+            panic!("expected a struct definition, got {:?}", program[0].kind);
+        };
+        assert_eq!(name, "Config");
+        assert_eq!(fields.len(), 2);
+        assert!(matches!(&fields[0].default, Some(Expression::Literal(Literal::Integer(3)))));
+        assert!(fields[1].default.is_none());
+        assert!(parse("struct Config { retries = 3 }").is_err());
+    }
+
+    #[test]
+    fn test_parse_dbg_keeps_its_source() {
+        let source = "dbg(a  +  b[0]);\ndbg(1, 2);";
+        let program = Parser::from_lexer(Lexer::new(source)).with_source(source).parse();
+        assert!(matches!(&program[0].kind, StatementKind::Expression(Expression::Debug { source, line: 1, .. }) if source == "a  +  b[0]"));
+        // Any other number of arguments is an ordinary call, for the compiler to reject
+        assert!(matches!(&program[1].kind, StatementKind::Expression(Expression::Call { name, .. }) if name == "dbg"));
+
+        // Without the source, the expression is written out as the parser would
+        let (tokens, _) = Lexer::new(source).into_tokens();
+        let program = Parser::new(tokens).parse();
+        assert!(matches!(&program[0].kind, StatementKind::Expression(Expression::Debug { source, line: 0, .. }) if source == "a + b[0]"));
+    }
+
+    #[test]
+    fn test_parse_try_catch() {
+        let parse = |source: &str| Parser::from_lexer(Lexer::new(source)).try_parse();
+        let program = parse("try { let x = 1 / 0; } catch err { puts(err); }").unwrap();
+        let StatementKind::TryCatch { body, error_binding, binding_span, handler } = &program[0].kind else {
+            panic!("expected a try statement, got {:?}", program[0].kind);
+        };
+        assert!(matches!(body[0].kind, StatementKind::VariableDeclaration { .. }));
+        assert_eq!(error_binding, "err");
+        assert_eq!((binding_span.start, binding_span.end, binding_span.column), (29, 32, 30));
+        assert!(matches!(handler[0].kind, StatementKind::Expression(_)));
+
+        assert!(parse("try { } puts(1);").is_err());
+        assert!(parse("try { } catch { }").is_err());
+    }
+
+    #[test]
+    fn test_parse_interpolated_strings() {
+        let parse = |source: &str| Parser::from_lexer(Lexer::new(source)).try_parse();
+        let program = parse(r#"let s = "{x} = ${x * 2}, ${len("a}")}";"#).unwrap();
+        let StatementKind::VariableDeclaration { value: Expression::FormatCall { name, format_string, arguments }, .. } =
+            &program[0].kind
+        else {
+            panic!("expected a format call, got {:?}", program[0].kind);
+        };
+        assert_eq!(name, "format");
+        assert_eq!(format_string, "{{x}} = {}, {}");
+        assert!(matches!(arguments[0], Expression::Binary { operator: BinaryOp::Multiply, .. }));
+        assert!(matches!(&arguments[1], Expression::Call { name, .. } if name == "len"));
+
+        // `\${` is kept as text
+        let program = parse(r#"let s = "\${x}";"#).unwrap();
+        assert!(matches!(
+            &program[0].kind,
+            StatementKind::VariableDeclaration { value: Expression::Literal(Literal::String(s)), .. } if s == "${x}"
+        ));
+
+        assert_eq!(parse(r#"let s = "${}";"#).unwrap_err(), "unexpected end of file, expected expression");
+        assert_eq!(parse(r#"let s = "${x y}";"#).unwrap_err(), "Expected '}' to end the interpolation, got Identifier(\"y\")");
+    }
+
+    #[test]
+    fn test_parse_loops() {
+        let ast = Parser::from_lexer(Lexer::new("loop { break; } let x = loop { break 1 + 2; };")).parse();
+        match &ast[0].kind {
+            StatementKind::Loop(body) => assert!(matches!(body[0].kind, StatementKind::Break(None))),
+            other => panic!("Expected a loop, got {:?}", other),
+        }
+        match &ast[1].kind {
+            StatementKind::VariableDeclaration { value: Expression::Loop(body), .. } => {
+                assert!(matches!(body[0].kind, StatementKind::Break(Some(Expression::Binary { .. }))));
+            }
+            other => panic!("Expected a loop as a value, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_parse_fixed_size_array_types() {
+        let ast = Parser::from_lexer(Lexer::new("let xs: [[int; 2]; 0] = [];")).parse();
+        let pair = voltage_core::Type::Array(Box::new(voltage_core::Type::Integer), 2);
+        assert!(matches!(
+            &ast[0].kind,
+            StatementKind::VariableDeclaration { explicit_type: Some(voltage_core::Type::Array(element, 0)), .. }
+                if **element == pair
+        ));
+
+        let error = |source: &str| Parser::from_lexer(Lexer::new(source)).try_parse().unwrap_err();
+        assert_eq!(error("let xs: [int; -1] = [];"), "Invalid type annotation: array size can't be negative: -1");
+        assert_eq!(error("let xs: [int; n] = [];"), "Invalid type annotation: array size must be an integer literal, got `n`");
+        assert_eq!(error("let n: number = 1;"), "Invalid type annotation: Unknown type: number");
+    }
+
+    #[test]
+    fn test_names_before_a_block_are_not_struct_initializers() {
+        let source = "if ready { puts(\"go\"); } elif other { } \
+                      while flag { } \
+                      for x in items { } \
+                      for i in 0..n { }";
+        let ast = Parser::from_lexer(Lexer::new(source)).try_parse().unwrap();
+        match &ast[0].kind {
+            StatementKind::If { condition: Expression::Variable(name), then_branch, elif_branches, .. } => {
+                assert_eq!(name, "ready");
+                assert_eq!(then_branch.len(), 1);
+                assert!(matches!(&elif_branches[0].0, Expression::Variable(name) if name == "other"));
+            }
+            other => panic!("Expected an if on a variable, got {:?}", other),
+        }
+        assert!(matches!(&ast[1].kind, StatementKind::While { condition: Expression::Variable(_), .. }));
+        assert!(matches!(&ast[2].kind, StatementKind::For { iterable: Expression::Variable(_), .. }));
+        assert!(matches!(&ast[3].kind, StatementKind::For { iterable: Expression::Range { .. }, .. }));
+    }
+
+    #[test]
+    fn test_struct_initializers_outside_headers() {
+        let source = "let p = Point { x: 1, y: 2 }; \
+                      draw(Point { x: 1, y: 2 }); \
+                      if (Point { x: 1, y: 2 }) == origin { }";
+        let ast = Parser::from_lexer(Lexer::new(source)).try_parse().unwrap();
+        assert!(matches!(
+            &ast[0].kind,
+            StatementKind::VariableDeclaration { value: Expression::StructInitialization { .. }, .. }
+        ));
+        match &ast[1].kind {
+            StatementKind::Expression(Expression::Call { arguments, .. }) => {
+                assert!(matches!(&arguments[0], Expression::StructInitialization { .. }));
+            }
+            other => panic!("Expected a call, got {:?}", other),
+        }
+        // In brackets, a header can still hold one
+        match &ast[2].kind {
+            StatementKind::If { condition: Expression::Binary { left, .. }, .. } => {
+                assert!(matches!(left.as_ref(), Expression::StructInitialization { .. }));
+            }
+            other => panic!("Expected an if comparing a struct, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_try_parse_returns_syntax_errors() {
+        let error = Parser::from_lexer(Lexer::new("fn broken( { }")).try_parse().unwrap_err();
+        assert!(error.starts_with("Expected parameter name"));
+        let error = Parser::from_lexer(Lexer::new("let x = 1; }")).try_parse().unwrap_err();
+        assert_eq!(error, "Unexpected '}'");
+    }
+
+    #[test]
+    fn test_parse_return_statement() {
+        let source = r#"fn square(n) { return n * n; } fn noop() { return; }"#;
+        let lexer = Lexer::new(source);
+        let mut parser = Parser::from_lexer(lexer);
+        let ast = parser.parse();
+
+        match &ast[0].kind {
+            StatementKind::Function(func) => {
+                assert!(matches!(func.body[0].kind, StatementKind::Return(Some(Expression::Binary { .. }))));
+            }
+            _ => panic!("Expected a function statement"),
+        }
+        match &ast[1].kind {
+            StatementKind::Function(func) => assert!(matches!(func.body[0].kind, StatementKind::Return(None))),
+            _ => panic!("Expected a function statement"),
+        }
+    }
+
+    #[test]
+    fn test_from_lexer_parses_like_copied_tokens() {
+        let source = "fn add(a, b) { return a + b; }\nlet s = \"{add(1, 2)}\";\nputs(s);";
+        let lexer = Lexer::new(source);
+        let copied = Parser::with_spans(lexer.tokenize().iter().cloned(), lexer.spans().iter().copied()).parse();
+        let taken = Parser::from_lexer(lexer).parse();
+        assert_eq!(format!("{:?}", copied), format!("{:?}", taken));
+    }
+
+    #[test]
+    fn test_keywords_are_not_names() {
+        let error = |source: &str| Parser::from_lexer(Lexer::new(source)).try_parse().unwrap_err();
+        assert_eq!(error("let in = 2;"), "`in` is a reserved keyword and cannot be used as an identifier");
+        assert_eq!(error("fn match() { }"), "`match` is a reserved keyword and cannot be used as an identifier");
+        assert_eq!(error("fn f(a, null) { }"), "`null` is a reserved keyword and cannot be used as an identifier");
+        assert_eq!(error("puts(enum);"), "`enum` is a reserved keyword and cannot be used as an identifier");
+
+        let result = crate::parse_with_diagnostics("let x = 1;\nlet const = 2;");
+        assert_eq!(result.diagnostics.len(), 1);
+        assert_eq!((result.diagnostics[0].span.line, result.diagnostics[0].span.column), (2, 5));
+    }
+
+    #[test]
+    fn test_invalid_escapes_point_inside_the_literal() {
+        let source = "let s = \"ab\\u{D800}\";\nlet t = \"${s}\n  \\q\";";
+        let result = crate::parse_with_diagnostics(source);
+        let found: Vec<_> = result
+            .diagnostics
+            .iter()
+            .map(|error| (error.code, error.span.line, error.span.column, &source[error.span.start..error.span.end]))
+            .collect();
+        assert_eq!(found, [(Some("invalid_escape"), 1, 12, r"\u{D800}"), (Some("invalid_escape"), 3, 3, r"\q")]);
+        assert_eq!(result.diagnostics[0].message, "invalid escape `\\u{D800}`: surrogates aren't characters");
+
+        let ast = Parser::from_lexer(Lexer::new("\"\\u{1F600}\\t\\${x}\";")).try_parse().unwrap();
+        let StatementKind::Expression(Expression::Literal(Literal::String(text))) = &ast[0].kind else {
+            panic!("expected a string literal, got {:?}", ast[0].kind);
+        };
+        assert_eq!(text, "😀\t${x}");
+    }
+}
@@ -0,0 +1,38 @@
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use voltage_parser::Lexer;
+
+// Counts the bytes allocated and not yet freed. This file has one test, so
+// nothing else allocates while it measures.
+struct Counting;
+
+static LIVE: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        LIVE.fetch_add(layout.size(), Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        LIVE.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static ALLOCATOR: Counting = Counting;
+
+#[test]
+fn test_the_lexer_keeps_no_copy_of_the_source() {
+    // A megabyte of comment on one line, around four tokens: a copy of the
+    // source would be most of what the lexer holds
+    let source = format!("let /*{}*/ x = 1;", " ".repeat(1 << 20));
+    let before = LIVE.load(Ordering::SeqCst);
+    let lexer = Lexer::new(&source);
+    let held = LIVE.load(Ordering::SeqCst) - before;
+
+    assert_eq!(lexer.tokenize().len(), 5);
+    assert!(held < 4096, "the lexer holds {} bytes for {} bytes of source", held, source.len());
+    assert_eq!(lexer.line_col(source.len() - 1), (1, source.len() as u32));
+}
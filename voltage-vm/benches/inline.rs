@@ -0,0 +1,41 @@
+//! A loop that spends most of its time calling a one-line accessor, run as
+//! compiled and with the accessor inlined.
+//!
+//! Run with `cargo bench -p voltage-vm`.
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use voltage_vm::{inline_functions, VirtualMachine, INLINE_LIMIT};
+
+const SOURCE: &str = "
+fn x_of(p) { return p.x; }
+let p = Point { x: 1, y: 2 };
+let total = [0];
+for i in 0..100000 {
+    total[0] += x_of(p);
+}
+total[0];
+";
+
+fn inline(c: &mut Criterion) {
+    let program = voltage_vm::compile(SOURCE).unwrap();
+    let inlined = inline_functions(program.clone(), INLINE_LIMIT);
+    let mut group = c.benchmark_group("100k accessor calls");
+    group.sample_size(20);
+    for (name, program) in [("calls", program), ("inlined", inlined)] {
+        group.bench_function(name, |b| {
+            b.iter_batched(
+                || {
+                    let mut vm = VirtualMachine::new();
+                    vm.load_program(program.clone()).unwrap();
+                    vm
+                },
+                |mut vm| vm.run().unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, inline);
+criterion_main!(benches);
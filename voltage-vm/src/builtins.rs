@@ -0,0 +1,308 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::BufRead;
+use std::rc::Rc;
+use crate::convert::IntoHostFunction;
+use crate::env::EnvAccess;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::fs::FsAccess;
+use crate::higher_order::{Caller, HigherOrderFunction};
+use crate::random::Rng;
+use crate::vm::{RuntimeValue, BUILTINS};
+
+/// A function provided by the host program and callable from Voltage code.
+pub type HostFunction = Rc<dyn Fn(&[RuntimeValue]) -> Result<RuntimeValue, String>>;
+
+/// How many arguments a builtin takes. The compiler rejects calls outside
+/// the range, so builtins only check argument counts themselves when called
+/// as values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Arity {
+    pub min: usize,
+    // `None` for variadic builtins
+    pub max: Option<usize>,
+}
+
+impl Arity {
+    pub fn exactly(count: usize) -> Self {
+        Self { min: count, max: Some(count) }
+    }
+
+    pub fn at_least(min: usize) -> Self {
+        Self { min, max: None }
+    }
+
+    pub fn between(min: usize, max: usize) -> Self {
+        Self { min, max: Some(max) }
+    }
+
+    pub fn accepts(&self, count: usize) -> bool {
+        count >= self.min && self.max.is_none_or(|max| count <= max)
+    }
+}
+
+impl fmt::Display for Arity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let plural = |count: usize| if count == 1 { "argument" } else { "arguments" };
+        match self.max {
+            Some(max) if max == self.min => write!(f, "{} {}", max, plural(max)),
+            Some(max) => write!(f, "{} to {} {}", self.min, max, plural(max)),
+            None => write!(f, "at least {} {}", self.min, plural(self.min)),
+        }
+    }
+}
+
+/// The builtin functions a VM knows about, indexed by their `CallBuiltin` id.
+///
+/// The core builtins (`puts`, `print`) are implemented by the VM itself and
+/// always occupy the first ids; host functions are appended after them. With
+/// the `json` feature, `parse_json` and `to_json_string` are registered too.
+/// Builtins like `map` that call back into the program are registered with
+/// [`BuiltinRegistry::register_higher_order`].
+/// The file builtins are registered disabled until [`BuiltinRegistry::enable_fs`],
+/// and `env` and `set_env` until [`BuiltinRegistry::enable_env`].
+/// The functions of the standard library [modules](crate::modules) are
+/// registered under their qualified names, like `std.math.sqrt`.
+#[derive(Clone)]
+pub struct BuiltinRegistry {
+    names: Vec<String>,
+    // `None` for the core builtins the VM handles itself
+    functions: Vec<Option<Implementation>>,
+    // `None` for functions registered without declaring their arguments
+    arities: Vec<Option<Arity>>,
+    ids: HashMap<String, usize>,
+    // State of the random builtins; clones of a registry share it
+    rng: Rc<RefCell<Rng>>,
+    // Where `std.io.input` reads lines from; clones of a registry share it
+    input: Rc<RefCell<Box<dyn BufRead>>>,
+}
+
+// How a builtin that isn't a core builtin is implemented
+#[derive(Clone)]
+enum Implementation {
+    Host(HostFunction),
+    HigherOrder(HigherOrderFunction),
+}
+
+impl Default for BuiltinRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl BuiltinRegistry {
+    pub fn new() -> Self {
+        let mut registry = Self {
+            names: Vec::new(),
+            functions: Vec::new(),
+            arities: Vec::new(),
+            ids: HashMap::new(),
+            rng: Rc::new(RefCell::new(Rng::from_entropy())),
+            input: Rc::new(RefCell::new(crate::modules::default_input())),
+        };
+        for name in BUILTINS {
+            registry.insert(name, None, Some(Arity::exactly(1)));
+        }
+        let rng = Rc::clone(&registry.rng);
+        crate::format::register_builtins(&mut registry);
+        crate::collections::register_builtins(&mut registry);
+        crate::numbers::register_builtins(&mut registry);
+        crate::higher_order::register_builtins(&mut registry);
+        crate::random::register_builtins(&mut registry, &rng);
+        crate::testing::register_builtins(&mut registry);
+        registry
+            .register_fn("truthy", |value: RuntimeValue| value.is_truthy())
+            .expect("truthy is not a core builtin");
+        registry
+            .register_fn("approx_eq", |a: f64, b: f64, tolerance: f64| (a - b).abs() <= tolerance)
+            .expect("approx_eq is not a core builtin");
+        registry
+            .register_fn("is_nan", |x: f64| x.is_nan())
+            .expect("is_nan is not a core builtin");
+        registry
+            .register_fn("is_inf", |x: f64| x.is_infinite())
+            .expect("is_inf is not a core builtin");
+        crate::fs::register_disabled(&mut registry);
+        crate::env::register_disabled(&mut registry);
+        let input = Rc::clone(&registry.input);
+        crate::modules::register_builtins(&mut registry, &input);
+        #[cfg(feature = "json")]
+        crate::json::register_builtins(&mut registry);
+        registry
+    }
+
+    /// Registers a host function under `name` and returns its id. Registering
+    /// a name again replaces the previous function but keeps its id; the core
+    /// builtins cannot be replaced. Calls may pass any number of arguments.
+    pub fn register<F>(&mut self, name: &str, function: F) -> Result<usize, String>
+    where
+        F: Fn(&[RuntimeValue]) -> Result<RuntimeValue, String> + 'static,
+    {
+        self.register_host_function(name, Rc::new(function))
+    }
+
+    /// Like [`BuiltinRegistry::register`], but calls passing a number of
+    /// arguments that `arity` doesn't allow fail to compile.
+    pub fn register_with_arity<F>(&mut self, name: &str, arity: Arity, function: F) -> Result<usize, String>
+    where
+        F: Fn(&[RuntimeValue]) -> Result<RuntimeValue, String> + 'static,
+    {
+        self.add(name, Rc::new(function), Some(arity))
+    }
+
+    /// Registers a closure with typed parameters, e.g.
+    /// `|a: f64, b: f64| (a * a + b * b).sqrt()`. Arguments are converted
+    /// from `RuntimeValue`, and a wrong type becomes a runtime error. Calls
+    /// with the wrong number of arguments fail to compile.
+    pub fn register_fn<Args, F>(&mut self, name: &str, function: F) -> Result<usize, String>
+    where
+        F: IntoHostFunction<Args>,
+    {
+        self.add(name, function.into_host_function(name), Some(Arity::exactly(F::ARITY)))
+    }
+
+    /// Lets scripts use `read_file`, `write_file` and `file_exists` within
+    /// the limits of `access`. Not available in WebAssembly builds.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn enable_fs(&mut self, access: FsAccess) {
+        crate::fs::register_builtins(self, access);
+    }
+
+    /// Lets scripts read and set environment variables through `env` and
+    /// `set_env`, in the environment `access` gives them.
+    pub fn enable_env(&mut self, access: EnvAccess) {
+        crate::env::register_builtins(self, access);
+    }
+
+    /// Makes `std.io.input` read lines from `input` rather than stdin.
+    pub fn set_input(&self, input: impl BufRead + 'static) {
+        *self.input.borrow_mut() = Box::new(input);
+    }
+
+    /// Restarts the sequence of the random builtins from `seed`, making runs
+    /// reproducible. Scripts can do the same with `seed_random(n)`.
+    pub fn seed_random(&self, seed: u64) {
+        *self.rng.borrow_mut() = Rng::with_seed(seed);
+    }
+
+    pub fn register_host_function(&mut self, name: &str, function: HostFunction) -> Result<usize, String> {
+        self.add(name, function, None)
+    }
+
+    /// Registers a builtin that calls functions of the program, like `map`,
+    /// through the [`Caller`] it is given. See [`crate::higher_order`] for
+    /// how the calls run.
+    pub fn register_higher_order<F>(&mut self, name: &str, arity: Arity, function: F) -> Result<usize, String>
+    where
+        F: Fn(&mut dyn Caller, &[RuntimeValue]) -> Result<RuntimeValue, String> + 'static,
+    {
+        self.add_implementation(name, Implementation::HigherOrder(Rc::new(function)), Some(arity))
+    }
+
+    fn add(&mut self, name: &str, function: HostFunction, arity: Option<Arity>) -> Result<usize, String> {
+        self.add_implementation(name, Implementation::Host(function), arity)
+    }
+
+    fn add_implementation(&mut self, name: &str, function: Implementation, arity: Option<Arity>) -> Result<usize, String> {
+        if BUILTINS.contains(&name) {
+            return Err(format!("Cannot replace core builtin: {}", name));
+        }
+        Ok(self.insert(name, Some(function), arity))
+    }
+
+    fn insert(&mut self, name: &str, function: Option<Implementation>, arity: Option<Arity>) -> usize {
+        match self.ids.get(name) {
+            Some(&id) => {
+                self.functions[id] = function;
+                self.arities[id] = arity;
+                id
+            }
+            None => {
+                let id = self.names.len();
+                self.names.push(name.to_string());
+                self.functions.push(function);
+                self.arities.push(arity);
+                self.ids.insert(name.to_string(), id);
+                id
+            }
+        }
+    }
+
+    pub fn id(&self, name: &str) -> Option<usize> {
+        self.ids.get(name).copied()
+    }
+
+    pub fn name(&self, id: usize) -> Option<&str> {
+        self.names.get(id).map(String::as_str)
+    }
+
+    /// The arguments calls to `name` may pass, if it declared them.
+    pub fn arity(&self, name: &str) -> Option<Arity> {
+        self.arities[self.id(name)?]
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.names.iter().map(String::as_str)
+    }
+
+    pub(crate) fn host_function(&self, id: usize) -> Option<&HostFunction> {
+        match self.functions.get(id)? {
+            Some(Implementation::Host(function)) => Some(function),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn higher_order_function(&self, id: usize) -> Option<&HigherOrderFunction> {
+        match self.functions.get(id)? {
+            Some(Implementation::HigherOrder(function)) => Some(function),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Debug for BuiltinRegistry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("BuiltinRegistry").field("names", &self.names).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_core_builtins_keep_their_ids() {
+        let mut registry = BuiltinRegistry::new();
+        assert_eq!(registry.id("puts"), Some(0));
+        assert_eq!(registry.id("print"), Some(1));
+        assert!(registry.host_function(0).is_none());
+        assert!(registry.register("puts", |_| Ok(RuntimeValue::Null)).is_err());
+    }
+
+    #[test]
+    fn test_reregistering_replaces_function() {
+        let mut registry = BuiltinRegistry::new();
+        let first = registry.register("answer", |_| Ok(RuntimeValue::Integer(1))).unwrap();
+        let second = registry.register("answer", |_| Ok(RuntimeValue::Integer(2))).unwrap();
+        assert_eq!(first, second);
+
+        let function = registry.host_function(first).unwrap();
+        assert_eq!(function(&[]), Ok(RuntimeValue::Integer(2)));
+    }
+
+    #[test]
+    fn test_arities() {
+        let mut registry = BuiltinRegistry::new();
+        assert_eq!(registry.arity("puts"), Some(Arity::exactly(1)));
+        assert_eq!(registry.arity("approx_eq"), Some(Arity::exactly(3)));
+        assert_eq!(registry.arity("format"), Some(Arity::at_least(1)));
+        assert!(Arity::between(1, 2).accepts(2) && !Arity::between(1, 2).accepts(3));
+        assert_eq!(Arity::at_least(1).to_string(), "at least 1 argument");
+        assert_eq!(Arity::between(1, 2).to_string(), "1 to 2 arguments");
+
+        registry.register("anything", |_| Ok(RuntimeValue::Null)).unwrap();
+        assert_eq!(registry.arity("anything"), None);
+        assert_eq!(registry.arity("missing"), None);
+    }
+}
@@ -0,0 +1,176 @@
+//! The static call graph: which functions call which, for `voltagec
+//! --emit=callgraph`, and the lint for functions that call themselves
+//! before they could ever return.
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+use voltage_core::{Expression, Function, Span, Statement, StatementKind};
+use crate::compiler::referenced_names;
+use crate::program::Program;
+
+/// What the top-level code is called in the graph, as in traces.
+const TOP_LEVEL: &str = "<top level>";
+
+/// The program's call graph in Graphviz DOT format: a node for the
+/// top-level code and each function, and an edge from each to every
+/// function it calls or uses as a value. Method calls pick their function
+/// when they run, so they have no edges.
+///
+/// ```
+/// use voltage_vm::call_graph;
+///
+/// let program = voltage_vm::compile("fn double(n) { return n * 2; }\nputs(double(4));").unwrap();
+/// assert!(call_graph(&program).contains("\"<top level>\" -> \"double\";"));
+/// ```
+pub fn call_graph(program: &Program) -> String {
+    let functions: BTreeSet<&str> = program.functions.iter().map(|function| function.name.as_str()).collect();
+    // The functions that the instructions at `ips` call, in order
+    let callees = |ips: &mut dyn Iterator<Item = usize>| -> BTreeSet<String> {
+        referenced_names(program, ips).into_iter().filter(|name| functions.contains(name.as_str())).collect()
+    };
+
+    let mut top_level = (0..program.bytecode.len()).filter(|&ip| !program.functions.iter().any(|f| f.contains(ip)));
+    let mut callers = vec![(TOP_LEVEL, callees(&mut top_level))];
+    for function in &program.functions {
+        callers.push((&function.name, callees(&mut (function.start..function.end))));
+    }
+
+    let mut out = String::from("digraph calls {\n");
+    for (caller, _) in &callers {
+        let _ = writeln!(out, "    \"{}\";", caller);
+    }
+    for (caller, callees) in &callers {
+        for callee in callees {
+            let _ = writeln!(out, "    \"{}\" -> \"{}\";", caller, callee);
+        }
+    }
+    out.push_str("}\n");
+    out
+}
+
+/// Where `func` calls itself, if it does so on every path through its body
+/// before it could return: a recursive function missing its base case,
+/// which can only end by running out of stack. Branches that might return
+/// or stop, and loop bodies that might not run, are assumed to.
+pub(crate) fn unconditional_recursion(func: &Function) -> Option<Span> {
+    // Calls inside the function use the name it was written with, not its
+    // full name like `utils::helper`
+    let name = func.name.rsplit("::").next().unwrap_or(&func.name);
+    match walk(&func.body, name) {
+        Path::Recurses(span) => Some(span),
+        Path::MayLeave | Path::Continues => None,
+    }
+}
+
+/// How running some statements can go, as far as recursion is concerned.
+enum Path {
+    /// Every way through them calls the function, at the statement at `Span`.
+    Recurses(Span),
+    /// Some way through them might return or stop without calling it.
+    MayLeave,
+    /// Every way through them either calls it or carries on after them.
+    Continues,
+}
+
+fn walk(statements: &[Statement], name: &str) -> Path {
+    for stmt in statements {
+        match step(stmt, name) {
+            Path::Continues => {}
+            path => return path,
+        }
+    }
+    Path::Continues
+}
+
+fn step(stmt: &Statement, name: &str) -> Path {
+    let expression = |expr: &Expression| {
+        if always_calls(expr, name) {
+            Path::Recurses(stmt.span)
+        } else if matches!(expr, Expression::Loop(_)) || matches!(expr, Expression::Call { name: called, .. } if called == "panic") {
+            Path::MayLeave
+        } else {
+            Path::Continues
+        }
+    };
+    match &stmt.kind {
+        StatementKind::Expression(expr) | StatementKind::VariableDeclaration { value: expr, .. } => expression(expr),
+        StatementKind::Return(Some(expr)) => match expression(expr) {
+            Path::Recurses(span) => Path::Recurses(span),
+            _ => Path::MayLeave,
+        },
+        StatementKind::Block(body) | StatementKind::UnsafeBlock(body) => walk(body, name),
+        StatementKind::If { condition, then_branch, elif_branches, else_branch } => {
+            if always_calls(condition, name) {
+                return Path::Recurses(stmt.span);
+            }
+            // Conditions of `elif`s only run on some paths, so they're left out
+            let mut branches = vec![walk(then_branch, name)];
+            branches.extend(elif_branches.iter().map(|(_, body)| walk(body, name)));
+            branches.push(else_branch.as_deref().map_or(Path::Continues, |body| walk(body, name)));
+            if branches.iter().all(|path| matches!(path, Path::Recurses(_))) {
+                Path::Recurses(stmt.span)
+            } else if branches.iter().any(|path| matches!(path, Path::MayLeave)) {
+                Path::MayLeave
+            } else {
+                Path::Continues
+            }
+        }
+        // The body might not run, so only what it might leave by counts
+        StatementKind::While { condition: header, body } | StatementKind::For { iterable: header, body, .. } => {
+            if always_calls(header, name) {
+                Path::Recurses(stmt.span)
+            } else if matches!(walk(body, name), Path::MayLeave) {
+                Path::MayLeave
+            } else {
+                Path::Continues
+            }
+        }
+        // A loop runs until something leaves it
+        StatementKind::Loop(body) => match walk(body, name) {
+            Path::Recurses(span) => Path::Recurses(span),
+            _ => Path::MayLeave,
+        },
+        // A caught error could be the end of the recursion
+        StatementKind::TryCatch { .. }
+        | StatementKind::Return(None)
+        | StatementKind::Break(_)
+        | StatementKind::Continue => Path::MayLeave,
+        StatementKind::Function(_)
+        | StatementKind::Import(_)
+        | StatementKind::ImportAs(..)
+        | StatementKind::Impl { .. }
+        | StatementKind::Struct { .. } => Path::Continues,
+    }
+}
+
+// Whether evaluating `expr` always calls the function `name`
+fn always_calls(expr: &Expression, name: &str) -> bool {
+    match expr {
+        Expression::Literal(_) | Expression::Variable(_) => false,
+        Expression::Call { name: called, arguments } => called == name || arguments.iter().any(|arg| always_calls(arg, name)),
+        Expression::IndirectCall { callee, arguments } => {
+            always_calls(callee, name) || arguments.iter().any(|arg| always_calls(arg, name))
+        }
+        Expression::FormatCall { arguments, .. } | Expression::ArrayLiteral(arguments) => {
+            arguments.iter().any(|arg| always_calls(arg, name))
+        }
+        Expression::EnumVariantCreation { values, .. } => values.iter().any(|value| always_calls(value, name)),
+        Expression::StructInitialization { fields, .. } => fields.iter().any(|(_, value)| always_calls(value, name)),
+        Expression::VariableDeclaration { value, .. } => always_calls(value, name),
+        Expression::Unary { operand, .. } => always_calls(operand, name),
+        Expression::Debug { expression, .. } => always_calls(expression, name),
+        Expression::StructFieldAccess { object, .. } => always_calls(object, name),
+        // Only the left side of `&&` and `||` always runs
+        Expression::Logical { left, .. } => always_calls(left, name),
+        // Only the value matched on always runs, not the arms
+        Expression::EnumMatch { expression, .. } => always_calls(expression, name),
+        Expression::Binary { left, right, .. } => [left, right].iter().any(|expr| always_calls(expr, name)),
+        Expression::Range { start, end, .. } => [start, end].iter().any(|expr| always_calls(expr, name)),
+        Expression::ArrayAccess { array, index } => [array, index].iter().any(|expr| always_calls(expr, name)),
+        Expression::ArrayAssignment { array, index, value } | Expression::ArrayCompoundAssignment { array, index, value, .. } => {
+            [array, index, value].iter().any(|expr| always_calls(expr, name))
+        }
+        Expression::StructFieldAssignment { object, value, .. } => [object, value].iter().any(|expr| always_calls(expr, name)),
+        Expression::Loop(body) => matches!(walk(body, name), Path::Recurses(_)),
+    }
+}
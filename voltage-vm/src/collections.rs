@@ -0,0 +1,126 @@
+//! Builtins that work on arrays, strings and ranges alike, and a few for
+//! arrays alone.
+
+use std::cmp::Ordering;
+use crate::builtins::BuiltinRegistry;
+use crate::vm::{length, RuntimeValue};
+
+/// Whether `collection` has `value` as an element, or as a substring when
+/// both are strings.
+fn contains(collection: &RuntimeValue, value: &RuntimeValue) -> Result<bool, String> {
+    match (collection, value) {
+        (RuntimeValue::Array(elements), value) => Ok(elements.contains(value)),
+        (RuntimeValue::String(s), RuntimeValue::String(part)) => Ok(s.contains(part.as_str())),
+        (RuntimeValue::String(_), other) => Err(format!("contains: a string can only contain strings, got {}", other.type_name())),
+        (RuntimeValue::Range { start, end, inclusive }, RuntimeValue::Integer(n)) => {
+            Ok(start <= n && if *inclusive { n <= end } else { n < end })
+        }
+        (RuntimeValue::Range { .. }, _) => Ok(false),
+        (other, _) => Err(format!("contains expects an array, string or range, got {}", other.type_name())),
+    }
+}
+
+/// What a `for` loop over `value` visits: an array's elements, a string's
+/// characters or a range's integers.
+fn elements(name: &str, value: RuntimeValue) -> Result<Vec<RuntimeValue>, String> {
+    match value {
+        RuntimeValue::Array(elements) => Ok(elements),
+        RuntimeValue::String(s) => Ok(s.chars().map(|c| RuntimeValue::String(c.to_string())).collect()),
+        RuntimeValue::Range { start, end, inclusive } => {
+            let end = if inclusive { end.checked_add(1) } else { Some(end) };
+            Ok(match end {
+                Some(end) => (start..end).map(RuntimeValue::Integer).collect(),
+                None => (start..=i64::MAX).map(RuntimeValue::Integer).collect(),
+            })
+        }
+        other => Err(format!("{} expects an array, string or range, got {}", name, other.type_name())),
+    }
+}
+
+/// Each of `values` paired with its index, as `[index, value]`.
+fn enumerate(values: Vec<RuntimeValue>) -> Vec<RuntimeValue> {
+    (0..).zip(values).map(|(index, value)| RuntimeValue::Array(vec![RuntimeValue::Integer(index), value])).collect()
+}
+
+/// The elements of `a` and `b` paired up in order, as `[a, b]`, for as many
+/// as the shorter has.
+fn zip(a: Vec<RuntimeValue>, b: Vec<RuntimeValue>) -> Vec<RuntimeValue> {
+    a.into_iter().zip(b).map(|(a, b)| RuntimeValue::Array(vec![a, b])).collect()
+}
+
+/// The elements of `elements`, which must all be strings, with `separator`
+/// between each pair.
+fn join(elements: &[RuntimeValue], separator: &str) -> Result<String, String> {
+    let mut parts = Vec::with_capacity(elements.len());
+    for (index, element) in elements.iter().enumerate() {
+        match element {
+            RuntimeValue::String(s) => parts.push(s.as_str()),
+            other => return Err(format!("join: element {} is {}, expected string", index, other.type_name())),
+        }
+    }
+    Ok(parts.join(separator))
+}
+
+/// `elements` sorted, for arrays of only integers, only floats or only
+/// strings. Equal elements keep their order, and NaN sorts last whichever the
+/// direction.
+fn sorted(name: &str, mut elements: Vec<RuntimeValue>, descending: bool) -> Result<Vec<RuntimeValue>, String> {
+    if let Some(first) = elements.first() {
+        if !matches!(first, RuntimeValue::Integer(_) | RuntimeValue::Float(_) | RuntimeValue::String(_)) {
+            return Err(format!("{}: can only sort integers, floats or strings, got {}", name, first.type_name()));
+        }
+        if let Some(other) = elements.iter().find(|element| element.type_name() != first.type_name()) {
+            return Err(format!("{}: cannot sort a mix of {} and {}", name, first.type_name(), other.type_name()));
+        }
+    }
+    elements.sort_by(|a, b| match (a, b) {
+        (RuntimeValue::Float(x), RuntimeValue::Float(y)) if x.is_nan() || y.is_nan() => x.is_nan().cmp(&y.is_nan()),
+        _ if descending => compare(b, a),
+        _ => compare(a, b),
+    });
+    Ok(elements)
+}
+
+// Orders two values of the same sortable type
+fn compare(a: &RuntimeValue, b: &RuntimeValue) -> Ordering {
+    match (a, b) {
+        (RuntimeValue::Integer(x), RuntimeValue::Integer(y)) => x.cmp(y),
+        (RuntimeValue::Float(x), RuntimeValue::Float(y)) => x.total_cmp(y),
+        (RuntimeValue::String(x), RuntimeValue::String(y)) => x.cmp(y),
+        _ => Ordering::Equal,
+    }
+}
+
+pub(crate) fn register_builtins(registry: &mut BuiltinRegistry) {
+    registry
+        .register_fn("len", |value: RuntimeValue| length(&value).map(|len| len as i64))
+        .expect("len is not a core builtin");
+    registry
+        .register_fn("contains", |collection: RuntimeValue, value: RuntimeValue| contains(&collection, &value))
+        .expect("contains is not a core builtin");
+    // `clone` is a no-op that returns its argument. Every value is copied
+    // when it is stored or passed, arrays and structs included, so the
+    // argument is already a copy that changes nothing else when changed. For
+    // the same reason there is no `is_same`: no two variables ever share a
+    // value, so there is no identity to tell apart from `==`
+    registry
+        .register_fn("clone", |value: RuntimeValue| value)
+        .expect("clone is not a core builtin");
+    registry
+        .register_fn("join", |elements: Vec<RuntimeValue>, separator: String| join(&elements, &separator))
+        .expect("join is not a core builtin");
+    registry
+        .register_fn("sort", |elements: Vec<RuntimeValue>| sorted("sort", elements, false))
+        .expect("sort is not a core builtin");
+    registry
+        .register_fn("sort_desc", |elements: Vec<RuntimeValue>| sorted("sort_desc", elements, true))
+        .expect("sort_desc is not a core builtin");
+    registry
+        .register_fn("enumerate", |value: RuntimeValue| elements("enumerate", value).map(enumerate))
+        .expect("enumerate is not a core builtin");
+    registry
+        .register_fn("zip", |a: RuntimeValue, b: RuntimeValue| {
+            Ok::<_, String>(zip(elements("zip", a)?, elements("zip", b)?))
+        })
+        .expect("zip is not a core builtin");
+}
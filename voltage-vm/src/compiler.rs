@@ -1,33 +1,340 @@
-use crate::vm::{Bytecode, RuntimeValue};
-use voltage_core::{Statement, Expression, Literal, BinaryOp, Function};
+use std::collections::{HashMap, HashSet};
+use std::fmt;
+use std::ops::Range;
+use std::path::Path;
+use crate::builtins::BuiltinRegistry;
+use crate::call_graph::unconditional_recursion;
+use crate::inline::{inline_functions, INLINE_LIMIT};
+use crate::modules::{script_file, Member, Module, ModuleLoader};
+use crate::program::{FunctionEntry, Program, MAX_CONSTANT_DEPTH};
+use crate::propagate::propagate_constants;
+use crate::resolver::{Resolution, Resolver};
+use crate::source_map::{LocalVariable, SourceMap};
+use crate::types;
+use crate::validate::ValidationError;
+use crate::vm::{Bytecode, RuntimeValue, BUILTINS};
+use voltage_core::{Span, Statement, StatementKind, Expression, Literal, BinaryOp, LogicalOp, UnaryOp, Function};
+use voltage_parser::{Diagnostic, Lexer, Parser, Severity};
+
+/// The order compiled code evaluates expressions in, which scripts can rely
+/// on. `voltagec self info --language` prints it.
+pub const EVALUATION_ORDER: &str = "\
+Expressions are evaluated left to right, each one completely before the next:
+- call arguments in order, after the function called when that is an expression itself
+- the left operand of an operator before the right; `&&` and `||` skip the right when the left decides
+- a method call's receiver, then its arguments
+- array, struct and range literals in the order they are written
+- `a[i] = v`: the index, then the value, then the variable `a` is read and updated
+- `a[i] op= v`: the index, then the element, then the value, then the variable `a` is read and updated
+- `a[i][j] = v`, `a.f[i] = v` and `a[i].f = v`: each index from left to right, then the value, then the variable `a` is read and updated through every level
+- `a[i].m(x)`: the index, then the variable `a` is read, then the arguments; the method's changes to its receiver are written back to `a` once it returns
+- `s.f = v`: the value, then the variable `s` is read and updated
+";
+
+// The end of a local's scope until its block ends
+const OPEN_SCOPE: usize = usize::MAX;
+
+/// What kind of mistake stopped a program from compiling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompileErrorKind {
+    /// A call to a function that doesn't exist.
+    UnknownFunction,
+    /// A call with the wrong number of arguments for a builtin or for a
+    /// function the program defines.
+    ArityMismatch,
+    /// `break` or `continue` outside a loop.
+    BreakOutsideLoop,
+    /// `break` with a value out of a `while` or `for` loop, which have no
+    /// value to give.
+    BreakWithValue,
+    /// A `fn` inside a block rather than directly in a function body or at
+    /// the top level.
+    NestedFunctionUnsupported,
+    /// A function defined inside another that uses a parameter or variable
+    /// of the one it's in.
+    CapturedVariable,
+    /// A variable that is never declared. Only scripts are checked, see
+    /// [`BytecodeCompiler::compile_script`].
+    UndefinedVariable,
+    /// A value that doesn't match its annotation, or an array literal whose
+    /// elements have different types.
+    TypeMismatch,
+    /// An assignment to something that can't be assigned to.
+    InvalidAssignmentTarget,
+    /// A declaration where an expression was expected.
+    UnexpectedDeclaration,
+    /// A script with both `fn main` and top-level statements.
+    MixedMainAndStatements,
+    /// Compiled code that the VM refused to load.
+    InvalidBytecode,
+    /// A top-level name defined twice, as two functions or as a function
+    /// and a global.
+    DuplicateDefinition,
+    /// A function named after a builtin.
+    ReservedName,
+    /// A builtin used as a value rather than called. Builtins aren't
+    /// values, so a variable can't hold one. Modules aren't values either.
+    BuiltinAsValue,
+    /// An import of a module the [`ModuleLoader`] doesn't have and none of
+    /// its search paths has a file for.
+    UnknownModule,
+    /// A module file that couldn't be read, or that has a syntax error.
+    InvalidModule,
+    /// A member that an imported module doesn't have, like `math.sqrtt`.
+    UnknownModuleMember,
+    /// A function or variable of a module file used from outside it
+    /// without being `pub`.
+    PrivateModuleMember,
+    /// An initializer of a defined struct that leaves out a field with no
+    /// default.
+    MissingField,
+    /// An initializer of a defined struct that gives a field the struct
+    /// doesn't have.
+    UnknownField,
+    /// A struct field default that isn't a constant.
+    NonConstantDefault,
+    /// A warning, with [`CompilerOptions::deny_warnings`] set.
+    DeniedWarning,
+}
+
+impl CompileErrorKind {
+    /// The [`Diagnostic::code`] for errors of this kind.
+    pub fn code(self) -> &'static str {
+        match self {
+            CompileErrorKind::UnknownFunction => "unknown_function",
+            CompileErrorKind::ArityMismatch => "arity_mismatch",
+            CompileErrorKind::BreakOutsideLoop => "break_outside_loop",
+            CompileErrorKind::BreakWithValue => "break_with_value",
+            CompileErrorKind::NestedFunctionUnsupported => "nested_function_unsupported",
+            CompileErrorKind::CapturedVariable => "captured_variable",
+            CompileErrorKind::UndefinedVariable => "undefined_variable",
+            CompileErrorKind::TypeMismatch => "type_mismatch",
+            CompileErrorKind::InvalidAssignmentTarget => "invalid_assignment_target",
+            CompileErrorKind::UnexpectedDeclaration => "unexpected_declaration",
+            CompileErrorKind::MixedMainAndStatements => "mixed_main_and_statements",
+            CompileErrorKind::InvalidBytecode => "invalid_bytecode",
+            CompileErrorKind::DuplicateDefinition => "duplicate_definition",
+            CompileErrorKind::ReservedName => "reserved_name",
+            CompileErrorKind::BuiltinAsValue => "builtin_as_value",
+            CompileErrorKind::UnknownModule => "unknown_module",
+            CompileErrorKind::InvalidModule => "invalid_module",
+            CompileErrorKind::UnknownModuleMember => "unknown_module_member",
+            CompileErrorKind::PrivateModuleMember => "private_module_member",
+            CompileErrorKind::MissingField => "missing_field",
+            CompileErrorKind::UnknownField => "unknown_field",
+            CompileErrorKind::NonConstantDefault => "non_constant_default",
+            CompileErrorKind::DeniedWarning => "denied_warning",
+        }
+    }
+}
+
+/// An error from compiling a program, and where it is in the source.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CompileError {
+    pub kind: CompileErrorKind,
+    /// The innermost statement containing the error, if the program has spans.
+    pub span: Option<Span>,
+    /// For a [`CompileErrorKind::DuplicateDefinition`], the earlier definition.
+    pub earlier: Option<Span>,
+    pub message: String,
+}
+
+impl CompileError {
+    pub fn new(kind: CompileErrorKind, message: impl Into<String>) -> Self {
+        Self { kind, span: None, earlier: None, message: message.into() }
+    }
+
+    // Places an error that has no location yet in `span`
+    fn within(mut self, span: Span) -> Self {
+        if self.span.is_none() && span != Span::default() {
+            self.span = Some(span);
+        }
+        self
+    }
+
+    // Records the earlier definition a duplicate clashes with
+    fn after(mut self, earlier: Span) -> Self {
+        if earlier != Span::default() {
+            self.earlier = Some(earlier);
+        }
+        self
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+impl From<ValidationError> for CompileError {
+    fn from(error: ValidationError) -> Self {
+        CompileError::new(CompileErrorKind::InvalidBytecode, error.to_string())
+    }
+}
+
+impl From<CompileError> for Diagnostic {
+    fn from(error: CompileError) -> Self {
+        let diagnostic = Diagnostic::new(error.message, error.span.unwrap_or_default()).with_code(error.kind.code());
+        match error.earlier {
+            Some(earlier) => diagnostic.with_note("first defined here", earlier),
+            None => diagnostic,
+        }
+    }
+}
+
+/// How a [`BytecodeCompiler`] compiles, for
+/// [`BytecodeCompiler::with_options`]. The default is the compiler that
+/// [`BytecodeCompiler::new`] gives.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CompilerOptions {
+    /// Whether [`compile_script`](BytecodeCompiler::compile_script)
+    /// [optimizes](optimize) the script, as `voltagec -O` does. Programs
+    /// from [`compile_program`](BytecodeCompiler::compile_program) aren't
+    /// optimized, since code compiled later can change their globals.
+    pub optimize: bool,
+    /// Whether programs have a [`SourceMap`], which runtime errors, coverage
+    /// and the debugger need to say where in the source they are.
+    pub emit_debug_info: bool,
+    /// Whether any warning stops the program compiling, with a
+    /// [`CompileErrorKind::DeniedWarning`] for the first one. The warnings
+    /// all become errors.
+    pub deny_warnings: bool,
+}
+
+impl Default for CompilerOptions {
+    fn default() -> Self {
+        Self { optimize: false, emit_debug_info: true, deny_warnings: false }
+    }
+}
+
+/// `program` with its constant globals loaded as constants, then its calls
+/// to small functions inlined: what [`CompilerOptions::optimize`] does to a
+/// script. Only for whole programs; see [`propagate_constants`].
+pub fn optimize(program: Program) -> Program {
+    inline_functions(propagate_constants(program), INLINE_LIMIT)
+}
 
 pub struct BytecodeCompiler {
+    options: CompilerOptions,
     bytecode: Vec<Bytecode>,
     constants: Vec<RuntimeValue>,
+    // Where each scalar constant already is in `constants`
+    constant_slots: HashMap<ConstantKey, usize>,
+    source_map: SourceMap,
+    // What each name in the program refers to
+    names: Resolver,
+    // The local slots of the function being compiled, for the source map
+    locals: Vec<LocalVariable>,
+    // The `break` and `continue` jumps of each enclosing loop, innermost last
+    loops: Vec<LoopJumps>,
+    // How many `try` bodies enclose the code being compiled
+    tries: usize,
+    // Functions kept however unused; `None` to keep every function
+    exports: Option<Vec<String>>,
+    // Whether a variable at the top of a function body may hide a parameter
+    // without a warning
+    parameter_shadowing: bool,
+    // The statements of the module files the program imported
+    imported: Vec<Statement>,
+    // Which module file each of the top-level statements the program starts
+    // with comes from: the path of each, and how many in a row it has
+    module_code: Vec<(String, usize)>,
+    warnings: Vec<Diagnostic>,
+}
+
+impl Default for BytecodeCompiler {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl BytecodeCompiler {
     pub fn new() -> Self {
+        Self::with_options(CompilerOptions::default())
+    }
+
+    /// A compiler that optimizes, keeps debug info and treats warnings as
+    /// `options` says.
+    pub fn with_options(options: CompilerOptions) -> Self {
         Self {
+            options,
             bytecode: Vec::new(),
             constants: Vec::new(),
+            constant_slots: HashMap::new(),
+            source_map: SourceMap::new(),
+            names: Resolver::new(),
+            locals: Vec::new(),
+            loops: Vec::new(),
+            tries: 0,
+            exports: None,
+            parameter_shadowing: false,
+            imported: Vec::new(),
+            module_code: Vec::new(),
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Checks calls to builtins against `builtins`, for programs that will
+    /// run on a VM with host functions of its own.
+    pub fn use_builtins(&mut self, builtins: &BuiltinRegistry) {
+        self.names.builtins = builtins.clone();
+    }
+
+    /// Resolves `import`s against `modules` rather than the standard
+    /// library alone, and looks for module files in its search paths.
+    pub fn use_modules(&mut self, modules: &ModuleLoader) {
+        self.names.modules = modules.clone();
+    }
+
+    /// The modules `import`s were resolved against, with the module files
+    /// the program imported among them.
+    pub(crate) fn modules(&self) -> &ModuleLoader {
+        &self.names.modules
+    }
+
+    /// The statements of the module files the program imported, which
+    /// [`compile_program`](BytecodeCompiler::compile_program) put ahead of
+    /// its own.
+    pub(crate) fn imported(&self) -> &[Statement] {
+        &self.imported
+    }
+
+    /// Rejects a call to the builtin `name` that passes `count` arguments it
+    /// doesn't take.
+    fn check_builtin_call(&self, name: &str, count: usize) -> Result<(), CompileError> {
+        self.check_call_to(name, name, count)
+    }
+
+    // Like `check_builtin_call`, for a builtin that the source calls as
+    // `called_as`, such as `math.sqrt`
+    fn check_call_to(&self, builtin: &str, called_as: &str, count: usize) -> Result<(), CompileError> {
+        match self.names.builtins.arity(builtin) {
+            Some(arity) if !arity.accepts(count) => Err(CompileError::new(
+                CompileErrorKind::ArityMismatch,
+                format!("{} expects {}, got {}", called_as, arity, count),
+            )),
+            _ => Ok(()),
         }
     }
 
     fn add_constant(&mut self, value: RuntimeValue) -> usize {
-        // For now, just add every constant - we can optimize later
+        let key = ConstantKey::of(&value);
+        if let Some(&index) = key.as_ref().and_then(|key| self.constant_slots.get(key)) {
+            return index;
+        }
         let index = self.constants.len();
         self.constants.push(value);
+        if let Some(key) = key {
+            self.constant_slots.insert(key, index);
+        }
         index
     }
 
-    pub fn compile_function(&mut self, func: &Function) -> Result<(Vec<Bytecode>, Vec<RuntimeValue>), String> {
-        // For now, let's compile a simple example
-        // In a real implementation, we'd walk the AST
-        
-        for stmt in &func.body {
-            self.compile_statement(stmt)?;
-        }
+    pub fn compile_function(&mut self, func: &Function) -> Result<(Vec<Bytecode>, Vec<RuntimeValue>), CompileError> {
+        self.compile_body(func, Span::default())?;
         
         // Add return null if needed
         let const_index = self.add_constant(RuntimeValue::Integer(0));
@@ -37,89 +344,591 @@ impl BytecodeCompiler {
         Ok((self.bytecode.clone(), self.constants.clone()))
     }
 
-    fn compile_statement(&mut self, stmt: &Statement) -> Result<(), String> {
-        match stmt {
-            Statement::Expression(expr) => {
-                self.compile_expression(expr)?;
-                // Pop the result since expressions as statements don't return anything
-                self.bytecode.push(Bytecode::Pop);
+    /// Leaves out of compiled programs every function that can't be reached
+    /// from the top-level code, `main` or one of `exports`, with a warning for
+    /// each. A function is reached when reachable code calls it by name or
+    /// uses it as a value, so functions passed around stay in.
+    pub fn eliminate_dead_code(&mut self, exports: impl IntoIterator<Item = impl Into<String>>) {
+        self.exports = Some(exports.into_iter().map(Into::into).collect());
+    }
+
+    /// Lets a variable declared at the top level of a function body hide a
+    /// parameter of the same name without the `shadowed_parameter` warning.
+    /// Variables in the body's blocks never get it.
+    pub fn allow_parameter_shadowing(&mut self) {
+        self.parameter_shadowing = true;
+    }
+
+    /// Problems that didn't stop the program compiling, such as functions
+    /// that [dead code elimination](BytecodeCompiler::eliminate_dead_code) left
+    /// out, or variables that shadow a builtin.
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
+    /// Compiles a whole program: every top-level function plus the top-level
+    /// statements, which run in order once the functions have been defined as
+    /// globals. The value of a trailing expression statement is the program's
+    /// result.
+    ///
+    /// The module files the program imports, and the ones those import, are
+    /// compiled with it: their top-level functions and variables become
+    /// globals under full names like `utils::helper`, and their top-level
+    /// statements run before the program's. A module's own code uses its
+    /// names as they're written; anywhere else they're only usable through
+    /// an import of the module, and only if they're `pub`.
+    pub fn compile_program(&mut self, program: &[Statement]) -> Result<Program, CompileError> {
+        let compiled = self.compile_statements(program)?;
+        self.finish(compiled, false)
+    }
+
+    // Applies the options to a compiled program, optimizing it if it's a
+    // whole script
+    fn finish(&mut self, mut program: Program, script: bool) -> Result<Program, CompileError> {
+        if self.options.deny_warnings && !self.warnings.is_empty() {
+            self.warnings.iter_mut().for_each(|warning| warning.severity = Severity::Error);
+            let first = &self.warnings[0];
+            let error = CompileError::new(CompileErrorKind::DeniedWarning, format!("{} (warnings are denied)", first.message));
+            return Err(error.within(first.span));
+        }
+        if script && self.options.optimize {
+            program = optimize(program);
+        }
+        if !self.options.emit_debug_info {
+            program.source_map = SourceMap::new();
+        }
+        Ok(program)
+    }
+
+    fn compile_statements(&mut self, program: &[Statement]) -> Result<Program, CompileError> {
+        let mut imported = Vec::new();
+        self.load_module_files(program, &mut imported)?;
+        self.imported = imported.clone();
+        imported.extend_from_slice(program);
+        let program = &self.define_structs(&imported)?;
+        let program = &methods_as_functions(program)?;
+        check_definitions(program)?;
+        let program = &nested_as_functions(program, &mut self.names)?;
+        let mut globals = HashSet::new();
+        declared_globals(program, &mut globals);
+        // Functions are globals too, but calls to them are checked against
+        // their parameters
+        for stmt in program {
+            if let StatementKind::Function(func) = &stmt.kind {
+                globals.remove(&func.name);
             }
-            Statement::VariableDeclaration { name, value, explicit_type: _ } => {
-                // Compile the value
-                self.compile_expression(value)?;
-                // For now, we'll just pop it since we don't have proper local variable handling
-                self.bytecode.push(Bytecode::Pop);
-                println!("Compiling variable declaration: {}", name);
+        }
+        globals.iter().for_each(|name| self.names.declare_global(name));
+        self.import_modules(program)?;
+        let Some(exports) = &self.exports else {
+            return self.compile_all(program);
+        };
+
+        // Which functions are used is read from the code of the whole program
+        let mut full = BytecodeCompiler::new();
+        full.names = self.names.clone();
+        full.module_code = self.module_code.clone();
+        let compiled = full.compile_all(program).inspect_err(|_| {
+            // What was found before the error is still worth reporting
+            self.warnings.append(&mut full.warnings);
+        })?;
+        let reachable = reachable_functions(&compiled, exports);
+
+        let mut kept = Vec::new();
+        for stmt in program {
+            match &stmt.kind {
+                // A module's functions are there for whichever of them the
+                // program uses
+                StatementKind::Function(func) if !reachable.contains(&func.name) && self.is_imported(&func.name) => {}
+                StatementKind::Function(func) if !reachable.contains(&func.name) => {
+                    self.warnings
+                        .push(Diagnostic::warning(format!("function `{}` is never used", func.name), stmt.span).with_code("unused_function"));
+                }
+                _ => kept.push(stmt.clone()),
             }
-            Statement::Block(statements) => {
-                for stmt in statements {
-                    self.compile_statement(stmt)?;
+        }
+        self.compile_all(&kept)
+    }
+
+    fn compile_all(&mut self, program: &[Statement]) -> Result<Program, CompileError> {
+        // Function bodies come first; jump over them to the entry code
+        let entry_jump = self.bytecode.len();
+        self.bytecode.push(Bytecode::Jump(0));
+        
+        for stmt in program {
+            if let StatementKind::Function(func) = &stmt.kind {
+                self.names.define_function(&func.name, func.parameters.len());
+                for (name, _) in &func.parameters {
+                    self.warn_if_shadowing_builtin(name, stmt.span);
                 }
             }
-            Statement::Function(_) => {
-                return Err("Nested functions not implemented yet".to_string());
+        }
+        
+        let mut functions = Vec::new();
+        for stmt in program {
+            if let StatementKind::Function(func) = &stmt.kind {
+                let start = self.bytecode.len();
+                self.compile_function_body(func, stmt.span)?;
+                // The implicit return belongs to the function as a whole
+                self.source_map.record(start..self.bytecode.len(), stmt.span);
+                functions.push(FunctionEntry {
+                    name: func.name.clone(),
+                    start,
+                    end: self.bytecode.len(),
+                    num_params: func.parameters.len(),
+                });
             }
-            Statement::If { condition, then_branch, elif_branches, else_branch } => {
-                // For now, let's just compile all branches without actual control flow
-                // In a real implementation, we'd use conditional jumps
-                self.compile_expression(condition)?;
-                for stmt in then_branch {
+        }
+        
+        self.bytecode[entry_jump] = Bytecode::Jump(self.bytecode.len());
+        
+        for function in &functions {
+            let index = self.add_constant(RuntimeValue::Function {
+                name: function.name.clone(),
+                ip: function.start,
+                num_params: function.num_params,
+            });
+            self.bytecode.push(Bytecode::LoadConst(index));
+            self.bytecode.push(Bytecode::StoreGlobal(function.name.clone()));
+        }
+        
+        let statements: Vec<&Statement> = program
+            .iter()
+            .filter(|stmt| !matches!(stmt.kind, StatementKind::Function(_)))
+            .collect();
+        // The code of the module files comes first, then the program's own
+        let mut modules = self
+            .module_code
+            .clone()
+            .into_iter()
+            .flat_map(|(path, count)| std::iter::repeat_n(Some(path), count))
+            .chain(std::iter::repeat(None));
+        
+        match statements.split_last() {
+            Some((Statement { kind: StatementKind::Expression(last), span }, rest)) => {
+                for stmt in rest {
+                    self.names.set_module(modules.next().flatten().as_deref());
+                    self.compile_statement(stmt)?;
+                }
+                self.names.set_module(modules.next().flatten().as_deref());
+                let start = self.bytecode.len();
+                self.compile_expression(last).map_err(|e| e.within(*span))?;
+                self.source_map.record(start..self.bytecode.len(), *span);
+            }
+            _ => {
+                for stmt in &statements {
+                    self.names.set_module(modules.next().flatten().as_deref());
                     self.compile_statement(stmt)?;
                 }
-                for (elif_condition, elif_body) in elif_branches {
-                    self.compile_expression(elif_condition)?;
-                    for stmt in elif_body {
-                        self.compile_statement(stmt)?;
+                let index = self.add_constant(RuntimeValue::Null);
+                self.bytecode.push(Bytecode::LoadConst(index));
+            }
+        }
+        self.names.set_module(None);
+        self.bytecode.push(Bytecode::Return);
+        
+        Ok(Program {
+            bytecode: self.bytecode.clone(),
+            constants: self.constants.clone(),
+            functions,
+            source_map: self.source_map.clone(),
+        })
+    }
+
+    /// Compiles a script file. A script either defines `main`, for its runner
+    /// to call, or has top-level statements, which run in order as its entry
+    /// point and can call every function the file declares. Top-level `let`s
+    /// and imports are fine alongside `main`; other statements are an error.
+    ///
+    /// A script is the whole program, so unlike [`compile_program`] this also
+    /// rejects variables that the script never declares.
+    ///
+    /// [`compile_program`]: BytecodeCompiler::compile_program
+    pub fn compile_script(&mut self, program: &[Statement]) -> Result<Program, CompileError> {
+        let has_main = program
+            .iter()
+            .any(|stmt| matches!(&stmt.kind, StatementKind::Function(func) if func.name == "main"));
+        let statement = program.iter().find(|stmt| {
+            !matches!(
+                stmt.kind,
+                StatementKind::Function(_)
+                    | StatementKind::Impl { .. }
+                    | StatementKind::Struct { .. }
+                    | StatementKind::VariableDeclaration { .. }
+                    | StatementKind::Import(_)
+                    | StatementKind::ImportAs(..)
+            )
+        });
+        if let (true, Some(statement)) = (has_main, statement) {
+            let error = CompileError::new(
+                CompileErrorKind::MixedMainAndStatements,
+                "cannot mix top-level statements with fn main",
+            );
+            return Err(error.within(statement.span));
+        }
+        self.names.require_declarations();
+        let compiled = self.compile_statements(program)?;
+        self.finish(compiled, true)
+    }
+
+    // Records the `struct` definitions in `program` for initializers to be
+    // checked against, and returns the rest of it. A field's default has to
+    // be a constant, and is worked out here.
+    fn define_structs(&mut self, program: &[Statement]) -> Result<Vec<Statement>, CompileError> {
+        let mut statements = Vec::new();
+        let mut defined: HashMap<&str, Span> = HashMap::new();
+        for stmt in program {
+            let StatementKind::Struct { name, fields } = &stmt.kind else {
+                statements.push(stmt.clone());
+                continue;
+            };
+            if let Some(&earlier) = defined.get(name.as_str()) {
+                let message = format!("duplicate definition of struct `{}`", name);
+                return Err(CompileError::new(CompileErrorKind::DuplicateDefinition, message).within(stmt.span).after(earlier));
+            }
+            defined.insert(name, stmt.span);
+            let mut defaults: Vec<(String, Option<RuntimeValue>)> = Vec::new();
+            for field in fields {
+                if defaults.iter().any(|(name, _)| *name == field.name) {
+                    let message = format!("field `{}` of `{}` is defined twice", field.name, name);
+                    return Err(CompileError::new(CompileErrorKind::DuplicateDefinition, message).within(stmt.span));
+                }
+                let default = match &field.default {
+                    Some(value) => {
+                        types::check_declaration(&field.name, &field.field_type, value)
+                            .map_err(|message| CompileError::new(CompileErrorKind::TypeMismatch, message).within(stmt.span))?;
+                        let Some(value) = constant_value(value) else {
+                            let message = format!("the default of `{}.{}` must be a constant", name, field.name);
+                            return Err(CompileError::new(CompileErrorKind::NonConstantDefault, message).within(stmt.span));
+                        };
+                        Some(value)
                     }
+                    None => None,
+                };
+                defaults.push((field.name.clone(), default));
+            }
+            self.names.define_struct(name, defaults);
+        }
+        Ok(statements)
+    }
+
+    // Loads the modules that `statements` import from files, if the loader
+    // doesn't have them, along with the modules those import in turn. Each
+    // comes from the first search path with a file for it, and its
+    // statements go in `imported` after those of the modules it imports.
+    fn load_module_files(&mut self, statements: &[Statement], imported: &mut Vec<Statement>) -> Result<(), CompileError> {
+        for stmt in statements {
+            let (StatementKind::Import(path) | StatementKind::ImportAs(path, _)) = &stmt.kind else {
+                continue;
+            };
+            if self.names.modules.get(path).is_some() {
+                continue;
+            }
+            // `import_modules` reports the modules that aren't anywhere
+            let files = self.names.modules.find_script(path);
+            let Some((file, others)) = files.split_first() else {
+                continue;
+            };
+            if !others.is_empty() {
+                let others: Vec<String> = others.iter().map(|other| other.display().to_string()).collect();
+                let message = format!("module `{}` is also in {}; using {}", path, others.join(", "), file.display());
+                self.warnings.push(Diagnostic::warning(message, stmt.span).with_code("duplicate_module"));
+            }
+            let statements = read_module_file(file)
+                .map_err(|message| CompileError::new(CompileErrorKind::InvalidModule, message).within(stmt.span))?;
+            // Added before its own imports are loaded, so that modules
+            // importing each other are only loaded once
+            let (statements, module) = qualify(path, statements);
+            self.names.modules.insert(path, module);
+            self.load_module_files(&statements, imported)?;
+            let top_level = statements
+                .iter()
+                .filter(|stmt| !matches!(stmt.kind, StatementKind::Function(_) | StatementKind::Struct { .. } | StatementKind::Impl { .. }))
+                .count();
+            self.module_code.push((path.clone(), top_level));
+            imported.extend(statements);
+        }
+        Ok(())
+    }
+
+    // Whether the function `name` came from a module file
+    fn is_imported(&self, name: &str) -> bool {
+        self.imported.iter().any(|stmt| matches!(&stmt.kind, StatementKind::Function(func) if func.name == name))
+    }
+
+    // Makes the modules that `program` imports usable everywhere in it, under
+    // their aliases or the last part of their paths
+    fn import_modules(&mut self, program: &[Statement]) -> Result<(), CompileError> {
+        for stmt in program {
+            let (path, alias) = match &stmt.kind {
+                StatementKind::Import(path) => (path, path.rsplit('.').next().unwrap_or(path)),
+                StatementKind::ImportAs(path, alias) => (path, alias.as_str()),
+                _ => continue,
+            };
+            if self.names.modules.get(path).is_none() {
+                let mut message = format!("unknown module `{}`; the modules are {}", path, self.names.modules.paths().join(", "));
+                let searched: Vec<String> = self.names.modules.search_paths().map(|dir| dir.display().to_string()).collect();
+                if !searched.is_empty() {
+                    message += &format!(", and {} is in none of {}", script_file(path).display(), searched.join(", "));
+                }
+                return Err(CompileError::new(CompileErrorKind::UnknownModule, message).within(stmt.span));
+            }
+            self.names.import(alias, path);
+        }
+        Ok(())
+    }
+
+    fn compile_function_body(&mut self, func: &Function, span: Span) -> Result<(), CompileError> {
+        self.compile_body(func, span)?;
+        
+        // Falling off the end of a function returns null
+        let const_index = self.add_constant(RuntimeValue::Null);
+        self.bytecode.push(Bytecode::LoadConst(const_index));
+        self.bytecode.push(Bytecode::Return);
+        Ok(())
+    }
+
+    // Compiles the body of `func`, declared at `span`, warning about the
+    // parameters and locals it never reads
+    fn compile_body(&mut self, func: &Function, span: Span) -> Result<(), CompileError> {
+        // The parser rejects these too, but not every program comes from it
+        let parameters = || func.parameters.iter().map(|(name, _)| name).filter(|name| *name != "_");
+        if let Some(name) = parameters().enumerate().find_map(|(i, name)| parameters().skip(i + 1).find(|other| *other == name)) {
+            let message = format!("duplicate parameter `{}` in `{}`", name, func.name);
+            return Err(CompileError::new(CompileErrorKind::DuplicateDefinition, message).within(span));
+        }
+        // Parameters occupy the first local slots, in order
+        self.names.enter_function(&func.name, func.parameters.iter().map(|(name, _)| name.as_str()), span);
+        let start = self.bytecode.len();
+        self.locals = func
+            .parameters
+            .iter()
+            .enumerate()
+            .map(|(slot, (name, _))| LocalVariable { slot, name: name.clone(), span, scope: start..OPEN_SCOPE })
+            .collect();
+        let result = func.body.iter().try_for_each(|stmt| self.compile_statement(stmt));
+        self.close_scopes(0..self.locals.len());
+        let locals = std::mem::take(&mut self.locals);
+        self.source_map.record_locals(&func.name, locals);
+        for local in self.names.leave_function() {
+            let what = if local.parameter { "parameter" } else { "variable" };
+            let message = format!("unused {} `{}`; name it `_` if that's intended", what, local.name);
+            self.warnings.push(Diagnostic::warning(message, local.span).with_code("unused_variable"));
+        }
+        if let Some(call) = unconditional_recursion(func) {
+            let message = format!("`{}` calls itself on every path, so it can never return", func.name);
+            let warning = Diagnostic::warning(message, call).with_code("unconditional_recursion");
+            self.warnings.push(warning.with_note("defined here; it needs a branch that returns without the call", span));
+        }
+        result
+    }
+
+    // Builtins can be shadowed, but a call meant for the builtin would then
+    // reach the variable instead
+    fn warn_if_shadowing_builtin(&mut self, name: &str, span: Span) {
+        if self.names.builtins.id(name).is_some() {
+            self.warnings.push(Diagnostic::warning(format!("`{}` shadows a builtin function", name), span).with_code("shadowed_builtin"));
+        }
+    }
+
+    fn compile_statement(&mut self, stmt: &Statement) -> Result<(), CompileError> {
+        if let StatementKind::VariableDeclaration { name, .. } | StatementKind::For { variable: name, .. } = &stmt.kind {
+            self.warn_if_shadowing_builtin(name, stmt.span);
+        }
+        let start = self.bytecode.len();
+        self.compile_statement_kind(&stmt.kind, stmt.span).map_err(|e| e.within(stmt.span))?;
+        self.source_map.record(start..self.bytecode.len(), stmt.span);
+        Ok(())
+    }
+
+    fn compile_statement_kind(&mut self, kind: &StatementKind, span: Span) -> Result<(), CompileError> {
+        match kind {
+            StatementKind::Expression(expr) => {
+                self.compile_expression(expr)?;
+                // Pop the result since expressions as statements don't return anything
+                self.bytecode.push(Bytecode::Pop);
+            }
+            StatementKind::VariableDeclaration { name, value, explicit_type, .. } => {
+                if let Some(declared) = explicit_type {
+                    types::check_declaration(name, declared, value)
+                        .map_err(|message| CompileError::new(CompileErrorKind::TypeMismatch, message))?;
+                }
+                self.compile_expression(value)?;
+                self.declare_variable(name, span);
+            }
+            StatementKind::Block(statements) => self.compile_block(statements)?,
+            StatementKind::Function(func) => {
+                return Err(CompileError::new(
+                    CompileErrorKind::NestedFunctionUnsupported,
+                    format!("`fn {}` must be at the top level or directly in a function body", func.name),
+                ));
+            }
+            StatementKind::Impl { type_name, .. } => {
+                return Err(CompileError::new(
+                    CompileErrorKind::NestedFunctionUnsupported,
+                    format!("`impl {}` must be at the top level", type_name),
+                ));
+            }
+            StatementKind::Struct { name, .. } => {
+                return Err(CompileError::new(
+                    CompileErrorKind::NestedFunctionUnsupported,
+                    format!("`struct {}` must be at the top level", name),
+                ));
+            }
+            StatementKind::If { condition, then_branch, elif_branches, else_branch } => {
+                // Each condition that fails jumps to the next one; each branch
+                // that runs jumps past the rest
+                let mut end_jumps = Vec::new();
+                let branches = std::iter::once((condition, then_branch))
+                    .chain(elif_branches.iter().map(|(condition, body)| (condition, body)));
+                for (condition, body) in branches {
+                    self.compile_expression(condition)?;
+                    let next = self.emit_jump(Bytecode::JumpIfFalse);
+                    self.compile_block(body)?;
+                    end_jumps.push(self.emit_jump(Bytecode::Jump));
+                    self.patch_jump(next);
                 }
                 if let Some(else_body) = else_branch {
-                    for stmt in else_body {
-                        self.compile_statement(stmt)?;
-                    }
+                    self.compile_block(else_body)?;
+                }
+                for jump in end_jumps {
+                    self.patch_jump(jump);
                 }
             }
-            Statement::While { condition, body } => {
-                // For now, just compile without actual looping
-                // In a real implementation, we'd use jumps for the loop
+            StatementKind::While { condition, body } => {
+                let start = self.bytecode.len();
                 self.compile_expression(condition)?;
-                for stmt in body {
-                    self.compile_statement(stmt)?;
-                }
+                let exit = self.emit_jump(Bytecode::JumpIfFalse);
+                let jumps = self.compile_loop_body(body, false)?;
+                jumps.continues.into_iter().for_each(|jump| self.patch_jump(jump));
+                self.bytecode.push(Bytecode::Jump(start));
+                self.patch_jump(exit);
+                jumps.breaks.into_iter().for_each(|jump| self.patch_jump(jump));
             }
-            Statement::For { iterable, body, .. } => {
-                // For now, just compile without actual iteration
-                // In a real implementation, we'd handle iteration properly
+            StatementKind::For { variable, iterable, body } => {
+                // Walks the array, string or range by index. The sequence and
+                // the index live in variables that source code can't name.
+                self.names.begin_scope();
+                let sequence = format!("$sequence{}", self.bytecode.len());
+                let index = format!("$index{}", self.bytecode.len());
                 self.compile_expression(iterable)?;
-                for stmt in body {
-                    self.compile_statement(stmt)?;
+                self.declare_variable(&sequence, span);
+                let zero = self.add_constant(RuntimeValue::Integer(0));
+                self.bytecode.push(Bytecode::LoadConst(zero));
+                self.declare_variable(&index, span);
+
+                let start = self.bytecode.len();
+                self.load_variable(&index)?;
+                self.load_variable(&sequence)?;
+                self.bytecode.push(Bytecode::Len);
+                self.bytecode.push(Bytecode::Lt);
+                let exit = self.emit_jump(Bytecode::JumpIfFalse);
+
+                self.load_variable(&sequence)?;
+                self.load_variable(&index)?;
+                self.bytecode.push(Bytecode::IndexGet);
+                self.declare_variable(variable, span);
+                let jumps = self.compile_loop_body(body, false)?;
+
+                jumps.continues.into_iter().for_each(|jump| self.patch_jump(jump));
+                self.load_variable(&index)?;
+                let one = self.add_constant(RuntimeValue::Integer(1));
+                self.bytecode.push(Bytecode::LoadConst(one));
+                self.bytecode.push(Bytecode::Add);
+                self.store_variable(&index);
+                self.bytecode.push(Bytecode::Jump(start));
+                self.patch_jump(exit);
+                jumps.breaks.into_iter().for_each(|jump| self.patch_jump(jump));
+                self.end_scope();
+            }
+            StatementKind::Loop(body) => {
+                self.compile_loop(body)?;
+                self.bytecode.push(Bytecode::Pop);
+            }
+            StatementKind::Break(value) => {
+                let Some(&LoopJumps { yields_value, tries, .. }) = self.loops.last() else {
+                    return Err(CompileError::new(CompileErrorKind::BreakOutsideLoop, "`break` outside of a loop"));
+                };
+                // Every way out of a `loop` leaves it a value
+                match (value, yields_value) {
+                    (Some(value), true) => self.compile_expression(value)?,
+                    (None, true) => {
+                        let null = self.add_constant(RuntimeValue::Null);
+                        self.bytecode.push(Bytecode::LoadConst(null));
+                    }
+                    (Some(_), false) => {
+                        return Err(CompileError::new(
+                            CompileErrorKind::BreakWithValue,
+                            "`break` with a value can only leave a `loop`",
+                        ));
+                    }
+                    (None, false) => {}
+                }
+                self.leave_tries(tries);
+                let jump = self.emit_jump(Bytecode::Jump);
+                if let Some(jumps) = self.loops.last_mut() {
+                    jumps.breaks.push(jump);
                 }
             }
-            Statement::Break | Statement::Continue => {
-                // These would be handled in a proper loop context
-                // For now, just compile as no-op
+            StatementKind::Continue => {
+                let Some(tries) = self.loops.last().map(|jumps| jumps.tries) else {
+                    return Err(CompileError::new(CompileErrorKind::BreakOutsideLoop, "`continue` outside of a loop"));
+                };
+                self.leave_tries(tries);
+                let jump = self.emit_jump(Bytecode::Jump);
+                if let Some(jumps) = self.loops.last_mut() {
+                    jumps.continues.push(jump);
+                }
             }
-            Statement::UnsafeBlock(statements) => {
-                // For now, just compile the contents of the unsafe block
-                for stmt in statements {
-                    self.compile_statement(stmt)?;
+            StatementKind::Return(value) => {
+                match value {
+                    Some(expr) => self.compile_expression(expr)?,
+                    None => {
+                        let index = self.add_constant(RuntimeValue::Null);
+                        self.bytecode.push(Bytecode::LoadConst(index));
+                    }
+                }
+                // A call whose result is returned as it is can reuse the frame
+                // of the function making it, unless a `try` should catch its
+                // errors
+                if let (true, 0, Some(last)) = (self.names.in_function(), self.tries, self.bytecode.last_mut()) {
+                    if let Bytecode::Call(num_args) = *last {
+                        *last = Bytecode::TailCall(num_args);
+                    }
                 }
+                self.bytecode.push(Bytecode::Return);
             }
-            Statement::Import(module_name) => {
-                // In a full implementation, this would load the specified module
-                // For now, just compile to a no-op
-                // Add the imported module to the module registry
-                println!("Importing module: {}", module_name);
+            StatementKind::TryCatch { body, error_binding, binding_span, handler } => {
+                // The VM jumps to the handler with the error message on the
+                // stack, which the handler's first instruction stores
+                let catch = self.emit_jump(Bytecode::PushHandler);
+                self.tries += 1;
+                let result = self.compile_block(body);
+                self.tries -= 1;
+                result?;
+                self.bytecode.push(Bytecode::PopHandler);
+                let end = self.emit_jump(Bytecode::Jump);
+
+                self.patch_jump(catch);
+                self.names.begin_scope();
+                self.warn_if_shadowing_builtin(error_binding, *binding_span);
+                self.declare_variable(error_binding, *binding_span);
+                let result = handler.iter().try_for_each(|stmt| self.compile_statement(stmt));
+                self.end_scope();
+                result?;
+                self.patch_jump(end);
             }
-            Statement::ImportAs(module_name, alias) => {
-                // Import module with an alias
-                // In a full implementation, register the module with the given alias
-                println!("Importing module: {} as {}", module_name, alias);
+            StatementKind::UnsafeBlock(statements) => {
+                // For now, just compile the contents of the unsafe block
+                self.compile_block(statements)?;
             }
+            // Imports take effect before anything runs, see `import_modules`
+            StatementKind::Import(_) | StatementKind::ImportAs(..) => {}
         }
         Ok(())
     }
 
-    fn compile_expression(&mut self, expr: &Expression) -> Result<(), String> {
+    fn compile_expression(&mut self, expr: &Expression) -> Result<(), CompileError> {
         match expr {
             Expression::Literal(literal) => {
                 let value = self.literal_to_runtime_value(literal)?;
@@ -127,31 +936,47 @@ impl BytecodeCompiler {
                 self.bytecode.push(Bytecode::LoadConst(index));
             }
             Expression::VariableDeclaration { .. } => {
-                return Err("VariableDeclaration expression not expected in this context".to_string());
-            }
-            Expression::Variable(name) => {
-                // For now, assume it's a global variable
-                self.bytecode.push(Bytecode::LoadGlobal(name.clone()));
+                return Err(CompileError::new(
+                    CompileErrorKind::UnexpectedDeclaration,
+                    "VariableDeclaration expression not expected in this context",
+                ));
             }
+            Expression::Variable(name) => self.load_variable(name)?,
             Expression::Binary { left, operator, right } => {
                 // Compile left operand
                 self.compile_expression(left)?;
                 // Compile right operand
                 self.compile_expression(right)?;
                 // Apply operator
-                match operator {
-                    BinaryOp::Add => self.bytecode.push(Bytecode::Add),
-                    BinaryOp::Subtract => self.bytecode.push(Bytecode::Sub),
-                    BinaryOp::Multiply => self.bytecode.push(Bytecode::Mul),
-                    BinaryOp::Divide => self.bytecode.push(Bytecode::Div),
-                    BinaryOp::Modulo => self.bytecode.push(Bytecode::Mod), // Add Modulo operation
-                    BinaryOp::Equal => self.bytecode.push(Bytecode::Eq),
-                    BinaryOp::NotEqual => self.bytecode.push(Bytecode::Ne),
-                    BinaryOp::Less => self.bytecode.push(Bytecode::Lt),
-                    BinaryOp::LessEqual => self.bytecode.push(Bytecode::Le),
-                    BinaryOp::Greater => self.bytecode.push(Bytecode::Gt),
-                    BinaryOp::GreaterEqual => self.bytecode.push(Bytecode::Ge),
-                }
+                self.bytecode.push(binary_instruction(operator));
+            }
+            Expression::Unary { operator: UnaryOp::Negate, operand } => {
+                self.compile_expression(operand)?;
+                self.bytecode.push(Bytecode::Neg);
+            }
+            Expression::Logical { left, operator, right } => {
+                // Both operands must be booleans, so each goes through a
+                // conditional jump; `right` is skipped when `left` decides
+                let decides = match operator {
+                    LogicalOp::And => Bytecode::JumpIfFalse,
+                    LogicalOp::Or => Bytecode::JumpIfTrue,
+                };
+                self.compile_expression(left)?;
+                let left_decides = self.emit_jump(decides);
+                self.compile_expression(right)?;
+                let right_decides = self.emit_jump(decides);
+                let (decided, undecided) = match operator {
+                    LogicalOp::And => (false, true),
+                    LogicalOp::Or => (true, false),
+                };
+                let undecided = self.add_constant(RuntimeValue::Boolean(undecided));
+                self.bytecode.push(Bytecode::LoadConst(undecided));
+                let end = self.emit_jump(Bytecode::Jump);
+                self.patch_jump(left_decides);
+                self.patch_jump(right_decides);
+                let decided = self.add_constant(RuntimeValue::Boolean(decided));
+                self.bytecode.push(Bytecode::LoadConst(decided));
+                self.patch_jump(end);
             }
             Expression::Call { name, arguments } => {
                 // Compile arguments (push them on stack)
@@ -159,129 +984,312 @@ impl BytecodeCompiler {
                     self.compile_expression(arg)?;
                 }
                 
-                // Handle built-in functions specially
-                match name.as_str() {
-                    "print" => {
-                        if arguments.len() == 1 {
-                            self.bytecode.push(Bytecode::CallBuiltin(1));
-                        } else {
-                            return Err("print function expects 1 argument".to_string());
-                        }
+                match self.names.resolve(name) {
+                    // A local holding a function is called like any other value
+                    Some(Resolution::Local(slot)) => {
+                        self.bytecode.push(Bytecode::LoadLocal(slot));
+                        self.bytecode.push(Bytecode::Call(arguments.len()));
                     }
-                    "puts" => {
-                        if arguments.len() == 1 {
-                            self.bytecode.push(Bytecode::CallBuiltin(0));
-                        } else {
-                            return Err("puts function expects 1 argument".to_string());
-                        }
+                    Some(
+                        Resolution::Function(num_params)
+                        | Resolution::Nested(_, num_params)
+                        | Resolution::Own(_, Some(num_params)),
+                    ) if num_params != arguments.len() => {
+                        return Err(CompileError::new(
+                            CompileErrorKind::ArityMismatch,
+                            format!("Function {} expects {} arguments, got {}", name, num_params, arguments.len()),
+                        ));
                     }
-                    _ => {
-                        // For user-defined functions, push the function name and call
-                        // In a more complete implementation, we'd have function lookup
+                    Some(Resolution::Nested(function, _) | Resolution::Own(function, _)) => {
+                        let func_name_const = self.add_constant(RuntimeValue::String(function));
+                        self.bytecode.push(Bytecode::LoadConst(func_name_const));
+                        self.bytecode.push(Bytecode::Call(arguments.len()));
+                    }
+                    Some(Resolution::Captured(outer)) => return Err(self.captured(name, &outer)),
+                    None if self.names.defined_inside(name).is_some() => return Err(self.out_of_scope(name)),
+                    None if self.names.defined_in_module(name).is_some() => return Err(self.outside_module(name)),
+                    None => {
+                        return Err(CompileError::new(
+                            CompileErrorKind::UnknownFunction,
+                            format!("Unknown function: {}", name),
+                        ));
+                    }
+                    // Core builtins are called directly by id
+                    Some(Resolution::Builtin(id)) if id < BUILTINS.len() => {
+                        self.check_builtin_call(name, arguments.len())?;
+                        self.bytecode.push(Bytecode::CallBuiltin(id, 1));
+                    }
+                    resolution => {
+                        if let Some(Resolution::Builtin(_)) = resolution {
+                            self.check_builtin_call(name, arguments.len())?;
+                        }
+                        // Functions, registered builtins and globals holding functions are
+                        // looked up by name when called
                         let func_name_const = self.add_constant(RuntimeValue::String(name.clone()));
                         self.bytecode.push(Bytecode::LoadConst(func_name_const));
                         self.bytecode.push(Bytecode::Call(arguments.len()));
                     }
                 }
             }
+            Expression::IndirectCall { callee, arguments } => {
+                let member = self.module_member(callee)?;
+                if let (None, Expression::StructFieldAccess { object, field }) = (&member, callee.as_ref()) {
+                    // A method call: the value it's called on goes below the
+                    // arguments, and its struct picks the method when it runs.
+                    // The method gets a copy, which it hands back as it left
+                    // it, to be written back to where the copy came from
+                    let Some((name, steps)) = place(object) else {
+                        self.compile_expression(object)?;
+                        for arg in arguments {
+                            self.compile_expression(arg)?;
+                        }
+                        self.bytecode.push(Bytecode::CallMethod(field.clone(), arguments.len()));
+                        self.bytecode.push(Bytecode::Swap);
+                        self.bytecode.push(Bytecode::Pop);
+                        return Ok(());
+                    };
+                    let mut held = Vec::new();
+                    self.push_indexes(&steps, &mut held)?;
+                    self.load_through(name, &steps, &mut held)?;
+                    for arg in arguments {
+                        self.compile_expression(arg)?;
+                    }
+                    self.bytecode.push(Bytecode::CallMethod(field.clone(), arguments.len()));
+                    // [indexes.., receiver, result] -> [result, indexes.., receiver]
+                    held.pop();
+                    held.extend([Held::Value, Held::Container]);
+                    for _ in 1..held.len() {
+                        let bottom = held[0];
+                        self.roll(&mut held, bottom);
+                    }
+                    held.remove(0);
+                    return self.store_through(name, &steps, &mut held);
+                }
+                // The callee goes on top of its arguments, as a function value
+                match member {
+                    // Module functions are builtins, called by name
+                    Some((called_as, Member::Function(builtin))) => {
+                        self.check_call_to(&builtin, &called_as, arguments.len())?;
+                        for arg in arguments {
+                            self.compile_expression(arg)?;
+                        }
+                        let name = self.add_constant(RuntimeValue::String(builtin));
+                        self.bytecode.push(Bytecode::LoadConst(name));
+                    }
+                    // Anything else is evaluated before the arguments, then
+                    // rolled up over them
+                    _ => {
+                        self.compile_expression(callee)?;
+                        for arg in arguments {
+                            self.compile_expression(arg)?;
+                        }
+                        if !arguments.is_empty() {
+                            self.bytecode.push(Bytecode::Roll(arguments.len()));
+                        }
+                    }
+                }
+                self.bytecode.push(Bytecode::Call(arguments.len()));
+            }
+            // The value, then what `dbg` prints it with; it leaves the value
+            Expression::Debug { expression, source, line } => {
+                self.compile_expression(expression)?;
+                let source = self.add_constant(RuntimeValue::String(source.clone()));
+                self.bytecode.push(Bytecode::LoadConst(source));
+                let line = self.add_constant(RuntimeValue::Integer(*line as i64));
+                self.bytecode.push(Bytecode::LoadConst(line));
+                let id = builtin_id("dbg").expect("dbg is a core builtin");
+                self.bytecode.push(Bytecode::CallBuiltin(id, 3));
+            }
             Expression::FormatCall { name, format_string, arguments } => {
-                // For formatted calls, we need to compile all arguments
-                // For now, simplify by just taking the first argument (which should be the format string)
-                // and push all argument values for potential formatting
-                println!("Format call: {} with format string: {} and {} args", name, format_string, arguments.len());
-                
-                // Compile format string as constant
-                let fmt_value = RuntimeValue::String(format_string.clone());
-                let fmt_index = self.add_constant(fmt_value);
-                
-                // Compile all arguments
+                // The format string and its arguments go to the `format` builtin;
+                // print and puts then output the string it returns
+                self.check_builtin_call("format", arguments.len() + 1)?;
+                let fmt_const = self.add_constant(RuntimeValue::String(format_string.clone()));
+                self.bytecode.push(Bytecode::LoadConst(fmt_const));
                 for arg in arguments {
                     self.compile_expression(arg)?;
                 }
-                
-                // For now, compile as a regular builtin call since we don't have full formatting
-                // In a complete implementation, we'd handle format string substitution
-                match name.as_str() {
-                    "puts" => {
-                        self.bytecode.push(Bytecode::CallBuiltin(0)); // puts builtin
-                    }
-                    "print" => {
-                        self.bytecode.push(Bytecode::CallBuiltin(1)); // print builtin
-                    }
-                    _ => {
-                        return Err(format!("Unknown function: {}", name));
+                let format_name = self.add_constant(RuntimeValue::String("format".to_string()));
+                self.bytecode.push(Bytecode::LoadConst(format_name));
+                self.bytecode.push(Bytecode::Call(arguments.len() + 1));
+
+                match builtin_id(name) {
+                    Some(id) => self.bytecode.push(Bytecode::CallBuiltin(id, 1)),
+                    None if name == "format" => {}
+                    None => {
+                        return Err(CompileError::new(
+                            CompileErrorKind::UnknownFunction,
+                            format!("Unknown function: {}", name),
+                        ));
                     }
                 }
             }
             Expression::ArrayLiteral(elements) => {
-                // For now, compile array literals by pushing each element
-                // In a real implementation, we'd create an actual array object
+                // Arrays are homogeneous
+                types::infer_type(expr)
+                    .map_err(|message| CompileError::new(CompileErrorKind::TypeMismatch, message))?;
                 for element in elements {
                     self.compile_expression(element)?;
                 }
-                // For now, just push a placeholder indicating an array was created
-                let array_placeholder = RuntimeValue::String(format!("array_{}", elements.len()));
-                let index = self.add_constant(array_placeholder);
-                self.bytecode.push(Bytecode::LoadConst(index));
+                self.bytecode.push(Bytecode::MakeArray(elements.len()));
+            },
+            Expression::Range { start, end, inclusive } => {
+                self.compile_expression(start)?;
+                self.compile_expression(end)?;
+                self.bytecode.push(Bytecode::MakeRange(*inclusive));
             },
             Expression::ArrayAccess { array, index } => {
-                // For now, just compile the array and index expressions
-                // In a real implementation, we'd access the array element
                 self.compile_expression(array)?;
                 self.compile_expression(index)?;
-                // For now, push a placeholder value
-                let const_idx = self.add_constant(RuntimeValue::Integer(0));
-                self.bytecode.push(Bytecode::LoadConst(const_idx));
+                self.bytecode.push(Bytecode::IndexGet);
             },
             Expression::ArrayAssignment { array, index, value } => {
-                // For now, compile all components
-                // In a real implementation, we'd assign to the array element
-                self.compile_expression(array)?;
-                self.compile_expression(index)?;
-                self.compile_expression(value)?;
-                // Pop everything since assignment doesn't return a value
-                self.bytecode.push(Bytecode::Pop);
-                self.bytecode.push(Bytecode::Pop);
-                self.bytecode.push(Bytecode::Pop);
+                match array.as_ref() {
+                    // Containers are values, so the updated one replaces the
+                    // variable it came from. The variable is read after the
+                    // index and value, so that what they do to it is kept:
+                    // [index, value, array] -> [array, index, value]
+                    Expression::Variable(name) => {
+                        self.compile_expression(index)?;
+                        self.compile_expression(value)?;
+                        self.load_variable(name)?;
+                        self.bytecode.push(Bytecode::Roll(2));
+                        self.bytecode.push(Bytecode::Roll(2));
+                        self.bytecode.push(Bytecode::IndexSet);
+                        self.store_variable(name);
+                    }
+                    // A nested element, like `a[i][j]` or `p.xs[i]`: each
+                    // container on the way is updated and written back
+                    _ => {
+                        let Some((name, mut steps)) = place(array) else {
+                            return Err(CompileError::new(
+                                CompileErrorKind::InvalidAssignmentTarget,
+                                "Only an element of a variable can be assigned to",
+                            ));
+                        };
+                        steps.push(Step::Index(index));
+                        let mut held = Vec::new();
+                        self.push_indexes(&steps, &mut held)?;
+                        self.compile_expression(value)?;
+                        held.push(Held::Value);
+                        self.store_through(name, &steps, &mut held)?;
+                    }
+                }
                 // Push null for no return value
                 let const_idx = self.add_constant(RuntimeValue::Null);
                 self.bytecode.push(Bytecode::LoadConst(const_idx));
             },
-            Expression::StructDefinition { .. } => {
-                // Struct definitions are compile-time constructs, no runtime code needed
+            Expression::ArrayCompoundAssignment { array, index, operator, value } => {
+                // The array is loaded twice, so it has to be a plain variable;
+                // the index is evaluated once and duplicated. The element is
+                // read before the value is evaluated, and the array it's
+                // stored into after, as in plain assignment
+                let Expression::Variable(name) = array.as_ref() else {
+                    return Err(CompileError::new(
+                        CompileErrorKind::InvalidAssignmentTarget,
+                        "Compound assignment needs an array variable",
+                    ));
+                };
+                self.compile_expression(index)?;
+                self.bytecode.push(Bytecode::Dup);
+                self.load_variable(name)?;
+                // [index, index, array] -> [index, array, index]
+                self.bytecode.push(Bytecode::Swap);
+                self.bytecode.push(Bytecode::IndexGet);
+                self.compile_expression(value)?;
+                self.bytecode.push(binary_instruction(operator));
+                self.load_variable(name)?;
+                // [index, result, array] -> [array, index, result]
+                self.bytecode.push(Bytecode::Roll(2));
+                self.bytecode.push(Bytecode::Roll(2));
+                self.bytecode.push(Bytecode::IndexSet);
+                self.store_variable(name);
+                // Push null for no return value
                 let const_idx = self.add_constant(RuntimeValue::Null);
                 self.bytecode.push(Bytecode::LoadConst(const_idx));
             },
-            Expression::StructInitialization { fields, .. } => {
-                // For now, compile field values
-                // In a real implementation, we'd create a struct instance
-                for (_, value) in fields {
+            Expression::StructInitialization { name, fields } => {
+                let mut names = Vec::new();
+                for (field, value) in fields {
+                    if names.contains(field) {
+                        return Err(CompileError::new(
+                            CompileErrorKind::DuplicateDefinition,
+                            format!("field `{}` of `{}` is initialized twice", field, name),
+                        ));
+                    }
                     self.compile_expression(value)?;
+                    names.push(field.clone());
                 }
-                // Push a placeholder struct value
-                let struct_value = RuntimeValue::String("struct_instance".to_string());
-                let const_idx = self.add_constant(struct_value);
-                self.bytecode.push(Bytecode::LoadConst(const_idx));
+                // A defined struct gets the defaults of the fields left out,
+                // after the ones given
+                if let Some(defined) = self.names.struct_fields(name).map(<[_]>::to_vec) {
+                    if let Some(field) = names.iter().find(|field| !defined.iter().any(|(name, _)| name == *field)) {
+                        let message = format!("`{}` has no field `{}`", name, field);
+                        return Err(CompileError::new(CompileErrorKind::UnknownField, message));
+                    }
+                    for (field, default) in defined {
+                        if names.contains(&field) {
+                            continue;
+                        }
+                        let Some(default) = default else {
+                            let message = format!("missing required field `{}` in initializer of `{}`", field, name);
+                            return Err(CompileError::new(CompileErrorKind::MissingField, message));
+                        };
+                        let index = self.add_constant(default);
+                        self.bytecode.push(Bytecode::LoadConst(index));
+                        names.push(field);
+                    }
+                }
+                self.bytecode.push(Bytecode::MakeStruct(name.clone(), names));
             },
             Expression::StructFieldAccess { object, field } => {
-                // Compile the object
+                match self.module_member(expr)? {
+                    Some((_, Member::Constant(value))) => {
+                        let index = self.add_constant(value);
+                        self.bytecode.push(Bytecode::LoadConst(index));
+                        return Ok(());
+                    }
+                    Some((called_as, Member::Function(_))) => {
+                        return Err(CompileError::new(
+                            CompileErrorKind::BuiltinAsValue,
+                            format!("builtin function `{}` can only be called, not used as a value", called_as),
+                        ));
+                    }
+                    Some((_, Member::Global { global, .. })) => return self.load_variable(&global),
+                    None => {}
+                }
                 self.compile_expression(object)?;
-                // For now, push a placeholder
-                // In reality, we'd access the field from the object
-                let field_name = RuntimeValue::String(field.clone());
-                let const_idx = self.add_constant(field_name);
-                self.bytecode.push(Bytecode::LoadConst(const_idx));
+                self.bytecode.push(Bytecode::GetField(field.clone()));
             },
             Expression::StructFieldAssignment { object, field, value } => {
-                // Compile the object and value
-                self.compile_expression(object)?;
-                self.compile_expression(value)?;
-                // In reality, we'd assign the value to the field
-                // For now, pop the values and return null
-                self.bytecode.push(Bytecode::Pop); // pop value
-                self.bytecode.push(Bytecode::Pop); // pop object
-                let field_name = RuntimeValue::String(field.clone());
-                let const_idx = self.add_constant(field_name);
+                match object.as_ref() {
+                    // Like arrays, the updated struct replaces the variable
+                    // it came from, which is read after the value
+                    Expression::Variable(name) => {
+                        self.compile_expression(value)?;
+                        self.load_variable(name)?;
+                        self.bytecode.push(Bytecode::Swap);
+                        self.bytecode.push(Bytecode::SetField(field.clone()));
+                        self.store_variable(name);
+                    }
+                    // A nested field, like `ps[i].x` or `p.q.x`
+                    _ => {
+                        let Some((name, mut steps)) = place(object) else {
+                            return Err(CompileError::new(
+                                CompileErrorKind::InvalidAssignmentTarget,
+                                "Only a field of a variable can be assigned to",
+                            ));
+                        };
+                        steps.push(Step::Field(field));
+                        let mut held = Vec::new();
+                        self.push_indexes(&steps, &mut held)?;
+                        self.compile_expression(value)?;
+                        held.push(Held::Value);
+                        self.store_through(name, &steps, &mut held)?;
+                    }
+                }
+                // Push null for no return value
+                let const_idx = self.add_constant(RuntimeValue::Null);
                 self.bytecode.push(Bytecode::LoadConst(const_idx));
             },
             Expression::EnumVariantCreation { variant_name, values, .. } => {
@@ -301,11 +1309,304 @@ impl BytecodeCompiler {
                 let const_idx = self.add_constant(RuntimeValue::Null);
                 self.bytecode.push(Bytecode::LoadConst(const_idx));
             },
+            Expression::Loop(body) => self.compile_loop(body)?,
+        }
+        Ok(())
+    }
+
+    // Compiles a block, whose locals go out of scope at its end
+    fn compile_block(&mut self, statements: &[Statement]) -> Result<(), CompileError> {
+        self.names.begin_scope();
+        let result = statements.iter().try_for_each(|stmt| self.compile_statement(stmt));
+        self.end_scope();
+        result
+    }
+
+    // Ends the innermost block, and the scopes of the locals declared in it
+    fn end_scope(&mut self) {
+        let slots = self.names.end_scope();
+        self.close_scopes(slots);
+    }
+
+    // Ends the scopes of the locals in `slots` here, but for those of blocks
+    // that ended already
+    fn close_scopes(&mut self, slots: Range<usize>) {
+        let end = self.bytecode.len();
+        for local in &mut self.locals[slots] {
+            if local.scope.end == OPEN_SCOPE {
+                local.scope.end = end;
+            }
         }
+    }
+
+    // Compiles the body of a loop and returns its `break`s and `continue`s
+    // for the loop to patch
+    fn compile_loop_body(&mut self, body: &[Statement], yields_value: bool) -> Result<LoopJumps, CompileError> {
+        self.loops.push(LoopJumps { yields_value, tries: self.tries, ..LoopJumps::default() });
+        let result = self.compile_block(body);
+        let jumps = self.loops.pop().unwrap_or_default();
+        result.map(|_| jumps)
+    }
+
+    // Compiles `loop { body }`, leaving the value of the `break` that ends
+    // it on the stack. Only a `let` uses that value directly: locals the
+    // body declares go on the stack too, so nothing may sit there waiting
+    // for the loop to finish.
+    fn compile_loop(&mut self, body: &[Statement]) -> Result<(), CompileError> {
+        let start = self.bytecode.len();
+        let jumps = self.compile_loop_body(body, true)?;
+        jumps.continues.into_iter().for_each(|jump| self.patch_jump(jump));
+        self.bytecode.push(Bytecode::Jump(start));
+        jumps.breaks.into_iter().for_each(|jump| self.patch_jump(jump));
         Ok(())
     }
 
-    fn literal_to_runtime_value(&self, literal: &Literal) -> Result<RuntimeValue, String> {
+    // Ends the `try` bodies that a `break` or `continue` jumps out of: those
+    // begun since its loop, which began inside `tries` of them
+    fn leave_tries(&mut self, tries: usize) {
+        for _ in tries..self.tries {
+            self.bytecode.push(Bytecode::PopHandler);
+        }
+    }
+
+    // Emits a jump whose target is filled in by `patch_jump`
+    fn emit_jump(&mut self, jump: fn(usize) -> Bytecode) -> usize {
+        self.bytecode.push(jump(0));
+        self.bytecode.len() - 1
+    }
+
+    // Points the jump at `at` to the next instruction to be emitted
+    fn patch_jump(&mut self, at: usize) {
+        let target = self.bytecode.len();
+        self.bytecode[at] = match self.bytecode[at] {
+            Bytecode::Jump(_) => Bytecode::Jump(target),
+            Bytecode::JumpIfFalse(_) => Bytecode::JumpIfFalse(target),
+            Bytecode::JumpIfTrue(_) => Bytecode::JumpIfTrue(target),
+            Bytecode::PushHandler(_) => Bytecode::PushHandler(target),
+            ref other => unreachable!("patching {:?}, which is not a jump", other),
+        };
+    }
+
+    // Pops the top of the stack into a new variable declared at `span`: a
+    // local inside a function, a global at the top level. A global `_` just
+    // discards the value.
+    fn declare_variable(&mut self, name: &str, span: Span) {
+        if let Some(parameter) = self.names.shadowed_parameter(name).filter(|_| !self.parameter_shadowing) {
+            let message = format!("variable `{}` hides the parameter of the same name for the rest of the function", name);
+            let warning = Diagnostic::warning(message, span).with_code("shadowed_parameter");
+            self.warnings.push(warning.with_note("parameter declared here", parameter));
+        }
+        match self.names.declare(name, span) {
+            Some(slot) => {
+                self.bytecode.push(Bytecode::StoreLocal(slot));
+                let scope = self.bytecode.len()..OPEN_SCOPE;
+                self.locals.push(LocalVariable { slot, name: name.to_string(), span, scope });
+            }
+            None if name == "_" => self.bytecode.push(Bytecode::Pop),
+            None => self.bytecode.push(Bytecode::StoreGlobal(name.to_string())),
+        }
+    }
+
+    fn load_variable(&mut self, name: &str) -> Result<(), CompileError> {
+        match self.names.resolve(name) {
+            Some(Resolution::Local(slot)) => self.bytecode.push(Bytecode::LoadLocal(slot)),
+            // Functions are globals too
+            Some(Resolution::Global | Resolution::Function(_)) => {
+                self.bytecode.push(Bytecode::LoadGlobal(name.to_string()));
+            }
+            Some(Resolution::Nested(function, _) | Resolution::Own(function, _)) => {
+                self.bytecode.push(Bytecode::LoadGlobal(function));
+            }
+            Some(Resolution::Captured(outer)) => return Err(self.captured(name, &outer)),
+            None if self.names.defined_inside(name).is_some() => return Err(self.out_of_scope(name)),
+            None if self.names.defined_in_module(name).is_some() => return Err(self.outside_module(name)),
+            Some(Resolution::Builtin(_)) => {
+                return Err(CompileError::new(
+                    CompileErrorKind::BuiltinAsValue,
+                    format!("builtin function `{}` can only be called, not used as a value", name),
+                ));
+            }
+            Some(Resolution::Module(path)) => {
+                return Err(CompileError::new(
+                    CompileErrorKind::BuiltinAsValue,
+                    format!("`{}` is the module `{}`, not a value", name, path),
+                ));
+            }
+            None if self.names.requires_declarations() => {
+                return Err(CompileError::new(
+                    CompileErrorKind::UndefinedVariable,
+                    format!("Undefined variable: {}", name),
+                ));
+            }
+            None => self.bytecode.push(Bytecode::LoadGlobal(name.to_string())),
+        }
+        Ok(())
+    }
+
+    // The member that `expr` names, and how the source refers to it, if
+    // `expr` is `module.member` for an imported module
+    fn module_member(&mut self, expr: &Expression) -> Result<Option<(String, Member)>, CompileError> {
+        let Expression::StructFieldAccess { object, field } = expr else {
+            return Ok(None);
+        };
+        let Expression::Variable(alias) = object.as_ref() else {
+            return Ok(None);
+        };
+        let Some(Resolution::Module(path)) = self.names.resolve(alias) else {
+            return Ok(None);
+        };
+        let module = self.names.modules.get(&path).expect("imports are checked against the loader");
+        match module.member(field) {
+            Some(Member::Global { global, public: false }) if self.names.module() != Some(path.as_str()) => {
+                let what = if self.names.is_function(global) { "function" } else { "variable" };
+                let message = format!("{} `{}` is private to module `{}`; mark it `pub` to use it here", what, field, path);
+                Err(CompileError::new(CompileErrorKind::PrivateModuleMember, message))
+            }
+            Some(member) => Ok(Some((format!("{}.{}", alias, field), member.clone()))),
+            None => {
+                let members: Vec<&str> = module.member_names().collect();
+                let message = if members.is_empty() {
+                    format!("module `{}` has no member `{}`; it has no members", path, field)
+                } else {
+                    format!("module `{}` has no member `{}`; its members are {}", path, field, members.join(", "))
+                };
+                Err(CompileError::new(CompileErrorKind::UnknownModuleMember, message))
+            }
+        }
+    }
+
+    // The error for using `name`, a variable of the function `outer`, in a
+    // function defined inside it
+    fn captured(&self, name: &str, outer: &str) -> CompileError {
+        let function = self.names.function().unwrap_or_default();
+        let function = function.rsplit_once("::").map_or(function, |(_, name)| name);
+        let message = format!(
+            "`{}` can't use `{}`, a variable of `{}`; functions defined inside another can't capture its variables yet",
+            function, name, outer
+        );
+        CompileError::new(CompileErrorKind::CapturedVariable, message)
+    }
+
+    // The error for using `name`, a function or variable of a module file,
+    // without going through the module
+    fn outside_module(&self, name: &str) -> CompileError {
+        let module = self.names.defined_in_module(name).unwrap_or_default();
+        let message = format!("`{}` is defined in module `{}`; use it through an import of the module", name, module);
+        CompileError::new(CompileErrorKind::UnknownFunction, message)
+    }
+
+    // The error for using `name`, a function defined inside another, from
+    // outside that one
+    fn out_of_scope(&self, name: &str) -> CompileError {
+        let outer = self.names.defined_inside(name).unwrap_or_default();
+        let message = format!("`{}` is defined inside `{}` and can only be used there", name, outer);
+        CompileError::new(CompileErrorKind::UnknownFunction, message)
+    }
+
+    // Evaluates the indexes among `steps`, left to right, and notes them in
+    // `held`
+    fn push_indexes(&mut self, steps: &[Step], held: &mut Vec<Held>) -> Result<(), CompileError> {
+        for (i, step) in steps.iter().enumerate() {
+            if let Step::Index(index) = step {
+                self.compile_expression(index)?;
+                held.push(Held::Index(i));
+            }
+        }
+        Ok(())
+    }
+
+    // Replaces the part of the variable `name` that `steps` lead to with the
+    // value in `held`, given the indexes of `steps` are there too. Containers
+    // are values, so each one on the way is read, updated and written back
+    // into the one it came from, up to the variable, which is read last
+    fn store_through(&mut self, name: &str, steps: &[Step], held: &mut Vec<Held>) -> Result<(), CompileError> {
+        if steps.is_empty() {
+            self.roll(held, Held::Value);
+            self.store_variable(name);
+            held.pop();
+            return Ok(());
+        }
+        self.load_variable(name)?;
+        held.push(Held::Container);
+        // [.., c0] -> [.., c0, c1, .. cn-1], each container inside the last
+        for (i, step) in steps[..steps.len() - 1].iter().enumerate() {
+            self.bytecode.push(Bytecode::Dup);
+            held.push(Held::Container);
+            self.step_into(step, i, held);
+        }
+        self.roll(held, Held::Value);
+        // [.., ci-1, new ci] -> [.., new ci-1]
+        for (i, step) in steps.iter().enumerate().rev() {
+            match step {
+                Step::Index(_) => {
+                    self.roll(held, Held::Index(i));
+                    self.bytecode.push(Bytecode::Swap);
+                    self.bytecode.push(Bytecode::IndexSet);
+                    held.truncate(held.len() - 2);
+                }
+                Step::Field(field) => {
+                    self.bytecode.push(Bytecode::SetField(field.to_string()));
+                    held.pop();
+                }
+            }
+            *held.last_mut().expect("the container is held") = Held::Container;
+        }
+        self.store_variable(name);
+        held.pop();
+        Ok(())
+    }
+
+    // Pushes the part of the variable `name` that `steps` lead to, given the
+    // indexes of `steps` are in `held`
+    fn load_through(&mut self, name: &str, steps: &[Step], held: &mut Vec<Held>) -> Result<(), CompileError> {
+        self.load_variable(name)?;
+        held.push(Held::Container);
+        for (i, step) in steps.iter().enumerate() {
+            self.step_into(step, i, held);
+        }
+        Ok(())
+    }
+
+    // Replaces the container on top of the stack with the part of it that
+    // `step`, the `i`th step of its place, leads to
+    fn step_into(&mut self, step: &Step, i: usize, held: &mut Vec<Held>) {
+        match step {
+            Step::Index(_) => {
+                self.pick(held, Held::Index(i));
+                self.bytecode.push(Bytecode::IndexGet);
+                held.pop();
+            }
+            Step::Field(field) => self.bytecode.push(Bytecode::GetField(field.to_string())),
+        }
+    }
+
+    // Pushes a copy of `item`
+    fn pick(&mut self, held: &mut Vec<Held>, item: Held) {
+        let depth = held_depth(held, item);
+        self.bytecode.push(Bytecode::Pick(depth));
+        held.push(item);
+    }
+
+    // Moves `item` to the top of the stack
+    fn roll(&mut self, held: &mut Vec<Held>, item: Held) {
+        let depth = held_depth(held, item);
+        if depth > 0 {
+            self.bytecode.push(if depth == 1 { Bytecode::Swap } else { Bytecode::Roll(depth) });
+            let item = held.remove(held.len() - 1 - depth);
+            held.push(item);
+        }
+    }
+
+    // Pops the top of the stack into the local or global called `name`
+    fn store_variable(&mut self, name: &str) {
+        match self.names.resolve(name) {
+            Some(Resolution::Local(slot)) => self.bytecode.push(Bytecode::StoreLocal(slot)),
+            Some(Resolution::Own(global, _)) => self.bytecode.push(Bytecode::StoreGlobal(global)),
+            _ => self.bytecode.push(Bytecode::StoreGlobal(name.to_string())),
+        }
+    }
+
+    fn literal_to_runtime_value(&self, literal: &Literal) -> Result<RuntimeValue, CompileError> {
         match literal {
             Literal::Integer(n) => Ok(RuntimeValue::Integer(*n)),
             Literal::Float(f) => Ok(RuntimeValue::Float(*f)),
@@ -313,4 +1614,569 @@ impl BytecodeCompiler {
             Literal::Boolean(b) => Ok(RuntimeValue::Boolean(*b)),
         }
     }
-}
\ No newline at end of file
+}
+
+// The statements of the module file `file`, or why it can't be imported
+fn read_module_file(file: &Path) -> Result<Vec<Statement>, String> {
+    let source = std::fs::read_to_string(file).map_err(|e| format!("could not read module {}: {}", file.display(), e))?;
+    Lexer::try_new(&source)
+        .and_then(|lexer| Parser::from_lexer(lexer).with_source(&source).try_parse())
+        .map_err(|message| format!("in module {}: {}", file.display(), message))
+}
+
+// Gives the top-level functions and variables of the module file `path`
+// full names, like `utils::helper`, so that they can't clash with another
+// module's, and returns the module that names them
+fn qualify(path: &str, statements: Vec<Statement>) -> (Vec<Statement>, Module) {
+    let mut module = Module::new();
+    let statements = statements
+        .into_iter()
+        .map(|stmt| {
+            let kind = match stmt.kind {
+                StatementKind::Function(func) => {
+                    let global = format!("{}::{}", path, func.name);
+                    module = std::mem::take(&mut module).global(&func.name, &global, func.public);
+                    StatementKind::Function(Function { name: global, ..func })
+                }
+                StatementKind::VariableDeclaration { name, value, explicit_type, public } => {
+                    let global = format!("{}::{}", path, name);
+                    module = std::mem::take(&mut module).global(&name, &global, public);
+                    StatementKind::VariableDeclaration { name: global, value, explicit_type, public }
+                }
+                kind => kind,
+            };
+            Statement::new(kind, stmt.span)
+        })
+        .collect();
+    (statements, module)
+}
+
+// The functions that code outside every function, `main` and `exports` call
+// or refer to, directly or through other functions
+fn reachable_functions(program: &Program, exports: &[String]) -> HashSet<String> {
+    let top_level = (0..program.bytecode.len()).filter(|&ip| !program.functions.iter().any(|f| f.contains(ip)));
+    let mut pending = referenced_names(program, top_level);
+    pending.push("main".to_string());
+    // Methods are picked by the value they're called on, so any may be called
+    pending.extend(program.functions.iter().filter(|f| f.name.contains('.')).map(|f| f.name.clone()));
+    pending.extend(exports.iter().cloned());
+
+    let mut reached = HashSet::new();
+    while let Some(name) = pending.pop() {
+        let Some(function) = program.functions.iter().find(|function| function.name == name) else {
+            continue;
+        };
+        if reached.insert(name) {
+            pending.extend(referenced_names(program, function.start..function.end));
+        }
+    }
+    reached
+}
+
+// The names that the instructions at `ips` load as globals or string
+// constants: functions are called by name and used as values through globals
+pub(crate) fn referenced_names(program: &Program, ips: impl Iterator<Item = usize>) -> Vec<String> {
+    ips.filter_map(|ip| match &program.bytecode[ip] {
+        Bytecode::LoadGlobal(name) => Some(name.clone()),
+        Bytecode::LoadConst(index) => match &program.constants[*index] {
+            RuntimeValue::String(name) => Some(name.clone()),
+            _ => None,
+        },
+        _ => None,
+    })
+    .collect()
+}
+
+// The jumps out of one loop, patched once the loop's code is complete
+#[derive(Default)]
+struct LoopJumps {
+    breaks: Vec<usize>,
+    continues: Vec<usize>,
+    // Whether each `break` leaves a value, as in a `loop`
+    yields_value: bool,
+    // How many `try` bodies enclose the loop
+    tries: usize,
+}
+
+// A step from a variable to the part of it an assignment replaces
+enum Step<'a> {
+    Index(&'a Expression),
+    Field(&'a str),
+}
+
+// The variable `target` is part of, and the steps from it to that part,
+// outermost first: `a[i].f` is `a`, then `[i]`, then `.f`. None if `target`
+// isn't part of a variable, like `f()[0]`
+fn place(target: &Expression) -> Option<(&str, Vec<Step<'_>>)> {
+    match target {
+        Expression::Variable(name) => Some((name, Vec::new())),
+        Expression::ArrayAccess { array, index } => {
+            let (name, mut steps) = place(array)?;
+            steps.push(Step::Index(index));
+            Some((name, steps))
+        }
+        Expression::StructFieldAccess { object, field } => {
+            let (name, mut steps) = place(object)?;
+            steps.push(Step::Field(field));
+            Some((name, steps))
+        }
+        _ => None,
+    }
+}
+
+// What the compiler knows is on the stack while it assigns through a place,
+// top last, so that it can find each value by its depth
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Held {
+    // The index of the step with this position
+    Index(usize),
+    // The value being assigned
+    Value,
+    Container,
+}
+
+fn held_depth(held: &[Held], item: Held) -> usize {
+    let position = held.iter().rposition(|&held| held == item).expect("the item is held");
+    held.len() - 1 - position
+}
+
+// The value of `expr` if it's a constant: a literal, a negated constant
+// number, arithmetic on constant numbers, or an array of constants. Arrays
+// nested deeper than a `.vbc` file may hold are built when the code runs.
+fn constant_value(expr: &Expression) -> Option<RuntimeValue> {
+    constant_within(expr, MAX_CONSTANT_DEPTH)
+}
+
+fn constant_within(expr: &Expression, depth: usize) -> Option<RuntimeValue> {
+    use RuntimeValue::{Float, Integer};
+    match expr {
+        Expression::Literal(literal) => Some(match literal {
+            Literal::Integer(n) => Integer(*n),
+            Literal::Float(f) => Float(*f),
+            Literal::String(s) => RuntimeValue::String(s.clone()),
+            Literal::Boolean(b) => RuntimeValue::Boolean(*b),
+        }),
+        Expression::Unary { operator: UnaryOp::Negate, operand } => match constant_within(operand, depth)? {
+            Integer(n) => n.checked_neg().map(Integer),
+            Float(f) => Some(Float(-f)),
+            _ => None,
+        },
+        Expression::Binary { left, operator, right } => match (constant_within(left, depth)?, operator, constant_within(right, depth)?) {
+            (Integer(a), BinaryOp::Add, Integer(b)) => a.checked_add(b).map(Integer),
+            (Integer(a), BinaryOp::Subtract, Integer(b)) => a.checked_sub(b).map(Integer),
+            (Integer(a), BinaryOp::Multiply, Integer(b)) => a.checked_mul(b).map(Integer),
+            (Float(a), BinaryOp::Add, Float(b)) => Some(Float(a + b)),
+            (Float(a), BinaryOp::Subtract, Float(b)) => Some(Float(a - b)),
+            (Float(a), BinaryOp::Multiply, Float(b)) => Some(Float(a * b)),
+            _ => None,
+        },
+        Expression::ArrayLiteral(elements) if depth > 0 => elements
+            .iter()
+            .map(|element| constant_within(element, depth - 1))
+            .collect::<Option<_>>()
+            .map(RuntimeValue::Array),
+        _ => None,
+    }
+}
+
+// `program` with the methods of its `impl` blocks as top-level functions,
+// named after their type: `impl Point { fn norm(self) }` defines `Point.norm`.
+// Source code can't spell those names, so only method calls reach them.
+fn methods_as_functions(program: &[Statement]) -> Result<Vec<Statement>, CompileError> {
+    let mut statements = Vec::new();
+    for stmt in program {
+        let StatementKind::Impl { type_name, methods } = &stmt.kind else {
+            statements.push(stmt.clone());
+            continue;
+        };
+        for method in methods {
+            if method.parameters.is_empty() {
+                let message = format!(
+                    "method `{}` of `{}` needs a first parameter for the value it is called on",
+                    method.name, type_name
+                );
+                return Err(CompileError::new(CompileErrorKind::ArityMismatch, message).within(stmt.span));
+            }
+            let function = Function { name: format!("{}.{}", type_name, method.name), ..method.clone() };
+            statements.push(Statement::new(StatementKind::Function(function), stmt.span));
+        }
+    }
+    Ok(statements)
+}
+
+// `program` with the functions defined directly in the bodies of other
+// functions as top-level functions, named after the function they're in:
+// `fn main() { fn helper() }` defines `main::helper`. Source code can't spell
+// those names, so only code in `main`, which `names` is told about, reaches
+// them. Functions inside those are lifted out the same way.
+fn nested_as_functions(program: &[Statement], names: &mut Resolver) -> Result<Vec<Statement>, CompileError> {
+    let mut statements = Vec::new();
+    let mut pending: Vec<Statement> = program.iter().rev().cloned().collect();
+    while let Some(stmt) = pending.pop() {
+        let StatementKind::Function(func) = &stmt.kind else {
+            statements.push(stmt);
+            continue;
+        };
+        let (inner, body): (Vec<Statement>, Vec<Statement>) =
+            func.body.iter().cloned().partition(|stmt| matches!(stmt.kind, StatementKind::Function(_)));
+        if inner.is_empty() {
+            statements.push(stmt);
+            continue;
+        }
+        check_definitions(&inner)?;
+        let mut locals: HashSet<String> = func.parameters.iter().map(|(name, _)| name.clone()).collect();
+        declared_globals(&body, &mut locals);
+        for nested in inner.into_iter().rev() {
+            let StatementKind::Function(function) = nested.kind else {
+                continue;
+            };
+            names.define_nested(&func.name, &function.name, function.parameters.len(), &locals);
+            let name = format!("{}::{}", func.name, function.name);
+            pending.push(Statement::new(StatementKind::Function(Function { name, ..function }), nested.span));
+        }
+        let function = Function { body, ..func.clone() };
+        statements.push(Statement::new(StatementKind::Function(function), stmt.span));
+    }
+    Ok(statements)
+}
+
+// Adds the name of every global that top-level code in `program` declares:
+// functions, `let`s and loop variables, including those in nested blocks
+fn declared_globals(program: &[Statement], globals: &mut HashSet<String>) {
+    for stmt in program {
+        match &stmt.kind {
+            StatementKind::Function(func) => {
+                globals.insert(func.name.clone());
+            }
+            StatementKind::VariableDeclaration { name, value, .. } => {
+                globals.insert(name.clone());
+                if let Expression::Loop(body) = value {
+                    declared_globals(body, globals);
+                }
+            }
+            StatementKind::For { variable, body, .. } => {
+                globals.insert(variable.clone());
+                declared_globals(body, globals);
+            }
+            StatementKind::If { then_branch, elif_branches, else_branch, .. } => {
+                declared_globals(then_branch, globals);
+                for (_, body) in elif_branches {
+                    declared_globals(body, globals);
+                }
+                if let Some(body) = else_branch {
+                    declared_globals(body, globals);
+                }
+            }
+            StatementKind::TryCatch { body, error_binding, handler, .. } => {
+                declared_globals(body, globals);
+                globals.insert(error_binding.clone());
+                declared_globals(handler, globals);
+            }
+            StatementKind::While { body, .. }
+            | StatementKind::Loop(body)
+            | StatementKind::Block(body)
+            | StatementKind::UnsafeBlock(body) => declared_globals(body, globals),
+            _ => {}
+        }
+    }
+}
+
+/// Rejects top-level names that are defined more than once: two functions,
+/// or a function and a global. A global can be declared again, which is how
+/// scripts give it a new value. Functions can't take a builtin's name either.
+pub(crate) fn check_definitions(program: &[Statement]) -> Result<(), CompileError> {
+    let builtins = BuiltinRegistry::new();
+    let mut functions: HashMap<&str, Span> = HashMap::new();
+    let mut globals: HashMap<&str, Span> = HashMap::new();
+    for stmt in program {
+        let (name, earlier) = match &stmt.kind {
+            StatementKind::Function(func) => {
+                let name = func.name.as_str();
+                if builtins.id(name).is_some() {
+                    let message = format!("`{}` is a builtin function and can't be redefined", name);
+                    return Err(CompileError::new(CompileErrorKind::ReservedName, message).within(stmt.span));
+                }
+                if let Some(&earlier) = functions.get(name) {
+                    let message = format!("duplicate definition of `{}`", name);
+                    return Err(CompileError::new(CompileErrorKind::DuplicateDefinition, message)
+                        .within(stmt.span)
+                        .after(earlier));
+                }
+                functions.insert(name, stmt.span);
+                (name, globals.get(name))
+            }
+            StatementKind::VariableDeclaration { name, .. } => {
+                globals.entry(name.as_str()).or_insert(stmt.span);
+                (name.as_str(), functions.get(name.as_str()))
+            }
+            _ => continue,
+        };
+        if let Some(&earlier) = earlier {
+            let message = format!("name `{}` conflicts with an earlier definition", name);
+            return Err(CompileError::new(CompileErrorKind::DuplicateDefinition, message)
+                .within(stmt.span)
+                .after(earlier));
+        }
+    }
+    Ok(())
+}
+
+/// Identifies a scalar constant for deduplication. Floats are keyed by their
+/// bits rather than by `==`, which would never match a NaN and would merge
+/// `0.0` with `-0.0`.
+#[derive(PartialEq, Eq, Hash)]
+enum ConstantKey {
+    Integer(i64),
+    Float(u64),
+    String(String),
+    Boolean(bool),
+    Null,
+}
+
+impl ConstantKey {
+    fn of(value: &RuntimeValue) -> Option<Self> {
+        match value {
+            RuntimeValue::Integer(n) => Some(ConstantKey::Integer(*n)),
+            RuntimeValue::Float(x) => Some(ConstantKey::Float(x.to_bits())),
+            RuntimeValue::String(s) => Some(ConstantKey::String(s.clone())),
+            RuntimeValue::Boolean(b) => Some(ConstantKey::Boolean(*b)),
+            RuntimeValue::Null => Some(ConstantKey::Null),
+            RuntimeValue::Array(_)
+            | RuntimeValue::Struct { .. }
+            | RuntimeValue::Range { .. }
+            | RuntimeValue::Function { .. } => None,
+        }
+    }
+}
+
+/// The instruction that applies a binary operator to the top two values.
+fn binary_instruction(operator: &BinaryOp) -> Bytecode {
+    match operator {
+        BinaryOp::Add => Bytecode::Add,
+        BinaryOp::Subtract => Bytecode::Sub,
+        BinaryOp::Multiply => Bytecode::Mul,
+        BinaryOp::Divide => Bytecode::Div,
+        BinaryOp::Modulo => Bytecode::Mod,
+        BinaryOp::Power => Bytecode::Pow,
+        BinaryOp::Equal => Bytecode::Eq,
+        BinaryOp::NotEqual => Bytecode::Ne,
+        BinaryOp::Less => Bytecode::Lt,
+        BinaryOp::LessEqual => Bytecode::Le,
+        BinaryOp::Greater => Bytecode::Gt,
+        BinaryOp::GreaterEqual => Bytecode::Ge,
+    }
+}
+
+/// The `CallBuiltin` id of a core builtin like `puts`.
+fn builtin_id(name: &str) -> Option<usize> {
+    BUILTINS.iter().position(|builtin| *builtin == name)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::vm::VirtualMachine;
+    use voltage_parser::{Lexer, Parser};
+
+    fn run(source: &str) -> Result<RuntimeValue, String> {
+        let program = Parser::from_lexer(Lexer::new(source)).parse();
+        let program = BytecodeCompiler::new().compile_program(&program).map_err(|e| e.to_string())?;
+        let mut vm = VirtualMachine::new();
+        vm.load_program(program).map_err(|e| e.to_string())?;
+        vm.run().map_err(|e| e.to_string())
+    }
+
+    #[test]
+    fn test_program_calls_functions_with_locals() {
+        let source = r#"
+            fn add(a, b) { let sum = a + b; return sum; }
+            fn twice(n) { return add(n, n); }
+            twice(add(2, 3));
+        "#;
+        assert_eq!(run(source), Ok(RuntimeValue::Integer(10)));
+    }
+
+    fn compile_script(source: &str) -> Result<Program, CompileError> {
+        let lexer = Lexer::new(source);
+        let program = Parser::from_lexer(lexer).parse();
+        BytecodeCompiler::new().compile_script(&program)
+    }
+
+    fn error_kind(source: &str) -> Option<CompileErrorKind> {
+        compile_script(source).err().map(|error| error.kind)
+    }
+
+    #[test]
+    fn test_script_is_either_main_or_top_level_statements() {
+        assert!(compile_script("let x = 2; fn f() { return x; } puts(\"{}\", f() * 21);").is_ok());
+        assert!(compile_script("let x = 2; fn main() { puts(x); }").is_ok());
+        assert_eq!(error_kind("fn main() { puts(1); } main();"), Some(CompileErrorKind::MixedMainAndStatements));
+    }
+
+    #[test]
+    fn test_error_kinds() {
+        assert_eq!(error_kind("fn f(a) { return a; } f(1, 2);"), Some(CompileErrorKind::ArityMismatch));
+        assert_eq!(error_kind("puts(1, 2);"), Some(CompileErrorKind::ArityMismatch));
+        assert_eq!(error_kind("fn f() { if true { fn g() { } } }"), Some(CompileErrorKind::NestedFunctionUnsupported));
+        assert_eq!(error_kind("fn f() { break; }"), Some(CompileErrorKind::BreakOutsideLoop));
+        assert_eq!(error_kind("if true { continue; }"), Some(CompileErrorKind::BreakOutsideLoop));
+        assert_eq!(error_kind("let xs: [int] = [true];"), Some(CompileErrorKind::TypeMismatch));
+        assert_eq!(error_kind("[1, \"a\"];"), Some(CompileErrorKind::TypeMismatch));
+        assert_eq!(error_kind("let xs = [[1]]; xs[0][0] += 1;"), Some(CompileErrorKind::InvalidAssignmentTarget));
+    }
+
+    #[test]
+    fn test_duplicate_definitions() {
+        let error = compile_script("fn add(a, b) { return a + b; }\nlet x = 1;\nfn add(a, b) { return b + a; }").unwrap_err();
+        assert_eq!(error.kind, CompileErrorKind::DuplicateDefinition);
+        assert_eq!(error.message, "duplicate definition of `add`");
+        assert_eq!(error.span.map(|span| span.line), Some(3));
+        assert_eq!(error.earlier.map(|span| span.line), Some(1));
+
+        // A function and a global, in either order
+        let error = compile_script("let point = 1;\nfn point() { }").unwrap_err();
+        assert_eq!(error.message, "name `point` conflicts with an earlier definition");
+        assert_eq!((error.span.unwrap().line, error.earlier.unwrap().line), (2, 1));
+        assert_eq!(error_kind("fn point() { }\nlet point = 1;"), Some(CompileErrorKind::DuplicateDefinition));
+
+        // Declaring a global again gives it a new value
+        assert_eq!(error_kind("let x = 1; let x = x + 1;"), None);
+    }
+
+    // The codes of the warnings from compiling `source`
+    fn warning_codes(compiler: &mut BytecodeCompiler, source: &str) -> Vec<&'static str> {
+        let program = Parser::from_lexer(Lexer::new(source)).parse();
+        compiler.compile_program(&program).unwrap();
+        compiler.warnings().iter().filter_map(|warning| warning.code).collect()
+    }
+
+    #[test]
+    fn test_variables_at_the_top_of_a_body_warn_when_hiding_a_parameter() {
+        let source = "fn f(x) {\n    let x = x + 1;\n    return x;\n}";
+        let mut compiler = BytecodeCompiler::new();
+        assert_eq!(warning_codes(&mut compiler, source), ["shadowed_parameter"]);
+        let warning = &compiler.warnings()[0];
+        assert_eq!(warning.span.line, 2);
+        assert_eq!((warning.notes[0].message.as_str(), warning.notes[0].span.line), ("parameter declared here", 1));
+
+        let mut compiler = BytecodeCompiler::new();
+        compiler.allow_parameter_shadowing();
+        assert!(warning_codes(&mut compiler, source).is_empty());
+
+        // A block's variables only hide the parameter inside the block
+        let nested = "fn f(x) { if x > 0 { let x = 2; return x; } for x in 0..3 { puts(x); } return x; }";
+        assert!(warning_codes(&mut BytecodeCompiler::new(), nested).is_empty());
+    }
+
+    #[test]
+    fn test_duplicate_parameters_are_rejected() {
+        let parameter = |name: &str| (name.to_string(), voltage_core::Type::Unknown);
+        let func = Function {
+            name: "f".to_string(),
+            parameters: vec![parameter("x"), parameter("_"), parameter("_"), parameter("x")],
+            return_type: voltage_core::Type::Void,
+            body: Vec::new(),
+            public: false,
+        };
+        let error = BytecodeCompiler::new().compile_function(&func).unwrap_err();
+        assert_eq!(error.kind, CompileErrorKind::DuplicateDefinition);
+        assert_eq!(error.message, "duplicate parameter `x` in `f`");
+    }
+
+    #[test]
+    fn test_builtin_names_are_reserved() {
+        let error = compile_script("fn len(x) { return 0; }").unwrap_err();
+        assert_eq!(error.kind, CompileErrorKind::ReservedName);
+        assert_eq!(error.message, "`len` is a builtin function and can't be redefined");
+        assert_eq!(error_kind("fn puts(x) { }"), Some(CompileErrorKind::ReservedName));
+    }
+
+    #[test]
+    fn test_scripts_reject_undefined_variables() {
+        assert_eq!(error_kind("puts(missing);"), Some(CompileErrorKind::UndefinedVariable));
+        assert_eq!(error_kind("fn f(a) { return b; }"), Some(CompileErrorKind::UndefinedVariable));
+        // Globals declared anywhere at the top level, even after the function using them
+        assert_eq!(error_kind("fn f() { return late + i; } let late = 1; for i in 0..3 { f(); }"), None);
+        assert_eq!(error_kind("if true { let inner = 1; } puts(inner);"), None);
+        // Programs may use globals that earlier runs defined
+        assert_eq!(run("let defined = 1;"), Ok(RuntimeValue::Null));
+    }
+
+    #[test]
+    fn test_errors_point_at_the_innermost_statement() {
+        let error = compile_script("fn f() {\n    while true {\n        g(1, 2);\n    }\n}\nfn g(x) { }").unwrap_err();
+        assert_eq!(error.span.map(|span| (span.line, span.column)), Some((3, 9)));
+        assert_eq!(Diagnostic::from(error).to_string(), "3:9: Function g expects 1 arguments, got 2");
+    }
+
+    #[test]
+    fn test_builtin_calls_are_checked_against_their_arity() {
+        let message = |source: &str| compile_script(source).unwrap_err().message;
+        assert_eq!(message("puts(1, 2);"), "puts expects 1 argument, got 2");
+        assert_eq!(message("let n = len([1], 2);"), "len expects 1 argument, got 2");
+        assert_eq!(message("assert();"), "assert expects 1 to 2 arguments, got 0");
+        assert_eq!(message("let s = format();"), "format expects at least 1 argument, got 0");
+
+        // Variadic builtins take any number of arguments past their minimum
+        assert!(compile_script("let s = \"{}\"; let a = format(s); let b = format(s, 1, 2, 3, 4);").is_ok());
+        assert!(compile_script("let s = format(\"{}{}{}{}\", 1, 2, 3, 4);").is_ok());
+
+        let error = compile_script("fn main() {\n    let x = 1;\n    if approx_eq(1.0, 2.0) {\n    }\n}").unwrap_err();
+        assert_eq!(error.kind, CompileErrorKind::ArityMismatch);
+        assert_eq!(Diagnostic::from(error).to_string(), "3:5: approx_eq expects 3 arguments, got 2");
+    }
+
+    #[test]
+    fn test_array_types_are_checked_at_compile_time() {
+        assert_eq!(run("let xs: [int] = [1, 2]; xs[1];"), Ok(RuntimeValue::Integer(2)));
+        assert_eq!(
+            run("let xs: [int] = [1, \"two\"];"),
+            Err("Array element 1 is string, but `xs` is declared as [int]".to_string())
+        );
+        assert!(run("puts([1, true]);").is_err());
+    }
+
+    #[test]
+    fn test_scalar_constants_are_shared() {
+        let source = "let a = [1.5, 1.5]; let b = [2, 2]; let c = \"x\"; puts(\"x\");";
+        let statements = Parser::from_lexer(Lexer::new(source)).parse();
+        let program = BytecodeCompiler::new().compile_program(&statements).unwrap();
+        let count = |value: &RuntimeValue| program.constants.iter().filter(|c| *c == value).count();
+        assert_eq!(count(&RuntimeValue::Float(1.5)), 1);
+        assert_eq!(count(&RuntimeValue::Integer(2)), 1);
+        assert_eq!(count(&RuntimeValue::String("x".to_string())), 1);
+    }
+
+    #[test]
+    fn test_float_constants_are_keyed_by_bits() {
+        assert!(ConstantKey::of(&RuntimeValue::Float(f64::NAN)) == ConstantKey::of(&RuntimeValue::Float(f64::NAN)));
+        assert!(ConstantKey::of(&RuntimeValue::Float(0.0)) != ConstantKey::of(&RuntimeValue::Float(-0.0)));
+        assert!(ConstantKey::of(&RuntimeValue::Array(vec![])).is_none());
+    }
+
+    #[test]
+    fn test_program_globals_and_arity_errors() {
+        assert_eq!(run("let base = 40; fn f() { return base + 2; } f();"), Ok(RuntimeValue::Integer(42)));
+        assert!(run("fn f(a) { return a; } f(1, 2);").is_err());
+    }
+
+    #[test]
+    fn test_break_and_continue() {
+        let source = r#"
+            let total = 0;
+            for i in 0..10 {
+                if i == 2 { continue; }
+                if i == 5 { break; }
+                let j = 0;
+                while true {
+                    let j = j + 1;
+                    if j > (i) { break; }
+                    let total = total + 1;
+                }
+            }
+            total;
+        "#;
+        // 0 + 1 + 3 + 4, skipping 2
+        assert_eq!(run(source), Ok(RuntimeValue::Integer(8)));
+    }
+}
@@ -0,0 +1,232 @@
+//! Conversions between `RuntimeValue` and Rust types, and the adapter that
+//! turns typed Rust closures into host functions.
+
+use std::rc::Rc;
+use crate::builtins::HostFunction;
+use crate::vm::RuntimeValue;
+
+impl From<i64> for RuntimeValue {
+    fn from(value: i64) -> Self {
+        RuntimeValue::Integer(value)
+    }
+}
+
+impl From<f64> for RuntimeValue {
+    fn from(value: f64) -> Self {
+        RuntimeValue::Float(value)
+    }
+}
+
+impl From<bool> for RuntimeValue {
+    fn from(value: bool) -> Self {
+        RuntimeValue::Boolean(value)
+    }
+}
+
+impl From<String> for RuntimeValue {
+    fn from(value: String) -> Self {
+        RuntimeValue::String(value)
+    }
+}
+
+impl From<&str> for RuntimeValue {
+    fn from(value: &str) -> Self {
+        RuntimeValue::String(value.to_string())
+    }
+}
+
+impl From<Vec<RuntimeValue>> for RuntimeValue {
+    fn from(value: Vec<RuntimeValue>) -> Self {
+        RuntimeValue::Array(value)
+    }
+}
+
+impl From<()> for RuntimeValue {
+    fn from(_: ()) -> Self {
+        RuntimeValue::Null
+    }
+}
+
+fn type_mismatch(expected: &str, found: &RuntimeValue) -> String {
+    format!("expected {}, found {}", expected, found.type_name())
+}
+
+impl TryFrom<RuntimeValue> for i64 {
+    type Error = String;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, String> {
+        match value {
+            RuntimeValue::Integer(n) => Ok(n),
+            other => Err(type_mismatch("int", &other)),
+        }
+    }
+}
+
+/// Integers are accepted too, since scripts write `2` far more often than `2.0`.
+impl TryFrom<RuntimeValue> for f64 {
+    type Error = String;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, String> {
+        match value {
+            RuntimeValue::Float(x) => Ok(x),
+            RuntimeValue::Integer(n) => Ok(n as f64),
+            other => Err(type_mismatch("float", &other)),
+        }
+    }
+}
+
+impl TryFrom<RuntimeValue> for bool {
+    type Error = String;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, String> {
+        match value {
+            RuntimeValue::Boolean(b) => Ok(b),
+            other => Err(type_mismatch("bool", &other)),
+        }
+    }
+}
+
+impl TryFrom<RuntimeValue> for String {
+    type Error = String;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, String> {
+        match value {
+            RuntimeValue::String(s) => Ok(s),
+            other => Err(type_mismatch("string", &other)),
+        }
+    }
+}
+
+impl TryFrom<RuntimeValue> for Vec<RuntimeValue> {
+    type Error = String;
+
+    fn try_from(value: RuntimeValue) -> Result<Self, String> {
+        match value {
+            RuntimeValue::Array(elements) => Ok(elements),
+            other => Err(type_mismatch("array", &other)),
+        }
+    }
+}
+
+/// A type a typed host function can take as a parameter. Implemented for the
+/// types with a `TryFrom<RuntimeValue>` conversion, plus `RuntimeValue`
+/// itself for parameters that accept anything.
+pub trait FromRuntimeValue: Sized {
+    fn from_runtime_value(value: RuntimeValue) -> Result<Self, String>;
+}
+
+macro_rules! impl_from_runtime_value {
+    ($($ty:ty),*) => {
+        $(
+            impl FromRuntimeValue for $ty {
+                fn from_runtime_value(value: RuntimeValue) -> Result<Self, String> {
+                    Self::try_from(value)
+                }
+            }
+        )*
+    };
+}
+
+impl_from_runtime_value!(i64, f64, bool, String, Vec<RuntimeValue>);
+
+impl FromRuntimeValue for RuntimeValue {
+    fn from_runtime_value(value: RuntimeValue) -> Result<Self, String> {
+        Ok(value)
+    }
+}
+
+/// What a typed host function may return: a plain value, or a `Result` whose
+/// error becomes a runtime error.
+pub trait IntoHostResult {
+    fn into_host_result(self) -> Result<RuntimeValue, String>;
+}
+
+macro_rules! impl_into_host_result {
+    ($($ty:ty),*) => {
+        $(
+            impl IntoHostResult for $ty {
+                fn into_host_result(self) -> Result<RuntimeValue, String> {
+                    Ok(self.into())
+                }
+            }
+        )*
+    };
+}
+
+impl_into_host_result!(i64, f64, bool, String, Vec<RuntimeValue>, (), RuntimeValue);
+
+impl<T: Into<RuntimeValue>> IntoHostResult for Result<T, String> {
+    fn into_host_result(self) -> Result<RuntimeValue, String> {
+        self.map(Into::into)
+    }
+}
+
+/// Adapts a Rust closure with typed parameters to the registry's dynamic
+/// signature, checking the argument count and converting each argument.
+/// `Args` is the tuple of parameter types and only exists to pick the impl.
+pub trait IntoHostFunction<Args> {
+    /// How many parameters the closure takes.
+    const ARITY: usize;
+
+    fn into_host_function(self, name: &str) -> HostFunction;
+}
+
+macro_rules! impl_into_host_function {
+    ($($arg:ident),*) => {
+        impl<F, R, $($arg,)*> IntoHostFunction<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> R + 'static,
+            R: IntoHostResult,
+            $($arg: FromRuntimeValue,)*
+        {
+            const ARITY: usize = <[&str]>::len(&[$(stringify!($arg)),*]);
+
+            #[allow(non_snake_case, unused_mut, unused_variables)]
+            fn into_host_function(self, name: &str) -> HostFunction {
+                let name = name.to_string();
+                Rc::new(move |args: &[RuntimeValue]| {
+                    // Still checked for calls through function values
+                    let arity = Self::ARITY;
+                    if args.len() != arity {
+                        return Err(format!("{} expects {} arguments, got {}", name, arity, args.len()));
+                    }
+                    let mut args = args.iter().cloned();
+                    let mut position = 0;
+                    $(
+                        position += 1;
+                        let $arg = $arg::from_runtime_value(args.next().unwrap())
+                            .map_err(|e| format!("{}: argument {} {}", name, position, e))?;
+                    )*
+                    (self)($($arg),*).into_host_result()
+                })
+            }
+        }
+    };
+}
+
+impl_into_host_function!();
+impl_into_host_function!(A);
+impl_into_host_function!(A, B);
+impl_into_host_function!(A, B, C);
+impl_into_host_function!(A, B, C, D);
+impl_into_host_function!(A, B, C, D, E);
+impl_into_host_function!(A, B, C, D, E, G);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_conversions_round_trip() {
+        assert_eq!(i64::try_from(RuntimeValue::from(7)), Ok(7));
+        assert_eq!(f64::try_from(RuntimeValue::from(1.5)), Ok(1.5));
+        assert_eq!(f64::try_from(RuntimeValue::from(2)), Ok(2.0));
+        assert_eq!(bool::try_from(RuntimeValue::from(true)), Ok(true));
+        assert_eq!(String::try_from(RuntimeValue::from("hi")), Ok("hi".to_string()));
+        assert_eq!(
+            Vec::<RuntimeValue>::try_from(RuntimeValue::from(vec![RuntimeValue::Null])),
+            Ok(vec![RuntimeValue::Null])
+        );
+        assert_eq!(i64::try_from(RuntimeValue::from("7")), Err("expected int, found string".to_string()));
+    }
+}
@@ -0,0 +1,90 @@
+//! Which parts of a program ran, and the line coverage reports made from it.
+
+use std::collections::BTreeMap;
+use voltage_core::Span;
+use crate::source_map::SourceMap;
+
+/// One bit per instruction of a program, set once the instruction has run.
+/// It records whether code ran, not how often.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Coverage {
+    words: Vec<u64>,
+}
+
+impl Coverage {
+    pub fn new(instructions: usize) -> Self {
+        Self { words: vec![0; instructions.div_ceil(64)] }
+    }
+
+    #[inline]
+    pub(crate) fn record(&mut self, instruction: usize) {
+        self.words[instruction / 64] |= 1 << (instruction % 64);
+    }
+
+    pub fn contains(&self, instruction: usize) -> bool {
+        self.words
+            .get(instruction / 64)
+            .is_some_and(|word| word & (1 << (instruction % 64)) != 0)
+    }
+
+    /// Maps the instructions that ran to the source lines they came from.
+    /// A statement holding others, like a function, counts as run once
+    /// anything inside it has, so a function's own line is covered when the
+    /// function is called even though its implicit return never runs.
+    pub fn lines(&self, source_map: &SourceMap) -> LineCoverage {
+        let mut ran: Vec<Span> = source_map
+            .iter()
+            .filter(|(instruction, _)| self.contains(*instruction))
+            .map(|(_, span)| span)
+            .collect();
+        ran.sort_by_key(|span| (span.start, span.end));
+        ran.dedup();
+
+        let mut lines = BTreeMap::new();
+        for (_, span) in source_map.iter() {
+            // The spans that ran and start inside this one
+            let first = ran.partition_point(|other| other.start < span.start);
+            let covered = ran[first..]
+                .iter()
+                .take_while(|other| other.start < span.end)
+                .any(|other| other.end <= span.end);
+            *lines.entry(span.line).or_insert(false) |= covered;
+        }
+        LineCoverage { lines }
+    }
+}
+
+/// Every line of a source file that has code, and whether any of that code ran.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct LineCoverage {
+    pub lines: BTreeMap<usize, bool>,
+}
+
+impl LineCoverage {
+    pub fn covered(&self) -> Vec<usize> {
+        self.lines.iter().filter(|(_, ran)| **ran).map(|(line, _)| *line).collect()
+    }
+
+    pub fn uncovered(&self) -> Vec<usize> {
+        self.lines.iter().filter(|(_, ran)| !**ran).map(|(line, _)| *line).collect()
+    }
+
+    /// A line like `script.v: 3/4 lines covered (75.0%)`.
+    pub fn summary(&self, file: &str) -> String {
+        let covered = self.covered().len();
+        let total = self.lines.len();
+        let percent = if total == 0 { 100.0 } else { covered as f64 * 100.0 / total as f64 };
+        format!("{}: {}/{} lines covered ({:.1}%)", file, covered, total, percent)
+    }
+
+    /// The report in lcov's tracefile format, as `file`. Lines that ran
+    /// count one hit.
+    pub fn to_lcov(&self, file: &str) -> String {
+        let mut out = format!("TN:\nSF:{}\n", file);
+        for (line, ran) in &self.lines {
+            out.push_str(&format!("DA:{},{}\n", line, *ran as u8));
+        }
+        out.push_str(&format!("LF:{}\nLH:{}\nend_of_record\n", self.lines.len(), self.covered().len()));
+        out
+    }
+}
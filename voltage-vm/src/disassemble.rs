@@ -0,0 +1,88 @@
+//! A readable listing of a compiled program, for debugging the compiler.
+
+use std::fmt::Write;
+use crate::program::Program;
+use crate::validate::unused_constants;
+use crate::vm::RuntimeValue;
+use voltage_parser::escape::quoted;
+
+// How many characters of a string constant are shown before it's cut short
+const SHOWN_CHARS: usize = 40;
+
+/// Lists the constant pool, noting constants that nothing loads, then every
+/// instruction with its index, labelling where each function starts. Given
+/// the program's source, each run of instructions compiled from the same line
+/// is preceded by that line.
+///
+/// ```
+/// use voltage_parser::{Lexer, Parser};
+/// use voltage_vm::{disassemble, BytecodeCompiler};
+///
+/// let source = "let x = 1;\nputs(x);";
+/// let lexer = Lexer::new(source);
+/// let statements = Parser::from_lexer(lexer).parse();
+/// let program = BytecodeCompiler::new().compile_program(&statements).unwrap();
+///
+/// let listing = disassemble(&program, Some(source));
+/// assert!(listing.contains("; 2 | puts(x);\n"));
+/// ```
+pub fn disassemble(program: &Program, source: Option<&str>) -> String {
+    let lines: Vec<&str> = source.map(|source| source.lines().collect()).unwrap_or_default();
+    let mut out = String::new();
+    if !program.constants.is_empty() {
+        let unused = unused_constants(&program.bytecode, &program.constants);
+        let _ = writeln!(out, "constants:");
+        for (index, line) in list_constants(&program.constants).lines().enumerate() {
+            let note = if unused.contains(&index) { "  ; never loaded" } else { "" };
+            let _ = writeln!(out, "{}{}", line, note);
+        }
+        let _ = writeln!(out, "code:");
+    }
+    let mut current_line = None;
+
+    for (index, instruction) in program.bytecode.iter().enumerate() {
+        if let Some(function) = program.functions.iter().find(|function| function.start == index) {
+            let _ = writeln!(out, "{}:", function.name);
+            current_line = None;
+        }
+
+        let line = program.source_map.span_for(index).map(|span| span.line);
+        if let Some(line) = line.filter(|_| line != current_line) {
+            if let Some(text) = lines.get(line - 1) {
+                let _ = writeln!(out, "    ; {} | {}", line, text.trim());
+            }
+        }
+        current_line = line;
+
+        let _ = writeln!(out, "{:>6}  {:?}", index, instruction);
+    }
+    out
+}
+
+/// Lists a constant pool one constant to a line, with its index and type.
+/// Strings are quoted as literals, and long ones are cut short with their length given.
+///
+/// ```
+/// use voltage_vm::{list_constants, RuntimeValue};
+///
+/// let constants = [RuntimeValue::Integer(7), RuntimeValue::String("x".repeat(50))];
+/// assert_eq!(
+///     list_constants(&constants),
+///     format!("     0  int       7\n     1  string    \"{}\"... (50 chars)\n", "x".repeat(40))
+/// );
+/// ```
+pub fn list_constants(constants: &[RuntimeValue]) -> String {
+    let mut out = String::new();
+    for (index, constant) in constants.iter().enumerate() {
+        let value = match constant {
+            RuntimeValue::String(text) if text.chars().count() > SHOWN_CHARS => {
+                let shown: String = text.chars().take(SHOWN_CHARS).collect();
+                format!("{}... ({} chars)", quoted(&shown), text.chars().count())
+            }
+            RuntimeValue::String(text) => quoted(text),
+            _ => constant.to_string(),
+        };
+        let _ = writeln!(out, "{:>6}  {:<8}  {}", index, constant.type_name(), value);
+    }
+    out
+}
@@ -0,0 +1,602 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+use std::error::Error;
+use std::fmt;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+use voltage_core::{Span, Statement, StatementKind};
+use voltage_parser::{Diagnostic, Lexer, Parser};
+use crate::builtins::BuiltinRegistry;
+use crate::compiler::{check_definitions, BytecodeCompiler, CompileError, CompilerOptions};
+use crate::convert::{FromRuntimeValue, IntoHostFunction};
+use crate::coverage::LineCoverage;
+use crate::env::EnvAccess;
+#[cfg(not(target_arch = "wasm32"))]
+use crate::fs::FsAccess;
+use crate::modules::ModuleLoader;
+use crate::output::OutputEvent;
+use crate::program::Program;
+use crate::vm::{RuntimeError, RuntimeErrorKind, RuntimeValue, VirtualMachine, VmConfig};
+
+/// An error from one of the stages a script goes through.
+#[derive(Debug, Clone, PartialEq)]
+pub enum VoltageError {
+    Lex(String),
+    Parse(String),
+    Compile(CompileError),
+    Runtime(RuntimeError),
+    /// The script called `panic`: a bug in the script rather than a failed
+    /// operation.
+    Panic(RuntimeError),
+    /// A result wasn't of the Rust type the host asked for, as from
+    /// [`Engine::eval_expression`].
+    Conversion(String),
+}
+
+impl fmt::Display for VoltageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            VoltageError::Lex(message) => write!(f, "Lex error: {}", message),
+            VoltageError::Parse(message) => write!(f, "Parse error: {}", message),
+            VoltageError::Compile(error) => write!(f, "Compile error: {}", error),
+            VoltageError::Runtime(error) => write!(f, "Runtime error: {}", error),
+            VoltageError::Panic(error) => write!(f, "Panic: {}", error),
+            VoltageError::Conversion(message) => write!(f, "Conversion error: {}", message),
+        }
+    }
+}
+
+impl From<RuntimeError> for VoltageError {
+    fn from(error: RuntimeError) -> Self {
+        match error.kind {
+            RuntimeErrorKind::Panic => VoltageError::Panic(error),
+            RuntimeErrorKind::Error | RuntimeErrorKind::Limit => VoltageError::Runtime(error),
+        }
+    }
+}
+
+impl Error for VoltageError {}
+
+/// Compiles a whole script without running it, e.g. to save it as a `.vbc`
+/// container. Scripts follow the rules of
+/// [`BytecodeCompiler::compile_script`]: `fn main` or top-level statements,
+/// not both.
+///
+/// ```
+/// let program = voltage_vm::compile("fn main() { puts(1); }").unwrap();
+/// assert_eq!(program.functions[0].name, "main");
+/// ```
+pub fn compile(source: &str) -> Result<Program, VoltageError> {
+    compile_with_modules(source, None, &ModuleLoader::new()).map(|(program, _)| program)
+}
+
+/// Like [`compile`], but leaves out the functions that nothing in the script
+/// can call, as [`BytecodeCompiler::eliminate_dead_code`] describes. Returns a
+/// warning for each function left out along with the program.
+///
+/// ```
+/// let source = "fn main() { puts(1); } fn helper() { }";
+/// let (program, warnings) = voltage_vm::compile_reachable(source, &[]).unwrap();
+/// assert_eq!(program.functions.len(), 1);
+/// assert_eq!(warnings[0].message, "function `helper` is never used");
+/// ```
+pub fn compile_reachable(source: &str, exports: &[&str]) -> Result<(Program, Vec<Diagnostic>), VoltageError> {
+    compile_with_modules(source, Some(exports), &ModuleLoader::new())
+}
+
+/// Like [`compile_reachable`], or [`compile`] when `exports` is `None`, but
+/// resolving `import`s against `modules`, which can find module files in
+/// its search paths.
+pub fn compile_with_modules(
+    source: &str,
+    exports: Option<&[&str]>,
+    modules: &ModuleLoader,
+) -> Result<(Program, Vec<Diagnostic>), VoltageError> {
+    compile_with_options(source, exports, modules, CompilerOptions::default())
+}
+
+/// Like [`compile_with_modules`], with a compiler set up by `options`.
+///
+/// ```
+/// use voltage_vm::{CompilerOptions, ModuleLoader};
+///
+/// let options = CompilerOptions { emit_debug_info: false, ..CompilerOptions::default() };
+/// let (program, _) = voltage_vm::compile_with_options("puts(1);", None, &ModuleLoader::new(), options).unwrap();
+/// assert!(program.source_map.is_empty());
+/// ```
+pub fn compile_with_options(
+    source: &str,
+    exports: Option<&[&str]>,
+    modules: &ModuleLoader,
+    options: CompilerOptions,
+) -> Result<(Program, Vec<Diagnostic>), VoltageError> {
+    let mut compiler = BytecodeCompiler::with_options(options);
+    compiler.use_modules(modules);
+    if let Some(exports) = exports {
+        compiler.eliminate_dead_code(exports.iter().copied());
+    }
+    let program = compiler.compile_script(&parse(source)?).map_err(VoltageError::Compile)?;
+    Ok((program, compiler.warnings().to_vec()))
+}
+
+fn parse(source: &str) -> Result<Vec<Statement>, VoltageError> {
+    let lexer = Lexer::try_new(source).map_err(VoltageError::Lex)?;
+    Parser::from_lexer(lexer)
+        .with_source(source)
+        .try_parse()
+        .map_err(VoltageError::Parse)
+}
+
+/// Runs Voltage source on a VM that lives as long as the engine, so globals
+/// and functions defined by one call stay available to the next.
+///
+/// ```
+/// use voltage_vm::{Engine, RuntimeValue};
+///
+/// let mut engine = Engine::new();
+/// engine.load("fn area(w, h) { return w * h; }").unwrap();
+///
+/// let area = engine.call("area", &[RuntimeValue::Integer(3), RuntimeValue::Integer(4)]);
+/// assert_eq!(area, Ok(RuntimeValue::Integer(12)));
+/// assert_eq!(engine.eval("area(5, 5) + 1;"), Ok(RuntimeValue::Integer(26)));
+/// ```
+pub struct Engine {
+    vm: VirtualMachine,
+    // Every function definition so far; each compile includes all of them
+    functions: BTreeMap<String, Statement>,
+    // Functions kept by dead code elimination, or `None` to keep them all
+    exports: Option<Vec<String>>,
+    // Functions defined as globals on the VM. Later programs have to keep
+    // them, or those globals would point into code that is gone.
+    defined: BTreeSet<String>,
+    // What scripts can import
+    modules: ModuleLoader,
+    // The imports of the module files loaded so far, which their functions
+    // need in every later program
+    module_imports: Vec<Statement>,
+    // How `eval`s and `load`s are compiled
+    options: CompilerOptions,
+    warnings: Vec<Diagnostic>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Engine {
+    pub fn new() -> Self {
+        Self::with_options(VmConfig::default(), CompilerOptions::default())
+    }
+
+    /// An engine whose VM is set up by `config` and that compiles scripts as
+    /// `options` says. Scripts are compiled a piece at a time, so
+    /// [`CompilerOptions::optimize`] has no effect on `eval` and `load`.
+    ///
+    /// ```
+    /// use voltage_vm::{CompilerOptions, Engine, VmConfig};
+    ///
+    /// let options = CompilerOptions { deny_warnings: true, ..CompilerOptions::default() };
+    /// let mut engine = Engine::with_options(VmConfig::default(), options);
+    /// assert!(engine.eval("let puts = 1;").is_err());
+    /// ```
+    pub fn with_options(config: VmConfig, options: CompilerOptions) -> Self {
+        Self {
+            vm: VirtualMachine::with_config(config),
+            functions: BTreeMap::new(),
+            exports: None,
+            defined: BTreeSet::new(),
+            modules: ModuleLoader::new(),
+            module_imports: Vec::new(),
+            options,
+            warnings: Vec::new(),
+        }
+    }
+
+    /// Runs a script and returns the value of its final expression statement,
+    /// or null if it doesn't end with one.
+    ///
+    /// ```
+    /// use voltage_vm::{Engine, RuntimeValue, VoltageError};
+    ///
+    /// let mut engine = Engine::new();
+    /// assert_eq!(engine.eval("let x = 20; x * 2 + 2;"), Ok(RuntimeValue::Integer(42)));
+    /// assert!(matches!(engine.eval("1 / 0;"), Err(VoltageError::Runtime(_))));
+    /// assert!(matches!(engine.eval(r#"panic("unreachable");"#), Err(VoltageError::Panic(_))));
+    /// ```
+    pub fn eval(&mut self, source: &str) -> Result<RuntimeValue, VoltageError> {
+        let statements = parse(source)?;
+        // Merging by name below would hide a function defined twice
+        check_definitions(&statements).map_err(VoltageError::Compile)?;
+
+        let mut functions = self.functions.clone();
+        let mut top_level = Vec::new();
+        for stmt in statements {
+            match &stmt.kind {
+                StatementKind::Function(func) => {
+                    functions.insert(func.name.clone(), stmt);
+                }
+                _ => top_level.push(stmt),
+            }
+        }
+
+        self.run_with(functions, top_level)
+    }
+
+    /// Evaluates a single expression, like a formula a user typed in, with
+    /// `bindings` as globals, and converts its value to `T`. The bindings
+    /// only last for the evaluation; globals they hide get their values back
+    /// afterwards. Statements, several expressions, function definitions and
+    /// imports are all rejected, but the functions scripts defined before
+    /// can be called.
+    ///
+    /// ```
+    /// use std::collections::HashMap;
+    /// use voltage_vm::{Engine, RuntimeValue};
+    ///
+    /// let mut engine = Engine::new();
+    /// let bindings = HashMap::from([
+    ///     ("base".to_string(), RuntimeValue::Float(100.0)),
+    ///     ("rate".to_string(), RuntimeValue::Float(0.25)),
+    /// ]);
+    /// assert_eq!(engine.eval_expression::<f64>("base * rate + 5.0", &bindings), Ok(30.0));
+    /// ```
+    pub fn eval_expression<T: FromRuntimeValue>(
+        &mut self,
+        source: &str,
+        bindings: &HashMap<String, RuntimeValue>,
+    ) -> Result<T, VoltageError> {
+        // The `;` that makes the expression a statement is optional, and
+        // statements like `fn` don't take one
+        let source = source.trim();
+        let statements = match parse(&format!("{};", source)) {
+            Ok(statements) => statements,
+            Err(error) => parse(source).map_err(|_| error)?,
+        };
+        let statement = match statements.as_slice() {
+            [statement @ Statement { kind: StatementKind::Expression(_), .. }] => statement.clone(),
+            [_] => return Err(VoltageError::Parse("expected an expression, found a statement".to_string())),
+            _ => return Err(VoltageError::Parse("expected a single expression".to_string())),
+        };
+
+        let hidden: Vec<(&String, Option<RuntimeValue>)> =
+            bindings.iter().map(|(name, value)| (name, self.vm.set_global(name, value.clone()))).collect();
+        let result = self.run_with(self.functions.clone(), vec![statement]);
+        for (name, value) in hidden {
+            match value {
+                Some(value) => self.vm.set_global(name, value),
+                None => self.vm.remove_global(name),
+            };
+        }
+        T::from_runtime_value(result?).map_err(VoltageError::Conversion)
+    }
+
+    // Compiles `top_level` along with `functions`, which replace the
+    // functions defined so far, and runs it
+    fn run_with(&mut self, mut functions: BTreeMap<String, Statement>, top_level: Vec<Statement>) -> Result<RuntimeValue, VoltageError> {
+        let mut program = self.module_imports.clone();
+        program.extend(functions.values().cloned());
+        program.extend(top_level);
+
+        let mut compiler = BytecodeCompiler::with_options(self.options);
+        compiler.use_builtins(self.vm.builtins());
+        compiler.use_modules(&self.modules);
+        if let Some(exports) = &self.exports {
+            compiler.eliminate_dead_code(exports.iter().chain(&self.defined).cloned());
+        }
+        let program = compiler.compile_program(&program).map_err(VoltageError::Compile)?;
+        let defined: Vec<String> = program.functions.iter().map(|function| function.name.clone()).collect();
+        self.vm
+            .load_program(program)
+            .map_err(|e| VoltageError::Compile(e.into()))?;
+        // Imported modules stay loaded, and later programs keep their
+        // functions like any others
+        for stmt in compiler.imported() {
+            match &stmt.kind {
+                StatementKind::Function(func) => {
+                    functions.insert(func.name.clone(), stmt.clone());
+                }
+                StatementKind::Import(_) | StatementKind::ImportAs(..) => self.module_imports.push(stmt.clone()),
+                _ => {}
+            }
+        }
+        self.modules = compiler.modules().clone();
+        self.functions = functions;
+        self.defined.extend(defined);
+        self.warnings = compiler.warnings().to_vec();
+
+        self.vm.run().map_err(VoltageError::from)
+    }
+
+    /// Defines the functions in `source` and runs its top-level statements,
+    /// ready for repeated calls through [`Engine::call`].
+    pub fn load(&mut self, source: &str) -> Result<(), VoltageError> {
+        self.eval(source).map(|_| ())
+    }
+
+    /// Runs an already compiled script, such as one read from a `.vbc`
+    /// container, in place of everything loaded so far. Its functions can be
+    /// called, but later `eval`s are compiled without them.
+    pub fn load_program(&mut self, program: Program) -> Result<(), VoltageError> {
+        self.vm
+            .load_program(program)
+            .map_err(|e| VoltageError::Compile(e.into()))?;
+        self.functions.clear();
+        self.vm.run().map(|_| ()).map_err(VoltageError::from)
+    }
+
+    /// Makes later `eval`s and `load`s leave out functions that nothing can
+    /// call, as [`BytecodeCompiler::eliminate_dead_code`] describes. Only
+    /// `main`, `exports` and functions the scripts use can be called through
+    /// [`Engine::call`]; functions an earlier script defined are always kept.
+    ///
+    /// ```
+    /// use voltage_vm::{Engine, RuntimeValue};
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.eliminate_dead_code(&["area"]);
+    /// engine.load("fn area(w, h) { return w * h; } fn unused() { }").unwrap();
+    /// assert_eq!(engine.warnings()[0].message, "function `unused` is never used");
+    /// assert!(engine.get_global("unused").is_none());
+    /// ```
+    pub fn eliminate_dead_code(&mut self, exports: &[&str]) {
+        self.exports = Some(exports.iter().map(|name| name.to_string()).collect());
+    }
+
+    /// Records which code runs from the next `eval`, `load` or
+    /// `load_program` on, for [`Engine::line_coverage`].
+    pub fn enable_coverage(&mut self) {
+        self.vm.enable_coverage();
+    }
+
+    /// The lines of the most recently loaded program that have code, and
+    /// whether any of it has run so far. `None` unless coverage is enabled.
+    ///
+    /// ```
+    /// use voltage_vm::Engine;
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.enable_coverage();
+    /// engine.load("let x = 1;\nif x > 5 {\n    puts(x);\n}").unwrap();
+    /// let coverage = engine.line_coverage().unwrap();
+    /// assert_eq!(coverage.uncovered(), vec![3]);
+    /// ```
+    pub fn line_coverage(&self) -> Option<LineCoverage> {
+        Some(self.vm.coverage()?.lines(self.vm.source_map()))
+    }
+
+    /// Warnings from compiling the most recent `eval` or `load`.
+    pub fn warnings(&self) -> &[Diagnostic] {
+        &self.warnings
+    }
+
+    /// Calls a function defined by an earlier `load` or `eval`.
+    pub fn call(&mut self, name: &str, args: &[RuntimeValue]) -> Result<RuntimeValue, VoltageError> {
+        self.vm.run_function(name, args).map_err(VoltageError::from)
+    }
+
+    /// Names the file scripts come from, for `dbg` to show with its line.
+    pub fn set_script_name(&mut self, name: &str) {
+        self.vm.set_script_name(name);
+    }
+
+    /// Limits how many calls may be in progress at once; deeper recursion
+    /// is a runtime error.
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.vm.set_max_call_depth(depth);
+    }
+
+    /// Limits how many values the VM's stack may hold.
+    pub fn set_max_stack_size(&mut self, size: usize) {
+        self.vm.set_max_stack_size(size);
+    }
+
+    /// Limits how many instructions may run from now on; a program that
+    /// runs longer is a runtime error.
+    pub fn set_fuel(&mut self, instructions: u64) {
+        self.vm.set_fuel(instructions);
+    }
+
+    /// Attaches a dump of the VM's stack, locals and last instructions to
+    /// runtime errors from now on, for `--verbose`.
+    pub fn enable_state_dumps(&mut self) {
+        self.vm.enable_state_dumps();
+    }
+
+    /// Where the last instruction run came from, so after a runtime error
+    /// it points at the failing statement.
+    pub fn last_span(&self) -> Option<Span> {
+        self.vm.last_span()
+    }
+
+    /// Looks up a global variable or function defined by the scripts run so far.
+    pub fn get_global(&self, name: &str) -> Option<&RuntimeValue> {
+        self.vm.get_global(name)
+    }
+
+    /// Sends everything scripts print to `output` instead of stdout.
+    ///
+    /// ```
+    /// use std::cell::RefCell;
+    /// use std::io::{self, Write};
+    /// use std::rc::Rc;
+    /// use voltage_vm::Engine;
+    ///
+    /// #[derive(Clone, Default)]
+    /// struct Captured(Rc<RefCell<Vec<u8>>>);
+    ///
+    /// impl Write for Captured {
+    ///     fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    ///         self.0.borrow_mut().write(buf)
+    ///     }
+    ///     fn flush(&mut self) -> io::Result<()> {
+    ///         Ok(())
+    ///     }
+    /// }
+    ///
+    /// let captured = Captured::default();
+    /// let mut engine = Engine::new();
+    /// engine.set_output(captured.clone());
+    /// engine.eval(r#"puts("hello");"#).unwrap();
+    /// assert_eq!(captured.0.borrow().as_slice(), b"hello\n");
+    /// ```
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.vm.set_output(Box::new(output));
+    }
+
+    /// Calls `handler` with each `print` and `puts` as it runs, in program
+    /// order, rather than writing to a sink; see [`OutputEvent`].
+    ///
+    /// ```
+    /// use std::cell::RefCell;
+    /// use std::rc::Rc;
+    /// use voltage_vm::{Engine, OutputEvent};
+    ///
+    /// let events = Rc::new(RefCell::new(Vec::new()));
+    /// let recorded = Rc::clone(&events);
+    /// let mut engine = Engine::new();
+    /// engine.set_output_handler(move |event| recorded.borrow_mut().push(event));
+    /// engine.eval(r#"print("a"); puts("b");"#).unwrap();
+    /// assert_eq!(*events.borrow(), [OutputEvent::Print("a".to_string()), OutputEvent::PutsLine("b".to_string())]);
+    /// ```
+    pub fn set_output_handler(&mut self, handler: impl FnMut(OutputEvent) + 'static) {
+        self.vm.set_output_handler(handler);
+    }
+
+    /// Makes a host function callable from scripts by name.
+    ///
+    /// ```
+    /// use voltage_vm::{Engine, RuntimeValue};
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.register_builtin("double", |args| match args {
+    ///     [RuntimeValue::Integer(n)] => Ok(RuntimeValue::Integer(n * 2)),
+    ///     _ => Err("double expects one integer".to_string()),
+    /// }).unwrap();
+    /// assert_eq!(engine.eval("double(21);"), Ok(RuntimeValue::Integer(42)));
+    /// ```
+    pub fn register_builtin<F>(&mut self, name: &str, function: F) -> Result<(), String>
+    where
+        F: Fn(&[RuntimeValue]) -> Result<RuntimeValue, String> + 'static,
+    {
+        self.vm.builtins_mut().register(name, function).map(|_| ())
+    }
+
+    /// Makes a Rust closure with typed parameters callable from scripts.
+    /// Arguments are converted automatically; a wrong argument count or type
+    /// becomes a runtime error naming the function and parameter position.
+    ///
+    /// ```
+    /// use voltage_vm::{Engine, RuntimeValue};
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.register_fn("hypot", |a: f64, b: f64| -> f64 { (a * a + b * b).sqrt() }).unwrap();
+    /// assert_eq!(engine.eval("hypot(3, 4);"), Ok(RuntimeValue::Float(5.0)));
+    /// ```
+    pub fn register_fn<Args, F>(&mut self, name: &str, function: F) -> Result<(), String>
+    where
+        F: IntoHostFunction<Args>,
+    {
+        self.vm.builtins_mut().register_fn(name, function).map(|_| ())
+    }
+
+    /// Enables the file system builtins, which otherwise fail with "file
+    /// system access is disabled".
+    ///
+    /// ```
+    /// use voltage_vm::{Engine, FsAccess, RuntimeValue};
+    ///
+    /// let mut engine = Engine::new();
+    /// assert!(engine.eval(r#"file_exists("Cargo.toml");"#).is_err());
+    ///
+    /// engine.allow_fs(FsAccess::within(".").unwrap());
+    /// assert_eq!(engine.eval(r#"file_exists("Cargo.toml");"#), Ok(RuntimeValue::Boolean(true)));
+    /// ```
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn allow_fs(&mut self, access: FsAccess) {
+        self.vm.builtins_mut().enable_fs(access);
+    }
+
+    /// Enables `env` and `set_env`, which otherwise fail with "environment
+    /// access is disabled".
+    ///
+    /// ```
+    /// use voltage_vm::{Engine, EnvAccess, RuntimeValue};
+    ///
+    /// let mut engine = Engine::new();
+    /// assert!(engine.eval(r#"env("HOME");"#).is_err());
+    ///
+    /// engine.allow_env(EnvAccess::with_vars([("HOME", "/home/volt")]));
+    /// assert_eq!(engine.eval(r#"env("HOME");"#), Ok(RuntimeValue::String("/home/volt".to_string())));
+    /// ```
+    pub fn allow_env(&mut self, access: EnvAccess) {
+        self.vm.builtins_mut().enable_env(access);
+    }
+
+    /// Empties the `std.io` module, so scripts can't read files or input
+    /// through it whatever [`Engine::allow_fs`] permits.
+    ///
+    /// ```
+    /// use voltage_vm::Engine;
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.disable_std_io();
+    /// assert!(engine.eval("import std.io; let line = io.input();").is_err());
+    /// ```
+    pub fn disable_std_io(&mut self) {
+        self.modules.disable_io();
+    }
+
+    /// Makes `io.input()` read lines from `input` rather than stdin.
+    ///
+    /// ```
+    /// use voltage_vm::{Engine, RuntimeValue};
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.set_input("first\nsecond\n".as_bytes());
+    /// let line = engine.eval("import std.io; io.input(); io.input();");
+    /// assert_eq!(line, Ok(RuntimeValue::String("second".to_string())));
+    /// ```
+    pub fn set_input(&mut self, input: impl BufRead + 'static) {
+        self.vm.builtins().set_input(input);
+    }
+
+    /// Looks for module files in `dir` too, after the directories added
+    /// before it; see [`ModuleLoader`] for the order of the others. Nothing
+    /// is looked for in `VOLTAGE_PATH` unless the host passes it to
+    /// [`ModuleLoader::set_voltage_path`].
+    ///
+    /// ```no_run
+    /// use voltage_vm::Engine;
+    ///
+    /// let mut engine = Engine::new();
+    /// engine.add_module_path("scripts/lib".into());
+    /// // Runs scripts/lib/shapes.v before the rest
+    /// engine.eval("import shapes; shapes.area(2, 3);").unwrap();
+    /// ```
+    pub fn add_module_path(&mut self, dir: PathBuf) {
+        self.modules.add_search_path(dir);
+    }
+
+    pub fn modules(&self) -> &ModuleLoader {
+        &self.modules
+    }
+
+    pub fn modules_mut(&mut self) -> &mut ModuleLoader {
+        &mut self.modules
+    }
+
+    /// Seeds the generator behind `random` and `random_int`, so a script
+    /// produces the same numbers on every run.
+    pub fn seed_random(&mut self, seed: u64) {
+        self.vm.builtins().seed_random(seed);
+    }
+
+    pub fn builtins(&self) -> &BuiltinRegistry {
+        self.vm.builtins()
+    }
+
+    pub fn builtins_mut(&mut self) -> &mut BuiltinRegistry {
+        self.vm.builtins_mut()
+    }
+}
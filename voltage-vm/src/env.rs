@@ -0,0 +1,85 @@
+//! The `env` and `set_env` builtins. Like the file builtins, they are always
+//! registered, but fail with "environment access is disabled" until the host
+//! enables them with an [`EnvAccess`].
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+use crate::builtins::{Arity, BuiltinRegistry};
+use crate::vm::RuntimeValue;
+
+const DISABLED: &str = "environment access is disabled";
+
+/// Which environment variables scripts see.
+#[derive(Debug, Clone)]
+pub struct EnvAccess {
+    // `None` for the process environment
+    vars: Option<Rc<RefCell<HashMap<String, String>>>>,
+}
+
+impl EnvAccess {
+    /// The environment of the process itself; `set_env` changes it for the
+    /// whole process. Not available in WebAssembly builds.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn process() -> Self {
+        Self { vars: None }
+    }
+
+    /// Only the variables in `vars`, for sandboxes and tests. `set_env`
+    /// changes the map, not the process.
+    pub fn with_vars<K, V>(vars: impl IntoIterator<Item = (K, V)>) -> Self
+    where
+        K: Into<String>,
+        V: Into<String>,
+    {
+        let vars = vars.into_iter().map(|(key, value)| (key.into(), value.into())).collect();
+        Self { vars: Some(Rc::new(RefCell::new(vars))) }
+    }
+
+    /// The value of `key`, or `None` if it is unset.
+    pub fn get(&self, key: &str) -> Option<String> {
+        match &self.vars {
+            Some(vars) => vars.borrow().get(key).cloned(),
+            #[cfg(not(target_arch = "wasm32"))]
+            None => std::env::var(key).ok(),
+            #[cfg(target_arch = "wasm32")]
+            None => None,
+        }
+    }
+
+    pub fn set(&self, key: &str, value: &str) -> Result<(), String> {
+        // The process environment would panic on these
+        if key.is_empty() || key.contains(['=', '\0']) || value.contains('\0') {
+            return Err(format!("Invalid environment variable name or value: {:?}", key));
+        }
+        match &self.vars {
+            Some(vars) => {
+                vars.borrow_mut().insert(key.to_string(), value.to_string());
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            None => std::env::set_var(key, value),
+            #[cfg(target_arch = "wasm32")]
+            None => {}
+        }
+        Ok(())
+    }
+}
+
+pub(crate) fn register_disabled(registry: &mut BuiltinRegistry) {
+    for (name, arity) in [("env", 1), ("set_env", 2)] {
+        registry
+            .register_with_arity(name, Arity::exactly(arity), |_| Err(DISABLED.to_string()))
+            .expect("environment builtins are not core builtins");
+    }
+}
+
+pub(crate) fn register_builtins(registry: &mut BuiltinRegistry, access: EnvAccess) {
+    let read_access = access.clone();
+    registry
+        .register_fn("env", move |key: String| read_access.get(&key).map_or(RuntimeValue::Null, RuntimeValue::String))
+        .expect("environment builtins are not core builtins");
+
+    registry
+        .register_fn("set_env", move |key: String, value: String| access.set(&key, &value))
+        .expect("environment builtins are not core builtins");
+}
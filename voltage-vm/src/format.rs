@@ -0,0 +1,80 @@
+//! `{}` substitution shared by the `format` builtin and formatted
+//! `print`/`puts` calls.
+
+use crate::builtins::{Arity, BuiltinRegistry};
+use crate::vm::RuntimeValue;
+
+/// Replaces each `{}` in `template` with the next argument. `{{` and `}}`
+/// produce literal braces; the number of placeholders must match the number
+/// of arguments.
+pub fn format_values(template: &str, args: &[RuntimeValue]) -> Result<String, String> {
+    let mut result = String::with_capacity(template.len());
+    let mut args_iter = args.iter();
+    let mut placeholders = 0;
+    let mut chars = template.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                result.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                placeholders += 1;
+                if let Some(arg) = args_iter.next() {
+                    result.push_str(&arg.to_string());
+                }
+            }
+            ('{', _) => return Err("unmatched `{` in format string; use `{{` for a literal brace".to_string()),
+            ('}', _) => return Err("unmatched `}` in format string; use `}}` for a literal brace".to_string()),
+            _ => result.push(c),
+        }
+    }
+
+    if placeholders != args.len() {
+        return Err(format!(
+            "format string has {} placeholders but {} arguments were given",
+            placeholders,
+            args.len()
+        ));
+    }
+    Ok(result)
+}
+
+pub(crate) fn register_builtins(registry: &mut BuiltinRegistry) {
+    registry
+        .register_with_arity("format", Arity::at_least(1), |args| match args {
+            [RuntimeValue::String(template), rest @ ..] => format_values(template, rest).map(RuntimeValue::String),
+            [other, ..] => Err(format!("format: argument 1 expected string, found {}", other.type_name())),
+            [] => Err("format expects at least 1 argument, got 0".to_string()),
+        })
+        .expect("format is not a core builtin");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_substitutes_in_order() {
+        let args = [RuntimeValue::Integer(1), RuntimeValue::String("two".to_string()), RuntimeValue::Boolean(true)];
+        assert_eq!(format_values("{}-{}-{}", &args), Ok("1-two-true".to_string()));
+        assert_eq!(format_values("no placeholders", &[]), Ok("no placeholders".to_string()));
+    }
+
+    #[test]
+    fn test_escaped_braces() {
+        assert_eq!(format_values("{{}} {{{}}}", &[RuntimeValue::Integer(5)]), Ok("{} {5}".to_string()));
+    }
+
+    #[test]
+    fn test_mismatches_are_errors() {
+        assert_eq!(
+            format_values("{} {}", &[RuntimeValue::Null]),
+            Err("format string has 2 placeholders but 1 arguments were given".to_string())
+        );
+        assert!(format_values("{", &[]).unwrap_err().starts_with("unmatched `{`"));
+        assert!(format_values("a } b", &[]).unwrap_err().starts_with("unmatched `}`"));
+    }
+}
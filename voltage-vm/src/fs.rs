@@ -0,0 +1,144 @@
+//! The `read_file`, `write_file` and `file_exists` builtins. They are always
+//! registered, but fail with "file system access is disabled" until the host
+//! enables them with an [`FsAccess`]. WebAssembly builds have no file system,
+//! so there they stay disabled.
+
+#[cfg(not(target_arch = "wasm32"))]
+use std::fs;
+use std::path::{Path, PathBuf};
+#[cfg(not(target_arch = "wasm32"))]
+use std::path::Component;
+use crate::builtins::{Arity, BuiltinRegistry};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::vm::RuntimeValue;
+
+const DISABLED: &str = "file system access is disabled";
+
+/// How much of the file system scripts may reach.
+#[derive(Debug, Clone)]
+pub struct FsAccess {
+    root: Option<PathBuf>,
+}
+
+impl FsAccess {
+    /// Any path the process itself can reach.
+    pub fn unrestricted() -> Self {
+        Self { root: None }
+    }
+
+    /// Only paths inside `root`. Relative paths in scripts are resolved
+    /// against it, and anything escaping it (through `..` or a symlink) is
+    /// rejected.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn within(root: impl AsRef<Path>) -> Result<Self, String> {
+        let root = root.as_ref();
+        let root = root
+            .canonicalize()
+            .map_err(|e| format!("Invalid file system root {}: {}", root.display(), e))?;
+        Ok(Self { root: Some(root) })
+    }
+
+    pub fn root(&self) -> Option<&Path> {
+        self.root.as_deref()
+    }
+
+    /// Resolves a script-supplied path, checking it against the root.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn resolve(&self, path: &str) -> Result<PathBuf, String> {
+        let Some(root) = &self.root else {
+            return Ok(PathBuf::from(path));
+        };
+
+        let joined = root.join(path);
+        // Follow symlinks where the path exists; a file about to be written
+        // only has its directory resolved
+        let resolved = joined
+            .canonicalize()
+            .or_else(|_| match (joined.parent(), joined.file_name()) {
+                (Some(parent), Some(name)) => parent.canonicalize().map(|parent| parent.join(name)),
+                _ => Err(std::io::ErrorKind::NotFound.into()),
+            })
+            .unwrap_or_else(|_| normalize(&joined));
+
+        if resolved.starts_with(root) {
+            Ok(resolved)
+        } else {
+            Err(format!("{} is outside the allowed directory {}", path, root.display()))
+        }
+    }
+}
+
+// Removes `.` and `..` components without touching the file system
+#[cfg(not(target_arch = "wasm32"))]
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+pub(crate) fn register_disabled(registry: &mut BuiltinRegistry) {
+    for (name, arity) in [("read_file", 1), ("write_file", 2), ("file_exists", 1)] {
+        registry
+            .register_with_arity(name, Arity::exactly(arity), |_| Err(DISABLED.to_string()))
+            .expect("file builtins are not core builtins");
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn register_builtins(registry: &mut BuiltinRegistry, access: FsAccess) {
+    let read_access = access.clone();
+    registry
+        .register_fn("read_file", move |path: String| -> Result<String, String> {
+            let resolved = read_access.resolve(&path)?;
+            fs::read_to_string(&resolved).map_err(|e| format!("Could not read {}: {}", path, e))
+        })
+        .expect("file builtins are not core builtins");
+
+    let write_access = access.clone();
+    registry
+        .register_fn("write_file", move |path: String, contents: RuntimeValue| -> Result<(), String> {
+            let resolved = write_access.resolve(&path)?;
+            fs::write(&resolved, contents.to_string()).map_err(|e| format!("Could not write {}: {}", path, e))
+        })
+        .expect("file builtins are not core builtins");
+
+    registry
+        .register_fn("file_exists", move |path: String| -> Result<bool, String> {
+            Ok(access.resolve(&path)?.exists())
+        })
+        .expect("file builtins are not core builtins");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unrestricted_paths_are_untouched() {
+        let access = FsAccess::unrestricted();
+        assert_eq!(access.resolve("../x.txt"), Ok(PathBuf::from("../x.txt")));
+    }
+
+    #[test]
+    fn test_paths_resolve_inside_root() {
+        let dir = std::env::temp_dir().join(format!("voltage_fs_root_{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub")).unwrap();
+        let access = FsAccess::within(&dir).unwrap();
+        let root = access.root().unwrap().to_path_buf();
+
+        assert_eq!(access.resolve("sub/new.txt"), Ok(root.join("sub/new.txt")));
+        assert_eq!(access.resolve("sub/../missing/a.txt"), Ok(root.join("missing/a.txt")));
+        assert!(access.resolve("../escape.txt").unwrap_err().contains("outside the allowed directory"));
+        assert!(access.resolve("sub/../../escape.txt").is_err());
+        assert!(access.resolve("/etc/passwd").is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,104 @@
+//! Builtins that take functions: `map`, `filter` and `reduce`.
+//!
+//! # Calling back into the program
+//!
+//! Ordinary host functions only ever see values. A higher-order builtin is
+//! also handed a [`Caller`], through which it can call a function value of
+//! the running program and get its result, as many times as it likes, before
+//! returning its own.
+//!
+//! The VM is the `Caller`. A call through it pushes a frame for the function
+//! as `Call` would, with the builtin's caller as the place to return to, and
+//! then runs instructions with the same loop body as the main dispatch loop
+//! until that frame returns. The builtin is still on the Rust stack all the
+//! while, so:
+//!
+//! - An error the function doesn't catch itself is returned to the builtin
+//!   rather than handed to a `try` around the builtin's call. The builtin
+//!   passes it on with `?`, and the VM's own loop then hands it to that
+//!   `try`, or reports it with the function's frame still in the trace.
+//! - Panics and limits get through like any other error, and the call depth
+//!   limit counts the function's frames.
+//! - Stepping in the debugger runs the whole builtin, callbacks included, as
+//!   one instruction.
+//!
+//! New higher-order builtins register with
+//! [`BuiltinRegistry::register_higher_order`] and need nothing else from
+//! the VM.
+
+use std::rc::Rc;
+use crate::builtins::{Arity, BuiltinRegistry};
+use crate::vm::RuntimeValue;
+
+/// Calls functions of the running program on behalf of a higher-order
+/// builtin.
+pub trait Caller {
+    /// Calls `function`, which must be a function value, with `args` and
+    /// returns its result. Errors are the program's runtime errors as they
+    /// would be from `Call`, and should be passed on unchanged.
+    fn call_function(&mut self, function: &RuntimeValue, args: &[RuntimeValue]) -> Result<RuntimeValue, String>;
+}
+
+/// A builtin that can call back into the program through a [`Caller`].
+pub type HigherOrderFunction = Rc<dyn Fn(&mut dyn Caller, &[RuntimeValue]) -> Result<RuntimeValue, String>>;
+
+// The elements a builtin works through, and the function it calls on them
+fn array_and_function<'a>(
+    name: &str,
+    elements: &'a RuntimeValue,
+    function: &'a RuntimeValue,
+) -> Result<(&'a [RuntimeValue], &'a RuntimeValue), String> {
+    match (elements, function) {
+        (RuntimeValue::Array(elements), function @ RuntimeValue::Function { .. }) => Ok((elements, function)),
+        (RuntimeValue::Array(_), other) => Err(format!("{}: expected a function, got {}", name, other.type_name())),
+        (other, _) => Err(format!("{}: expected an array, got {}", name, other.type_name())),
+    }
+}
+
+pub(crate) fn register_builtins(registry: &mut BuiltinRegistry) {
+    registry
+        .register_higher_order("map", Arity::exactly(2), |caller, args| {
+            let [elements, function] = args else {
+                return Err(format!("map expects 2 arguments, got {}", args.len()));
+            };
+            let (elements, function) = array_and_function("map", elements, function)?;
+            let mapped = elements
+                .iter()
+                .map(|element| caller.call_function(function, std::slice::from_ref(element)))
+                .collect::<Result<_, _>>()?;
+            Ok(RuntimeValue::Array(mapped))
+        })
+        .expect("map is not a core builtin");
+
+    // Predicates must return bools, as conditions must
+    registry
+        .register_higher_order("filter", Arity::exactly(2), |caller, args| {
+            let [elements, predicate] = args else {
+                return Err(format!("filter expects 2 arguments, got {}", args.len()));
+            };
+            let (elements, predicate) = array_and_function("filter", elements, predicate)?;
+            let mut kept = Vec::new();
+            for element in elements {
+                match caller.call_function(predicate, std::slice::from_ref(element))? {
+                    RuntimeValue::Boolean(true) => kept.push(element.clone()),
+                    RuntimeValue::Boolean(false) => {}
+                    other => return Err(format!("filter: predicate returned {}, expected bool", other.type_name())),
+                }
+            }
+            Ok(RuntimeValue::Array(kept))
+        })
+        .expect("filter is not a core builtin");
+
+    // Folds from the left: `reduce([a, b], init, f)` is `f(f(init, a), b)`
+    registry
+        .register_higher_order("reduce", Arity::exactly(3), |caller, args| {
+            let [elements, initial, function] = args else {
+                return Err(format!("reduce expects 3 arguments, got {}", args.len()));
+            };
+            let (elements, function) = array_and_function("reduce", elements, function)?;
+            elements
+                .iter()
+                .try_fold(initial.clone(), |acc, element| caller.call_function(function, &[acc, element.clone()]))
+        })
+        .expect("reduce is not a core builtin");
+}
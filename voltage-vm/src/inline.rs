@@ -0,0 +1,220 @@
+//! Inlining: replacing calls to small functions with the functions' code.
+//!
+//! An inlined call leaves its arguments where the call would have found
+//! them, on top of the stack. The function's `LoadLocal`s become `Pick`s of
+//! them, and once its result is computed the arguments under it are dropped.
+//! Locals live on the same stack, below the operands, so the arguments
+//! can't be moved into fresh local slots of the caller while other operands
+//! are above them.
+//!
+//! The names the inlined calls loaded are then left out of the constant
+//! pool, unless something else still loads them.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use crate::program::{FunctionEntry, Program};
+use crate::source_map::{InlinedCall, LocalVariable, SourceMap};
+use crate::validate::{stack_effect, unused_constants};
+use crate::vm::{Bytecode, RuntimeValue};
+
+/// How many instructions a function can have, its `return` included, and be
+/// inlined by `voltagec -O`.
+pub const INLINE_LIMIT: usize = 8;
+
+/// A function that can be inlined.
+struct Inlinable {
+    name: String,
+    num_params: usize,
+    // Its code up to the `Return`
+    body: Range<usize>,
+}
+
+/// Inlines the calls in `program` to functions with fewer than `limit`
+/// instructions, which saves the VM setting up a frame for each of them.
+/// Only functions without jumps are inlined, so none with loops or
+/// branches, and none that call themselves. Calls through function values
+/// aren't inlined, so every function is kept for them.
+///
+/// A runtime error in inlined code has the same trace it would have had
+/// without inlining, with the inlined frames marked.
+///
+/// ```
+/// use voltage_vm::{inline_functions, Bytecode, Engine, INLINE_LIMIT};
+///
+/// let program = voltage_vm::compile("fn double(n) { return n * 2; }\ndouble(21);").unwrap();
+/// let inlined = inline_functions(program, INLINE_LIMIT);
+/// assert!(!inlined.bytecode.iter().any(|op| matches!(op, Bytecode::Call(_))));
+///
+/// let mut engine = Engine::new();
+/// engine.load_program(inlined).unwrap();
+/// ```
+pub fn inline_functions(program: Program, limit: usize) -> Program {
+    let inlinable: HashMap<&str, Inlinable> = program
+        .functions
+        .iter()
+        .filter_map(|function| Some((function.name.as_str(), inlinable(&program, function, limit)?)))
+        .collect();
+    // The function a `LoadConst` calls by name if the next instruction is a call
+    let called_at = |ip: usize| -> Option<&Inlinable> {
+        let (Bytecode::LoadConst(index), Some(Bytecode::Call(count) | Bytecode::TailCall(count))) =
+            (&program.bytecode[ip], program.bytecode.get(ip + 1))
+        else {
+            return None;
+        };
+        let RuntimeValue::String(name) = &program.constants[*index] else {
+            return None;
+        };
+        inlinable.get(name.as_str()).filter(|function| function.num_params == *count)
+    };
+
+    let mut bytecode = Vec::new();
+    let mut spans = Vec::new();
+    let mut inlined = Vec::new();
+    // Where each instruction of `program` ends up
+    let mut moved = vec![0; program.bytecode.len() + 1];
+    let mut ip = 0;
+    while ip < program.bytecode.len() {
+        moved[ip] = bytecode.len();
+        let Some(function) = called_at(ip) else {
+            bytecode.push(program.bytecode[ip].clone());
+            spans.push(program.source_map.span_for(ip));
+            ip += 1;
+            continue;
+        };
+        moved[ip + 1] = bytecode.len();
+        let start = bytecode.len();
+        let call_site = program.source_map.span_for(ip + 1);
+        // Calls already inlined into the function are inlined here with it
+        for call in program.source_map.inlined() {
+            if function.body.start <= call.instructions.start && call.instructions.end <= function.body.end {
+                let instructions = call.instructions.start - function.body.start + start
+                    ..call.instructions.end - function.body.start + start;
+                inlined.push(InlinedCall { instructions, ..call.clone() });
+            }
+        }
+        // How many values the function has pushed over its arguments
+        let mut depth = 0;
+        for at in function.body.clone() {
+            let op = match &program.bytecode[at] {
+                Bytecode::LoadLocal(slot) => Bytecode::Pick(function.num_params - 1 - slot + depth),
+                // A tail call from inlined code isn't the caller's tail call
+                Bytecode::TailCall(count) => Bytecode::Call(*count),
+                op => op.clone(),
+            };
+            let (pops, pushes) = stack_effect(&op);
+            depth = depth + pushes - pops;
+            bytecode.push(op);
+            spans.push(program.source_map.span_for(at));
+        }
+        // The result is on top of the arguments
+        for _ in 0..function.num_params {
+            bytecode.extend([Bytecode::Swap, Bytecode::Pop]);
+            spans.extend([call_site, call_site]);
+        }
+        inlined.push(InlinedCall { instructions: start..bytecode.len(), function: function.name.clone(), call_site });
+        ip += 2;
+    }
+    moved[program.bytecode.len()] = bytecode.len();
+
+    for op in &mut bytecode {
+        if let Bytecode::Jump(target) | Bytecode::JumpIfFalse(target) | Bytecode::JumpIfTrue(target) | Bytecode::PushHandler(target) = op {
+            *target = moved[*target];
+        }
+    }
+    let constants: Vec<RuntimeValue> = program
+        .constants
+        .into_iter()
+        .map(|constant| match constant {
+            RuntimeValue::Function { name, ip, num_params } => RuntimeValue::Function { name, ip: moved[ip], num_params },
+            constant => constant,
+        })
+        .collect();
+    let constants = drop_unused_constants(&mut bytecode, constants);
+    let functions = program
+        .functions
+        .iter()
+        .map(|function| FunctionEntry { start: moved[function.start], end: moved[function.end], ..function.clone() })
+        .collect();
+    let mut source_map = SourceMap::new();
+    for (instruction, span) in spans.into_iter().enumerate() {
+        if let Some(span) = span {
+            source_map.record(instruction..instruction + 1, span);
+        }
+    }
+    for call in program.source_map.inlined() {
+        let instructions = moved[call.instructions.start]..moved[call.instructions.end];
+        source_map.record_inlined(InlinedCall { instructions, ..call.clone() });
+    }
+    inlined.into_iter().for_each(|call| source_map.record_inlined(call));
+    for (function, locals) in program.source_map.locals() {
+        let locals = locals
+            .iter()
+            .map(|local| LocalVariable { scope: moved[local.scope.start]..moved[local.scope.end], ..local.clone() })
+            .collect();
+        source_map.record_locals(function, locals);
+    }
+    Program { bytecode, constants, functions, source_map }
+}
+
+// `constants` without the ones nothing in `bytecode` loads any more, with
+// the `LoadConst`s renumbered to match
+fn drop_unused_constants(bytecode: &mut [Bytecode], constants: Vec<RuntimeValue>) -> Vec<RuntimeValue> {
+    let unused = unused_constants(bytecode, &constants);
+    if unused.is_empty() {
+        return constants;
+    }
+    // Where each constant ends up, if it's kept
+    let mut renumbered = vec![None; constants.len()];
+    let mut kept = Vec::new();
+    for (index, constant) in constants.into_iter().enumerate() {
+        if unused.binary_search(&index).is_err() {
+            renumbered[index] = Some(kept.len());
+            kept.push(constant);
+        }
+    }
+    for op in bytecode {
+        if let Bytecode::LoadConst(index) = op {
+            *index = renumbered[*index].expect("loaded constants are kept");
+        }
+    }
+    kept
+}
+
+// `function` as something to inline, if it's small and simple enough
+fn inlinable(program: &Program, function: &FunctionEntry, limit: usize) -> Option<Inlinable> {
+    let code = &program.bytecode[function.start..function.end];
+    let length = code.iter().position(|op| *op == Bytecode::Return)?;
+    if length + 1 >= limit {
+        return None;
+    }
+    // Any constant naming the function counts as a call to itself
+    let names_itself = |index: usize| match &program.constants[index] {
+        RuntimeValue::String(name) | RuntimeValue::Function { name, .. } => *name == function.name,
+        _ => false,
+    };
+    let mut depth = 0usize;
+    for op in &code[..length] {
+        let simple = match op {
+            Bytecode::LoadLocal(slot) => *slot < function.num_params,
+            Bytecode::LoadConst(index) => !names_itself(*index),
+            Bytecode::StoreLocal(_)
+            | Bytecode::Jump(_)
+            | Bytecode::JumpIfFalse(_)
+            | Bytecode::JumpIfTrue(_)
+            | Bytecode::PushHandler(_)
+            | Bytecode::PopHandler => false,
+            _ => true,
+        };
+        // The arguments are only reached through `LoadLocal`
+        let (pops, pushes) = stack_effect(op);
+        if !simple || pops > depth {
+            return None;
+        }
+        depth = depth - pops + pushes;
+    }
+    (depth == 1).then(|| Inlinable {
+        name: function.name.clone(),
+        num_params: function.num_params,
+        body: function.start..function.start + length,
+    })
+}
@@ -0,0 +1,151 @@
+//! Conversions between `RuntimeValue` and JSON, and the `parse_json` /
+//! `to_json_string` builtins built on them.
+
+use serde_json::{Number, Value};
+use crate::builtins::BuiltinRegistry;
+use crate::vm::RuntimeValue;
+
+impl RuntimeValue {
+    /// Converts a JSON value. Integers outside the `int` range become floats,
+    /// the same precision loss JSON readers in most languages accept. Objects
+    /// are rejected until the language has a map type to hold them.
+    pub fn from_json(value: &Value) -> Result<RuntimeValue, String> {
+        match value {
+            Value::Null => Ok(RuntimeValue::Null),
+            Value::Bool(b) => Ok(RuntimeValue::Boolean(*b)),
+            Value::Number(n) => match n.as_i64() {
+                Some(i) => Ok(RuntimeValue::Integer(i)),
+                None => n
+                    .as_f64()
+                    .map(RuntimeValue::Float)
+                    .ok_or_else(|| format!("cannot convert JSON number {}", n)),
+            },
+            Value::String(s) => Ok(RuntimeValue::String(s.clone())),
+            Value::Array(elements) => elements
+                .iter()
+                .map(RuntimeValue::from_json)
+                .collect::<Result<Vec<_>, _>>()
+                .map(RuntimeValue::Array),
+            Value::Object(_) => Err("cannot convert JSON object: maps are not supported yet".to_string()),
+        }
+    }
+
+    /// Converts this value to JSON. Structs become objects of their fields.
+    /// Functions, ranges and non-finite floats have no JSON representation
+    /// and produce an error.
+    pub fn to_json(&self) -> Result<Value, String> {
+        match self {
+            RuntimeValue::Null => Ok(Value::Null),
+            RuntimeValue::Boolean(b) => Ok(Value::Bool(*b)),
+            RuntimeValue::Integer(i) => Ok(Value::Number((*i).into())),
+            RuntimeValue::Float(x) => Number::from_f64(*x)
+                .map(Value::Number)
+                .ok_or_else(|| format!("cannot convert {} to JSON: only finite floats are allowed", x)),
+            RuntimeValue::String(s) => Ok(Value::String(s.clone())),
+            RuntimeValue::Array(elements) => elements
+                .iter()
+                .map(RuntimeValue::to_json)
+                .collect::<Result<Vec<_>, _>>()
+                .map(Value::Array),
+            RuntimeValue::Struct { fields, .. } => fields
+                .iter()
+                .map(|(field, value)| Ok((field.clone(), value.to_json()?)))
+                .collect::<Result<_, String>>()
+                .map(Value::Object),
+            RuntimeValue::Function { name, .. } => {
+                Err(format!("cannot convert function {} to JSON", name))
+            }
+            RuntimeValue::Range { .. } => Err(format!("cannot convert range {} to JSON", self)),
+        }
+    }
+}
+
+/// Parses a JSON document into a runtime value.
+pub fn parse_json(source: &str) -> Result<RuntimeValue, String> {
+    let value: Value = serde_json::from_str(source).map_err(|e| format!("invalid JSON: {}", e))?;
+    RuntimeValue::from_json(&value)
+}
+
+/// Serializes a runtime value as compact JSON text.
+pub fn to_json_string(value: &RuntimeValue) -> Result<String, String> {
+    Ok(value.to_json()?.to_string())
+}
+
+pub(crate) fn register_builtins(registry: &mut BuiltinRegistry) {
+    registry
+        .register_fn("parse_json", |source: String| parse_json(&source))
+        .expect("parse_json is not a core builtin");
+    registry
+        .register_fn("to_json_string", |value: RuntimeValue| to_json_string(&value))
+        .expect("to_json_string is not a core builtin");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    fn arb_value() -> impl Strategy<Value = RuntimeValue> {
+        let leaf = prop_oneof![
+            Just(RuntimeValue::Null),
+            any::<bool>().prop_map(RuntimeValue::Boolean),
+            any::<i64>().prop_map(RuntimeValue::Integer),
+            // Whole-number floats serialize as e.g. `2.0` and read back as floats
+            (-1.0e15f64..1.0e15).prop_map(RuntimeValue::Float),
+            ".*".prop_map(RuntimeValue::String),
+        ];
+        leaf.prop_recursive(4, 64, 8, |inner| {
+            prop::collection::vec(inner, 0..8).prop_map(RuntimeValue::Array)
+        })
+    }
+
+    proptest! {
+        #[test]
+        fn prop_json_round_trip(value in arb_value()) {
+            let text = to_json_string(&value).unwrap();
+            prop_assert_eq!(parse_json(&text).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn test_nested_document() {
+        let value = parse_json(r#"[1, 2.5, "three", [true, null], []]"#).unwrap();
+        assert_eq!(
+            value,
+            RuntimeValue::Array(vec![
+                RuntimeValue::Integer(1),
+                RuntimeValue::Float(2.5),
+                RuntimeValue::String("three".to_string()),
+                RuntimeValue::Array(vec![RuntimeValue::Boolean(true), RuntimeValue::Null]),
+                RuntimeValue::Array(vec![]),
+            ])
+        );
+        assert_eq!(to_json_string(&value).unwrap(), r#"[1,2.5,"three",[true,null],[]]"#);
+    }
+
+    #[test]
+    fn test_non_finite_floats_are_rejected() {
+        for x in [f64::NAN, f64::INFINITY, f64::NEG_INFINITY] {
+            let error = RuntimeValue::Array(vec![RuntimeValue::Float(x)]).to_json().unwrap_err();
+            assert!(error.contains("only finite floats"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn test_huge_integers() {
+        let max = RuntimeValue::Integer(i64::MAX);
+        assert_eq!(parse_json(&to_json_string(&max).unwrap()), Ok(max));
+        assert_eq!(parse_json("-9223372036854775808"), Ok(RuntimeValue::Integer(i64::MIN)));
+        // Past the int range, numbers degrade to floats instead of failing
+        assert_eq!(parse_json("9223372036854775808"), Ok(RuntimeValue::Float(9223372036854775808.0)));
+        assert_eq!(parse_json("1e300"), Ok(RuntimeValue::Float(1e300)));
+    }
+
+    #[test]
+    fn test_unsupported_values() {
+        let function = RuntimeValue::Function { name: "main".to_string(), ip: 0, num_params: 0 };
+        assert_eq!(function.to_json(), Err("cannot convert function main to JSON".to_string()));
+        assert!(parse_json(r#"{"a": 1}"#).unwrap_err().contains("maps are not supported"));
+        assert!(parse_json("[1,").unwrap_err().starts_with("invalid JSON"));
+    }
+}
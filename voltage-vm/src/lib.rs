@@ -1,4 +1,48 @@
 pub mod vm;
 pub mod compiler;
-pub use vm::{VirtualMachine, RuntimeValue, Bytecode};
-pub use compiler::BytecodeCompiler;
\ No newline at end of file
+pub mod builtins;
+pub mod collections;
+pub mod higher_order;
+pub mod coverage;
+pub mod engine;
+pub mod convert;
+pub mod env;
+pub mod fs;
+pub mod format;
+pub mod random;
+pub mod numbers;
+pub mod testing;
+pub mod program;
+pub mod validate;
+pub mod inline;
+pub mod propagate;
+pub mod call_graph;
+pub mod source_map;
+pub mod disassemble;
+pub mod types;
+pub mod modules;
+pub mod output;
+mod resolver;
+#[cfg(feature = "json")]
+pub mod json;
+pub use vm::{VirtualMachine, RuntimeValue, RuntimeError, RuntimeErrorKind, StateDump, StepResult, TraceFrame, Bytecode, VmConfig, BUILTINS, DEFAULT_MAX_CALL_DEPTH, DEFAULT_MAX_STACK_SIZE, DUMPED_INSTRUCTIONS, DUMPED_STACK_VALUES, MAX_COMPARISON_DEPTH, REPEATED_FRAMES_SHOWN};
+pub use compiler::{optimize, BytecodeCompiler, CompileError, CompileErrorKind, CompilerOptions, EVALUATION_ORDER};
+pub use program::{content_hash, FunctionEntry, Program, BYTECODE_VERSION, MAX_CONSTANT_DEPTH};
+pub use validate::{unused_constants, validate, ValidationError};
+pub use modules::{Member, Module, ModuleLoader};
+pub use output::OutputEvent;
+pub use source_map::{InlinedCall, LocalVariable, SourceMap};
+pub use inline::{inline_functions, INLINE_LIMIT};
+pub use propagate::propagate_constants;
+pub use call_graph::call_graph;
+pub use coverage::{Coverage, LineCoverage};
+pub use disassemble::{disassemble, list_constants};
+pub use builtins::{Arity, BuiltinRegistry, HostFunction};
+pub use higher_order::{Caller, HigherOrderFunction};
+pub use engine::{compile, compile_reachable, compile_with_modules, compile_with_options, Engine, VoltageError};
+pub use convert::{FromRuntimeValue, IntoHostFunction, IntoHostResult};
+pub use env::EnvAccess;
+pub use fs::FsAccess;
+pub use format::format_values;
+#[cfg(feature = "json")]
+pub use json::{parse_json, to_json_string};
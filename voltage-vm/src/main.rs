@@ -1,42 +1,17 @@
-use voltage_vm::{VirtualMachine, BytecodeCompiler};
-use voltage_parser::{Lexer, Parser};
-use voltage_core::Function;
+use voltage_vm::Engine;
 
 fn main() {
     // Example Voltage code
-    let source = r#"fn main() { let x = 42; puts("Hello from Voltage VM!"); }"#;
+    let source = r#"fn main() { let x = 42; puts("Hello from Voltage VM!"); return x; }"#;
 
-    // Parse the source code
-    let lexer = Lexer::new(source.to_string());
-    let tokens = lexer.tokenize();
-    let mut parser = Parser::new(tokens.to_vec());
-    let ast = parser.parse();
-    
-    // Find the main function
-    for stmt in ast {
-        if let voltage_core::Statement::Function(func) = stmt {
-            if func.name == "main" {
-                // Compile to bytecode
-                let mut compiler = BytecodeCompiler::new();
-                match compiler.compile_function(&func) {
-                    Ok((bytecode, constants)) => {
-                        println!("Successfully compiled to bytecode!");
-                        println!("Bytecode length: {}", bytecode.len());
-                        println!("Constants count: {}", constants.len());
-                        
-                        // Create and run the VM
-                        let mut vm = VirtualMachine::new();
-                        vm.load_bytecode(bytecode, constants);
-                        
-                        match vm.run() {
-                            Ok(result) => println!("Program result: {:?}", result),
-                            Err(e) => eprintln!("Runtime error: {}", e),
-                        }
-                    }
-                    Err(e) => eprintln!("Compilation error: {}", e),
-                }
-                break;
-            }
-        }
+    let mut engine = Engine::new();
+    if let Err(e) = engine.load(source) {
+        eprintln!("{}", e);
+        return;
     }
-}
\ No newline at end of file
+
+    match engine.call("main", &[]) {
+        Ok(result) => println!("Program result: {:?}", result),
+        Err(e) => eprintln!("{}", e),
+    }
+}
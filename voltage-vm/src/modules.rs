@@ -0,0 +1,324 @@
+//! The modules scripts can `import`: `std.math`, `std.string` and `std.io`,
+//! implemented in Rust, and modules written in Voltage, found in the
+//! loader's search paths.
+//!
+//! A module's functions are builtins registered under names scripts can't
+//! write, like `std.math.sqrt`, so they only appear in the global namespace
+//! through an import.
+
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap};
+use std::ffi::OsStr;
+use std::io::{self, BufRead};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use crate::builtins::{Arity, BuiltinRegistry};
+use crate::vm::RuntimeValue;
+
+/// Something a module gives its importers.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Member {
+    /// A function, by the name of the builtin that implements it.
+    Function(String),
+    /// A value, like `math.pi`.
+    Constant(RuntimeValue),
+    /// A function or variable that a module file defines, by its full name
+    /// among the program's globals, like `utils::helper`. Only `pub` ones
+    /// can be used from outside the module.
+    Global { global: String, public: bool },
+}
+
+/// A module that scripts can import.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Module {
+    members: BTreeMap<String, Member>,
+}
+
+impl Module {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn function(mut self, name: &str, builtin: &str) -> Self {
+        self.members.insert(name.to_string(), Member::Function(builtin.to_string()));
+        self
+    }
+
+    pub fn constant(mut self, name: &str, value: RuntimeValue) -> Self {
+        self.members.insert(name.to_string(), Member::Constant(value));
+        self
+    }
+
+    pub fn global(mut self, name: &str, global: &str, public: bool) -> Self {
+        self.members.insert(name.to_string(), Member::Global { global: global.to_string(), public });
+        self
+    }
+
+    pub fn member(&self, name: &str) -> Option<&Member> {
+        self.members.get(name)
+    }
+
+    /// The names of the members, in alphabetical order.
+    pub fn member_names(&self) -> impl Iterator<Item = &str> {
+        self.members.keys().map(String::as_str)
+    }
+}
+
+/// The modules that `import` can find, by path, and where it looks for
+/// modules written in Voltage.
+///
+/// The module `a.b` is the file `a/b.v` in the first of the search paths
+/// that has it, looked through in this order:
+///
+/// 1. the directory of the script being run, see [`ModuleLoader::set_entry_dir`]
+/// 2. the paths given to [`ModuleLoader::add_search_path`], in the order
+///    they were added
+/// 3. the paths in `VOLTAGE_PATH`, see [`ModuleLoader::set_voltage_path`]
+///
+/// Modules the loader already has, like the standard library, are found
+/// before any file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ModuleLoader {
+    modules: HashMap<String, Module>,
+    entry_dir: Option<PathBuf>,
+    search_paths: Vec<PathBuf>,
+    voltage_path: Vec<PathBuf>,
+}
+
+impl Default for ModuleLoader {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ModuleLoader {
+    /// A loader with the standard library: `std.math`, `std.string` and
+    /// `std.io`.
+    pub fn new() -> Self {
+        let mut loader = Self::empty();
+        loader.insert("std.math", math());
+        loader.insert("std.string", string());
+        loader.insert("std.io", io());
+        loader
+    }
+
+    /// A loader without any modules.
+    pub fn empty() -> Self {
+        Self { modules: HashMap::new(), entry_dir: None, search_paths: Vec::new(), voltage_path: Vec::new() }
+    }
+
+    /// Makes `path` importable, replacing any module already there.
+    pub fn insert(&mut self, path: &str, module: Module) {
+        self.modules.insert(path.to_string(), module);
+    }
+
+    pub fn get(&self, path: &str) -> Option<&Module> {
+        self.modules.get(path)
+    }
+
+    /// The paths of every module, in alphabetical order.
+    pub fn paths(&self) -> Vec<&str> {
+        let mut paths: Vec<&str> = self.modules.keys().map(String::as_str).collect();
+        paths.sort_unstable();
+        paths
+    }
+
+    /// Looks for modules in `dir`, the directory of the script that imports
+    /// them, before any other search path.
+    pub fn set_entry_dir(&mut self, dir: impl Into<PathBuf>) {
+        self.entry_dir = Some(dir.into());
+    }
+
+    /// Looks for modules in `dir` after the entry directory and the paths
+    /// added before it.
+    pub fn add_search_path(&mut self, dir: impl Into<PathBuf>) {
+        self.search_paths.push(dir.into());
+    }
+
+    /// Looks for modules in the directories listed in `voltage_path`, the
+    /// value of the `VOLTAGE_PATH` environment variable, after every other
+    /// search path. They're separated as in `PATH`: by `:`, or `;` on
+    /// Windows.
+    pub fn set_voltage_path(&mut self, voltage_path: &OsStr) {
+        self.voltage_path = std::env::split_paths(voltage_path).filter(|dir| !dir.as_os_str().is_empty()).collect();
+    }
+
+    /// The directories modules written in Voltage are looked for in, in the
+    /// order they're looked through.
+    pub fn search_paths(&self) -> impl Iterator<Item = &Path> {
+        self.entry_dir.iter().chain(&self.search_paths).chain(&self.voltage_path).map(PathBuf::as_path)
+    }
+
+    /// The files in the search paths that are the module `path`, in search
+    /// order. The first is the one that gets imported. A directory that is
+    /// searched twice only counts once.
+    pub fn find_script(&self, path: &str) -> Vec<PathBuf> {
+        let file = script_file(path);
+        let mut found: Vec<PathBuf> = Vec::new();
+        for candidate in self.search_paths().map(|dir| dir.join(&file)) {
+            if candidate.is_file() && !found.contains(&candidate) {
+                found.push(candidate);
+            }
+        }
+        found
+    }
+
+    /// Empties `std.io`, for hosts whose scripts mustn't touch files or
+    /// stdin. Scripts can still import it, but have nothing to use.
+    pub fn disable_io(&mut self) {
+        self.insert("std.io", Module::new());
+    }
+}
+
+/// Where the module `path` is in a search path: `a/b.v` for `a.b`.
+pub fn script_file(path: &str) -> PathBuf {
+    let mut file: PathBuf = path.split('.').collect();
+    file.set_extension("v");
+    file
+}
+
+fn math() -> Module {
+    let module = Module::new()
+        .constant("pi", RuntimeValue::Float(std::f64::consts::PI))
+        .constant("e", RuntimeValue::Float(std::f64::consts::E));
+    ["sqrt", "abs", "floor", "ceil", "sin", "cos", "min", "max"]
+        .into_iter()
+        .fold(module, |module, name| module.function(name, &format!("std.math.{}", name)))
+}
+
+fn string() -> Module {
+    let module = Module::new()
+        .function("len", "len")
+        .function("contains", "contains")
+        .function("format", "format")
+        .function("lines", "lines");
+    ["upper", "lower", "trim", "split", "starts_with", "ends_with", "replace"]
+        .into_iter()
+        .fold(module, |module, name| module.function(name, &format!("std.string.{}", name)))
+}
+
+fn io() -> Module {
+    Module::new()
+        .function("read_file", "read_file")
+        .function("write_file", "write_file")
+        .function("file_exists", "file_exists")
+        .function("input", "std.io.input")
+}
+
+// Keeps ints ints where the result is exact
+fn number(value: RuntimeValue, f: fn(f64) -> f64, int: fn(i64) -> Option<i64>) -> Result<RuntimeValue, String> {
+    match value {
+        RuntimeValue::Integer(n) => int(n)
+            .map(RuntimeValue::Integer)
+            .ok_or_else(|| format!("Integer overflow: {}", n)),
+        RuntimeValue::Float(x) => Ok(RuntimeValue::Float(f(x))),
+        other => Err(format!("expected a number, got {}", other.type_name())),
+    }
+}
+
+fn pick(a: RuntimeValue, b: RuntimeValue, first: fn(f64, f64) -> bool) -> Result<RuntimeValue, String> {
+    match (&a, &b) {
+        (RuntimeValue::Integer(_) | RuntimeValue::Float(_), RuntimeValue::Integer(_) | RuntimeValue::Float(_)) => {
+            let x = f64::try_from(a.clone())?;
+            let y = f64::try_from(b.clone())?;
+            Ok(if first(x, y) { a } else { b })
+        }
+        _ => Err(format!("expected two numbers, got {} and {}", a.type_name(), b.type_name())),
+    }
+}
+
+// The pieces a string was split into, as an array of strings
+fn strings<'a>(parts: impl Iterator<Item = &'a str>) -> Vec<RuntimeValue> {
+    parts.map(|part| RuntimeValue::String(part.to_string())).collect()
+}
+
+/// Where `std.io.input` reads from until the host says otherwise: stdin.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn default_input() -> Box<dyn BufRead> {
+    Box::new(io::BufReader::new(io::stdin()))
+}
+
+/// WebAssembly builds have no stdin; input is empty without a reader.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn default_input() -> Box<dyn BufRead> {
+    Box::new(io::empty())
+}
+
+pub(crate) fn register_builtins(registry: &mut BuiltinRegistry, input: &Rc<RefCell<Box<dyn BufRead>>>) {
+    type Float = fn(f64) -> f64;
+    let math: [(&str, Float); 5] =
+        [("sqrt", f64::sqrt), ("floor", f64::floor), ("ceil", f64::ceil), ("sin", f64::sin), ("cos", f64::cos)];
+    for (name, f) in math {
+        registry
+            .register_fn(&format!("std.math.{}", name), move |x: f64| f(x))
+            .expect("std.math functions are not core builtins");
+    }
+    registry
+        .register_fn("std.math.abs", |value: RuntimeValue| number(value, f64::abs, i64::checked_abs))
+        .expect("std.math.abs is not a core builtin");
+    registry
+        .register_fn("std.math.min", |a: RuntimeValue, b: RuntimeValue| pick(a, b, |x, y| x <= y))
+        .expect("std.math.min is not a core builtin");
+    registry
+        .register_fn("std.math.max", |a: RuntimeValue, b: RuntimeValue| pick(a, b, |x, y| x >= y))
+        .expect("std.math.max is not a core builtin");
+
+    type Text = fn(&str) -> String;
+    let string: [(&str, Text); 3] =
+        [("upper", str::to_uppercase), ("lower", str::to_lowercase), ("trim", |s| s.trim().to_string())];
+    for (name, f) in string {
+        registry
+            .register_fn(&format!("std.string.{}", name), move |s: String| f(&s))
+            .expect("std.string functions are not core builtins");
+    }
+    registry
+        .register_fn("std.string.split", |s: String, separator: String| strings(s.split(separator.as_str())))
+        .expect("std.string.split is not a core builtin");
+    // Global, since it's what reading a file line by line needs:
+    // `for line in lines(read_file(path))`. A line ending after the last line
+    // doesn't start another, and `\r\n` counts as a line ending.
+    registry
+        .register_fn("lines", |s: String| strings(s.lines()))
+        .expect("lines is not a core builtin");
+    registry
+        .register_fn("std.string.starts_with", |s: String, prefix: String| s.starts_with(&prefix))
+        .expect("std.string.starts_with is not a core builtin");
+    registry
+        .register_fn("std.string.ends_with", |s: String, suffix: String| s.ends_with(&suffix))
+        .expect("std.string.ends_with is not a core builtin");
+    registry
+        .register_fn("std.string.replace", |s: String, from: String, to: String| s.replace(&from, &to))
+        .expect("std.string.replace is not a core builtin");
+
+    // Reads a line without its line ending; null at the end of input
+    let input = Rc::clone(input);
+    registry
+        .register_with_arity("std.io.input", Arity::exactly(0), move |_| {
+            let mut line = String::new();
+            match input.borrow_mut().read_line(&mut line) {
+                Ok(0) => Ok(RuntimeValue::Null),
+                Ok(_) => Ok(RuntimeValue::String(line.trim_end_matches(['\n', '\r']).to_string())),
+                Err(e) => Err(format!("Could not read input: {}", e)),
+            }
+        })
+        .expect("std.io.input is not a core builtin");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_every_std_function_is_registered() {
+        let registry = BuiltinRegistry::new();
+        let loader = ModuleLoader::new();
+        for path in loader.paths() {
+            let module = loader.get(path).unwrap();
+            for name in module.member_names() {
+                if let Some(Member::Function(builtin)) = module.member(name) {
+                    assert!(registry.id(builtin).is_some(), "{}.{} has no builtin {}", path, name, builtin);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,89 @@
+//! Turning text into numbers: `int`, `try_int` and `try_float`.
+//!
+//! All of them ignore whitespace around the number and take an optional
+//! leading `+` or `-`. `int` fails on text that isn't a number, which a
+//! `try` can catch; the `try_` versions return `null` instead, for checking
+//! input where bad text is expected.
+
+use crate::builtins::{Arity, BuiltinRegistry};
+use crate::vm::RuntimeValue;
+
+/// The bases `int` and `try_int` accept.
+const RADIXES: std::ops::RangeInclusive<i64> = 2..=36;
+
+/// Parses `text` as an integer in base `radix`, which is 2 to 36.
+pub fn parse_int(text: &str, radix: u32) -> Option<i64> {
+    i64::from_str_radix(text.trim(), radix).ok()
+}
+
+/// Parses `text` as a decimal float, like `2.5`, `-1e3` or `7`. The names
+/// Rust accepts for infinity and NaN are not numbers here.
+pub fn parse_float(text: &str) -> Option<f64> {
+    let text = text.trim();
+    if text.contains(|c: char| c.is_ascii_alphabetic() && c != 'e' && c != 'E') {
+        return None;
+    }
+    text.parse().ok()
+}
+
+// The radix passed as the second argument, 10 if there isn't one
+fn radix(name: &str, args: &[RuntimeValue]) -> Result<u32, String> {
+    match args.get(1) {
+        None => Ok(10),
+        Some(RuntimeValue::Integer(radix)) if RADIXES.contains(radix) => Ok(*radix as u32),
+        Some(RuntimeValue::Integer(radix)) => Err(format!("{}: radix must be between 2 and 36, got {}", name, radix)),
+        Some(other) => Err(format!("{}: argument 2 expected int, found {}", name, other.type_name())),
+    }
+}
+
+fn int(args: &[RuntimeValue]) -> Result<RuntimeValue, String> {
+    let radix = radix("int", args)?;
+    match &args[0] {
+        RuntimeValue::String(text) => parse_int(text, radix).map(RuntimeValue::Integer).ok_or_else(|| match radix {
+            10 => format!("int: {:?} is not an integer", text),
+            _ => format!("int: {:?} is not an integer in base {}", text, radix),
+        }),
+        RuntimeValue::Integer(_) if args.len() == 1 => Ok(args[0].clone()),
+        // Toward zero, as `as` does in Rust, but not past the ends of the range
+        RuntimeValue::Float(value) if args.len() == 1 => match value.trunc() {
+            value if value >= i64::MIN as f64 && value < i64::MAX as f64 => Ok(RuntimeValue::Integer(value as i64)),
+            _ => Err(format!("int: {} is out of range", value)),
+        },
+        other => Err(format!("int: argument 1 expected string, found {}", other.type_name())),
+    }
+}
+
+pub(crate) fn register_builtins(registry: &mut BuiltinRegistry) {
+    registry
+        .register_with_arity("int", Arity::between(1, 2), int)
+        .expect("int is not a core builtin");
+    registry
+        .register_with_arity("try_int", Arity::between(1, 2), |args| {
+            let radix = radix("try_int", args)?;
+            match &args[0] {
+                RuntimeValue::String(text) => Ok(parse_int(text, radix).map_or(RuntimeValue::Null, RuntimeValue::Integer)),
+                other => Err(format!("try_int: argument 1 expected string, found {}", other.type_name())),
+            }
+        })
+        .expect("try_int is not a core builtin");
+    registry
+        .register_fn("try_float", |text: String| parse_float(&text).map_or(RuntimeValue::Null, RuntimeValue::Float))
+        .expect("try_float is not a core builtin");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signs_and_whitespace() {
+        assert_eq!(parse_int(" +42\n", 10), Some(42));
+        assert_eq!(parse_int("-ff", 16), Some(-255));
+        assert_eq!(parse_int("-9223372036854775808", 10), Some(i64::MIN));
+        assert_eq!(parse_int("+-1", 10), None);
+        assert_eq!(parse_int("1 2", 10), None);
+        assert_eq!(parse_float(" -2.5e1 "), Some(-25.0));
+        assert_eq!(parse_float("inf"), None);
+        assert_eq!(parse_float("NaN"), None);
+    }
+}
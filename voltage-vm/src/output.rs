@@ -0,0 +1,57 @@
+//! What scripts print, as events for the host to handle.
+
+use std::io::{self, Write};
+
+/// One call to `print`, `puts` or `dbg`, with the text it printed.
+///
+/// Events reach the handler synchronously, while the builtin runs, so they
+/// arrive in program order and before the script carries on.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OutputEvent {
+    /// `print`: text with no line ending.
+    Print(String),
+    /// `puts`: a whole line, without its line ending.
+    PutsLine(String),
+    /// `dbg`: where it was called, the expression and its value, which may
+    /// take several lines. Written to stderr by default.
+    Debug(String),
+}
+
+impl OutputEvent {
+    /// Writes the event as the console shows it: `puts` ends its line,
+    /// `print` leaves it open and flushes so the text appears at once.
+    pub fn write_to(&self, output: &mut dyn Write) -> io::Result<()> {
+        match self {
+            OutputEvent::Print(text) => {
+                write!(output, "{}", text)?;
+                output.flush()
+            }
+            OutputEvent::PutsLine(text) | OutputEvent::Debug(text) => writeln!(output, "{}", text),
+        }
+    }
+}
+
+/// Handles output for a VM. Failing stops the script with a runtime error.
+pub(crate) type OutputHandler = Box<dyn FnMut(OutputEvent) -> io::Result<()>>;
+
+/// Where output goes until the host says otherwise: stdout.
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) fn default_output() -> Box<dyn Write> {
+    Box::new(io::stdout())
+}
+
+/// WebAssembly builds have no stdout; output goes nowhere without a handler.
+#[cfg(target_arch = "wasm32")]
+pub(crate) fn default_output() -> Box<dyn Write> {
+    Box::new(io::sink())
+}
+
+/// The handler that writes every event to `output`, as stdout gets by
+/// default, except that `dbg` always goes to stderr.
+pub(crate) fn write_to(mut output: Box<dyn Write>) -> OutputHandler {
+    Box::new(move |event| match event {
+        OutputEvent::Debug(_) => event.write_to(&mut io::stderr()),
+        _ => event.write_to(&mut output),
+    })
+}
@@ -0,0 +1,610 @@
+//! Compiled programs and the `.vbc` container they are saved in.
+//!
+//! A container is the magic bytes `VBC\0`, a little-endian `u16` format
+//! version, then the constants, the function table and the instructions.
+//! Counts and lengths are `u32`, indices and addresses `u64`. The source map
+//! may follow, for programs that are only read back by this build, as the
+//! compile cache's are.
+
+use crate::source_map::{InlinedCall, LocalVariable, SourceMap};
+use crate::vm::{Bytecode, RuntimeValue};
+use voltage_core::Span;
+
+/// The container version this build writes, and the newest it can read.
+pub const BYTECODE_VERSION: u16 = 7;
+
+const MAGIC: &[u8; 4] = b"VBC\0";
+
+/// How deeply nested a constant's arrays and structs may be, so a crafted
+/// file can't exhaust the native stack while it is read.
+pub const MAX_CONSTANT_DEPTH: usize = 256;
+
+/// Where a compiled function's code lives.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FunctionEntry {
+    pub name: String,
+    /// First instruction of the function.
+    pub start: usize,
+    /// One past its last instruction.
+    pub end: usize,
+    pub num_params: usize,
+}
+
+impl FunctionEntry {
+    pub fn contains(&self, ip: usize) -> bool {
+        (self.start..self.end).contains(&ip)
+    }
+}
+
+/// Bytecode, its constant pool and function table, as produced by
+/// [`BytecodeCompiler::compile_program`](crate::BytecodeCompiler::compile_program).
+/// Code outside every function is the program's top-level code, which starts
+/// at instruction 0.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Program {
+    pub bytecode: Vec<Bytecode>,
+    pub constants: Vec<RuntimeValue>,
+    pub functions: Vec<FunctionEntry>,
+    /// Debug information only; it isn't saved in `.vbc` containers.
+    pub source_map: SourceMap,
+}
+
+impl Program {
+    /// Serializes the program as a `.vbc` container.
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Writer(Vec::new());
+        out.0.extend_from_slice(MAGIC);
+        out.0.extend_from_slice(&BYTECODE_VERSION.to_le_bytes());
+
+        out.count(self.constants.len());
+        for constant in &self.constants {
+            out.value(constant);
+        }
+
+        out.count(self.functions.len());
+        for function in &self.functions {
+            out.string(&function.name);
+            out.index(function.start);
+            out.index(function.end);
+            out.index(function.num_params);
+        }
+
+        out.count(self.bytecode.len());
+        for instruction in &self.bytecode {
+            out.instruction(instruction);
+        }
+        out.0
+    }
+
+    /// Serializes the program as [`to_bytes`](Self::to_bytes) does, followed
+    /// by its source map, so that errors from the program read back still
+    /// say which line they came from.
+    pub fn to_bytes_with_source_map(&self) -> Vec<u8> {
+        let mut out = Writer(self.to_bytes());
+        let map = &self.source_map;
+
+        let spans: Vec<(usize, Span)> = map.iter().collect();
+        out.count(spans.len());
+        for (instruction, span) in spans {
+            out.index(instruction);
+            out.span(span);
+        }
+
+        out.count(map.inlined().len());
+        for call in map.inlined() {
+            out.index(call.instructions.start);
+            out.index(call.instructions.end);
+            out.string(&call.function);
+            match call.call_site {
+                Some(span) => {
+                    out.0.push(1);
+                    out.span(span);
+                }
+                None => out.0.push(0),
+            }
+        }
+
+        // Sorted, so the same program always gives the same bytes
+        let mut functions: Vec<(&str, &[LocalVariable])> = map.locals().collect();
+        functions.sort_by_key(|(function, _)| *function);
+        out.count(functions.len());
+        for (function, locals) in functions {
+            out.string(function);
+            out.count(locals.len());
+            for local in locals {
+                out.index(local.slot);
+                out.string(&local.name);
+                out.span(local.span);
+                out.index(local.scope.start);
+                out.index(local.scope.end);
+            }
+        }
+        out.0
+    }
+
+    /// A hash of the instructions, constants and function table, taken over
+    /// the encoding [`to_bytes`](Self::to_bytes) writes. It is the same on
+    /// every run and platform for a given compiler version, so it tells
+    /// whether recompiling actually changed anything.
+    ///
+    /// Functions are laid out in the order they are defined, so moving a
+    /// function definition changes the fingerprint even when the program
+    /// behaves the same.
+    pub fn fingerprint(&self) -> u64 {
+        content_hash(&self.to_bytes())
+    }
+
+    /// Whether `bytes` start like a `.vbc` container, rather than source code.
+    pub fn is_container(bytes: &[u8]) -> bool {
+        bytes.starts_with(MAGIC)
+    }
+
+    /// Reads a `.vbc` container. The program still has to pass validation
+    /// when it is loaded into a VM.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Program, String> {
+        let mut input = Reader { bytes, pos: 0 };
+        if input.take(MAGIC.len())? != MAGIC {
+            return Err("Not a Voltage bytecode file".to_string());
+        }
+        let version = u16::from_le_bytes(input.array()?);
+        if version > BYTECODE_VERSION {
+            return Err(format!(
+                "Bytecode version {} is newer than the supported version {}",
+                version, BYTECODE_VERSION
+            ));
+        }
+
+        let mut program = Program::default();
+        for _ in 0..input.count()? {
+            program.constants.push(input.value(MAX_CONSTANT_DEPTH)?);
+        }
+        for _ in 0..input.count()? {
+            program.functions.push(FunctionEntry {
+                name: input.string()?,
+                start: input.index()?,
+                end: input.index()?,
+                num_params: input.index()?,
+            });
+        }
+        for _ in 0..input.count()? {
+            program.bytecode.push(input.instruction()?);
+        }
+        if input.pos != bytes.len() {
+            program.source_map = input.source_map(program.bytecode.len())?;
+        }
+
+        if input.pos != bytes.len() {
+            return Err("Unexpected data after the end of the bytecode".to_string());
+        }
+        // Before version 7, a method call left only its result
+        if version < 7 && program.bytecode.iter().any(|instruction| matches!(instruction, Bytecode::CallMethod(..))) {
+            return Err(format!("Bytecode version {} calls methods as older builds did; compile it again", version));
+        }
+        Ok(program)
+    }
+}
+
+/// A 64-bit FNV-1a hash of `bytes`: quick, and the same on every platform
+/// and run, which makes it suitable for cache keys.
+pub fn content_hash(bytes: &[u8]) -> u64 {
+    bytes
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, byte| (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3))
+}
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn count(&mut self, n: usize) {
+        self.0.extend_from_slice(&(n as u32).to_le_bytes());
+    }
+
+    fn index(&mut self, n: usize) {
+        self.0.extend_from_slice(&(n as u64).to_le_bytes());
+    }
+
+    fn span(&mut self, span: Span) {
+        for n in [span.start, span.end, span.line, span.column] {
+            self.index(n);
+        }
+    }
+
+    fn string(&mut self, s: &str) {
+        self.count(s.len());
+        self.0.extend_from_slice(s.as_bytes());
+    }
+
+    fn value(&mut self, value: &RuntimeValue) {
+        match value {
+            RuntimeValue::Integer(n) => {
+                self.0.push(0);
+                self.0.extend_from_slice(&n.to_le_bytes());
+            }
+            RuntimeValue::Float(x) => {
+                self.0.push(1);
+                self.0.extend_from_slice(&x.to_le_bytes());
+            }
+            RuntimeValue::String(s) => {
+                self.0.push(2);
+                self.string(s);
+            }
+            RuntimeValue::Boolean(b) => self.0.extend_from_slice(&[3, *b as u8]),
+            RuntimeValue::Array(elements) => {
+                self.0.push(4);
+                self.count(elements.len());
+                for element in elements {
+                    self.value(element);
+                }
+            }
+            RuntimeValue::Function { name, ip, num_params } => {
+                self.0.push(5);
+                self.string(name);
+                self.index(*ip);
+                self.index(*num_params);
+            }
+            RuntimeValue::Null => self.0.push(6),
+            RuntimeValue::Range { start, end, inclusive } => {
+                self.0.push(7);
+                self.0.extend_from_slice(&start.to_le_bytes());
+                self.0.extend_from_slice(&end.to_le_bytes());
+                self.0.push(*inclusive as u8);
+            }
+            RuntimeValue::Struct { name, fields } => {
+                self.0.push(8);
+                self.string(name);
+                self.count(fields.len());
+                for (field, value) in fields {
+                    self.string(field);
+                    self.value(value);
+                }
+            }
+        }
+    }
+
+    fn instruction(&mut self, instruction: &Bytecode) {
+        let (tag, operands): (u8, &[usize]) = match instruction {
+            Bytecode::LoadConst(i) => (0, &[*i]),
+            Bytecode::StoreLocal(i) => (1, &[*i]),
+            Bytecode::LoadLocal(i) => (2, &[*i]),
+            Bytecode::StoreGlobal(name) => {
+                self.0.push(3);
+                return self.string(name);
+            }
+            Bytecode::LoadGlobal(name) => {
+                self.0.push(4);
+                return self.string(name);
+            }
+            Bytecode::Add => (5, &[]),
+            Bytecode::Sub => (6, &[]),
+            Bytecode::Mul => (7, &[]),
+            Bytecode::Div => (8, &[]),
+            Bytecode::Mod => (9, &[]),
+            Bytecode::Eq => (10, &[]),
+            Bytecode::Ne => (11, &[]),
+            Bytecode::Lt => (12, &[]),
+            Bytecode::Gt => (13, &[]),
+            Bytecode::Le => (14, &[]),
+            Bytecode::Ge => (15, &[]),
+            Bytecode::Jump(target) => (16, &[*target]),
+            Bytecode::JumpIfFalse(target) => (17, &[*target]),
+            Bytecode::JumpIfTrue(target) => (18, &[*target]),
+            Bytecode::Call(n) => (19, &[*n]),
+            Bytecode::CallBuiltin(id, n) => {
+                self.0.push(20);
+                self.index(*id);
+                return self.index(*n);
+            }
+            Bytecode::Return => (21, &[]),
+            Bytecode::Pop => (22, &[]),
+            Bytecode::Dup => (23, &[]),
+            Bytecode::MakeArray(n) => (24, &[*n]),
+            Bytecode::IndexGet => (25, &[]),
+            Bytecode::IndexSet => (26, &[]),
+            Bytecode::Swap => (27, &[]),
+            Bytecode::Nop => (28, &[]),
+            Bytecode::MakeRange(inclusive) => (29, &[*inclusive as usize]),
+            Bytecode::Len => (30, &[]),
+            Bytecode::TailCall(n) => (31, &[*n]),
+            Bytecode::Pow => (32, &[]),
+            Bytecode::Neg => (33, &[]),
+            Bytecode::PushHandler(target) => (34, &[*target]),
+            Bytecode::PopHandler => (35, &[]),
+            Bytecode::MakeStruct(name, fields) => {
+                self.0.push(36);
+                self.string(name);
+                self.count(fields.len());
+                return fields.iter().for_each(|field| self.string(field));
+            }
+            Bytecode::GetField(field) => {
+                self.0.push(37);
+                return self.string(field);
+            }
+            Bytecode::SetField(field) => {
+                self.0.push(38);
+                return self.string(field);
+            }
+            Bytecode::CallMethod(method, n) => {
+                self.0.push(39);
+                self.string(method);
+                return self.index(*n);
+            }
+            Bytecode::Roll(depth) => (40, &[*depth]),
+            Bytecode::Pick(depth) => (41, &[*depth]),
+        };
+        self.0.push(tag);
+        for operand in operands {
+            self.index(*operand);
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn take(&mut self, n: usize) -> Result<&[u8], String> {
+        let end = self.pos.checked_add(n).filter(|end| *end <= self.bytes.len());
+        let end = end.ok_or_else(|| "Bytecode file is truncated".to_string())?;
+        let slice = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn array<const N: usize>(&mut self) -> Result<[u8; N], String> {
+        Ok(self.take(N)?.try_into().unwrap())
+    }
+
+    fn byte(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn count(&mut self) -> Result<usize, String> {
+        Ok(u32::from_le_bytes(self.array()?) as usize)
+    }
+
+    fn index(&mut self) -> Result<usize, String> {
+        let n = u64::from_le_bytes(self.array()?);
+        usize::try_from(n).map_err(|_| format!("Index {} is too large for this platform", n))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.count()?;
+        String::from_utf8(self.take(len)?.to_vec()).map_err(|_| "Invalid UTF-8 in bytecode file".to_string())
+    }
+
+    fn span(&mut self) -> Result<Span, String> {
+        Ok(Span { start: self.index()?, end: self.index()?, line: self.index()?, column: self.index()? })
+    }
+
+    // The source map of a program with `len` instructions
+    fn source_map(&mut self, len: usize) -> Result<SourceMap, String> {
+        let mut map = SourceMap::new();
+        for _ in 0..self.count()? {
+            let instruction = self.index()?;
+            if instruction >= len {
+                return Err(format!("Source map refers to instruction {} of {}", instruction, len));
+            }
+            map.record(instruction..instruction + 1, self.span()?);
+        }
+        for _ in 0..self.count()? {
+            let instructions = self.index()?..self.index()?;
+            let function = self.string()?;
+            let call_site = match self.byte()? {
+                0 => None,
+                _ => Some(self.span()?),
+            };
+            map.record_inlined(InlinedCall { instructions, function, call_site });
+        }
+        for _ in 0..self.count()? {
+            let function = self.string()?;
+            let mut locals = Vec::new();
+            for _ in 0..self.count()? {
+                locals.push(LocalVariable {
+                    slot: self.index()?,
+                    name: self.string()?,
+                    span: self.span()?,
+                    scope: self.index()?..self.index()?,
+                });
+            }
+            map.record_locals(&function, locals);
+        }
+        Ok(map)
+    }
+
+    // `depth` is how many more arrays and structs the value may be nested in
+    fn value(&mut self, depth: usize) -> Result<RuntimeValue, String> {
+        let tag = self.byte()?;
+        if matches!(tag, 4 | 8) && depth == 0 {
+            return Err(format!("Constant nested more than {} deep in bytecode file", MAX_CONSTANT_DEPTH));
+        }
+        Ok(match tag {
+            0 => RuntimeValue::Integer(i64::from_le_bytes(self.array()?)),
+            1 => RuntimeValue::Float(f64::from_le_bytes(self.array()?)),
+            2 => RuntimeValue::String(self.string()?),
+            3 => RuntimeValue::Boolean(self.byte()? != 0),
+            4 => {
+                let len = self.count()?;
+                let mut elements = Vec::new();
+                for _ in 0..len {
+                    elements.push(self.value(depth - 1)?);
+                }
+                RuntimeValue::Array(elements)
+            }
+            5 => RuntimeValue::Function {
+                name: self.string()?,
+                ip: self.index()?,
+                num_params: self.index()?,
+            },
+            6 => RuntimeValue::Null,
+            7 => RuntimeValue::Range {
+                start: i64::from_le_bytes(self.array()?),
+                end: i64::from_le_bytes(self.array()?),
+                inclusive: self.byte()? != 0,
+            },
+            8 => {
+                let name = self.string()?;
+                let mut fields = Vec::new();
+                for _ in 0..self.count()? {
+                    fields.push((self.string()?, self.value(depth - 1)?));
+                }
+                RuntimeValue::Struct { name, fields }
+            }
+            tag => return Err(format!("Unknown constant tag {} in bytecode file", tag)),
+        })
+    }
+
+    fn instruction(&mut self) -> Result<Bytecode, String> {
+        Ok(match self.byte()? {
+            0 => Bytecode::LoadConst(self.index()?),
+            1 => Bytecode::StoreLocal(self.index()?),
+            2 => Bytecode::LoadLocal(self.index()?),
+            3 => Bytecode::StoreGlobal(self.string()?),
+            4 => Bytecode::LoadGlobal(self.string()?),
+            5 => Bytecode::Add,
+            6 => Bytecode::Sub,
+            7 => Bytecode::Mul,
+            8 => Bytecode::Div,
+            9 => Bytecode::Mod,
+            10 => Bytecode::Eq,
+            11 => Bytecode::Ne,
+            12 => Bytecode::Lt,
+            13 => Bytecode::Gt,
+            14 => Bytecode::Le,
+            15 => Bytecode::Ge,
+            16 => Bytecode::Jump(self.index()?),
+            17 => Bytecode::JumpIfFalse(self.index()?),
+            18 => Bytecode::JumpIfTrue(self.index()?),
+            19 => Bytecode::Call(self.index()?),
+            20 => Bytecode::CallBuiltin(self.index()?, self.index()?),
+            21 => Bytecode::Return,
+            22 => Bytecode::Pop,
+            23 => Bytecode::Dup,
+            24 => Bytecode::MakeArray(self.index()?),
+            25 => Bytecode::IndexGet,
+            26 => Bytecode::IndexSet,
+            27 => Bytecode::Swap,
+            28 => Bytecode::Nop,
+            29 => Bytecode::MakeRange(self.index()? != 0),
+            30 => Bytecode::Len,
+            31 => Bytecode::TailCall(self.index()?),
+            32 => Bytecode::Pow,
+            33 => Bytecode::Neg,
+            34 => Bytecode::PushHandler(self.index()?),
+            35 => Bytecode::PopHandler,
+            36 => {
+                let name = self.string()?;
+                let mut fields = Vec::new();
+                for _ in 0..self.count()? {
+                    fields.push(self.string()?);
+                }
+                Bytecode::MakeStruct(name, fields)
+            }
+            37 => Bytecode::GetField(self.string()?),
+            38 => Bytecode::SetField(self.string()?),
+            39 => Bytecode::CallMethod(self.string()?, self.index()?),
+            40 => Bytecode::Roll(self.index()?),
+            41 => Bytecode::Pick(self.index()?),
+            tag => return Err(format!("Unknown instruction tag {} in bytecode file", tag)),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample() -> Program {
+        Program {
+            bytecode: vec![
+                Bytecode::Jump(4),
+                Bytecode::LoadLocal(0),
+                Bytecode::CallBuiltin(0, 1),
+                Bytecode::Return,
+                Bytecode::LoadConst(0),
+                Bytecode::StoreGlobal("show".to_string()),
+                Bytecode::LoadConst(1),
+                Bytecode::Return,
+            ],
+            constants: vec![
+                RuntimeValue::Function { name: "show".to_string(), ip: 1, num_params: 1 },
+                RuntimeValue::Array(vec![RuntimeValue::Float(1.5), RuntimeValue::String("é".to_string()), RuntimeValue::Null]),
+            ],
+            functions: vec![FunctionEntry { name: "show".to_string(), start: 1, end: 4, num_params: 1 }],
+            source_map: SourceMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_container_round_trip() {
+        let program = sample();
+        let bytes = program.to_bytes();
+        assert!(bytes.starts_with(b"VBC\0\x07\x00"));
+        assert_eq!(Program::from_bytes(&bytes), Ok(program));
+    }
+
+    #[test]
+    fn test_source_map_round_trip() {
+        let mut program = sample();
+        let span = |line| Span { start: line * 10, end: line * 10 + 4, line, column: 1 };
+        program.source_map.record(1..4, span(2));
+        program.source_map.record(4..8, span(5));
+        program.source_map.record_inlined(InlinedCall { instructions: 1..3, function: "show".to_string(), call_site: Some(span(5)) });
+        let local = LocalVariable { slot: 0, name: "x".to_string(), span: span(2), scope: 1..4 };
+        program.source_map.record_locals("show", vec![local]);
+
+        let bytes = program.to_bytes_with_source_map();
+        assert!(bytes.starts_with(&program.to_bytes()));
+        assert_eq!(Program::from_bytes(&bytes), Ok(program));
+    }
+
+    #[test]
+    fn test_newer_version_is_rejected() {
+        let mut bytes = sample().to_bytes();
+        bytes[4..6].copy_from_slice(&8u16.to_le_bytes());
+        assert_eq!(
+            Program::from_bytes(&bytes),
+            Err("Bytecode version 8 is newer than the supported version 7".to_string())
+        );
+    }
+
+    #[test]
+    fn test_malformed_files_are_rejected() {
+        assert_eq!(Program::from_bytes(b"ELF\0\x01\x00"), Err("Not a Voltage bytecode file".to_string()));
+        let bytes = sample().to_bytes();
+        assert_eq!(Program::from_bytes(&bytes[..bytes.len() - 1]), Err("Bytecode file is truncated".to_string()));
+    }
+
+    #[test]
+    fn test_deeply_nested_constants_are_rejected() {
+        let container = |depth: usize| {
+            let mut bytes = b"VBC\0\x07\x00".to_vec();
+            bytes.extend(1u32.to_le_bytes());
+            for _ in 0..depth {
+                bytes.push(4);
+                bytes.extend(1u32.to_le_bytes());
+            }
+            bytes.push(6);
+            bytes.extend([0; 8]);
+            bytes
+        };
+        assert!(Program::from_bytes(&container(MAX_CONSTANT_DEPTH)).is_ok());
+        assert_eq!(
+            Program::from_bytes(&container(MAX_CONSTANT_DEPTH + 1)),
+            Err("Constant nested more than 256 deep in bytecode file".to_string())
+        );
+        // Far past the limit, where reading it recursively would overflow the stack
+        assert!(Program::from_bytes(&container(1_000_000)).is_err());
+    }
+
+    #[test]
+    fn test_fingerprint_ignores_source_map() {
+        let mut program = sample();
+        let fingerprint = program.fingerprint();
+        program.source_map.record(0..2, voltage_core::Span { start: 0, end: 4, line: 1, column: 1 });
+        assert_eq!(program.fingerprint(), fingerprint);
+        // Pinned so an accidental change to the encoding shows up here
+        assert_eq!(fingerprint, 0xb51a_5282_2763_cff4);
+    }
+}
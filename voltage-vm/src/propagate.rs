@@ -0,0 +1,84 @@
+//! Constant propagation: loading globals that never change from the
+//! constant pool instead of the VM's globals.
+
+use std::collections::HashMap;
+use crate::program::Program;
+use crate::vm::{Bytecode, RuntimeValue};
+
+/// Replaces each load of a global that is effectively constant with a load
+/// of its value from the constant pool, which spares the VM looking the
+/// global up by name. A global is effectively constant when the program
+/// stores to it exactly once, with a constant, in the straight-line code
+/// the top-level code starts with, where no function can run yet and none
+/// of its loads come before the store. Functions are left as globals. The
+/// stores stay, so the globals are still there to look up.
+///
+/// Only for whole programs whose globals nothing else changes: a REPL
+/// session defines them again from one input to the next, and a host can
+/// set them between calls.
+///
+/// ```
+/// use voltage_vm::{propagate_constants, Bytecode};
+///
+/// let program = voltage_vm::compile("let rate = 0.25;\nfn tax(n) { return n * rate; }\nputs(tax(8.0));").unwrap();
+/// let propagated = propagate_constants(program);
+/// assert!(!propagated.bytecode.iter().any(|op| matches!(op, Bytecode::LoadGlobal(_))));
+/// ```
+pub fn propagate_constants(mut program: Program) -> Program {
+    let constants = constant_globals(&program);
+    for op in &mut program.bytecode {
+        if let Bytecode::LoadGlobal(name) = op {
+            if let Some(&index) = constants.get(name.as_str()) {
+                *op = Bytecode::LoadConst(index);
+            }
+        }
+    }
+    program
+}
+
+// The effectively constant globals, with where their values are in the
+// constant pool
+fn constant_globals(program: &Program) -> HashMap<String, usize> {
+    let mut stores: HashMap<&str, usize> = HashMap::new();
+    for op in &program.bytecode {
+        if let Bytecode::StoreGlobal(name) = op {
+            *stores.entry(name).or_default() += 1;
+        }
+    }
+
+    // The top-level code starts after the function bodies, which the program
+    // jumps over
+    let entry = match program.bytecode.first() {
+        Some(Bytecode::Jump(target)) => *target,
+        _ => 0,
+    };
+    let mut constants = HashMap::new();
+    let mut loaded = Vec::new();
+    for (ip, op) in program.bytecode.iter().enumerate().skip(entry) {
+        match op {
+            // Anything that could run a function or skip code ends the
+            // straight-line start
+            Bytecode::Jump(_)
+            | Bytecode::JumpIfFalse(_)
+            | Bytecode::JumpIfTrue(_)
+            | Bytecode::Call(_)
+            | Bytecode::TailCall(_)
+            | Bytecode::CallBuiltin(..)
+            | Bytecode::CallMethod(..)
+            | Bytecode::Return
+            | Bytecode::PushHandler(_)
+            | Bytecode::PopHandler => break,
+            Bytecode::LoadGlobal(name) => loaded.push(name),
+            Bytecode::StoreGlobal(name) if stores[name.as_str()] == 1 && !loaded.contains(&name) => {
+                let Some(Bytecode::LoadConst(index)) = ip.checked_sub(1).map(|previous| &program.bytecode[previous]) else {
+                    continue;
+                };
+                if !matches!(program.constants[*index], RuntimeValue::Function { .. }) {
+                    constants.insert(name.clone(), *index);
+                }
+            }
+            _ => {}
+        }
+    }
+    constants
+}
@@ -0,0 +1,99 @@
+//! The pseudo-random number generator behind `random`, `random_int` and
+//! `seed_random`. Each builtin registry owns one, so VMs never share a
+//! sequence.
+
+use std::cell::RefCell;
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::rc::Rc;
+use crate::builtins::BuiltinRegistry;
+
+/// SplitMix64: tiny, fast, and good enough for games and shuffling. Not
+/// suitable for anything security related.
+#[derive(Debug, Clone)]
+pub struct Rng {
+    state: u64,
+}
+
+impl Rng {
+    pub fn with_seed(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    /// A generator seeded from the process's hash randomness.
+    pub fn from_entropy() -> Self {
+        Self::with_seed(RandomState::new().build_hasher().finish())
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// A float in `[0, 1)`.
+    pub fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// An integer in `lo..=hi`; the caller checks `lo <= hi`.
+    pub fn next_in_range(&mut self, lo: i64, hi: i64) -> i64 {
+        let span = (hi as i128 - lo as i128 + 1) as u128;
+        // Multiply-shift maps the 64 random bits onto the span without a modulo
+        let offset = (self.next_u64() as u128 * span) >> 64;
+        (lo as i128 + offset as i128) as i64
+    }
+}
+
+pub(crate) fn register_builtins(registry: &mut BuiltinRegistry, rng: &Rc<RefCell<Rng>>) {
+    let state = Rc::clone(rng);
+    registry
+        .register_fn("random", move || state.borrow_mut().next_f64())
+        .expect("random is not a core builtin");
+
+    let state = Rc::clone(rng);
+    registry
+        .register_fn("random_int", move |lo: i64, hi: i64| -> Result<i64, String> {
+            if lo > hi {
+                return Err(format!("random_int: lo ({}) must not be greater than hi ({})", lo, hi));
+            }
+            Ok(state.borrow_mut().next_in_range(lo, hi))
+        })
+        .expect("random_int is not a core builtin");
+
+    let state = Rc::clone(rng);
+    registry
+        .register_fn("seed_random", move |seed: i64| {
+            *state.borrow_mut() = Rng::with_seed(seed as u64);
+        })
+        .expect("seed_random is not a core builtin");
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_same_seed_same_sequence() {
+        let mut a = Rng::with_seed(7);
+        let mut b = Rng::with_seed(7);
+        let first: Vec<u64> = (0..5).map(|_| a.next_u64()).collect();
+        assert_eq!(first, (0..5).map(|_| b.next_u64()).collect::<Vec<_>>());
+        assert_ne!(first[0], Rng::with_seed(8).next_u64());
+    }
+
+    #[test]
+    fn test_values_stay_in_bounds() {
+        let mut rng = Rng::with_seed(1);
+        for _ in 0..10_000 {
+            let x = rng.next_f64();
+            assert!((0.0..1.0).contains(&x));
+            assert!((-3..=3).contains(&rng.next_in_range(-3, 3)));
+        }
+        assert_eq!(rng.next_in_range(4, 4), 4);
+        // The full range must not overflow the span computation
+        rng.next_in_range(i64::MIN, i64::MAX);
+    }
+}
@@ -0,0 +1,364 @@
+//! What the names in a program refer to, for the bytecode compiler.
+
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use crate::builtins::BuiltinRegistry;
+use crate::modules::{Member, ModuleLoader};
+use crate::vm::RuntimeValue;
+use voltage_core::Span;
+
+/// What a name refers to. Names are looked up in this order, so a local
+/// hides a global of the same name, and a global hides a builtin.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Resolution {
+    /// A slot of the function being compiled: a parameter, or a `let` in
+    /// the block being compiled or one enclosing it.
+    Local(usize),
+    /// A variable of the top-level code.
+    Global,
+    /// A module the program imports, with its path.
+    Module(String),
+    /// A function the program defines, with its parameter count.
+    Function(usize),
+    /// A function defined inside the one being compiled or one enclosing
+    /// it, with its full name, like `main::helper`, and parameter count.
+    Nested(String, usize),
+    /// A function or variable of the module file being compiled, with its
+    /// full name, like `utils::helper`, and its parameter count if it's a
+    /// function.
+    Own(String, Option<usize>),
+    /// A parameter or variable of the enclosing function named here, which
+    /// a function defined inside it can't use.
+    Captured(String),
+    /// A builtin, with its id in the registry.
+    Builtin(usize),
+}
+
+// A local slot of the function being compiled
+#[derive(Clone)]
+struct Local {
+    name: String,
+    // Where it was declared
+    span: Span,
+    parameter: bool,
+    // Whether the name can be used; `_` never can, and other locals can't
+    // once their block ends
+    visible: bool,
+    used: bool,
+}
+
+/// A local that was never read, for the unused variable warning.
+#[derive(Clone)]
+pub(crate) struct Unused {
+    pub(crate) name: String,
+    pub(crate) span: Span,
+    pub(crate) parameter: bool,
+}
+
+#[derive(Clone)]
+pub(crate) struct Resolver {
+    // Local slots of the function being compiled; `None` while compiling
+    // top-level code. The slots of blocks that ended keep their place.
+    locals: Option<Vec<Local>>,
+    // How many locals there were when each enclosing block began
+    scopes: Vec<usize>,
+    // The globals the program declares
+    globals: HashSet<String>,
+    // Whether names that aren't declared anywhere are errors; otherwise
+    // they are globals that earlier runs on the same VM may have defined
+    strict: bool,
+    // The parameter count of each function the program defines
+    functions: HashMap<String, usize>,
+    // The functions defined inside other functions, by the full name of
+    // the function they're in and then their own name, with parameter counts
+    nested: HashMap<String, HashMap<String, usize>>,
+    // The parameters and variables of each function with functions defined
+    // inside it, which those can't use
+    outer_locals: HashMap<String, HashSet<String>>,
+    // The full name of the function being compiled
+    function: Option<String>,
+    // The fields of each struct the program defines, in order, with their
+    // defaults
+    structs: HashMap<String, Vec<(String, Option<RuntimeValue>)>>,
+    pub(crate) builtins: BuiltinRegistry,
+    // The modules the program may import
+    pub(crate) modules: ModuleLoader,
+    // The path of each module the program imports, by the name it's used as
+    imports: HashMap<String, String>,
+    // The path of the module file whose code is being compiled, if any
+    module: Option<String>,
+    // Locals whose scope ended without them being read
+    unused: Vec<Unused>,
+}
+
+impl Resolver {
+    pub(crate) fn new() -> Self {
+        Self {
+            locals: None,
+            scopes: Vec::new(),
+            globals: HashSet::new(),
+            strict: false,
+            functions: HashMap::new(),
+            nested: HashMap::new(),
+            outer_locals: HashMap::new(),
+            function: None,
+            structs: HashMap::new(),
+            builtins: BuiltinRegistry::new(),
+            modules: ModuleLoader::new(),
+            imports: HashMap::new(),
+            module: None,
+            unused: Vec::new(),
+        }
+    }
+
+    /// Makes names that the program never declares errors, as they are in
+    /// a script.
+    pub(crate) fn require_declarations(&mut self) {
+        self.strict = true;
+    }
+
+    pub(crate) fn requires_declarations(&self) -> bool {
+        self.strict
+    }
+
+    pub(crate) fn declare_global(&mut self, name: &str) {
+        self.globals.insert(name.to_string());
+    }
+
+    pub(crate) fn import(&mut self, alias: &str, path: &str) {
+        self.imports.insert(alias.to_string(), path.to_string());
+    }
+
+    pub(crate) fn define_function(&mut self, name: &str, num_params: usize) {
+        self.functions.insert(name.to_string(), num_params);
+    }
+
+    pub(crate) fn is_function(&self, name: &str) -> bool {
+        self.functions.contains_key(name)
+    }
+
+    pub(crate) fn define_struct(&mut self, name: &str, fields: Vec<(String, Option<RuntimeValue>)>) {
+        self.structs.insert(name.to_string(), fields);
+    }
+
+    /// The fields of the struct `name` and their defaults, if the program
+    /// defines it. Structs it doesn't define can have any fields.
+    pub(crate) fn struct_fields(&self, name: &str) -> Option<&[(String, Option<RuntimeValue>)]> {
+        self.structs.get(name).map(Vec::as_slice)
+    }
+
+    /// Defines `name` inside the function `outer`, with `locals` the
+    /// parameters and variables of `outer`.
+    pub(crate) fn define_nested(&mut self, outer: &str, name: &str, num_params: usize, locals: &HashSet<String>) {
+        self.nested.entry(outer.to_string()).or_default().insert(name.to_string(), num_params);
+        self.outer_locals.insert(outer.to_string(), locals.clone());
+    }
+
+    /// The function that `name` is defined inside, if it's defined inside
+    /// one. Code anywhere else can't call it.
+    pub(crate) fn defined_inside(&self, name: &str) -> Option<&str> {
+        self.nested
+            .iter()
+            .filter(|(_, functions)| functions.contains_key(name))
+            .map(|(outer, _)| outer.as_str())
+            .min()
+    }
+
+    /// Compiles top-level code of the module file `module`, whose names
+    /// then hide any others, or of the program itself.
+    pub(crate) fn set_module(&mut self, module: Option<&str>) {
+        self.module = module.map(str::to_string);
+    }
+
+    /// The path of the module file whose code is being compiled, if any.
+    pub(crate) fn module(&self) -> Option<&str> {
+        self.module.as_deref()
+    }
+
+    /// The module file whose top-level code defines `name`, a name the
+    /// program uses without its module.
+    pub(crate) fn defined_in_module(&self, name: &str) -> Option<&str> {
+        self.modules.paths().into_iter().find(|path| {
+            let module = self.modules.get(path).expect("paths are of modules the loader has");
+            matches!(module.member(name), Some(Member::Global { .. }))
+        })
+    }
+
+    /// Starts compiling the function called `name`, declared at `span`,
+    /// whose parameters take the first slots. A function with a full name
+    /// like `utils::helper` is in the module file `utils`.
+    pub(crate) fn enter_function<'a>(&mut self, name: &str, parameters: impl IntoIterator<Item = &'a str>, span: Span) {
+        self.module = self.modules.paths().into_iter().find(|path| name.starts_with(&format!("{}::", path))).map(str::to_string);
+        self.function = Some(name.to_string());
+        self.locals = Some(parameters.into_iter().map(|name| Local::new(name, span, true)).collect());
+    }
+
+    /// The full name of the function being compiled, if any.
+    pub(crate) fn function(&self) -> Option<&str> {
+        self.function.as_deref()
+    }
+
+    /// Finishes the function being compiled and returns its locals that
+    /// were never read, in the order they were declared.
+    pub(crate) fn leave_function(&mut self) -> Vec<Unused> {
+        self.scopes.clear();
+        self.end_scope_at(0);
+        self.locals = None;
+        self.function = None;
+        self.module = None;
+        let mut unused = std::mem::take(&mut self.unused);
+        unused.sort_by_key(|local| local.span.start);
+        unused
+    }
+
+        pub(crate) fn in_function(&self) -> bool {
+        self.locals.is_some()
+    }
+
+    pub(crate) fn begin_scope(&mut self) {
+        if let Some(locals) = &self.locals {
+            self.scopes.push(locals.len());
+        }
+    }
+
+    /// Hides the locals declared since the matching `begin_scope`, and
+    /// returns their slots.
+    pub(crate) fn end_scope(&mut self) -> Range<usize> {
+        let Some(start) = self.scopes.pop() else {
+            return 0..0;
+        };
+        self.end_scope_at(start);
+        start..self.locals.as_ref().map_or(start, Vec::len)
+    }
+
+    fn end_scope_at(&mut self, start: usize) {
+        let Some(locals) = &mut self.locals else {
+            return;
+        };
+        for local in locals.iter_mut().skip(start).filter(|local| local.visible) {
+            local.visible = false;
+            // The compiler's own locals start with `$`
+            if !local.used && !local.name.starts_with('$') {
+                self.unused.push(Unused { name: local.name.clone(), span: local.span, parameter: local.parameter });
+            }
+        }
+    }
+
+    /// Where the parameter that a variable called `name` declared here would
+    /// hide is, if this is the top level of a function's body. There it
+    /// hides the parameter for the rest of the function; blocks in the body
+    /// are scopes of their own, whose variables only hide it inside them.
+    pub(crate) fn shadowed_parameter(&self, name: &str) -> Option<Span> {
+        if !self.scopes.is_empty() {
+            return None;
+        }
+        let locals = self.locals.as_ref()?;
+        locals.iter().find(|local| local.parameter && local.visible && local.name == name).map(|local| local.span)
+    }
+
+    /// Declares a variable, at `span`, in the innermost scope and returns
+    /// its slot, or `None` for a global.
+    pub(crate) fn declare(&mut self, name: &str, span: Span) -> Option<usize> {
+        match &mut self.locals {
+            Some(locals) => {
+                locals.push(Local::new(name, span, false));
+                Some(locals.len() - 1)
+            }
+            None => {
+                self.globals.insert(name.to_string());
+                None
+            }
+        }
+    }
+
+    /// What `name` refers to here, or `None` if it isn't declared anywhere
+    /// the program can see. A local found this way counts as used.
+    pub(crate) fn resolve(&mut self, name: &str) -> Option<Resolution> {
+        let locals = self.locals.as_deref_mut().unwrap_or_default();
+        if let Some(slot) = locals.iter().rposition(|local| local.visible && local.name == name) {
+            locals[slot].used = true;
+            return Some(Resolution::Local(slot));
+        }
+        // The functions this one is defined in, innermost first
+        let function = self.function.as_deref();
+        let enclosing = std::iter::successors(function, |name| name.rsplit_once("::").map(|(outer, _)| outer));
+        for (depth, outer) in enclosing.enumerate() {
+            if depth > 0 && self.outer_locals.get(outer).is_some_and(|locals| locals.contains(name)) {
+                return Some(Resolution::Captured(outer.to_string()));
+            }
+            if let Some(&num_params) = self.nested.get(outer).and_then(|functions| functions.get(name)) {
+                return Some(Resolution::Nested(format!("{}::{}", outer, name), num_params));
+            }
+        }
+        // A module file's own names, whether `pub` or not
+        let module = self.module.as_deref().and_then(|path| self.modules.get(path));
+        if let Some(Member::Global { global, .. }) = module.and_then(|module| module.member(name)) {
+            return Some(Resolution::Own(global.clone(), self.functions.get(global).copied()));
+        }
+        if self.globals.contains(name) {
+            return Some(Resolution::Global);
+        }
+        if let Some(path) = self.imports.get(name) {
+            return Some(Resolution::Module(path.clone()));
+        }
+        if let Some(&num_params) = self.functions.get(name) {
+            return Some(Resolution::Function(num_params));
+        }
+        self.builtins.id(name).map(Resolution::Builtin)
+    }
+}
+
+impl Local {
+    fn new(name: &str, span: Span, parameter: bool) -> Self {
+        Self { name: name.to_string(), span, parameter, visible: name != "_", used: false }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolution_order() {
+        let mut names = Resolver::new();
+        names.declare_global("count");
+        names.define_function("helper", 2);
+        names.enter_function("f", ["count"], Span::default());
+        assert_eq!(names.resolve("count"), Some(Resolution::Local(0)));
+        assert_eq!(names.resolve("helper"), Some(Resolution::Function(2)));
+        assert_eq!(names.resolve("puts"), Some(Resolution::Builtin(0)));
+        assert_eq!(names.resolve("missing"), None);
+
+        names.leave_function();
+        assert_eq!(names.resolve("count"), Some(Resolution::Global));
+    }
+
+    #[test]
+    fn test_scopes_hide_their_locals_when_they_end() {
+        let mut names = Resolver::new();
+        names.enter_function("f", ["x"], Span::default());
+        names.begin_scope();
+        assert_eq!(names.declare("x", Span::default()), Some(1));
+        assert_eq!(names.resolve("x"), Some(Resolution::Local(1)));
+        names.end_scope();
+        assert_eq!(names.resolve("x"), Some(Resolution::Local(0)));
+        // The slot stays taken
+        assert_eq!(names.declare("y", Span::default()), Some(2));
+    }
+
+    #[test]
+    fn test_nested_functions_are_seen_from_their_function() {
+        let mut names = Resolver::new();
+        names.define_function("helper", 0);
+        names.define_nested("main", "helper", 1, &HashSet::from(["total".to_string()]));
+        names.enter_function("main", [], Span::default());
+        assert_eq!(names.resolve("helper"), Some(Resolution::Nested("main::helper".to_string(), 1)));
+        names.leave_function();
+        names.enter_function("main::helper", ["x"], Span::default());
+        assert_eq!(names.resolve("helper"), Some(Resolution::Nested("main::helper".to_string(), 1)));
+        assert_eq!(names.resolve("total"), Some(Resolution::Captured("main".to_string())));
+        names.leave_function();
+        assert_eq!(names.resolve("helper"), Some(Resolution::Function(0)));
+        assert_eq!(names.defined_inside("helper"), Some("main"));
+    }
+}
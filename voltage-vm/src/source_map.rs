@@ -0,0 +1,163 @@
+//! Where each instruction of a compiled program came from in the source.
+
+use std::collections::HashMap;
+use std::ops::Range;
+use voltage_core::Span;
+
+/// The span of the statement each instruction was compiled from. Code the
+/// compiler adds on its own, like the jump over function bodies, has none.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct SourceMap {
+    spans: Vec<Option<Span>>,
+    inlined: Vec<InlinedCall>,
+    // The local slots of each function
+    locals: HashMap<String, Vec<LocalVariable>>,
+}
+
+/// A call that [inlining](crate::inline_functions) replaced with the code of
+/// the function called. Its instructions keep the spans they had in that
+/// function, so this is what tells a trace which call they are part of.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InlinedCall {
+    pub instructions: Range<usize>,
+    pub function: String,
+    /// Where the call was.
+    pub call_site: Option<Span>,
+}
+
+/// A local slot of a compiled function: a parameter, a variable, or a
+/// variable of the compiler's own, whose name starts with `$`. Slots aren't
+/// reused, so a variable that shadows another has a slot of its own.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LocalVariable {
+    pub slot: usize,
+    pub name: String,
+    /// Where it was declared; a parameter's is its function's.
+    pub span: Span,
+    /// The instructions that can use it, from its declaration to the end of
+    /// its block.
+    pub scope: Range<usize>,
+}
+
+impl SourceMap {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Gives every instruction in `instructions` that doesn't have a span yet
+    /// the span `span`. Statements are recorded after the statements nested
+    /// inside them, so each instruction keeps the innermost span.
+    pub fn record(&mut self, instructions: std::ops::Range<usize>, span: Span) {
+        if !span.is_known() {
+            return;
+        }
+        if self.spans.len() < instructions.end {
+            self.spans.resize(instructions.end, None);
+        }
+        for slot in &mut self.spans[instructions] {
+            slot.get_or_insert(span);
+        }
+    }
+
+    pub fn span_for(&self, instruction: usize) -> Option<Span> {
+        self.spans.get(instruction).copied().flatten()
+    }
+
+    /// The instructions compiled from statements starting on `line`, in order.
+    pub fn instructions_for_line(&self, line: usize) -> Vec<usize> {
+        self.spans
+            .iter()
+            .enumerate()
+            .filter(|(_, span)| span.is_some_and(|span| span.line == line))
+            .map(|(instruction, _)| instruction)
+            .collect()
+    }
+
+    /// Each instruction that has a span, with the span, in order.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, Span)> + '_ {
+        self.spans
+            .iter()
+            .enumerate()
+            .filter_map(|(instruction, span)| Some((instruction, (*span)?)))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.spans.iter().all(Option::is_none)
+    }
+
+    pub fn record_inlined(&mut self, call: InlinedCall) {
+        self.inlined.push(call);
+    }
+
+    /// Every inlined call, in the order they were recorded.
+    pub fn inlined(&self) -> &[InlinedCall] {
+        &self.inlined
+    }
+
+    /// Records the local slots of `function`, in slot order.
+    pub fn record_locals(&mut self, function: &str, locals: Vec<LocalVariable>) {
+        self.locals.insert(function.to_string(), locals);
+    }
+
+    /// The local slots of `function` in slot order, parameters first, if it
+    /// was compiled from source.
+    pub fn locals_of(&self, function: &str) -> Option<&[LocalVariable]> {
+        self.locals.get(function).map(Vec::as_slice)
+    }
+
+    /// Every function with recorded locals, and the locals.
+    pub fn locals(&self) -> impl Iterator<Item = (&str, &[LocalVariable])> + '_ {
+        self.locals.iter().map(|(function, locals)| (function.as_str(), locals.as_slice()))
+    }
+
+    /// The inlined calls whose code `instruction` is part of, innermost
+    /// first: more than one when an inlined function had another inlined
+    /// into it.
+    pub fn inlined_at(&self, instruction: usize) -> Vec<&InlinedCall> {
+        let mut calls: Vec<&InlinedCall> =
+            self.inlined.iter().filter(|call| call.instructions.contains(&instruction)).collect();
+        calls.sort_by_key(|call| call.instructions.len());
+        calls
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(start: usize, end: usize, line: usize) -> Span {
+        Span { start, end, line, column: 1 }
+    }
+
+    #[test]
+    fn test_inner_spans_win() {
+        let mut map = SourceMap::new();
+        map.record(1..3, span(10, 20, 2));
+        map.record(0..4, span(0, 30, 1));
+        assert_eq!(map.span_for(0), Some(span(0, 30, 1)));
+        assert_eq!(map.span_for(2), Some(span(10, 20, 2)));
+        assert_eq!(map.span_for(4), None);
+        assert_eq!(map.instructions_for_line(2), vec![1, 2]);
+        assert_eq!(map.instructions_for_line(1), vec![0, 3]);
+    }
+
+    #[test]
+    fn test_nested_inlined_calls_come_innermost_first() {
+        let mut map = SourceMap::new();
+        let call = |instructions, function: &str| InlinedCall { instructions, function: function.to_string(), call_site: None };
+        map.record_inlined(call(2..10, "outer"));
+        map.record_inlined(call(4..6, "inner"));
+        let names = |instruction| map.inlined_at(instruction).iter().map(|call| call.function.clone()).collect::<Vec<_>>();
+        assert_eq!(names(5), ["inner", "outer"]);
+        assert_eq!(names(8), ["outer"]);
+        assert!(names(10).is_empty());
+    }
+
+    #[test]
+    fn test_unknown_spans_are_not_recorded() {
+        let mut map = SourceMap::new();
+        map.record(0..2, Span::default());
+        assert!(map.is_empty());
+        assert_eq!(map.span_for(0), None);
+    }
+}
@@ -0,0 +1,42 @@
+//! Builtins for writing tests in Voltage itself.
+
+use crate::builtins::{Arity, BuiltinRegistry};
+use crate::vm::{condition, RuntimeValue};
+
+pub(crate) fn register_builtins(registry: &mut BuiltinRegistry) {
+    registry
+        .register_with_arity("assert", Arity::between(1, 2), |args| {
+            let (cond, message) = match args {
+                [cond] => (cond, None),
+                [cond, message] => (cond, Some(message)),
+                _ => return Err(format!("assert expects 1 or 2 arguments, got {}", args.len())),
+            };
+            // The condition follows the same rule as `if` and `while`
+            match (condition(cond)?, message) {
+                (true, _) => Ok(RuntimeValue::Null),
+                (false, None) => Err("Assertion failed".to_string()),
+                (false, Some(message)) => Err(format!("Assertion failed: {}", message)),
+            }
+        })
+        .expect("assert is not a core builtin");
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{Engine, RuntimeValue};
+
+    #[test]
+    fn test_assert() {
+        let mut engine = Engine::new();
+        assert_eq!(engine.eval("assert(1 < 2);"), Ok(RuntimeValue::Null));
+        assert_eq!(engine.eval("assert(2 < 1);").unwrap_err().to_string(), "Runtime error: Assertion failed");
+        assert_eq!(
+            engine.eval(r#"assert(2 < 1, "two is small");"#).unwrap_err().to_string(),
+            "Runtime error: Assertion failed: two is small"
+        );
+        assert_eq!(
+            engine.eval("assert(1);").unwrap_err().to_string(),
+            "Runtime error: expected bool in condition, found int"
+        );
+    }
+}
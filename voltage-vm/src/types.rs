@@ -0,0 +1,167 @@
+//! Static types of expressions, as far as they can be told without running
+//! them, and the checks the compiler makes with them.
+
+use voltage_core::{Expression, Literal, Type};
+
+/// The type of `expr`: literals and array literals have one, anything else
+/// is `Type::Unknown` until it runs.
+///
+/// An array literal takes its element type from its elements, which must all
+/// have the same type; the error names the first one that doesn't. Elements
+/// of unknown type fit any array, and an empty literal is `[unknown]`, so
+/// `[]` can start any array.
+pub fn infer_type(expr: &Expression) -> Result<Type, String> {
+    match expr {
+        Expression::Literal(literal) => Ok(match literal {
+            Literal::Integer(_) => Type::Integer,
+            Literal::Float(_) => Type::Float,
+            Literal::String(_) => Type::String,
+            Literal::Boolean(_) => Type::Boolean,
+        }),
+        Expression::ArrayLiteral(elements) => {
+            let mut element_type = Type::Unknown;
+            for (index, element) in elements.iter().enumerate() {
+                let found = infer_type(element)?;
+                element_type = unify(&element_type, &found).ok_or_else(|| {
+                    format!("Array element {} is {}, but the elements before it are {}", index, found, element_type)
+                })?;
+            }
+            Ok(Type::DynamicArray(Box::new(element_type)))
+        }
+        _ => Ok(Type::Unknown),
+    }
+}
+
+/// Checks the value of `let name: declared = value` against its annotation.
+/// For an array literal declared as `[T]`, the error names the first element
+/// that isn't a `T`. One declared as `[T; N]` must also have exactly N
+/// elements; fixed-size arrays are always declared with a value, so there's
+/// no default to fill them with.
+pub fn check_declaration(name: &str, declared: &Type, value: &Expression) -> Result<(), String> {
+    if let (Type::Array(_, len), Expression::ArrayLiteral(elements)) = (declared, value) {
+        if elements.len() != *len {
+            return Err(format!("`{}` is declared as {}, but its value has {} elements", name, declared, elements.len()));
+        }
+    }
+    if let (Type::Array(element_type, _) | Type::DynamicArray(element_type), Expression::ArrayLiteral(elements)) =
+        (declared, value)
+    {
+        for (index, element) in elements.iter().enumerate() {
+            let found = infer_type(element)?;
+            if unify(element_type, &found).is_none() {
+                return Err(format!("Array element {} is {}, but `{}` is declared as {}", index, found, name, declared));
+            }
+        }
+        return Ok(());
+    }
+
+    let found = infer_type(value)?;
+    match unify(declared, &found) {
+        Some(_) => Ok(()),
+        None => Err(format!("`{}` is declared as {}, but its value is {}", name, declared, found)),
+    }
+}
+
+// The more specific of two types that agree, or `None` if they don't
+fn unify(a: &Type, b: &Type) -> Option<Type> {
+    match (a, b) {
+        (Type::Unknown, other) | (other, Type::Unknown) => Some(other.clone()),
+        (Type::DynamicArray(a), Type::DynamicArray(b)) => Some(Type::DynamicArray(Box::new(unify(a, b)?))),
+        // Array literals infer as `[T]`, whatever their length
+        (Type::Array(a, len), Type::DynamicArray(b)) | (Type::DynamicArray(b), Type::Array(a, len)) => {
+            Some(Type::Array(Box::new(unify(a, b)?), *len))
+        }
+        _ if a == b => Some(a.clone()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use voltage_core::StatementKind;
+    use voltage_parser::{Lexer, Parser};
+
+    // The value and annotation of the `let` in `source`
+    fn declaration(source: &str) -> (String, Option<Type>, Expression) {
+        match Parser::from_lexer(Lexer::new(source)).parse().remove(0).kind {
+            StatementKind::VariableDeclaration { name, value, explicit_type, .. } => (name, explicit_type, value),
+            other => panic!("Expected a declaration, got {:?}", other),
+        }
+    }
+
+    fn check(source: &str) -> Result<Type, String> {
+        let (name, declared, value) = declaration(source);
+        match declared {
+            Some(declared) => check_declaration(&name, &declared, &value).map(|_| declared),
+            None => infer_type(&value),
+        }
+    }
+
+    fn array_of(element: Type) -> Type {
+        Type::DynamicArray(Box::new(element))
+    }
+
+    #[test]
+    fn test_element_type_is_inferred_from_the_elements() {
+        assert_eq!(check("let xs = [1, 2, 3];"), Ok(array_of(Type::Integer)));
+        assert_eq!(check("let xs = [n, 2];"), Ok(array_of(Type::Integer)));
+        assert_eq!(
+            check("let xs = [1, 2, \"three\", true];"),
+            Err("Array element 2 is string, but the elements before it are int".to_string())
+        );
+    }
+
+    #[test]
+    fn test_annotated_array_must_match_its_element_type() {
+        assert_eq!(check("let xs: [string] = [\"a\", \"b\"];"), Ok(array_of(Type::String)));
+        assert_eq!(
+            check("let xs: [int] = [1, \"two\"];"),
+            Err("Array element 1 is string, but `xs` is declared as [int]".to_string())
+        );
+        assert_eq!(
+            check("let n: int = [1];"),
+            Err("`n` is declared as int, but its value is [int]".to_string())
+        );
+    }
+
+    #[test]
+    fn test_empty_arrays() {
+        assert_eq!(check("let xs = [];"), Ok(array_of(Type::Unknown)));
+        assert_eq!(check("let xs: [bool] = [];"), Ok(array_of(Type::Boolean)));
+        assert_eq!(check("let xs: [[int]] = [[], [1]];"), Ok(array_of(array_of(Type::Integer))));
+    }
+
+    #[test]
+    fn test_fixed_size_arrays_must_have_their_length() {
+        let fixed = |element, len| Type::Array(Box::new(element), len);
+        assert_eq!(check("let xs: [int; 3] = [1, 2, 3];"), Ok(fixed(Type::Integer, 3)));
+        assert_eq!(check("let xs: [int; 0] = [];"), Ok(fixed(Type::Integer, 0)));
+        assert_eq!(
+            check("let xs: [int; 3] = [1, 2];"),
+            Err("`xs` is declared as [int; 3], but its value has 2 elements".to_string())
+        );
+        assert_eq!(
+            check("let xs: [int; 2] = [1, true];"),
+            Err("Array element 1 is bool, but `xs` is declared as [int; 2]".to_string())
+        );
+        assert_eq!(check("let grid: [[int; 2]; 1] = [[1, 2]];"), Ok(fixed(fixed(Type::Integer, 2), 1)));
+    }
+
+    #[test]
+    fn test_nested_arrays() {
+        assert_eq!(check("let grid = [[1, 2], [], [3]];"), Ok(array_of(array_of(Type::Integer))));
+        assert_eq!(
+            check("let grid = [[1], [\"a\"]];"),
+            Err("Array element 1 is [string], but the elements before it are [int]".to_string())
+        );
+        assert_eq!(
+            check("let grid = [[1], [2, \"b\"]];"),
+            Err("Array element 1 is string, but the elements before it are int".to_string())
+        );
+        assert_eq!(
+            check("let grid: [[int]] = [[1], 2];"),
+            Err("Array element 1 is int, but `grid` is declared as [[int]]".to_string())
+        );
+    }
+}
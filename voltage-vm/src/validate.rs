@@ -0,0 +1,388 @@
+//! Checks run on a program before a VM executes it, so malformed bytecode
+//! (from a buggy compiler or a hand-edited `.vbc` file) is rejected up front
+//! instead of misbehaving halfway through a run.
+
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use crate::builtins::BuiltinRegistry;
+use crate::program::FunctionEntry;
+use crate::vm::{Bytecode, RuntimeValue};
+
+/// Why a program failed validation. `instruction` is always the index of the
+/// offending instruction.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ValidationError {
+    ConstantOutOfRange { instruction: usize, index: usize, len: usize },
+    JumpOutOfRange { instruction: usize, target: usize },
+    /// A jump from one function into another, or between a function and
+    /// top-level code.
+    JumpOutsideFunction { instruction: usize, target: usize, function: String },
+    FunctionOutOfRange { function: String },
+    UnknownBuiltin { instruction: usize, id: usize },
+    StackUnderflow { instruction: usize, function: String },
+    /// A `Return` with anything other than exactly the return value on the stack.
+    UnbalancedStack { instruction: usize, function: String, depth: usize },
+    /// Two paths reach the same instruction with different stack depths.
+    InconsistentStack { instruction: usize, function: String, expected: usize, found: usize },
+    /// A function whose last instruction carries on past its end instead of returning.
+    MissingReturn { instruction: usize, function: String },
+}
+
+impl fmt::Display for ValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ValidationError::ConstantOutOfRange { instruction, index, len } => write!(
+                f,
+                "instruction {} loads constant {} but the pool has {} constants",
+                instruction, index, len
+            ),
+            ValidationError::JumpOutOfRange { instruction, target } => {
+                write!(f, "instruction {} jumps to {}, past the end of the code", instruction, target)
+            }
+            ValidationError::JumpOutsideFunction { instruction, target, function } => {
+                write!(f, "instruction {} jumps to {}, outside {}", instruction, target, function)
+            }
+            ValidationError::FunctionOutOfRange { function } => {
+                write!(f, "function {} lies outside the code", function)
+            }
+            ValidationError::UnknownBuiltin { instruction, id } => {
+                write!(f, "instruction {} calls unregistered builtin {}", instruction, id)
+            }
+            ValidationError::StackUnderflow { instruction, function } => {
+                write!(f, "instruction {} in {} pops from an empty stack", instruction, function)
+            }
+            ValidationError::UnbalancedStack { instruction, function, depth } => write!(
+                f,
+                "{} returns at instruction {} with {} values on the stack instead of 1",
+                function, instruction, depth
+            ),
+            ValidationError::InconsistentStack { instruction, function, expected, found } => write!(
+                f,
+                "instruction {} in {} is reached with stack depths {} and {}",
+                instruction, function, expected, found
+            ),
+            ValidationError::MissingReturn { instruction, function } => {
+                write!(f, "function `{}` ended without return at instruction {}", function, instruction)
+            }
+        }
+    }
+}
+
+impl Error for ValidationError {}
+
+const TOP_LEVEL: &str = "top-level code";
+
+/// Checks constant indices, jump targets and builtin ids, and simulates the
+/// stack depth along every path through each function and the top-level
+/// code. Every index of `bytecode` is an instruction boundary, so a target
+/// in range is a valid place to land. Every path through a function has to
+/// end in a `Return`; top-level code may also run off the end of the code.
+pub fn validate(
+    bytecode: &[Bytecode],
+    constants: &[RuntimeValue],
+    functions: &[FunctionEntry],
+    builtins: &BuiltinRegistry,
+) -> Result<(), ValidationError> {
+    for function in functions {
+        if function.start > function.end || function.end > bytecode.len() {
+            return Err(ValidationError::FunctionOutOfRange { function: function.name.clone() });
+        }
+    }
+
+    let region = |ip: usize| functions.iter().find(|function| function.contains(ip));
+
+    for (instruction, op) in bytecode.iter().enumerate() {
+        match op {
+            Bytecode::LoadConst(index) if *index >= constants.len() => {
+                return Err(ValidationError::ConstantOutOfRange { instruction, index: *index, len: constants.len() });
+            }
+            Bytecode::Jump(target)
+            | Bytecode::JumpIfFalse(target)
+            | Bytecode::JumpIfTrue(target)
+            | Bytecode::PushHandler(target) => {
+                if *target >= bytecode.len() {
+                    return Err(ValidationError::JumpOutOfRange { instruction, target: *target });
+                }
+                let from = region(instruction);
+                if from != region(*target) {
+                    let function = from.map_or(TOP_LEVEL, |function| &function.name).to_string();
+                    return Err(ValidationError::JumpOutsideFunction { instruction, target: *target, function });
+                }
+            }
+            Bytecode::CallBuiltin(id, _) if builtins.name(*id).is_none() => {
+                return Err(ValidationError::UnknownBuiltin { instruction, id: *id });
+            }
+            _ => {}
+        }
+    }
+
+    if !bytecode.is_empty() {
+        check_stack(bytecode, None)?;
+    }
+    for function in functions.iter().filter(|function| function.start < function.end) {
+        check_stack(bytecode, Some(function))?;
+    }
+    Ok(())
+}
+
+/// The indices of the constants no `LoadConst` loads. They don't stop a
+/// program from running, so they aren't a [`ValidationError`], but a
+/// compiler that leaves them in the pool is wasting space.
+pub fn unused_constants(bytecode: &[Bytecode], constants: &[RuntimeValue]) -> Vec<usize> {
+    let mut loaded = vec![false; constants.len()];
+    for op in bytecode {
+        if let Bytecode::LoadConst(index) = op {
+            if let Some(loaded) = loaded.get_mut(*index) {
+                *loaded = true;
+            }
+        }
+    }
+    (0..constants.len()).filter(|index| !loaded[*index]).collect()
+}
+
+// Follows every path through a function, or the top-level code if
+// `function` is `None`, tracking how many operands are on the stack. Locals
+// live below the operands and aren't counted.
+fn check_stack(bytecode: &[Bytecode], function: Option<&FunctionEntry>) -> Result<(), ValidationError> {
+    let (start, end) = function.map_or((0, bytecode.len()), |function| (function.start, function.end));
+    let must_return = function.is_some();
+    let function = function.map_or(TOP_LEVEL, |function| &function.name);
+    let mut depths: HashMap<usize, usize> = HashMap::new();
+    let mut pending = vec![(start, 0usize)];
+
+    while let Some((instruction, depth)) = pending.pop() {
+        if instruction >= end {
+            // Top-level code stops the VM by running off the end, but a
+            // function would run into whatever code comes after it
+            if !must_return {
+                continue;
+            }
+            return Err(ValidationError::MissingReturn { instruction: end - 1, function: function.to_string() });
+        }
+        match depths.get(&instruction) {
+            Some(&expected) if expected == depth => continue,
+            Some(&expected) => {
+                return Err(ValidationError::InconsistentStack {
+                    instruction,
+                    function: function.to_string(),
+                    expected,
+                    found: depth,
+                })
+            }
+            None => {
+                depths.insert(instruction, depth);
+            }
+        }
+
+        let op = &bytecode[instruction];
+        let (pops, pushes) = stack_effect(op);
+        let depth = depth
+            .checked_sub(pops)
+            .ok_or_else(|| ValidationError::StackUnderflow { instruction, function: function.to_string() })?
+            + pushes;
+
+        match op {
+            Bytecode::Return if depth != 0 => {
+                // The popped return value was accounted for above
+                return Err(ValidationError::UnbalancedStack { instruction, function: function.to_string(), depth: depth + 1 });
+            }
+            Bytecode::Return => {}
+            Bytecode::Jump(target) => pending.push((*target, depth)),
+            Bytecode::JumpIfFalse(target) | Bytecode::JumpIfTrue(target) => {
+                pending.push((*target, depth));
+                pending.push((instruction + 1, depth));
+            }
+            // The `catch` starts with the error message in place of anything
+            // the `try` body pushed
+            Bytecode::PushHandler(target) => {
+                pending.push((*target, depth + 1));
+                pending.push((instruction + 1, depth));
+            }
+            _ => pending.push((instruction + 1, depth)),
+        }
+    }
+    Ok(())
+}
+
+// How many values an instruction pops and then pushes
+pub(crate) fn stack_effect(op: &Bytecode) -> (usize, usize) {
+    match op {
+        Bytecode::LoadConst(_) | Bytecode::LoadLocal(_) | Bytecode::LoadGlobal(_) => (0, 1),
+        Bytecode::StoreLocal(_) | Bytecode::StoreGlobal(_) | Bytecode::Pop => (1, 0),
+        Bytecode::Add | Bytecode::Sub | Bytecode::Mul | Bytecode::Div | Bytecode::Mod | Bytecode::Pow => (2, 1),
+        Bytecode::Neg => (1, 1),
+        Bytecode::Eq | Bytecode::Ne | Bytecode::Lt | Bytecode::Gt | Bytecode::Le | Bytecode::Ge => (2, 1),
+        Bytecode::Jump(_) => (0, 0),
+        Bytecode::JumpIfFalse(_) | Bytecode::JumpIfTrue(_) => (1, 0),
+        // The callee sits on top of its arguments
+        Bytecode::Call(num_args) | Bytecode::TailCall(num_args) => (num_args + 1, 1),
+        Bytecode::CallBuiltin(_, num_args) => (*num_args, 1),
+        Bytecode::Return => (1, 0),
+        Bytecode::Dup => (1, 2),
+        Bytecode::Swap => (2, 2),
+        Bytecode::Roll(depth) => (depth + 1, depth + 1),
+        Bytecode::Pick(depth) => (depth + 1, depth + 2),
+        Bytecode::Nop => (0, 0),
+        Bytecode::PushHandler(_) | Bytecode::PopHandler => (0, 0),
+        Bytecode::MakeArray(count) => (*count, 1),
+        Bytecode::MakeRange(_) => (2, 1),
+        Bytecode::Len => (1, 1),
+        Bytecode::IndexGet => (2, 1),
+        Bytecode::IndexSet => (3, 1),
+        Bytecode::MakeStruct(_, fields) => (fields.len(), 1),
+        Bytecode::GetField(_) => (1, 1),
+        Bytecode::SetField(_) => (2, 1),
+        // The receiver sits below the arguments, and comes back below the result
+        Bytecode::CallMethod(_, num_args) => (num_args + 1, 2),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(bytecode: Vec<Bytecode>, constants: Vec<RuntimeValue>, functions: Vec<FunctionEntry>) -> Result<(), ValidationError> {
+        validate(&bytecode, &constants, &functions, &BuiltinRegistry::new())
+    }
+
+    fn function(name: &str, start: usize, end: usize) -> FunctionEntry {
+        FunctionEntry { name: name.to_string(), start, end, num_params: 0 }
+    }
+
+    #[test]
+    fn test_valid_program_passes() {
+        let bytecode = vec![
+            Bytecode::Jump(4),
+            Bytecode::LoadConst(0),
+            Bytecode::CallBuiltin(0, 1),
+            Bytecode::Return,
+            Bytecode::LoadConst(0),
+            Bytecode::JumpIfFalse(7),
+            Bytecode::Jump(7),
+            Bytecode::LoadConst(0),
+            Bytecode::Return,
+        ];
+        assert_eq!(check(bytecode, vec![RuntimeValue::Null], vec![function("f", 1, 4)]), Ok(()));
+    }
+
+    #[test]
+    fn test_unused_constants() {
+        let bytecode = vec![Bytecode::LoadConst(2), Bytecode::LoadConst(0), Bytecode::Add];
+        let constants = vec![RuntimeValue::Integer(1), RuntimeValue::Integer(2), RuntimeValue::Integer(3)];
+        assert_eq!(unused_constants(&bytecode, &constants), [1]);
+        // A load past the end is an error for `validate` to report
+        assert_eq!(unused_constants(&[Bytecode::LoadConst(5)], &constants), [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_constant_out_of_range() {
+        assert_eq!(
+            check(vec![Bytecode::LoadConst(2), Bytecode::Return], vec![RuntimeValue::Null], vec![]),
+            Err(ValidationError::ConstantOutOfRange { instruction: 0, index: 2, len: 1 })
+        );
+    }
+
+    #[test]
+    fn test_jump_targets() {
+        assert_eq!(
+            check(vec![Bytecode::Jump(5)], vec![], vec![]),
+            Err(ValidationError::JumpOutOfRange { instruction: 0, target: 5 })
+        );
+        // A function jumping into top-level code
+        let bytecode = vec![Bytecode::Jump(3), Bytecode::Jump(3), Bytecode::Return, Bytecode::Return];
+        assert_eq!(
+            check(bytecode, vec![], vec![function("f", 1, 3)]),
+            Err(ValidationError::JumpOutsideFunction { instruction: 1, target: 3, function: "f".to_string() })
+        );
+    }
+
+    #[test]
+    fn test_unknown_builtin() {
+        assert_eq!(
+            check(vec![Bytecode::CallBuiltin(999, 0), Bytecode::Return], vec![], vec![]),
+            Err(ValidationError::UnknownBuiltin { instruction: 0, id: 999 })
+        );
+    }
+
+    #[test]
+    fn test_stack_underflow() {
+        assert_eq!(
+            check(vec![Bytecode::LoadConst(0), Bytecode::Add, Bytecode::Return], vec![RuntimeValue::Null], vec![]),
+            Err(ValidationError::StackUnderflow { instruction: 1, function: TOP_LEVEL.to_string() })
+        );
+    }
+
+    #[test]
+    fn test_stack_manipulation_effects() {
+        let constants = vec![RuntimeValue::Null];
+        let balanced = vec![Bytecode::LoadConst(0), Bytecode::Dup, Bytecode::Swap, Bytecode::Nop, Bytecode::Pop, Bytecode::Return];
+        assert_eq!(check(balanced, constants.clone(), vec![]), Ok(()));
+        // Swap needs two values
+        assert_eq!(
+            check(vec![Bytecode::LoadConst(0), Bytecode::Swap, Bytecode::Return], constants, vec![]),
+            Err(ValidationError::StackUnderflow { instruction: 1, function: TOP_LEVEL.to_string() })
+        );
+    }
+
+    #[test]
+    fn test_unbalanced_return() {
+        let bytecode = vec![Bytecode::LoadConst(0), Bytecode::LoadConst(0), Bytecode::Return];
+        assert_eq!(
+            check(bytecode, vec![RuntimeValue::Null], vec![]),
+            Err(ValidationError::UnbalancedStack { instruction: 2, function: TOP_LEVEL.to_string(), depth: 2 })
+        );
+    }
+
+    #[test]
+    fn test_paths_must_agree_on_depth() {
+        // Falling through pushes a value before the Return; jumping doesn't
+        let bytecode = vec![
+            Bytecode::LoadConst(0),
+            Bytecode::JumpIfTrue(3),
+            Bytecode::LoadConst(0),
+            Bytecode::Return,
+        ];
+        let error = check(bytecode, vec![RuntimeValue::Boolean(true)], vec![]).unwrap_err();
+        assert_eq!(
+            error,
+            ValidationError::InconsistentStack { instruction: 3, function: TOP_LEVEL.to_string(), expected: 1, found: 0 }
+        );
+    }
+
+    #[test]
+    fn test_functions_must_return() {
+        // `f` falls through into top-level code
+        let bytecode = vec![Bytecode::Jump(2), Bytecode::Nop, Bytecode::LoadConst(0), Bytecode::Return];
+        assert_eq!(
+            check(bytecode, vec![RuntimeValue::Null], vec![function("f", 1, 2)]),
+            Err(ValidationError::MissingReturn { instruction: 1, function: "f".to_string() })
+        );
+        // Top-level code may run off the end
+        assert_eq!(check(vec![Bytecode::Nop], vec![], vec![]), Ok(()));
+    }
+
+    #[test]
+    fn test_loops_run_however_little_they_do() {
+        // `loop { }` runs until the fuel limit stops it
+        assert_eq!(check(vec![Bytecode::Jump(0)], vec![], vec![]), Ok(()));
+        let nops = vec![Bytecode::Nop, Bytecode::Nop, Bytecode::Jump(0), Bytecode::Return];
+        assert_eq!(check(nops, vec![], vec![]), Ok(()));
+        let bytecode = vec![
+            Bytecode::LoadConst(0),
+            Bytecode::JumpIfFalse(3),
+            Bytecode::Jump(0),
+            Bytecode::LoadConst(0),
+            Bytecode::Return,
+        ];
+        assert_eq!(check(bytecode, vec![RuntimeValue::Boolean(false)], vec![]), Ok(()));
+    }
+
+    #[test]
+    fn test_function_entry_out_of_range() {
+        assert_eq!(
+            check(vec![Bytecode::Return], vec![], vec![function("f", 0, 9)]),
+            Err(ValidationError::FunctionOutOfRange { function: "f".to_string() })
+        );
+    }
+}
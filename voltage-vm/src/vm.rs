@@ -1,7 +1,18 @@
-use std::collections::HashMap;
-use voltage_core::Literal;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fmt;
+use std::iter;
+use std::io::Write;
+use crate::builtins::BuiltinRegistry;
+use crate::coverage::Coverage;
+use crate::higher_order::Caller;
+use crate::output::{self, OutputEvent, OutputHandler};
+use crate::program::Program;
+use crate::source_map::{LocalVariable, SourceMap};
+use crate::validate::{validate, ValidationError};
+use voltage_core::Span;
+use voltage_parser::escape::quoted;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Bytecode {
     // Constants and variables
     LoadConst(usize),           // Load constant from constant pool
@@ -16,8 +27,10 @@ pub enum Bytecode {
     Mul,
     Div,
     Mod,  // Modulo operation
+    Pow,                        // Raise the second value to the power of the top one
+    Neg,                        // Negate the top value
 
-    // Comparison operations
+    // Comparison operations, which produce the bools that conditions require
     Eq,
     Ne,
     Lt,
@@ -27,336 +40,1791 @@ pub enum Bytecode {
 
     // Control flow
     Jump(usize),                // Unconditional jump
-    JumpIfFalse(usize),         // Jump if top of stack is false
-    JumpIfTrue(usize),          // Jump if top of stack is true
+    JumpIfFalse(usize),         // Pop a bool and jump if it is false
+    JumpIfTrue(usize),          // Pop a bool and jump if it is true
     Call(usize),                // Call function (arg = num args)
-    CallBuiltin(usize),         // Call builtin function (arg = builtin id)
+    TailCall(usize),            // Call whose result is returned; a function calling itself reuses its frame
+    CallBuiltin(usize, usize),  // Call builtin function (builtin id, num args)
     Return,                     // Return from function
-
-    // Built-in functions
-    Print,
-    Puts,
+    PushHandler(usize),         // Start a `try` body whose errors jump to the `catch` at the target
+    PopHandler,                 // End the innermost `try` body without an error
 
     // Stack operations
     Pop,
-    Dup,
+    Dup,                        // Push a copy of the top value
+    Swap,                       // Exchange the top two values
+    Roll(usize),                // Move the value n below the top up to the top
+    Pick(usize),                // Push a copy of the value n below the top
+    Nop,                        // Do nothing
+
+    // Composite values
+    MakeArray(usize),           // Collect the top n values into an array
+    MakeRange(bool),            // Replace a start and an end with a range (inclusive of the end if true)
+    Len,                        // Replace an array, string or range with its length
+    IndexGet,                   // Replace a container and an index with the element
+    IndexSet,                   // Replace a container, an index and a value with the updated container
+    MakeStruct(String, Vec<String>), // Collect the top values into a struct with these fields, in order
+    GetField(String),           // Replace a struct with the value of one of its fields
+    SetField(String),           // Replace a struct and a value with the struct with the field set to it
+    CallMethod(String, usize),  // Call a method of the value below the n arguments, passing it first; leaves the receiver as the method left it, then the result
 }
 
+/// Names of the builtin functions, indexed by their `CallBuiltin` id.
+///
+/// Every builtin, these and host functions alike, consumes its arguments and
+/// leaves exactly one value on the stack: null for the ones like `puts` that
+/// are only called for their effect. The compiler relies on this to pop the
+/// result of an expression statement unconditionally. `panic` never gets to
+/// leave its value: it stops the VM. `dbg` leaves the value it printed, and
+/// is called with its source text and line as well, which the compiler adds.
+pub const BUILTINS: &[&str] = &["puts", "print", "panic", "dbg"];
+
 #[derive(Debug, Clone)]
 pub enum RuntimeValue {
     Integer(i64),
     Float(f64),
     String(String),
     Boolean(bool),
+    /// Arrays are values: storing one copies it, and assigning to an element
+    /// replaces the whole array. Nothing is shared, so an array can't contain
+    /// itself and dropping a value always frees it without a collector.
+    Array(Vec<RuntimeValue>),
+    /// An instance of the struct `name`, with its fields in the order they
+    /// were initialized. Structs are values like arrays: assigning to a
+    /// field replaces the whole struct.
+    Struct { name: String, fields: Vec<(String, RuntimeValue)> },
+    Range { start: i64, end: i64, inclusive: bool },
     Function { name: String, ip: usize, num_params: usize }, // Function with bytecode position
     Null,
 }
 
-// Implement PartialEq manually to handle floats properly
+impl RuntimeValue {
+    /// The name used for this value's type in error messages.
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            RuntimeValue::Integer(_) => "int",
+            RuntimeValue::Float(_) => "float",
+            RuntimeValue::String(_) => "string",
+            RuntimeValue::Boolean(_) => "bool",
+            RuntimeValue::Array(_) => "array",
+            RuntimeValue::Struct { .. } => "struct",
+            RuntimeValue::Range { .. } => "range",
+            RuntimeValue::Function { .. } => "function",
+            RuntimeValue::Null => "null",
+        }
+    }
+
+    /// Scripting-style truthiness, as offered by the `truthy` builtin: false,
+    /// null, zero, and empty strings, arrays and ranges are falsy. Conditions don't
+    /// use this; they must be booleans.
+    pub fn is_truthy(&self) -> bool {
+        match self {
+            RuntimeValue::Boolean(b) => *b,
+            RuntimeValue::Integer(n) => *n != 0,
+            RuntimeValue::Float(x) => *x != 0.0,
+            RuntimeValue::String(s) => !s.is_empty(),
+            RuntimeValue::Array(elements) => !elements.is_empty(),
+            RuntimeValue::Struct { .. } => true,
+            RuntimeValue::Range { start, end, inclusive } => range_len(*start, *end, *inclusive) > 0,
+            RuntimeValue::Function { .. } => true,
+            RuntimeValue::Null => false,
+        }
+    }
+}
+
+/// The shortest text that reads back as `x`, always with a decimal point or
+/// an exponent so it can't be mistaken for an int: `2.0`, `0.1`, `1e20`.
+/// NaN and the infinities are `nan`, `inf` and `-inf`.
+fn format_float(x: f64) -> String {
+    if x.is_nan() {
+        "nan".to_string()
+    } else if x.is_infinite() {
+        if x > 0.0 { "inf" } else { "-inf" }.to_string()
+    } else {
+        // Debug formatting is the shortest round-trip form, and keeps the `.0`
+        format!("{:?}", x)
+    }
+}
+
+/// The value of a condition, which must be a boolean: `if 1 { }` is an error
+/// rather than a guess. Comparisons are the way to get a boolean from
+/// anything else, or `truthy(x)` for scripting-style coercion.
+pub(crate) fn condition(value: &RuntimeValue) -> Result<bool, String> {
+    match value {
+        RuntimeValue::Boolean(b) => Ok(*b),
+        other => Err(format!("expected bool in condition, found {}", other.type_name())),
+    }
+}
+
+/// Value equality, as used by `==`. Floats compare exactly, as IEEE 754
+/// defines it: NaN equals nothing, not even itself, and `-0.0 == 0.0`. Use
+/// the `approx_eq` builtin to allow for rounding error.
 impl PartialEq for RuntimeValue {
     fn eq(&self, other: &Self) -> bool {
         match (self, other) {
             (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => a == b,
-            (RuntimeValue::Float(a), RuntimeValue::Float(b)) => (a - b).abs() < f64::EPSILON,
+            (RuntimeValue::Float(a), RuntimeValue::Float(b)) => a == b,
             (RuntimeValue::String(a), RuntimeValue::String(b)) => a == b,
             (RuntimeValue::Boolean(a), RuntimeValue::Boolean(b)) => a == b,
             (RuntimeValue::Function { name: a, .. }, RuntimeValue::Function { name: b, .. }) => a == b,
+            (RuntimeValue::Array(a), RuntimeValue::Array(b)) => a == b,
+            (RuntimeValue::Struct { name: a, fields: x }, RuntimeValue::Struct { name: b, fields: y }) => {
+                a == b && same_fields(x, y, |x, y| Ok(x == y)) == Ok(true)
+            }
+            (
+                RuntimeValue::Range { start: a, end: b, inclusive: c },
+                RuntimeValue::Range { start: x, end: y, inclusive: z },
+            ) => (a, b, c) == (x, y, z),
             (RuntimeValue::Null, RuntimeValue::Null) => true,
             _ => false,
         }
     }
 }
 
-// We'll avoid using RuntimeValue as a HashMap key for floats by using indices instead
-// The compiler module will handle constant deduplication differently
+/// How deeply nested arrays `==` and `!=` compare before giving up with a
+/// runtime error, rather than running out of native stack.
+pub const MAX_COMPARISON_DEPTH: usize = 1_000;
+
+/// `==` as the VM runs it: [`PartialEq`], element by element through arrays
+/// and field by field through structs, but an error for values nested more
+/// than [`MAX_COMPARISON_DEPTH`] deep. Arrays and structs are values, so they
+/// can't contain themselves; the limit is for very deep ones.
+pub(crate) fn deep_equal(a: &RuntimeValue, b: &RuntimeValue) -> Result<bool, String> {
+    equal_within(a, b, MAX_COMPARISON_DEPTH)
+}
+
+fn equal_within(a: &RuntimeValue, b: &RuntimeValue, depth: usize) -> Result<bool, String> {
+    let nested = matches!(a, RuntimeValue::Array(_) | RuntimeValue::Struct { .. });
+    if nested && depth == 0 {
+        return Err(format!("cannot compare values nested more than {} deep", MAX_COMPARISON_DEPTH));
+    }
+    match (a, b) {
+        (RuntimeValue::Array(a), RuntimeValue::Array(b)) => {
+            if a.len() != b.len() {
+                return Ok(false);
+            }
+            for (x, y) in a.iter().zip(b) {
+                if !equal_within(x, y, depth - 1)? {
+                    return Ok(false);
+                }
+            }
+            Ok(true)
+        }
+        (RuntimeValue::Struct { name: a, fields: x }, RuntimeValue::Struct { name: b, fields: y }) => {
+            Ok(a == b && same_fields(x, y, |x, y| equal_within(x, y, depth - 1))?)
+        }
+        _ => Ok(a == b),
+    }
+}
+
+// Whether two structs have the same fields with equal values, in whatever
+// order they were initialized
+fn same_fields(
+    a: &[(String, RuntimeValue)],
+    b: &[(String, RuntimeValue)],
+    mut equal: impl FnMut(&RuntimeValue, &RuntimeValue) -> Result<bool, String>,
+) -> Result<bool, String> {
+    if a.len() != b.len() {
+        return Ok(false);
+    }
+    for (name, x) in a {
+        match b.iter().find(|(other, _)| other == name) {
+            Some((_, y)) if equal(x, y)? => {}
+            _ => return Ok(false),
+        }
+    }
+    Ok(true)
+}
+
+impl fmt::Display for RuntimeValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeValue::Integer(i) => write!(f, "{}", i),
+            RuntimeValue::Float(x) => write!(f, "{}", format_float(*x)),
+            // `{:#}` quotes strings as literals, as `dbg` shows them
+            RuntimeValue::String(s) if f.alternate() => write!(f, "{}", quoted(s)),
+            RuntimeValue::String(s) => write!(f, "{}", s),
+            RuntimeValue::Boolean(b) => write!(f, "{}", b),
+            RuntimeValue::Function { name, .. } => write!(f, "<function {}>", name),
+            RuntimeValue::Range { start, end, inclusive: false } => write!(f, "{}..{}", start, end),
+            RuntimeValue::Range { start, end, inclusive: true } => write!(f, "{}..={}", start, end),
+            // `{:#}` puts each element and field on its own indented line
+            RuntimeValue::Array(elements) if f.alternate() && !elements.is_empty() => {
+                writeln!(f, "[")?;
+                for element in elements {
+                    writeln!(f, "{},", indented(&format!("{:#}", element)))?;
+                }
+                write!(f, "]")
+            }
+            RuntimeValue::Array(elements) => {
+                write!(f, "[")?;
+                for (i, element) in elements.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", element)?;
+                }
+                write!(f, "]")
+            }
+            RuntimeValue::Struct { name, fields } if fields.is_empty() => write!(f, "{} {{}}", name),
+            RuntimeValue::Struct { name, fields } if f.alternate() => {
+                writeln!(f, "{} {{", name)?;
+                for (field, value) in fields {
+                    writeln!(f, "{},", indented(&format!("{}: {:#}", field, value)))?;
+                }
+                write!(f, "}}")
+            }
+            RuntimeValue::Struct { name, fields } => {
+                write!(f, "{} {{ ", name)?;
+                for (i, (field, value)) in fields.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}: {}", field, value)?;
+                }
+                write!(f, " }}")
+            }
+            RuntimeValue::Null => write!(f, "null"),
+        }
+    }
+}
+
+// `text` with every line indented a level, for `{:#}`
+fn indented(text: &str) -> String {
+    text.lines().map(|line| format!("    {}", line)).collect::<Vec<_>>().join("\n")
+}
+
+/// A runtime error and the calls that were in progress when it happened.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub message: String,
+    /// The active calls, innermost first. The last frame is the code the VM
+    /// was started on: a function called by name, or the top-level code.
+    /// A function that returned a call to itself (a tail call) has one frame
+    /// however many times it recursed.
+    pub trace: Vec<TraceFrame>,
+    /// What the VM held when the error happened, if
+    /// [state dumps](VirtualMachine::enable_state_dumps) are enabled.
+    pub state: Option<Box<StateDump>>,
+}
+
+/// What stopped the VM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RuntimeErrorKind {
+    /// An operation failed, like a division by zero or a failing builtin.
+    Error,
+    /// The script called `panic`, with the message it gave.
+    Panic,
+    /// The script went past a limit of the VM, like its maximum call depth.
+    Limit,
+}
+
+/// One call in a [`RuntimeError`] trace.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    /// The function running in this frame, or `<top level>`.
+    pub function: String,
+    /// The statement the frame was executing, if the program has a source map.
+    pub span: Option<Span>,
+    /// Whether the call was inlined, so that the function's code ran in its
+    /// caller's frame. The trace shows it as a call all the same.
+    pub inlined: bool,
+}
+
+/// The VM's state when a runtime error happened, for `voltagec --verbose`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateDump {
+    /// The function running, or `<top level>`.
+    pub function: String,
+    /// The instruction that failed.
+    pub ip: usize,
+    /// The values on top of the stack, top first, at most
+    /// [`DUMPED_STACK_VALUES`] of them.
+    pub stack: Vec<RuntimeValue>,
+    /// The running function's local slots with their names and values.
+    /// Empty if the program wasn't compiled from source, which leaves the
+    /// VM without the names.
+    pub locals: Vec<(usize, String, RuntimeValue)>,
+    /// The last instructions run, oldest first, with where they are.
+    pub recent: Vec<(usize, Bytecode)>,
+}
+
+/// How many values from the top of the stack a [`StateDump`] keeps.
+pub const DUMPED_STACK_VALUES: usize = 10;
+
+/// How many of the last instructions run a [`StateDump`] keeps.
+pub const DUMPED_INSTRUCTIONS: usize = 5;
+
+impl fmt::Display for StateDump {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // The type of each value, and strings quoted so that they stand out
+        let typed = |value: &RuntimeValue| match value {
+            RuntimeValue::String(s) => format!("string {:?}", s),
+            value => format!("{} {}", value.type_name(), value),
+        };
+        writeln!(f, "state at instruction {} in {}:", self.ip, self.function)?;
+        writeln!(f, "  stack, top first:")?;
+        for value in &self.stack {
+            writeln!(f, "    {}", typed(value))?;
+        }
+        if !self.locals.is_empty() {
+            writeln!(f, "  locals:")?;
+            for (slot, name, value) in &self.locals {
+                writeln!(f, "    {:>2} {} = {}", slot, name, typed(value))?;
+            }
+        }
+        writeln!(f, "  last instructions run:")?;
+        for (ip, op) in &self.recent {
+            writeln!(f, "    {:>6}  {:?}", ip, op)?;
+        }
+        Ok(())
+    }
+}
+
+impl RuntimeError {
+    fn new(message: String) -> Self {
+        Self { kind: RuntimeErrorKind::Error, message, trace: Vec::new(), state: None }
+    }
+
+    /// Where the failing statement is, if the program has a source map.
+    pub fn location(&self) -> Option<Span> {
+        self.trace.first().and_then(|frame| frame.span)
+    }
+
+    /// The trace as indented `at function (line n)` lines, innermost first.
+    /// Deep recursion shows the first [`REPEATED_FRAMES_SHOWN`] of the same
+    /// call, then one line for how many more there were.
+    pub fn backtrace(&self) -> String {
+        let mut out = String::new();
+        for (frame, more) in self.collapsed_trace() {
+            match frame.span {
+                Some(span) => out.push_str(&format!("  at {} (line {})\n", frame.function, span.line)),
+                None => out.push_str(&format!("  at {}\n", frame.function)),
+            }
+            if more > 0 {
+                out.push_str(&format!("  ... {} more calls to {}\n", more, frame.function));
+            }
+        }
+        out
+    }
+
+    /// The trace, innermost first, with each frame paired with how many
+    /// frames the same as it were left out after it. A run of more than
+    /// [`REPEATED_FRAMES_SHOWN`] + 1 identical frames is cut to that many.
+    pub fn collapsed_trace(&self) -> Vec<(&TraceFrame, usize)> {
+        let mut collapsed = Vec::new();
+        let mut rest = self.trace.as_slice();
+        while let Some(frame) = rest.first() {
+            let run = rest.iter().take_while(|other| *other == frame).count();
+            if run > REPEATED_FRAMES_SHOWN + 1 {
+                collapsed.extend(iter::repeat_n((frame, 0), REPEATED_FRAMES_SHOWN - 1));
+                collapsed.push((frame, run - REPEATED_FRAMES_SHOWN));
+            } else {
+                collapsed.extend(iter::repeat_n((frame, 0), run));
+            }
+            rest = &rest[run..];
+        }
+        collapsed
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+/// How many frames in a row for the same call a [`RuntimeError`]'s
+/// backtrace shows before summing up the rest.
+pub const REPEATED_FRAMES_SHOWN: usize = 3;
+
+/// How deeply functions may call each other before the VM gives up.
+pub const DEFAULT_MAX_CALL_DEPTH: usize = 10_000;
+
+/// How many values the VM's stack may hold before the VM gives up.
+pub const DEFAULT_MAX_STACK_SIZE: usize = 1_000_000;
+
+/// How a [`VirtualMachine`] is set up, for [`VirtualMachine::with_config`].
+/// The default is the VM that [`VirtualMachine::new`] gives.
+///
+/// ```
+/// use voltage_vm::{VirtualMachine, VmConfig};
+///
+/// let vm = VirtualMachine::with_config(VmConfig { fuel: Some(1_000), coverage: true, ..VmConfig::default() });
+/// assert!(vm.coverage().is_some());
+/// ```
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VmConfig {
+    /// How many calls may be in progress at once, as
+    /// [`VirtualMachine::set_max_call_depth`] sets it.
+    pub max_call_depth: usize,
+    /// How many values the stack may hold, as
+    /// [`VirtualMachine::set_max_stack_size`] sets it.
+    pub max_stack: usize,
+    /// How many instructions may run, as [`VirtualMachine::set_fuel`] sets
+    /// it; `None` for no limit.
+    pub fuel: Option<u64>,
+    /// Whether runtime errors carry a [`StateDump`], as after
+    /// [`VirtualMachine::enable_state_dumps`].
+    pub trace: bool,
+    /// Whether to record which instructions run, as after
+    /// [`VirtualMachine::enable_coverage`].
+    pub coverage: bool,
+}
+
+impl Default for VmConfig {
+    fn default() -> Self {
+        Self {
+            max_call_depth: DEFAULT_MAX_CALL_DEPTH,
+            max_stack: DEFAULT_MAX_STACK_SIZE,
+            fuel: None,
+            trace: false,
+            coverage: false,
+        }
+    }
+}
+
+// Frames listed in the error when the call depth limit is hit
+const TRACE_FRAMES: usize = 3;
+
+/// Where the VM is after [`VirtualMachine::step`] or
+/// [`VirtualMachine::continue_until_breakpoint`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum StepResult {
+    /// Paused before the instruction at `ip`.
+    Running { ip: usize },
+    /// The code being run returned or reached its end, with this result.
+    Finished(RuntimeValue),
+}
+
+/// An active function call: where to resume the caller and where the callee's
+/// locals start on the value stack.
+#[derive(Debug, Clone)]
+struct CallFrame {
+    // The function this frame runs, and its first instruction
+    function: String,
+    start: usize,
+    return_ip: usize,
+    base: usize,
+    // Whether `CallMethod` made the frame, so that returning hands back the
+    // receiver, which is its first local, along with the result
+    method: bool,
+}
+
+// An instruction budget set by `set_fuel`
+#[derive(Debug, Clone, Copy)]
+struct Fuel {
+    given: u64,
+    left: u64,
+}
+
+// A `try` body that is running, and where its `catch` picks up
+struct Handler {
+    // The first instruction of the `catch`
+    ip: usize,
+    // How many values were on the stack, and frames active, when the body began
+    stack: usize,
+    frames: usize,
+}
 
 pub struct VirtualMachine {
     bytecode: Vec<Bytecode>,
     constants: Vec<RuntimeValue>,
+    source_map: SourceMap,
     stack: Vec<RuntimeValue>,
+    frames: Vec<CallFrame>,
+    // The `try` bodies running now, innermost last
+    handlers: Vec<Handler>,
     globals: HashMap<String, RuntimeValue>,
+    builtins: BuiltinRegistry,
+    output: OutputHandler,  // Where print and puts go
+    // The file the program came from, for `dbg` to say where it was called
+    script_name: Option<String>,
     ip: usize,  // Instruction pointer
-    // For now, function locations will be stored in constants or we'll implement function mapping
+    // The function `run_function` started and its first instruction; `None`
+    // for top-level code
+    entry: Option<(String, usize)>,
+    // Instructions that `continue_until_breakpoint` stops before
+    breakpoints: BTreeSet<usize>,
+    // The instructions that have run, while coverage is enabled
+    coverage: Option<Coverage>,
+    max_call_depth: usize,
+    max_stack_size: usize,
+    // How many more instructions may run, and how many were given, while
+    // fuel is limited
+    fuel: Option<Fuel>,
+    // Set when the error `execute` is returning can't be caught: a panic or
+    // a limit
+    fatal: Option<RuntimeErrorKind>,
+    // Where the last instructions run were, oldest first, while state dumps
+    // are enabled
+    recent: Option<VecDeque<usize>>,
+}
+
+impl Default for VirtualMachine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl VirtualMachine {
     pub fn new() -> Self {
-        Self {
+        Self::with_config(VmConfig::default())
+    }
+
+    /// A VM with the limits and recording that `config` asks for.
+    pub fn with_config(config: VmConfig) -> Self {
+        let mut vm = Self {
             bytecode: Vec::new(),
             constants: Vec::new(),
+            source_map: SourceMap::new(),
             stack: Vec::new(),
+            frames: Vec::new(),
+            handlers: Vec::new(),
             globals: HashMap::new(),
+            builtins: BuiltinRegistry::new(),
+            output: output::write_to(output::default_output()),
+            script_name: None,
             ip: 0,
+            entry: None,
+            breakpoints: BTreeSet::new(),
+            coverage: None,
+            max_call_depth: config.max_call_depth,
+            max_stack_size: config.max_stack,
+            fuel: None,
+            fatal: None,
+            recent: None,
+        };
+        if let Some(fuel) = config.fuel {
+            vm.set_fuel(fuel);
+        }
+        if config.trace {
+            vm.enable_state_dumps();
         }
+        if config.coverage {
+            vm.enable_coverage();
+        }
+        vm
+    }
+
+    /// Names the file the program came from, which `dbg` shows along with
+    /// the line it was called on.
+    pub fn set_script_name(&mut self, name: &str) {
+        self.script_name = Some(name.to_string());
+    }
+
+    /// Limits how many calls may be in progress at once, so runaway recursion
+    /// becomes a runtime error. Defaults to [`DEFAULT_MAX_CALL_DEPTH`].
+    pub fn set_max_call_depth(&mut self, depth: usize) {
+        self.max_call_depth = depth;
+    }
+
+    /// Limits how many values the stack may hold. Defaults to
+    /// [`DEFAULT_MAX_STACK_SIZE`].
+    pub fn set_max_stack_size(&mut self, size: usize) {
+        self.max_stack_size = size;
+    }
+
+    /// Limits how many more instructions may run, across everything the VM
+    /// runs until the fuel is set again, so a program that never finishes
+    /// becomes a runtime error. Unlimited by default.
+    pub fn set_fuel(&mut self, instructions: u64) {
+        self.fuel = Some(Fuel { given: instructions, left: instructions });
     }
 
+    /// Sends everything the program prints to `output` instead of stdout.
+    pub fn set_output(&mut self, output: Box<dyn Write>) {
+        self.output = output::write_to(output);
+    }
+
+    /// Passes each `print` and `puts` to `handler` as an [`OutputEvent`]
+    /// instead of writing it anywhere. Events arrive in program order, each
+    /// before the builtin that made it returns.
+    pub fn set_output_handler(&mut self, mut handler: impl FnMut(OutputEvent) + 'static) {
+        self.output = Box::new(move |event| {
+            handler(event);
+            Ok(())
+        });
+    }
+
+    pub fn builtins(&self) -> &BuiltinRegistry {
+        &self.builtins
+    }
+
+    pub fn builtins_mut(&mut self) -> &mut BuiltinRegistry {
+        &mut self.builtins
+    }
+
+    /// Validates a compiled program and loads it in place of the current one.
+    /// Globals are kept, as with [`VirtualMachine::load_bytecode`].
+    pub fn load_program(&mut self, program: Program) -> Result<(), ValidationError> {
+        validate(&program.bytecode, &program.constants, &program.functions, &self.builtins)?;
+        self.load_bytecode(program.bytecode, program.constants);
+        self.source_map = program.source_map;
+        Ok(())
+    }
+
+    /// Replaces the loaded program, without validating it. Globals are kept,
+    /// so a host (like the REPL) can run several programs against the same
+    /// global state.
     pub fn load_bytecode(&mut self, bytecode: Vec<Bytecode>, constants: Vec<RuntimeValue>) {
         self.bytecode = bytecode;
         self.constants = constants;
+        self.source_map = SourceMap::new();
+        self.stack.clear();
+        self.frames.clear();
+        self.handlers.clear();
         self.ip = 0;
+        self.entry = None;
+        if self.coverage.is_some() {
+            self.coverage = Some(Coverage::new(self.bytecode.len()));
+        }
+        if let Some(recent) = &mut self.recent {
+            recent.clear();
+        }
+    }
+
+    /// Where the loaded program's instructions came from, if it was compiled
+    /// from source.
+    pub fn source_map(&self) -> &SourceMap {
+        &self.source_map
+    }
+
+    /// Where the most recently executed instruction came from. After `run`
+    /// fails, this is the location of the failure.
+    pub fn last_span(&self) -> Option<Span> {
+        self.source_map.span_for(self.ip.checked_sub(1)?)
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<&RuntimeValue> {
+        self.globals.get(name)
+    }
+
+    /// Sets a global variable, returning the value it had before.
+    pub fn set_global(&mut self, name: &str, value: RuntimeValue) -> Option<RuntimeValue> {
+        self.globals.insert(name.to_string(), value)
+    }
+
+    pub fn remove_global(&mut self, name: &str) -> Option<RuntimeValue> {
+        self.globals.remove(name)
+    }
+
+    /// Calls a function defined by the loaded program and runs it to completion.
+    pub fn run_function(&mut self, name: &str, args: &[RuntimeValue]) -> Result<RuntimeValue, RuntimeError> {
+        self.enter_function(name, args)?;
+        self.execute().map_err(|message| self.runtime_error(message))
+    }
+
+    /// Sets up a call like [`VirtualMachine::run_function`] but leaves the VM
+    /// paused at the function's first instruction, for stepping through it.
+    pub fn enter_function(&mut self, name: &str, args: &[RuntimeValue]) -> Result<(), RuntimeError> {
+        let (ip, num_params) = match self.globals.get(name) {
+            Some(RuntimeValue::Function { ip, num_params, .. }) => (*ip, *num_params),
+            _ => return Err(RuntimeError::new(format!("Unknown function: {}", name))),
+        };
+        if args.len() != num_params {
+            return Err(RuntimeError::new(format!(
+                "Function {} expects {} arguments, got {}",
+                name,
+                num_params,
+                args.len()
+            )));
+        }
+        // The arguments become the locals of the outermost frame, whose return ends the run
+        self.stack.clear();
+        self.frames.clear();
+        self.handlers.clear();
+        self.stack.extend_from_slice(args);
+        self.ip = ip;
+        self.entry = Some((name.to_string(), ip));
+        Ok(())
     }
 
-    pub fn run(&mut self) -> Result<RuntimeValue, String> {
+    /// Runs the loaded program from the current instruction, normally its
+    /// top-level code.
+    pub fn run(&mut self) -> Result<RuntimeValue, RuntimeError> {
+        self.entry = None;
+        self.execute().map_err(|message| self.runtime_error(message))
+    }
+
+    /// Runs exactly one instruction, from wherever the VM is paused: the
+    /// start of the loaded program, a function set up by
+    /// [`VirtualMachine::enter_function`], or the previous step.
+    pub fn step(&mut self) -> Result<StepResult, RuntimeError> {
+        match self.execute_one() {
+            Ok(result) => Ok(result),
+            Err(message) => match self.catch(message) {
+                Ok(()) => Ok(StepResult::Running { ip: self.ip }),
+                Err(message) => Err(self.runtime_error(message)),
+            },
+        }
+    }
+
+    /// Steps until the VM is about to run an instruction with a breakpoint,
+    /// or finishes. The instruction it is paused on runs first either way,
+    /// so continuing from a breakpoint moves past it.
+    pub fn continue_until_breakpoint(&mut self) -> Result<StepResult, RuntimeError> {
         loop {
-            if self.ip >= self.bytecode.len() {
-                break;
+            match self.step()? {
+                StepResult::Running { ip } if !self.breakpoints.contains(&ip) => {}
+                result => return Ok(result),
             }
+        }
+    }
 
-            let instruction = self.bytecode[self.ip].clone();
-            self.ip += 1;
+    /// Makes [`VirtualMachine::continue_until_breakpoint`] stop before the
+    /// instruction at `instruction`.
+    pub fn set_breakpoint(&mut self, instruction: usize) {
+        self.breakpoints.insert(instruction);
+    }
 
-            match instruction {
-                Bytecode::LoadConst(index) => {
-                    let value = self.constants[index].clone();
-                    self.stack.push(value);
-                }
-                Bytecode::Add => {
-                    let right = self.pop_value()?;
-                    let left = self.pop_value()?;
-                    match (left, right) {
-                        (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => {
-                            self.stack.push(RuntimeValue::Integer(a + b));
-                        }
-                        (RuntimeValue::Float(a), RuntimeValue::Float(b)) => {
-                            self.stack.push(RuntimeValue::Float(a + b));
-                        }
-                        _ => return Err("Type error: Cannot add non-numeric values".to_string()),
+    /// Sets a breakpoint at the start of the code for each statement on
+    /// `line`, going by the source map, and returns where. Nothing is set if
+    /// no code came from that line.
+    pub fn set_line_breakpoint(&mut self, line: usize) -> Vec<usize> {
+        let instructions = self.source_map.instructions_for_line(line);
+        // A statement's code is contiguous, so only the first of each run counts;
+        // the rest would stop again after every call the statement makes
+        let starts: Vec<usize> = instructions
+            .iter()
+            .enumerate()
+            .filter(|&(i, &instruction)| i == 0 || instructions[i - 1] + 1 != instruction)
+            .map(|(_, &instruction)| instruction)
+            .collect();
+        self.breakpoints.extend(&starts);
+        starts
+    }
+
+    pub fn clear_breakpoints(&mut self) {
+        self.breakpoints.clear();
+    }
+
+    /// The value stack, bottom first: every frame's locals and the operands
+    /// of the expression in progress.
+    pub fn stack_snapshot(&self) -> Vec<RuntimeValue> {
+        self.stack.clone()
+    }
+
+    /// The running function's parameters and the variables in scope, with
+    /// their values, in slot order. A variable that a later one shadows is
+    /// left out. Empty in top-level code, whose variables are globals, and
+    /// in code that wasn't compiled from source, which has no names.
+    pub fn locals_snapshot(&self) -> Vec<(String, RuntimeValue)> {
+        let Some(locals) = self.running_function().and_then(|function| self.source_map.locals_of(function)) else {
+            return Vec::new();
+        };
+        let in_scope: Vec<&LocalVariable> = locals.iter().filter(|local| local.scope.contains(&self.ip)).collect();
+        let base = self.frame_base();
+        in_scope
+            .iter()
+            .enumerate()
+            .filter(|(i, local)| !in_scope[i + 1..].iter().any(|later| later.name == local.name))
+            .filter_map(|(_, local)| Some((local.name.clone(), self.stack.get(base + local.slot)?.clone())))
+            .collect()
+    }
+
+    /// Records which instructions run, from now until the next program is
+    /// loaded and then for each program loaded after it.
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new(self.bytecode.len()));
+    }
+
+    /// The instructions of the loaded program that have run, if coverage is
+    /// enabled.
+    pub fn coverage(&self) -> Option<&Coverage> {
+        self.coverage.as_ref()
+    }
+
+    /// Attaches a [`StateDump`] to every runtime error from now on, which
+    /// takes keeping track of the last few instructions run.
+    pub fn enable_state_dumps(&mut self) {
+        self.recent.get_or_insert_with(|| VecDeque::with_capacity(DUMPED_INSTRUCTIONS));
+    }
+
+    /// The instruction the VM will run next.
+    pub fn ip(&self) -> usize {
+        self.ip
+    }
+
+    // Attaches the calls in progress to an error from `execute`
+    fn runtime_error(&mut self, message: String) -> RuntimeError {
+        let mut trace = Vec::new();
+        // The innermost frame is at the failing instruction, each caller at its call
+        let mut ip = self.ip.checked_sub(1);
+        for frame in self.frames.iter().rev() {
+            self.trace_frames(&mut trace, frame.function.clone(), ip);
+            ip = frame.return_ip.checked_sub(1);
+        }
+        let entry = self.entry.as_ref().map_or_else(|| "<top level>".to_string(), |(name, _)| name.clone());
+        self.trace_frames(&mut trace, entry, ip);
+        let kind = self.fatal.take().unwrap_or(RuntimeErrorKind::Error);
+        let state = self.recent.is_some().then(|| Box::new(self.state_dump()));
+        RuntimeError { kind, message, trace, state }
+    }
+
+    fn state_dump(&self) -> StateDump {
+        let function = self.running_function().unwrap_or("<top level>").to_string();
+        let stack = self.stack.iter().rev().take(DUMPED_STACK_VALUES).cloned().collect();
+        // Top-level variables are globals, so only functions have locals
+        let locals = self.running_function().and_then(|function| self.source_map.locals_of(function)).unwrap_or_default();
+        let base = self.frame_base();
+        let locals = locals
+            .iter()
+            .filter_map(|local| Some((local.slot, local.name.clone(), self.stack.get(base + local.slot)?.clone())))
+            .collect();
+        let recent = self.recent.iter().flatten().map(|&ip| (ip, self.bytecode[ip].clone())).collect();
+        StateDump { function, ip: self.ip.saturating_sub(1), stack, locals, recent }
+    }
+
+    fn execute(&mut self) -> Result<RuntimeValue, String> {
+        loop {
+            match self.execute_one() {
+                Ok(StepResult::Finished(result)) => return Ok(result),
+                Ok(StepResult::Running { .. }) => {}
+                Err(message) => self.catch(message)?,
+            }
+        }
+    }
+
+    // Hands an error to the `catch` of the innermost `try` body running, as
+    // a string on top of the stack as it was when the body began. Errors no
+    // `try` catches, panics and limits come back as they are.
+    fn catch(&mut self, message: String) -> Result<(), String> {
+        if self.fatal.is_some() {
+            return Err(message);
+        }
+        let Some(handler) = self.handlers.pop() else {
+            return Err(message);
+        };
+        self.frames.truncate(handler.frames);
+        self.stack.truncate(handler.stack);
+        self.push(RuntimeValue::String(message))?;
+        self.ip = handler.ip;
+        Ok(())
+    }
+
+    // Runs the instruction at `ip`. This is the VM's only dispatch loop body,
+    // shared by `run` and the stepping methods.
+    fn execute_one(&mut self) -> Result<StepResult, String> {
+        if self.ip >= self.bytecode.len() {
+            // Top-level code may end by running out of instructions, but
+            // a function has to return
+            if let Some(function) = self.running_function() {
+                return Err(format!("function `{}` ended without return", function));
+            }
+            return self.finish();
+        }
+
+        if let Some(fuel) = &mut self.fuel {
+            if fuel.left == 0 {
+                self.fatal = Some(RuntimeErrorKind::Limit);
+                return Err(format!("out of fuel after running {} instructions", fuel.given));
+            }
+            fuel.left -= 1;
+        }
+
+        let instruction = self.bytecode[self.ip].clone();
+        if let Some(coverage) = &mut self.coverage {
+            coverage.record(self.ip);
+        }
+        if let Some(recent) = &mut self.recent {
+            if recent.len() == DUMPED_INSTRUCTIONS {
+                recent.pop_front();
+            }
+            recent.push_back(self.ip);
+        }
+        self.ip += 1;
+        let next = self.ip;
+        let jumps = moves_ip(&instruction);
+
+        match instruction {
+            Bytecode::LoadConst(index) => {
+                let value = self.constants[index].clone();
+                self.push(value)?;
+            }
+            Bytecode::Add => {
+                let right = self.pop_value()?;
+                let left = self.pop_value()?;
+                match (left, right) {
+                    (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => {
+                        let result = a.checked_add(b).ok_or_else(|| format!("Integer overflow: {} + {}", a, b))?;
+                        self.push(RuntimeValue::Integer(result))?;
+                    }
+                    (RuntimeValue::Float(a), RuntimeValue::Float(b)) => {
+                        self.push(RuntimeValue::Float(a + b))?;
+                    }
+                    (left, right) => {
+                        let result = self.call_operator("__add__", left, right, "Type error: Cannot add non-numeric values")?;
+                        self.push(result)?;
                     }
                 }
-                Bytecode::Sub => {
-                    let right = self.pop_value()?;
-                    let left = self.pop_value()?;
-                    match (left, right) {
-                        (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => {
-                            self.stack.push(RuntimeValue::Integer(a - b));
-                        }
-                        (RuntimeValue::Float(a), RuntimeValue::Float(b)) => {
-                            self.stack.push(RuntimeValue::Float(a - b));
-                        }
-                        _ => return Err("Type error: Cannot subtract non-numeric values".to_string()),
+            }
+            Bytecode::Sub => {
+                let right = self.pop_value()?;
+                let left = self.pop_value()?;
+                match (left, right) {
+                    (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => {
+                        let result = a.checked_sub(b).ok_or_else(|| format!("Integer overflow: {} - {}", a, b))?;
+                        self.push(RuntimeValue::Integer(result))?;
+                    }
+                    (RuntimeValue::Float(a), RuntimeValue::Float(b)) => {
+                        self.push(RuntimeValue::Float(a - b))?;
+                    }
+                    (left, right) => {
+                        let result = self.call_operator("__sub__", left, right, "Type error: Cannot subtract non-numeric values")?;
+                        self.push(result)?;
                     }
                 }
-                Bytecode::Mul => {
-                    let right = self.pop_value()?;
-                    let left = self.pop_value()?;
-                    match (left, right) {
-                        (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => {
-                            self.stack.push(RuntimeValue::Integer(a * b));
-                        }
-                        (RuntimeValue::Float(a), RuntimeValue::Float(b)) => {
-                            self.stack.push(RuntimeValue::Float(a * b));
-                        }
-                        _ => return Err("Type error: Cannot multiply non-numeric values".to_string()),
+            }
+            Bytecode::Mul => {
+                let right = self.pop_value()?;
+                let left = self.pop_value()?;
+                match (left, right) {
+                    (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => {
+                        let result = a.checked_mul(b).ok_or_else(|| format!("Integer overflow: {} * {}", a, b))?;
+                        self.push(RuntimeValue::Integer(result))?;
+                    }
+                    (RuntimeValue::Float(a), RuntimeValue::Float(b)) => {
+                        self.push(RuntimeValue::Float(a * b))?;
+                    }
+                    (left, right) => {
+                        let result = self.call_operator("__mul__", left, right, "Type error: Cannot multiply non-numeric values")?;
+                        self.push(result)?;
                     }
                 }
-                Bytecode::Div => {
-                    let right = self.pop_value()?;
-                    let left = self.pop_value()?;
-                    match (left, right) {
-                        (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => {
-                            if b == 0 {
-                                return Err("Division by zero".to_string());
-                            }
-                            self.stack.push(RuntimeValue::Integer(a / b));
-                        }
-                        (RuntimeValue::Float(a), RuntimeValue::Float(b)) => {
-                            if b == 0.0 {
-                                return Err("Division by zero".to_string());
-                            }
-                            self.stack.push(RuntimeValue::Float(a / b));
+            }
+            Bytecode::Div => {
+                let right = self.pop_value()?;
+                let left = self.pop_value()?;
+                match (left, right) {
+                    (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => {
+                        if b == 0 {
+                            return Err("Division by zero".to_string());
                         }
-                        _ => return Err("Type error: Cannot divide non-numeric values".to_string()),
+                        let result = a.checked_div(b).ok_or_else(|| format!("Integer overflow: {} / {}", a, b))?;
+                        self.push(RuntimeValue::Integer(result))?;
                     }
+                    // Dividing a float by zero gives inf or nan, as IEEE 754 says
+                    (RuntimeValue::Float(a), RuntimeValue::Float(b)) => {
+                        self.push(RuntimeValue::Float(a / b))?;
+                    }
+                    _ => return Err("Type error: Cannot divide non-numeric values".to_string()),
                 }
-                Bytecode::Mod => {
-                    let right = self.pop_value()?;
-                    let left = self.pop_value()?;
-                    match (left, right) {
-                        (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => {
-                            if b == 0 {
-                                return Err("Modulo by zero".to_string());
-                            }
-                            self.stack.push(RuntimeValue::Integer(a % b));
-                        }
-                        (RuntimeValue::Float(a), RuntimeValue::Float(b)) => {
-                            if b == 0.0 {
-                                return Err("Modulo by zero".to_string());
-                            }
-                            self.stack.push(RuntimeValue::Float(a % b));
+            }
+            Bytecode::Mod => {
+                let right = self.pop_value()?;
+                let left = self.pop_value()?;
+                match (left, right) {
+                    (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => {
+                        if b == 0 {
+                            return Err("Modulo by zero".to_string());
                         }
-                        _ => return Err("Type error: Cannot perform modulo on non-numeric values".to_string()),
+                        let result = a.checked_rem(b).ok_or_else(|| format!("Integer overflow: {} % {}", a, b))?;
+                        self.push(RuntimeValue::Integer(result))?;
                     }
+                    (RuntimeValue::Float(a), RuntimeValue::Float(b)) => {
+                        self.push(RuntimeValue::Float(a % b))?;
+                    }
+                    _ => return Err("Type error: Cannot perform modulo on non-numeric values".to_string()),
                 }
-                Bytecode::Eq => {
-                    let right = self.pop_value()?;
-                    let left = self.pop_value()?;
-                    let result = left == right;
-                    self.stack.push(RuntimeValue::Boolean(result));
-                }
-                Bytecode::Ne => {
-                    let right = self.pop_value()?;
-                    let left = self.pop_value()?;
-                    let result = left != right;
-                    self.stack.push(RuntimeValue::Boolean(result));
+            }
+            Bytecode::Pow => {
+                let right = self.pop_value()?;
+                let left = self.pop_value()?;
+                let result = match (left, right) {
+                    (RuntimeValue::Integer(_), RuntimeValue::Integer(b)) if b < 0 => {
+                        return Err(format!("Cannot raise an int to a negative power: {}", b));
+                    }
+                    (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => u32::try_from(b)
+                        .ok()
+                        .and_then(|b| a.checked_pow(b))
+                        .map(RuntimeValue::Integer)
+                        .ok_or_else(|| format!("Integer overflow: {} ** {}", a, b))?,
+                    (RuntimeValue::Float(a), RuntimeValue::Float(b)) => RuntimeValue::Float(a.powf(b)),
+                    _ => return Err("Type error: Cannot raise non-numeric values to a power".to_string()),
+                };
+                self.push(result)?;
+            }
+            Bytecode::Neg => {
+                let result = match self.pop_value()? {
+                    RuntimeValue::Integer(n) => RuntimeValue::Integer(
+                        n.checked_neg().ok_or_else(|| format!("Integer overflow: -({})", n))?,
+                    ),
+                    RuntimeValue::Float(f) => RuntimeValue::Float(-f),
+                    other => return Err(format!("Type error: Cannot negate a value of type {}", other.type_name())),
+                };
+                self.push(result)?;
+            }
+            Bytecode::Eq => {
+                let right = self.pop_value()?;
+                let left = self.pop_value()?;
+                let result = self.equal(left, right)?;
+                self.push(RuntimeValue::Boolean(result))?;
+            }
+            Bytecode::Ne => {
+                let right = self.pop_value()?;
+                let left = self.pop_value()?;
+                let result = !self.equal(left, right)?;
+                self.push(RuntimeValue::Boolean(result))?;
+            }
+            Bytecode::Lt => {
+                let right = self.pop_value()?;
+                let left = self.pop_value()?;
+                let result = match (left, right) {
+                    (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => a < b,
+                    (RuntimeValue::Float(a), RuntimeValue::Float(b)) => a < b,
+                    (left, right) => {
+                        self.compare_with("__lt__", left, right, "Type error: Cannot compare non-numeric values")?
+                    }
+                };
+                self.push(RuntimeValue::Boolean(result))?;
+            }
+            Bytecode::Gt => {
+                let right = self.pop_value()?;
+                let left = self.pop_value()?;
+                let result = match (left, right) {
+                    (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => a > b,
+                    (RuntimeValue::Float(a), RuntimeValue::Float(b)) => a > b,
+                    _ => return Err("Type error: Cannot compare non-numeric values".to_string()),
+                };
+                self.push(RuntimeValue::Boolean(result))?;
+            }
+            Bytecode::Le => {
+                let right = self.pop_value()?;
+                let left = self.pop_value()?;
+                let result = match (left, right) {
+                    (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => a <= b,
+                    (RuntimeValue::Float(a), RuntimeValue::Float(b)) => a <= b,
+                    _ => return Err("Type error: Cannot compare non-numeric values".to_string()),
+                };
+                self.push(RuntimeValue::Boolean(result))?;
+            }
+            Bytecode::Ge => {
+                let right = self.pop_value()?;
+                let left = self.pop_value()?;
+                let result = match (left, right) {
+                    (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => a >= b,
+                    (RuntimeValue::Float(a), RuntimeValue::Float(b)) => a >= b,
+                    _ => return Err("Type error: Cannot compare non-numeric values".to_string()),
+                };
+                self.push(RuntimeValue::Boolean(result))?;
+            }
+            Bytecode::StoreLocal(index) => {
+                let value = self.pop_value()?;
+                let slot = self.frame_base() + index;
+                // Locals declared in branches that never ran leave gaps; fill them with null
+                while self.stack.len() < slot {
+                    self.push(RuntimeValue::Null)?;
                 }
-                Bytecode::Lt => {
-                    let right = self.pop_value()?;
-                    let left = self.pop_value()?;
-                    let result = match (left, right) {
-                        (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => a < b,
-                        (RuntimeValue::Float(a), RuntimeValue::Float(b)) => a < b,
-                        _ => return Err("Type error: Cannot compare non-numeric values".to_string()),
-                    };
-                    self.stack.push(RuntimeValue::Boolean(result));
+                if slot == self.stack.len() {
+                    self.push(value)?;
+                } else {
+                    self.stack[slot] = value;
                 }
-                Bytecode::Gt => {
-                    let right = self.pop_value()?;
-                    let left = self.pop_value()?;
-                    let result = match (left, right) {
-                        (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => a > b,
-                        (RuntimeValue::Float(a), RuntimeValue::Float(b)) => a > b,
-                        _ => return Err("Type error: Cannot compare non-numeric values".to_string()),
-                    };
-                    self.stack.push(RuntimeValue::Boolean(result));
+            }
+            Bytecode::LoadLocal(index) => {
+                let slot = self.frame_base() + index;
+                let value = self.stack.get(slot).cloned().unwrap_or(RuntimeValue::Null);
+                self.push(value)?;
+            }
+            Bytecode::Call(num_args) => self.call(num_args, false)?,
+            Bytecode::TailCall(num_args) => self.call(num_args, true)?,
+            Bytecode::CallBuiltin(builtin_id, num_args) => {
+                self.call_builtin(builtin_id, num_args)?;
+            }
+            Bytecode::Return => {
+                let result = self.pop_value().unwrap_or(RuntimeValue::Null);
+                // Returning leaves the `try` bodies of the function
+                let frames = self.frames.len();
+                self.handlers.retain(|handler| handler.frames < frames);
+                match self.frames.pop() {
+                    Some(frame) => {
+                        // Discard the callee's locals and hand the result to the caller
+                        let receiver = frame.method.then(|| self.stack.get(frame.base).cloned().unwrap_or(RuntimeValue::Null));
+                        self.stack.truncate(frame.base);
+                        if let Some(receiver) = receiver {
+                            self.push(receiver)?;
+                        }
+                        self.push(result)?;
+                        self.ip = frame.return_ip;
+                    }
+                    // Returning from the entry point ends the program
+                    None => return Ok(StepResult::Finished(result)),
                 }
-                Bytecode::Le => {
-                    let right = self.pop_value()?;
-                    let left = self.pop_value()?;
-                    let result = match (left, right) {
-                        (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => a <= b,
-                        (RuntimeValue::Float(a), RuntimeValue::Float(b)) => a <= b,
-                        _ => return Err("Type error: Cannot compare non-numeric values".to_string()),
-                    };
-                    self.stack.push(RuntimeValue::Boolean(result));
+            }
+            Bytecode::PushHandler(target) => {
+                self.handlers.push(Handler { ip: target, stack: self.stack.len(), frames: self.frames.len() });
+            }
+            Bytecode::PopHandler => {
+                self.handlers.pop().ok_or_else(|| "No try body to end".to_string())?;
+            }
+            Bytecode::MakeArray(count) => {
+                if self.stack.len() < count {
+                    return Err("Stack underflow".to_string());
                 }
-                Bytecode::Ge => {
-                    let right = self.pop_value()?;
-                    let left = self.pop_value()?;
-                    let result = match (left, right) {
-                        (RuntimeValue::Integer(a), RuntimeValue::Integer(b)) => a >= b,
-                        (RuntimeValue::Float(a), RuntimeValue::Float(b)) => a >= b,
-                        _ => return Err("Type error: Cannot compare non-numeric values".to_string()),
-                    };
-                    self.stack.push(RuntimeValue::Boolean(result));
+                let elements = self.stack.split_off(self.stack.len() - count);
+                self.push(RuntimeValue::Array(elements))?;
+            }
+            Bytecode::MakeRange(inclusive) => {
+                let end = self.pop_value()?;
+                let start = self.pop_value()?;
+                match (start, end) {
+                    (RuntimeValue::Integer(start), RuntimeValue::Integer(end)) => {
+                        self.push(RuntimeValue::Range { start, end, inclusive })?;
+                    }
+                    (start, end) => {
+                        return Err(format!(
+                            "Range bounds must be ints, got {} and {}",
+                            start.type_name(),
+                            end.type_name()
+                        ));
+                    }
                 }
-                Bytecode::Print => {
-                    let value = self.pop_value()?;
-                    print!("{}", self.value_to_string(&value));
+            }
+            Bytecode::Len => {
+                let value = self.pop_value()?;
+                self.push(RuntimeValue::Integer(length(&value)? as i64))?;
+            }
+            Bytecode::IndexGet => {
+                let index = self.pop_value()?;
+                let container = self.pop_value()?;
+                self.push(index_get(&container, &index)?)?;
+            }
+            Bytecode::IndexSet => {
+                let value = self.pop_value()?;
+                let index = self.pop_value()?;
+                let container = self.pop_value()?;
+                self.push(index_set(container, &index, value)?)?;
+            }
+            Bytecode::MakeStruct(name, names) => {
+                if self.stack.len() < names.len() {
+                    return Err("Stack underflow".to_string());
                 }
-                Bytecode::Puts => {
-                    let value = self.pop_value()?;
-                    println!("{}", self.value_to_string(&value));
+                let values = self.stack.split_off(self.stack.len() - names.len());
+                let fields = names.into_iter().zip(values).collect();
+                self.push(RuntimeValue::Struct { name, fields })?;
+            }
+            Bytecode::GetField(field) => {
+                let value = self.pop_value()?;
+                self.push(get_field(value, &field)?)?;
+            }
+            Bytecode::SetField(field) => {
+                let value = self.pop_value()?;
+                let container = self.pop_value()?;
+                self.push(set_field(container, &field, value)?)?;
+            }
+            Bytecode::CallMethod(method, num_args) => {
+                let receiver = self.stack.len().checked_sub(num_args + 1).ok_or_else(|| "Stack underflow".to_string())?;
+                let function = self.method(&self.stack[receiver], &method)?;
+                self.push(function)?;
+                self.call(num_args + 1, false)?;
+                if let Some(frame) = self.frames.last_mut() {
+                    frame.method = true;
                 }
-                Bytecode::StoreLocal(index) => {
-                    let value = self.pop_value()?;
-                    // For now, we'll just store in a temporary place
-                    // In a full implementation, we'd have proper local variable storage
-                    println!("Storing local var {}: {:?}", index, value);
+            }
+            Bytecode::Jump(target) => {
+                self.ip = target;
+            }
+            Bytecode::JumpIfFalse(target) => {
+                if !condition(&self.pop_value()?)? {
+                    self.ip = target;
                 }
-                Bytecode::LoadLocal(index) => {
-                    // For now, just push a dummy value
-                    // In a full implementation, we'd load from proper local storage
-                    println!("Loading local var {}", index);
+            }
+            Bytecode::JumpIfTrue(target) => {
+                if condition(&self.pop_value()?)? {
+                    self.ip = target;
                 }
-                Bytecode::Call(num_args) => {
-                    // For now, we'll handle user function calls by name
-                    // Pop the function name (for direct calls)
-                    let func_name_value = self.pop_value()?;
-                    
-                    if let RuntimeValue::String(func_name) = func_name_value {
-                        match func_name.as_str() {
-                            "puts" => {
-                                if num_args == 1 {
-                                    let arg = self.pop_value()?;
-                                    println!("{}", self.value_to_string(&arg));
-                                    self.stack.push(RuntimeValue::Null);
-                                } else {
-                                    return Err("puts expects 1 argument".to_string());
-                                }
-                            }
-                            "print" => {
-                                if num_args == 1 {
-                                    let arg = self.pop_value()?;
-                                    print!("{}", self.value_to_string(&arg));
-                                    self.stack.push(RuntimeValue::Null);
-                                } else {
-                                    return Err("print expects 1 argument".to_string());
-                                }
-                            }
-                            _ => {
-                                return Err(format!("Unknown function: {}", func_name));
-                            }
-                        }
-                    } else {
-                        return Err("Function call expects function name as string".to_string());
-                    }
+            }
+            Bytecode::Pop => {
+                self.stack.pop();
+            }
+            Bytecode::Dup => {
+                let top = self.stack.last().cloned().ok_or_else(|| "Stack underflow".to_string())?;
+                self.push(top)?;
+            }
+            Bytecode::Swap => {
+                let len = self.stack.len();
+                if len < 2 {
+                    return Err("Stack underflow".to_string());
                 }
-                Bytecode::CallBuiltin(builtin_id) => {
-                    // Handle builtin functions by ID
-                    match builtin_id {
-                        0 => { // puts
-                            let arg = self.pop_value()?;
-                            println!("{}", self.value_to_string(&arg));
-                            self.stack.push(RuntimeValue::Null);
-                        }
-                        1 => { // print
-                            let arg = self.pop_value()?;
-                            print!("{}", self.value_to_string(&arg));
-                            self.stack.push(RuntimeValue::Null);
-                        }
-                        _ => return Err(format!("Unknown builtin function ID: {}", builtin_id)),
+                self.stack.swap(len - 1, len - 2);
+            }
+            Bytecode::Roll(depth) => {
+                let from = self.stack.len().checked_sub(depth + 1).ok_or_else(|| "Stack underflow".to_string())?;
+                let value = self.stack.remove(from);
+                self.stack.push(value);
+            }
+            Bytecode::Pick(depth) => {
+                let at = self.stack.len().checked_sub(depth + 1).ok_or_else(|| "Stack underflow".to_string())?;
+                self.push(self.stack[at].clone())?;
+            }
+            Bytecode::Nop => {}
+            Bytecode::LoadGlobal(name) => {
+                // Try to get from globals, or return an error
+                match self.globals.get(&name).cloned() {
+                    Some(value) => {
+                        self.push(value)?;
                     }
-                }
-                Bytecode::Return => {
-                    // For now, just pop the return value and continue
-                    // In a real implementation with call frames, this would restore the previous frame
-                    let _ = self.pop_value().unwrap_or(RuntimeValue::Null); // Pop return value if any
-                    // For now, we'll continue execution, but in a real implementation
-                    // this would return to the caller. We'll just continue until end.
-                }
-                Bytecode::Pop => {
-                    self.stack.pop();
-                }
-                Bytecode::LoadGlobal(name) => {
-                    // Try to get from globals, or return an error
-                    match self.globals.get(&name) {
-                        Some(value) => {
-                            self.stack.push(value.clone());
-                        }
-                        None => {
-                            // If not found in globals, it might be undefined
-                            // For now return a default value
-                            self.stack.push(RuntimeValue::Integer(0));
-                        }
+                    None => {
+                        return Err(format!("Undefined variable: {}", name));
                     }
                 }
-                Bytecode::StoreGlobal(name) => {
-                    let value = self.pop_value()?;
-                    self.globals.insert(name, value);
-                }
-                // More instructions will be added later...
-                _ => {
-                    return Err(format!("Unsupported instruction: {:?}", instruction));
-                }
+            }
+            Bytecode::StoreGlobal(name) => {
+                let value = self.pop_value()?;
+                self.globals.insert(name, value);
             }
         }
+        debug_assert!(jumps || self.ip == next, "instruction {} moved the instruction pointer", next - 1);
+        Ok(StepResult::Running { ip: self.ip })
+    }
 
-        // Return the top of the stack or null if empty
-        Ok(self.stack.pop().unwrap_or(RuntimeValue::Null))
+    // Ends a run of top-level code that reached the end of the program
+    fn finish(&mut self) -> Result<StepResult, String> {
+        // The result is the top of the stack, or null if it is empty.
+        // Compiled code leaves nothing else behind, so in debug builds
+        // anything more points at a compiler bug.
+        let result = self.stack.pop().unwrap_or(RuntimeValue::Null);
+        if cfg!(debug_assertions) && !self.stack.is_empty() {
+            return Err(format!(
+                "unbalanced stack: {} values were left over when the program ended",
+                self.stack.len()
+            ));
+        }
+        Ok(StepResult::Finished(result))
+    }
+
+    fn push(&mut self, value: RuntimeValue) -> Result<(), String> {
+        if self.stack.len() >= self.max_stack_size {
+            self.fatal = Some(RuntimeErrorKind::Limit);
+            return Err(format!("maximum stack size of {} values exceeded", self.max_stack_size));
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn recursion_error(&self, function: &str) -> String {
+        let recent: Vec<&str> = self.frames
+            .iter()
+            .rev()
+            .take(TRACE_FRAMES)
+            .map(|frame| frame.function.as_str())
+            .collect();
+        format!(
+            "maximum recursion depth of {} exceeded in function `{}` (most recent calls: {})",
+            self.max_call_depth,
+            function,
+            recent.join(" <- ")
+        )
     }
 
     fn pop_value(&mut self) -> Result<RuntimeValue, String> {
         self.stack.pop().ok_or_else(|| "Stack underflow".to_string())
     }
 
+    /// Runs the builtin with the given id on the top `num_args` stack values,
+    /// replacing them with its result.
+    fn call_builtin(&mut self, id: usize, num_args: usize) -> Result<(), String> {
+        if self.stack.len() < num_args {
+            return Err("Stack underflow".to_string());
+        }
+        let args = self.stack.split_off(self.stack.len() - num_args);
+
+        let result = match (BUILTINS.get(id), args.as_slice()) {
+            (Some(&"puts"), [value]) => self.write_output(value, true).map(|_| RuntimeValue::Null)?,
+            (Some(&"print"), [value]) => self.write_output(value, false).map(|_| RuntimeValue::Null)?,
+            (Some(&"panic"), [value]) => {
+                self.fatal = Some(RuntimeErrorKind::Panic);
+                return Err(self.value_to_string(value));
+            }
+            (Some(&"dbg"), [value, RuntimeValue::String(source), RuntimeValue::Integer(line)]) => {
+                self.write_debug(value, source, *line)?;
+                value.clone()
+            }
+            (Some(name), _) => return Err(format!("{} expects 1 argument, got {}", name, num_args)),
+            (None, _) => match self.builtins.higher_order_function(id).cloned() {
+                // Handed the VM itself, to call functions with
+                Some(function) => function(self, &args)?,
+                None => match self.builtins.host_function(id) {
+                    Some(function) => function.clone()(&args)?,
+                    None => return Err(format!("Unknown builtin function ID: {}", id)),
+                },
+            },
+        };
+        self.push(result)?;
+        Ok(())
+    }
+
+    fn write_output(&mut self, value: &RuntimeValue, newline: bool) -> Result<(), String> {
+        let text = self.value_to_string(value);
+        let event = if newline { OutputEvent::PutsLine(text) } else { OutputEvent::Print(text) };
+        (self.output)(event).map_err(|e| format!("Failed to write output: {}", e))
+    }
+
+    // What `dbg` prints: where it was called, the expression and its value,
+    // as `[script.v:3] x * 2 = 8`. Line 0 is code parsed without spans.
+    fn write_debug(&mut self, value: &RuntimeValue, source: &str, line: i64) -> Result<(), String> {
+        let location = match (&self.script_name, line) {
+            (_, 0) => String::new(),
+            (Some(name), line) => format!("[{}:{}] ", name, line),
+            (None, line) => format!("[line {}] ", line),
+        };
+        let text = match source {
+            "" => format!("{}{:#}", location, value),
+            source => format!("{}{} = {:#}", location, source, value),
+        };
+        (self.output)(OutputEvent::Debug(text)).map_err(|e| format!("Failed to write output: {}", e))
+    }
+
+    // Calls the function or builtin on top of the stack with the `num_args`
+    // values below it. A tail call from a function to itself reuses the
+    // caller's frame: the arguments replace its locals and it starts over,
+    // so traces show one frame however deep the recursion goes.
+    fn call(&mut self, num_args: usize, tail: bool) -> Result<(), String> {
+        // The callee sits on top of its arguments: either a function value
+        // or the name of a function to look up
+        let callee = self.pop_value()?;
+
+        let callee = match callee {
+            RuntimeValue::String(func_name) => match self.globals.get(&func_name) {
+                Some(value @ RuntimeValue::Function { .. }) => value.clone(),
+                _ => match self.builtins.id(&func_name) {
+                    Some(id) => return self.call_builtin(id, num_args),
+                    None => return Err(format!("Unknown function: {}", func_name)),
+                },
+            },
+            value @ RuntimeValue::Function { .. } => value,
+            other => return Err(format!("Cannot call a value of type {}", other.type_name())),
+        };
+
+        if let RuntimeValue::Function { name, ip, num_params } = callee {
+            if num_args != num_params {
+                return Err(format!(
+                    "Function {} expects {} arguments, got {}",
+                    name, num_params, num_args
+                ));
+            }
+            if self.stack.len() < num_args {
+                return Err("Stack underflow".to_string());
+            }
+            if tail && self.running_function_start() == Some(ip) {
+                let args = self.stack.split_off(self.stack.len() - num_args);
+                self.stack.truncate(self.frame_base());
+                self.stack.extend(args);
+                self.ip = ip;
+                return Ok(());
+            }
+            if self.frames.len() >= self.max_call_depth {
+                self.fatal = Some(RuntimeErrorKind::Limit);
+                return Err(self.recursion_error(&name));
+            }
+            self.frames.push(CallFrame {
+                function: name,
+                start: ip,
+                return_ip: self.ip,
+                base: self.stack.len() - num_args,
+                method: false,
+            });
+            self.ip = ip;
+        }
+        Ok(())
+    }
+
+    // Adds the frame of `function` at `ip` to `trace`, after a frame for each
+    // inlined call that `ip` is inside
+    fn trace_frames(&self, trace: &mut Vec<TraceFrame>, function: String, ip: Option<usize>) {
+        let mut span = ip.and_then(|ip| self.source_map.span_for(ip));
+        for call in ip.map(|ip| self.source_map.inlined_at(ip)).unwrap_or_default() {
+            trace.push(TraceFrame { function: call.function.clone(), span, inlined: true });
+            span = call.call_site;
+        }
+        trace.push(TraceFrame { function, span, inlined: false });
+    }
+
+    // The name of the function running now, if any
+    fn running_function(&self) -> Option<&str> {
+        match self.frames.last() {
+            Some(frame) => Some(&frame.function),
+            None => self.entry.as_ref().map(|(name, _)| name.as_str()),
+        }
+    }
+
+    // The first instruction of the function running now, if any
+    fn running_function_start(&self) -> Option<usize> {
+        match self.frames.last() {
+            Some(frame) => Some(frame.start),
+            None => self.entry.as_ref().map(|(_, start)| *start),
+        }
+    }
+
+    // The function that is `method` of `receiver`: `Point.norm` for a
+    // `Point`. Methods are the globals an `impl` block defines.
+    fn method(&self, receiver: &RuntimeValue, method: &str) -> Result<RuntimeValue, String> {
+        let RuntimeValue::Struct { name, .. } = receiver else {
+            return Err(format!("Cannot call method `{}` on a value of type {}", method, receiver.type_name()));
+        };
+        let prefix = format!("{}.", name);
+        if let Some(function @ RuntimeValue::Function { .. }) = self.globals.get(&format!("{}{}", prefix, method)) {
+            return Ok(function.clone());
+        }
+        let mut methods: Vec<&str> = self
+            .globals
+            .iter()
+            .filter(|(_, value)| matches!(value, RuntimeValue::Function { .. }))
+            .filter_map(|(global, _)| global.strip_prefix(&prefix))
+            .collect();
+        methods.sort_unstable();
+        if methods.is_empty() {
+            Err(format!("{} has no method `{}`; it has no methods", name, method))
+        } else {
+            Err(format!("{} has no method `{}`; its methods are {}", name, method, methods.join(", ")))
+        }
+    }
+
+    // `left + right` and the like for a struct that defines the operator's
+    // method, like `__add__`, called with both operands. `error` is the
+    // operator's type error, for the values that don't define it.
+    fn call_operator(
+        &mut self,
+        method: &str,
+        left: RuntimeValue,
+        right: RuntimeValue,
+        error: &str,
+    ) -> Result<RuntimeValue, String> {
+        if !matches!(left, RuntimeValue::Struct { .. }) {
+            return Err(error.to_string());
+        }
+        let function = self.method(&left, method).map_err(|e| format!("{} ({})", error, e))?;
+        self.call_function(&function, &[left, right])
+    }
+
+    // Like `call_operator`, for a comparison, which has to give a boolean
+    fn compare_with(&mut self, method: &str, left: RuntimeValue, right: RuntimeValue, error: &str) -> Result<bool, String> {
+        let owner = match &left {
+            RuntimeValue::Struct { name, .. } => name.clone(),
+            _ => return Err(error.to_string()),
+        };
+        match self.call_operator(method, left, right, error)? {
+            RuntimeValue::Boolean(result) => Ok(result),
+            other => Err(format!("{}.{} must return a bool, but returned {}", owner, method, other.type_name())),
+        }
+    }
+
+    // `left == right`: what `__eq__` says for a struct that defines it, and
+    // otherwise whether the values are equal
+    fn equal(&mut self, left: RuntimeValue, right: RuntimeValue) -> Result<bool, String> {
+        if let RuntimeValue::Struct { name, .. } = &left {
+            if self.globals.contains_key(&format!("{}.__eq__", name)) {
+                return self.compare_with("__eq__", left, right, "");
+            }
+        }
+        deep_equal(&left, &right)
+    }
+
+    fn frame_base(&self) -> usize {
+        self.frames.last().map_or(0, |frame| frame.base)
+    }
+
     fn value_to_string(&self, value: &RuntimeValue) -> String {
-        match value {
-            RuntimeValue::Integer(i) => i.to_string(),
-            RuntimeValue::Float(f) => f.to_string(),
-            RuntimeValue::String(s) => s.clone(),
-            RuntimeValue::Boolean(b) => b.to_string(),
-            RuntimeValue::Function { name, .. } => format!("<function {}>", name),
-            RuntimeValue::Null => "null".to_string(),
+        value.to_string()
+    }
+}
+// Calls made by higher-order builtins. The function gets a frame as with
+// `Call`, returning to just after the builtin's `CallBuiltin`, and runs until
+// that frame returns; see `higher_order` for what this means for errors.
+impl Caller for VirtualMachine {
+    fn call_function(&mut self, function: &RuntimeValue, args: &[RuntimeValue]) -> Result<RuntimeValue, String> {
+        let RuntimeValue::Function { name, ip, num_params } = function else {
+            return Err(format!("Cannot call a value of type {}", function.type_name()));
+        };
+        if args.len() != *num_params {
+            return Err(format!("Function {} expects {} arguments, got {}", name, num_params, args.len()));
+        }
+        if self.frames.len() >= self.max_call_depth {
+            self.fatal = Some(RuntimeErrorKind::Limit);
+            return Err(self.recursion_error(name));
+        }
+
+        let depth = self.frames.len();
+        let base = self.stack.len();
+        for arg in args {
+            self.push(arg.clone())?;
+        }
+        self.frames.push(CallFrame { function: name.clone(), start: *ip, return_ip: self.ip, base, method: false });
+        self.ip = *ip;
+        while self.frames.len() > depth {
+            match self.execute_one() {
+                Ok(StepResult::Running { .. }) => {}
+                Ok(StepResult::Finished(result)) => return Ok(result),
+                // Only `try` bodies inside the function are the loop's to
+                // run; the ones around the builtin wait for it to return
+                Err(message) if self.handlers.last().is_some_and(|handler| handler.frames > depth) => {
+                    self.catch(message)?
+                }
+                Err(message) => return Err(message),
+            }
+        }
+        self.pop_value()
+    }
+}
+
+// Whether an instruction may go anywhere other than the next instruction
+fn moves_ip(instruction: &Bytecode) -> bool {
+    matches!(
+        instruction,
+        Bytecode::Jump(_)
+            | Bytecode::JumpIfFalse(_)
+            | Bytecode::JumpIfTrue(_)
+            | Bytecode::Call(_)
+            | Bytecode::TailCall(_)
+            | Bytecode::CallMethod(..)
+            | Bytecode::Return
+    )
+}
+
+/// How many elements an array, characters a string or integers a range has.
+pub(crate) fn length(value: &RuntimeValue) -> Result<usize, String> {
+    match value {
+        RuntimeValue::Array(elements) => Ok(elements.len()),
+        RuntimeValue::String(s) => Ok(s.chars().count()),
+        RuntimeValue::Range { start, end, inclusive } => Ok(range_len(*start, *end, *inclusive)),
+        other => Err(format!("Cannot take the length of a value of type {}", other.type_name())),
+    }
+}
+
+// A range whose start is past its end is empty
+fn range_len(start: i64, end: i64, inclusive: bool) -> usize {
+    let len = end as i128 - start as i128 + inclusive as i128;
+    usize::try_from(len.max(0)).unwrap_or(usize::MAX)
+}
+
+/// Indexing with an int gets one element, or a one-character string.
+/// Indexing an array or string with a range gets a copy of that part of it:
+/// `xs[1..3]` is a new two-element array and `s[0..5]` the first five
+/// characters. A slice must lie within the value, but may be empty.
+fn index_get(container: &RuntimeValue, index: &RuntimeValue) -> Result<RuntimeValue, String> {
+    match (container, index) {
+        (RuntimeValue::Array(elements), RuntimeValue::Range { start, end, inclusive }) => {
+            let range = checked_slice(*start, *end, *inclusive, elements.len())?;
+            Ok(RuntimeValue::Array(elements[range].to_vec()))
+        }
+        (RuntimeValue::String(s), RuntimeValue::Range { start, end, inclusive }) => {
+            let range = checked_slice(*start, *end, *inclusive, s.chars().count())?;
+            Ok(RuntimeValue::String(s.chars().skip(range.start).take(range.len()).collect()))
+        }
+        (RuntimeValue::Array(elements), _) => Ok(elements[checked_index(index, elements.len())?].clone()),
+        (RuntimeValue::Range { start, end, inclusive }, _) => {
+            let position = checked_index(index, range_len(*start, *end, *inclusive))?;
+            Ok(RuntimeValue::Integer(start + position as i64))
+        }
+        (RuntimeValue::String(s), _) => {
+            let position = checked_index(index, s.chars().count())?;
+            let c = s.chars().nth(position).expect("index was checked against the length");
+            Ok(RuntimeValue::String(c.to_string()))
+        }
+        (other, _) => Err(format!("Cannot index into a value of type {}", other.type_name())),
+    }
+}
+
+fn index_set(container: RuntimeValue, index: &RuntimeValue, value: RuntimeValue) -> Result<RuntimeValue, String> {
+    match container {
+        RuntimeValue::Array(mut elements) => {
+            let position = checked_index(index, elements.len())?;
+            elements[position] = value;
+            Ok(RuntimeValue::Array(elements))
         }
+        RuntimeValue::String(_) => Err("Cannot assign to an index of a string: strings are immutable".to_string()),
+        other => Err(format!("Cannot index into a value of type {}", other.type_name())),
     }
-}
\ No newline at end of file
+}
+
+fn get_field(value: RuntimeValue, field: &str) -> Result<RuntimeValue, String> {
+    match value {
+        RuntimeValue::Struct { name, fields } => fields
+            .into_iter()
+            .find_map(|(other, value)| (other == field).then_some(value))
+            .ok_or_else(|| format!("{} has no field `{}`", name, field)),
+        other => Err(format!("Cannot get field `{}` of a value of type {}", field, other.type_name())),
+    }
+}
+
+// Structs keep the fields they were made with, so only those can be set
+fn set_field(container: RuntimeValue, field: &str, value: RuntimeValue) -> Result<RuntimeValue, String> {
+    match container {
+        RuntimeValue::Struct { name, mut fields } => match fields.iter_mut().find(|(other, _)| other == field) {
+            Some((_, slot)) => {
+                *slot = value;
+                Ok(RuntimeValue::Struct { name, fields })
+            }
+            None => Err(format!("{} has no field `{}`", name, field)),
+        },
+        other => Err(format!("Cannot set field `{}` of a value of type {}", field, other.type_name())),
+    }
+}
+
+fn checked_slice(start: i64, end: i64, inclusive: bool, len: usize) -> Result<std::ops::Range<usize>, String> {
+    let bounds = usize::try_from(start)
+        .ok()
+        .zip(usize::try_from(end as i128 + inclusive as i128).ok())
+        .filter(|&(start, end)| start <= end && end <= len);
+    match bounds {
+        Some((start, end)) => Ok(start..end),
+        None => {
+            let range = RuntimeValue::Range { start, end, inclusive };
+            Err(format!("Slice {} is out of bounds for length {}", range, len))
+        }
+    }
+}
+
+fn checked_index(index: &RuntimeValue, len: usize) -> Result<usize, String> {
+    match index {
+        RuntimeValue::Integer(i) => usize::try_from(*i)
+            .ok()
+            .filter(|&i| i < len)
+            .ok_or_else(|| format!("Index {} is out of bounds for length {}", i, len)),
+        other => Err(format!("Index must be an int, got {}", other.type_name())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+    use voltage_parser::{Lexer, Parser};
+    use crate::compiler::BytecodeCompiler;
+
+    #[derive(Clone, Default)]
+    struct Captured(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Runs a program, returning its output and how many values it left behind
+    fn run(source: &str) -> (String, usize) {
+        let program = Parser::from_lexer(Lexer::new(source)).parse();
+        let program = BytecodeCompiler::new().compile_program(&program).unwrap();
+
+        let captured = Captured::default();
+        let mut vm = VirtualMachine::new();
+        vm.set_output(Box::new(captured.clone()));
+        vm.load_program(program).unwrap();
+        vm.run().unwrap();
+
+        let output = String::from_utf8(captured.0.borrow().clone()).unwrap();
+        (output, vm.stack.len())
+    }
+
+    #[test]
+    fn test_print_paths_share_output_and_stack_effect() {
+        let expected = ("7\n7".to_string(), 0);
+        assert_eq!(run("puts(7); print(7);"), expected);
+        assert_eq!(run(r#"puts("{}", 7); print("{}", 7);"#), expected);
+        assert_eq!(run("fn show(x) { puts(x); print(x); } show(7);"), expected);
+        assert_eq!(run(r#"fn show(x) { puts("{}", x); print("{}", x); } show(7);"#), expected);
+    }
+
+    #[test]
+    fn test_builtins_leave_one_value() {
+        // The value of a print call is null, whichever way it was compiled
+        let (output, depth) = run("let a = puts(1); let b = puts(\"{}\", 2); puts([a, b]);");
+        assert_eq!(output, "1\n2\n[null, null]\n");
+        assert_eq!(depth, 0);
+    }
+
+    #[test]
+    fn test_wrong_arity_through_call_is_an_error() {
+        let mut vm = VirtualMachine::new();
+        vm.load_bytecode(
+            vec![Bytecode::LoadConst(0), Bytecode::LoadConst(0), Bytecode::LoadConst(1), Bytecode::Call(2)],
+            vec![RuntimeValue::Integer(1), RuntimeValue::String("puts".to_string())],
+        );
+        assert_eq!(vm.run().unwrap_err().message, "puts expects 1 argument, got 2");
+    }
+
+    // Runs hand-built bytecode over the constants 10 and 3
+    fn run_bytecode(bytecode: Vec<Bytecode>) -> Result<RuntimeValue, RuntimeError> {
+        let mut vm = VirtualMachine::new();
+        vm.load_bytecode(bytecode, vec![RuntimeValue::Integer(10), RuntimeValue::Integer(3)]);
+        vm.run()
+    }
+
+    #[test]
+    fn test_dup_copies_the_top_value() {
+        let result = run_bytecode(vec![Bytecode::LoadConst(1), Bytecode::Dup, Bytecode::Mul]);
+        assert_eq!(result, Ok(RuntimeValue::Integer(9)));
+        assert_eq!(run_bytecode(vec![Bytecode::Dup]).unwrap_err().message, "Stack underflow");
+    }
+
+    #[test]
+    fn test_swap_exchanges_the_top_two_values() {
+        let in_order = vec![Bytecode::LoadConst(0), Bytecode::LoadConst(1), Bytecode::Sub];
+        assert_eq!(run_bytecode(in_order), Ok(RuntimeValue::Integer(7)));
+        let swapped = vec![Bytecode::LoadConst(0), Bytecode::LoadConst(1), Bytecode::Swap, Bytecode::Sub];
+        assert_eq!(run_bytecode(swapped), Ok(RuntimeValue::Integer(-7)));
+        let one_value = vec![Bytecode::LoadConst(0), Bytecode::Swap];
+        assert_eq!(run_bytecode(one_value).unwrap_err().message, "Stack underflow");
+    }
+
+    #[test]
+    fn test_roll_brings_a_deeper_value_to_the_top() {
+        let rolled = vec![
+            Bytecode::LoadConst(0),
+            Bytecode::LoadConst(1),
+            Bytecode::LoadConst(1),
+            Bytecode::Roll(2),
+            Bytecode::MakeArray(3),
+        ];
+        let expected = [3, 3, 10].map(RuntimeValue::Integer).to_vec();
+        assert_eq!(run_bytecode(rolled), Ok(RuntimeValue::Array(expected)));
+        let too_deep = vec![Bytecode::LoadConst(0), Bytecode::LoadConst(1), Bytecode::Roll(2)];
+        assert_eq!(run_bytecode(too_deep).unwrap_err().message, "Stack underflow");
+    }
+
+    #[test]
+    fn test_pick_copies_a_deeper_value() {
+        let picked = vec![Bytecode::LoadConst(0), Bytecode::LoadConst(1), Bytecode::Pick(1), Bytecode::MakeArray(3)];
+        let expected = [10, 3, 10].map(RuntimeValue::Integer).to_vec();
+        assert_eq!(run_bytecode(picked), Ok(RuntimeValue::Array(expected)));
+        let too_deep = vec![Bytecode::LoadConst(0), Bytecode::Pick(1)];
+        assert_eq!(run_bytecode(too_deep).unwrap_err().message, "Stack underflow");
+    }
+
+    #[test]
+    fn test_nop_does_nothing() {
+        let result = run_bytecode(vec![Bytecode::Nop, Bytecode::LoadConst(0), Bytecode::Nop]);
+        assert_eq!(result, Ok(RuntimeValue::Integer(10)));
+    }
+
+    #[test]
+    fn test_function_running_off_the_end_is_an_error() {
+        // `f` starts at instruction 3 and has no Return
+        let mut vm = VirtualMachine::new();
+        let function = RuntimeValue::Function { name: "f".to_string(), ip: 3, num_params: 0 };
+        vm.load_bytecode(
+            vec![Bytecode::LoadConst(0), Bytecode::Call(0), Bytecode::Return, Bytecode::LoadConst(1)],
+            vec![function, RuntimeValue::Null],
+        );
+        assert_eq!(vm.run().unwrap_err().message, "function `f` ended without return");
+    }
+
+    #[test]
+    fn test_values_left_on_the_stack_are_reported() {
+        let leftover = vec![Bytecode::LoadConst(0), Bytecode::LoadConst(1)];
+        assert_eq!(
+            run_bytecode(leftover).unwrap_err().message,
+            "unbalanced stack: 1 values were left over when the program ended"
+        );
+    }
+}
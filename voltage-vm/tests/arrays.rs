@@ -0,0 +1,148 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use voltage_vm::{Engine, OutputEvent, RuntimeValue, VoltageError};
+
+fn eval(source: &str) -> Result<RuntimeValue, VoltageError> {
+    Engine::new().eval(source)
+}
+
+fn ints(values: &[i64]) -> RuntimeValue {
+    RuntimeValue::Array(values.iter().map(|&n| RuntimeValue::Integer(n)).collect())
+}
+
+// Evaluates `expression` with `xs` bound to `elements`, which array literals
+// couldn't always hold since they must be all one type
+fn eval_with(expression: &str, elements: Vec<RuntimeValue>) -> Result<RuntimeValue, VoltageError> {
+    let mut engine = Engine::new();
+    engine.load(&format!("fn apply(xs) {{ return {}; }}", expression)).unwrap();
+    engine.call("apply", &[RuntimeValue::Array(elements)])
+}
+
+fn runtime_message(result: Result<RuntimeValue, VoltageError>) -> String {
+    match result {
+        Err(VoltageError::Runtime(error)) => error.message,
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_join_round_trips_split() {
+    let source = r#"import std.string; let csv = "a,,b c,d"; join(string.split(csv, ","), ",") == csv;"#;
+    assert_eq!(eval(source), Ok(RuntimeValue::Boolean(true)));
+    assert_eq!(eval(r#"join(["x", "y", "z"], " - ");"#), Ok(RuntimeValue::String("x - y - z".to_string())));
+    assert_eq!(eval(r#"join([], ",");"#), Ok(RuntimeValue::String(String::new())));
+}
+
+#[test]
+fn test_join_reports_the_first_non_string() {
+    let elements = vec![RuntimeValue::String("a".to_string()), RuntimeValue::Integer(1), RuntimeValue::Boolean(true)];
+    assert_eq!(runtime_message(eval_with(r#"join(xs, ",")"#, elements)), "join: element 1 is int, expected string");
+}
+
+#[test]
+fn test_sorting_keeps_duplicates_and_leaves_the_input_alone() {
+    let source = "let xs = [3, 1, 3, 2, 1]; let sorted = sort(xs); [sorted, xs, sort_desc(xs)];";
+    assert_eq!(
+        eval(source),
+        Ok(RuntimeValue::Array(vec![ints(&[1, 1, 2, 3, 3]), ints(&[3, 1, 3, 2, 1]), ints(&[3, 3, 2, 1, 1])]))
+    );
+    assert_eq!(eval(r#"sort(["pear", "apple", "fig"]);"#), eval(r#"["apple", "fig", "pear"];"#));
+}
+
+#[test]
+fn test_nan_sorts_last() {
+    let floats = [2.5, f64::NAN, -1.0, 2.5].map(RuntimeValue::Float).to_vec();
+    let both = eval_with("[sort(xs), sort_desc(xs)]", floats).unwrap();
+    assert_eq!(both.to_string(), "[[-1.0, 2.5, 2.5, nan], [2.5, 2.5, -1.0, nan]]");
+}
+
+#[test]
+fn test_mixed_types_cannot_be_sorted() {
+    let mixed = vec![RuntimeValue::Integer(1), RuntimeValue::Float(2.0)];
+    assert_eq!(runtime_message(eval_with("sort(xs)", mixed)), "sort: cannot sort a mix of int and float");
+    let mixed = vec![RuntimeValue::String("a".to_string()), RuntimeValue::Integer(1)];
+    assert_eq!(runtime_message(eval_with("sort_desc(xs)", mixed)), "sort_desc: cannot sort a mix of string and int");
+    assert_eq!(
+        runtime_message(eval("sort([true, false]);")),
+        "sort: can only sort integers, floats or strings, got bool"
+    );
+}
+
+#[test]
+fn test_sorting_an_empty_array() {
+    assert_eq!(eval("sort([]);"), Ok(ints(&[])));
+    assert_eq!(eval("sort_desc([]);"), Ok(ints(&[])));
+}
+
+#[test]
+fn test_enumerate_pairs_elements_with_their_index() {
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    let recorded = Rc::clone(&lines);
+    let mut engine = Engine::new();
+    engine.set_output_handler(move |event| {
+        if let OutputEvent::PutsLine(line) = event {
+            recorded.borrow_mut().push(line);
+        }
+    });
+    engine.eval(r#"for pair in enumerate(["a", "b", "c"]) { puts("{}: {}", pair[0], pair[1]); }"#).unwrap();
+    assert_eq!(*lines.borrow(), ["0: a", "1: b", "2: c"]);
+
+    // Strings and ranges enumerate like the `for` loops over them
+    let string = |s: &str| RuntimeValue::String(s.to_string());
+    let pair = |a, b| RuntimeValue::Array(vec![a, b]);
+    let index = RuntimeValue::Integer;
+    let expected = vec![pair(index(0), string("h")), pair(index(1), string("i"))];
+    assert_eq!(eval(r#"enumerate("hi");"#), Ok(RuntimeValue::Array(expected)));
+    assert_eq!(eval("enumerate(5..=6);"), eval("[[0, 5], [1, 6]];"));
+    assert_eq!(eval("enumerate([]);"), Ok(ints(&[])));
+    assert_eq!(runtime_message(eval("enumerate(3);")), "enumerate expects an array, string or range, got int");
+}
+
+#[test]
+fn test_zip_stops_at_the_shorter_input() {
+    let string = |s: &str| RuntimeValue::String(s.to_string());
+    let pair = |a, b| RuntimeValue::Array(vec![a, b]);
+    let int = RuntimeValue::Integer;
+    let expected = vec![pair(int(1), string("a")), pair(int(2), string("b"))];
+    assert_eq!(eval(r#"zip([1, 2, 3], ["a", "b"]);"#), Ok(RuntimeValue::Array(expected)));
+    let expected = vec![pair(string("x"), int(0)), pair(string("y"), int(1))];
+    assert_eq!(eval(r#"zip("xy", 0..10);"#), Ok(RuntimeValue::Array(expected)));
+    assert_eq!(eval("zip([1, 2], []);"), Ok(ints(&[])));
+    assert_eq!(runtime_message(eval("zip([1], true);")), "zip expects an array, string or range, got bool");
+}
+
+#[test]
+fn test_arrays_are_copied_not_shared() {
+    // Changing a copy leaves the original alone, through `let` and calls alike
+    let source = "let a = [[1, 2], [3]]; let b = a; b[0] = [9]; [a, b];";
+    assert_eq!(eval(source), eval("[[[1, 2], [3]], [[9], [3]]];"));
+    let source = "fn grow(xs) { xs[0] = 0; return xs; } let a = [5]; let b = grow(a); [a, b];";
+    assert_eq!(eval(source), Ok(RuntimeValue::Array(vec![ints(&[5]), ints(&[0])])));
+
+    // `clone` makes the same copy explicitly, however deep
+    let source = "let a = [[1, 2], [3]]; let b = clone(a); b[0] = [7]; [a == clone(a), b == a, b[1] == a[1]];";
+    assert_eq!(eval(source), eval("[true, false, true];"));
+    assert_eq!(eval("clone(3);"), Ok(RuntimeValue::Integer(3)));
+
+    // Nothing is shared, so there is no identity to compare
+    assert!(matches!(eval("let a = [1]; is_same(a, a);"), Err(VoltageError::Compile(_))));
+}
+
+#[test]
+fn test_nested_elements_are_written_back() {
+    let lines = Rc::new(RefCell::new(Vec::new()));
+    let recorded = Rc::clone(&lines);
+    let mut engine = Engine::new();
+    engine.set_output_handler(move |event| {
+        if let OutputEvent::PutsLine(line) = event {
+            recorded.borrow_mut().push(line);
+        }
+    });
+    engine.eval("let a = [[1, 2], [3]]; a[0][1] = 5; puts(a);").unwrap();
+    engine.eval("let grid = [[[0, 0], [0, 0]]]; grid[0][1][0] = 7; puts(grid);").unwrap();
+    assert_eq!(*lines.borrow(), ["[[1, 5], [3]]", "[[[0, 0], [7, 0]]]"]);
+
+    // Only a variable can be written back to
+    let error = eval("fn rows() { return [[1]]; } rows()[0] = [2];").unwrap_err();
+    assert_eq!(error.to_string(), "Compile error: Only an element of a variable can be assigned to");
+}
@@ -0,0 +1,56 @@
+use voltage_vm::{call_graph, Engine};
+
+// The codes of the warnings compiling `source` gives
+fn warning_codes(source: &str) -> Vec<&'static str> {
+    let mut engine = Engine::new();
+    engine.load(source).unwrap();
+    engine.warnings().iter().filter_map(|warning| warning.code).collect()
+}
+
+#[test]
+fn test_recursion_without_a_base_case_warns() {
+    let source = "fn count(n) {\n    puts(n);\n    return count(n + 1);\n}";
+    let mut engine = Engine::new();
+    engine.load(source).unwrap();
+    let warnings = engine.warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].code, Some("unconditional_recursion"));
+    assert_eq!(warnings[0].message, "`count` calls itself on every path, so it can never return");
+    assert_eq!(warnings[0].span.line, 3);
+    assert_eq!(warnings[0].notes[0].span.line, 1);
+
+    // Every branch recursing is no better
+    let source = "fn spin(n) { if n > 0 { spin(n - 1); } else { spin(n + 1); } }";
+    assert_eq!(warning_codes(source), ["unconditional_recursion"]);
+    let source = "fn sum(xs) { let rest = sum(xs); return xs[0] + rest; }";
+    assert_eq!(warning_codes(source), ["unconditional_recursion"]);
+}
+
+#[test]
+fn test_recursion_with_a_way_out_does_not_warn() {
+    let sources = [
+        "fn fact(n) { if n < 2 { return 1; } return n * fact(n - 1); }",
+        "fn fib(n) { if n < 2 { return n; } else { return fib(n - 1) + fib(n - 2); } }",
+        "fn down(n) { while n > 100 { return 0; } return down(n - 1); }",
+        "fn check(n) { if n < 0 { panic(\"negative\"); } return n > 0 && check(n - 1); }",
+        "fn retry(n) { try { return 1 / n; } catch e { puts(e); } return retry(n + 1); }",
+    ];
+    for source in sources {
+        assert!(warning_codes(source).is_empty(), "{}", source);
+    }
+}
+
+#[test]
+fn test_call_graph_has_an_edge_for_each_call() {
+    let source = "fn report() { let p = parse(\"1,2\"); puts(total(p)); }\n\
+                  fn parse(text) { return [text]; }\n\
+                  fn total(parts) { if len(parts) == 0 { return 0; } let f = parse; return len(parts) + len(f(\"3\")) + total([]); }\n\
+                  report();";
+    let program = voltage_vm::compile(source).unwrap();
+    assert_eq!(
+        call_graph(&program),
+        "digraph calls {\n    \"<top level>\";\n    \"report\";\n    \"parse\";\n    \"total\";\n    \
+         \"<top level>\" -> \"report\";\n    \"report\" -> \"parse\";\n    \"report\" -> \"total\";\n    \
+         \"total\" -> \"parse\";\n    \"total\" -> \"total\";\n}\n"
+    );
+}
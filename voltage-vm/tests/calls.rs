@@ -0,0 +1,57 @@
+use voltage_vm::{Engine, RuntimeValue};
+
+const HELPERS: &str = "fn double(n) { return n * 2; }
+fn negate(n) { return 0 - n; }
+fn pick(negative) {
+    if negative {
+        return negate;
+    }
+    return double;
+}";
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.load(HELPERS).unwrap();
+    engine
+}
+
+#[test]
+fn test_calling_a_parenthesized_expression() {
+    let mut engine = engine();
+    assert_eq!(engine.eval("(pick(true))(5);"), Ok(RuntimeValue::Integer(-5)));
+}
+
+#[test]
+fn test_calling_the_result_of_a_call() {
+    let mut engine = engine();
+    assert_eq!(engine.eval("pick(false)(5);"), Ok(RuntimeValue::Integer(10)));
+}
+
+#[test]
+fn test_calling_an_array_element() {
+    let mut engine = engine();
+    assert_eq!(engine.eval("let handlers = [double, negate]; handlers[1](3);"), Ok(RuntimeValue::Integer(-3)));
+}
+
+#[test]
+fn test_calling_a_parameter() {
+    let mut engine = engine();
+    engine.load("fn apply(f, x) { return f(x); }").unwrap();
+    assert_eq!(engine.eval("apply(double, 21);"), Ok(RuntimeValue::Integer(42)));
+}
+
+#[test]
+fn test_builtins_are_still_called_by_name() {
+    let mut engine = engine();
+    assert_eq!(engine.eval("puts(double(2));"), Ok(RuntimeValue::Null));
+    assert_eq!(engine.eval("print(\"x\");"), Ok(RuntimeValue::Null));
+}
+
+#[test]
+fn test_calling_a_value_that_is_not_a_function_is_an_error() {
+    let mut engine = engine();
+    assert_eq!(
+        engine.eval("[1, 2][0](3);").unwrap_err().to_string(),
+        "Runtime error: Cannot call a value of type int"
+    );
+}
@@ -0,0 +1,90 @@
+use voltage_vm::{Engine, RuntimeValue};
+
+fn int(n: i64) -> RuntimeValue {
+    RuntimeValue::Integer(n)
+}
+
+const CLASSIFY: &str = r#"
+    fn classify(n) {
+        if n < 0 {
+            return "negative";
+        } elif n == 0 {
+            return "zero";
+        } else {
+            return "positive";
+        }
+    }
+"#;
+
+#[test]
+fn test_if_runs_exactly_one_branch() {
+    let mut engine = Engine::new();
+    engine.load(CLASSIFY).unwrap();
+    assert_eq!(engine.eval("classify(0 - 5);"), Ok(RuntimeValue::String("negative".to_string())));
+    assert_eq!(engine.eval("classify(0);"), Ok(RuntimeValue::String("zero".to_string())));
+    assert_eq!(engine.eval("classify(5);"), Ok(RuntimeValue::String("positive".to_string())));
+}
+
+#[test]
+fn test_while_loops_until_its_condition_is_false() {
+    let mut engine = Engine::new();
+    let source = "let i = [0]; let total = [0]; \
+                  while i[0] < 5 { i[0] += 1; total[0] += i[0]; } \
+                  total[0];";
+    assert_eq!(engine.eval(source), Ok(int(15)));
+    assert_eq!(engine.eval("while false { puts(1); } 7;"), Ok(int(7)));
+}
+
+#[test]
+fn test_conditions_must_be_booleans() {
+    let mut engine = Engine::new();
+    assert_eq!(
+        engine.eval("if 1 { puts(1); }").unwrap_err().to_string(),
+        "Runtime error: expected bool in condition, found int"
+    );
+    assert_eq!(
+        engine.eval("if false { } elif \"yes\" { }").unwrap_err().to_string(),
+        "Runtime error: expected bool in condition, found string"
+    );
+    assert_eq!(
+        engine.eval("while [1] { }").unwrap_err().to_string(),
+        "Runtime error: expected bool in condition, found array"
+    );
+}
+
+#[test]
+fn test_logical_operators_short_circuit() {
+    let mut engine = Engine::new();
+    // The right operand would fail if it were evaluated
+    engine.load("fn boom() { assert(false); return true; }").unwrap();
+    assert_eq!(engine.eval("false && boom();"), Ok(RuntimeValue::Boolean(false)));
+    assert_eq!(engine.eval("true || boom();"), Ok(RuntimeValue::Boolean(true)));
+    assert_eq!(engine.eval("1 < 2 && 2 < 3;"), Ok(RuntimeValue::Boolean(true)));
+    assert_eq!(engine.eval("1 > 2 || 2 > 3;"), Ok(RuntimeValue::Boolean(false)));
+    assert_eq!(engine.eval("true && 1 < 2 || boom();"), Ok(RuntimeValue::Boolean(true)));
+}
+
+#[test]
+fn test_logical_operands_must_be_booleans() {
+    let mut engine = Engine::new();
+    assert_eq!(
+        engine.eval("1 && true;").unwrap_err().to_string(),
+        "Runtime error: expected bool in condition, found int"
+    );
+    assert_eq!(
+        engine.eval("false || \"no\";").unwrap_err().to_string(),
+        "Runtime error: expected bool in condition, found string"
+    );
+}
+
+#[test]
+fn test_truthy_coerces_scripting_style() {
+    let mut engine = Engine::new();
+    for falsy in ["false", "0", "\"\"", "[]"] {
+        assert_eq!(engine.eval(&format!("truthy({});", falsy)), Ok(RuntimeValue::Boolean(false)), "{}", falsy);
+    }
+    for truthy in ["true", "2", "\"a\"", "[0]"] {
+        assert_eq!(engine.eval(&format!("truthy({});", truthy)), Ok(RuntimeValue::Boolean(true)), "{}", truthy);
+    }
+    assert_eq!(engine.eval("if truthy(1) { 3; } 4;"), Ok(int(4)));
+}
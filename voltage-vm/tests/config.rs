@@ -0,0 +1,93 @@
+use voltage_core::Statement;
+use voltage_parser::{Lexer, Parser, Severity};
+use voltage_vm::{
+    Bytecode, BytecodeCompiler, CompileErrorKind, CompilerOptions, Engine, RuntimeValue, VirtualMachine, VmConfig,
+    VoltageError,
+};
+
+fn statements(source: &str) -> Vec<Statement> {
+    Parser::from_lexer(Lexer::new(source)).with_source(source).parse()
+}
+
+fn engine(config: VmConfig) -> Engine {
+    Engine::with_options(config, CompilerOptions::default())
+}
+
+#[test]
+fn test_defaults_are_what_new_gives() {
+    let config = VmConfig::default();
+    assert_eq!(config.max_call_depth, voltage_vm::DEFAULT_MAX_CALL_DEPTH);
+    assert_eq!(config.max_stack, voltage_vm::DEFAULT_MAX_STACK_SIZE);
+    assert_eq!((config.fuel, config.trace, config.coverage), (None, false, false));
+    assert!(VirtualMachine::new().coverage().is_none());
+    let options = CompilerOptions::default();
+    assert_eq!((options.optimize, options.emit_debug_info, options.deny_warnings), (false, true, false));
+}
+
+#[test]
+fn test_vm_config_sets_limits() {
+    let mut fueled = engine(VmConfig { fuel: Some(100), ..VmConfig::default() });
+    let error = fueled.eval("let i = 0; while true { let i = i + 1; }").unwrap_err();
+    assert_eq!(error.to_string(), "Runtime error: out of fuel after running 100 instructions");
+
+    let mut shallow = engine(VmConfig { max_call_depth: 2, ..VmConfig::default() });
+    shallow.load("fn down(n) { if n == 0 { return 0; } return 1 + down(n - 1); }").unwrap();
+    assert_eq!(shallow.call("down", &[RuntimeValue::Integer(2)]), Ok(RuntimeValue::Integer(2)));
+    assert!(shallow.call("down", &[RuntimeValue::Integer(3)]).is_err());
+
+    let mut small = engine(VmConfig { max_stack: 3, ..VmConfig::default() });
+    assert_eq!(small.eval("[1, 2, 3, 4];").unwrap_err().to_string(), "Runtime error: maximum stack size of 3 values exceeded");
+}
+
+#[test]
+fn test_vm_config_turns_on_recording() {
+    let mut traced = engine(VmConfig { trace: true, ..VmConfig::default() });
+    let Err(VoltageError::Runtime(error)) = traced.eval("let x = 1; x / 0;") else {
+        panic!("expected a runtime error");
+    };
+    assert!(error.state.is_some());
+
+    let mut covered = engine(VmConfig { coverage: true, ..VmConfig::default() });
+    covered.load("let x = 1;\nif x > 5 {\n    puts(x);\n}").unwrap();
+    assert_eq!(covered.line_coverage().unwrap().uncovered(), vec![3]);
+}
+
+#[test]
+fn test_programs_without_debug_info_have_no_source_map() {
+    let source = "fn half(n) { return n / 2; }\nputs(half(4));";
+    let with = BytecodeCompiler::new().compile_script(&statements(source)).unwrap();
+    let options = CompilerOptions { emit_debug_info: false, ..CompilerOptions::default() };
+    let without = BytecodeCompiler::with_options(options).compile_script(&statements(source)).unwrap();
+    assert!(!with.source_map.is_empty());
+    assert!(without.source_map.is_empty());
+    assert_eq!(without.bytecode, with.bytecode);
+}
+
+#[test]
+fn test_denied_warnings_fail_compilation() {
+    let source = "fn main() { let puts = 1; }";
+    let mut allowed = BytecodeCompiler::new();
+    allowed.compile_script(&statements(source)).unwrap();
+    assert!(!allowed.warnings().is_empty());
+
+    let mut denied = BytecodeCompiler::with_options(CompilerOptions { deny_warnings: true, ..CompilerOptions::default() });
+    let error = denied.compile_script(&statements(source)).unwrap_err();
+    assert_eq!(error.kind, CompileErrorKind::DeniedWarning);
+    assert_eq!(error.message, format!("{} (warnings are denied)", allowed.warnings()[0].message));
+    assert_eq!(error.span.map(|span| span.line), Some(1));
+    assert_eq!(denied.warnings()[0].severity, Severity::Error);
+
+    let options = CompilerOptions { deny_warnings: true, ..CompilerOptions::default() };
+    assert!(matches!(Engine::with_options(VmConfig::default(), options).eval(source), Err(VoltageError::Compile(_))));
+}
+
+#[test]
+fn test_optimized_scripts_inline_calls() {
+    let source = "fn double(n) { return n * 2; }\nputs(double(4));";
+    let calls = |options| {
+        let program = BytecodeCompiler::with_options(options).compile_script(&statements(source)).unwrap();
+        program.bytecode.iter().filter(|op| matches!(op, Bytecode::Call(_))).count()
+    };
+    assert_eq!(calls(CompilerOptions::default()), 1);
+    assert_eq!(calls(CompilerOptions { optimize: true, ..CompilerOptions::default() }), 0);
+}
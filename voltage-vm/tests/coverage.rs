@@ -0,0 +1,47 @@
+use voltage_vm::{Engine, RuntimeValue};
+
+const SOURCE: &str = "fn classify(n) {
+    if n < 0 {
+        return \"negative\";
+    }
+    return \"non-negative\";
+}
+fn unused() {
+    return 0;
+}
+let result = classify(5);";
+
+#[test]
+fn test_untaken_branches_and_uncalled_functions_are_uncovered() {
+    let mut engine = Engine::new();
+    engine.enable_coverage();
+    engine.load(SOURCE).unwrap();
+    assert_eq!(engine.get_global("result"), Some(&RuntimeValue::String("non-negative".to_string())));
+
+    let coverage = engine.line_coverage().unwrap();
+    assert_eq!(coverage.covered(), vec![1, 2, 5, 10]);
+    assert_eq!(coverage.uncovered(), vec![3, 7, 8]);
+    assert_eq!(coverage.summary("classify.v"), "classify.v: 4/7 lines covered (57.1%)");
+
+    // Calling the function again with another argument covers the branch
+    engine.call("classify", &[RuntimeValue::Integer(-1)]).unwrap();
+    assert_eq!(engine.line_coverage().unwrap().uncovered(), vec![7, 8]);
+}
+
+#[test]
+fn test_lcov_output() {
+    let mut engine = Engine::new();
+    engine.enable_coverage();
+    engine.load("let a = 1;\nif a > 1 {\n    puts(a);\n}").unwrap();
+    assert_eq!(
+        engine.line_coverage().unwrap().to_lcov("small.v"),
+        "TN:\nSF:small.v\nDA:1,1\nDA:2,1\nDA:3,0\nLF:3\nLH:2\nend_of_record\n"
+    );
+}
+
+#[test]
+fn test_coverage_is_off_by_default() {
+    let mut engine = Engine::new();
+    engine.load("let a = 1;").unwrap();
+    assert!(engine.line_coverage().is_none());
+}
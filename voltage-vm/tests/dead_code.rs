@@ -0,0 +1,66 @@
+use voltage_vm::{compile_reachable, Engine, Program, RuntimeValue};
+
+fn function_names(program: &Program) -> Vec<&str> {
+    program.functions.iter().map(|function| function.name.as_str()).collect()
+}
+
+#[test]
+fn test_unused_functions_are_left_out_with_a_warning() {
+    let source = "fn main() { puts(double(2)); }\n\
+                  fn double(n) { return add(n, n); }\n\
+                  fn add(a, b) { return a + b; }\n\
+                  fn unused() { return add(1, 2); }";
+    let (program, warnings) = compile_reachable(source, &[]).unwrap();
+    assert_eq!(function_names(&program), vec!["main", "double", "add"]);
+    let warnings: Vec<String> = warnings.iter().map(ToString::to_string).collect();
+    assert_eq!(warnings, vec!["4:1: function `unused` is never used"]);
+
+    // Without elimination every function is compiled
+    assert_eq!(voltage_vm::compile(source).unwrap().functions.len(), 4);
+}
+
+#[test]
+fn test_functions_used_as_values_are_kept() {
+    let source = "fn helper() { return 42; }\n\
+                  fn unused() { return 0; }\n\
+                  let f = helper;\n\
+                  f();";
+    let (program, warnings) = compile_reachable(source, &[]).unwrap();
+    assert_eq!(function_names(&program), vec!["helper"]);
+    assert_eq!(warnings.len(), 1);
+
+    let mut engine = Engine::new();
+    engine.load_program(program).unwrap();
+    assert_eq!(engine.call("helper", &[]), Ok(RuntimeValue::Integer(42)));
+}
+
+#[test]
+fn test_exports_are_kept() {
+    let source = "fn api() { return inner(); } fn inner() { return 1; } fn unused() { }";
+    let (program, _) = compile_reachable(source, &["api"]).unwrap();
+    assert_eq!(function_names(&program), vec!["api", "inner"]);
+}
+
+#[test]
+fn test_engine_keeps_functions_it_already_defined() {
+    let mut engine = Engine::new();
+    engine.eliminate_dead_code(&["entry"]);
+    engine.load("fn entry() { return 1; } fn later() { return 2; }").unwrap();
+    assert!(engine.get_global("later").is_none());
+
+    // Once defined, a function stays defined when later code doesn't use it
+    assert_eq!(engine.eval("later();"), Ok(RuntimeValue::Integer(2)));
+    assert_eq!(engine.eval("1;"), Ok(RuntimeValue::Integer(1)));
+    assert!(engine.warnings().is_empty());
+    assert_eq!(engine.call("later", &[]), Ok(RuntimeValue::Integer(2)));
+}
+
+#[test]
+fn test_warnings_before_an_error_are_kept() {
+    let source = "fn helper() {\n    let unused = 1;\n    return 0;\n}\nputs(helper());\nputs(missing);";
+    let statements = voltage_parser::parse_with_diagnostics(source).statements;
+    let mut compiler = voltage_vm::BytecodeCompiler::new();
+    compiler.eliminate_dead_code(std::iter::empty::<&str>());
+    assert!(compiler.compile_script(&statements).is_err());
+    assert_eq!(compiler.warnings()[0].code, Some("unused_variable"));
+}
@@ -0,0 +1,83 @@
+use std::io;
+use voltage_vm::{compile, RuntimeValue, StepResult, VirtualMachine};
+
+const SOURCE: &str = "fn main() {
+    let a = 20;
+    let b = a + 22;
+    puts(b);
+    return b;
+}";
+
+// A VM paused at the start of `main`
+fn paused_in_main() -> VirtualMachine {
+    let mut vm = VirtualMachine::new();
+    vm.set_output(Box::new(io::sink()));
+    vm.load_program(compile(SOURCE).unwrap()).unwrap();
+    vm.run().unwrap();
+    vm.enter_function("main", &[]).unwrap();
+    vm
+}
+
+fn int(n: i64) -> RuntimeValue {
+    RuntimeValue::Integer(n)
+}
+
+#[test]
+fn test_break_on_a_line_and_step_through_it() {
+    let mut vm = paused_in_main();
+    let breakpoints = vm.set_line_breakpoint(3);
+    assert_eq!(breakpoints.len(), 1);
+
+    let paused = vm.continue_until_breakpoint().unwrap();
+    assert_eq!(paused, StepResult::Running { ip: breakpoints[0] });
+    assert_eq!(vm.source_map().span_for(vm.ip()).map(|span| span.line), Some(3));
+    assert_eq!(vm.locals_snapshot(), vec![("a".to_string(), int(20))]);
+
+    // Load `a`, then 22
+    assert_eq!(vm.step(), Ok(StepResult::Running { ip: breakpoints[0] + 1 }));
+    assert_eq!(vm.step(), Ok(StepResult::Running { ip: breakpoints[0] + 2 }));
+    assert_eq!(vm.stack_snapshot(), vec![int(20), int(20), int(22)]);
+
+    assert_eq!(vm.continue_until_breakpoint(), Ok(StepResult::Finished(int(42))));
+}
+
+#[test]
+fn test_run_and_steps_agree() {
+    let mut vm = paused_in_main();
+    let result = loop {
+        if let StepResult::Finished(result) = vm.step().unwrap() {
+            break result;
+        }
+    };
+    assert_eq!(result, int(42));
+    assert_eq!(paused_in_main().run_function("main", &[]), Ok(int(42)));
+}
+
+#[test]
+fn test_breakpoints_on_instructions() {
+    let mut vm = paused_in_main();
+    let start = vm.ip();
+    vm.set_breakpoint(start + 2);
+    assert_eq!(vm.continue_until_breakpoint(), Ok(StepResult::Running { ip: start + 2 }));
+
+    vm.clear_breakpoints();
+    assert_eq!(vm.set_line_breakpoint(99), Vec::<usize>::new());
+    assert_eq!(vm.continue_until_breakpoint(), Ok(StepResult::Finished(int(42))));
+}
+
+#[test]
+fn test_locals_are_named_and_shadowed_ones_left_out() {
+    let source = "fn main() {\n    let x = 1;\n    if x > 0 {\n        let x = 2;\n        puts(x);\n    }\n    return x;\n}";
+    let mut vm = VirtualMachine::new();
+    vm.set_output(Box::new(io::sink()));
+    vm.load_program(compile(source).unwrap()).unwrap();
+    vm.run().unwrap();
+    vm.enter_function("main", &[]).unwrap();
+
+    vm.set_line_breakpoint(5);
+    vm.set_line_breakpoint(7);
+    vm.continue_until_breakpoint().unwrap();
+    assert_eq!(vm.locals_snapshot(), vec![("x".to_string(), int(2))]);
+    vm.continue_until_breakpoint().unwrap();
+    assert_eq!(vm.locals_snapshot(), vec![("x".to_string(), int(1))]);
+}
@@ -0,0 +1,218 @@
+use std::collections::HashMap;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+use voltage_vm::{Engine, Program, RuntimeValue, VoltageError};
+
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl SharedBuffer {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_eval_returns_final_expression() {
+    let mut engine = Engine::new();
+    assert_eq!(engine.eval("let x = 6; x * 7;"), Ok(RuntimeValue::Integer(42)));
+    assert_eq!(engine.eval("let y = 1;"), Ok(RuntimeValue::Null));
+    // Globals persist between evaluations
+    assert_eq!(engine.eval("x + y;"), Ok(RuntimeValue::Integer(7)));
+}
+
+#[test]
+fn test_load_then_call_many_times() {
+    let mut engine = Engine::new();
+    engine.load("fn square(n) { return n * n; } fn cube(n) { return square(n) * n; }").unwrap();
+
+    for n in 0..5 {
+        assert_eq!(engine.call("cube", &[RuntimeValue::Integer(n)]), Ok(RuntimeValue::Integer(n * n * n)));
+    }
+
+    // Functions from earlier loads survive later ones
+    engine.load("fn inc(n) { return n + 1; }").unwrap();
+    assert_eq!(engine.call("square", &[RuntimeValue::Integer(3)]), Ok(RuntimeValue::Integer(9)));
+}
+
+#[test]
+fn test_load_precompiled_program() {
+    let program = voltage_vm::compile("let base = 10; fn add_base(n) { return n + base; }").unwrap();
+    let program = Program::from_bytes(&program.to_bytes()).unwrap();
+
+    let mut engine = Engine::new();
+    engine.load_program(program).unwrap();
+    assert_eq!(engine.call("add_base", &[RuntimeValue::Integer(5)]), Ok(RuntimeValue::Integer(15)));
+}
+
+#[test]
+fn test_round_trips_values_through_calls() {
+    let mut engine = Engine::new();
+    engine.load("fn identity(value) { return value; }").unwrap();
+
+    let values = [
+        RuntimeValue::Integer(-7),
+        RuntimeValue::Float(2.5),
+        RuntimeValue::String("héllo".to_string()),
+        RuntimeValue::Boolean(true),
+        RuntimeValue::Null,
+    ];
+    for value in values {
+        assert_eq!(engine.call("identity", std::slice::from_ref(&value)), Ok(value));
+    }
+    assert_eq!(engine.eval(r#""text";"#), Ok(RuntimeValue::String("text".to_string())));
+}
+
+#[test]
+fn test_registered_host_function_is_callable_from_scripts() {
+    let calls = Rc::new(RefCell::new(Vec::new()));
+    let recorded = Rc::clone(&calls);
+
+    let mut engine = Engine::new();
+    engine
+        .register_builtin("record", move |args| {
+            recorded.borrow_mut().extend_from_slice(args);
+            Ok(RuntimeValue::Integer(args.len() as i64))
+        })
+        .unwrap();
+
+    let result = engine.eval(r#"fn go() { return record(1, "two"); } go();"#);
+    assert_eq!(result, Ok(RuntimeValue::Integer(2)));
+    assert_eq!(
+        *calls.borrow(),
+        vec![RuntimeValue::Integer(1), RuntimeValue::String("two".to_string())]
+    );
+
+    // Errors from the host surface as runtime errors
+    engine.register_builtin("fail", |_| Err("host says no".to_string())).unwrap();
+    assert_eq!(engine.eval("fail();").unwrap_err().to_string(), "Runtime error: host says no");
+}
+
+#[test]
+fn test_output_goes_to_configured_writer() {
+    let output = SharedBuffer::default();
+    let mut engine = Engine::new();
+    engine.set_output(output.clone());
+
+    engine.eval(r#"print("a"); puts("b"); puts(1 + 2);"#).unwrap();
+    assert_eq!(output.contents(), "ab\n3\n");
+}
+
+#[test]
+fn test_errors_name_their_stage() {
+    let mut engine = Engine::new();
+    assert!(matches!(engine.eval("let x = #;"), Err(VoltageError::Lex(_))));
+    assert_eq!(
+        engine.eval("let x = 99999999999999999999;").unwrap_err().to_string(),
+        "Lex error: integer literal out of range for i64: 99999999999999999999 at line 1, column 9"
+    );
+    assert!(matches!(engine.eval("fn broken( {"), Err(VoltageError::Parse(_))));
+    assert!(matches!(engine.eval("fn f() { if true { fn g() { } } }"), Err(VoltageError::Compile(_))));
+    assert!(matches!(engine.eval("missing();"), Err(VoltageError::Compile(_))));
+    assert!(matches!(engine.call("missing", &[]), Err(VoltageError::Runtime(_))));
+}
+
+#[test]
+fn test_last_span_points_at_failing_statement() {
+    let mut engine = Engine::new();
+    engine.load("fn check(n) {\n    let half = n / 2;\n    assert(half > 1);\n}").unwrap();
+    assert!(engine.call("check", &[RuntimeValue::Integer(8)]).is_ok());
+    assert!(engine.call("check", &[RuntimeValue::Integer(2)]).is_err());
+
+    let span = engine.last_span().unwrap();
+    assert_eq!((span.line, span.column), (3, 5));
+}
+
+#[test]
+fn test_format_builds_strings_without_printing() {
+    let output = SharedBuffer::default();
+    let mut engine = Engine::new();
+    engine.set_output(output.clone());
+
+    engine.eval(r#"let s = format("{} + {} = {}", 1, 2, 1 + 2);"#).unwrap();
+    assert_eq!(engine.get_global("s"), Some(&RuntimeValue::String("1 + 2 = 3".to_string())));
+    assert_eq!(engine.eval(r#"s == "1 + 2 = 3";"#), Ok(RuntimeValue::Boolean(true)));
+    assert_eq!(engine.eval(r#"format("{{}}");"#), Ok(RuntimeValue::String("{}".to_string())));
+    assert_eq!(output.contents(), "");
+
+    assert_eq!(
+        engine.eval(r#"format("{} {}", 1);"#).unwrap_err().to_string(),
+        "Runtime error: format string has 2 placeholders but 1 arguments were given"
+    );
+}
+
+#[test]
+fn test_formatted_print_and_puts_substitute_arguments() {
+    let output = SharedBuffer::default();
+    let mut engine = Engine::new();
+    engine.set_output(output.clone());
+
+    engine.eval(r#"let x = 7; print("x={} ", x); puts("{} and {}", x, [1]);"#).unwrap();
+    assert_eq!(output.contents(), "x=7 7 and [1]\n");
+}
+
+#[test]
+fn test_functions_defined_twice_in_one_load_are_an_error() {
+    let mut engine = Engine::new();
+    let error = engine.load("fn f() { return 1; } fn f() { return 2; }").unwrap_err();
+    assert_eq!(error.to_string(), "Compile error: duplicate definition of `f`");
+
+    // A later load can still replace a function
+    engine.load("fn f() { return 1; }").unwrap();
+    engine.load("fn f() { return 2; }").unwrap();
+    assert_eq!(engine.call("f", &[]), Ok(RuntimeValue::Integer(2)));
+}
+
+fn bindings(values: &[(&str, RuntimeValue)]) -> HashMap<String, RuntimeValue> {
+    values.iter().map(|(name, value)| (name.to_string(), value.clone())).collect()
+}
+
+#[test]
+fn test_expressions_are_evaluated_with_their_bindings() {
+    let mut engine = Engine::new();
+    engine.load("fn clamp(x, low, high) { if x < low { return low; } if x > high { return high; } return x; }").unwrap();
+    let prices = bindings(&[("base", RuntimeValue::Float(80.0)), ("rate", RuntimeValue::Float(0.5)), ("fee", RuntimeValue::Float(2.5))]);
+    assert_eq!(engine.eval_expression::<f64>("base * rate + fee", &prices), Ok(42.5));
+    let counts = bindings(&[("rate", RuntimeValue::Integer(2)), ("fee", RuntimeValue::Integer(5))]);
+    assert_eq!(engine.eval_expression::<i64>("clamp(rate * fee, 0, 8);", &counts), Ok(8));
+
+    // Bindings only last for the evaluation they were given to
+    engine.eval("let rate = 3;").unwrap();
+    assert_eq!(engine.eval_expression::<i64>("rate", &counts), Ok(2));
+    assert_eq!(engine.get_global("rate"), Some(&RuntimeValue::Integer(3)));
+    assert_eq!(engine.get_global("fee"), None);
+}
+
+#[test]
+fn test_expression_errors() {
+    let mut engine = Engine::new();
+    let values = bindings(&[("base", RuntimeValue::Integer(10)), ("name", RuntimeValue::from("volt"))]);
+    match engine.eval_expression::<i64>("base + fee", &values) {
+        Err(VoltageError::Runtime(error)) => assert_eq!(error.message, "Undefined variable: fee"),
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+    assert_eq!(
+        engine.eval_expression::<f64>("name", &values),
+        Err(VoltageError::Conversion("expected float, found string".to_string()))
+    );
+
+    let not_an_expression = Err(VoltageError::Parse("expected an expression, found a statement".to_string()));
+    assert_eq!(engine.eval_expression::<i64>("let x = 1;", &values), not_an_expression);
+    assert_eq!(engine.eval_expression::<i64>("fn f() { return 1; }", &values), not_an_expression);
+    assert_eq!(engine.eval_expression::<i64>("import math;", &values), not_an_expression);
+    assert_eq!(
+        engine.eval_expression::<i64>("base; base", &values),
+        Err(VoltageError::Parse("expected a single expression".to_string()))
+    );
+}
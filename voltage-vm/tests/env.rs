@@ -0,0 +1,42 @@
+use voltage_vm::{Engine, EnvAccess, RuntimeValue, VoltageError};
+
+fn engine_with_vars() -> Engine {
+    let mut engine = Engine::new();
+    engine.allow_env(EnvAccess::with_vars([("PATH", "/usr/bin:/bin"), ("EMPTY", "")]));
+    engine
+}
+
+#[test]
+fn test_env_builtins_are_disabled_by_default() {
+    let mut engine = Engine::new();
+    for source in [r#"env("PATH");"#, r#"set_env("PATH", "/tmp");"#] {
+        assert_eq!(
+            engine.eval(source).unwrap_err().to_string(),
+            "Runtime error: environment access is disabled"
+        );
+    }
+}
+
+#[test]
+fn test_lookups_use_the_injected_vars() {
+    let mut engine = engine_with_vars();
+    assert_eq!(engine.eval(r#"env("PATH");"#), Ok(RuntimeValue::String("/usr/bin:/bin".to_string())));
+    assert_eq!(engine.eval(r#"env("EMPTY");"#), Ok(RuntimeValue::String(String::new())));
+    // Nothing of the real environment leaks through
+    assert_eq!(engine.eval(r#"env("HOME");"#), Ok(RuntimeValue::Null));
+}
+
+#[test]
+fn test_set_env_changes_the_injected_vars() {
+    let mut engine = engine_with_vars();
+    assert_eq!(engine.eval(r#"set_env("MODE", "test"); env("MODE");"#), Ok(RuntimeValue::String("test".to_string())));
+    assert!(std::env::var("MODE").is_err());
+    assert!(engine.eval(r#"set_env("A=B", "x");"#).is_err());
+}
+
+#[test]
+fn test_keys_must_be_strings() {
+    let mut engine = engine_with_vars();
+    let error = engine.eval("env(1);").unwrap_err();
+    assert!(matches!(&error, VoltageError::Runtime(e) if e.message.contains("expected string")), "{}", error);
+}
@@ -0,0 +1,42 @@
+use voltage_vm::{Engine, RuntimeValue, VoltageError, MAX_COMPARISON_DEPTH};
+
+fn eval(source: &str) -> Result<RuntimeValue, VoltageError> {
+    Engine::new().eval(source)
+}
+
+fn boolean(b: bool) -> Result<RuntimeValue, VoltageError> {
+    Ok(RuntimeValue::Boolean(b))
+}
+
+#[test]
+fn test_nested_arrays_compare_by_value() {
+    assert_eq!(eval("[1, 2] == [1, 2];"), boolean(true));
+    assert_eq!(eval("[[1, 2], [3]] == [[1, 2], [3]];"), boolean(true));
+    assert_eq!(eval("[[1, 2], [3]] == [[1, 2], [4]];"), boolean(false));
+    assert_eq!(eval("[[1], [2, 3]] == [[1, 2], [3]];"), boolean(false));
+    // Copies are equal to what they were copied from
+    assert_eq!(eval(r#"let a = [["x"], ["y"]]; let b = a; b == a;"#), boolean(true));
+}
+
+#[test]
+fn test_not_equal_follows() {
+    assert_eq!(eval("[[1, 2]] != [[1, 2]];"), boolean(false));
+    assert_eq!(eval("[[1, 2]] != [[2, 1]];"), boolean(true));
+    assert_eq!(eval("[1.0, 0.0] != [1.0, -0.0];"), boolean(false));
+}
+
+#[test]
+fn test_comparing_very_deep_arrays_is_an_error() {
+    let nest = |depth: usize| format!("let a = [0]; for i in 0..{} {{ let a = [a]; }}", depth);
+    // Up to the limit comparisons work as usual
+    let source = format!("{} a == a;", nest(MAX_COMPARISON_DEPTH - 1));
+    assert_eq!(eval(&source), boolean(true));
+
+    for operator in ["==", "!="] {
+        let source = format!("{} a {} a;", nest(MAX_COMPARISON_DEPTH), operator);
+        assert_eq!(
+            eval(&source).unwrap_err().to_string(),
+            format!("Runtime error: cannot compare values nested more than {} deep", MAX_COMPARISON_DEPTH)
+        );
+    }
+}
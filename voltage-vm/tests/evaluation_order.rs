@@ -0,0 +1,127 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use voltage_vm::{Engine, RuntimeValue};
+
+// An engine with `log(label, value)`, which records `label` and returns
+// `value`, and the labels in the order it was called with them
+fn engine() -> (Engine, Rc<RefCell<Vec<String>>>) {
+    let log = Rc::new(RefCell::new(Vec::new()));
+    let mut engine = Engine::new();
+    let record = Rc::clone(&log);
+    engine
+        .register_builtin("log", move |args| match args {
+            [RuntimeValue::String(label), value] => {
+                record.borrow_mut().push(label.clone());
+                Ok(value.clone())
+            }
+            _ => Err("log expects a label and a value".to_string()),
+        })
+        .unwrap();
+    (engine, log)
+}
+
+// The labels `source` logs, in order
+fn order(source: &str) -> Vec<String> {
+    let (mut engine, log) = engine();
+    engine.eval(source).unwrap();
+    let labels = log.borrow().clone();
+    labels
+}
+
+#[test]
+fn test_arguments_are_evaluated_left_to_right() {
+    let source = r#"
+        fn add(a, b, c) { return a + b + c; }
+        add(log("a", 1), log("b", 2), log("c", 3));
+    "#;
+    assert_eq!(order(source), ["a", "b", "c"]);
+    assert_eq!(order(r#"print("{} {}", log("a", 1), log("b", 2));"#), ["a", "b"]);
+}
+
+#[test]
+fn test_the_callee_is_evaluated_before_its_arguments() {
+    let source = r#"
+        fn double(x) { return x * 2; }
+        let doublers = [double];
+        doublers[log("callee", 0)](log("argument", 1));
+    "#;
+    assert_eq!(order(source), ["callee", "argument"]);
+}
+
+#[test]
+fn test_operands_are_evaluated_left_to_right() {
+    assert_eq!(order(r#"log("a", 1) - log("b", 2) * log("c", 3);"#), ["a", "b", "c"]);
+    assert_eq!(order(r#"log("a", 2) ** log("b", 3) ** log("c", 1);"#), ["a", "b", "c"]);
+    assert_eq!(order(r#"log("a", 1) < log("b", 2) && log("c", true);"#), ["a", "b", "c"]);
+    assert_eq!(order(r#"log("a", 1)..log("b", 2);"#), ["a", "b"]);
+}
+
+#[test]
+fn test_literals_are_evaluated_in_the_order_written() {
+    assert_eq!(order(r#"[log("a", 1), log("b", 2), log("c", 3)];"#), ["a", "b", "c"]);
+    assert_eq!(order(r#"Point { y: log("y", 2), x: log("x", 1) };"#), ["y", "x"]);
+}
+
+#[test]
+fn test_the_index_is_evaluated_before_the_value() {
+    let source = r#"
+        let xs = [0, 0];
+        xs[log("index", 0)] = log("value", 1);
+        xs[log("index", 1)] += log("value", 2);
+    "#;
+    assert_eq!(order(source), ["index", "value", "index", "value"]);
+}
+
+#[test]
+fn test_nested_indexes_are_evaluated_left_to_right_before_the_value() {
+    let source = r#"
+        let grid = [[0, 0], [0, 0]];
+        grid[log("row", 1)][log("column", 0)] = log("value", 1);
+    "#;
+    assert_eq!(order(source), ["row", "column", "value"]);
+}
+
+#[test]
+fn test_the_receiver_is_evaluated_before_the_arguments() {
+    let source = r#"
+        impl Counter {
+            fn plus(self, n) { return self.count + n; }
+        }
+        log("receiver", Counter { count: 1 }).plus(log("argument", 2));
+    "#;
+    assert_eq!(order(source), ["receiver", "argument"]);
+}
+
+#[test]
+fn test_assignments_keep_what_the_index_and_value_change() {
+    // The variable assigned to is read once the index and value are
+    // evaluated, so their changes to it aren't overwritten
+    let source = r#"
+        let xs = [0, 0, 0];
+        fn index() { xs[2] = 9; return 0; }
+        fn value() { xs[1] = 8; return 7; }
+        xs[index()] = value();
+        xs;
+    "#;
+    let (mut engine, _) = engine();
+    let expected = [7, 8, 9].map(RuntimeValue::Integer).to_vec();
+    assert_eq!(engine.eval(source), Ok(RuntimeValue::Array(expected)));
+
+    let source = r#"
+        let ys = [1, 0];
+        fn bump() { ys[1] = 5; return 10; }
+        ys[0] += bump();
+        ys;
+    "#;
+    let expected = [11, 5].map(RuntimeValue::Integer).to_vec();
+    assert_eq!(engine.eval(source), Ok(RuntimeValue::Array(expected)));
+
+    let source = r#"
+        let pair = Pair { left: 0, right: 0 };
+        fn right() { pair.right = 2; return 1; }
+        pair.left = right();
+        [pair.left, pair.right];
+    "#;
+    let expected = [1, 2].map(RuntimeValue::Integer).to_vec();
+    assert_eq!(engine.eval(source), Ok(RuntimeValue::Array(expected)));
+}
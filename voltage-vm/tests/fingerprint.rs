@@ -0,0 +1,40 @@
+use voltage_parser::{Lexer, Parser};
+use voltage_vm::BytecodeCompiler;
+
+fn fingerprint(source: &str) -> u64 {
+    let statements = Parser::from_lexer(Lexer::new(source)).parse();
+    BytecodeCompiler::new().compile_program(&statements).unwrap().fingerprint()
+}
+
+const SOURCE: &str = "
+fn double(n) { return n * 2; }
+fn greet() { puts(\"hello\"); }
+let x = double(21);
+";
+
+#[test]
+fn test_identical_source_has_identical_fingerprint() {
+    assert_eq!(fingerprint(SOURCE), fingerprint(SOURCE));
+}
+
+#[test]
+fn test_changing_a_literal_changes_fingerprint() {
+    assert_ne!(fingerprint(SOURCE), fingerprint(&SOURCE.replace("21", "22")));
+    assert_ne!(fingerprint(SOURCE), fingerprint(&SOURCE.replace("hello", "hullo")));
+}
+
+#[test]
+fn test_reordering_functions_changes_fingerprint() {
+    let reordered = "
+fn greet() { puts(\"hello\"); }
+fn double(n) { return n * 2; }
+let x = double(21);
+";
+    assert_ne!(fingerprint(SOURCE), fingerprint(reordered));
+}
+
+#[test]
+fn test_source_positions_do_not_affect_fingerprint() {
+    // Only the compiled output counts, not where it came from
+    assert_eq!(fingerprint(SOURCE), fingerprint(&SOURCE.replace('\n', "\n\n")));
+}
@@ -0,0 +1,10 @@
+fn count(limit) {
+    let total = 0;
+    while total < 3 {
+        let step = total + 1;
+        puts(step);
+    }
+    return total;
+}
+
+count(3);
@@ -0,0 +1,12 @@
+fn divide(a, b) {
+    return a / b;
+}
+
+fn average(total, count) {
+    let result = divide(total, count);
+    return result;
+}
+
+fn report(total) {
+    return average(total, 0);
+}
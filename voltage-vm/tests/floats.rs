@@ -0,0 +1,82 @@
+use voltage_vm::{Engine, RuntimeValue};
+
+fn boolean(b: bool) -> RuntimeValue {
+    RuntimeValue::Boolean(b)
+}
+
+#[test]
+fn test_float_equality_is_exact() {
+    let mut engine = Engine::new();
+    assert_eq!(engine.eval("0.1 + 0.2 == 0.3;"), Ok(boolean(false)));
+    assert_eq!(engine.eval("0.5 + 0.25 == 0.75;"), Ok(boolean(true)));
+    assert_eq!(engine.eval("1.0 == 1.0000000000000002;"), Ok(boolean(false)));
+}
+
+#[test]
+fn test_nan_and_signed_zero() {
+    let nan = RuntimeValue::Float(f64::NAN);
+    assert_ne!(nan, nan.clone());
+    assert_eq!(RuntimeValue::Float(-0.0), RuntimeValue::Float(0.0));
+
+    let mut engine = Engine::new();
+    engine.register_fn("nan", || f64::NAN).unwrap();
+    assert_eq!(engine.eval("let x = nan(); x == x;"), Ok(boolean(false)));
+    assert_eq!(engine.eval("x != x;"), Ok(boolean(true)));
+    assert_eq!(engine.eval("0.0 - 0.0 == 0.0 * (0.0 - 1.0);"), Ok(boolean(true)));
+}
+
+#[test]
+fn test_approx_eq_allows_for_rounding() {
+    let mut engine = Engine::new();
+    assert_eq!(engine.eval("approx_eq(0.1 + 0.2, 0.3, 0.000000001);"), Ok(boolean(true)));
+    assert_eq!(engine.eval("approx_eq(1.0, 1.5, 0.1);"), Ok(boolean(false)));
+    // Ints are accepted as floats
+    assert_eq!(engine.eval("approx_eq(2, 2.05, 0.1);"), Ok(boolean(true)));
+    assert_eq!(
+        engine.eval("approx_eq(1.0, 1.0);").unwrap_err().to_string(),
+        "Compile error: approx_eq expects 3 arguments, got 2"
+    );
+}
+
+#[test]
+fn test_floats_print_distinctly_from_ints() {
+    let cases = [
+        (2.0, "2.0"),
+        (0.1, "0.1"),
+        (1e20, "1e20"),
+        (1.5e-7, "1.5e-7"),
+        (-0.0, "-0.0"),
+        (f64::NAN, "nan"),
+        (f64::INFINITY, "inf"),
+        (f64::NEG_INFINITY, "-inf"),
+    ];
+    for (x, expected) in cases {
+        assert_eq!(RuntimeValue::Float(x).to_string(), expected);
+    }
+    assert_eq!(RuntimeValue::Array(vec![RuntimeValue::Float(3.0)]).to_string(), "[3.0]");
+
+    let mut engine = Engine::new();
+    assert_eq!(
+        engine.eval(r#"format("{} and {}", 2.0, 2);"#),
+        Ok(RuntimeValue::String("2.0 and 2".to_string()))
+    );
+}
+
+#[test]
+fn test_dividing_floats_by_zero_follows_ieee() {
+    let mut engine = Engine::new();
+    assert_eq!(engine.eval("1.0 / 0.0;"), Ok(RuntimeValue::Float(f64::INFINITY)));
+    assert_eq!(engine.eval("(0.0 - 1.0) / 0.0;"), Ok(RuntimeValue::Float(f64::NEG_INFINITY)));
+    assert_eq!(engine.eval("is_nan(0.0 / 0.0);"), Ok(boolean(true)));
+    assert_eq!(engine.eval("is_nan(5.5 % 0.0);"), Ok(boolean(true)));
+    let checks = engine.eval("[is_inf(1.0 / 0.0), is_inf(1.0 / 2.0), is_nan(1), is_inf(0.0 / 0.0)];");
+    assert_eq!(checks, engine.eval("[true, false, false, false];"));
+    assert_eq!(
+        engine.eval(r#"format("{} {} {}", 1.0 / 0.0, (0.0 - 1.0) / 0.0, 0.0 % 0.0);"#),
+        Ok(RuntimeValue::String("inf -inf nan".to_string()))
+    );
+
+    // Only ints still can't be divided by zero
+    assert_eq!(engine.eval("1 / 0;").unwrap_err().to_string(), "Runtime error: Division by zero");
+    assert_eq!(engine.eval("1 % 0;").unwrap_err().to_string(), "Runtime error: Modulo by zero");
+}
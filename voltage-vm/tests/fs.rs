@@ -0,0 +1,55 @@
+use std::fs;
+use voltage_vm::{Engine, FsAccess, RuntimeValue, VoltageError};
+
+fn sandbox() -> String {
+    format!("{}/tests/fixtures/sandbox", env!("CARGO_MANIFEST_DIR"))
+}
+
+#[test]
+fn test_file_builtins_are_disabled_by_default() {
+    let mut engine = Engine::new();
+    for source in [r#"read_file("greeting.txt");"#, r#"write_file("x.txt", 1);"#, r#"file_exists("x");"#] {
+        assert_eq!(
+            engine.eval(source).unwrap_err().to_string(),
+            "Runtime error: file system access is disabled"
+        );
+    }
+}
+
+#[test]
+fn test_read_fixture_within_root() {
+    let mut engine = Engine::new();
+    engine.allow_fs(FsAccess::within(sandbox()).unwrap());
+    assert_eq!(
+        engine.eval(r#"read_file("greeting.txt");"#),
+        Ok(RuntimeValue::String("hello from a file\n".to_string()))
+    );
+    assert_eq!(engine.eval(r#"file_exists("greeting.txt");"#), Ok(RuntimeValue::Boolean(true)));
+    assert_eq!(engine.eval(r#"file_exists("nope.txt");"#), Ok(RuntimeValue::Boolean(false)));
+}
+
+#[test]
+fn test_escaping_the_root_is_rejected() {
+    let mut engine = Engine::new();
+    engine.allow_fs(FsAccess::within(sandbox()).unwrap());
+    let error = engine.eval(r#"read_file("../secret.txt");"#).unwrap_err();
+    assert!(
+        matches!(&error, VoltageError::Runtime(e) if e.message.contains("outside the allowed directory")),
+        "{}",
+        error
+    );
+    assert!(engine.eval(r#"file_exists("../secret.txt");"#).is_err());
+}
+
+#[test]
+fn test_write_then_read_back() {
+    let dir = std::env::temp_dir().join(format!("voltage_fs_write_{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+
+    let mut engine = Engine::new();
+    engine.allow_fs(FsAccess::within(&dir).unwrap());
+    assert_eq!(engine.eval(r#"write_file("out.txt", [1, 2]);"#), Ok(RuntimeValue::Null));
+    assert_eq!(engine.eval(r#"read_file("out.txt");"#), Ok(RuntimeValue::String("[1, 2]".to_string())));
+    assert_eq!(fs::read_to_string(dir.join("out.txt")).unwrap(), "[1, 2]");
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,86 @@
+use voltage_vm::{Engine, RuntimeErrorKind, RuntimeValue, VoltageError};
+
+fn eval(source: &str) -> Result<RuntimeValue, VoltageError> {
+    Engine::new().eval(source)
+}
+
+fn ints(values: &[i64]) -> RuntimeValue {
+    RuntimeValue::Array(values.iter().map(|&n| RuntimeValue::Integer(n)).collect())
+}
+
+fn runtime_error(result: Result<RuntimeValue, VoltageError>) -> voltage_vm::RuntimeError {
+    match result {
+        Err(VoltageError::Runtime(error)) => error,
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_map_with_a_named_function() {
+    assert_eq!(eval("fn double(x) { return x * 2; } map([1, 2, 3], double);"), Ok(ints(&[2, 4, 6])));
+    assert_eq!(eval(r#"fn show(x) { return "<${x}>"; } map([], show);"#), Ok(RuntimeValue::Array(vec![])));
+
+    // Calls from inside functions, and from inside the mapped function
+    let source = "
+        fn square(x) { return x * x; }
+        fn squares_of(rows) { return map(rows, square_all); }
+        fn square_all(row) { return map(row, square); }
+        squares_of([[1, 2], [3]]);
+    ";
+    assert_eq!(eval(source), Ok(RuntimeValue::Array(vec![ints(&[1, 4]), ints(&[9])])));
+}
+
+#[test]
+fn test_filter_with_a_threshold_from_outside() {
+    // Functions can't capture locals yet, but they see globals
+    let source = "
+        let threshold = 3;
+        fn above(x) { return x > threshold; }
+        filter([5, 1, 3, 4, 2], above);
+    ";
+    assert_eq!(eval(source), Ok(ints(&[5, 4])));
+}
+
+#[test]
+fn test_reduce_to_a_sum() {
+    assert_eq!(eval("fn add(acc, x) { return acc + x; } reduce([1, 2, 3, 4], 0, add);"), Ok(RuntimeValue::Integer(10)));
+    // Folds from the left, starting from the initial value
+    let source = r#"fn append(acc, x) { return format("({} {})", acc, x); } reduce([1, 2], "init", append);"#;
+    assert_eq!(eval(source), Ok(RuntimeValue::String("((init 1) 2)".to_string())));
+    assert_eq!(eval("fn add(acc, x) { return acc + x; } reduce([], 7, add);"), Ok(RuntimeValue::Integer(7)));
+}
+
+#[test]
+fn test_predicates_must_return_bools() {
+    let error = runtime_error(eval("fn parity(x) { return x % 2; } filter([1, 2], parity);"));
+    assert_eq!(error.message, "filter: predicate returned int, expected bool");
+
+    let error = runtime_error(eval("map([1], 2);"));
+    assert_eq!(error.message, "map: expected a function, got int");
+    let error = runtime_error(eval("fn pair(a, b) { return a; } map([1], pair);"));
+    assert_eq!(error.message, "Function pair expects 2 arguments, got 1");
+}
+
+#[test]
+fn test_errors_in_callbacks_unwind_through_the_builtin() {
+    // The trace shows the callback and the call to the builtin
+    let error = runtime_error(eval("fn invert(x) { return 1 / x; }\nlet xs = [1, 0];\nmap(xs, invert);"));
+    assert_eq!(error.message, "Division by zero");
+    assert_eq!(error.backtrace(), "  at invert (line 1)\n  at <top level> (line 3)\n");
+
+    // A `try` around the call catches it, and one inside the callback first
+    let source = "
+        fn invert(x) { return 1 / x; }
+        let result = 0;
+        try { let result = map([1, 0], invert); } catch err { let result = err; }
+        fn safe_invert(x) { try { return 1 / x; } catch _ { return 0; } }
+        [result, format(\"{}\", map([1, 0], safe_invert))];
+    ";
+    assert_eq!(eval(source), eval(r#"["Division by zero", "[1, 0]"];"#));
+
+    // The call depth limit counts the callback frames
+    let mut engine = Engine::new();
+    engine.set_max_call_depth(20);
+    let error = runtime_error(engine.eval("fn deeper(x) { return map([x], deeper); } deeper(1);"));
+    assert_eq!(error.kind, RuntimeErrorKind::Limit);
+}
@@ -0,0 +1,80 @@
+use voltage_vm::{Engine, RuntimeValue};
+
+fn engine() -> Engine {
+    let mut engine = Engine::new();
+    engine.register_fn("answer", || 42i64).unwrap();
+    engine.register_fn("negate", |b: bool| !b).unwrap();
+    engine.register_fn("hypot", |a: f64, b: f64| -> f64 { (a * a + b * b).sqrt() }).unwrap();
+    engine.register_fn("repeat", |s: String, n: i64| s.repeat(n as usize)).unwrap();
+    engine.register_fn("count", |items: Vec<RuntimeValue>| items.len() as i64).unwrap();
+    engine
+        .register_fn("sum6", |a: i64, b: i64, c: i64, d: i64, e: i64, f: i64| a + b + c + d + e + f)
+        .unwrap();
+    engine
+        .register_fn("checked_div", |a: i64, b: i64| -> Result<i64, String> {
+            a.checked_div(b).ok_or_else(|| "division by zero".to_string())
+        })
+        .unwrap();
+    engine.register_fn("ignore", |_value: RuntimeValue| {}).unwrap();
+    engine
+}
+
+#[test]
+fn test_typed_functions_of_several_arities() {
+    let mut engine = engine();
+    assert_eq!(engine.eval("answer();"), Ok(RuntimeValue::Integer(42)));
+    assert_eq!(engine.eval("negate(false);"), Ok(RuntimeValue::Boolean(true)));
+    assert_eq!(engine.eval("hypot(3, 4);"), Ok(RuntimeValue::Float(5.0)));
+    assert_eq!(engine.eval(r#"repeat("ab", 3);"#), Ok(RuntimeValue::String("ababab".to_string())));
+    assert_eq!(engine.eval("count([[1], [], [2, 3]]);"), Ok(RuntimeValue::Integer(3)));
+    assert_eq!(engine.eval("sum6(1, 2, 3, 4, 5, 6);"), Ok(RuntimeValue::Integer(21)));
+    assert_eq!(engine.eval("ignore(1);"), Ok(RuntimeValue::Null));
+}
+
+#[test]
+fn test_typed_functions_compose_with_script_functions() {
+    let mut engine = engine();
+    engine.load("fn diagonal(side) { return hypot(side, side); }").unwrap();
+    assert_eq!(
+        engine.call("diagonal", &[RuntimeValue::Float(1.0)]),
+        Ok(RuntimeValue::Float(2f64.sqrt()))
+    );
+}
+
+#[test]
+fn test_result_errors_become_runtime_errors() {
+    let mut engine = engine();
+    assert_eq!(engine.eval("checked_div(9, 3);"), Ok(RuntimeValue::Integer(3)));
+    assert_eq!(
+        engine.eval("checked_div(1, 0);").unwrap_err().to_string(),
+        "Runtime error: division by zero"
+    );
+}
+
+#[test]
+fn test_wrong_argument_type_names_the_parameter() {
+    let mut engine = engine();
+    assert_eq!(
+        engine.eval(r#"hypot(3, "four");"#).unwrap_err().to_string(),
+        "Runtime error: hypot: argument 2 expected float, found string"
+    );
+    assert_eq!(
+        engine.eval("repeat(1, 2);").unwrap_err().to_string(),
+        "Runtime error: repeat: argument 1 expected string, found int"
+    );
+}
+
+#[test]
+fn test_wrong_argument_count_is_reported() {
+    let mut engine = engine();
+    assert_eq!(
+        engine.eval("hypot(3);").unwrap_err().to_string(),
+        "Compile error: hypot expects 2 arguments, got 1"
+    );
+    // Functions have to be registered before the code calling them is loaded
+    let mut engine = Engine::new();
+    assert_eq!(
+        engine.load("fn call_hypot() { return hypot(3); }").unwrap_err().to_string(),
+        "Compile error: Unknown function: hypot"
+    );
+}
@@ -0,0 +1,99 @@
+use voltage_vm::{Engine, RuntimeValue};
+
+fn string(s: &str) -> RuntimeValue {
+    RuntimeValue::String(s.to_string())
+}
+
+#[test]
+fn test_ascii_string_index_is_one_character_string() {
+    let mut engine = Engine::new();
+    assert_eq!(engine.eval(r#"let s = "abc"; s[0];"#), Ok(string("a")));
+    assert_eq!(engine.eval("s[2];"), Ok(string("c")));
+}
+
+#[test]
+fn test_strings_are_indexed_by_character_not_byte() {
+    let mut engine = Engine::new();
+    // 'é' and '€' take two and three bytes
+    assert_eq!(engine.eval(r#"let s = "né€x"; s[1];"#), Ok(string("é")));
+    assert_eq!(engine.eval("s[2];"), Ok(string("€")));
+    assert_eq!(engine.eval("s[3];"), Ok(string("x")));
+}
+
+#[test]
+fn test_out_of_bounds_index_is_an_error() {
+    let mut engine = Engine::new();
+    assert_eq!(
+        engine.eval(r#""né€"[3];"#).unwrap_err().to_string(),
+        "Runtime error: Index 3 is out of bounds for length 3"
+    );
+    assert_eq!(
+        engine.eval("[1, 2][0 - 1];").unwrap_err().to_string(),
+        "Runtime error: Index -1 is out of bounds for length 2"
+    );
+    assert_eq!(
+        engine.eval(r#""abc"["a"];"#).unwrap_err().to_string(),
+        "Runtime error: Index must be an int, got string"
+    );
+}
+
+#[test]
+fn test_assigning_into_a_string_is_an_error() {
+    let mut engine = Engine::new();
+    assert_eq!(
+        engine.eval(r#"let s = "abc"; s[0] = "z";"#).unwrap_err().to_string(),
+        "Runtime error: Cannot assign to an index of a string: strings are immutable"
+    );
+    assert_eq!(engine.eval("s;"), Ok(string("abc")));
+}
+
+#[test]
+fn test_array_elements_can_be_read_and_replaced() {
+    let mut engine = Engine::new();
+    engine.load("fn bump(values) { values[1] = values[1] + 10; return values; }").unwrap();
+    assert_eq!(
+        engine.eval("let a = [1, 2, 3]; a[0] = 7; a;"),
+        Ok(RuntimeValue::Array(vec![RuntimeValue::Integer(7), RuntimeValue::Integer(2), RuntimeValue::Integer(3)]))
+    );
+    assert_eq!(
+        engine.eval("bump(a);"),
+        Ok(RuntimeValue::Array(vec![RuntimeValue::Integer(7), RuntimeValue::Integer(12), RuntimeValue::Integer(3)]))
+    );
+    // The caller's array is a separate value
+    assert_eq!(engine.eval("a[1];"), Ok(RuntimeValue::Integer(2)));
+}
+
+#[test]
+fn test_compound_assignment_evaluates_the_index_once() {
+    let mut engine = Engine::new();
+    engine.load("let calls = [0]; fn second() { calls[0] += 1; return 1; }").unwrap();
+    assert_eq!(
+        engine.eval("let a = [5, 6]; a[second()] *= 7; a[0] -= 1; a;"),
+        Ok(RuntimeValue::Array(vec![RuntimeValue::Integer(4), RuntimeValue::Integer(42)]))
+    );
+    assert_eq!(engine.eval("calls[0];"), Ok(RuntimeValue::Integer(1)));
+    assert_eq!(
+        engine.eval("[1][0] += 1;").unwrap_err().to_string(),
+        "Compile error: Compound assignment needs an array variable"
+    );
+}
+
+#[test]
+fn test_storing_an_array_in_itself_stores_a_copy() {
+    let mut engine = Engine::new();
+    engine.eval("let a = [1, 2]; a[0] = a;").unwrap();
+    assert_eq!(engine.eval("a;").unwrap().to_string(), "[[1, 2], 2]");
+    engine.eval("a[1] = 3;").unwrap();
+    assert_eq!(engine.eval("a[0];").unwrap().to_string(), "[1, 2]");
+}
+
+#[test]
+fn test_fixed_size_arrays() {
+    let mut engine = Engine::new();
+    assert_eq!(engine.eval("let xs: [int; 3] = [1, 2, 3]; xs[2];"), Ok(RuntimeValue::Integer(3)));
+    assert_eq!(engine.eval("let empty: [int; 0] = []; len(empty);"), Ok(RuntimeValue::Integer(0)));
+    assert_eq!(
+        engine.eval("let ys: [int; 2] = [1, 2, 3];").unwrap_err().to_string(),
+        "Compile error: `ys` is declared as [int; 2], but its value has 3 elements"
+    );
+}
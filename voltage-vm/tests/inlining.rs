@@ -0,0 +1,100 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use voltage_vm::{
+    inline_functions, unused_constants, Bytecode, Engine, OutputEvent, Program, RuntimeValue, VoltageError, INLINE_LIMIT,
+};
+
+// What running `program` printed, and the error it ended with as it would
+// be reported
+fn run(program: Program) -> (Vec<OutputEvent>, Option<String>) {
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let recorded = Rc::clone(&events);
+    let mut engine = Engine::new();
+    engine.set_output_handler(move |event| recorded.borrow_mut().push(event));
+    let error = engine.load_program(program).err().map(|error| match &error {
+        VoltageError::Runtime(e) => format!("{}\n{}", error, e.backtrace()),
+        _ => error.to_string(),
+    });
+    let events = events.borrow().clone();
+    (events, error)
+}
+
+fn calls(program: &Program) -> usize {
+    program.bytecode.iter().filter(|op| matches!(op, Bytecode::Call(_) | Bytecode::TailCall(_))).count()
+}
+
+#[test]
+fn test_inlined_programs_behave_the_same() {
+    let sources = [
+        // Arguments in order, among the caller's locals and temporaries
+        "fn sub(a, b) { return a - b; }\n\
+         fn twice(f) { let y = 3; return y + sub(f, y) * sub(y, f) + y; }\n\
+         puts(twice(10));",
+        // Accessors, a function without parameters and calls inside calls
+        "fn x_of(p) { return p.x; }\n\
+         fn origin() { return Point { x: 0, y: 0 }; }\n\
+         fn shifted(p, dx) { return Point { x: x_of(p) + dx, y: p.y }; }\n\
+         puts(x_of(shifted(shifted(origin(), 2), 3)));",
+        // Calls in loops and branches, which jump around the inlined code
+        "fn is_even(n) { return n % 2 == 0; }\n\
+         fn half(n) { return n / 2; }\n\
+         let total = [0];\n\
+         for i in 0..10 { if is_even(i) { total[0] += half(i); } else { total[0] += 1; } }\n\
+         puts(total[0]);",
+        // Functions are still values
+        "fn double(n) { return n * 2; }\n\
+         let f = double;\n\
+         puts([f(4), double(5)]);",
+        // Errors from inlined code are caught as before
+        "fn invert(n) { return 1 / n; }\n\
+         try { puts(invert(0)); } catch e { puts(\"caught {}\", e); }\n\
+         puts(invert(0));",
+    ];
+    for source in sources {
+        let program = voltage_vm::compile(source).unwrap();
+        let inlined = inline_functions(program.clone(), INLINE_LIMIT);
+        assert!(calls(&inlined) < calls(&program), "nothing inlined in {}", source);
+        assert_eq!(run(inlined), run(program), "{}", source);
+    }
+}
+
+#[test]
+fn test_names_of_inlined_calls_leave_the_constant_pool() {
+    let source = "fn double(n) { return n * 2; }\nfn main() { let x = \"kept\"; puts(double(21)); puts(x); }";
+    let program = voltage_vm::compile(source).unwrap();
+    let inlined = inline_functions(program.clone(), INLINE_LIMIT);
+    assert_eq!(calls(&inlined), 0);
+    assert!(unused_constants(&inlined.bytecode, &inlined.constants).is_empty());
+    assert_eq!(inlined.constants.len(), program.constants.len() - 1);
+    assert!(!inlined.constants.contains(&RuntimeValue::String("double".to_string())));
+
+    let mut engine = Engine::new();
+    engine.load_program(inlined).unwrap();
+    assert_eq!(engine.call("main", &[]), Ok(RuntimeValue::Null));
+}
+
+#[test]
+fn test_recursive_and_larger_functions_are_not_inlined() {
+    let source = "fn forever(n) { return forever(n); }\n\
+                  fn fact(n) { if n < 2 { return 1; } return n * fact(n - 1); }\n\
+                  fn sum(a, b, c, d) { return a + b + c + d; }\n\
+                  let x = fact(5) + sum(1, 2, 3, 4);";
+    let program = voltage_vm::compile(source).unwrap();
+    assert_eq!(inline_functions(program.clone(), INLINE_LIMIT).bytecode, program.bytecode);
+    // `sum` is eight instructions with its return
+    assert!(calls(&inline_functions(program.clone(), 9)) < calls(&program));
+}
+
+#[test]
+fn test_errors_in_inlined_code_keep_their_trace() {
+    let program = voltage_vm::compile(include_str!("fixtures/trace.v")).unwrap();
+    let mut engine = Engine::new();
+    engine.load_program(inline_functions(program, INLINE_LIMIT)).unwrap();
+
+    let Err(VoltageError::Runtime(error)) = engine.call("report", &[RuntimeValue::Integer(10)]) else {
+        panic!("expected a runtime error");
+    };
+    assert_eq!(error.backtrace(), "  at divide (line 2)\n  at average (line 6)\n  at report (line 11)\n");
+    let inlined: Vec<bool> = error.trace.iter().map(|frame| frame.inlined).collect();
+    assert_eq!(inlined, [true, false, false]);
+}
@@ -0,0 +1,54 @@
+use voltage_vm::{Engine, RuntimeValue, VoltageError};
+
+fn eval(source: &str) -> Result<RuntimeValue, VoltageError> {
+    Engine::new().eval(source)
+}
+
+fn string(value: &str) -> Result<RuntimeValue, VoltageError> {
+    Ok(RuntimeValue::String(value.to_string()))
+}
+
+#[test]
+fn test_interpolating_expressions() {
+    assert_eq!(eval(r#"let x = 6; "x = ${x}, twice is ${x * 2 + 0}";"#), string("x = 6, twice is 12"));
+    // Braces outside the interpolations are plain text
+    assert_eq!(eval(r#"let xs = [1, 2]; "{xs} ${xs} {}";"#), string("{xs} [1, 2] {}"));
+}
+
+#[test]
+fn test_interpolated_strings_are_ordinary_values() {
+    let source = r#"
+        fn greet(name) { return "hello, ${name}"; }
+        let names = ["ada", "bob"];
+        let greetings = [greet(names[0]), "${greet(names[1])}!"];
+        len("${greetings[0]}") + len(greetings[1]);
+    "#;
+    assert_eq!(eval(source), Ok(RuntimeValue::Integer(21)));
+}
+
+#[test]
+fn test_escaped_interpolation_is_literal() {
+    assert_eq!(eval(r#"let price = 3; "\${price} is ${price}";"#), string("${price} is 3"));
+}
+
+#[test]
+fn test_escapes_around_interpolations() {
+    assert_eq!(eval(r#"let n = 1; "\u{2192} ${n}\n\"${n + 1}\"";"#), string("\u{2192} 1\n\"2\""));
+    let error = eval(r#"let n = 1; "${n} \u{110000}";"#).unwrap_err();
+    assert!(error.to_string().contains("the last Unicode code point is `\\u{10FFFF}`"), "{}", error);
+}
+
+#[test]
+fn test_string_literals_inside_interpolations() {
+    let source = r#"let n = 2; "got ${format("{} item{}", n, "s")}, last: ${"}"}";"#;
+    assert_eq!(eval(source), string("got 2 items, last: }"));
+    assert_eq!(eval(r#""${"nested ${1 + 1}"}";"#), string("nested 2"));
+}
+
+#[test]
+fn test_errors_in_interpolations() {
+    let error = eval(r#"let x = 1; "${x +}";"#).unwrap_err();
+    assert!(matches!(error, VoltageError::Parse(_)), "{}", error);
+    let error = eval(r#""${missing}";"#).unwrap_err();
+    assert!(matches!(error, VoltageError::Runtime(_)), "{}", error);
+}
@@ -0,0 +1,31 @@
+#![cfg(feature = "json")]
+
+use voltage_vm::{Engine, RuntimeValue, VoltageError};
+
+#[test]
+fn test_json_builtins_round_trip_through_scripts() {
+    let mut engine = Engine::new();
+    assert_eq!(
+        engine.eval(r#"parse_json("[1, [true, null], 2.5]");"#),
+        Ok(RuntimeValue::Array(vec![
+            RuntimeValue::Integer(1),
+            RuntimeValue::Array(vec![RuntimeValue::Boolean(true), RuntimeValue::Null]),
+            RuntimeValue::Float(2.5),
+        ]))
+    );
+    assert_eq!(
+        engine.eval("to_json_string([[1], [2, 3], []]);"),
+        Ok(RuntimeValue::String("[[1],[2,3],[]]".to_string()))
+    );
+}
+
+#[test]
+fn test_json_builtin_errors_are_runtime_errors() {
+    let mut engine = Engine::new();
+    engine.load("fn f() { return 1; }").unwrap();
+    assert_eq!(
+        engine.eval("to_json_string(f);").unwrap_err().to_string(),
+        "Runtime error: cannot convert function f to JSON"
+    );
+    assert!(matches!(engine.eval(r#"parse_json("[1,");"#), Err(VoltageError::Runtime(_))));
+}
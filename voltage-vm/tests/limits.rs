@@ -0,0 +1,98 @@
+use voltage_vm::{Engine, RuntimeValue};
+
+// Each function calls the next, five calls deep
+const CHAIN: &str = "
+fn f1(n) { return f2(n + 1); }
+fn f2(n) { return f3(n + 1); }
+fn f3(n) { return f4(n + 1); }
+fn f4(n) { return f5(n + 1); }
+fn f5(n) { return n; }
+";
+
+#[test]
+fn test_unbounded_recursion_is_a_runtime_error() {
+    let mut engine = Engine::new();
+    // Not a tail call, which would reuse its frame and never hit the limit
+    engine.load("fn forever(n) { return 1 + forever(n + 1); }").unwrap();
+    let error = engine.call("forever", &[RuntimeValue::Integer(0)]).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Runtime error: maximum recursion depth of 10000 exceeded in function `forever` \
+         (most recent calls: forever <- forever <- forever)"
+    );
+    // The engine is still usable afterwards
+    assert_eq!(engine.eval("1 + 1;"), Ok(RuntimeValue::Integer(2)));
+}
+
+#[test]
+fn test_calls_within_the_limit_succeed() {
+    let mut engine = Engine::new();
+    engine.set_max_call_depth(4);
+    engine.load(CHAIN).unwrap();
+    // f1 runs as the entry point, so f2..f5 are the four nested calls
+    assert_eq!(engine.call("f1", &[RuntimeValue::Integer(0)]), Ok(RuntimeValue::Integer(4)));
+
+    engine.set_max_call_depth(3);
+    let error = engine.call("f1", &[RuntimeValue::Integer(0)]).unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "Runtime error: maximum recursion depth of 3 exceeded in function `f5` (most recent calls: f4 <- f3 <- f2)"
+    );
+}
+
+#[test]
+fn test_value_stack_limit() {
+    let mut engine = Engine::new();
+    engine.set_max_stack_size(3);
+    assert_eq!(engine.eval("1 + 2;"), Ok(RuntimeValue::Integer(3)));
+    assert_eq!(
+        engine.eval("[1, 2, 3, 4];").unwrap_err().to_string(),
+        "Runtime error: maximum stack size of 3 values exceeded"
+    );
+}
+
+#[test]
+fn test_fuel_limits_how_many_instructions_run() {
+    let mut engine = Engine::new();
+    engine.set_fuel(1000);
+    let error = engine.eval("let i = 0; while true { let i = i + 1; }").unwrap_err();
+    assert_eq!(error.to_string(), "Runtime error: out of fuel after running 1000 instructions");
+    // Fuel isn't topped up between runs
+    assert!(engine.eval("1 + 1;").is_err());
+    engine.set_fuel(1000);
+    assert_eq!(engine.eval("1 + 1;"), Ok(RuntimeValue::Integer(2)));
+}
+
+#[test]
+fn test_fuel_stops_a_loop_without_a_break() {
+    let mut engine = Engine::new();
+    engine.set_fuel(1000);
+    engine.load("fn main() { loop { } }").unwrap();
+    let error = engine.call("main", &[]).unwrap_err();
+    assert_eq!(error.to_string(), "Runtime error: out of fuel after running 1000 instructions");
+}
+
+#[test]
+fn test_tail_calls_to_the_same_function_reuse_the_frame() {
+    let mut engine = Engine::new();
+    engine.set_max_call_depth(10);
+    engine.load("fn countdown(n) { if n == 0 { return \"done\"; } return countdown(n - 1); }").unwrap();
+    assert_eq!(
+        engine.call("countdown", &[RuntimeValue::Integer(1_000_000)]),
+        Ok(RuntimeValue::String("done".to_string()))
+    );
+    assert_eq!(engine.eval("countdown(1000000);"), Ok(RuntimeValue::String("done".to_string())));
+
+    engine.load("fn count(n) { if n == 0 { return 0; } return 1 + count(n - 1); }").unwrap();
+    let error = engine.call("count", &[RuntimeValue::Integer(1_000_000)]).unwrap_err();
+    assert!(error.to_string().contains("maximum recursion depth of 10 exceeded in function `count`"));
+}
+
+#[test]
+fn test_tail_calls_to_other_functions_still_nest() {
+    let mut engine = Engine::new();
+    engine.set_max_call_depth(10);
+    engine.load("fn ping(n) { if n == 0 { return 0; } return pong(n - 1); } fn pong(n) { return ping(n); }").unwrap();
+    assert_eq!(engine.call("ping", &[RuntimeValue::Integer(5)]), Ok(RuntimeValue::Integer(0)));
+    assert!(engine.call("ping", &[RuntimeValue::Integer(20)]).is_err());
+}
@@ -0,0 +1,66 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use voltage_vm::{Engine, FsAccess, OutputEvent, RuntimeValue};
+
+fn sandbox() -> String {
+    format!("{}/tests/fixtures/sandbox", env!("CARGO_MANIFEST_DIR"))
+}
+
+fn strings(values: &[&str]) -> RuntimeValue {
+    RuntimeValue::Array(values.iter().map(|s| RuntimeValue::String(s.to_string())).collect())
+}
+
+// `lines` of a string from Rust, since script literals don't unescape `\r`
+fn lines(text: &str) -> RuntimeValue {
+    let mut engine = Engine::new();
+    engine.load("fn split_lines(s) { return lines(s); }").unwrap();
+    engine.call("split_lines", &[RuntimeValue::String(text.to_string())]).unwrap()
+}
+
+#[test]
+fn test_trailing_newline_does_not_add_a_line() {
+    assert_eq!(lines("one\ntwo\n"), strings(&["one", "two"]));
+    assert_eq!(lines("one\ntwo"), strings(&["one", "two"]));
+    // Blank lines in the middle are kept
+    assert_eq!(lines("one\n\ntwo\n\n"), strings(&["one", "", "two", ""]));
+}
+
+#[test]
+fn test_crlf_line_endings_are_stripped() {
+    assert_eq!(lines("one\r\ntwo\r\n"), strings(&["one", "two"]));
+
+    let mut engine = Engine::new();
+    engine.allow_fs(FsAccess::within(sandbox()).unwrap());
+    assert_eq!(engine.eval(r#"lines(read_file("crlf.txt"));"#), Ok(strings(&["alpha", "beta", "", "gamma"])));
+}
+
+#[test]
+fn test_empty_input_has_no_lines() {
+    assert_eq!(lines(""), strings(&[]));
+
+    let mut engine = Engine::new();
+    engine.allow_fs(FsAccess::within(sandbox()).unwrap());
+    assert_eq!(engine.eval(r#"len(lines(read_file("empty.txt")));"#), Ok(RuntimeValue::Integer(0)));
+    assert!(engine.eval("lines(1);").is_err());
+}
+
+#[test]
+fn test_numbering_the_lines_of_a_file() {
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let recorded = Rc::clone(&events);
+    let mut engine = Engine::new();
+    engine.set_output_handler(move |event| recorded.borrow_mut().push(event));
+    engine.allow_fs(FsAccess::within(sandbox()).unwrap());
+
+    let source = r#"
+        let n = 0;
+        for line in lines(read_file("crlf.txt")) {
+            let n = n + 1;
+            puts("{}: {}", n, line);
+        }
+    "#;
+    engine.eval(source).unwrap();
+    let printed: Vec<OutputEvent> =
+        ["1: alpha", "2: beta", "3: ", "4: gamma"].iter().map(|line| OutputEvent::PutsLine(line.to_string())).collect();
+    assert_eq!(*events.borrow(), printed);
+}
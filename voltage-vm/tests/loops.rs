@@ -0,0 +1,135 @@
+use voltage_vm::{
+    Bytecode, Engine, FunctionEntry, Program, RuntimeValue, SourceMap, ValidationError, VirtualMachine, VoltageError,
+};
+
+#[test]
+fn test_loop_gives_the_value_it_breaks_with() {
+    let mut engine = Engine::new();
+    // Retries until an attempt succeeds, and gives back what it computed
+    engine
+        .load(
+            "fn first_square_above(limit) {
+                let attempt = [0];
+                let found = loop {
+                    attempt[0] += 1;
+                    let square = attempt[0] * attempt[0];
+                    if square <= limit {
+                        continue;
+                    }
+                    break square;
+                };
+                return found;
+            }",
+        )
+        .unwrap();
+    assert_eq!(engine.call("first_square_above", &[RuntimeValue::Integer(50)]), Ok(RuntimeValue::Integer(64)));
+
+    // At the top level too, and `break;` gives null
+    assert_eq!(engine.eval("let n = 0; let done = loop { let n = n + 1; if n == 3 { break n * 10; } }; done;"), Ok(RuntimeValue::Integer(30)));
+    assert_eq!(engine.eval("let nothing = loop { break; }; nothing;"), Ok(RuntimeValue::Null));
+}
+
+#[test]
+fn test_break_leaves_the_innermost_loop() {
+    let mut engine = Engine::new();
+    let source = "
+        let outer = 0;
+        let inner_total = 0;
+        loop {
+            let outer = outer + 1;
+            let inner = loop {
+                break outer * 2;
+            };
+            let inner_total = inner_total + inner;
+            for i in 0..10 {
+                if i == 1 {
+                    break;
+                }
+            }
+            if outer == 3 {
+                break;
+            }
+        }
+        inner_total;";
+    assert_eq!(engine.eval(source), Ok(RuntimeValue::Integer(12)));
+}
+
+#[test]
+fn test_break_with_a_value_only_leaves_a_loop() {
+    let mut engine = Engine::new();
+    let error = engine.eval("while true { break 1; }").unwrap_err();
+    assert!(matches!(&error, VoltageError::Compile(e) if e.message == "`break` with a value can only leave a `loop`"), "{}", error);
+    assert!(engine.eval("break;").is_err());
+}
+
+#[test]
+fn test_return_leaves_every_loop_it_is_in() {
+    let mut engine = Engine::new();
+    engine
+        .load(
+            "fn find(grid, target) {
+                for row in grid {
+                    for x in row {
+                        if x == target {
+                            return x * 10;
+                        }
+                    }
+                }
+                return -1;
+            }
+            fn first_late(xs) {
+                let i = [0];
+                while i[0] < len(xs) {
+                    let step = loop {
+                        if xs[i[0]] > 2 || never() {
+                            return xs[i[0]];
+                        }
+                        break 1;
+                    };
+                    i[0] += step;
+                }
+                return 0;
+            }
+            fn never() { return false; }",
+        )
+        .unwrap();
+    let grid = RuntimeValue::Array(vec![
+        RuntimeValue::Array(vec![RuntimeValue::Integer(1), RuntimeValue::Integer(2)]),
+        RuntimeValue::Array(vec![RuntimeValue::Integer(3), RuntimeValue::Integer(4)]),
+    ]);
+    assert_eq!(engine.call("find", &[grid.clone(), RuntimeValue::Integer(3)]), Ok(RuntimeValue::Integer(30)));
+    assert_eq!(engine.call("find", &[grid, RuntimeValue::Integer(5)]), Ok(RuntimeValue::Integer(-1)));
+    let xs = RuntimeValue::Array(vec![RuntimeValue::Integer(1), RuntimeValue::Integer(5)]);
+    assert_eq!(engine.call("first_late", &[xs]), Ok(RuntimeValue::Integer(5)));
+
+    // The caller's own values on the stack are still there afterwards
+    assert_eq!(engine.eval("1 + find([[7]], 7) * 2;"), Ok(RuntimeValue::Integer(141)));
+}
+
+#[test]
+fn test_validator_rejects_a_return_that_leaves_values_behind() {
+    // `fn f() { if a || b { return 1; } ... }` with the copy of the
+    // condition that `||` keeps still on the stack when it returns
+    let program = Program {
+        bytecode: vec![
+            Bytecode::Jump(9),
+            Bytecode::LoadConst(0),
+            Bytecode::Dup,
+            Bytecode::JumpIfFalse(6),
+            Bytecode::LoadConst(1),
+            Bytecode::Return,
+            Bytecode::Pop,
+            Bytecode::LoadConst(1),
+            Bytecode::Return,
+            Bytecode::LoadConst(1),
+            Bytecode::Return,
+        ],
+        constants: vec![RuntimeValue::Boolean(true), RuntimeValue::Integer(1)],
+        functions: vec![FunctionEntry { name: "f".to_string(), start: 1, end: 9, num_params: 0 }],
+        source_map: SourceMap::new(),
+    };
+    assert!(matches!(
+        VirtualMachine::new().load_program(program),
+        Err(ValidationError::UnbalancedStack { instruction: 5, depth: 2, .. })
+    ));
+}
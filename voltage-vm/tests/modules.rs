@@ -0,0 +1,228 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use voltage_vm::{CompileErrorKind, Engine, RuntimeValue, VoltageError};
+
+fn eval(source: &str) -> Result<RuntimeValue, VoltageError> {
+    Engine::new().eval(source)
+}
+
+fn compile_error(engine: &mut Engine, source: &str) -> (CompileErrorKind, String) {
+    match engine.eval(source) {
+        Err(VoltageError::Compile(error)) => (error.kind, error.message),
+        other => panic!("expected a compile error, got {:?}", other),
+    }
+}
+
+// A fresh directory in the temp dir with `files` in it, by path
+fn module_dir(name: &str, files: &[(&str, &str)]) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("voltage_modules_{}_{}", name, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    for (file, source) in files {
+        let path = dir.join(file);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, source).unwrap();
+    }
+    dir
+}
+
+#[test]
+fn test_import_by_the_last_part_of_the_path() {
+    assert_eq!(eval("import std.math; math.sqrt(16.0);"), Ok(RuntimeValue::Float(4.0)));
+    assert_eq!(eval("import std.math; math.max(3, 7) - math.abs(-2);"), Ok(RuntimeValue::Integer(5)));
+    assert_eq!(eval("import std.math; math.pi > 3.14;"), Ok(RuntimeValue::Boolean(true)));
+    assert_eq!(
+        eval("import std.string; string.upper(string.trim(\"  volt \"));"),
+        Ok(RuntimeValue::String("VOLT".to_string()))
+    );
+
+    // Functions compiled before the top-level code can use imports too
+    let source = "fn hypot(a, b) { return math.sqrt(a * a + b * b); } import std.math; hypot(3, 4);";
+    assert_eq!(eval(source), Ok(RuntimeValue::Float(5.0)));
+}
+
+#[test]
+fn test_import_with_an_alias() {
+    assert_eq!(eval("import std.math as m; m.floor(2.7);"), Ok(RuntimeValue::Float(2.0)));
+    let source = "import std.string as s; s.split(\"a,b\", \",\");";
+    assert_eq!(
+        eval(source),
+        Ok(RuntimeValue::Array(vec![RuntimeValue::String("a".to_string()), RuntimeValue::String("b".to_string())]))
+    );
+
+    // Only the alias names the module
+    assert!(eval("import std.math as m; math.sqrt(2.0);").is_err());
+}
+
+#[test]
+fn test_module_functions_are_not_globals() {
+    assert!(eval("sqrt(2.0);").is_err());
+    let (kind, _) = compile_error(&mut Engine::new(), "import std.math; let f = math.sqrt;");
+    assert_eq!(kind, CompileErrorKind::BuiltinAsValue);
+}
+
+#[test]
+fn test_unknown_members_list_the_module() {
+    let (kind, message) = compile_error(&mut Engine::new(), "import std.math; math.sqrtt(2.0);");
+    assert_eq!(kind, CompileErrorKind::UnknownModuleMember);
+    assert_eq!(
+        message,
+        "module `std.math` has no member `sqrtt`; its members are abs, ceil, cos, e, floor, max, min, pi, sin, sqrt"
+    );
+
+    let (kind, message) = compile_error(&mut Engine::new(), "import std.maths;");
+    assert_eq!(kind, CompileErrorKind::UnknownModule);
+    assert_eq!(message, "unknown module `std.maths`; the modules are std.io, std.math, std.string");
+
+    let (_, message) = compile_error(&mut Engine::new(), "import std.math; math.sqrt(1.0, 2.0);");
+    assert_eq!(message, "math.sqrt expects 1 argument, got 2");
+}
+
+#[test]
+fn test_embedders_can_disable_io() {
+    let mut engine = Engine::new();
+    engine.disable_std_io();
+    let (kind, message) = compile_error(&mut engine, "import std.io; io.read_file(\"secrets.txt\");");
+    assert_eq!(kind, CompileErrorKind::UnknownModuleMember);
+    assert_eq!(message, "module `std.io` has no member `read_file`; it has no members");
+
+    // The rest of the standard library still works
+    assert_eq!(engine.eval("import std.math; math.min(2, 1);"), Ok(RuntimeValue::Integer(1)));
+}
+
+#[test]
+fn test_module_files_are_imported_from_the_module_paths() {
+    let dir = module_dir("paths", &[
+        ("shapes.v", "import std.math;\npub let unit = 1;\npub fn area(w, h) { return w * h; }\npub fn diagonal(w, h) { return math.sqrt(w * w + h * h); }"),
+        ("geo/points.v", "import shapes;\npub fn unit_square() { return shapes.area(shapes.unit, shapes.unit); }"),
+    ]);
+    let mut engine = Engine::new();
+    engine.add_module_path(dir.clone());
+    assert_eq!(engine.eval("import shapes; shapes.area(2, 3) + shapes.unit;"), Ok(RuntimeValue::Integer(7)));
+    assert_eq!(engine.eval("import shapes as s; let f = s.diagonal; f(3.0, 4.0);"), Ok(RuntimeValue::Float(5.0)));
+    assert_eq!(engine.eval("import geo.points; points.unit_square();"), Ok(RuntimeValue::Integer(1)));
+
+    // Imported functions stay loaded
+    assert_eq!(engine.call("shapes::area", &[RuntimeValue::Integer(4), RuntimeValue::Integer(5)]), Ok(RuntimeValue::Integer(20)));
+
+    let (kind, message) = compile_error(&mut Engine::new(), "import shapes;");
+    assert_eq!(kind, CompileErrorKind::UnknownModule);
+    assert_eq!(message, "unknown module `shapes`; the modules are std.io, std.math, std.string");
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_module_paths_are_searched_in_order() {
+    let dirs = ["entry", "first", "second", "env"].map(|name| {
+        module_dir(&format!("order_{}", name), &[("which.v", &format!("pub let name = \"{}\";", name))])
+    });
+    let [entry, first, second, env] = &dirs;
+    let which = |engine: &mut Engine| engine.eval("import which; which.name;").unwrap().to_string();
+
+    // However they're given, the entry directory comes first and
+    // `VOLTAGE_PATH` last
+    let mut engine = Engine::new();
+    let voltage_path = std::env::join_paths([env, first]).unwrap();
+    engine.modules_mut().set_voltage_path(&voltage_path);
+    engine.add_module_path(first.clone());
+    engine.add_module_path(second.clone());
+    engine.modules_mut().set_entry_dir(entry);
+    let searched: Vec<&Path> = engine.modules().search_paths().collect();
+    assert_eq!(searched, [entry, first, second, env, first].map(PathBuf::as_path));
+    assert_eq!(which(&mut engine), "entry");
+
+    let mut engine = Engine::new();
+    engine.add_module_path(second.clone());
+    engine.add_module_path(first.clone());
+    assert_eq!(which(&mut engine), "second");
+
+    let mut engine = Engine::new();
+    engine.modules_mut().set_voltage_path(&voltage_path);
+    assert_eq!(which(&mut engine), "env");
+    dirs.iter().for_each(|dir| fs::remove_dir_all(dir).unwrap());
+}
+
+#[test]
+fn test_a_module_in_two_paths_warns_and_uses_the_first() {
+    let first = module_dir("twice_first", &[("twice.v", "pub let n = 1;")]);
+    let second = module_dir("twice_second", &[("twice.v", "pub let n = 2;")]);
+    let mut engine = Engine::new();
+    engine.add_module_path(first.clone());
+    engine.add_module_path(second.clone());
+    // Searching a directory twice doesn't find the module twice
+    engine.add_module_path(first.clone());
+    assert_eq!(engine.eval("import twice; twice.n;"), Ok(RuntimeValue::Integer(1)));
+
+    let warnings = engine.warnings();
+    assert_eq!(warnings.len(), 1);
+    assert_eq!(warnings[0].code, Some("duplicate_module"));
+    assert_eq!(
+        warnings[0].message,
+        format!("module `twice` is also in {}; using {}", second.join("twice.v").display(), first.join("twice.v").display())
+    );
+    fs::remove_dir_all(&first).unwrap();
+    fs::remove_dir_all(&second).unwrap();
+}
+
+#[test]
+fn test_unknown_modules_list_every_directory_searched() {
+    let entry = module_dir("missing_entry", &[]);
+    let other = module_dir("missing_other", &[("broken.v", "fn f( {")]);
+    let mut engine = Engine::new();
+    engine.modules_mut().set_entry_dir(&entry);
+    engine.add_module_path(other.clone());
+    engine.modules_mut().set_voltage_path("/no/such/dir".as_ref());
+
+    let (kind, message) = compile_error(&mut engine, "import util.strings;");
+    assert_eq!(kind, CompileErrorKind::UnknownModule);
+    let file = PathBuf::from("util").join("strings.v");
+    assert_eq!(
+        message,
+        format!(
+            "unknown module `util.strings`; the modules are std.io, std.math, std.string, and {} is in none of {}, {}, /no/such/dir",
+            file.display(),
+            entry.display(),
+            other.display()
+        )
+    );
+
+    let (kind, message) = compile_error(&mut engine, "import broken;");
+    assert_eq!(kind, CompileErrorKind::InvalidModule);
+    assert!(message.starts_with(&format!("in module {}: ", other.join("broken.v").display())), "{}", message);
+    fs::remove_dir_all(&entry).unwrap();
+    fs::remove_dir_all(&other).unwrap();
+}
+
+#[test]
+fn test_modules_can_define_the_same_names() {
+    let dir = module_dir("namespaces", &[
+        ("left.v", "let side = \"left\";\nfn helper() { return side; }\npub fn name() { return helper(); }"),
+        ("right.v", "let side = \"right\";\nfn helper() { return side; }\npub fn name() { return helper(); }"),
+    ]);
+    let mut engine = Engine::new();
+    engine.add_module_path(dir.clone());
+    let source = "import left;\nimport right;\nfn helper() { return \"main\"; }\nformat(\"{} {} {}\", left.name(), right.name(), helper());";
+    assert_eq!(engine.eval(source), Ok(RuntimeValue::String("left right main".to_string())));
+    assert_eq!(engine.call("right::name", &[]), Ok(RuntimeValue::String("right".to_string())));
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_only_pub_members_are_usable_outside_their_module() {
+    let dir = module_dir("private", &[("utils.v", "fn helper(n) { return n + 1; }\nlet limit = 3;\npub fn next(n) { return helper(n); }")]);
+    let mut engine = Engine::new();
+    engine.add_module_path(dir.clone());
+    assert_eq!(engine.eval("import utils; utils.next(1);"), Ok(RuntimeValue::Integer(2)));
+
+    let (kind, message) = compile_error(&mut engine, "import utils; utils.helper(1);");
+    assert_eq!(kind, CompileErrorKind::PrivateModuleMember);
+    assert_eq!(message, "function `helper` is private to module `utils`; mark it `pub` to use it here");
+    let (_, message) = compile_error(&mut engine, "import utils; utils.limit;");
+    assert_eq!(message, "variable `limit` is private to module `utils`; mark it `pub` to use it here");
+
+    // Without the module, its names aren't there at all
+    let (kind, message) = compile_error(&mut engine, "import utils; next(1);");
+    assert_eq!(kind, CompileErrorKind::UnknownFunction);
+    assert_eq!(message, "`next` is defined in module `utils`; use it through an import of the module");
+    fs::remove_dir_all(&dir).unwrap();
+}
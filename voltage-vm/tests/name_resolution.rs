@@ -0,0 +1,108 @@
+use voltage_vm::{CompileErrorKind, Engine, RuntimeValue, VoltageError};
+
+fn eval(source: &str) -> Result<RuntimeValue, VoltageError> {
+    Engine::new().eval(source)
+}
+
+fn warnings(source: &str) -> Vec<String> {
+    let mut engine = Engine::new();
+    engine.eval(source).unwrap();
+    engine.warnings().iter().map(ToString::to_string).collect()
+}
+
+#[test]
+fn test_parameters_hide_globals() {
+    let source = "let x = 10; fn f(x) { return x; } f(1);";
+    assert_eq!(eval(source), Ok(RuntimeValue::Integer(1)));
+}
+
+#[test]
+fn test_globals_are_visible_in_functions() {
+    let source = "fn f() { return x + 1; } let x = 10; f();";
+    assert_eq!(eval(source), Ok(RuntimeValue::Integer(11)));
+}
+
+#[test]
+fn test_functions_are_values() {
+    let source = "fn double(n) { return n * 2; } fn f() { let g = double; return g(21); } f();";
+    assert_eq!(eval(source), Ok(RuntimeValue::Integer(42)));
+}
+
+#[test]
+fn test_builtins_come_last() {
+    assert_eq!(eval("len([1, 2, 3]);"), Ok(RuntimeValue::Integer(3)));
+
+    // A local of the same name hides the builtin, for calls too
+    let source = "fn twice(n) { return n * 2; } fn f() { let len = twice; return len(4); } f();";
+    assert_eq!(eval(source), Ok(RuntimeValue::Integer(8)));
+    let source = "fn f(puts) { return puts(1); } fn one(n) { return n; } f(one);";
+    assert_eq!(eval(source), Ok(RuntimeValue::Integer(1)));
+
+    // Builtins themselves aren't values
+    let Err(VoltageError::Compile(error)) = eval("let f = len;") else {
+        panic!("a builtin used as a value should not compile");
+    };
+    assert_eq!(error.message, "builtin function `len` can only be called, not used as a value");
+}
+
+#[test]
+fn test_calling_an_undefined_function_does_not_compile() {
+    // Even where the call would never run
+    let Err(VoltageError::Compile(error)) = eval("fn main() { if false { nope(1); } }") else {
+        panic!("a call to an undefined function should not compile");
+    };
+    assert_eq!(error.kind, CompileErrorKind::UnknownFunction);
+    assert_eq!(error.message, "Unknown function: nope");
+
+    // A global may hold a function, so calling one is left until it runs
+    assert_eq!(eval("fn one() { return 1; } let f = one; f();"), Ok(RuntimeValue::Integer(1)));
+}
+
+#[test]
+fn test_shadowing_a_builtin_is_a_warning() {
+    assert_eq!(warnings("fn f() {\n    let len = 3;\n    return len;\n}\nf();"), vec!["2:5: `len` shadows a builtin function"]);
+    assert_eq!(warnings("for len in 0..2 {\n}"), vec!["1:1: `len` shadows a builtin function"]);
+    assert_eq!(warnings("fn show(print) {\n    return print;\n}"), vec!["1:1: `print` shadows a builtin function"]);
+    assert!(warnings("let length = 3;").is_empty());
+}
+
+#[test]
+fn test_block_locals_go_out_of_scope() {
+    // The inner `x` is a slot of its own while its block runs
+    let source = "fn f() { let x = 1; if true { let x = 2; return x * 10 + 1; } return x; } f();";
+    assert_eq!(eval(source), Ok(RuntimeValue::Integer(21)));
+
+    // After the block, `x` is the outer one again
+    let source = "fn f() { let x = 1; if true { let x = 2; } return x; } f();";
+    assert_eq!(eval(source), Ok(RuntimeValue::Integer(1)));
+    let source = "fn f() { let x = 1; for x in 5..7 { } return x; } f();";
+    assert_eq!(eval(source), Ok(RuntimeValue::Integer(1)));
+}
+
+#[test]
+fn test_unused_parameters_and_locals_are_warnings() {
+    let source = "fn f(a, b) {\n    let c = 1;\n    let d = 2;\n    return b + d;\n}\nf(1, 2);";
+    assert_eq!(
+        warnings(source),
+        vec![
+            "1:1: unused parameter `a`; name it `_` if that's intended",
+            "2:5: unused variable `c`; name it `_` if that's intended",
+        ]
+    );
+    let source = "fn f(xs) {\n    for x in xs {\n    }\n    return 0;\n}";
+    assert_eq!(warnings(source), vec!["2:5: unused variable `x`; name it `_` if that's intended"]);
+    // The error of a `catch` is pointed at by its name
+    let source = "fn f() {\n    try {\n        return 1 / 0;\n    } catch e {\n    }\n    return 0;\n}\nf();";
+    assert_eq!(warnings(source), vec!["4:13: unused variable `e`; name it `_` if that's intended"]);
+
+    // Only locals are checked; later code may read a global
+    assert!(warnings("let unread = 1;").is_empty());
+}
+
+#[test]
+fn test_underscore_discards_without_a_warning() {
+    let source = "fn second(_, b, _) {\n    let _ = b * 2;\n    for _ in 0..2 {\n    }\n    return b;\n}\nsecond(1, 2, 3);";
+    assert!(warnings(source).is_empty());
+    assert_eq!(eval(source), Ok(RuntimeValue::Integer(2)));
+    assert_eq!(eval("let _ = 5; let x = 1; x;"), Ok(RuntimeValue::Integer(1)));
+}
@@ -0,0 +1,76 @@
+use voltage_vm::{CompileErrorKind, Engine, RuntimeValue, VoltageError};
+
+fn eval(source: &str) -> Result<RuntimeValue, VoltageError> {
+    Engine::new().eval(source)
+}
+
+fn compile_error(source: &str) -> (CompileErrorKind, String) {
+    match eval(source) {
+        Err(VoltageError::Compile(error)) => (error.kind, error.message),
+        other => panic!("expected a compile error, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_defining_and_calling_a_nested_helper() {
+    let source = "
+        fn sum_of_squares(xs) {
+            fn square(x) { return x * x; }
+            let total = [0];
+            for x in xs { total[0] += square(x); }
+            return total[0];
+        }
+        sum_of_squares([1, 2, 3]);
+    ";
+    assert_eq!(eval(source), Ok(RuntimeValue::Integer(14)));
+
+    // Helpers call themselves and each other, and may be passed around
+    let source = "
+        fn f(n) {
+            fn even(n) { if n == 0 { return true; } return odd(n - 1); }
+            fn odd(n) { if n == 0 { return false; } return even(n - 1); }
+            return map([n, n + 1], odd);
+        }
+        f(4);
+    ";
+    assert_eq!(eval(source), eval("[false, true];"));
+}
+
+#[test]
+fn test_calling_a_nested_function_from_outside_is_an_error() {
+    let source = "fn main() { fn helper() { return 1; } return helper(); } helper();";
+    assert_eq!(
+        compile_error(source),
+        (CompileErrorKind::UnknownFunction, "`helper` is defined inside `main` and can only be used there".to_string())
+    );
+}
+
+#[test]
+fn test_nested_functions_shadow_outer_ones() {
+    let source = "
+        fn label() { return \"outer\"; }
+        fn f() {
+            fn label() { return \"inner\"; }
+            return label();
+        }
+        fn g() { return label(); }
+        [f(), g()];
+    ";
+    assert_eq!(eval(source), eval("[\"inner\", \"outer\"];"));
+}
+
+#[test]
+fn test_capturing_a_variable_of_the_outer_function_is_an_error() {
+    let source = "fn scale(xs, factor) {\n    fn times(x) {\n        return x * factor;\n    }\n    return map(xs, times);\n}";
+    match eval(source) {
+        Err(VoltageError::Compile(error)) => {
+            assert_eq!(error.kind, CompileErrorKind::CapturedVariable);
+            assert_eq!(
+                error.message,
+                "`times` can't use `factor`, a variable of `scale`; functions defined inside another can't capture its variables yet"
+            );
+            assert_eq!(error.span.map(|span| span.line), Some(3));
+        }
+        other => panic!("expected a compile error, got {:?}", other),
+    }
+}
@@ -0,0 +1,73 @@
+use voltage_vm::{Engine, RuntimeValue};
+
+fn eval(source: &str) -> RuntimeValue {
+    Engine::new().eval(source).unwrap()
+}
+
+fn eval_error(source: &str) -> String {
+    Engine::new().eval(source).unwrap_err().to_string()
+}
+
+#[test]
+fn test_int_parses_in_any_radix() {
+    assert_eq!(eval("int(\"ff\", 16);"), RuntimeValue::Integer(255));
+    assert_eq!(eval("int(\"1010\", 2);"), RuntimeValue::Integer(10));
+    assert_eq!(eval("int(\"Zz\", 36);"), RuntimeValue::Integer(1295));
+    assert_eq!(eval("int(\"42\");"), RuntimeValue::Integer(42));
+    assert_eq!(eval("int(7.9);"), RuntimeValue::Integer(7));
+    assert_eq!(
+        eval_error("int(\"12\", 2);"),
+        "Runtime error: int: \"12\" is not an integer in base 2"
+    );
+}
+
+#[test]
+fn test_invalid_radix_is_an_error() {
+    assert_eq!(eval_error("int(\"1\", 1);"), "Runtime error: int: radix must be between 2 and 36, got 1");
+    assert_eq!(eval_error("try_int(\"1\", 37);"), "Runtime error: try_int: radix must be between 2 and 36, got 37");
+}
+
+#[test]
+fn test_signs_and_whitespace_are_handled_alike() {
+    assert_eq!(eval("int(\" -ff \", 16);"), RuntimeValue::Integer(-255));
+    assert_eq!(eval("int(\"+12\");"), RuntimeValue::Integer(12));
+    assert_eq!(eval("try_int(\"  -3 \");"), RuntimeValue::Integer(-3));
+    assert_eq!(eval("try_float(\" +2.5 \");"), RuntimeValue::Float(2.5));
+    assert_eq!(eval("try_float(\"-1e3\");"), RuntimeValue::Float(-1000.0));
+    assert_eq!(eval("try_int(\"--3\");"), RuntimeValue::Null);
+    assert_eq!(eval("try_int(\"- 3\");"), RuntimeValue::Null);
+}
+
+#[test]
+fn test_try_versions_return_null_on_garbage() {
+    assert_eq!(eval("try_int(\"12abc\");"), RuntimeValue::Null);
+    assert_eq!(eval("try_int(\"\");"), RuntimeValue::Null);
+    assert_eq!(eval("try_int(\"99\");"), RuntimeValue::Integer(99));
+    assert_eq!(eval("try_float(\"nan\");"), RuntimeValue::Null);
+    assert_eq!(eval("try_float(\"1.5.2\");"), RuntimeValue::Null);
+}
+
+#[test]
+fn test_int_errors_can_be_caught() {
+    let source = "let n = 0; try { let n = int(\"oops\"); } catch e { let n = -1; } n;";
+    assert_eq!(eval(source), RuntimeValue::Integer(-1));
+}
+
+#[test]
+fn test_i64_min_can_be_written_as_a_literal() {
+    assert_eq!(eval("-9223372036854775808;"), RuntimeValue::Integer(i64::MIN));
+    assert_eq!(eval("let n = -9223372036854775808; n + 1;"), RuntimeValue::Integer(i64::MIN + 1));
+    assert_eq!(eval("format(\"{}\", -9223372036854775808);"), RuntimeValue::String("-9223372036854775808".to_string()));
+    assert!(eval_error("-9223372036854775809;").contains("integer literal out of range for i64"));
+}
+
+#[test]
+fn test_int_arithmetic_that_overflows_is_an_error() {
+    let min = "let min = -9223372036854775808;";
+    assert_eq!(eval_error(&format!("{} min / -1;", min)), "Runtime error: Integer overflow: -9223372036854775808 / -1");
+    assert_eq!(eval_error(&format!("{} min % -1;", min)), "Runtime error: Integer overflow: -9223372036854775808 % -1");
+    assert_eq!(eval_error(&format!("{} min - 1;", min)), "Runtime error: Integer overflow: -9223372036854775808 - 1");
+    assert_eq!(eval_error("9223372036854775807 + 1;"), "Runtime error: Integer overflow: 9223372036854775807 + 1");
+    assert_eq!(eval_error("let big = 4611686018427387904; big * 2;"), "Runtime error: Integer overflow: 4611686018427387904 * 2");
+    assert_eq!(eval("-9223372036854775808 / 1;"), RuntimeValue::Integer(i64::MIN));
+}
@@ -0,0 +1,23 @@
+use voltage_vm::{Engine, RuntimeValue};
+
+fn eval(source: &str) -> Result<RuntimeValue, String> {
+    Engine::new().eval(source).map_err(|e| e.to_string())
+}
+
+#[test]
+fn test_exponents() {
+    assert_eq!(eval("2 ** 3 ** 2 == 512;"), Ok(RuntimeValue::Boolean(true)));
+    assert_eq!(eval("2 * 3 ** 2;"), Ok(RuntimeValue::Integer(18)));
+    assert_eq!(eval("7 ** 0;"), Ok(RuntimeValue::Integer(1)));
+    assert_eq!(eval("2.0 ** 0.5;"), Ok(RuntimeValue::Float(2f64.sqrt())));
+    assert_eq!(eval("2 ** 63;"), Err("Runtime error: Integer overflow: 2 ** 63".to_string()));
+    assert_eq!(eval("2 ** -1;"), Err("Runtime error: Cannot raise an int to a negative power: -1".to_string()));
+}
+
+#[test]
+fn test_negation() {
+    assert_eq!(eval("-2 ** 2;"), Ok(RuntimeValue::Integer(-4)));
+    assert_eq!(eval("let x = 5; -x * 2;"), Ok(RuntimeValue::Integer(-10)));
+    assert_eq!(eval("-1.5;"), Ok(RuntimeValue::Float(-1.5)));
+    assert_eq!(eval("-\"a\";"), Err("Runtime error: Type error: Cannot negate a value of type string".to_string()));
+}
@@ -0,0 +1,106 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use voltage_vm::{Engine, OutputEvent, RuntimeValue};
+
+// An engine whose output is recorded rather than written
+fn recording_engine() -> (Engine, Rc<RefCell<Vec<OutputEvent>>>) {
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let recorded = Rc::clone(&events);
+    let mut engine = Engine::new();
+    engine.set_output_handler(move |event| recorded.borrow_mut().push(event));
+    (engine, events)
+}
+
+fn print(text: &str) -> OutputEvent {
+    OutputEvent::Print(text.to_string())
+}
+
+fn puts(text: &str) -> OutputEvent {
+    OutputEvent::PutsLine(text.to_string())
+}
+
+fn debug(text: &str) -> OutputEvent {
+    OutputEvent::Debug(text.to_string())
+}
+
+#[test]
+fn test_events_arrive_in_program_order() {
+    let (mut engine, events) = recording_engine();
+    let source = "fn shout(word) { puts(word); return word; }\n\
+                  for i in 0..2 {\n\
+                      print(i);\n\
+                      shout(\"line {}\");\n\
+                      print(\"{}!\", i);\n\
+                  }\n\
+                  puts([1, 2]);";
+    engine.eval(source).unwrap();
+    assert_eq!(
+        *events.borrow(),
+        [
+            print("0"),
+            puts("line {}"),
+            print("0!"),
+            print("1"),
+            puts("line {}"),
+            print("1!"),
+            puts("[1, 2]"),
+        ]
+    );
+}
+
+#[test]
+fn test_output_before_an_error_is_delivered() {
+    let (mut engine, events) = recording_engine();
+    assert!(engine.eval("puts(\"before\"); let x = 1 / 0; puts(\"after\");").is_err());
+    assert_eq!(*events.borrow(), [puts("before")]);
+}
+
+#[test]
+fn test_events_write_as_the_console_shows_them() {
+    let mut written = Vec::new();
+    for event in [print("a"), print("b"), puts("c"), puts("")] {
+        event.write_to(&mut written).unwrap();
+    }
+    assert_eq!(written, b"abc\n\n");
+}
+
+#[test]
+fn test_dbg_shows_the_expression_and_returns_its_value() {
+    let (mut engine, events) = recording_engine();
+    let y = engine.eval("let x = 4;\nlet y = dbg(x  *  2) + 1;\ny;").unwrap();
+    assert_eq!(y, RuntimeValue::Integer(9));
+    // The expression as written, spacing and all
+    assert_eq!(*events.borrow(), [debug("[line 2] x  *  2 = 8")]);
+
+    let (mut engine, events) = recording_engine();
+    engine.set_script_name("sums.v");
+    let sum = engine.eval("dbg(dbg(1) + dbg(2));").unwrap();
+    assert_eq!(sum, RuntimeValue::Integer(3));
+    assert_eq!(*events.borrow(), [debug("[sums.v:1] 1 = 1"), debug("[sums.v:1] 2 = 2"), debug("[sums.v:1] dbg(1) + dbg(2) = 3")]);
+}
+
+#[test]
+fn test_dbg_shows_values_pretty() {
+    let (mut engine, events) = recording_engine();
+    engine.eval(r#"dbg([["a", "b"], []]); dbg(Point { x: 1, label: "origin" }); dbg("${dbg(Empty {})}");"#).unwrap();
+    assert_eq!(
+        *events.borrow(),
+        [
+            debug("[line 1] [[\"a\", \"b\"], []] = [\n    [\n        \"a\",\n        \"b\",\n    ],\n    [],\n]"),
+            debug("[line 1] Point { x: 1, label: \"origin\" } = Point {\n    x: 1,\n    label: \"origin\",\n}"),
+            debug("[line 1] Empty {} = Empty {}"),
+            debug("[line 1] \"${dbg(Empty {})}\" = \"Empty {}\""),
+        ]
+    );
+}
+
+#[test]
+fn test_unicode_escapes_print_as_their_characters() {
+    let (mut engine, events) = recording_engine();
+    engine.eval(r#"puts("\u{1F600} caf\u{e9}\tok"); dbg("bell\u{7} \u{1F600}");"#).unwrap();
+    // `dbg` writes the string back as a literal, escaping only what can't be seen
+    assert_eq!(
+        *events.borrow(),
+        [puts("😀 café\tok"), debug(r#"[line 1] "bell\u{7} \u{1F600}" = "bell\u{7} 😀""#)]
+    );
+}
@@ -0,0 +1,48 @@
+use voltage_vm::{Engine, RuntimeError, RuntimeErrorKind, RuntimeValue, VoltageError};
+
+fn panic_error(result: Result<RuntimeValue, VoltageError>) -> RuntimeError {
+    match result {
+        Err(VoltageError::Panic(error)) => error,
+        other => panic!("expected a panic, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_panic_formats_its_message() {
+    let mut engine = Engine::new();
+    let error = panic_error(engine.eval(r#"let x = 7; panic("unreachable state: {} of {}", x, [1, 2]);"#));
+    assert_eq!(error.kind, RuntimeErrorKind::Panic);
+    assert_eq!(error.message, "unreachable state: 7 of [1, 2]");
+
+    let error = panic_error(engine.eval(r#"panic("plain");"#));
+    assert_eq!(error.to_string(), "plain");
+    let error = panic_error(engine.eval("panic(42);"));
+    assert_eq!(error.message, "42");
+}
+
+#[test]
+fn test_panic_carries_its_location_and_trace() {
+    let mut engine = Engine::new();
+    engine.load("fn check(n) {\n    if n > 2 {\n        panic(\"too big: {}\", n);\n    }\n    return n;\n}").unwrap();
+
+    assert_eq!(engine.call("check", &[RuntimeValue::Integer(1)]), Ok(RuntimeValue::Integer(1)));
+    let error = panic_error(engine.call("check", &[RuntimeValue::Integer(3)]));
+    assert_eq!(error.location().map(|span| span.line), Some(3));
+    assert_eq!(error.backtrace(), "  at check (line 3)\n");
+    assert_eq!(VoltageError::Panic(error).to_string(), "Panic: too big: 3");
+}
+
+#[test]
+fn test_other_errors_are_not_panics() {
+    let mut engine = Engine::new();
+    assert!(matches!(engine.eval("1 / 0;"), Err(VoltageError::Runtime(e)) if e.kind == RuntimeErrorKind::Error));
+    assert!(matches!(engine.eval("assert(false);"), Err(VoltageError::Runtime(_))));
+    // A panic doesn't carry over to the next error
+    assert!(engine.eval(r#"panic("once");"#).is_err());
+    assert!(matches!(engine.eval("1 / 0;"), Err(VoltageError::Runtime(_))));
+}
+
+#[test]
+fn test_panic_needs_a_message() {
+    assert!(matches!(Engine::new().eval("panic();"), Err(VoltageError::Compile(_))));
+}
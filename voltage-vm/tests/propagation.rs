@@ -0,0 +1,63 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+use voltage_vm::{disassemble, propagate_constants, Engine, OutputEvent, Program};
+
+// What running `program` printed, and the error it ended with
+fn run(program: Program) -> (Vec<OutputEvent>, Option<String>) {
+    let events = Rc::new(RefCell::new(Vec::new()));
+    let recorded = Rc::clone(&events);
+    let mut engine = Engine::new();
+    engine.set_output_handler(move |event| recorded.borrow_mut().push(event));
+    let error = engine.load_program(program).err().map(|error| error.to_string());
+    let events = events.borrow().clone();
+    (events, error)
+}
+
+const SOURCE: &str = "let rate = 0.25;
+let name = \"total\";
+let count = [0];
+let limit = 3;
+fn tax(n) { return n * rate; }
+fn bump() { count[0] += 1; return count[0]; }
+puts(\"{}: {}\", name, tax(8.0));
+for i in 0..limit { bump(); }
+let limit = limit + 1;
+puts(\"{} {}\", count[0], limit);";
+
+#[test]
+fn test_globals_that_never_change_are_loaded_as_constants() {
+    let program = voltage_vm::compile(SOURCE).unwrap();
+    let listing = disassemble(&program, None);
+    assert!(listing.contains("LoadGlobal(\"rate\")") && listing.contains("LoadGlobal(\"name\")"));
+    let listing = disassemble(&propagate_constants(program), None);
+    for name in ["rate", "name"] {
+        assert!(!listing.contains(&format!("LoadGlobal(\"{}\")", name)), "{}", listing);
+        // The global is still defined for the host
+        assert!(listing.contains(&format!("StoreGlobal(\"{}\")", name)), "{}", listing);
+    }
+}
+
+#[test]
+fn test_globals_stored_again_are_left_alone() {
+    let listing = disassemble(&propagate_constants(voltage_vm::compile(SOURCE).unwrap()), None);
+    // `count` is updated by `bump` and `limit` is declared again
+    assert!(listing.contains("LoadGlobal(\"count\")"), "{}", listing);
+    assert!(listing.contains("LoadGlobal(\"limit\")"), "{}", listing);
+
+    // Nor are globals read before they're stored, or stored after a call
+    let source = "fn early() { return late; }\nputs(early());\nlet late = 1;";
+    let listing = disassemble(&propagate_constants(voltage_vm::compile(source).unwrap()), None);
+    assert!(listing.contains("LoadGlobal(\"late\")"), "{}", listing);
+}
+
+#[test]
+fn test_propagated_programs_behave_the_same() {
+    let program = voltage_vm::compile(SOURCE).unwrap();
+    let (output, error) = run(propagate_constants(program.clone()));
+    assert_eq!(error, None);
+    assert_eq!((output, error), run(program));
+
+    // A read of a global that isn't defined yet still fails
+    let program = voltage_vm::compile("fn early() { return late; }\nputs(early());\nlet late = 1;").unwrap();
+    assert_eq!(run(propagate_constants(program.clone())), run(program));
+}
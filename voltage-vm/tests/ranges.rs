@@ -0,0 +1,85 @@
+use voltage_vm::{Engine, RuntimeValue};
+
+fn int(n: i64) -> RuntimeValue {
+    RuntimeValue::Integer(n)
+}
+
+fn boolean(b: bool) -> RuntimeValue {
+    RuntimeValue::Boolean(b)
+}
+
+#[test]
+fn test_range_is_a_value() {
+    let mut engine = Engine::new();
+    assert_eq!(
+        engine.eval("let r = 2..5; r;"),
+        Ok(RuntimeValue::Range { start: 2, end: 5, inclusive: false })
+    );
+    assert_eq!(engine.eval("1..=3;").unwrap().to_string(), "1..=3");
+    assert_eq!(engine.eval("r[1];"), Ok(int(3)));
+    assert_eq!(
+        engine.eval("r[3];").unwrap_err().to_string(),
+        "Runtime error: Index 3 is out of bounds for length 3"
+    );
+    assert_eq!(
+        engine.eval("1..\"2\";").unwrap_err().to_string(),
+        "Runtime error: Range bounds must be ints, got int and string"
+    );
+}
+
+#[test]
+fn test_len_and_contains() {
+    let mut engine = Engine::new();
+    engine.load("let r = 0..10; let inclusive = 0..=10;").unwrap();
+    assert_eq!(engine.eval("len(r);"), Ok(int(10)));
+    assert_eq!(engine.eval("len(inclusive);"), Ok(int(11)));
+    assert_eq!(engine.eval("contains(r, 9);"), Ok(boolean(true)));
+    assert_eq!(engine.eval("contains(r, 10);"), Ok(boolean(false)));
+    assert_eq!(engine.eval("contains(inclusive, 10);"), Ok(boolean(true)));
+    assert_eq!(engine.eval("contains(r, 0 - 1);"), Ok(boolean(false)));
+
+    assert_eq!(engine.eval("len([1, 2]) + len(\"né\");"), Ok(int(4)));
+    assert_eq!(engine.eval("contains([1, 2], 2);"), Ok(boolean(true)));
+    assert_eq!(engine.eval("contains(\"voltage\", \"tag\");"), Ok(boolean(true)));
+    assert_eq!(
+        engine.eval("len(5);").unwrap_err().to_string(),
+        "Runtime error: Cannot take the length of a value of type int"
+    );
+}
+
+#[test]
+fn test_for_iterates_a_range_stored_in_a_variable() {
+    let mut engine = Engine::new();
+    let source = "let r = 1..=4; let total = [0]; \
+                  for i in r { total[0] += i; } \
+                  total[0];";
+    assert_eq!(engine.eval(source), Ok(int(10)));
+}
+
+#[test]
+fn test_for_iterates_arrays_strings_and_literal_ranges() {
+    let mut engine = Engine::new();
+    engine
+        .load(
+            "fn sum(values) { let total = [0]; for v in values { total[0] += v; } return total[0]; }
+             fn squares(n) { let out = [0, 0, 0, 0]; for i in 0..n { out[i] = i * i; } return out; }",
+        )
+        .unwrap();
+    assert_eq!(engine.eval("sum([3, 4, 5]);"), Ok(int(12)));
+    assert_eq!(
+        engine.eval("squares(4);"),
+        Ok(RuntimeValue::Array(vec![int(0), int(1), int(4), int(9)]))
+    );
+    assert_eq!(
+        engine.eval("let seen = [\"\"]; for c in \"abc\" { seen[0] = c; } seen[0];"),
+        Ok(RuntimeValue::String("c".to_string()))
+    );
+}
+
+#[test]
+fn test_backwards_range_is_empty() {
+    let mut engine = Engine::new();
+    assert_eq!(engine.eval("let r = 5..2; len(r);"), Ok(int(0)));
+    assert_eq!(engine.eval("len(3..=2);"), Ok(int(0)));
+    assert_eq!(engine.eval("let runs = [0]; for i in 5..2 { runs[0] += 1; } runs[0];"), Ok(int(0)));
+}
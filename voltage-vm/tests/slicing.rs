@@ -0,0 +1,63 @@
+use voltage_vm::{Engine, RuntimeValue};
+
+fn ints(values: &[i64]) -> RuntimeValue {
+    RuntimeValue::Array(values.iter().map(|&n| RuntimeValue::Integer(n)).collect())
+}
+
+fn string(s: &str) -> RuntimeValue {
+    RuntimeValue::String(s.to_string())
+}
+
+#[test]
+fn test_array_slices_are_copies() {
+    let mut engine = Engine::new();
+    engine.load("let xs = [10, 20, 30, 40, 50];").unwrap();
+    assert_eq!(engine.eval("xs[1..3];"), Ok(ints(&[20, 30])));
+    assert_eq!(engine.eval("xs[1..=3];"), Ok(ints(&[20, 30, 40])));
+    assert_eq!(engine.eval("xs[0..len(xs)];"), Ok(ints(&[10, 20, 30, 40, 50])));
+    // Changing the slice leaves the array alone
+    assert_eq!(engine.eval("let part = xs[0..2]; part[0] = 99; xs[0];"), Ok(RuntimeValue::Integer(10)));
+}
+
+#[test]
+fn test_empty_slices() {
+    let mut engine = Engine::new();
+    assert_eq!(engine.eval("[1, 2, 3][2..2];"), Ok(ints(&[])));
+    assert_eq!(engine.eval("[1, 2, 3][3..3];"), Ok(ints(&[])));
+    assert_eq!(engine.eval("\"abc\"[1..1];"), Ok(string("")));
+}
+
+#[test]
+fn test_out_of_bounds_slices_are_errors() {
+    let mut engine = Engine::new();
+    engine.load("let xs = [1, 2, 3];").unwrap();
+    assert_eq!(
+        engine.eval("xs[1..4];").unwrap_err().to_string(),
+        "Runtime error: Slice 1..4 is out of bounds for length 3"
+    );
+    assert_eq!(
+        engine.eval("xs[0..=3];").unwrap_err().to_string(),
+        "Runtime error: Slice 0..=3 is out of bounds for length 3"
+    );
+    assert_eq!(
+        engine.eval("xs[2..1];").unwrap_err().to_string(),
+        "Runtime error: Slice 2..1 is out of bounds for length 3"
+    );
+    assert_eq!(
+        engine.eval("xs[0 - 1..2];").unwrap_err().to_string(),
+        "Runtime error: Slice -1..2 is out of bounds for length 3"
+    );
+}
+
+#[test]
+fn test_string_slices_count_characters() {
+    let mut engine = Engine::new();
+    engine.load("let s = \"né€x voltage\";").unwrap();
+    assert_eq!(engine.eval("s[0..3];"), Ok(string("né€")));
+    assert_eq!(engine.eval("s[2..=3];"), Ok(string("€x")));
+    assert_eq!(engine.eval("s[5..12];"), Ok(string("voltage")));
+    assert_eq!(
+        engine.eval("s[5..13];").unwrap_err().to_string(),
+        "Runtime error: Slice 5..13 is out of bounds for length 12"
+    );
+}
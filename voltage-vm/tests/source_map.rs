@@ -0,0 +1,119 @@
+use voltage_core::Statement;
+use voltage_parser::{Lexer, Parser};
+use voltage_vm::{disassemble, Bytecode, BytecodeCompiler, Program, RuntimeValue};
+
+const FIXTURE: &str = include_str!("fixtures/source_map.v");
+
+fn parse(source: &str) -> Vec<Statement> {
+    let lexer = Lexer::new(source);
+    Parser::from_lexer(lexer).parse()
+}
+
+fn compile(source: &str) -> Program {
+    BytecodeCompiler::new().compile_program(&parse(source)).unwrap()
+}
+
+#[test]
+fn test_statement_inside_loop_maps_to_its_line() {
+    let program = compile(FIXTURE);
+    let instructions = program.source_map.instructions_for_line(5);
+    assert!(!instructions.is_empty());
+    assert!(instructions.iter().any(|&i| matches!(program.bytecode[i], Bytecode::CallBuiltin(..))));
+
+    for instruction in instructions {
+        let span = program.source_map.span_for(instruction).unwrap();
+        assert_eq!((span.line, span.column), (5, 9));
+        assert_eq!(&FIXTURE[span.start..span.end], "puts(step);");
+    }
+    // The loop's condition belongs to the `while` itself
+    let condition = program.source_map.instructions_for_line(3);
+    assert!(condition.iter().any(|&i| program.bytecode[i] == Bytecode::Lt));
+}
+
+#[test]
+fn test_last_instruction_of_function_maps_to_function() {
+    let program = compile(FIXTURE);
+    let count = program.functions.iter().find(|f| f.name == "count").unwrap();
+    let last = count.end - 1;
+    assert_eq!(program.bytecode[last], Bytecode::Return);
+
+    let span = program.source_map.span_for(last).unwrap();
+    assert_eq!(span.line, 1);
+    let text = &FIXTURE[span.start..span.end];
+    assert!(text.starts_with("fn count(limit) {"));
+    assert!(text.ends_with('}'));
+}
+
+#[test]
+fn test_generated_code_has_no_span() {
+    let program = compile(FIXTURE);
+    // The jump over the function bodies isn't from any statement
+    assert_eq!(program.source_map.span_for(0), None);
+}
+
+#[test]
+fn test_disassembly_interleaves_source() {
+    let program = compile(FIXTURE);
+    let listing = disassemble(&program, Some(FIXTURE));
+    assert!(listing.contains("count:\n"));
+    assert!(listing.contains("    ; 5 | puts(step);\n"));
+
+    let plain = disassemble(&program, None);
+    let code = plain.split_once("code:\n").unwrap().1;
+    assert!(!code.contains(';'));
+    assert_eq!(code.lines().count(), program.bytecode.len() + 1);
+}
+
+#[test]
+fn test_disassembly_lists_constants_first() {
+    let mut program = compile(FIXTURE);
+    let listing = disassemble(&program, None);
+    let constants = listing.split_once("code:\n").unwrap().0;
+    let expected = [
+        "constants:",
+        "     0  int       0",
+        "     1  int       3",
+        "     2  int       1",
+        "     3  null      null",
+        "     4  function  <function count>",
+        "     5  string    \"count\"",
+    ];
+    assert_eq!(constants.lines().collect::<Vec<_>>(), expected);
+
+    // A constant nothing loads is waste the compiler should not have left
+    program.constants.push(RuntimeValue::String("stale".to_string()));
+    let listing = disassemble(&program, None);
+    assert!(listing.contains("     6  string    \"stale\"  ; never loaded\n"), "{}", listing);
+}
+
+const SHADOWED: &str = "fn f(x) {
+    let y = x + 1;
+    if y > 0 {
+        let x = y * 2;
+        puts(x);
+    }
+    return x;
+}";
+
+#[test]
+fn test_shadowed_locals_have_slots_and_scopes_of_their_own() {
+    let program = compile(SHADOWED);
+    let function = &program.functions[0];
+    let locals = program.source_map.locals_of("f").unwrap();
+    let names: Vec<(usize, &str)> = locals.iter().map(|local| (local.slot, local.name.as_str())).collect();
+    assert_eq!(names, [(0, "x"), (1, "y"), (2, "x")]);
+    let (outer, inner) = (&locals[0], &locals[2]);
+    assert_eq!(outer.span.line, 1);
+    assert_eq!(inner.span.line, 4);
+
+    // The parameter is in scope for the whole body, the inner `x` from its
+    // `let` to the end of the `if`
+    assert_eq!(outer.scope.start, function.start);
+    assert_eq!(program.bytecode[inner.scope.start - 1], Bytecode::StoreLocal(2));
+    let bytecode = &program.bytecode;
+    let reads = |slot| (0..bytecode.len()).filter(move |&i| bytecode[i] == Bytecode::LoadLocal(slot));
+    assert!(reads(2).all(|i| inner.scope.contains(&i)));
+    let returned = reads(0).next_back().unwrap();
+    assert!(outer.scope.contains(&returned) && !inner.scope.contains(&returned));
+    assert!(outer.scope.start < inner.scope.start && inner.scope.end < outer.scope.end);
+}
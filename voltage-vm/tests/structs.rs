@@ -0,0 +1,218 @@
+use voltage_vm::{CompileErrorKind, Engine, RuntimeValue, VoltageError};
+
+fn eval(source: &str) -> Result<RuntimeValue, VoltageError> {
+    Engine::new().eval(source)
+}
+
+fn ints(values: &[i64]) -> RuntimeValue {
+    RuntimeValue::Array(values.iter().map(|&n| RuntimeValue::Integer(n)).collect())
+}
+
+fn runtime_message(result: Result<RuntimeValue, VoltageError>) -> String {
+    match result {
+        Err(VoltageError::Runtime(error)) => error.message,
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+}
+
+fn compile_error(result: Result<RuntimeValue, VoltageError>) -> (CompileErrorKind, String) {
+    match result {
+        Err(VoltageError::Compile(error)) => (error.kind, error.message),
+        other => panic!("expected a compile error, got {:?}", other),
+    }
+}
+
+const POINT: &str = "
+    impl Point {
+        fn norm2(self) { return self.x * self.x + self.y * self.y; }
+        fn shifted(self, dx) { return Point { x: self.x + dx, y: self.y }; }
+    }
+";
+
+#[test]
+fn test_calling_methods() {
+    let source = format!("{} let p = Point {{ x: 3, y: 4 }}; [p.norm2(), p.shifted(1).norm2(), p.x];", POINT);
+    assert_eq!(eval(&source), Ok(ints(&[25, 32, 3])));
+
+    // Methods call each other through `self`, and the struct picks the method
+    let source = "
+        impl Square { fn area(self) { return self.side * self.side; } fn describe(self) { return self.area(); } }
+        impl Rect { fn area(self) { return self.w * self.h; } fn describe(self) { return self.area(); } }
+        fn describe(shape) { return shape.describe(); }
+        [describe(Square { side: 3 }), describe(Rect { w: 2, h: 5 })];
+    ";
+    assert_eq!(eval(source), Ok(ints(&[9, 10])));
+}
+
+#[test]
+fn test_methods_update_their_receiver() {
+    // A method changes fields through `self` like any other variable, and
+    // the change is written back to the variable it was called on. A struct
+    // a method returns is a copy, so calling another on that leaves the
+    // variable as the first call left it
+    let source = "
+        impl Counter { fn bump(self, by) { self.count = self.count + by; return self; } }
+        let c = Counter { count: 0, label: \"clicks\" };
+        let d = c.bump(2).bump(3);
+        [format(\"{}\", c), format(\"{}\", d)];
+    ";
+    assert_eq!(eval(source), eval(r#"["Counter { count: 2, label: clicks }", "Counter { count: 5, label: clicks }"];"#));
+
+    // The receiver can be an element or field of a variable too
+    let source = "
+        impl P { fn bump(self) { self.x = self.x + 1; return self.x; } }
+        let p = P { x: 1 };
+        let ps = [P { x: 10 }, P { x: 20 }];
+        [p.bump(), p.x, ps[1].bump(), ps[1].x, ps[0].x];
+    ";
+    assert_eq!(eval(source), Ok(ints(&[2, 2, 21, 21, 10])));
+
+    let source = "let p = Point { x: 1, y: 2 }; p.y = 7; p == Point { y: 7, x: 1 };";
+    assert_eq!(eval(source), Ok(RuntimeValue::Boolean(true)));
+}
+
+#[test]
+fn test_nested_fields_are_written_back() {
+    let source = "let ps = [Point { x: 1, y: 1 }, Point { x: 2, y: 2 }]; ps[0].x = 7; [ps[0].x, ps[1].x];";
+    assert_eq!(eval(source), Ok(ints(&[7, 2])));
+    let source = "let p = Path { xs: [1, 2] }; p.xs[0] = 9; p.xs;";
+    assert_eq!(eval(source), Ok(ints(&[9, 2])));
+    let source = "let line = Line { end: Point { x: 0, y: 0 } }; line.end.y = 4; line.end.y;";
+    assert_eq!(eval(source), Ok(RuntimeValue::Integer(4)));
+
+    let source = "fn origin() { return Point { x: 0, y: 0 }; } origin().x = 1;";
+    assert_eq!(
+        compile_error(eval(source)),
+        (CompileErrorKind::InvalidAssignmentTarget, "Only a field of a variable can be assigned to".to_string())
+    );
+}
+
+#[test]
+fn test_unknown_methods_list_the_ones_there_are() {
+    let source = format!("{} Point {{ x: 0, y: 0 }}.area();", POINT);
+    assert_eq!(runtime_message(eval(&source)), "Point has no method `area`; its methods are norm2, shifted");
+    assert_eq!(runtime_message(eval("Empty {}.area();")), "Empty has no method `area`; it has no methods");
+    assert_eq!(runtime_message(eval("[1, 2].len();")), "Cannot call method `len` on a value of type array");
+}
+
+#[test]
+fn test_fields_must_exist() {
+    assert_eq!(runtime_message(eval("Point { x: 1 }.y;")), "Point has no field `y`");
+    assert_eq!(runtime_message(eval("let p = Point { x: 1 }; p.y = 2;")), "Point has no field `y`");
+    assert_eq!(runtime_message(eval("let n = 1; n.y;")), "Cannot get field `y` of a value of type int");
+
+    let (kind, message) = compile_error(eval("Point { x: 1, x: 2 };"));
+    assert_eq!(kind, CompileErrorKind::DuplicateDefinition);
+    assert_eq!(message, "field `x` of `Point` is initialized twice");
+}
+
+#[test]
+fn test_impl_blocks_are_checked() {
+    let (kind, message) = compile_error(eval("impl Point { fn origin() { return 0; } }"));
+    assert_eq!(kind, CompileErrorKind::ArityMismatch);
+    assert_eq!(message, "method `origin` of `Point` needs a first parameter for the value it is called on");
+
+    let (kind, _) = compile_error(eval("impl P { fn a(self) { return 1; } } impl P { fn a(self) { return 2; } }"));
+    assert_eq!(kind, CompileErrorKind::DuplicateDefinition);
+    let (_, message) = compile_error(eval("fn setup() { impl P { fn a(self) { return 1; } } }"));
+    assert_eq!(message, "`impl P` must be at the top level");
+}
+
+const VECTOR: &str = "
+    impl Vec2 {
+        fn __add__(self, other) { return Vec2 { x: self.x + other.x, y: self.y + other.y }; }
+        fn __sub__(self, other) { return Vec2 { x: self.x - other.x, y: self.y - other.y }; }
+        fn __mul__(self, k) { return Vec2 { x: self.x * k, y: self.y * k }; }
+        fn __eq__(self, other) { return self.x * other.y == self.y * other.x; }
+        fn __lt__(self, other) { return self.x * self.x + self.y * self.y < other.x * other.x + other.y * other.y; }
+    }
+";
+
+#[test]
+fn test_operators_call_the_struct_methods() {
+    let source = format!(
+        "{} let a = Vec2 {{ x: 1, y: 2 }}; let b = Vec2 {{ x: 3, y: 5 }}; [(a + b).x, (a + b).y, (b - a).y, (a * 3).x];",
+        VECTOR
+    );
+    assert_eq!(eval(&source), Ok(ints(&[4, 7, 3, 3])));
+    let source = format!("{} Vec2 {{ x: 1, y: 1 }} < Vec2 {{ x: 0, y: 2 }};", VECTOR);
+    assert_eq!(eval(&source), Ok(RuntimeValue::Boolean(true)));
+}
+
+#[test]
+fn test_equality_overload_is_used_by_eq_and_ne() {
+    // `__eq__` here says whether the vectors point the same way
+    let source = format!(
+        "{} let a = Vec2 {{ x: 1, y: 2 }}; let b = Vec2 {{ x: 2, y: 4 }}; [a == b, a != b, a != Vec2 {{ x: 2, y: 1 }}];",
+        VECTOR
+    );
+    assert_eq!(eval(&source), eval("[true, false, true];"));
+    // Structs without `__eq__` still compare their fields
+    assert_eq!(eval("Point { x: 1 } == Point { x: 1 };"), Ok(RuntimeValue::Boolean(true)));
+}
+
+#[test]
+fn test_missing_operator_method_is_a_type_error() {
+    let source = format!("{} Point {{ x: 1, y: 2 }} + Point {{ x: 3, y: 4 }};", POINT);
+    assert_eq!(
+        runtime_message(eval(&source)),
+        "Type error: Cannot add non-numeric values (Point has no method `__add__`; its methods are norm2, shifted)"
+    );
+    assert_eq!(runtime_message(eval("1 - Point { x: 1 };")), "Type error: Cannot subtract non-numeric values");
+}
+
+#[test]
+fn test_comparison_overloads_must_return_a_bool() {
+    let source = "impl Money { fn __lt__(self, other) { return self.cents - other.cents; } } Money { cents: 1 } < Money { cents: 2 };";
+    assert_eq!(runtime_message(eval(source)), "Money.__lt__ must return a bool, but returned int");
+}
+
+const CONFIG: &str = "struct Config { retries: int = 3, verbose: bool = false, name: str, tags: [str] = [\"a\"] }\n";
+
+#[test]
+fn test_defaulted_fields_may_be_left_out() {
+    let source = format!("{} let c = Config {{ name: \"db\" }}; [c.retries, len(c.tags)];", CONFIG);
+    assert_eq!(eval(&source), Ok(ints(&[3, 1])));
+    let source = format!("{} format(\"{{}}\", Config {{ name: \"db\" }});", CONFIG);
+    assert_eq!(
+        eval(&source),
+        Ok(RuntimeValue::String("Config { name: db, retries: 3, verbose: false, tags: [a] }".to_string()))
+    );
+}
+
+#[test]
+fn test_overriding_a_default() {
+    let source = format!("{} let c = Config {{ retries: 10, name: \"db\" }}; c.retries;", CONFIG);
+    assert_eq!(eval(&source), Ok(RuntimeValue::Integer(10)));
+    // Defaults can be worked out from constants
+    let source = "struct Timeout { ms: int = 60 * 1000, scale: float = -0.5 } let t = Timeout {}; t.ms;";
+    assert_eq!(eval(source), Ok(RuntimeValue::Integer(60_000)));
+}
+
+#[test]
+fn test_missing_a_required_field() {
+    let source = format!("{} Config {{ retries: 1 }};", CONFIG);
+    assert_eq!(
+        compile_error(eval(&source)),
+        (CompileErrorKind::MissingField, "missing required field `name` in initializer of `Config`".to_string())
+    );
+    let source = format!("{} Config {{ name: \"db\", retry: 1 }};", CONFIG);
+    assert_eq!(compile_error(eval(&source)), (CompileErrorKind::UnknownField, "`Config` has no field `retry`".to_string()));
+    // Structs without a definition still take any fields
+    assert_eq!(eval("let p = Anything { x: 1 }; p.x;"), Ok(RuntimeValue::Integer(1)));
+}
+
+#[test]
+fn test_defaults_must_be_constants() {
+    let source = "fn three() { return 3; }\nstruct Config {\n    retries: int = three(),\n}";
+    match eval(source) {
+        Err(VoltageError::Compile(error)) => {
+            assert_eq!(error.kind, CompileErrorKind::NonConstantDefault);
+            assert_eq!(error.message, "the default of `Config.retries` must be a constant");
+            assert_eq!(error.span.map(|span| span.line), Some(2));
+        }
+        other => panic!("expected a compile error, got {:?}", other),
+    }
+    let (kind, _) = compile_error(eval("struct Config { retries: int = \"three\" }"));
+    assert_eq!(kind, CompileErrorKind::TypeMismatch);
+}
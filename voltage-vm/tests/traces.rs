@@ -0,0 +1,76 @@
+use voltage_vm::{Engine, RuntimeError, RuntimeValue, VoltageError};
+
+const FIXTURE: &str = include_str!("fixtures/trace.v");
+
+fn runtime_error(result: Result<RuntimeValue, VoltageError>) -> RuntimeError {
+    match result {
+        Err(VoltageError::Runtime(error)) => error,
+        other => panic!("expected a runtime error, got {:?}", other),
+    }
+}
+
+fn frames(error: &RuntimeError) -> Vec<(&str, Option<usize>)> {
+    error
+        .trace
+        .iter()
+        .map(|frame| (frame.function.as_str(), frame.span.map(|span| span.line)))
+        .collect()
+}
+
+#[test]
+fn test_error_three_calls_deep_lists_each_frame() {
+    let mut engine = Engine::new();
+    engine.load(FIXTURE).unwrap();
+
+    let error = runtime_error(engine.call("report", &[RuntimeValue::Integer(10)]));
+    assert_eq!(error.message, "Division by zero");
+    assert_eq!(frames(&error), vec![("divide", Some(2)), ("average", Some(6)), ("report", Some(11))]);
+    assert_eq!(error.backtrace(), "  at divide (line 2)\n  at average (line 6)\n  at report (line 11)\n");
+}
+
+#[test]
+fn test_top_level_code_is_the_outermost_frame() {
+    let mut engine = Engine::new();
+    engine.load(FIXTURE).unwrap();
+
+    let error = runtime_error(engine.eval("let x = 1;\nreport(x);"));
+    assert_eq!(
+        frames(&error),
+        vec![("divide", Some(2)), ("average", Some(6)), ("report", Some(11)), ("<top level>", Some(2))]
+    );
+}
+
+#[test]
+fn test_deep_recursion_is_summed_up_in_the_backtrace() {
+    let mut engine = Engine::new();
+    engine.load("fn down(n) {\n    return 1 + down(n + 1);\n}\nfn start() {\n    return down(0);\n}").unwrap();
+
+    let error = runtime_error(engine.call("start", &[]));
+    assert_eq!(
+        error.backtrace(),
+        "  at down (line 2)\n  at down (line 2)\n  at down (line 2)\n  ... 9997 more calls to down\n  at start (line 5)\n"
+    );
+
+    // A few calls in a row are all shown
+    engine.set_max_call_depth(4);
+    let error = runtime_error(engine.call("start", &[]));
+    assert_eq!(error.backtrace(), format!("{}  at start (line 5)\n", "  at down (line 2)\n".repeat(4)));
+}
+
+#[test]
+fn test_state_dumps_show_the_stack_locals_and_last_instructions() {
+    let source = "fn ratio(a, b) {\n    let scaled = a * 100;\n    let parts = [scaled, b];\n    return scaled / b;\n}\nratio(7, 0);";
+    let mut engine = Engine::new();
+    assert_eq!(runtime_error(engine.eval(source)).state, None);
+
+    engine.enable_state_dumps();
+    let state = runtime_error(engine.eval(source)).state.unwrap();
+    assert_eq!((state.function.as_str(), state.ip), ("ratio", 11));
+    // `Div` took its operands off the stack before failing
+    assert_eq!(state.stack[..2], [RuntimeValue::Array(vec![RuntimeValue::Integer(700), RuntimeValue::Integer(0)]), RuntimeValue::Integer(700)]);
+    let locals: Vec<(usize, &str)> = state.locals.iter().map(|(slot, name, _)| (*slot, name.as_str())).collect();
+    assert_eq!(locals, [(0, "a"), (1, "b"), (2, "scaled"), (3, "parts")]);
+    let dump = state.to_string();
+    assert!(dump.contains("     2 scaled = int 700\n     3 parts = array [700, 0]\n"), "{}", dump);
+    assert!(dump.ends_with("  last instructions run:\n         7  MakeArray(2)\n         8  StoreLocal(3)\n         9  LoadLocal(2)\n        10  LoadLocal(1)\n        11  Div\n"), "{}", dump);
+}
@@ -0,0 +1,115 @@
+use voltage_vm::{Engine, RuntimeErrorKind, RuntimeValue, VoltageError};
+
+fn eval(source: &str) -> Result<RuntimeValue, VoltageError> {
+    Engine::new().eval(source)
+}
+
+#[test]
+fn test_catching_a_division_by_zero_and_continuing() {
+    let source = r#"
+        let message = "none";
+        try {
+            let x = 1 / 0;
+            let message = "unreachable";
+        } catch err {
+            let message = err;
+        }
+        format("{}!", message);
+    "#;
+    assert_eq!(eval(source), Ok(RuntimeValue::String("Division by zero!".to_string())));
+
+    // Without an error the handler doesn't run
+    let source = "let result = 0; try { let result = 10 / 2; } catch err { let result = -1; } result;";
+    assert_eq!(eval(source), Ok(RuntimeValue::Integer(5)));
+}
+
+#[test]
+fn test_errors_in_called_functions_unwind_to_the_caller() {
+    let source = "
+        fn divide(a, b) { return a / b; }
+        fn safe_divide(a, b) {
+            try {
+                return divide(a, b);
+            } catch _ {
+                return 0;
+            }
+        }
+        safe_divide(10, 2) * 100 + safe_divide(1, 0) + len([safe_divide(3, 0)]);
+    ";
+    assert_eq!(eval(source), Ok(RuntimeValue::Integer(501)));
+}
+
+#[test]
+fn test_nested_handlers_catch_innermost_first() {
+    let source = r#"
+        fn f() {
+            try {
+                try {
+                    let x = [1, 2][5];
+                } catch inner {
+                    let _ = inner;
+                    let y = 1 / 0;
+                }
+                return "skipped";
+            } catch outer {
+                return format("outer: {}", outer);
+            }
+        }
+        f();
+    "#;
+    assert_eq!(eval(source), Ok(RuntimeValue::String("outer: Division by zero".to_string())));
+}
+
+#[test]
+fn test_handlers_end_with_their_body() {
+    // Once the `try` body finishes, later errors aren't caught by it
+    let source = "try { let x = 1; } catch _ { } 1 / 0;";
+    assert!(matches!(eval(source), Err(VoltageError::Runtime(_))));
+
+    // Nor once `break` or `continue` leaves it
+    let source = "fn f() { for i in 0..3 { try { if i == 1 { continue; } break; } catch _ { } } return 1 / 0; } f();";
+    assert!(matches!(eval(source), Err(VoltageError::Runtime(_))));
+}
+
+#[test]
+fn test_rethrowing_with_panic() {
+    let source = r#"
+        try {
+            try {
+                let x = 1 / 0;
+            } catch err {
+                panic("could not divide: {}", err);
+            }
+        } catch _ {
+            puts("panics aren't caught");
+        }
+    "#;
+    match eval(source) {
+        Err(VoltageError::Panic(error)) => assert_eq!(error.message, "could not divide: Division by zero"),
+        other => panic!("expected a panic, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_limits_are_not_catchable() {
+    let mut engine = Engine::new();
+    engine.set_max_call_depth(50);
+    let source = "fn forever(n) { return 1 + forever(n + 1); } try { forever(0); } catch _ { 0; }";
+    match engine.eval(source) {
+        Err(VoltageError::Runtime(error)) => {
+            assert_eq!(error.kind, RuntimeErrorKind::Limit);
+            assert!(error.message.starts_with("maximum recursion depth of 50 exceeded"), "{}", error);
+        }
+        other => panic!("expected the limit to stop the script, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_uncaught_errors_are_unchanged() {
+    let error = eval("fn f() { return 1 / 0; }\nf();").unwrap_err();
+    assert_eq!(error.to_string(), "Runtime error: Division by zero");
+    // A handler left over from an earlier run doesn't catch anything
+    let mut engine = Engine::new();
+    assert!(engine.eval("try { let x = 1; } catch _ { }").is_ok());
+    assert!(engine.eval("1 / 0;").is_err());
+}
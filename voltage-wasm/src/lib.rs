@@ -0,0 +1,140 @@
+//! The Voltage parser and VM for the browser, built with
+//! `wasm-pack build --target web` (or `--target nodejs`).
+//!
+//! Scripts run without a file system or stdin: the file builtins stay
+//! disabled, `std.io` is empty, and everything printed is collected into the
+//! result.
+
+use std::cell::RefCell;
+use std::rc::Rc;
+use serde::Serialize;
+use voltage_core::Span;
+use voltage_parser::parse_with_diagnostics;
+use voltage_vm::{Engine, OutputEvent, VoltageError};
+use wasm_bindgen::prelude::*;
+
+/// What running a script gave, as `run` returns it to JavaScript.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RunResult {
+    /// Everything the script printed before it finished or failed.
+    pub output: String,
+    /// Why the script stopped early, or `None` if it ran to the end.
+    pub error: Option<String>,
+    /// Errors and warnings that point into the source, for an editor to show.
+    pub diagnostics: Vec<SourceDiagnostic>,
+}
+
+/// A problem at a place in the source. Lines and columns count from 1; 0
+/// means the place is unknown.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SourceDiagnostic {
+    /// `"error"` or `"warning"`.
+    pub severity: &'static str,
+    pub message: String,
+    pub line: usize,
+    pub column: usize,
+    /// Byte offsets of the span in the source.
+    pub start: usize,
+    pub end: usize,
+}
+
+impl SourceDiagnostic {
+    fn new(severity: &'static str, message: impl Into<String>, span: Span) -> Self {
+        Self { severity, message: message.into(), line: span.line, column: span.column, start: span.start, end: span.end }
+    }
+}
+
+/// Runs `source` on a fresh engine and returns a [`RunResult`] as a plain
+/// JavaScript object: `{ output, error, diagnostics }`, with `error` null
+/// when the script succeeded.
+#[wasm_bindgen]
+pub fn run(source: &str) -> JsValue {
+    let serializer = serde_wasm_bindgen::Serializer::json_compatible();
+    run_source(source).serialize(&serializer).unwrap_or(JsValue::NULL)
+}
+
+/// What [`run`] computes, for Rust callers and tests.
+pub fn run_source(source: &str) -> RunResult {
+    let output = Rc::new(RefCell::new(String::new()));
+    let printed = Rc::clone(&output);
+    let mut engine = Engine::new();
+    engine.disable_std_io();
+    engine.set_output_handler(move |event| match event {
+        OutputEvent::Print(text) => printed.borrow_mut().push_str(&text),
+        OutputEvent::PutsLine(text) | OutputEvent::Debug(text) => {
+            let mut printed = printed.borrow_mut();
+            printed.push_str(&text);
+            printed.push('\n');
+        }
+        _ => {}
+    });
+
+    let result = engine.eval(source);
+    let mut diagnostics = Vec::new();
+    match &result {
+        // The engine stops at the first syntax error; the editor wants them all
+        Err(VoltageError::Lex(_) | VoltageError::Parse(_)) => diagnostics.extend(
+            parse_with_diagnostics(source)
+                .diagnostics
+                .into_iter()
+                .map(|diagnostic| SourceDiagnostic::new("error", diagnostic.message, diagnostic.span)),
+        ),
+        Err(VoltageError::Compile(error)) => {
+            diagnostics.push(SourceDiagnostic::new("error", &error.message, error.span.unwrap_or_default()));
+        }
+        Err(VoltageError::Runtime(error) | VoltageError::Panic(error)) => {
+            let span = engine.last_span().unwrap_or_default();
+            diagnostics.push(SourceDiagnostic::new("error", error.to_string(), span));
+        }
+        // Not tied to any place in the source
+        Err(error @ VoltageError::Conversion(_)) => {
+            diagnostics.push(SourceDiagnostic::new("error", error.to_string(), Default::default()));
+        }
+        Ok(_) => {}
+    }
+    diagnostics.extend(
+        engine
+            .warnings()
+            .iter()
+            .map(|warning| SourceDiagnostic::new("warning", &warning.message, warning.span)),
+    );
+
+    let output = output.borrow().clone();
+    RunResult { output, error: result.err().map(|error| error.to_string()), diagnostics }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_output_is_collected() {
+        let result = run_source("print(\"Hello, \");\nputs(\"world\");\nputs(1 + 1);");
+        assert_eq!(result.output, "Hello, world\n2\n");
+        assert_eq!(result.error, None);
+        assert!(result.diagnostics.is_empty());
+    }
+
+    #[test]
+    fn test_runtime_errors_keep_the_output_before_them() {
+        let result = run_source("puts(\"before\");\nlet x = 1 / 0;");
+        assert_eq!(result.output, "before\n");
+        assert!(result.error.unwrap().starts_with("Runtime error: "));
+        assert_eq!((result.diagnostics[0].severity, result.diagnostics[0].line), ("error", 2));
+    }
+
+    #[test]
+    fn test_every_syntax_error_is_a_diagnostic() {
+        let result = run_source("let x = ;\nlet y = 2;\nlet z = ;");
+        assert!(result.error.unwrap().starts_with("Parse error: "));
+        let lines: Vec<usize> = result.diagnostics.iter().map(|diagnostic| diagnostic.line).collect();
+        assert_eq!(lines, vec![1, 3]);
+    }
+
+    #[test]
+    fn test_no_file_or_input_access() {
+        let result = run_source("import std.io;\nio.read_file(\"/etc/passwd\");");
+        assert_eq!(result.diagnostics[0].message, "module `std.io` has no member `read_file`; it has no members");
+        assert_eq!(run_source("read_file(\"/etc/passwd\");").error.unwrap(), "Runtime error: file system access is disabled");
+    }
+}
@@ -0,0 +1,42 @@
+//! Runs under `wasm-pack test --node`.
+#![cfg(target_arch = "wasm32")]
+
+use serde::Deserialize;
+use wasm_bindgen::JsValue;
+use wasm_bindgen_test::wasm_bindgen_test;
+
+// The shape of the object `run` returns to JavaScript
+#[derive(Debug, Deserialize)]
+struct RunResult {
+    output: String,
+    error: Option<String>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Diagnostic {
+    severity: String,
+    line: usize,
+}
+
+fn run(source: &str) -> RunResult {
+    let value: JsValue = voltage_wasm::run(source);
+    serde_wasm_bindgen::from_value(value).unwrap()
+}
+
+#[wasm_bindgen_test]
+fn hello_world() {
+    let result = run("puts(\"Hello, world!\");");
+    assert_eq!(result.output, "Hello, world!\n");
+    assert_eq!(result.error, None);
+    assert!(result.diagnostics.is_empty());
+}
+
+#[wasm_bindgen_test]
+fn runtime_error() {
+    let result = run("puts(\"start\");\nlet xs = [1, 2];\nputs(xs[5]);");
+    assert_eq!(result.output, "start\n");
+    assert!(result.error.unwrap().starts_with("Runtime error: "));
+    assert_eq!(result.diagnostics.len(), 1);
+    assert_eq!((result.diagnostics[0].severity.as_str(), result.diagnostics[0].line), ("error", 3));
+}